@@ -0,0 +1,119 @@
+//! Session accept throughput of one WebTransport endpoint against several
+//! sharing the port with `SO_REUSEPORT`, each on its own thread, as the server
+//! runs with `--shards`. Concurrent clients, each on its own socket so the
+//! kernel can spread them, open and close sessions until enough are accepted.
+//!
+//! ```bash
+//! cargo bench --bench accept
+//! ```
+
+#[path = "../src/shards.rs"]
+mod shards;
+
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use wtransport::{ClientConfig, Endpoint, Identity, ServerConfig, VarInt};
+
+/// Sessions opened per run.
+const SESSIONS: usize = 2000;
+
+/// Clients connecting at once.
+const CLIENTS: usize = 64;
+
+/// Endpoint counts compared; the first is the unsharded baseline.
+const SHARDS: [usize; 3] = [1, 2, 4];
+
+/// Each run listens on its own port, BASE_PORT + shards.
+const BASE_PORT: u16 = 8790;
+
+// One shard: accept sessions on `socket` and hold each until the client closes it
+async fn accept(socket: UdpSocket, identity: Identity, accepted: Arc<AtomicU64>) {
+    let config = ServerConfig::builder()
+        .with_bind_socket(socket)
+        .with_identity(identity)
+        .build();
+    let endpoint = Endpoint::server(config).unwrap();
+    loop {
+        let incoming = endpoint.accept().await;
+        let accepted = accepted.clone();
+        tokio::spawn(async move {
+            let Ok(request) = incoming.await else {
+                return;
+            };
+            if let Ok(connection) = request.accept().await {
+                accepted.fetch_add(1, Ordering::Relaxed);
+                connection.closed().await;
+            }
+        });
+    }
+}
+
+// Open SESSIONS sessions from CLIENTS concurrent clients
+async fn connect(port: u16) -> Duration {
+    let url = format!("https://localhost:{}/", port);
+    let opened = Arc::new(AtomicUsize::new(0));
+    let started = Instant::now();
+
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|_| {
+            let url = url.clone();
+            let opened = opened.clone();
+            tokio::spawn(async move {
+                let config = ClientConfig::builder()
+                    .with_bind_default()
+                    .with_no_cert_validation()
+                    .build();
+                let endpoint = Endpoint::client(config).unwrap();
+                while opened.fetch_add(1, Ordering::Relaxed) < SESSIONS {
+                    let connection = endpoint.connect(&url).await.unwrap();
+                    connection.close(VarInt::from_u32(0), b"");
+                }
+                endpoint.wait_idle().await;
+            })
+        })
+        .collect();
+    for client in clients {
+        client.await.unwrap();
+    }
+    started.elapsed()
+}
+
+fn main() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let identity = Identity::self_signed(["localhost"]).unwrap();
+    println!("{} sessions from {} concurrent clients", SESSIONS, CLIENTS);
+
+    for shards in SHARDS {
+        if shards > 1 && !shards::SUPPORTED {
+            println!("{} shards: SO_REUSEPORT is not supported here", shards);
+            continue;
+        }
+        let port = BASE_PORT + shards as u16;
+        let accepted: Vec<_> = (0..shards)
+            .map(|shard| {
+                let socket = shards::socket(port, shards > 1).unwrap();
+                let identity = identity.clone_identity();
+                let accepted = Arc::new(AtomicU64::new(0));
+                let counter = accepted.clone();
+                // Shards run until the bench exits
+                drop(shards::spawn(shard, move || accept(socket, identity, counter)).unwrap());
+                accepted
+            })
+            .collect();
+
+        let elapsed = runtime.block_on(connect(port));
+        let per_shard: Vec<_> = accepted
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed).to_string())
+            .collect();
+        println!(
+            "{} shard(s): {:?}, {:.0} sessions/s, accepted per shard: {}",
+            shards,
+            elapsed,
+            SESSIONS as f64 / elapsed.as_secs_f64(),
+            per_shard.join(" / ")
+        );
+    }
+}
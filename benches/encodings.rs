@@ -0,0 +1,130 @@
+//! Encoded size and encode/decode time of typical frames under each framing:
+//! length-prefixed JSON, JSON lines and protobuf.
+//!
+//! ```bash
+//! cargo bench --bench encodings --features protobuf
+//! ```
+
+use protocol::burst::BurstInstruction;
+use protocol::pipeline::Aggregate;
+use protocol::rpc::Outcome;
+use protocol::telemetry::BandwidthEstimate;
+use protocol::{Frame, FrameDecoder, Framing};
+use std::time::{Duration, Instant};
+
+/// Times each frame is encoded and decoded.
+const ITERATIONS: u32 = 100_000;
+
+fn samples() -> Vec<(&'static str, Frame)> {
+    vec![
+        (
+            "message",
+            Frame::Message {
+                text: "hello from the playground".to_string(),
+                seq: Some(42),
+                id: None,
+                timing: None,
+            },
+        ),
+        (
+            "request",
+            Frame::Request {
+                id: 7,
+                method: "echo".to_string(),
+                params: serde_json::json!({ "text": "ping", "delay_ms": 250 }),
+            },
+        ),
+        (
+            "response",
+            Frame::Response {
+                id: 7,
+                outcome: Outcome::Result(serde_json::json!({ "text": "ping" })),
+            },
+        ),
+        (
+            "bandwidth",
+            Frame::BandwidthEstimate(BandwidthEstimate {
+                bits_per_second: 48_000_000,
+                rtt_us: 23_500,
+                cwnd_bytes: 120_000,
+                sent_packets: 18_342,
+                lost_packets: 12,
+                datagrams_received: 4_096,
+            }),
+        ),
+        (
+            "burst",
+            Frame::Burst(BurstInstruction {
+                burst_id: 3,
+                at_unix_ms: 1_760_000_000_000,
+                count: 100,
+                payload_bytes: 1_000,
+            }),
+        ),
+        (
+            "aggregate",
+            Frame::Aggregate(Aggregate {
+                topic: "sensors".to_string(),
+                window_ms: 1_000,
+                count: 5,
+                publishers: 2,
+                payloads: (0..5).map(|i| format!("reading {}", i)).collect(),
+                truncated: 0,
+            }),
+        ),
+        (
+            "relay",
+            Frame::Relay {
+                room: "lobby".to_string(),
+                from: Some(12),
+                nick: Some("ada".to_string()),
+                text: "anyone around?".to_string(),
+                replayed: false,
+                id: None,
+                timing: None,
+            },
+        ),
+    ]
+}
+
+fn time(mut f: impl FnMut()) -> Duration {
+    let started = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    started.elapsed() / ITERATIONS
+}
+
+fn main() {
+    let framings = [
+        ("json", Framing::LengthPrefixed),
+        ("jsonl", Framing::JsonLines),
+        ("protobuf", Framing::Protobuf),
+    ];
+    println!("{} iterations per frame and framing", ITERATIONS);
+
+    for (name, frame) in samples() {
+        println!("{}:", name);
+        for (framing_name, framing) in framings {
+            let encoded = framing.encode(&frame);
+            let encode = time(|| {
+                std::hint::black_box(framing.encode(std::hint::black_box(&frame)));
+            });
+            let mut decoder = FrameDecoder::with_framing(framing);
+            let decode = time(|| {
+                decoder.push(&encoded);
+                std::hint::black_box(decoder.next_frame().unwrap());
+            });
+
+            decoder.push(&encoded);
+            assert_eq!(decoder.next_frame().unwrap(), Some(frame.clone()));
+            println!(
+                "  {:>8}: {:>4} bytes, encode {:>9?}, decode {:>9?}",
+                framing_name,
+                encoded.len(),
+                encode,
+                decode
+            );
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Allocation churn of stream read buffers: a fresh `vec![0u8; 1024]` per
+//! stream against buffers taken from the server's pool, with many streams
+//! open at once.
+//!
+//! ```bash
+//! cargo bench --bench read_buffers
+//! ```
+
+#[path = "../src/pool.rs"]
+mod pool;
+
+use pool::{BufferPool, READ_BUFFER_LEN};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Streams open at once.
+const CONCURRENT_STREAMS: usize = 200;
+
+/// Streams each task opens one after another.
+const STREAMS_PER_TASK: usize = 50;
+
+/// Reads per stream.
+const READS_PER_STREAM: usize = 8;
+
+// Counts every allocation the process makes
+struct Counting;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[derive(Clone, Copy)]
+enum Strategy {
+    PerStream,
+    Pooled,
+}
+
+// Stand-in for one stream's reads: fill part of the buffer and yield, so the
+// other streams run in between and many buffers are live at once
+async fn read_stream(buffer: &mut [u8]) -> u64 {
+    let mut sum = 0u64;
+    for read in 0..READS_PER_STREAM {
+        let len = (read * 131) % READ_BUFFER_LEN + 1;
+        buffer[..len].fill(read as u8);
+        sum += buffer[..len].iter().map(|b| *b as u64).sum::<u64>();
+        tokio::task::yield_now().await;
+    }
+    sum
+}
+
+async fn run(strategy: Strategy, pool: Arc<BufferPool>) -> (u64, u64, Duration) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes = ALLOCATED_BYTES.load(Ordering::Relaxed);
+    let started = Instant::now();
+
+    let tasks: Vec<_> = (0..CONCURRENT_STREAMS)
+        .map(|_| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                let mut sum = 0;
+                for _ in 0..STREAMS_PER_TASK {
+                    sum += match strategy {
+                        Strategy::PerStream => read_stream(&mut vec![0u8; READ_BUFFER_LEN]).await,
+                        Strategy::Pooled => read_stream(&mut pool.take()).await,
+                    };
+                }
+                sum
+            })
+        })
+        .collect();
+    for task in tasks {
+        std::hint::black_box(task.await.unwrap());
+    }
+
+    (
+        ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+        ALLOCATED_BYTES.load(Ordering::Relaxed) - bytes,
+        started.elapsed(),
+    )
+}
+
+fn main() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let pool = Arc::new(BufferPool::new());
+    println!(
+        "{} concurrent streams x {} streams each, {} reads per stream",
+        CONCURRENT_STREAMS, STREAMS_PER_TASK, READS_PER_STREAM
+    );
+
+    for (name, strategy) in [
+        ("vec per stream", Strategy::PerStream),
+        ("pooled", Strategy::Pooled),
+    ] {
+        let (allocations, bytes, elapsed) = runtime.block_on(run(strategy, pool.clone()));
+        println!(
+            "{:>15}: {:>7} allocations, {:>9} bytes allocated, {:?}",
+            name, allocations, bytes, elapsed
+        );
+    }
+
+    let snapshot = pool.snapshot();
+    println!(
+        "pool: {} buffers allocated, {} reused, {} idle",
+        snapshot.allocated, snapshot.reused, snapshot.idle
+    );
+}
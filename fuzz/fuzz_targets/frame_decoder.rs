@@ -0,0 +1,34 @@
+// Feeds arbitrary bytes to the frame decoder the way a hostile client's stream
+// would arrive: in chunks of varying size, draining frames after each one.
+//
+//     cargo +nightly fuzz run frame_decoder
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use protocol::{FrameDecoder, MAX_BUFFERED_LEN};
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the chunk size, so the fuzzer explores how frames
+    // straddle reads as well as their contents
+    let Some((&chunk, data)) = data.split_first() else {
+        return;
+    };
+    let chunk = usize::from(chunk).max(1);
+
+    let mut decoder = FrameDecoder::new();
+    for bytes in data.chunks(chunk) {
+        decoder.push(bytes);
+        loop {
+            match decoder.next_frame() {
+                Ok(Some(frame)) => {
+                    // Whatever decodes must re-encode
+                    let _ = protocol::encode(&frame);
+                }
+                Ok(None) => break,
+                // The server closes the stream on any decode error
+                Err(_) => return,
+            }
+        }
+        assert!(decoder.buffered() <= MAX_BUFFERED_LEN);
+    }
+});
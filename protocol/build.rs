@@ -0,0 +1,14 @@
+// With the `protobuf` feature, generate the protobuf frame types from
+// proto/playground.proto. protox compiles the schema, so no protoc is needed.
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "protobuf")]
+    {
+        println!("cargo:rerun-if-changed=proto/playground.proto");
+        let descriptors = protox::compile(["proto/playground.proto"], ["proto"])
+            .expect("proto/playground.proto should compile");
+        prost_build::Config::new()
+            .compile_fds(descriptors)
+            .expect("protobuf types should generate");
+    }
+}
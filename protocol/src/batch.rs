@@ -0,0 +1,68 @@
+//! Datagram batching: several small messages packed into one datagram, so tiny
+//! messages don't each pay a datagram's overhead.
+//!
+//! A batch is [`BATCH_PREFIX`], a count byte, then each message as a 2-byte
+//! big-endian length followed by its bytes. Both directions use the same format.
+
+/// Datagrams starting with these bytes are batches.
+pub const BATCH_PREFIX: &[u8] = b"\0batch";
+
+/// Most messages one batch holds, since the count is a single byte.
+pub const MAX_BATCH_MESSAGES: usize = u8::MAX as usize;
+
+const HEADER_LEN: usize = BATCH_PREFIX.len() + 1;
+
+const ENTRY_HEADER_LEN: usize = 2;
+
+/// The messages in a batch datagram, or `None` if it isn't a well-formed one.
+pub fn decode(datagram: &[u8]) -> Option<Vec<&[u8]>> {
+    let rest = datagram.strip_prefix(BATCH_PREFIX)?;
+    let (&count, mut rest) = rest.split_first()?;
+    let mut messages = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let len = u16::from_be_bytes(rest.get(..ENTRY_HEADER_LEN)?.try_into().ok()?) as usize;
+        let message = rest.get(ENTRY_HEADER_LEN..ENTRY_HEADER_LEN + len)?;
+        messages.push(message);
+        rest = &rest[ENTRY_HEADER_LEN + len..];
+    }
+    // Trailing bytes mean the count and the entries disagree
+    rest.is_empty().then_some(messages)
+}
+
+/// Pack `messages` in order into as few batches of at most `max_len` bytes as
+/// it takes. A message too big to share a batch still gets one to itself, which
+/// may be over `max_len`; messages over 64 KiB can't be batched at all and are
+/// skipped.
+pub fn pack<T: AsRef<[u8]>>(messages: &[T], max_len: usize) -> Vec<Vec<u8>> {
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut count = 0;
+    for message in messages {
+        let message = message.as_ref();
+        let Ok(len) = u16::try_from(message.len()) else {
+            continue;
+        };
+        let entry_len = ENTRY_HEADER_LEN + message.len();
+        if count > 0 && (batch.len() + entry_len > max_len || count == MAX_BATCH_MESSAGES) {
+            batches.push(finish(batch, count));
+            batch = Vec::new();
+            count = 0;
+        }
+        if count == 0 {
+            batch.extend_from_slice(BATCH_PREFIX);
+            batch.push(0);
+        }
+        batch.extend_from_slice(&len.to_be_bytes());
+        batch.extend_from_slice(message);
+        count += 1;
+    }
+    if count > 0 {
+        batches.push(finish(batch, count));
+    }
+    batches
+}
+
+fn finish(mut batch: Vec<u8>, count: usize) -> Vec<u8> {
+    batch[HEADER_LEN - 1] = count as u8;
+    batch
+}
@@ -0,0 +1,11 @@
+//! Print the protocol's test vectors as JSON (see `protocol::vectors`).
+//!
+//! ```bash
+//! cargo run -p protocol --features protobuf --bin vectors > protocol/vectors/vectors.json
+//! ```
+
+fn main() {
+    let vectors = protocol::vectors::generate();
+    let json = serde_json::to_string_pretty(&vectors).expect("vectors always serialize");
+    println!("{}", json);
+}
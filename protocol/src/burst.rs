@@ -0,0 +1,40 @@
+//! Coordinated burst test: the server tells every client to send a burst of
+//! datagrams at the same instant and studies how they arrive.
+
+use serde::{Deserialize, Serialize};
+
+/// Datagrams starting with these bytes belong to a burst.
+pub const BURST_PREFIX: &[u8] = b"\0burst";
+
+const HEADER_LEN: usize = BURST_PREFIX.len() + 8 + 4;
+
+/// Server's instruction to send a burst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BurstInstruction {
+    pub burst_id: u64,
+    /// When to start sending, on the server's clock (Unix milliseconds).
+    /// Clients translate it with their clock-offset estimate.
+    pub at_unix_ms: u64,
+    /// Datagrams to send, back to back.
+    pub count: u32,
+    /// Size of each datagram.
+    pub payload_bytes: usize,
+}
+
+/// Build burst datagram `seq` of `burst_id`, padded to `len` bytes.
+pub fn encode_datagram(burst_id: u64, seq: u32, len: usize) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(len.max(HEADER_LEN));
+    datagram.extend_from_slice(BURST_PREFIX);
+    datagram.extend_from_slice(&burst_id.to_be_bytes());
+    datagram.extend_from_slice(&seq.to_be_bytes());
+    datagram.resize(len.max(HEADER_LEN), 0);
+    datagram
+}
+
+/// `(burst_id, seq)` of a burst datagram, or `None` if it isn't one.
+pub fn decode_datagram(datagram: &[u8]) -> Option<(u64, u32)> {
+    let rest = datagram.strip_prefix(BURST_PREFIX)?;
+    let burst_id = u64::from_be_bytes(rest.get(..8)?.try_into().ok()?);
+    let seq = u32::from_be_bytes(rest.get(8..12)?.try_into().ok()?);
+    Some((burst_id, seq))
+}
@@ -0,0 +1,43 @@
+//! Certificate pins served by the server's `/cert-hash` endpoint.
+
+use serde::{Deserialize, Serialize};
+
+/// One certificate hash a client may pin via `serverCertificateHashes`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertPin {
+    /// Hash algorithm; browsers only accept `sha-256`.
+    #[serde(default = "default_algorithm")]
+    pub algorithm: String,
+    /// Lowercase hex digest of the certificate's DER.
+    pub value: String,
+    /// When the certificate stops being valid (Unix milliseconds), if known.
+    /// Expired pins are skipped by clients.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_unix_ms: Option<u64>,
+}
+
+fn default_algorithm() -> String {
+    "sha-256".to_string()
+}
+
+impl CertPin {
+    pub fn is_expired(&self, now_unix_ms: u64) -> bool {
+        self.expires_unix_ms
+            .is_some_and(|expires| expires <= now_unix_ms)
+    }
+}
+
+/// Body of `/cert-hash`. `algorithm` and `value` describe the certificate the
+/// server is presenting now; `hashes` lists it first, followed by any other
+/// certificates being rotated in or out, so clients can try each in turn.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertPins {
+    pub algorithm: String,
+    pub value: String,
+    #[serde(default)]
+    pub hashes: Vec<CertPin>,
+    /// Base64 SHA-256 of the presented certificate's public key, for Chrome's
+    /// `--ignore-certificate-errors-spki-list`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spki_sha256: Option<String>,
+}
@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Why a session was closed: the application close code either side sends.
+///
+/// Kept apart from [`PlaygroundError`](crate::PlaygroundError) codes, which
+/// describe individual failures; a close code says why the whole session ended,
+/// and the reason string sent with it carries the details.
+///
+/// | Code | Variant | Sent when |
+/// |------|---------|-----------|
+/// | 0 | `Normal` | Either side is done with the session |
+/// | 100 | `AuthFailure` | The peer isn't allowed to use the session |
+/// | 101 | `ProtocolViolation` | The peer sent something it shouldn't have, e.g. a malformed frame |
+/// | 102 | `Idle` | Nothing was sent for too long |
+/// | 103 | `Drain` | The sender is going away and stopped waiting for in-flight data |
+/// | 104 | `Kicked` | An operator or scenario removed the session |
+/// | 105 | `RateLimited` | The peer kept exceeding its rate limit |
+/// | 106 | `Redirected` | The session was sent a `Redirect` and didn't follow it |
+/// | 107 | `TakenOver` | Another connection resumed the session with its token |
+/// | 108 | `Unresponsive` | The peer didn't answer a liveness probe in time |
+/// | 109 | `ServerError` | The server's handler for the session stopped making progress |
+/// | 110 | `QuotaExceeded` | The session used up the bytes it may send and receive |
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseCode {
+    #[default]
+    Normal,
+    AuthFailure,
+    ProtocolViolation,
+    Idle,
+    Drain,
+    Kicked,
+    RateLimited,
+    Redirected,
+    TakenOver,
+    Unresponsive,
+    ServerError,
+    QuotaExceeded,
+}
+
+impl CloseCode {
+    pub fn code(self) -> u32 {
+        match self {
+            CloseCode::Normal => 0,
+            CloseCode::AuthFailure => 100,
+            CloseCode::ProtocolViolation => 101,
+            CloseCode::Idle => 102,
+            CloseCode::Drain => 103,
+            CloseCode::Kicked => 104,
+            CloseCode::RateLimited => 105,
+            CloseCode::Redirected => 106,
+            CloseCode::TakenOver => 107,
+            CloseCode::Unresponsive => 108,
+            CloseCode::ServerError => 109,
+            CloseCode::QuotaExceeded => 110,
+        }
+    }
+
+    /// The close code with value `code`, if it's one of ours.
+    pub fn from_code(code: u32) -> Option<Self> {
+        Some(match code {
+            0 => CloseCode::Normal,
+            100 => CloseCode::AuthFailure,
+            101 => CloseCode::ProtocolViolation,
+            102 => CloseCode::Idle,
+            103 => CloseCode::Drain,
+            104 => CloseCode::Kicked,
+            105 => CloseCode::RateLimited,
+            106 => CloseCode::Redirected,
+            107 => CloseCode::TakenOver,
+            108 => CloseCode::Unresponsive,
+            109 => CloseCode::ServerError,
+            110 => CloseCode::QuotaExceeded,
+            _ => return None,
+        })
+    }
+
+    /// Fixed, human-readable description, e.g. for showing to users.
+    pub fn description(self) -> &'static str {
+        match self {
+            CloseCode::Normal => "Closed normally",
+            CloseCode::AuthFailure => "Not authorized",
+            CloseCode::ProtocolViolation => "Protocol violation",
+            CloseCode::Idle => "Closed for inactivity",
+            CloseCode::Drain => "Closed before all data was delivered",
+            CloseCode::Kicked => "Removed by the server",
+            CloseCode::RateLimited => "Rate limit exceeded",
+            CloseCode::Redirected => "Redirected elsewhere",
+            CloseCode::TakenOver => "Taken over by another connection",
+            CloseCode::Unresponsive => "Didn't answer a liveness probe",
+            CloseCode::ServerError => "Server error",
+            CloseCode::QuotaExceeded => "Byte quota used up",
+        }
+    }
+}
+
+impl fmt::Display for CloseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.description(), self.code())
+    }
+}
@@ -0,0 +1,217 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Errors shared by the server and clients.
+///
+/// Every variant has a stable numeric [`code`](PlaygroundError::code), used in JSON error
+/// bodies (`{"code": 31, "message": "..."}`). Sessions are closed with a
+/// [`CloseCode`](crate::CloseCode) instead.
+///
+/// | Code | Variant |
+/// |------|---------|
+/// | 1 | `Internal` |
+/// | 2 | `NotConnected` |
+/// | 3 | `Timeout` |
+/// | 4 | `Cancelled` |
+/// | 10 | `InvalidUrl` |
+/// | 11 | `ConnectFailed` |
+/// | 12 | `StreamFailed` |
+/// | 13 | `SendFailed` |
+/// | 14 | `ReceiveFailed` |
+/// | 20 | `MalformedFrame` |
+/// | 21 | `UnexpectedFrame` |
+/// | 30 | `MethodNotFound` |
+/// | 31 | `InvalidParams` |
+/// | 40 | `RateLimited` |
+/// | 41 | `Overloaded` |
+/// | 42 | `QuotaExceeded` |
+/// | 43 | `Unauthorized` |
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "WireError", from = "WireError")]
+pub enum PlaygroundError {
+    Internal(String),
+    NotConnected,
+    Timeout(String),
+    Cancelled(String),
+    InvalidUrl(String),
+    ConnectFailed(String),
+    StreamFailed(String),
+    SendFailed(String),
+    ReceiveFailed(String),
+    MalformedFrame(String),
+    UnexpectedFrame(String),
+    MethodNotFound(String),
+    InvalidParams(String),
+    RateLimited(String),
+    Overloaded(String),
+    QuotaExceeded(String),
+    Unauthorized(String),
+}
+
+impl PlaygroundError {
+    pub fn code(&self) -> u32 {
+        match self {
+            PlaygroundError::Internal(_) => 1,
+            PlaygroundError::NotConnected => 2,
+            PlaygroundError::Timeout(_) => 3,
+            PlaygroundError::Cancelled(_) => 4,
+            PlaygroundError::InvalidUrl(_) => 10,
+            PlaygroundError::ConnectFailed(_) => 11,
+            PlaygroundError::StreamFailed(_) => 12,
+            PlaygroundError::SendFailed(_) => 13,
+            PlaygroundError::ReceiveFailed(_) => 14,
+            PlaygroundError::MalformedFrame(_) => 20,
+            PlaygroundError::UnexpectedFrame(_) => 21,
+            PlaygroundError::MethodNotFound(_) => 30,
+            PlaygroundError::InvalidParams(_) => 31,
+            PlaygroundError::RateLimited(_) => 40,
+            PlaygroundError::Overloaded(_) => 41,
+            PlaygroundError::QuotaExceeded(_) => 42,
+            PlaygroundError::Unauthorized(_) => 43,
+        }
+    }
+
+    /// Rebuild an error from its code and detail message, e.g. from a close frame.
+    /// Unknown codes become `Internal`.
+    pub fn from_code(code: u32, detail: impl Into<String>) -> Self {
+        let detail = detail.into();
+        match code {
+            2 => PlaygroundError::NotConnected,
+            3 => PlaygroundError::Timeout(detail),
+            4 => PlaygroundError::Cancelled(detail),
+            10 => PlaygroundError::InvalidUrl(detail),
+            11 => PlaygroundError::ConnectFailed(detail),
+            12 => PlaygroundError::StreamFailed(detail),
+            13 => PlaygroundError::SendFailed(detail),
+            14 => PlaygroundError::ReceiveFailed(detail),
+            20 => PlaygroundError::MalformedFrame(detail),
+            21 => PlaygroundError::UnexpectedFrame(detail),
+            30 => PlaygroundError::MethodNotFound(detail),
+            31 => PlaygroundError::InvalidParams(detail),
+            40 => PlaygroundError::RateLimited(detail),
+            41 => PlaygroundError::Overloaded(detail),
+            42 => PlaygroundError::QuotaExceeded(detail),
+            43 => PlaygroundError::Unauthorized(detail),
+            _ => PlaygroundError::Internal(detail),
+        }
+    }
+
+    /// Fixed, human-readable description of the error kind.
+    pub fn description(&self) -> &'static str {
+        match self {
+            PlaygroundError::Internal(_) => "Internal error",
+            PlaygroundError::NotConnected => "Not connected",
+            PlaygroundError::Timeout(_) => "Timed out",
+            PlaygroundError::Cancelled(_) => "Cancelled",
+            PlaygroundError::InvalidUrl(_) => "Invalid URL",
+            PlaygroundError::ConnectFailed(_) => "Connection failed",
+            PlaygroundError::StreamFailed(_) => "Stream failed",
+            PlaygroundError::SendFailed(_) => "Send failed",
+            PlaygroundError::ReceiveFailed(_) => "Receive failed",
+            PlaygroundError::MalformedFrame(_) => "Malformed frame",
+            PlaygroundError::UnexpectedFrame(_) => "Unexpected frame",
+            PlaygroundError::MethodNotFound(_) => "Method not found",
+            PlaygroundError::InvalidParams(_) => "Invalid params",
+            PlaygroundError::RateLimited(_) => "Rate limited",
+            PlaygroundError::Overloaded(_) => "Server overloaded",
+            PlaygroundError::QuotaExceeded(_) => "Quota exceeded",
+            PlaygroundError::Unauthorized(_) => "Unauthorized",
+        }
+    }
+
+    /// Stable snake_case name of the variant, e.g. `"rate_limited"`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PlaygroundError::Internal(_) => "internal",
+            PlaygroundError::NotConnected => "not_connected",
+            PlaygroundError::Timeout(_) => "timeout",
+            PlaygroundError::Cancelled(_) => "cancelled",
+            PlaygroundError::InvalidUrl(_) => "invalid_url",
+            PlaygroundError::ConnectFailed(_) => "connect_failed",
+            PlaygroundError::StreamFailed(_) => "stream_failed",
+            PlaygroundError::SendFailed(_) => "send_failed",
+            PlaygroundError::ReceiveFailed(_) => "receive_failed",
+            PlaygroundError::MalformedFrame(_) => "malformed_frame",
+            PlaygroundError::UnexpectedFrame(_) => "unexpected_frame",
+            PlaygroundError::MethodNotFound(_) => "method_not_found",
+            PlaygroundError::InvalidParams(_) => "invalid_params",
+            PlaygroundError::RateLimited(_) => "rate_limited",
+            PlaygroundError::Overloaded(_) => "overloaded",
+            PlaygroundError::QuotaExceeded(_) => "quota_exceeded",
+            PlaygroundError::Unauthorized(_) => "unauthorized",
+        }
+    }
+
+    /// Whether making the same call again later may succeed: transient
+    /// failures, as opposed to bad input or a missing connection.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            PlaygroundError::Timeout(_)
+                | PlaygroundError::ConnectFailed(_)
+                | PlaygroundError::SendFailed(_)
+                | PlaygroundError::RateLimited(_)
+                | PlaygroundError::Overloaded(_)
+                | PlaygroundError::QuotaExceeded(_)
+        )
+    }
+
+    /// Variant-specific detail, if any.
+    pub fn detail(&self) -> Option<&str> {
+        match self {
+            PlaygroundError::NotConnected => None,
+            PlaygroundError::Internal(detail)
+            | PlaygroundError::Timeout(detail)
+            | PlaygroundError::Cancelled(detail)
+            | PlaygroundError::InvalidUrl(detail)
+            | PlaygroundError::ConnectFailed(detail)
+            | PlaygroundError::StreamFailed(detail)
+            | PlaygroundError::SendFailed(detail)
+            | PlaygroundError::ReceiveFailed(detail)
+            | PlaygroundError::MalformedFrame(detail)
+            | PlaygroundError::UnexpectedFrame(detail)
+            | PlaygroundError::MethodNotFound(detail)
+            | PlaygroundError::InvalidParams(detail)
+            | PlaygroundError::RateLimited(detail)
+            | PlaygroundError::Overloaded(detail)
+            | PlaygroundError::QuotaExceeded(detail)
+            | PlaygroundError::Unauthorized(detail) => Some(detail),
+        }
+    }
+}
+
+impl fmt::Display for PlaygroundError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.detail() {
+            Some(detail) if !detail.is_empty() => {
+                write!(f, "{} ({}): {}", self.description(), self.code(), detail)
+            }
+            _ => write!(f, "{} ({})", self.description(), self.code()),
+        }
+    }
+}
+
+impl std::error::Error for PlaygroundError {}
+
+/// JSON shape of a [`PlaygroundError`].
+#[derive(Serialize, Deserialize)]
+struct WireError {
+    code: u32,
+    #[serde(default)]
+    message: String,
+}
+
+impl From<PlaygroundError> for WireError {
+    fn from(error: PlaygroundError) -> Self {
+        Self {
+            code: error.code(),
+            message: error.detail().unwrap_or_default().to_string(),
+        }
+    }
+}
+
+impl From<WireError> for PlaygroundError {
+    fn from(wire: WireError) -> Self {
+        PlaygroundError::from_code(wire.code, wire.message)
+    }
+}
@@ -0,0 +1,56 @@
+//! Datagram expiry: a message only worth delivering for a while carries when it
+//! was sent and how long it stays useful, so a server it reaches too late can
+//! drop it instead of acting on stale data.
+//!
+//! An expiring datagram is [`EXPIRY_PREFIX`], the send time as 8-byte
+//! big-endian Unix milliseconds on the server's clock (as well as the sender
+//! knows it), the max age as 4-byte big-endian milliseconds, then the payload.
+
+/// Datagrams starting with these bytes carry an expiry.
+pub const EXPIRY_PREFIX: &[u8] = b"\0ttl";
+
+/// Bytes before the payload.
+pub const HEADER_LEN: usize = EXPIRY_PREFIX.len() + 8 + 4;
+
+/// An expiring datagram, borrowed from its bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expiring<'a> {
+    pub sent_unix_ms: u64,
+    pub max_age_ms: u32,
+    pub payload: &'a [u8],
+}
+
+impl Expiring<'_> {
+    /// How old the datagram is at `now_unix_ms`; 0 if it seems to come from
+    /// the future, as it may when the sender's clock estimate is off.
+    pub fn age_ms(&self, now_unix_ms: u64) -> u64 {
+        now_unix_ms.saturating_sub(self.sent_unix_ms)
+    }
+
+    pub fn is_expired(&self, now_unix_ms: u64) -> bool {
+        self.age_ms(now_unix_ms) > self.max_age_ms as u64
+    }
+}
+
+/// Build a datagram carrying `payload` that expires `max_age_ms` after
+/// `sent_unix_ms`.
+pub fn encode(sent_unix_ms: u64, max_age_ms: u32, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(HEADER_LEN + payload.len());
+    datagram.extend_from_slice(EXPIRY_PREFIX);
+    datagram.extend_from_slice(&sent_unix_ms.to_be_bytes());
+    datagram.extend_from_slice(&max_age_ms.to_be_bytes());
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+/// The expiry and payload of an expiring datagram, or `None` if it isn't one.
+pub fn decode(datagram: &[u8]) -> Option<Expiring<'_>> {
+    let rest = datagram.strip_prefix(EXPIRY_PREFIX)?;
+    let sent_unix_ms = u64::from_be_bytes(*rest.first_chunk::<8>()?);
+    let max_age_ms = u32::from_be_bytes(*rest.get(8..)?.first_chunk::<4>()?);
+    Some(Expiring {
+        sent_unix_ms,
+        max_age_ms,
+        payload: &datagram[HEADER_LEN..],
+    })
+}
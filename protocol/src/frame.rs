@@ -0,0 +1,447 @@
+use crate::PlaygroundError;
+use crate::burst::BurstInstruction;
+use crate::hello::ServerInfo;
+use crate::pipeline::Aggregate;
+use crate::room::{RelayTransport, RoomAction};
+use crate::rpc::Outcome;
+use crate::telemetry::{BandwidthEstimate, ServerTiming};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Largest frame body a decoder accepts. Anything bigger is treated as a protocol error.
+pub const MAX_FRAME_LEN: usize = 64 * 1024;
+
+const HEADER_LEN: usize = 4;
+
+/// Longest message `id` the server accepts, in bytes: room for a UUID and then some.
+pub const MAX_MESSAGE_ID_LEN: usize = 64;
+
+/// Most bytes a decoder holds before they're consumed as frames: a few frames'
+/// worth, so a peer can't grow the buffer by pipelining faster than it's drained.
+pub const MAX_BUFFERED_LEN: usize = 4 * (HEADER_LEN + MAX_FRAME_LEN);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Frame {
+    /// Free-form text, echoed back by the server. A `seq`, if present, is
+    /// echoed unchanged so clients can verify ordering. An `id`, a UUID the
+    /// client generates, makes the message safe to send again: the server
+    /// handles each id once per session and echoes it, so a client can drop
+    /// echoes it has already seen. The server's echoes carry `timing`.
+    Message {
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timing: Option<ServerTiming>,
+    },
+    /// A call to `method`, expecting exactly one `Response` with the same `id`.
+    Request {
+        id: u64,
+        method: String,
+        #[serde(default)]
+        params: serde_json::Value,
+    },
+    /// Answer to the `Request` with the matching `id`.
+    Response {
+        id: u64,
+        #[serde(flatten)]
+        outcome: Outcome,
+    },
+    /// A failure not tied to any request, typically sent just before closing.
+    Error {
+        #[serde(flatten)]
+        error: PlaygroundError,
+    },
+    /// Server's periodic estimate of the connection's available bandwidth.
+    BandwidthEstimate(BandwidthEstimate),
+    /// Server's instruction to send a burst of datagrams at a given instant.
+    Burst(BurstInstruction),
+    /// Start receiving aggregates for `topic`.
+    Subscribe { topic: String },
+    /// Stop receiving aggregates for `topic`.
+    Unsubscribe { topic: String },
+    /// Add `text` to the current window of `topic`. Dropped if nobody subscribes.
+    Publish { topic: String, text: String },
+    /// One window's worth of a topic, fanned out to its subscribers.
+    Aggregate(Aggregate),
+    /// Start receiving messages relayed to `room`, over `transport` rather than
+    /// however the room relays by default. Joining again changes it.
+    Join {
+        room: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        transport: Option<RelayTransport>,
+    },
+    /// Stop receiving messages relayed to `room`.
+    Leave { room: String },
+    /// A message for everyone else in `room`. The server fills in `from` with
+    /// the sender's connection id when relaying it, and `nick` with the sender's
+    /// `nick` session tag if it has one. `replayed` marks a message queued while
+    /// the recipient was disconnected and delivered after it resumed. An `id`
+    /// works as on `message`: a resent relay isn't relayed again, and
+    /// recipients get the sender's id to drop copies they already have. Relays
+    /// carry `timing` from when the server read the sender's to when it sent
+    /// this copy.
+    Relay {
+        room: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        from: Option<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nick: Option<String>,
+        text: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        replayed: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timing: Option<ServerTiming>,
+    },
+    /// Close this session and reconnect to `url`, e.g. because the server moved
+    /// or to spread load. The server may close sessions that don't follow as `redirected`.
+    Redirect { url: String },
+    /// The server's capabilities and limits, sent once as the session opens.
+    Hello(ServerInfo),
+    /// Take over the session `session_token` was issued to in its `hello`: its
+    /// rooms, nickname and the relayed messages it missed move to this
+    /// connection. Answered with `resumed`, or an error if the token is unknown
+    /// or its grace period has run out.
+    Resume { session_token: String },
+    /// The session was resumed: this connection is now in `rooms`, as `nick`
+    /// if it had one, and the `missed` relayed messages follow on their own
+    /// stream, oldest first, marked `replayed`. `dropped` more didn't fit the
+    /// session's queue.
+    Resumed {
+        rooms: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nick: Option<String>,
+        missed: u64,
+        #[serde(default)]
+        dropped: u64,
+    },
+    /// Liveness probe for a connection that has gone quiet. Answer with a
+    /// `pong` carrying the same `nonce` within `deadline_ms`, or the server
+    /// closes the session as `unresponsive`.
+    Ping { nonce: u64, deadline_ms: u64 },
+    /// Answer to the `ping` with the matching `nonce`.
+    Pong { nonce: u64 },
+    /// Take `member`, a connection id, out of `room` and keep it out for as
+    /// long as the room lasts. Only the room's owner may, and owners can't be
+    /// kicked.
+    Kick { room: String, member: u64 },
+    /// Stop relaying `member`'s messages in `room` until it's unmuted. Owner only.
+    Mute { room: String, member: u64 },
+    /// Relay `member`'s messages in `room` again. Owner only.
+    Unmute { room: String, member: u64 },
+    /// Something happened to `member` in `room`: it became the room's owner,
+    /// or the owner, `by`, kicked, muted or unmuted it. Sent to every member,
+    /// a kicked one included.
+    RoomNotice {
+        room: String,
+        action: RoomAction,
+        member: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        by: Option<u64>,
+    },
+    /// Start uploading a file of `size` bytes called `name`. `id`, a UUID the
+    /// client generates, names the transfer from then on, across sessions.
+    /// Answered with a `transfer_ack` at offset 0; starting an `id` the server
+    /// already has with the same name and size acks where it stands instead.
+    TransferStart { id: String, name: String, size: u64 },
+    /// Transfer `id`'s bytes from `offset`, hex-encoded in `data`, at most
+    /// [`transfer::MAX_CHUNK_LEN`](crate::transfer::MAX_CHUNK_LEN) of them.
+    /// `offset` must be where the bytes the server has end. Answered with a
+    /// `transfer_ack`.
+    TransferChunk {
+        id: String,
+        offset: u64,
+        data: String,
+    },
+    /// Ask how much of transfer `id` the server has, e.g. after reconnecting.
+    /// Answered with a `transfer_ack`, or an error if it's unknown or expired.
+    ResumeTransfer { id: String },
+    /// The server has transfer `id`'s first `offset` bytes, whose checksum
+    /// (see [`transfer::checksum`](crate::transfer::checksum)) is `checksum`.
+    /// Complete once `offset` is the transfer's size.
+    TransferAck {
+        id: String,
+        offset: u64,
+        checksum: u32,
+    },
+    /// The session has used `used` of the `limit` bytes it may send and
+    /// receive. Sent once as it nears the limit; at the limit the session is
+    /// closed as `quota_exceeded`.
+    QuotaWarning { used: u64, limit: u64 },
+}
+
+impl Frame {
+    /// The frame's `type` tag, e.g. `"bandwidth_estimate"`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Frame::Message { .. } => "message",
+            Frame::Request { .. } => "request",
+            Frame::Response { .. } => "response",
+            Frame::Error { .. } => "error",
+            Frame::BandwidthEstimate(_) => "bandwidth_estimate",
+            Frame::Burst(_) => "burst",
+            Frame::Subscribe { .. } => "subscribe",
+            Frame::Unsubscribe { .. } => "unsubscribe",
+            Frame::Publish { .. } => "publish",
+            Frame::Aggregate(_) => "aggregate",
+            Frame::Join { .. } => "join",
+            Frame::Leave { .. } => "leave",
+            Frame::Relay { .. } => "relay",
+            Frame::Redirect { .. } => "redirect",
+            Frame::Hello(_) => "hello",
+            Frame::Resume { .. } => "resume",
+            Frame::Resumed { .. } => "resumed",
+            Frame::Ping { .. } => "ping",
+            Frame::Pong { .. } => "pong",
+            Frame::Kick { .. } => "kick",
+            Frame::Mute { .. } => "mute",
+            Frame::Unmute { .. } => "unmute",
+            Frame::RoomNotice { .. } => "room_notice",
+            Frame::TransferStart { .. } => "transfer_start",
+            Frame::TransferChunk { .. } => "transfer_chunk",
+            Frame::ResumeTransfer { .. } => "resume_transfer",
+            Frame::TransferAck { .. } => "transfer_ack",
+            Frame::QuotaWarning { .. } => "quota_warning",
+        }
+    }
+
+    /// Set the send time of a frame carrying [`ServerTiming`], to `sent_us` on
+    /// the same clock as its receive time. Other frames are left alone.
+    pub fn stamp_sent(&mut self, sent_us: u64) {
+        if let Frame::Message {
+            timing: Some(timing),
+            ..
+        }
+        | Frame::Relay {
+            timing: Some(timing),
+            ..
+        } = self
+        {
+            timing.sent_us = sent_us;
+        }
+    }
+}
+
+/// How frames are delimited on a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Framing {
+    /// Each frame's JSON follows its 4-byte big-endian length.
+    #[default]
+    LengthPrefixed,
+    /// Each frame's JSON is one line, ended by `\n`: readable and writable with
+    /// generic tools that don't speak the binary framing.
+    JsonLines,
+    /// Each frame's protobuf encoding (see `proto/playground.proto`) follows its
+    /// 4-byte big-endian length.
+    #[cfg(feature = "protobuf")]
+    Protobuf,
+}
+
+impl Framing {
+    /// The framing of a session opened on `path`, query string aside: `/jsonl`
+    /// for JSON lines, `/protobuf` for protobuf bodies and anything else
+    /// length-prefixed. None for `/protobuf` without the `protobuf` feature,
+    /// so a client can fall back to `/`.
+    pub fn for_path(path: &str) -> Option<Self> {
+        match path.split('?').next() {
+            Some("/jsonl") => Some(Framing::JsonLines),
+            #[cfg(feature = "protobuf")]
+            Some("/protobuf") => Some(Framing::Protobuf),
+            #[cfg(not(feature = "protobuf"))]
+            Some("/protobuf") => None,
+            _ => Some(Framing::LengthPrefixed),
+        }
+    }
+
+    /// Serialize a frame delimited this way.
+    pub fn encode(self, frame: &Frame) -> Vec<u8> {
+        match self {
+            Framing::LengthPrefixed => encode(frame),
+            Framing::JsonLines => {
+                // serde_json escapes newlines inside strings, so a frame is always one line
+                let mut out = serde_json::to_vec(frame).expect("frames always serialize");
+                out.push(b'\n');
+                out
+            }
+            #[cfg(feature = "protobuf")]
+            Framing::Protobuf => with_header(crate::proto::encode_body(frame)),
+        }
+    }
+}
+
+/// Serialize a frame with its length prefix.
+pub fn encode(frame: &Frame) -> Vec<u8> {
+    with_header(serde_json::to_vec(frame).expect("frames always serialize"))
+}
+
+fn with_header(body: Vec<u8>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_LEN + body.len());
+    out.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    out.extend_from_slice(&body);
+    out
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The length prefix exceeds [`MAX_FRAME_LEN`].
+    TooLarge(usize),
+    /// Undecoded input would exceed [`MAX_BUFFERED_LEN`].
+    BufferFull(usize),
+    /// The body isn't a valid frame.
+    Invalid(serde_json::Error),
+    /// The body isn't a valid protobuf frame.
+    #[cfg(feature = "protobuf")]
+    InvalidProtobuf(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::TooLarge(len) => {
+                write!(f, "frame of {len} bytes exceeds limit of {MAX_FRAME_LEN}")
+            }
+            DecodeError::BufferFull(len) => {
+                write!(
+                    f,
+                    "{len} undecoded bytes exceed limit of {MAX_BUFFERED_LEN}"
+                )
+            }
+            DecodeError::Invalid(e) => write!(f, "invalid frame: {e}"),
+            #[cfg(feature = "protobuf")]
+            DecodeError::InvalidProtobuf(e) => write!(f, "invalid protobuf frame: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<DecodeError> for PlaygroundError {
+    fn from(error: DecodeError) -> Self {
+        PlaygroundError::MalformedFrame(error.to_string())
+    }
+}
+
+/// Incremental decoder: feed it whatever chunks the stream yields and pull out
+/// complete frames as they become available.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    framing: Framing,
+    buffer: Vec<u8>,
+    // Set once input has been discarded; the stream can't be resynchronized after that
+    overflow: Option<usize>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A decoder for frames delimited by `framing` rather than length prefixes.
+    pub fn with_framing(framing: Framing) -> Self {
+        Self {
+            framing,
+            ..Self::default()
+        }
+    }
+
+    /// Buffer `bytes`. Input that would take the buffer past [`MAX_BUFFERED_LEN`]
+    /// is discarded and reported by the next call to [`next_frame`](Self::next_frame),
+    /// so pull out frames after every push.
+    pub fn push(&mut self, bytes: &[u8]) {
+        let len = self.buffer.len() + bytes.len();
+        if self.overflow.is_some() || len > MAX_BUFFERED_LEN {
+            self.overflow.get_or_insert(len);
+            return;
+        }
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Bytes received but not yet consumed as frames.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Bytes still missing before [`next_frame`](Self::next_frame) can return
+    /// something: the rest of the header or of the frame's body. Zero once a frame
+    /// is complete, or when the next call would fail.
+    pub fn needed(&self) -> usize {
+        if self.overflow.is_some() {
+            return 0;
+        }
+        if self.framing == Framing::JsonLines {
+            // A line's length isn't known until its newline arrives
+            let complete = self.buffer.contains(&b'\n') || self.buffer.len() > MAX_FRAME_LEN;
+            return usize::from(!complete);
+        }
+        let Some(header) = self.buffer.first_chunk::<HEADER_LEN>() else {
+            return HEADER_LEN - self.buffer.len();
+        };
+        let len = u32::from_be_bytes(*header) as usize;
+        if len > MAX_FRAME_LEN {
+            return 0;
+        }
+        (HEADER_LEN + len).saturating_sub(self.buffer.len())
+    }
+
+    /// Next complete frame, or `None` if more bytes are needed.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, DecodeError> {
+        if let Some(len) = self.overflow {
+            return Err(DecodeError::BufferFull(len));
+        }
+        if self.framing == Framing::JsonLines {
+            return self.next_line();
+        }
+        let Some(header) = self.buffer.first_chunk::<HEADER_LEN>() else {
+            return Ok(None);
+        };
+
+        let len = u32::from_be_bytes(*header) as usize;
+        if len > MAX_FRAME_LEN {
+            return Err(DecodeError::TooLarge(len));
+        }
+        if self.buffer.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+
+        let body = &self.buffer[HEADER_LEN..HEADER_LEN + len];
+        let frame = match self.framing {
+            #[cfg(feature = "protobuf")]
+            Framing::Protobuf => {
+                crate::proto::decode_body(body).map_err(DecodeError::InvalidProtobuf)
+            }
+            _ => serde_json::from_slice(body).map_err(DecodeError::Invalid),
+        };
+        self.buffer.drain(..HEADER_LEN + len);
+        frame.map(Some)
+    }
+
+    // Next frame of a JSON-lines stream, skipping blank lines
+    fn next_line(&mut self) -> Result<Option<Frame>, DecodeError> {
+        loop {
+            let Some(end) = self.buffer.iter().position(|&b| b == b'\n') else {
+                if self.buffer.len() > MAX_FRAME_LEN {
+                    return Err(DecodeError::TooLarge(self.buffer.len()));
+                }
+                return Ok(None);
+            };
+            if end > MAX_FRAME_LEN {
+                return Err(DecodeError::TooLarge(end));
+            }
+
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = line[..end].trim_ascii();
+            if line.is_empty() {
+                continue;
+            }
+            return serde_json::from_slice(line)
+                .map(Some)
+                .map_err(DecodeError::Invalid);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+//! What the server tells each client about itself as a session opens.
+
+use serde::{Deserialize, Serialize};
+
+/// Version of the frame protocol this crate speaks, bumped on incompatible changes.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The server's capabilities and limits, sent in a `hello` frame on a
+/// server-opened unidirectional stream once the session is accepted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerInfo {
+    /// Protocol versions the server speaks; a client should check its own
+    /// [`PROTOCOL_VERSION`] is among them.
+    pub protocol_versions: Vec<u32>,
+    /// Largest datagram payload the session carries right now, or `None` if
+    /// the client didn't negotiate datagrams.
+    pub max_datagram_size: Option<u64>,
+    pub limits: Limits,
+    /// Optional capabilities this server has, e.g. `"protobuf"` or
+    /// `"reliable_datagrams"`.
+    pub features: Vec<String>,
+    pub build: BuildInfo,
+    /// Token a later connection sends in a `resume` frame to take over this
+    /// session, or `None` if the server doesn't resume sessions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<String>,
+}
+
+/// Application error code a stream opened over [`Limits::max_bi_streams`] or
+/// [`Limits::max_uni_streams`] is reset and stopped with.
+pub const STREAM_REFUSED_CODE: u8 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Limits {
+    /// Largest frame body accepted, as [`MAX_FRAME_LEN`](crate::MAX_FRAME_LEN).
+    pub max_frame_len: u64,
+    /// Messages per second per connection, across streams and datagrams; 0 means unlimited.
+    pub messages_per_second: u32,
+    /// Protocol violations after which the session is closed.
+    pub violation_strikes: u32,
+    /// Bidirectional streams a client may have open at once; 0 means unlimited.
+    /// Streams over the limit are reset and stopped with [`STREAM_REFUSED_CODE`].
+    #[serde(default)]
+    pub max_bi_streams: u32,
+    /// Unidirectional streams a client may have open at once; 0 means unlimited.
+    #[serde(default)]
+    pub max_uni_streams: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BuildInfo {
+    pub name: String,
+    pub version: String,
+    /// `"debug"` or `"release"`.
+    pub profile: String,
+}
@@ -0,0 +1,32 @@
+//! Wire protocol shared by the playground server and clients.
+//!
+//! Stream traffic is a sequence of frames, each a 4-byte big-endian length
+//! followed by that many bytes of JSON, or with [`Framing::JsonLines`] one
+//! line of JSON per frame. With the `protobuf` feature, [`Framing::Protobuf`]
+//! length-prefixes protobuf bodies instead (see [`proto`]).
+
+pub mod batch;
+pub mod burst;
+pub mod certs;
+mod close;
+mod error;
+pub mod expiry;
+mod frame;
+pub mod hello;
+pub mod pipeline;
+#[cfg(feature = "protobuf")]
+pub mod proto;
+pub mod raw;
+pub mod reliable;
+pub mod room;
+pub mod rpc;
+pub mod telemetry;
+pub mod transfer;
+pub mod vectors;
+
+pub use close::CloseCode;
+pub use error::PlaygroundError;
+pub use frame::{
+    DecodeError, Frame, FrameDecoder, Framing, MAX_BUFFERED_LEN, MAX_FRAME_LEN, MAX_MESSAGE_ID_LEN,
+    encode,
+};
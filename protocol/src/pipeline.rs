@@ -0,0 +1,33 @@
+//! Fan-in pipelines: clients publish to a named topic, and the server batches
+//! everything published in a window into one `Aggregate` frame per subscriber.
+
+use serde::{Deserialize, Serialize};
+
+/// Longest topic name, in bytes.
+pub const MAX_TOPIC_LEN: usize = 64;
+
+/// Most payload bytes one aggregate carries, as JSON-encoded, leaving room for the rest of the
+/// frame under [`MAX_FRAME_LEN`](crate::MAX_FRAME_LEN). Later payloads in the
+/// window are only counted.
+pub const MAX_AGGREGATE_BYTES: usize = 48 * 1024;
+
+/// Everything published to `topic` during one window.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Aggregate {
+    pub topic: String,
+    /// Length of the window, in milliseconds.
+    pub window_ms: u64,
+    /// Messages published in the window, including any not in `payloads`.
+    pub count: u64,
+    /// Distinct connections that published.
+    pub publishers: usize,
+    /// Published payloads in arrival order, up to [`MAX_AGGREGATE_BYTES`].
+    pub payloads: Vec<String>,
+    /// Messages counted but left out of `payloads` to keep the frame bounded.
+    pub truncated: u64,
+}
+
+/// Whether `topic` is an acceptable topic name.
+pub fn valid_topic(topic: &str) -> bool {
+    !topic.is_empty() && topic.len() <= MAX_TOPIC_LEN
+}
@@ -0,0 +1,373 @@
+//! Protobuf frame bodies: the types generated from `proto/playground.proto`,
+//! and conversions between them and [`Frame`].
+
+use crate::burst::BurstInstruction;
+use crate::hello::{BuildInfo, Limits, ServerInfo};
+use crate::pipeline::Aggregate;
+use crate::room::{RelayTransport, RoomAction};
+use crate::rpc::Outcome;
+use crate::telemetry::{BandwidthEstimate, ServerTiming};
+use crate::{Frame, PlaygroundError};
+use prost::Message as _;
+
+/// Types generated from `proto/playground.proto`.
+pub mod schema {
+    include!(concat!(env!("OUT_DIR"), "/playground.rs"));
+}
+
+use schema::frame::Kind;
+use schema::response::Outcome as WireOutcome;
+
+/// Encode a frame's body as protobuf.
+pub fn encode_body(frame: &Frame) -> Vec<u8> {
+    schema::Frame::from(frame.clone()).encode_to_vec()
+}
+
+/// Decode a protobuf frame body.
+pub fn decode_body(bytes: &[u8]) -> Result<Frame, String> {
+    let frame = schema::Frame::decode(bytes).map_err(|e| e.to_string())?;
+    Frame::try_from(frame)
+}
+
+impl From<Frame> for schema::Frame {
+    fn from(frame: Frame) -> Self {
+        let kind = match frame {
+            Frame::Message {
+                text,
+                seq,
+                id,
+                timing,
+            } => Kind::Message(schema::Message {
+                text,
+                seq,
+                id,
+                timing: timing.map(Into::into),
+            }),
+            Frame::Request { id, method, params } => Kind::Request(schema::Request {
+                id,
+                method,
+                params_json: params.to_string(),
+            }),
+            Frame::Response { id, outcome } => Kind::Response(schema::Response {
+                id,
+                outcome: Some(match outcome {
+                    Outcome::Result(result) => WireOutcome::ResultJson(result.to_string()),
+                    Outcome::Error(error) => WireOutcome::Error(error.into()),
+                }),
+            }),
+            Frame::Error { error } => Kind::Error(error.into()),
+            Frame::BandwidthEstimate(estimate) => {
+                Kind::BandwidthEstimate(schema::BandwidthEstimate {
+                    bits_per_second: estimate.bits_per_second,
+                    rtt_us: estimate.rtt_us,
+                    cwnd_bytes: estimate.cwnd_bytes,
+                    sent_packets: estimate.sent_packets,
+                    lost_packets: estimate.lost_packets,
+                    datagrams_received: estimate.datagrams_received,
+                })
+            }
+            Frame::Burst(burst) => Kind::Burst(schema::BurstInstruction {
+                burst_id: burst.burst_id,
+                at_unix_ms: burst.at_unix_ms,
+                count: burst.count,
+                payload_bytes: burst.payload_bytes as u64,
+            }),
+            Frame::Subscribe { topic } => Kind::Subscribe(schema::Topic { topic }),
+            Frame::Unsubscribe { topic } => Kind::Unsubscribe(schema::Topic { topic }),
+            Frame::Publish { topic, text } => Kind::Publish(schema::Publish { topic, text }),
+            Frame::Aggregate(aggregate) => Kind::Aggregate(schema::Aggregate {
+                topic: aggregate.topic,
+                window_ms: aggregate.window_ms,
+                count: aggregate.count,
+                publishers: aggregate.publishers as u64,
+                payloads: aggregate.payloads,
+                truncated: aggregate.truncated,
+            }),
+            Frame::Join { room, transport } => Kind::Join(schema::Join {
+                room,
+                transport: transport.map(|transport| transport.as_str().to_string()),
+            }),
+            Frame::Leave { room } => Kind::Leave(schema::Room { room }),
+            Frame::Relay {
+                room,
+                from,
+                nick,
+                text,
+                replayed,
+                id,
+                timing,
+            } => Kind::Relay(schema::Relay {
+                room,
+                from,
+                nick,
+                text,
+                replayed,
+                id,
+                timing: timing.map(Into::into),
+            }),
+            Frame::Redirect { url } => Kind::Redirect(schema::Redirect { url }),
+            Frame::Hello(info) => Kind::Hello(schema::Hello {
+                protocol_versions: info.protocol_versions,
+                max_datagram_size: info.max_datagram_size,
+                limits: Some(schema::Limits {
+                    max_frame_len: info.limits.max_frame_len,
+                    messages_per_second: info.limits.messages_per_second,
+                    violation_strikes: info.limits.violation_strikes,
+                    max_bi_streams: info.limits.max_bi_streams,
+                    max_uni_streams: info.limits.max_uni_streams,
+                }),
+                features: info.features,
+                build: Some(schema::BuildInfo {
+                    name: info.build.name,
+                    version: info.build.version,
+                    profile: info.build.profile,
+                }),
+                session_token: info.session_token,
+            }),
+            Frame::Resume { session_token } => Kind::Resume(schema::Resume { session_token }),
+            Frame::Resumed {
+                rooms,
+                nick,
+                missed,
+                dropped,
+            } => Kind::Resumed(schema::Resumed {
+                rooms,
+                nick,
+                missed,
+                dropped,
+            }),
+            Frame::Ping { nonce, deadline_ms } => Kind::Ping(schema::Ping { nonce, deadline_ms }),
+            Frame::Pong { nonce } => Kind::Pong(schema::Pong { nonce }),
+            Frame::Kick { room, member } => Kind::Kick(schema::RoomMember { room, member }),
+            Frame::Mute { room, member } => Kind::Mute(schema::RoomMember { room, member }),
+            Frame::Unmute { room, member } => Kind::Unmute(schema::RoomMember { room, member }),
+            Frame::RoomNotice {
+                room,
+                action,
+                member,
+                by,
+            } => Kind::RoomNotice(schema::RoomNotice {
+                room,
+                action: action.as_str().to_string(),
+                member,
+                by,
+            }),
+            Frame::TransferStart { id, name, size } => {
+                Kind::TransferStart(schema::TransferStart { id, name, size })
+            }
+            Frame::TransferChunk { id, offset, data } => {
+                Kind::TransferChunk(schema::TransferChunk { id, offset, data })
+            }
+            Frame::ResumeTransfer { id } => Kind::ResumeTransfer(schema::ResumeTransfer { id }),
+            Frame::TransferAck {
+                id,
+                offset,
+                checksum,
+            } => Kind::TransferAck(schema::TransferAck {
+                id,
+                offset,
+                checksum,
+            }),
+            Frame::QuotaWarning { used, limit } => {
+                Kind::QuotaWarning(schema::QuotaWarning { used, limit })
+            }
+        };
+        Self { kind: Some(kind) }
+    }
+}
+
+impl TryFrom<schema::Frame> for Frame {
+    type Error = String;
+
+    fn try_from(frame: schema::Frame) -> Result<Self, String> {
+        let kind = frame.kind.ok_or("frame has no kind")?;
+        Ok(match kind {
+            Kind::Message(message) => Frame::Message {
+                text: message.text,
+                seq: message.seq,
+                id: message.id,
+                timing: message.timing.map(Into::into),
+            },
+            Kind::Request(request) => Frame::Request {
+                id: request.id,
+                method: request.method,
+                params: json(&request.params_json)?,
+            },
+            Kind::Response(response) => Frame::Response {
+                id: response.id,
+                outcome: match response.outcome.ok_or("response has no outcome")? {
+                    WireOutcome::ResultJson(result) => Outcome::Result(json(&result)?),
+                    WireOutcome::Error(error) => Outcome::Error(error.into()),
+                },
+            },
+            Kind::Error(error) => Frame::Error {
+                error: error.into(),
+            },
+            Kind::BandwidthEstimate(estimate) => Frame::BandwidthEstimate(BandwidthEstimate {
+                bits_per_second: estimate.bits_per_second,
+                rtt_us: estimate.rtt_us,
+                cwnd_bytes: estimate.cwnd_bytes,
+                sent_packets: estimate.sent_packets,
+                lost_packets: estimate.lost_packets,
+                datagrams_received: estimate.datagrams_received,
+            }),
+            Kind::Burst(burst) => Frame::Burst(BurstInstruction {
+                burst_id: burst.burst_id,
+                at_unix_ms: burst.at_unix_ms,
+                count: burst.count,
+                payload_bytes: burst.payload_bytes as usize,
+            }),
+            Kind::Subscribe(topic) => Frame::Subscribe { topic: topic.topic },
+            Kind::Unsubscribe(topic) => Frame::Unsubscribe { topic: topic.topic },
+            Kind::Publish(publish) => Frame::Publish {
+                topic: publish.topic,
+                text: publish.text,
+            },
+            Kind::Aggregate(aggregate) => Frame::Aggregate(Aggregate {
+                topic: aggregate.topic,
+                window_ms: aggregate.window_ms,
+                count: aggregate.count,
+                publishers: aggregate.publishers as usize,
+                payloads: aggregate.payloads,
+                truncated: aggregate.truncated,
+            }),
+            Kind::Join(join) => Frame::Join {
+                room: join.room,
+                transport: match join.transport {
+                    Some(name) => Some(
+                        RelayTransport::from_name(&name)
+                            .ok_or_else(|| format!("unknown relay transport {:?}", name))?,
+                    ),
+                    None => None,
+                },
+            },
+            Kind::Leave(room) => Frame::Leave { room: room.room },
+            Kind::Relay(relay) => Frame::Relay {
+                room: relay.room,
+                from: relay.from,
+                nick: relay.nick,
+                text: relay.text,
+                replayed: relay.replayed,
+                id: relay.id,
+                timing: relay.timing.map(Into::into),
+            },
+            Kind::Redirect(redirect) => Frame::Redirect { url: redirect.url },
+            Kind::Hello(hello) => {
+                let limits = hello.limits.ok_or("hello has no limits")?;
+                let build = hello.build.ok_or("hello has no build info")?;
+                Frame::Hello(ServerInfo {
+                    protocol_versions: hello.protocol_versions,
+                    max_datagram_size: hello.max_datagram_size,
+                    limits: Limits {
+                        max_frame_len: limits.max_frame_len,
+                        messages_per_second: limits.messages_per_second,
+                        violation_strikes: limits.violation_strikes,
+                        max_bi_streams: limits.max_bi_streams,
+                        max_uni_streams: limits.max_uni_streams,
+                    },
+                    features: hello.features,
+                    build: BuildInfo {
+                        name: build.name,
+                        version: build.version,
+                        profile: build.profile,
+                    },
+                    session_token: hello.session_token,
+                })
+            }
+            Kind::Resume(resume) => Frame::Resume {
+                session_token: resume.session_token,
+            },
+            Kind::Resumed(resumed) => Frame::Resumed {
+                rooms: resumed.rooms,
+                nick: resumed.nick,
+                missed: resumed.missed,
+                dropped: resumed.dropped,
+            },
+            Kind::Ping(ping) => Frame::Ping {
+                nonce: ping.nonce,
+                deadline_ms: ping.deadline_ms,
+            },
+            Kind::Pong(pong) => Frame::Pong { nonce: pong.nonce },
+            Kind::Kick(kick) => Frame::Kick {
+                room: kick.room,
+                member: kick.member,
+            },
+            Kind::Mute(mute) => Frame::Mute {
+                room: mute.room,
+                member: mute.member,
+            },
+            Kind::Unmute(unmute) => Frame::Unmute {
+                room: unmute.room,
+                member: unmute.member,
+            },
+            Kind::RoomNotice(notice) => Frame::RoomNotice {
+                action: RoomAction::from_name(&notice.action)
+                    .ok_or_else(|| format!("unknown room action {:?}", notice.action))?,
+                room: notice.room,
+                member: notice.member,
+                by: notice.by,
+            },
+            Kind::TransferStart(start) => Frame::TransferStart {
+                id: start.id,
+                name: start.name,
+                size: start.size,
+            },
+            Kind::TransferChunk(chunk) => Frame::TransferChunk {
+                id: chunk.id,
+                offset: chunk.offset,
+                data: chunk.data,
+            },
+            Kind::ResumeTransfer(resume) => Frame::ResumeTransfer { id: resume.id },
+            Kind::TransferAck(ack) => Frame::TransferAck {
+                id: ack.id,
+                offset: ack.offset,
+                checksum: ack.checksum,
+            },
+            Kind::QuotaWarning(warning) => Frame::QuotaWarning {
+                used: warning.used,
+                limit: warning.limit,
+            },
+        })
+    }
+}
+
+impl From<PlaygroundError> for schema::Error {
+    fn from(error: PlaygroundError) -> Self {
+        Self {
+            code: error.code(),
+            message: error.detail().unwrap_or_default().to_string(),
+        }
+    }
+}
+
+impl From<schema::Error> for PlaygroundError {
+    fn from(error: schema::Error) -> Self {
+        PlaygroundError::from_code(error.code, error.message)
+    }
+}
+
+impl From<ServerTiming> for schema::ServerTiming {
+    fn from(timing: ServerTiming) -> Self {
+        Self {
+            received_us: timing.received_us,
+            sent_us: timing.sent_us,
+        }
+    }
+}
+
+impl From<schema::ServerTiming> for ServerTiming {
+    fn from(timing: schema::ServerTiming) -> Self {
+        Self {
+            received_us: timing.received_us,
+            sent_us: timing.sent_us,
+        }
+    }
+}
+
+// Embedded JSON; empty means null, as proto3 leaves unset strings empty
+fn json(text: &str) -> Result<serde_json::Value, String> {
+    if text.is_empty() {
+        return Ok(serde_json::Value::Null);
+    }
+    serde_json::from_str(text).map_err(|e| format!("invalid embedded JSON: {}", e))
+}
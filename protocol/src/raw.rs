@@ -0,0 +1,10 @@
+//! Raw streams: bidirectional streams carrying bytes instead of frames, for
+//! prototyping other protocols over a playground session.
+//!
+//! The opener writes [`RAW_PREAMBLE`] first and anything after it is the
+//! stream's payload, which the server echoes byte for byte. The preamble can't
+//! start a frame in any framing: as a length header it's over
+//! [`MAX_FRAME_LEN`](crate::MAX_FRAME_LEN), and it isn't JSON.
+
+/// Bidirectional streams starting with these bytes are raw.
+pub const RAW_PREAMBLE: &[u8] = b"\0raw";
@@ -0,0 +1,384 @@
+//! Reliable, ordered delivery rebuilt on top of datagrams: sequence numbers, a
+//! reorder buffer and NACK-based retransmission, with caps on both so a lost
+//! message costs bounded effort before it's given up. Pure state machines; the
+//! caller moves the datagrams and supplies the clock.
+//!
+//! Loss is only noticed when a later message arrives, so losing the last
+//! messages sent goes undetected until something follows them.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+/// Datagrams starting with these bytes belong to the reliable layer. The server
+/// reflects them back to the sender.
+pub const RELIABLE_PREFIX: &[u8] = b"\0rel";
+
+const DATA: u8 = 0;
+const NACK: u8 = 1;
+
+/// Most sequence numbers in one NACK datagram.
+pub const MAX_NACK_SEQS: usize = 64;
+
+/// A decoded reliable-layer datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet<'a> {
+    /// Message `seq` of the sender's sequence.
+    Data { seq: u32, payload: &'a [u8] },
+    /// The receiver is missing these messages.
+    Nack(Vec<u32>),
+}
+
+/// Build the datagram carrying message `seq`.
+pub fn encode_data(seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(RELIABLE_PREFIX.len() + 5 + payload.len());
+    datagram.extend_from_slice(RELIABLE_PREFIX);
+    datagram.push(DATA);
+    datagram.extend_from_slice(&seq.to_be_bytes());
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+/// Build the NACK datagrams asking for `seqs`, [`MAX_NACK_SEQS`] to a datagram.
+pub fn encode_nacks(seqs: &[u32]) -> Vec<Vec<u8>> {
+    seqs.chunks(MAX_NACK_SEQS)
+        .map(|chunk| {
+            let mut datagram = Vec::with_capacity(RELIABLE_PREFIX.len() + 1 + 4 * chunk.len());
+            datagram.extend_from_slice(RELIABLE_PREFIX);
+            datagram.push(NACK);
+            for seq in chunk {
+                datagram.extend_from_slice(&seq.to_be_bytes());
+            }
+            datagram
+        })
+        .collect()
+}
+
+/// Decode a reliable-layer datagram, or `None` if `datagram` isn't one.
+pub fn decode(datagram: &[u8]) -> Option<Packet<'_>> {
+    let (kind, rest) = datagram.strip_prefix(RELIABLE_PREFIX)?.split_first()?;
+    match *kind {
+        DATA => {
+            let (seq, payload) = rest.split_first_chunk::<4>()?;
+            Some(Packet::Data {
+                seq: u32::from_be_bytes(*seq),
+                payload,
+            })
+        }
+        NACK if rest.len() % 4 == 0 => Some(Packet::Nack(
+            rest.chunks_exact(4)
+                .map(|seq| u32::from_be_bytes(seq.try_into().unwrap()))
+                .collect(),
+        )),
+        _ => None,
+    }
+}
+
+/// Knobs for both ends. Every field is optional when deserialized.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReliableConfig {
+    /// Messages the sender keeps for retransmission; older ones can't be recovered.
+    pub history: usize,
+    /// Times the sender retransmits one message.
+    pub max_retransmits: u32,
+    /// Times the receiver asks for one missing message before giving up on it.
+    pub max_nacks: u32,
+    /// How long the receiver waits for a NACKed message before asking again.
+    pub nack_interval_ms: u64,
+    /// Messages the receiver holds waiting for a gap to fill. Past this, it gives
+    /// up on the oldest gap.
+    pub max_buffered: usize,
+}
+
+impl Default for ReliableConfig {
+    fn default() -> Self {
+        Self {
+            history: 256,
+            max_retransmits: 3,
+            max_nacks: 3,
+            nack_interval_ms: 100,
+            max_buffered: 256,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct SenderStats {
+    /// Messages sent, not counting retransmissions.
+    pub sent: u64,
+    pub retransmitted: u64,
+    /// Bytes of retransmitted datagrams: the cost of the layer over plain datagrams.
+    pub retransmitted_bytes: u64,
+    /// Sequence numbers asked for, by every NACK received.
+    pub nacked: u64,
+    /// NACKed messages not retransmitted: out of history or out of retransmits.
+    pub unrecoverable: u64,
+}
+
+struct Sent {
+    seq: u32,
+    payload: Vec<u8>,
+    retransmits: u32,
+}
+
+/// The sending end: numbers messages and answers NACKs from its history.
+pub struct Sender {
+    config: ReliableConfig,
+    next_seq: u32,
+    // Oldest first, consecutive sequence numbers
+    history: VecDeque<Sent>,
+    stats: SenderStats,
+}
+
+impl Sender {
+    pub fn new(config: ReliableConfig) -> Self {
+        Self {
+            config,
+            next_seq: 0,
+            history: VecDeque::new(),
+            stats: SenderStats::default(),
+        }
+    }
+
+    /// Number `payload` and return its sequence number and datagram.
+    pub fn send(&mut self, payload: &[u8]) -> (u32, Vec<u8>) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.history.push_back(Sent {
+            seq,
+            payload: payload.to_vec(),
+            retransmits: 0,
+        });
+        if self.history.len() > self.config.history {
+            self.history.pop_front();
+        }
+        self.stats.sent += 1;
+        (seq, encode_data(seq, payload))
+    }
+
+    /// Datagrams retransmitting whichever of `seqs` are still in history and
+    /// under the retransmit cap.
+    pub fn on_nack(&mut self, seqs: &[u32]) -> Vec<Vec<u8>> {
+        self.stats.nacked += seqs.len() as u64;
+        let oldest = self.history.front().map_or(self.next_seq, |sent| sent.seq);
+        let mut datagrams = Vec::new();
+        for &seq in seqs {
+            let sent = seq
+                .checked_sub(oldest)
+                .and_then(|index| self.history.get_mut(index as usize));
+            let Some(sent) = sent else {
+                self.stats.unrecoverable += 1;
+                continue;
+            };
+            if sent.retransmits >= self.config.max_retransmits {
+                self.stats.unrecoverable += 1;
+                continue;
+            }
+            sent.retransmits += 1;
+            let datagram = encode_data(seq, &sent.payload);
+            self.stats.retransmitted += 1;
+            self.stats.retransmitted_bytes += datagram.len() as u64;
+            datagrams.push(datagram);
+        }
+        datagrams
+    }
+
+    pub fn stats(&self) -> SenderStats {
+        self.stats
+    }
+
+    /// Messages kept for retransmission, and the bytes of their payloads.
+    pub fn history(&self) -> (usize, usize) {
+        let bytes = self.history.iter().map(|sent| sent.payload.len()).sum();
+        (self.history.len(), bytes)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ReceiverStats {
+    /// Messages delivered, in order.
+    pub delivered: u64,
+    /// Messages that arrived ahead of a gap and waited in the reorder buffer.
+    pub reordered: u64,
+    /// Copies of messages already received or given up on.
+    pub duplicates: u64,
+    /// Missing messages that arrived after being NACKed.
+    pub recovered: u64,
+    /// Messages given up on; delivery skipped past them.
+    pub lost: u64,
+    /// Sequence numbers asked for in NACKs.
+    pub nacked: u64,
+}
+
+/// What the receiver wants done after an arrival or a tick.
+#[derive(Debug, Default)]
+pub struct Output {
+    /// Messages now deliverable, in order, with their sequence numbers.
+    pub delivered: Vec<(u32, Vec<u8>)>,
+    /// Sequence numbers to NACK now.
+    pub nack: Vec<u32>,
+    /// Messages given up on by this call.
+    pub lost: u64,
+}
+
+struct Missing {
+    nacks: u32,
+    last_nack_ms: u64,
+}
+
+/// The receiving end: delivers in order, buffering ahead of gaps and NACKing them.
+pub struct Receiver {
+    config: ReliableConfig,
+    /// Next sequence number to deliver.
+    next_seq: u32,
+    /// One past the highest sequence number seen.
+    end: u32,
+    buffered: BTreeMap<u32, Vec<u8>>,
+    missing: BTreeMap<u32, Missing>,
+    /// Missing messages given up on before delivery reached them.
+    abandoned: BTreeSet<u32>,
+    stats: ReceiverStats,
+}
+
+impl Receiver {
+    pub fn new(config: ReliableConfig) -> Self {
+        Self {
+            config,
+            next_seq: 0,
+            end: 0,
+            buffered: BTreeMap::new(),
+            missing: BTreeMap::new(),
+            abandoned: BTreeSet::new(),
+            stats: ReceiverStats::default(),
+        }
+    }
+
+    /// Take in message `seq`, received at `now_ms`.
+    pub fn on_data(&mut self, seq: u32, payload: &[u8], now_ms: u64) -> Output {
+        let mut out = Output::default();
+        if seq < self.next_seq || self.buffered.contains_key(&seq) || self.abandoned.contains(&seq)
+        {
+            self.stats.duplicates += 1;
+            return out;
+        }
+
+        // A message far ahead gives up on everything the buffer couldn't hold anyway
+        let window = self.config.max_buffered.max(1) as u32;
+        if seq - self.next_seq >= window {
+            self.skip_to(seq - window + 1, &mut out);
+        }
+
+        if self.missing.remove(&seq).is_some() {
+            self.stats.recovered += 1;
+        }
+        if seq >= self.end {
+            for gap in self.end.max(self.next_seq)..seq {
+                self.missing.insert(
+                    gap,
+                    Missing {
+                        nacks: 1,
+                        last_nack_ms: now_ms,
+                    },
+                );
+                out.nack.push(gap);
+            }
+            self.end = seq + 1;
+        }
+        if seq != self.next_seq {
+            self.stats.reordered += 1;
+        }
+        self.buffered.insert(seq, payload.to_vec());
+
+        self.deliver(&mut out);
+        while self.buffered.len() > self.config.max_buffered {
+            let Some(&first) = self.buffered.keys().next() else {
+                break;
+            };
+            self.skip_to(first, &mut out);
+            self.deliver(&mut out);
+        }
+        self.stats.nacked += out.nack.len() as u64;
+        out
+    }
+
+    /// NACK missing messages again once `nack_interval_ms` has passed, and give
+    /// up on those asked for `max_nacks` times. Call periodically while
+    /// [`waiting`](Self::waiting).
+    pub fn poll(&mut self, now_ms: u64) -> Output {
+        let mut out = Output::default();
+        let mut given_up = Vec::new();
+        for (&seq, missing) in &mut self.missing {
+            if now_ms.saturating_sub(missing.last_nack_ms) < self.config.nack_interval_ms {
+                continue;
+            }
+            if missing.nacks >= self.config.max_nacks {
+                given_up.push(seq);
+                continue;
+            }
+            missing.nacks += 1;
+            missing.last_nack_ms = now_ms;
+            out.nack.push(seq);
+        }
+        for seq in given_up {
+            self.missing.remove(&seq);
+            self.abandoned.insert(seq);
+        }
+        self.deliver(&mut out);
+        self.stats.nacked += out.nack.len() as u64;
+        out
+    }
+
+    /// Whether any gap is still being waited for.
+    pub fn waiting(&self) -> bool {
+        !self.missing.is_empty()
+    }
+
+    pub fn stats(&self) -> ReceiverStats {
+        self.stats
+    }
+
+    /// Messages held in the reorder buffer ahead of a gap, and the bytes of
+    /// their payloads.
+    pub fn buffered(&self) -> (usize, usize) {
+        let bytes = self.buffered.values().map(Vec::len).sum();
+        (self.buffered.len(), bytes)
+    }
+
+    // Deliver buffered messages from next_seq on, skipping abandoned ones
+    fn deliver(&mut self, out: &mut Output) {
+        loop {
+            if let Some(payload) = self.buffered.remove(&self.next_seq) {
+                self.stats.delivered += 1;
+                out.delivered.push((self.next_seq, payload));
+            } else if self.abandoned.remove(&self.next_seq) {
+                self.stats.lost += 1;
+                out.lost += 1;
+            } else {
+                break;
+            }
+            self.next_seq += 1;
+        }
+    }
+
+    // Give up on every missing message before `seq`, delivering what's buffered among them
+    fn skip_to(&mut self, seq: u32, out: &mut Output) {
+        let skipped = seq - self.next_seq;
+        let mut delivered = 0;
+        while let Some(entry) = self.buffered.first_entry() {
+            if *entry.key() >= seq {
+                break;
+            }
+            let (seq, payload) = entry.remove_entry();
+            out.delivered.push((seq, payload));
+            delivered += 1;
+        }
+        let lost = u64::from(skipped) - delivered;
+        self.stats.delivered += delivered;
+        self.stats.lost += lost;
+        out.lost += lost;
+        self.missing = self.missing.split_off(&seq);
+        self.abandoned = self.abandoned.split_off(&seq);
+        self.next_seq = seq;
+        self.end = self.end.max(seq);
+    }
+}
@@ -0,0 +1,104 @@
+//! Chat rooms: clients join any number of named rooms, and the server relays
+//! each message sent to a room to the room's other members, tagged with the room.
+
+use crate::Frame;
+use serde::{Deserialize, Serialize};
+
+/// Longest room name, in bytes.
+pub const MAX_ROOM_LEN: usize = 64;
+
+/// Most rooms one connection may be in at once.
+pub const MAX_ROOMS: usize = 16;
+
+/// Whether `room` is an acceptable room name.
+pub fn valid_room(room: &str) -> bool {
+    !room.is_empty() && room.len() <= MAX_ROOM_LEN
+}
+
+/// How relays reach a room's members: set per room by the server, and
+/// overridable by each member in its `join` frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayTransport {
+    /// On a server-opened unidirectional stream: reliable and ordered.
+    #[default]
+    Stream,
+    /// In a relay datagram (see [`encode_datagram`]): quicker, but may be lost
+    /// or reordered. Members whose session has no datagrams, and relays too big
+    /// for one, get the stream instead.
+    Datagram,
+    /// Both at once: the datagram usually arrives first and the stream copy
+    /// makes up for it if lost. Receivers drop the second copy by its `id`.
+    Both,
+}
+
+impl RelayTransport {
+    /// The name it's serialized as.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Stream => "stream",
+            Self::Datagram => "datagram",
+            Self::Both => "both",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Stream, Self::Datagram, Self::Both]
+            .into_iter()
+            .find(|transport| transport.as_str() == name)
+    }
+
+    pub fn uses_datagrams(self) -> bool {
+        self != Self::Stream
+    }
+}
+
+/// What a `room_notice` reports happened to a member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoomAction {
+    /// It owns the room now: it created it, claimed it with the room's owner
+    /// token, or the previous owner left.
+    Owner,
+    Kicked,
+    Muted,
+    Unmuted,
+}
+
+impl RoomAction {
+    /// The name it's serialized as.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Owner => "owner",
+            Self::Kicked => "kicked",
+            Self::Muted => "muted",
+            Self::Unmuted => "unmuted",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Owner, Self::Kicked, Self::Muted, Self::Unmuted]
+            .into_iter()
+            .find(|action| action.as_str() == name)
+    }
+}
+
+/// Datagrams starting with these bytes carry a relay.
+pub const RELAY_PREFIX: &[u8] = b"\0room";
+
+/// Build the datagram carrying `relay`: [`RELAY_PREFIX`], then the frame as
+/// JSON, whatever framing the session uses for streams.
+pub fn encode_datagram(relay: &Frame) -> Vec<u8> {
+    let mut datagram = RELAY_PREFIX.to_vec();
+    serde_json::to_writer(&mut datagram, relay).expect("frames always serialize");
+    datagram
+}
+
+/// The relay frame a relay datagram carries, or `None` if it isn't one.
+pub fn decode_datagram(datagram: &[u8]) -> Option<Frame> {
+    let body = datagram.strip_prefix(RELAY_PREFIX)?;
+    match serde_json::from_slice(body).ok()? {
+        relay @ Frame::Relay { .. } => Some(relay),
+        _ => None,
+    }
+}
@@ -0,0 +1,128 @@
+//! Typed parameters and results for the server's RPC methods.
+//!
+//! A `Frame::Request` names a method and carries its params as JSON; the
+//! server answers with a `Frame::Response` holding either a result or a [`PlaygroundError`].
+
+use crate::PlaygroundError;
+use serde::{Deserialize, Serialize};
+
+pub const ECHO: &str = "echo";
+pub const TIME: &str = "time";
+pub const STATS: &str = "stats";
+pub const ROLL_DICE: &str = "roll_dice";
+pub const REPORT_MTU: &str = "report_mtu";
+pub const HEARTBEAT: &str = "heartbeat";
+pub const REPORT_VISIBILITY: &str = "report_visibility";
+pub const DRIP: &str = "drip";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EchoParams {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EchoResult {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimeResult {
+    /// Server wall clock, milliseconds since the Unix epoch.
+    pub unix_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatsResult {
+    /// Server-assigned id of the calling connection.
+    pub connection_id: u64,
+    pub connections: usize,
+    pub uptime_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RollDiceParams {
+    #[serde(default = "RollDiceParams::default_sides")]
+    pub sides: u32,
+    #[serde(default = "RollDiceParams::default_count")]
+    pub count: u32,
+}
+
+impl RollDiceParams {
+    fn default_sides() -> u32 {
+        6
+    }
+
+    fn default_count() -> u32 {
+        1
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RollDiceResult {
+    pub rolls: Vec<u32>,
+    pub total: u32,
+}
+
+/// A client's result from MTU discovery, kept in the server's stats.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MtuReport {
+    /// Largest datagram that reliably made the round trip, in bytes.
+    pub max_datagram_size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeartbeatParams {
+    /// How long the client means to wait before its next heartbeat, in ms.
+    /// A heartbeat arriving much later than announced suggests a throttled background tab.
+    pub interval_ms: u32,
+}
+
+/// A client's page was hidden or shown again (the Page Visibility API).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VisibilityReport {
+    pub visible: bool,
+}
+
+/// Ask the server for a `message` frame of `text_bytes` characters, written on a
+/// new unidirectional stream `chunk_bytes` at a time with `delay_ms` between
+/// writes, to watch incremental reads on the client.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DripParams {
+    #[serde(default = "DripParams::default_text_bytes")]
+    pub text_bytes: u32,
+    #[serde(default = "DripParams::default_chunk_bytes")]
+    pub chunk_bytes: u32,
+    #[serde(default = "DripParams::default_delay_ms")]
+    pub delay_ms: u32,
+}
+
+impl DripParams {
+    fn default_text_bytes() -> u32 {
+        64
+    }
+
+    fn default_chunk_bytes() -> u32 {
+        1
+    }
+
+    fn default_delay_ms() -> u32 {
+        100
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DripResult {
+    /// Bytes the stream will carry, framing included.
+    pub total_bytes: u32,
+    pub chunks: u32,
+    /// How long the drip will take, in ms.
+    pub duration_ms: u64,
+}
+
+/// Body of a `Frame::Response`: `{"result": ...}` or `{"error": {...}}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Result(serde_json::Value),
+    Error(PlaygroundError),
+}
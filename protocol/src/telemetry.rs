@@ -0,0 +1,92 @@
+//! Measurements the server pushes to clients about their connection.
+
+use serde::{Deserialize, Serialize};
+
+/// Datagrams starting with these bytes are rate-test load: the server counts
+/// them but doesn't log, moderate, or echo them.
+pub const PROBE_PREFIX: &[u8] = b"\0probe";
+
+/// The server's view of a connection's path, sent periodically on its push stream.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BandwidthEstimate {
+    /// Estimated available bandwidth: congestion window divided by smoothed RTT.
+    pub bits_per_second: u64,
+    /// Smoothed round-trip time in microseconds.
+    pub rtt_us: u64,
+    /// Current congestion window in bytes.
+    pub cwnd_bytes: u64,
+    /// Packets sent on the path since the connection started.
+    pub sent_packets: u64,
+    /// Packets lost on the path since the connection started.
+    pub lost_packets: u64,
+    /// Datagrams received from the client since the connection started. Comparing
+    /// deltas against datagrams sent gives the client its datagram loss rate.
+    pub datagrams_received: u64,
+}
+
+/// When the server read a message and when it sent the frame carrying it back
+/// (an echo) or on (a relay), in microseconds on the server's monotonic clock.
+/// The clock starts at an arbitrary point, so only the difference means
+/// anything: the time the message spent in the server. A round trip less that
+/// is the time spent on the network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerTiming {
+    pub received_us: u64,
+    pub sent_us: u64,
+}
+
+impl ServerTiming {
+    /// A message read at `received_us` and not yet sent.
+    pub fn received(received_us: u64) -> Self {
+        Self {
+            received_us,
+            sent_us: received_us,
+        }
+    }
+
+    /// Microseconds between receiving and sending.
+    pub fn dwell_us(&self) -> u64 {
+        self.sent_us.saturating_sub(self.received_us)
+    }
+}
+
+/// Datagrams starting with these bytes probe the usable datagram size. The
+/// server answers each with a short ack: the same prefix and probe id, no padding.
+pub const MTU_PREFIX: &[u8] = b"\0mtu";
+
+const MTU_HEADER_LEN: usize = MTU_PREFIX.len() + 4;
+
+/// Build MTU probe `probe_id`, padded to `len` bytes. A `len` shorter than the
+/// header gives the bare ack.
+pub fn encode_mtu_probe(probe_id: u32, len: usize) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(len.max(MTU_HEADER_LEN));
+    datagram.extend_from_slice(MTU_PREFIX);
+    datagram.extend_from_slice(&probe_id.to_be_bytes());
+    datagram.resize(len.max(MTU_HEADER_LEN), 0);
+    datagram
+}
+
+/// Probe id of an MTU probe or ack, or `None` if `datagram` isn't one.
+pub fn decode_mtu_probe(datagram: &[u8]) -> Option<u32> {
+    let id = datagram.strip_prefix(MTU_PREFIX)?.first_chunk::<4>()?;
+    Some(u32::from_be_bytes(*id))
+}
+
+/// Datagrams starting with these bytes are pings. The server sends each one
+/// back unchanged, through the connection's impairment, so pings see the same
+/// delay and loss as other datagrams.
+pub const PING_PREFIX: &[u8] = b"\0ping";
+
+/// Build ping `ping_id`.
+pub fn encode_ping(ping_id: u32) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(PING_PREFIX.len() + 4);
+    datagram.extend_from_slice(PING_PREFIX);
+    datagram.extend_from_slice(&ping_id.to_be_bytes());
+    datagram
+}
+
+/// Ping id of a ping or its echo, or `None` if `datagram` isn't one.
+pub fn decode_ping(datagram: &[u8]) -> Option<u32> {
+    let id = datagram.strip_prefix(PING_PREFIX)?.first_chunk::<4>()?;
+    Some(u32::from_be_bytes(*id))
+}
@@ -0,0 +1,40 @@
+//! Resumable file uploads: a `transfer_start`, then `transfer_chunk`s in
+//! order, each answered by a `transfer_ack` confirming how much the server has.
+//! A client cut off mid-transfer reconnects, asks where it stands with a
+//! `resume_transfer`, checks the acked [`checksum`] against its own copy and
+//! carries on from the acked offset.
+
+/// Most bytes a `transfer_chunk` carries, before hex encoding doubles them.
+pub const MAX_CHUNK_LEN: usize = 16 * 1024;
+
+/// Checksum of no bytes, where a transfer's starts.
+pub const EMPTY_CHECKSUM: u32 = 0;
+
+/// CRC-32 (as in zlib) of the bytes `checksum` covers followed by `bytes`, so
+/// a transfer's checksum is carried forward chunk by chunk.
+pub fn checksum(checksum: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !checksum;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// A chunk's bytes as they go in its `data`: lowercase hex.
+pub fn encode_chunk(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The bytes a chunk's `data` spells out, or `None` if it isn't hex.
+pub fn decode_chunk(data: &str) -> Option<Vec<u8>> {
+    if !data.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..data.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(data.get(at..at + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,537 @@
+//! Canonical encodings of the wire protocol, for checking other implementations
+//! against this one.
+//!
+//! [`generate`] builds one vector per frame type in every framing this build
+//! supports, plus the datagram formats, each with its exact bytes in hex. The
+//! `vectors` binary writes them out as JSON, and `vectors/vectors.json` holds
+//! the output of a `protobuf` build:
+//!
+//! ```bash
+//! cargo run -p protocol --features protobuf --bin vectors > protocol/vectors/vectors.json
+//! ```
+//!
+//! A client passes if it decodes each `hex` to the vector's `frame` (or
+//! datagram fields) and encodes them back to the same bytes. JSON bodies are
+//! compared byte for byte, so an encoder must also keep field order and omit
+//! the optional fields that are unset.
+
+use crate::burst::{self, BurstInstruction};
+use crate::hello::{BuildInfo, Limits, PROTOCOL_VERSION, ServerInfo};
+use crate::pipeline::Aggregate;
+use crate::room::{self, RelayTransport, RoomAction};
+use crate::rpc::Outcome;
+use crate::telemetry::{self, BandwidthEstimate, ServerTiming};
+use crate::transfer;
+use crate::{Frame, Framing, MAX_FRAME_LEN, PlaygroundError, batch, expiry, reliable};
+use serde::{Deserialize, Serialize};
+
+/// Every vector, as written by the `vectors` binary.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Vectors {
+    /// The [`PROTOCOL_VERSION`] the vectors were generated for.
+    pub protocol_version: u32,
+    pub frames: Vec<FrameVector>,
+    pub datagrams: Vec<DatagramVector>,
+}
+
+/// A frame and its bytes on a stream delimited by `framing`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrameVector {
+    pub name: String,
+    pub framing: Framing,
+    pub frame: Frame,
+    pub hex: String,
+}
+
+/// A datagram and its bytes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DatagramVector {
+    pub name: String,
+    #[serde(flatten)]
+    pub datagram: Datagram,
+    pub hex: String,
+}
+
+/// The datagram formats that aren't plain text.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Datagram {
+    /// A batch of messages (see [`batch`]).
+    Batch { messages: Vec<String> },
+    /// Reliable-layer message `seq` (see [`reliable`]).
+    ReliableData { seq: u32, payload: String },
+    /// Reliable-layer request to resend `seqs`.
+    ReliableNack { seqs: Vec<u32> },
+    /// MTU probe `probe_id` padded to `len` bytes (see [`telemetry`]).
+    MtuProbe { probe_id: u32, len: usize },
+    /// Ping `ping_id` (see [`telemetry`]).
+    Ping { ping_id: u32 },
+    /// Datagram `seq` of burst `burst_id`, padded to `len` bytes (see [`burst`]).
+    Burst { burst_id: u64, seq: u32, len: usize },
+    /// `payload`, dropped once older than `max_age_ms` (see [`expiry`]).
+    Expiring {
+        sent_unix_ms: u64,
+        max_age_ms: u32,
+        payload: String,
+    },
+    /// A relay delivered as a datagram (see [`room::encode_datagram`]).
+    Relay { frame: Frame },
+}
+
+impl Datagram {
+    /// The datagram's bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Datagram::Batch { messages } => batch::pack(messages, usize::MAX)
+                .into_iter()
+                .next()
+                .unwrap_or_default(),
+            Datagram::ReliableData { seq, payload } => {
+                reliable::encode_data(*seq, payload.as_bytes())
+            }
+            Datagram::ReliableNack { seqs } => reliable::encode_nacks(seqs)
+                .into_iter()
+                .next()
+                .unwrap_or_default(),
+            Datagram::MtuProbe { probe_id, len } => telemetry::encode_mtu_probe(*probe_id, *len),
+            Datagram::Ping { ping_id } => telemetry::encode_ping(*ping_id),
+            Datagram::Burst { burst_id, seq, len } => burst::encode_datagram(*burst_id, *seq, *len),
+            Datagram::Expiring {
+                sent_unix_ms,
+                max_age_ms,
+                payload,
+            } => expiry::encode(*sent_unix_ms, *max_age_ms, payload.as_bytes()),
+            Datagram::Relay { frame } => room::encode_datagram(frame),
+        }
+    }
+}
+
+/// Build every vector this build can encode. Protobuf ones need the `protobuf`
+/// feature.
+pub fn generate() -> Vectors {
+    let framings = [
+        Framing::LengthPrefixed,
+        Framing::JsonLines,
+        #[cfg(feature = "protobuf")]
+        Framing::Protobuf,
+    ];
+    let samples = frames();
+    let mut frames = Vec::new();
+    for framing in framings {
+        let prefix = serde_json::to_value(framing).expect("framings always serialize");
+        for (name, frame) in &samples {
+            frames.push(FrameVector {
+                name: format!("{}/{}", prefix.as_str().unwrap_or_default(), name),
+                framing,
+                hex: to_hex(&framing.encode(frame)),
+                frame: frame.clone(),
+            });
+        }
+    }
+
+    let datagrams = datagrams()
+        .into_iter()
+        .map(|(name, datagram)| DatagramVector {
+            name: name.to_string(),
+            hex: to_hex(&datagram.encode()),
+            datagram,
+        })
+        .collect();
+
+    Vectors {
+        protocol_version: PROTOCOL_VERSION,
+        frames,
+        datagrams,
+    }
+}
+
+// One sample of every frame type, and of the optional fields and escapes an
+// encoder is most likely to get wrong
+fn frames() -> Vec<(&'static str, Frame)> {
+    vec![
+        (
+            "message",
+            Frame::Message {
+                text: "hello".to_string(),
+                seq: None,
+                id: None,
+                timing: None,
+            },
+        ),
+        (
+            "message_with_seq",
+            Frame::Message {
+                text: "numbered".to_string(),
+                seq: Some(42),
+                id: None,
+                timing: None,
+            },
+        ),
+        (
+            "message_with_id",
+            Frame::Message {
+                text: "once".to_string(),
+                seq: None,
+                id: Some("6f9619ff-8b86-4011-b42d-00c04fc964ff".to_string()),
+                timing: None,
+            },
+        ),
+        (
+            "message_escapes",
+            Frame::Message {
+                text: "line\nbreak, \"quotes\", tab\t and caf\u{e9} \u{1f680}".to_string(),
+                seq: None,
+                id: None,
+                timing: None,
+            },
+        ),
+        (
+            "message_echoed",
+            Frame::Message {
+                text: "Server echo: numbered".to_string(),
+                seq: Some(42),
+                id: None,
+                timing: Some(ServerTiming {
+                    received_us: 1_500_000,
+                    sent_us: 1_500_250,
+                }),
+            },
+        ),
+        (
+            "request",
+            Frame::Request {
+                id: 7,
+                method: "echo".to_string(),
+                params: serde_json::json!({ "text": "ping" }),
+            },
+        ),
+        (
+            "response_result",
+            Frame::Response {
+                id: 7,
+                outcome: Outcome::Result(serde_json::json!({ "text": "ping" })),
+            },
+        ),
+        (
+            "response_error",
+            Frame::Response {
+                id: 8,
+                outcome: Outcome::Error(PlaygroundError::MethodNotFound("nope".to_string())),
+            },
+        ),
+        (
+            "error",
+            Frame::Error {
+                error: PlaygroundError::RateLimited("message dropped".to_string()),
+            },
+        ),
+        (
+            "bandwidth_estimate",
+            Frame::BandwidthEstimate(BandwidthEstimate {
+                bits_per_second: 12_000_000,
+                rtt_us: 25_000,
+                cwnd_bytes: 37_500,
+                sent_packets: 1200,
+                lost_packets: 3,
+                datagrams_received: 450,
+            }),
+        ),
+        (
+            "burst",
+            Frame::Burst(BurstInstruction {
+                burst_id: 1,
+                at_unix_ms: 1_700_000_000_000,
+                count: 20,
+                payload_bytes: 100,
+            }),
+        ),
+        (
+            "subscribe",
+            Frame::Subscribe {
+                topic: "ticks".to_string(),
+            },
+        ),
+        (
+            "unsubscribe",
+            Frame::Unsubscribe {
+                topic: "ticks".to_string(),
+            },
+        ),
+        (
+            "publish",
+            Frame::Publish {
+                topic: "ticks".to_string(),
+                text: "42".to_string(),
+            },
+        ),
+        (
+            "aggregate",
+            Frame::Aggregate(Aggregate {
+                topic: "ticks".to_string(),
+                window_ms: 1000,
+                count: 3,
+                publishers: 2,
+                payloads: vec!["41".to_string(), "42".to_string()],
+                truncated: 1,
+            }),
+        ),
+        (
+            "join",
+            Frame::Join {
+                room: "lobby".to_string(),
+                transport: None,
+            },
+        ),
+        (
+            "join_datagrams",
+            Frame::Join {
+                room: "lobby".to_string(),
+                transport: Some(RelayTransport::Both),
+            },
+        ),
+        (
+            "leave",
+            Frame::Leave {
+                room: "lobby".to_string(),
+            },
+        ),
+        (
+            "relay_sent",
+            Frame::Relay {
+                room: "lobby".to_string(),
+                from: None,
+                nick: None,
+                text: "hi all".to_string(),
+                replayed: false,
+                id: None,
+                timing: None,
+            },
+        ),
+        (
+            "relay_received",
+            Frame::Relay {
+                room: "lobby".to_string(),
+                from: Some(3),
+                nick: Some("alice".to_string()),
+                text: "hi all".to_string(),
+                replayed: true,
+                id: Some("6f9619ff-8b86-4011-b42d-00c04fc964ff".to_string()),
+                timing: Some(ServerTiming {
+                    received_us: 2_000_000,
+                    sent_us: 2_004_100,
+                }),
+            },
+        ),
+        (
+            "redirect",
+            Frame::Redirect {
+                url: "https://localhost:8766/".to_string(),
+            },
+        ),
+        (
+            "hello",
+            Frame::Hello(ServerInfo {
+                protocol_versions: vec![PROTOCOL_VERSION],
+                max_datagram_size: Some(1200),
+                limits: Limits {
+                    max_frame_len: MAX_FRAME_LEN as u64,
+                    messages_per_second: 0,
+                    violation_strikes: 3,
+                    max_bi_streams: 100,
+                    max_uni_streams: 0,
+                },
+                features: vec!["reliable_datagrams".to_string(), "resume".to_string()],
+                build: BuildInfo {
+                    name: "wtransport-test".to_string(),
+                    version: "0.1.0".to_string(),
+                    profile: "release".to_string(),
+                },
+                session_token: Some("00112233445566778899aabbccddeeff".to_string()),
+            }),
+        ),
+        (
+            "resume",
+            Frame::Resume {
+                session_token: "00112233445566778899aabbccddeeff".to_string(),
+            },
+        ),
+        (
+            "resumed",
+            Frame::Resumed {
+                rooms: vec!["lobby".to_string()],
+                nick: Some("alice".to_string()),
+                missed: 2,
+                dropped: 0,
+            },
+        ),
+        (
+            "ping",
+            Frame::Ping {
+                nonce: 1,
+                deadline_ms: 5000,
+            },
+        ),
+        ("pong", Frame::Pong { nonce: 1 }),
+        (
+            "kick",
+            Frame::Kick {
+                room: "lobby".to_string(),
+                member: 7,
+            },
+        ),
+        (
+            "mute",
+            Frame::Mute {
+                room: "lobby".to_string(),
+                member: 7,
+            },
+        ),
+        (
+            "unmute",
+            Frame::Unmute {
+                room: "lobby".to_string(),
+                member: 7,
+            },
+        ),
+        (
+            "room_notice",
+            Frame::RoomNotice {
+                room: "lobby".to_string(),
+                action: RoomAction::Muted,
+                member: 7,
+                by: Some(3),
+            },
+        ),
+        (
+            "room_owner",
+            Frame::RoomNotice {
+                room: "lobby".to_string(),
+                action: RoomAction::Owner,
+                member: 3,
+                by: None,
+            },
+        ),
+        (
+            "transfer_start",
+            Frame::TransferStart {
+                id: "6f1c2b7e-8a4d-4c1e-9b3f-2d5e7a9c0b14".to_string(),
+                name: "notes.txt".to_string(),
+                size: 5,
+            },
+        ),
+        (
+            "transfer_chunk",
+            Frame::TransferChunk {
+                id: "6f1c2b7e-8a4d-4c1e-9b3f-2d5e7a9c0b14".to_string(),
+                offset: 0,
+                data: transfer::encode_chunk(b"hello"),
+            },
+        ),
+        (
+            "resume_transfer",
+            Frame::ResumeTransfer {
+                id: "6f1c2b7e-8a4d-4c1e-9b3f-2d5e7a9c0b14".to_string(),
+            },
+        ),
+        (
+            "transfer_ack",
+            Frame::TransferAck {
+                id: "6f1c2b7e-8a4d-4c1e-9b3f-2d5e7a9c0b14".to_string(),
+                offset: 5,
+                checksum: transfer::checksum(transfer::EMPTY_CHECKSUM, b"hello"),
+            },
+        ),
+        (
+            "quota_warning",
+            Frame::QuotaWarning {
+                used: 838_860_800,
+                limit: 1_073_741_824,
+            },
+        ),
+    ]
+}
+
+fn datagrams() -> Vec<(&'static str, Datagram)> {
+    vec![
+        (
+            "batch",
+            Datagram::Batch {
+                messages: vec!["one".to_string(), "two".to_string(), String::new()],
+            },
+        ),
+        (
+            "reliable_data",
+            Datagram::ReliableData {
+                seq: 258,
+                payload: "payload".to_string(),
+            },
+        ),
+        (
+            "reliable_nack",
+            Datagram::ReliableNack {
+                seqs: vec![3, 5, 65_536],
+            },
+        ),
+        (
+            "mtu_probe",
+            Datagram::MtuProbe {
+                probe_id: 9,
+                len: 16,
+            },
+        ),
+        (
+            "mtu_ack",
+            Datagram::MtuProbe {
+                probe_id: 9,
+                len: 0,
+            },
+        ),
+        ("ping", Datagram::Ping { ping_id: 300 }),
+        (
+            "burst",
+            Datagram::Burst {
+                burst_id: 2,
+                seq: 17,
+                len: 24,
+            },
+        ),
+        (
+            "expiring",
+            Datagram::Expiring {
+                sent_unix_ms: 1_700_000_000_000,
+                max_age_ms: 500,
+                payload: "position 3,4".to_string(),
+            },
+        ),
+        (
+            "relay",
+            Datagram::Relay {
+                frame: Frame::Relay {
+                    room: "lobby".to_string(),
+                    from: Some(7),
+                    nick: Some("alice".to_string()),
+                    text: "hi all".to_string(),
+                    replayed: false,
+                    id: Some("m1".to_string()),
+                    timing: None,
+                },
+            },
+        ),
+    ]
+}
+
+/// Lowercase hex of `bytes`.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The bytes `hex` spells out, or `None` if it isn't an even number of hex digits.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(hex.get(at..at + 2)?, 16).ok())
+        .collect()
+}
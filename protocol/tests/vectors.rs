@@ -0,0 +1,100 @@
+//! Round-trips `vectors/vectors.json` through this crate, and checks it's what
+//! the `vectors` binary generates today.
+
+use protocol::vectors::{self, Datagram, Vectors, from_hex};
+use protocol::{FrameDecoder, batch, burst, expiry, reliable, room, telemetry};
+
+const FIXTURES: &str = include_str!("../vectors/vectors.json");
+
+// The fixtures, less the protobuf ones unless this build can read them
+fn fixtures() -> Vectors {
+    let mut json: serde_json::Value = serde_json::from_str(FIXTURES).expect("fixtures are JSON");
+    if cfg!(not(feature = "protobuf")) {
+        let frames = json["frames"].as_array_mut().expect("frames are a list");
+        frames.retain(|vector| vector["framing"] != "protobuf");
+    }
+    serde_json::from_value(json).expect("fixtures are vectors")
+}
+
+#[test]
+fn frames_decode_and_encode() {
+    for vector in fixtures().frames {
+        let bytes = from_hex(&vector.hex).expect("hex");
+        let mut decoder = FrameDecoder::with_framing(vector.framing);
+        decoder.push(&bytes);
+        let decoded = decoder.next_frame().expect(&vector.name);
+        assert_eq!(decoded.as_ref(), Some(&vector.frame), "{}", vector.name);
+        assert!(decoder.next_frame().expect(&vector.name).is_none());
+        assert_eq!(decoder.buffered(), 0, "{} has trailing bytes", vector.name);
+        assert_eq!(
+            vector.framing.encode(&vector.frame),
+            bytes,
+            "{}",
+            vector.name
+        );
+    }
+}
+
+#[test]
+fn datagrams_decode_and_encode() {
+    for vector in fixtures().datagrams {
+        let bytes = from_hex(&vector.hex).expect("hex");
+        assert_eq!(vector.datagram.encode(), bytes, "{}", vector.name);
+        match &vector.datagram {
+            Datagram::Batch { messages } => {
+                let decoded = batch::decode(&bytes).expect(&vector.name);
+                let expected: Vec<&[u8]> = messages.iter().map(|m| m.as_bytes()).collect();
+                assert_eq!(decoded, expected, "{}", vector.name);
+            }
+            Datagram::ReliableData { seq, payload } => {
+                let packet = reliable::Packet::Data {
+                    seq: *seq,
+                    payload: payload.as_bytes(),
+                };
+                assert_eq!(reliable::decode(&bytes), Some(packet), "{}", vector.name);
+            }
+            Datagram::ReliableNack { seqs } => {
+                let packet = reliable::Packet::Nack(seqs.clone());
+                assert_eq!(reliable::decode(&bytes), Some(packet), "{}", vector.name);
+            }
+            Datagram::MtuProbe { probe_id, .. } => {
+                let decoded = telemetry::decode_mtu_probe(&bytes);
+                assert_eq!(decoded, Some(*probe_id), "{}", vector.name);
+            }
+            Datagram::Ping { ping_id } => {
+                let decoded = telemetry::decode_ping(&bytes);
+                assert_eq!(decoded, Some(*ping_id), "{}", vector.name);
+            }
+            Datagram::Burst { burst_id, seq, .. } => {
+                let decoded = burst::decode_datagram(&bytes);
+                assert_eq!(decoded, Some((*burst_id, *seq)), "{}", vector.name);
+            }
+            Datagram::Expiring {
+                sent_unix_ms,
+                max_age_ms,
+                payload,
+            } => {
+                let expiring = expiry::Expiring {
+                    sent_unix_ms: *sent_unix_ms,
+                    max_age_ms: *max_age_ms,
+                    payload: payload.as_bytes(),
+                };
+                assert_eq!(expiry::decode(&bytes), Some(expiring), "{}", vector.name);
+            }
+            Datagram::Relay { frame } => {
+                let decoded = room::decode_datagram(&bytes);
+                assert_eq!(decoded.as_ref(), Some(frame), "{}", vector.name);
+            }
+        }
+    }
+}
+
+#[test]
+fn fixtures_are_current() {
+    assert_eq!(
+        fixtures(),
+        vectors::generate(),
+        "vectors/vectors.json is stale; regenerate it with \
+         `cargo run -p protocol --features protobuf --bin vectors`"
+    );
+}
@@ -0,0 +1,67 @@
+use crate::backoff::ErrorKind;
+use crate::registry::ConnectionId;
+use serde::Serialize;
+use std::path::PathBuf;
+use tokio::sync::broadcast;
+use tracing::debug;
+
+/// How many events a slow admin console may fall behind before it starts missing them.
+const CHANNEL_CAPACITY: usize = 64;
+
+/// Something operators watching `/admin/events` should know about.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AdminEvent {
+    /// The config file changed and these settings were applied.
+    ConfigReloaded { changes: Vec<String> },
+    /// The `--script` file changed and compiled.
+    ScriptReloaded { path: PathBuf },
+    /// A connection's handler stopped making progress or panicked, and the
+    /// connection was closed as `server_error`.
+    HandlerAborted {
+        connection: ConnectionId,
+        reason: String,
+    },
+    /// A shard's WebTransport endpoint moved to a new port; `redirected` sessions
+    /// were told to follow it.
+    EndpointMoved {
+        shard: usize,
+        from_port: u16,
+        to_port: u16,
+        redirected: usize,
+    },
+    /// Setting up a session on `shard` (or with none, accepting an HTTP API
+    /// connection) failed for lack of file descriptors, memory or connection
+    /// ids, `consecutive` times in a row; accepts pause for about `delay_ms`.
+    AcceptBackoff {
+        shard: Option<usize>,
+        kind: ErrorKind,
+        consecutive: u32,
+        delay_ms: u64,
+        error: String,
+    },
+}
+
+// Fan-out of admin events to every connected admin console.
+pub struct Admin {
+    events: broadcast::Sender<AdminEvent>,
+}
+
+impl Admin {
+    pub fn new() -> Self {
+        Self {
+            events: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+
+    pub fn publish(&self, event: AdminEvent) {
+        // No subscribers is fine: nobody is watching
+        if self.events.send(event).is_err() {
+            debug!("No admin console connected");
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<AdminEvent> {
+        self.events.subscribe()
+    }
+}
@@ -0,0 +1,198 @@
+use crate::admin::AdminEvent;
+use crate::state::State;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{debug, error};
+use wtransport::error::ConnectionError;
+
+/// Pause after the first session lost for lack of resources, doubled for each
+/// one after it in a row.
+const BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// Longest pause between accepts.
+const MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// Fraction each pause is randomly lengthened or shortened by, so shards
+/// backing off together don't resume together.
+const JITTER: f64 = 0.2;
+
+/// Why a session failed to open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// QUIC ran out of connection ids to hand out.
+    CidsExhausted,
+    /// The process hit its open file limit (EMFILE or ENFILE).
+    OutOfFiles,
+    /// The system refused memory or buffers (ENOMEM or ENOBUFS).
+    OutOfMemory,
+    /// Any other I/O error while setting the session up.
+    Io,
+    /// The handshake or session request timed out.
+    TimedOut,
+    /// The client broke the QUIC or HTTP/3 protocol.
+    Protocol,
+    /// Either side closed the connection before the session opened.
+    Closed,
+}
+
+impl ErrorKind {
+    pub fn of(error: &ConnectionError) -> Self {
+        match error {
+            ConnectionError::CidsExhausted => Self::CidsExhausted,
+            ConnectionError::TimedOut => Self::TimedOut,
+            ConnectionError::QuicProto(_) | ConnectionError::LocalH3Error(_) => Self::Protocol,
+            ConnectionError::ConnectionClosed(_)
+            | ConnectionError::ApplicationClosed(_)
+            | ConnectionError::LocallyClosed => Self::Closed,
+        }
+    }
+
+    pub fn of_io(error: &io::Error) -> Self {
+        #[cfg(unix)]
+        match error.raw_os_error() {
+            Some(libc::EMFILE | libc::ENFILE) => return Self::OutOfFiles,
+            Some(libc::ENOMEM | libc::ENOBUFS) => return Self::OutOfMemory,
+            _ => {}
+        }
+        match error.kind() {
+            io::ErrorKind::OutOfMemory => Self::OutOfMemory,
+            _ => Self::Io,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::CidsExhausted => "cids_exhausted",
+            Self::OutOfFiles => "out_of_files",
+            Self::OutOfMemory => "out_of_memory",
+            Self::Io => "io",
+            Self::TimedOut => "timed_out",
+            Self::Protocol => "protocol",
+            Self::Closed => "closed",
+        }
+    }
+
+    /// Whether accepting more sessions right away would likely fail the same way.
+    pub fn is_resource(self) -> bool {
+        matches!(
+            self,
+            Self::CidsExhausted | Self::OutOfFiles | Self::OutOfMemory
+        )
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    by_kind: BTreeMap<ErrorKind, u64>,
+    /// Resource failures since a session last opened.
+    consecutive: u32,
+    /// Pauses taken by accept loops.
+    backoffs: u64,
+}
+
+// Session failures, and how long accept loops should pause because of them.
+// Resource limits are process-wide, so every shard backs off together.
+pub struct AcceptBackoff {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// Sessions that failed to open, by why.
+    pub errors: BTreeMap<ErrorKind, u64>,
+    /// Resource failures in a row, as of now; 0 once a session opens.
+    pub consecutive_resource_errors: u32,
+    /// Times an accept loop paused.
+    pub backoffs: u64,
+}
+
+impl AcceptBackoff {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// A session opened or an HTTP API connection was accepted: resources are
+    /// back, so stop pausing.
+    pub fn succeeded(&self) {
+        self.inner.lock().unwrap().consecutive = 0;
+    }
+
+    /// How long to wait before accepting the next session, if at all.
+    pub fn delay(&self) -> Option<Duration> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.consecutive == 0 {
+            return None;
+        }
+        inner.backoffs += 1;
+        Some(delay(inner.consecutive))
+    }
+
+    pub fn report(&self) -> Report {
+        let inner = self.inner.lock().unwrap();
+        Report {
+            errors: inner.by_kind.clone(),
+            consecutive_resource_errors: inner.consecutive,
+            backoffs: inner.backoffs,
+        }
+    }
+}
+
+/// Count a session on `shard` that failed to open with `error`, or with no
+/// shard, an HTTP API connection that failed to accept. A resource failure also
+/// lengthens the pause before the next accept, and is logged and announced to
+/// admin consoles as `accept_backoff`.
+pub fn failed(state: &State, shard: Option<usize>, kind: ErrorKind, error: &str) {
+    let consecutive = {
+        let mut inner = state.accept_backoff.inner.lock().unwrap();
+        *inner.by_kind.entry(kind).or_default() += 1;
+        if !kind.is_resource() {
+            debug!("Session failed to open ({}): {}", kind.as_str(), error);
+            return;
+        }
+        inner.consecutive += 1;
+        inner.consecutive
+    };
+    let delay_ms = delay(consecutive).as_millis() as u64;
+    error!(
+        shard = ?shard,
+        kind = kind.as_str(),
+        consecutive,
+        delay_ms,
+        "Session failed for lack of resources, backing off accepts: {}",
+        error
+    );
+    state.admin.publish(AdminEvent::AcceptBackoff {
+        shard,
+        kind,
+        consecutive,
+        delay_ms,
+        error: error.to_string(),
+    });
+}
+
+/// An HTTP API listener failed to accept: a resource failure is counted and
+/// the listener carries on after a pause, anything else ends it.
+pub async fn http_accept_failed(state: &State, error: io::Error) -> io::Result<()> {
+    let kind = ErrorKind::of_io(&error);
+    if !kind.is_resource() {
+        return Err(error);
+    }
+    failed(state, None, kind, &error.to_string());
+    if let Some(delay) = state.accept_backoff.delay() {
+        tokio::time::sleep(delay).await;
+    }
+    Ok(())
+}
+
+// Pause after `consecutive` resource failures in a row, jittered
+fn delay(consecutive: u32) -> Duration {
+    let backoff = BASE_DELAY.saturating_mul(1 << (consecutive - 1).min(16));
+    let jitter = JITTER * (2.0 * rand::random::<f64>() - 1.0);
+    backoff.min(MAX_DELAY).mul_f64(1.0 + jitter)
+}
@@ -0,0 +1,54 @@
+use crate::registry::ConnectionId;
+use protocol::telemetry::BandwidthEstimate;
+use protocol::{Frame, Framing};
+use std::time::Duration;
+use tracing::{debug, warn};
+use wtransport::Connection;
+
+/// How often each client is sent a fresh estimate.
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Estimate available bandwidth from the QUIC path stats: one congestion window per RTT.
+pub fn estimate(connection: &Connection) -> BandwidthEstimate {
+    let stats = connection.quic_connection().stats();
+    let path = stats.path;
+    let rtt = path.rtt.max(Duration::from_micros(1));
+
+    BandwidthEstimate {
+        bits_per_second: (path.cwnd as f64 * 8.0 / rtt.as_secs_f64()) as u64,
+        rtt_us: path.rtt.as_micros() as u64,
+        cwnd_bytes: path.cwnd,
+        sent_packets: path.sent_packets,
+        lost_packets: path.lost_packets,
+        datagrams_received: stats.frame_rx.datagram,
+    }
+}
+
+// Push an estimate to the client every REPORT_INTERVAL on a server-opened
+// unidirectional stream. Runs until the stream can't be written.
+pub async fn report(id: ConnectionId, connection: Connection, framing: Framing) {
+    let opened = async { anyhow::Ok(connection.open_uni().await?.await?) }.await;
+    let mut stream = match opened {
+        Ok(stream) => stream,
+        Err(e) => {
+            warn!(
+                "Failed to open bandwidth stream to connection {}: {}",
+                id, e
+            );
+            return;
+        }
+    };
+
+    let mut interval = tokio::time::interval(REPORT_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let estimate = estimate(&connection);
+        debug!("Connection {} bandwidth estimate: {:?}", id, estimate);
+        let frame = Frame::BandwidthEstimate(estimate);
+        if let Err(e) = stream.write_all(&framing.encode(&frame)).await {
+            debug!("Stopped bandwidth reports to connection {}: {}", id, e);
+            return;
+        }
+    }
+}
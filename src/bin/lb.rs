@@ -0,0 +1,276 @@
+//! Round-robin load balancer in front of several playground servers, for
+//! multi-server topology experiments. Each session is assigned the next
+//! backend and either redirected to it with a `redirect` frame or proxied
+//! through: every stream and datagram copied both ways.
+//!
+//! ```bash
+//! cargo run --bin lb -- --backend https://localhost:8765 --backend https://localhost:8766
+//! ```
+
+use anyhow::Result;
+use clap::{Parser, ValueEnum};
+use protocol::{CloseCode, Frame, Framing};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+use wtransport::error::ConnectionError;
+use wtransport::{
+    ClientConfig, Connection, Endpoint, Identity, RecvStream, SendStream, ServerConfig, VarInt,
+};
+
+/// How long a redirected session gets to leave before it's closed.
+const GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Keeps proxied backend sessions alive while the client is quiet.
+const KEEP_ALIVE: Duration = Duration::from_secs(3);
+
+#[derive(Parser)]
+#[command(about = "Round-robin load balancer for WebTransport playground servers")]
+struct Args {
+    /// Port to accept WebTransport sessions on
+    #[arg(long, default_value_t = 8764)]
+    port: u16,
+
+    /// Backend server URL, e.g. https://localhost:8765; repeat for each backend
+    #[arg(long = "backend", required = true)]
+    backends: Vec<String>,
+
+    /// Whether to redirect sessions to their backend or proxy them through
+    #[arg(long, value_enum, default_value_t = Mode::Redirect)]
+    mode: Mode,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Mode {
+    /// Send the client a redirect frame naming its backend
+    Redirect,
+    /// Open a session to the backend and copy streams and datagrams both ways
+    Proxy,
+}
+
+struct Backends {
+    urls: Vec<String>,
+    next: AtomicUsize,
+}
+
+impl Backends {
+    fn new(urls: Vec<String>) -> Self {
+        let urls = urls
+            .into_iter()
+            .map(|url| url.trim_end_matches('/').to_string())
+            .collect();
+        Self {
+            urls,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    // The next backend in turn
+    fn pick(&self) -> &str {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.urls.len();
+        &self.urls[index]
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    tracing_subscriber::fmt::init();
+
+    let identity = Identity::load_pemfiles("cert.pem", "key.pem")
+        .await
+        .expect("Failed to load certificates. Run: openssl req -x509 -newkey rsa:4096 -keyout key.pem -out cert.pem -sha256 -days 365 -nodes -subj '/CN=localhost'");
+    let server = Endpoint::server(
+        ServerConfig::builder()
+            .with_bind_default(args.port)
+            .with_identity(identity)
+            .build(),
+    )?;
+
+    // Backends are playground servers with self-signed certificates, which
+    // can't be pinned by hash unless they're short-lived ECDSA ones
+    let client = Arc::new(Endpoint::client(
+        ClientConfig::builder()
+            .with_bind_default()
+            .with_no_cert_validation()
+            .keep_alive_interval(Some(KEEP_ALIVE))
+            .build(),
+    )?);
+
+    let backends = Arc::new(Backends::new(args.backends));
+    info!(
+        "Load balancer listening on https://localhost:{} ({:?} mode) for {}",
+        args.port,
+        args.mode,
+        backends.urls.join(", ")
+    );
+
+    let sessions = AtomicU64::new(0);
+    loop {
+        let incoming_session = server.accept().await;
+        let session = sessions.fetch_add(1, Ordering::Relaxed);
+        let backends = backends.clone();
+        let client = client.clone();
+        let mode = args.mode;
+
+        tokio::spawn(async move {
+            let request = match incoming_session.await {
+                Ok(request) => request,
+                Err(e) => {
+                    warn!("Session error: {}", e);
+                    return;
+                }
+            };
+            let path = request.path().to_string();
+            let url = format!("{}{}", backends.pick(), path);
+            info!("Session {} for {} -> {}", session, path, url);
+
+            match mode {
+                Mode::Redirect => {
+                    let Some(framing) = Framing::for_path(&path) else {
+                        warn!(
+                            "Session {}: encoding of {} not supported by this build",
+                            session, path
+                        );
+                        request.not_found().await;
+                        return;
+                    };
+                    match request.accept().await {
+                        Ok(connection) => redirect(session, connection, framing, url).await,
+                        Err(e) => warn!("Failed to accept session {}: {}", session, e),
+                    }
+                }
+                Mode::Proxy => {
+                    // Reach the backend first, so a dead one turns the client away
+                    let backend = match client.connect(&url).await {
+                        Ok(backend) => backend,
+                        Err(e) => {
+                            warn!("Backend {} unreachable for session {}: {}", url, session, e);
+                            request.not_found().await;
+                            return;
+                        }
+                    };
+                    match request.accept().await {
+                        Ok(connection) => proxy(session, connection, backend).await,
+                        Err(e) => warn!("Failed to accept session {}: {}", session, e),
+                    }
+                }
+            }
+        });
+    }
+}
+
+// Point the client at its backend, then close the session once it has left or
+// GRACE_PERIOD is up
+async fn redirect(session: u64, connection: Connection, framing: Framing, url: String) {
+    let frame = Frame::Redirect { url: url.clone() };
+    if let Err(e) = push_frame(&connection, framing, &frame).await {
+        warn!("Failed to redirect session {}: {}", session, e);
+    }
+    if tokio::time::timeout(GRACE_PERIOD, connection.closed())
+        .await
+        .is_err()
+    {
+        let reason = format!("redirected to {}", url);
+        connection.close(
+            VarInt::from_u32(CloseCode::Redirected.code()),
+            reason.as_bytes(),
+        );
+    }
+    info!("Session {} redirected to {}", session, url);
+}
+
+async fn push_frame(connection: &Connection, framing: Framing, frame: &Frame) -> Result<()> {
+    let mut stream = connection.open_uni().await?.await?;
+    stream.write_all(&framing.encode(frame)).await?;
+    stream.finish().await?;
+    Ok(())
+}
+
+// Copy streams and datagrams between the client's session and the backend's
+// until either side closes, then close the other with the same code and reason
+async fn proxy(session: u64, client: Connection, backend: Connection) {
+    let closed = loop {
+        tokio::select! {
+            stream = client.accept_bi() => match stream {
+                Ok((send, recv)) => {
+                    tokio::spawn(pipe_bi(session, backend.clone(), send, recv));
+                }
+                Err(e) => break (&backend, e),
+            },
+            stream = backend.accept_bi() => match stream {
+                Ok((send, recv)) => {
+                    tokio::spawn(pipe_bi(session, client.clone(), send, recv));
+                }
+                Err(e) => break (&client, e),
+            },
+            stream = client.accept_uni() => match stream {
+                Ok(recv) => {
+                    tokio::spawn(pipe_uni(session, backend.clone(), recv));
+                }
+                Err(e) => break (&backend, e),
+            },
+            stream = backend.accept_uni() => match stream {
+                Ok(recv) => {
+                    tokio::spawn(pipe_uni(session, client.clone(), recv));
+                }
+                Err(e) => break (&client, e),
+            },
+            datagram = client.receive_datagram() => match datagram {
+                Ok(datagram) => forward_datagram(session, &backend, datagram.payload()),
+                Err(e) => break (&backend, e),
+            },
+            datagram = backend.receive_datagram() => match datagram {
+                Ok(datagram) => forward_datagram(session, &client, datagram.payload()),
+                Err(e) => break (&client, e),
+            },
+        }
+    };
+
+    let (other, error) = closed;
+    match &error {
+        ConnectionError::ApplicationClosed(close) => other.close(close.code(), close.reason()),
+        _ => other.close(
+            VarInt::from_u32(CloseCode::Normal.code()),
+            error.to_string().as_bytes(),
+        ),
+    }
+    info!("Session {} closed: {}", session, error);
+}
+
+fn forward_datagram(session: u64, to: &Connection, payload: bytes::Bytes) {
+    if let Err(e) = to.send_datagram(payload) {
+        debug!("Session {}: failed to forward datagram: {}", session, e);
+    }
+}
+
+// Carry a bidirectional stream opened on one side over a new one on `to`
+async fn pipe_bi(session: u64, to: Connection, send: SendStream, recv: RecvStream) {
+    let result = async {
+        let (to_send, to_recv) = to.open_bi().await?.await?;
+        let (outbound, inbound) = tokio::join!(copy(recv, to_send), copy(to_recv, send));
+        outbound.and(inbound)
+    };
+    if let Err(e) = result.await {
+        debug!("Session {}: stream ended early: {}", session, e);
+    }
+}
+
+// Carry a unidirectional stream opened on one side over a new one on `to`
+async fn pipe_uni(session: u64, to: Connection, recv: RecvStream) {
+    let result = async {
+        let send = to.open_uni().await?.await?;
+        copy(recv, send).await
+    };
+    if let Err(e) = result.await {
+        debug!("Session {}: stream ended early: {}", session, e);
+    }
+}
+
+// Copy `recv` into `send` to the end, then finish `send`
+async fn copy(mut recv: RecvStream, mut send: SendStream) -> Result<()> {
+    tokio::io::copy(&mut recv, &mut send).await?;
+    send.finish().await?;
+    Ok(())
+}
@@ -0,0 +1,234 @@
+//! Stream priority starvation demo: one session carries a bulk stream, flooded
+//! with large messages, and a control stream sending a small message every so
+//! often. The control messages' round-trip delay is measured twice, first with
+//! both streams at the same priority and then with the control stream
+//! prioritized, to show what stream scheduling does for latency-sensitive
+//! traffic sharing a connection with bulk transfers.
+//!
+//! ```bash
+//! cargo run --bin priority -- --url https://localhost:8765
+//! ```
+//!
+//! Only this client's sending is prioritized; the server's echoes on both
+//! streams still share its side of the connection equally.
+
+use anyhow::{Context, Result, bail};
+use clap::Parser;
+use protocol::{Frame, FrameDecoder};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use wtransport::{ClientConfig, Connection, Endpoint, RecvStream, SendStream, VarInt};
+
+/// How long to wait for a control message's echo before counting it lost.
+const ECHO_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Parser)]
+#[command(about = "Measure control-message delay next to a bulk stream, with and without priority")]
+struct Args {
+    /// Playground server to run against; its `limits.messages_per_second` should be 0
+    #[arg(long, default_value = "https://localhost:8765")]
+    url: String,
+
+    /// Size of each bulk message
+    #[arg(long, default_value_t = 32 * 1024)]
+    bulk_message_bytes: usize,
+
+    /// Control messages sent per round
+    #[arg(long, default_value_t = 50)]
+    control_messages: u32,
+
+    /// Pause between control messages
+    #[arg(long, default_value_t = 20)]
+    interval_ms: u64,
+
+    /// How long the bulk stream runs before the control messages start
+    #[arg(long, default_value_t = 500)]
+    warmup_ms: u64,
+}
+
+// Control-message delays and bulk throughput for one round
+struct Round {
+    delays: Vec<Duration>,
+    lost: u32,
+    bulk_bytes: u64,
+    elapsed: Duration,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    if args.bulk_message_bytes == 0 || args.bulk_message_bytes > protocol::MAX_FRAME_LEN - 64 {
+        bail!(
+            "--bulk-message-bytes must be 1 to {}",
+            protocol::MAX_FRAME_LEN - 64
+        );
+    }
+
+    let endpoint = Endpoint::client(
+        ClientConfig::builder()
+            .with_bind_default()
+            .with_no_cert_validation()
+            .build(),
+    )?;
+    let connection = endpoint
+        .connect(&args.url)
+        .await
+        .with_context(|| format!("can't connect to {}", args.url))?;
+
+    println!(
+        "{} control messages every {} ms next to a bulk stream of {} byte messages",
+        args.control_messages, args.interval_ms, args.bulk_message_bytes
+    );
+    for prioritized in [false, true] {
+        let round = run_round(&connection, &args, prioritized).await?;
+        report(prioritized, &round);
+    }
+
+    connection.close(VarInt::from_u32(0), b"done");
+    endpoint.wait_idle().await;
+    Ok(())
+}
+
+async fn run_round(connection: &Connection, args: &Args, prioritized: bool) -> Result<Round> {
+    let (bulk_send, bulk_recv) = connection.open_bi().await?.await?;
+    let (control_send, control_recv) = connection.open_bi().await?.await?;
+    // Higher goes first; the default is 0
+    if prioritized {
+        control_send.set_priority(1);
+    }
+
+    let started = Instant::now();
+    let stop = Arc::new(AtomicBool::new(false));
+    let bulk_bytes = Arc::new(AtomicU64::new(0));
+    let bulk = flood(
+        bulk_send,
+        args.bulk_message_bytes,
+        stop.clone(),
+        bulk_bytes.clone(),
+    );
+    let drain = tokio::spawn(drain(bulk_recv));
+    tokio::time::sleep(Duration::from_millis(args.warmup_ms)).await;
+
+    let (delays, lost) = measure(control_send, control_recv, args).await?;
+    stop.store(true, Ordering::Relaxed);
+    let _ = bulk.await;
+    drain.abort();
+
+    Ok(Round {
+        delays,
+        lost,
+        bulk_bytes: bulk_bytes.load(Ordering::Relaxed),
+        elapsed: started.elapsed(),
+    })
+}
+
+// Write bulk messages back to back until told to stop, then reset the stream
+// so whatever is still queued doesn't hold up the next round
+fn flood(
+    mut send: SendStream,
+    message_bytes: usize,
+    stop: Arc<AtomicBool>,
+    sent: Arc<AtomicU64>,
+) -> JoinHandle<()> {
+    let frame = protocol::encode(&Frame::Message {
+        text: "x".repeat(message_bytes),
+        seq: None,
+        id: None,
+        timing: None,
+    });
+    tokio::spawn(async move {
+        while !stop.load(Ordering::Relaxed) {
+            if send.write_all(&frame).await.is_err() {
+                return;
+            }
+            sent.fetch_add(frame.len() as u64, Ordering::Relaxed);
+        }
+        let _ = send.reset(VarInt::from_u32(0));
+    })
+}
+
+// Read and discard the bulk stream's echoes, so they don't back up
+async fn drain(mut recv: RecvStream) {
+    let mut buffer = vec![0u8; 64 * 1024];
+    while let Ok(Some(_)) = recv.read(&mut buffer).await {}
+}
+
+// Send numbered control messages and time each until its echo comes back
+async fn measure(
+    mut send: SendStream,
+    mut recv: RecvStream,
+    args: &Args,
+) -> Result<(Vec<Duration>, u32)> {
+    let mut decoder = FrameDecoder::new();
+    let mut buffer = vec![0u8; 4096];
+    let mut delays = Vec::new();
+    let mut lost = 0;
+
+    for seq in 1..=args.control_messages {
+        let frame = Frame::Message {
+            text: format!("control {}", seq),
+            seq: Some(u64::from(seq)),
+            id: None,
+            timing: None,
+        };
+        let sent_at = Instant::now();
+        send.write_all(&protocol::encode(&frame)).await?;
+
+        let echo = tokio::time::timeout(ECHO_TIMEOUT, async {
+            loop {
+                while let Some(frame) = decoder.next_frame()? {
+                    if let Frame::Message {
+                        seq: Some(echoed), ..
+                    } = frame
+                        && echoed == u64::from(seq)
+                    {
+                        return anyhow::Ok(());
+                    }
+                }
+                match recv.read(&mut buffer).await? {
+                    Some(n) => decoder.push(&buffer[..n]),
+                    None => bail!("server finished the control stream"),
+                }
+            }
+        })
+        .await;
+        match echo {
+            Ok(result) => {
+                result?;
+                delays.push(sent_at.elapsed());
+            }
+            Err(_) => lost += 1,
+        }
+        tokio::time::sleep(Duration::from_millis(args.interval_ms)).await;
+    }
+
+    let _ = send.finish().await;
+    Ok((delays, lost))
+}
+
+fn report(prioritized: bool, round: &Round) {
+    let mut delays = round.delays.clone();
+    delays.sort();
+    let percentile = |p: f64| {
+        delays
+            .get(((delays.len() as f64 * p).ceil() as usize).saturating_sub(1))
+            .map_or(f64::NAN, |d| d.as_secs_f64() * 1000.0)
+    };
+    let throughput = round.bulk_bytes as f64 * 8.0 / round.elapsed.as_secs_f64() / 1e6;
+
+    println!(
+        "{:<18} control delay p50 {:>8.2} ms  p95 {:>8.2} ms  max {:>8.2} ms  ({} lost)  bulk {:>8.1} Mbit/s",
+        if prioritized {
+            "control priority"
+        } else {
+            "same priority"
+        },
+        percentile(0.5),
+        percentile(0.95),
+        percentile(1.0),
+        round.lost,
+        throughput
+    );
+}
@@ -0,0 +1,227 @@
+use crate::registry::ConnectionId;
+use crate::state::State;
+use protocol::Frame;
+use protocol::burst::BurstInstruction;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+struct Arrival {
+    connection: ConnectionId,
+    seq: u32,
+    at: Instant,
+}
+
+// The burst currently collecting arrivals.
+struct Collection {
+    burst_id: u64,
+    arrivals: Vec<Arrival>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionReport {
+    pub connection: ConnectionId,
+    pub received: u32,
+    /// Arrival of the first datagram, in ms relative to the target instant.
+    pub first_ms: f64,
+    pub last_ms: f64,
+    /// Datagrams that arrived after one with a higher sequence number.
+    pub reordered: u32,
+}
+
+/// Result of one coordinated burst, served by `/admin/burst`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub burst_id: u64,
+    pub clients: usize,
+    pub count_per_client: u32,
+    pub expected: u64,
+    pub received: u64,
+    pub loss: f64,
+    /// Time from the first to the last arrival across all clients.
+    pub spread_ms: f64,
+    /// Connections in order of their first arrival.
+    pub arrival_order: Vec<ConnectionId>,
+    /// How often consecutive arrivals came from different connections (0..1):
+    /// near 0 means bursts arrived one after another, higher means they interleaved.
+    pub interleaving: f64,
+    pub connections: Vec<ConnectionReport>,
+}
+
+// Runs coordinated burst tests: every client is told to send `count` datagrams
+// at the same server-clock instant, and arrivals are collected for a while
+// afterwards to study incast ordering and loss.
+pub struct Coordinator {
+    next_id: AtomicU64,
+    current: Mutex<Option<Collection>>,
+    last_report: Mutex<Option<Report>>,
+}
+
+impl Coordinator {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            current: Mutex::new(None),
+            last_report: Mutex::new(None),
+        }
+    }
+
+    /// Record a burst datagram from `connection`. Datagrams from other bursts are ignored.
+    pub fn record(&self, connection: ConnectionId, burst_id: u64, seq: u32) {
+        let at = Instant::now();
+        let mut current = self.current.lock().unwrap();
+        if let Some(collection) = current.as_mut().filter(|c| c.burst_id == burst_id) {
+            collection.arrivals.push(Arrival {
+                connection,
+                seq,
+                at,
+            });
+        }
+    }
+
+    pub fn last_report(&self) -> Option<Report> {
+        self.last_report.lock().unwrap().clone()
+    }
+
+    /// Tell every connected client to send `count` datagrams `lead` from now,
+    /// then collect arrivals for `collect` and produce a report.
+    pub async fn run(
+        &self,
+        state: &State,
+        count: u32,
+        payload_bytes: usize,
+        lead: Duration,
+        collect: Duration,
+    ) {
+        let burst_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let target = Instant::now() + lead;
+        let at_unix_ms = (SystemTime::now() + lead)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        *self.current.lock().unwrap() = Some(Collection {
+            burst_id,
+            arrivals: Vec::new(),
+        });
+
+        let instruction = Frame::Burst(BurstInstruction {
+            burst_id,
+            at_unix_ms,
+            count,
+            payload_bytes,
+        });
+        let connections = state.registry.all();
+        info!(
+            "Burst {}: {} clients x {} datagrams in {:?}",
+            burst_id,
+            connections.len(),
+            count,
+            lead
+        );
+        for (id, connection) in &connections {
+            let framing = state.registry.framing(*id);
+            if let Err(e) = State::push_frame(connection, framing, &instruction).await {
+                warn!(
+                    "Burst {}: failed to instruct connection {}: {}",
+                    burst_id, id, e
+                );
+            }
+        }
+
+        tokio::time::sleep_until((target + collect).into()).await;
+
+        let collection = self.current.lock().unwrap().take();
+        let arrivals = collection.map(|c| c.arrivals).unwrap_or_default();
+        let report = summarize(burst_id, connections.len(), count, target, &arrivals);
+        info!(
+            "Burst {}: received {}/{} ({:.1}% loss), spread {:.1} ms, interleaving {:.2}",
+            burst_id,
+            report.received,
+            report.expected,
+            report.loss * 100.0,
+            report.spread_ms,
+            report.interleaving
+        );
+
+        *self.last_report.lock().unwrap() = Some(report);
+    }
+}
+
+fn summarize(
+    burst_id: u64,
+    clients: usize,
+    count: u32,
+    target: Instant,
+    arrivals: &[Arrival],
+) -> Report {
+    // Signed milliseconds from the target instant; early arrivals are negative
+    let offset_ms = |at: Instant| match at.checked_duration_since(target) {
+        Some(after) => after.as_secs_f64() * 1000.0,
+        None => -(target - at).as_secs_f64() * 1000.0,
+    };
+
+    let mut per_connection: BTreeMap<ConnectionId, (ConnectionReport, u32)> = BTreeMap::new();
+    let mut arrival_order = Vec::new();
+    for arrival in arrivals {
+        let ms = offset_ms(arrival.at);
+        let (report, highest_seq) = per_connection.entry(arrival.connection).or_insert_with(|| {
+            arrival_order.push(arrival.connection);
+            let report = ConnectionReport {
+                connection: arrival.connection,
+                received: 0,
+                first_ms: ms,
+                last_ms: ms,
+                reordered: 0,
+            };
+            (report, arrival.seq)
+        });
+
+        report.received += 1;
+        report.last_ms = ms;
+        if arrival.seq < *highest_seq {
+            report.reordered += 1;
+        }
+        *highest_seq = (*highest_seq).max(arrival.seq);
+    }
+
+    let switches = arrivals
+        .windows(2)
+        .filter(|pair| pair[0].connection != pair[1].connection)
+        .count();
+    let interleaving = if arrivals.len() > 1 {
+        switches as f64 / (arrivals.len() - 1) as f64
+    } else {
+        0.0
+    };
+
+    let expected = clients as u64 * count as u64;
+    let received = arrivals.len() as u64;
+    let spread_ms = match (arrivals.first(), arrivals.last()) {
+        (Some(first), Some(last)) => (last.at - first.at).as_secs_f64() * 1000.0,
+        _ => 0.0,
+    };
+
+    Report {
+        burst_id,
+        clients,
+        count_per_client: count,
+        expected,
+        received,
+        loss: if expected > 0 {
+            1.0 - (received.min(expected) as f64 / expected as f64)
+        } else {
+            0.0
+        },
+        spread_ms,
+        arrival_order,
+        interleaving,
+        connections: per_connection
+            .into_values()
+            .map(|(report, _)| report)
+            .collect(),
+    }
+}
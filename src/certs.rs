@@ -0,0 +1,84 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use protocol::certs::CertPin;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+use wtransport::Identity;
+use x509_parser::prelude::X509Certificate;
+
+/// Longest validity browsers accept for a certificate pinned through
+/// `serverCertificateHashes`.
+const MAX_PINNED_VALIDITY_DAYS: i64 = 14;
+
+/// Digests of the leaf certificate an identity presents, in the forms browsers
+/// take them, so nothing has to be hashed with openssl by hand.
+#[derive(Debug, Clone)]
+pub struct Fingerprint {
+    /// SHA-256 of the certificate's DER, for `serverCertificateHashes`.
+    pub pin: CertPin,
+    /// Base64 SHA-256 of the SubjectPublicKeyInfo, for Chrome's
+    /// `--ignore-certificate-errors-spki-list`.
+    pub spki_sha256: Option<String>,
+    /// Validity period in days, if the certificate could be parsed.
+    validity_days: Option<i64>,
+}
+
+impl Fingerprint {
+    pub fn of(identity: &Identity) -> Self {
+        let der = identity.certificate_chain().as_slice()[0].der();
+        let value = hex(&Sha256::digest(der));
+
+        let parsed = x509_parser::parse_x509_certificate(der)
+            .map(|(_, parsed)| parsed)
+            .inspect_err(|e| warn!("Failed to parse certificate: {}", e))
+            .ok();
+
+        Self {
+            pin: CertPin {
+                algorithm: "sha-256".to_string(),
+                value,
+                expires_unix_ms: parsed.as_ref().and_then(expires_unix_ms),
+            },
+            spki_sha256: parsed
+                .as_ref()
+                .map(|cert| BASE64.encode(Sha256::digest(cert.public_key().raw))),
+            validity_days: parsed.as_ref().map(|cert| {
+                let validity = cert.validity();
+                (validity.not_after.timestamp() - validity.not_before.timestamp()) / 86_400
+            }),
+        }
+    }
+
+    /// Log the digests, warning if browsers won't accept the certificate for pinning.
+    pub fn log(&self) {
+        info!(
+            "Certificate SHA-256 (serverCertificateHashes): {}",
+            self.pin.value
+        );
+        if let Some(spki) = &self.spki_sha256 {
+            info!(
+                "Certificate SPKI SHA-256 (--ignore-certificate-errors-spki-list): {}",
+                spki
+            );
+        }
+        if let Some(days) = self.validity_days
+            && days > MAX_PINNED_VALIDITY_DAYS
+        {
+            warn!(
+                "Certificate is valid for {} days; browsers only accept serverCertificateHashes for certificates valid at most {} days",
+                days, MAX_PINNED_VALIDITY_DAYS
+            );
+        }
+    }
+}
+
+// Clients stop trying a pin once its certificate expires
+fn expires_unix_ms(cert: &X509Certificate) -> Option<u64> {
+    u64::try_from(cert.validity().not_after.timestamp())
+        .ok()
+        .map(|secs| secs * 1000)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,34 @@
+use crate::config::ChaosConfig;
+
+/// What chaos mode does to a client's frame or datagram instead of handling it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Reset the frame's stream (both directions) with this code.
+    ResetStream(u32),
+    /// Close the whole connection with this code.
+    CloseConnection(u32),
+}
+
+/// Roll the dice for one frame (`stream`) or datagram under `config`. Closing
+/// the connection is rolled first; datagrams have no stream to reset.
+pub fn roll(config: &ChaosConfig, stream: bool) -> Option<Action> {
+    if !config.enabled {
+        return None;
+    }
+    if chance(config.connection_close) {
+        return Some(Action::CloseConnection(pick(&config.close_codes)));
+    }
+    if stream && chance(config.stream_reset) {
+        return Some(Action::ResetStream(pick(&config.reset_codes)));
+    }
+    None
+}
+
+fn chance(probability: f64) -> bool {
+    probability > 0.0 && rand::random::<f64>() < probability
+}
+
+// Config validation keeps the code lists non-empty
+fn pick(codes: &[u32]) -> u32 {
+    codes[rand::random_range(0..codes.len())]
+}
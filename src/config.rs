@@ -0,0 +1,1269 @@
+use crate::admin::AdminEvent;
+use crate::journal::Fsync;
+use crate::memory::Policy;
+use crate::middleware::Layer;
+use crate::schedule::Job;
+use crate::state::State;
+use crate::storage::Backend;
+use anyhow::{Context, Result, bail};
+use protocol::CloseCode;
+use protocol::certs::CertPin;
+use protocol::room::{self, RelayTransport};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use toml::de::{DeTable, DeValue};
+use tracing::level_filters::LevelFilter;
+use tracing::{info, warn};
+use tracing_subscriber::{Registry, reload};
+
+/// How often the config file is checked for changes.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Handle for changing the log level at runtime.
+pub type LogHandle = reload::Handle<LevelFilter, Registry>;
+
+/// Server configuration, loaded from TOML:
+///
+/// ```toml
+/// port = 8765
+/// log_level = "info"
+/// # Browser origins allowed to open sessions; empty allows any
+/// origins = ["http://127.0.0.1:7654"]
+///
+/// [impairment]
+/// loss = 0.0
+///
+/// [datagrams]
+/// # Prefix echoes with "Server datagram echo: "; false echoes payloads as received
+/// echo_prefix = true
+///
+/// [limits]
+/// # Per connection, across streams and datagrams; 0 disables the limit
+/// messages_per_second = 50
+/// # Streams a connection may have open at once, by direction; 0 disables the limit
+/// max_bi_streams = 100
+/// max_uni_streams = 10
+///
+/// [violations]
+/// # Protocol violations that close a connection with code 101
+/// strikes = 3
+///
+/// [chaos]
+/// # Randomly reset streams or close connections instead of handling client input
+/// enabled = false
+/// stream_reset = 0.01
+/// connection_close = 0.001
+/// reset_codes = [0, 42]
+/// close_codes = [104]
+///
+/// [rooms]
+/// # Per room, across its members; 0 disables each quota
+/// messages_per_second = 20
+/// bytes_per_minute = 65536
+/// max_members = 50
+/// # How relays reach members: "stream", "datagram" or "both"; a member can
+/// # choose for itself when it joins
+/// transport = "stream"
+///
+/// # Rooms relaying differently from the rest
+/// [rooms.transports]
+/// positions = "datagram"
+///
+/// # Rooms whose owner is whoever joins with the token as its `token` tag,
+/// # rather than whoever created them; owners can kick and mute members
+/// [rooms.owners]
+/// lobby = "s3cret"
+///
+/// [resume]
+/// # How long a closed session's rooms, nickname and missed messages wait for a
+/// # `resume` with its token; 0 stops issuing tokens
+/// grace_secs = 30
+/// # Messages queued per closed session; the oldest are dropped past it
+/// max_queued = 256
+///
+/// [liveness]
+/// # Seconds a connection can send nothing before it's probed with a `ping`;
+/// # 0 turns probing off
+/// silent_secs = 30
+/// # Milliseconds a probed connection has to answer before it's closed
+/// deadline_ms = 5000
+///
+/// [quota]
+/// # Bytes each connection may send and receive, QUIC overhead included,
+/// # before it's closed as `quota_exceeded`; 0 means no cap
+/// bytes = 104857600
+/// # Share of the cap at which the connection gets a `quota_warning`
+/// warn_ratio = 0.8
+///
+/// [watchdog]
+/// # Milliseconds a connection's handler can go without a heartbeat before the
+/// # connection is closed as a server error; 0 turns the watchdog off
+/// deadline_ms = 10000
+///
+/// [stats]
+/// # Minutes of per-second samples kept for /stats/timeseries
+/// window_minutes = 10
+///
+/// [storage]
+/// # "memory", "filesystem" (a directory at `path`) or "sqlite" (a database at
+/// # `path`, with the sqlite feature); both read once at startup
+/// backend = "filesystem"
+/// path = "playground-data"
+/// # Messages kept per room for /rooms/history; 0 keeps none
+/// room_history = 100
+///
+/// [memory]
+/// # Bytes buffered on clients' behalf (partial frames, pipeline windows)
+/// budget_bytes = 67108864
+/// # "backpressure" stops reading streams when full; "drop" rejects the input
+/// policy = "backpressure"
+///
+/// [pipelines]
+/// # How often each topic's published messages are fanned out as one aggregate
+/// interval_ms = 1000
+///
+/// [fanout]
+/// # Tasks sending room relays and pipeline aggregates; read once at startup
+/// workers = 8
+/// # Frames queued per connection; the oldest are dropped past it
+/// queue_len = 256
+///
+/// [middleware]
+/// # Layers each frame from a client's stream passes through before it's
+/// # handled, outermost first: "log", "auth", "rate_limit", "metrics", "impairment"
+/// layers = ["log", "metrics", "impairment", "rate_limit"]
+/// # Session `token` tags the auth layer lets through
+/// tokens = []
+///
+/// [journal]
+/// # Append connects, joins, leaves, message count checkpoints and disconnects
+/// # here, to recover room membership after a crash; read once at startup
+/// path = "playground-journal.jsonl"
+/// # "always" (every record), "interval" or "never" (left to the OS)
+/// fsync = "interval"
+/// fsync_interval_ms = 1000
+/// # How often each connection's message counts are checkpointed; 0 never
+/// checkpoint_secs = 30
+///
+/// [transfers]
+/// # Largest file a client may upload with `transfer_start`, in bytes
+/// max_size = 67108864
+/// # How long an unfinished transfer waits to be resumed after its last chunk
+/// keep_secs = 300
+///
+/// [admin]
+/// # Also serve the HTTP API on this UNIX socket, for curl --unix-socket
+/// socket = "/tmp/playground.sock"
+/// # Listen on 127.0.0.1:7654; false leaves the socket as the only way in.
+/// # Both read once at startup
+/// http = true
+///
+/// # Announce to every connection every 30 s (see `schedule::Job`)
+/// [[schedule]]
+/// name = "heartbeat"
+/// every_ms = 30000
+/// announce = "Still here"
+///
+/// # Extra certificate hashes advertised by /cert-hash during a rotation
+/// [[cert_pins]]
+/// value = "dbecff3c..."
+/// expires_unix_ms = 1767225600000
+/// ```
+///
+/// Everything but `storage.backend`, `storage.path`, `fanout.workers`, `journal.path` and `admin` is applied
+/// live when the file changes. A new `port` moves the WebTransport endpoint and redirects
+/// existing sessions to it.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// WebTransport port. Changing it moves the endpoint and redirects live sessions.
+    pub port: u16,
+    pub log_level: String,
+    pub origins: Vec<String>,
+    pub impairment: ImpairmentConfig,
+    pub datagrams: DatagramConfig,
+    pub limits: Limits,
+    pub violations: ViolationConfig,
+    pub chaos: ChaosConfig,
+    pub rooms: RoomQuotas,
+    pub resume: ResumeConfig,
+    pub liveness: LivenessConfig,
+    pub quota: QuotaConfig,
+    pub watchdog: WatchdogConfig,
+    pub stats: StatsConfig,
+    pub storage: StorageConfig,
+    pub memory: MemoryConfig,
+    pub pipelines: PipelineConfig,
+    pub fanout: FanoutConfig,
+    pub middleware: MiddlewareConfig,
+    pub journal: JournalConfig,
+    pub transfers: TransferConfig,
+    pub admin: AdminConfig,
+    pub schedule: Vec<Job>,
+    pub cert_pins: Vec<CertPin>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImpairmentConfig {
+    /// Outgoing datagram loss rate (0.0..=1.0).
+    pub loss: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatagramConfig {
+    /// Prefix echoed datagrams. Without the prefix, unmoderated payloads are
+    /// echoed as received instead of being rebuilt.
+    pub echo_prefix: bool,
+}
+
+impl Default for DatagramConfig {
+    fn default() -> Self {
+        Self { echo_prefix: true }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub messages_per_second: u32,
+    /// Bidirectional streams a connection may have open at once; 0 disables the limit.
+    pub max_bi_streams: u32,
+    /// Unidirectional streams a connection may have open at once; 0 disables the limit.
+    pub max_uni_streams: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ViolationConfig {
+    /// Violations (malformed frames, oversized frames, bad sequence numbers) a
+    /// connection may commit; the one reaching this closes it.
+    pub strikes: u32,
+}
+
+impl Default for ViolationConfig {
+    fn default() -> Self {
+        Self { strikes: 3 }
+    }
+}
+
+/// Random failures injected for resilience testing, rolled for each frame and
+/// datagram a client sends while `enabled`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChaosConfig {
+    pub enabled: bool,
+    /// Probability (0.0..=1.0) that a frame's stream is reset instead of answered.
+    pub stream_reset: f64,
+    /// Probability (0.0..=1.0) that a frame or datagram closes its connection.
+    pub connection_close: f64,
+    /// Stream error codes to reset with, picked at random, from 0 to 255.
+    pub reset_codes: Vec<u32>,
+    /// Close codes to close with, picked at random.
+    pub close_codes: Vec<u32>,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            stream_reset: 0.01,
+            connection_close: 0.001,
+            reset_codes: vec![0],
+            close_codes: vec![CloseCode::Kicked.code()],
+        }
+    }
+}
+
+/// Quotas each chat room is held to, 0 leaving one unlimited, and how it
+/// relays.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomQuotas {
+    /// Messages relayed per second, from all members together.
+    pub messages_per_second: u32,
+    /// Bytes of message text relayed per minute, from all members together.
+    pub bytes_per_minute: u64,
+    /// Members at once. Lowering it doesn't remove anyone.
+    pub max_members: usize,
+    /// How rooms relay to members that didn't choose when joining.
+    pub transport: RelayTransport,
+    /// Rooms relaying other than by `transport`, by name.
+    pub transports: BTreeMap<String, RelayTransport>,
+    /// Tokens that make a joining session the room's owner, by room name,
+    /// matched against its `token` tag. Other rooms are owned by whoever
+    /// created them.
+    pub owners: BTreeMap<String, String>,
+}
+
+impl RoomQuotas {
+    /// How `room` relays to members that didn't choose.
+    pub fn transport(&self, room: &str) -> RelayTransport {
+        self.transports.get(room).copied().unwrap_or(self.transport)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResumeConfig {
+    /// Seconds a closed session stays resumable. Applies to sessions closing
+    /// after a change; with 0, sessions accepted from then on get no token.
+    pub grace_secs: u64,
+    /// Messages queued for each closed session until it resumes. Lowering it
+    /// trims each queue on its next message.
+    pub max_queued: usize,
+}
+
+impl Default for ResumeConfig {
+    fn default() -> Self {
+        Self {
+            grace_secs: 30,
+            max_queued: 256,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LivenessConfig {
+    /// Seconds without anything from a connection before it's probed; 0 turns
+    /// probing off.
+    pub silent_secs: u64,
+    /// Milliseconds a probed connection has to send something back, the
+    /// `pong` or anything else, before it's closed as `unresponsive`.
+    pub deadline_ms: u64,
+}
+
+impl Default for LivenessConfig {
+    fn default() -> Self {
+        Self {
+            silent_secs: 30,
+            deadline_ms: 5000,
+        }
+    }
+}
+
+/// The per-connection byte cap (see `quota`).
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaConfig {
+    /// Bytes a connection may send and receive in all, counted as UDP
+    /// payload; 0 means no cap.
+    pub bytes: u64,
+    /// Share of `bytes` (above 0.0, below 1.0) a connection has used when
+    /// it's warned.
+    pub warn_ratio: f64,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            bytes: 0,
+            warn_ratio: 0.8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchdogConfig {
+    /// Milliseconds a connection's handler can go without processing a
+    /// heartbeat before it's aborted and the connection closed as
+    /// `server_error`; 0 turns the watchdog off.
+    pub deadline_ms: u64,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self { deadline_ms: 10000 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatsConfig {
+    /// Minutes of per-second samples kept; shrinking it drops the oldest at once.
+    pub window_minutes: u64,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        Self { window_minutes: 10 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Where persisted data goes. Read at startup only.
+    pub backend: Backend,
+    /// Directory for `filesystem`, database file for `sqlite`. Read at startup only.
+    pub path: PathBuf,
+    /// Latest messages kept per room; lowering it trims each room's history
+    /// on its next message.
+    pub room_history: usize,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: Backend::default(),
+            path: PathBuf::from("playground-data"),
+            room_history: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemoryConfig {
+    pub budget_bytes: usize,
+    pub policy: Policy,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        Self {
+            budget_bytes: 64 * 1024 * 1024,
+            policy: Policy::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    pub interval_ms: u64,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self { interval_ms: 1000 }
+    }
+}
+
+/// How frames sent to many connections at once are queued and sent.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FanoutConfig {
+    /// Tasks taking turns at connections' queues. Read at startup only.
+    pub workers: usize,
+    /// Frames queued for each connection. Lowering it trims each queue on its
+    /// next frame.
+    pub queue_len: usize,
+}
+
+impl Default for FanoutConfig {
+    fn default() -> Self {
+        Self {
+            workers: 8,
+            queue_len: 256,
+        }
+    }
+}
+
+/// Cross-cutting handling of the frames clients send on streams, as layers
+/// around the handler (see `middleware`).
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MiddlewareConfig {
+    /// Outermost first. A layer left out is skipped altogether, e.g. without
+    /// `rate_limit` `limits.messages_per_second` no longer applies to streams.
+    pub layers: Vec<Layer>,
+    /// Values of the `token` session tag the `auth` layer accepts.
+    pub tokens: Vec<String>,
+}
+
+impl Default for MiddlewareConfig {
+    fn default() -> Self {
+        Self {
+            layers: vec![
+                Layer::Log,
+                Layer::Metrics,
+                Layer::Impairment,
+                Layer::RateLimit,
+            ],
+            tokens: Vec::new(),
+        }
+    }
+}
+
+/// The crash-safe session journal (see `journal`).
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JournalConfig {
+    /// File to append records to, across runs; none turns the journal off.
+    /// Read at startup only.
+    pub path: Option<PathBuf>,
+    pub fsync: Fsync,
+    /// How often the `interval` policy fsyncs.
+    pub fsync_interval_ms: u64,
+    /// Seconds between checkpoints of each connection's message counts,
+    /// written only if they changed; 0 leaves them to the disconnect.
+    pub checkpoint_secs: u64,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            fsync: Fsync::default(),
+            fsync_interval_ms: 1000,
+            checkpoint_secs: 30,
+        }
+    }
+}
+
+/// Resumable file uploads (see `transfers`).
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransferConfig {
+    /// Largest file a transfer may carry, in bytes.
+    pub max_size: u64,
+    /// Seconds an unfinished transfer is kept after it last moved, for its
+    /// client to reconnect and resume it.
+    pub keep_secs: u64,
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 64 * 1024 * 1024,
+            keep_secs: 300,
+        }
+    }
+}
+
+/// Where the HTTP API listens. Read at startup only.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// UNIX socket to serve the API on as well, for operators who'd rather not
+    /// have a TCP listener. Unix platforms only.
+    pub socket: Option<PathBuf>,
+    /// Listen on TCP 127.0.0.1:7654. Without it the client pages go too.
+    pub http: bool,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            socket: None,
+            http: true,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: 8765,
+            log_level: "info".to_string(),
+            origins: Vec::new(),
+            impairment: ImpairmentConfig::default(),
+            datagrams: DatagramConfig::default(),
+            limits: Limits::default(),
+            violations: ViolationConfig::default(),
+            chaos: ChaosConfig::default(),
+            rooms: RoomQuotas::default(),
+            resume: ResumeConfig::default(),
+            liveness: LivenessConfig::default(),
+            quota: QuotaConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            stats: StatsConfig::default(),
+            storage: StorageConfig::default(),
+            memory: MemoryConfig::default(),
+            pipelines: PipelineConfig::default(),
+            fanout: FanoutConfig::default(),
+            middleware: MiddlewareConfig::default(),
+            journal: JournalConfig::default(),
+            transfers: TransferConfig::default(),
+            admin: AdminConfig::default(),
+            schedule: Vec::new(),
+            cert_pins: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Read and validate the config at `path`. A file that parses but breaks
+    /// any rule fails with every problem found, one per line, each with the
+    /// line of the file it's on.
+    pub async fn load(path: &Path) -> Result<Self> {
+        let text = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        let config: Config =
+            toml::from_str(&text).with_context(|| format!("Invalid config {}", path.display()))?;
+
+        let problems = config.problems();
+        if !problems.is_empty() {
+            let mut problems: Vec<_> = problems
+                .into_iter()
+                .map(|(key, message)| (line_of(&text, &key), message))
+                .collect();
+            problems.sort_by_key(|(line, _)| *line);
+            let problems: Vec<_> = problems
+                .into_iter()
+                .map(|(line, message)| match line {
+                    Some(line) => format!("  {}:{}: {}", path.display(), line, message),
+                    None => format!("  {}: {}", path.display(), message),
+                })
+                .collect();
+            bail!(
+                "Invalid config {}:\n{}",
+                path.display(),
+                problems.join("\n")
+            );
+        }
+        Ok(config)
+    }
+
+    // Every rule the config breaks, as the key at fault and what's wrong with it
+    fn problems(&self) -> Vec<(String, String)> {
+        let mut problems = Vec::new();
+        let mut problem = |key: &str, message: String| problems.push((key.to_string(), message));
+
+        if self.port == 0 {
+            problem("port", "port must be from 1 to 65535".to_string());
+        }
+        if self.log_filter().is_err() {
+            problem(
+                "log_level",
+                format!(
+                    "log_level must be off, error, warn, info, debug or trace, not {:?}",
+                    self.log_level
+                ),
+            );
+        }
+        // Browsers send the bare origin, so anything more never matches
+        for (i, origin) in self.origins.iter().enumerate() {
+            let host = origin
+                .strip_prefix("http://")
+                .or_else(|| origin.strip_prefix("https://"));
+            if host.is_none_or(|host| host.is_empty() || host.contains('/')) {
+                problem(
+                    &format!("origins[{}]", i),
+                    format!(
+                        "origins entry {:?} must be scheme://host[:port], with no path",
+                        origin
+                    ),
+                );
+            }
+        }
+        if !(0.0..=1.0).contains(&self.impairment.loss) {
+            problem(
+                "impairment.loss",
+                "impairment.loss must be between 0.0 and 1.0".to_string(),
+            );
+        }
+        if self.violations.strikes == 0 {
+            problem(
+                "violations.strikes",
+                "violations.strikes must be positive".to_string(),
+            );
+        }
+        for (key, probability) in [
+            ("chaos.stream_reset", self.chaos.stream_reset),
+            ("chaos.connection_close", self.chaos.connection_close),
+        ] {
+            if !(0.0..=1.0).contains(&probability) {
+                problem(key, format!("{} must be between 0.0 and 1.0", key));
+            }
+        }
+        // Browsers see stream error codes as 8 bits, but close codes in full
+        if self.chaos.reset_codes.is_empty()
+            || self.chaos.reset_codes.iter().any(|&code| code > 255)
+        {
+            problem(
+                "chaos.reset_codes",
+                "chaos.reset_codes must list codes from 0 to 255".to_string(),
+            );
+        }
+        if self.chaos.close_codes.is_empty() {
+            problem(
+                "chaos.close_codes",
+                "chaos.close_codes must list at least one code".to_string(),
+            );
+        }
+        let named = (self
+            .rooms
+            .transports
+            .keys()
+            .map(|name| ("rooms.transports", name)))
+        .chain(self.rooms.owners.keys().map(|name| ("rooms.owners", name)));
+        for (key, name) in named {
+            if !room::valid_room(name) {
+                problem(
+                    key,
+                    format!(
+                        "{} names room {:?}, which must be 1 to {} bytes",
+                        key,
+                        name,
+                        room::MAX_ROOM_LEN
+                    ),
+                );
+            }
+        }
+        for (name, token) in &self.rooms.owners {
+            if token.is_empty() {
+                problem(
+                    "rooms.owners",
+                    format!("rooms.owners gives room {:?} an empty token", name),
+                );
+            }
+        }
+        if self.resume.grace_secs > 3600 {
+            problem(
+                "resume.grace_secs",
+                "resume.grace_secs must be at most 3600".to_string(),
+            );
+        }
+        if self.resume.max_queued > 65536 {
+            problem(
+                "resume.max_queued",
+                "resume.max_queued must be at most 65536".to_string(),
+            );
+        }
+        if self.liveness.silent_secs > 3600 {
+            problem(
+                "liveness.silent_secs",
+                "liveness.silent_secs must be at most 3600".to_string(),
+            );
+        }
+        if !(100..=60000).contains(&self.liveness.deadline_ms) {
+            problem(
+                "liveness.deadline_ms",
+                "liveness.deadline_ms must be from 100 to 60000".to_string(),
+            );
+        } else if self.liveness.silent_secs > 0
+            && self.liveness.deadline_ms >= self.liveness.silent_secs * 1000
+        {
+            // Otherwise a connection could go quiet again before its last probe
+            // timed out
+            problem(
+                "liveness.deadline_ms",
+                format!(
+                    "liveness.deadline_ms must be shorter than liveness.silent_secs ({} s) while probing is on",
+                    self.liveness.silent_secs
+                ),
+            );
+        }
+        if !(self.quota.warn_ratio > 0.0 && self.quota.warn_ratio < 1.0) {
+            problem(
+                "quota.warn_ratio",
+                "quota.warn_ratio must be above 0.0 and below 1.0".to_string(),
+            );
+        }
+        // Heartbeats come every second; a shorter deadline would trip on a
+        // handler that's merely busy
+        if self.watchdog.deadline_ms != 0 && !(2000..=600000).contains(&self.watchdog.deadline_ms) {
+            problem(
+                "watchdog.deadline_ms",
+                "watchdog.deadline_ms must be 0 or from 2000 to 600000".to_string(),
+            );
+        }
+        if !(1..=1440).contains(&self.stats.window_minutes) {
+            problem(
+                "stats.window_minutes",
+                "stats.window_minutes must be from 1 to 1440".to_string(),
+            );
+        }
+        if cfg!(not(feature = "sqlite")) && self.storage.backend == Backend::Sqlite {
+            problem(
+                "storage.backend",
+                "storage.backend \"sqlite\" needs the server built with the sqlite feature"
+                    .to_string(),
+            );
+        }
+        if self.storage.backend != Backend::Memory && self.storage.path.as_os_str().is_empty() {
+            problem(
+                "storage.path",
+                format!(
+                    "storage.path is needed for the {:?} backend",
+                    self.storage.backend
+                ),
+            );
+        }
+        // Enough for one stream to reassemble its largest frames
+        if self.memory.budget_bytes < protocol::MAX_BUFFERED_LEN {
+            problem(
+                "memory.budget_bytes",
+                format!(
+                    "memory.budget_bytes must be at least {}",
+                    protocol::MAX_BUFFERED_LEN
+                ),
+            );
+        }
+        if self.pipelines.interval_ms == 0 {
+            problem(
+                "pipelines.interval_ms",
+                "pipelines.interval_ms must be positive".to_string(),
+            );
+        }
+        if !(1..=256).contains(&self.fanout.workers) {
+            problem(
+                "fanout.workers",
+                "fanout.workers must be from 1 to 256".to_string(),
+            );
+        }
+        if !(1..=65536).contains(&self.fanout.queue_len) {
+            problem(
+                "fanout.queue_len",
+                "fanout.queue_len must be from 1 to 65536".to_string(),
+            );
+        }
+        for (i, layer) in self.middleware.layers.iter().enumerate() {
+            if self.middleware.layers[..i].contains(layer) {
+                problem(
+                    &format!("middleware.layers[{}]", i),
+                    format!(
+                        "middleware layer '{}' is listed more than once",
+                        layer.name()
+                    ),
+                );
+            }
+        }
+        if self.middleware.layers.contains(&Layer::Auth) && self.middleware.tokens.is_empty() {
+            problem(
+                "middleware.tokens",
+                "middleware.tokens must list a token for the auth layer to accept".to_string(),
+            );
+        }
+        if self
+            .journal
+            .path
+            .as_ref()
+            .is_some_and(|path| path.as_os_str().is_empty())
+        {
+            problem("journal.path", "journal.path must be a path".to_string());
+        }
+        if !(10..=60000).contains(&self.journal.fsync_interval_ms) {
+            problem(
+                "journal.fsync_interval_ms",
+                "journal.fsync_interval_ms must be from 10 to 60000".to_string(),
+            );
+        }
+        if self.journal.checkpoint_secs > 3600 {
+            problem(
+                "journal.checkpoint_secs",
+                "journal.checkpoint_secs must be at most 3600".to_string(),
+            );
+        }
+        if self.transfers.max_size == 0 {
+            problem(
+                "transfers.max_size",
+                "transfers.max_size must be at least 1".to_string(),
+            );
+        }
+        if !(1..=86400).contains(&self.transfers.keep_secs) {
+            problem(
+                "transfers.keep_secs",
+                "transfers.keep_secs must be from 1 to 86400".to_string(),
+            );
+        }
+        if self
+            .admin
+            .socket
+            .as_ref()
+            .is_some_and(|socket| socket.as_os_str().is_empty())
+        {
+            problem("admin.socket", "admin.socket must be a path".to_string());
+        }
+        if cfg!(not(unix)) && self.admin.socket.is_some() {
+            problem(
+                "admin.socket",
+                "admin.socket needs a unix platform".to_string(),
+            );
+        }
+        if !self.admin.http && self.admin.socket.is_none() {
+            problem(
+                "admin.http",
+                "admin.http = false needs admin.socket, or nothing serves the API".to_string(),
+            );
+        }
+        for (i, job) in self.schedule.iter().enumerate() {
+            let key = format!("schedule[{}]", i);
+            if let Err(e) = job.validate() {
+                problem(&key, format!("{:#}", e));
+            }
+            if self.schedule[..i]
+                .iter()
+                .any(|other| other.name == job.name)
+            {
+                problem(
+                    &format!("{}.name", key),
+                    format!("schedule name '{}' is used more than once", job.name),
+                );
+            }
+        }
+        for (i, pin) in self.cert_pins.iter().enumerate() {
+            if pin.algorithm != "sha-256"
+                || pin.value.len() != 64
+                || !pin.value.bytes().all(|b| b.is_ascii_hexdigit())
+            {
+                problem(
+                    &format!("cert_pins[{}].value", i),
+                    format!(
+                        "cert_pins entry {:?} is not a hex SHA-256 digest",
+                        pin.value
+                    ),
+                );
+            }
+        }
+        problems
+    }
+
+    pub fn log_filter(&self) -> Result<LevelFilter> {
+        self.log_level
+            .parse()
+            .with_context(|| format!("Invalid log_level {:?}", self.log_level))
+    }
+
+    /// Whether a session from `origin` may be accepted.
+    pub fn allows_origin(&self, origin: Option<&str>) -> bool {
+        self.origins.is_empty()
+            || origin.is_some_and(|origin| self.origins.iter().any(|o| o == origin))
+    }
+
+    // Human-readable list of what differs between two configs
+    fn changes(&self, new: &Config) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.port != new.port {
+            changes.push(format!(
+                "port {} -> {} (sessions redirected)",
+                self.port, new.port
+            ));
+        }
+        if self.log_level != new.log_level {
+            changes.push(format!("log_level {} -> {}", self.log_level, new.log_level));
+        }
+        if self.origins != new.origins {
+            changes.push(format!("origins {:?} -> {:?}", self.origins, new.origins));
+        }
+        if self.impairment.loss != new.impairment.loss {
+            changes.push(format!(
+                "impairment.loss {} -> {}",
+                self.impairment.loss, new.impairment.loss
+            ));
+        }
+        if self.datagrams.echo_prefix != new.datagrams.echo_prefix {
+            changes.push(format!(
+                "datagrams.echo_prefix {} -> {}",
+                self.datagrams.echo_prefix, new.datagrams.echo_prefix
+            ));
+        }
+        if self.limits.messages_per_second != new.limits.messages_per_second {
+            changes.push(format!(
+                "limits.messages_per_second {} -> {}",
+                self.limits.messages_per_second, new.limits.messages_per_second
+            ));
+        }
+        if self.limits.max_bi_streams != new.limits.max_bi_streams {
+            changes.push(format!(
+                "limits.max_bi_streams {} -> {}",
+                self.limits.max_bi_streams, new.limits.max_bi_streams
+            ));
+        }
+        if self.limits.max_uni_streams != new.limits.max_uni_streams {
+            changes.push(format!(
+                "limits.max_uni_streams {} -> {}",
+                self.limits.max_uni_streams, new.limits.max_uni_streams
+            ));
+        }
+        if self.violations.strikes != new.violations.strikes {
+            changes.push(format!(
+                "violations.strikes {} -> {}",
+                self.violations.strikes, new.violations.strikes
+            ));
+        }
+        if self.chaos != new.chaos {
+            changes.push(format!(
+                "chaos {} -> {} (reset {}, close {})",
+                if self.chaos.enabled { "on" } else { "off" },
+                if new.chaos.enabled { "on" } else { "off" },
+                new.chaos.stream_reset,
+                new.chaos.connection_close
+            ));
+        }
+        if (
+            self.rooms.messages_per_second,
+            self.rooms.bytes_per_minute,
+            self.rooms.max_members,
+        ) != (
+            new.rooms.messages_per_second,
+            new.rooms.bytes_per_minute,
+            new.rooms.max_members,
+        ) {
+            changes.push(format!(
+                "rooms {}/s, {} bytes/min, {} members -> {}/s, {} bytes/min, {} members",
+                self.rooms.messages_per_second,
+                self.rooms.bytes_per_minute,
+                self.rooms.max_members,
+                new.rooms.messages_per_second,
+                new.rooms.bytes_per_minute,
+                new.rooms.max_members
+            ));
+        }
+        if self.rooms.transport != new.rooms.transport
+            || self.rooms.transports != new.rooms.transports
+        {
+            changes.push(format!(
+                "rooms.transport {} ({} room(s) overridden) -> {} ({} room(s) overridden)",
+                self.rooms.transport.as_str(),
+                self.rooms.transports.len(),
+                new.rooms.transport.as_str(),
+                new.rooms.transports.len()
+            ));
+        }
+        // Tokens are never logged
+        if self.rooms.owners != new.rooms.owners {
+            changes.push(format!(
+                "rooms.owners {} room(s) -> {} room(s)",
+                self.rooms.owners.len(),
+                new.rooms.owners.len()
+            ));
+        }
+        if self.resume.grace_secs != new.resume.grace_secs {
+            changes.push(format!(
+                "resume.grace_secs {} -> {}",
+                self.resume.grace_secs, new.resume.grace_secs
+            ));
+        }
+        if self.resume.max_queued != new.resume.max_queued {
+            changes.push(format!(
+                "resume.max_queued {} -> {}",
+                self.resume.max_queued, new.resume.max_queued
+            ));
+        }
+        if self.liveness != new.liveness {
+            changes.push(format!(
+                "liveness.silent_secs {} -> {}, deadline_ms {} -> {}",
+                self.liveness.silent_secs,
+                new.liveness.silent_secs,
+                self.liveness.deadline_ms,
+                new.liveness.deadline_ms
+            ));
+        }
+        if self.quota != new.quota {
+            changes.push(format!(
+                "quota.bytes {} -> {}, warn_ratio {} -> {}",
+                self.quota.bytes, new.quota.bytes, self.quota.warn_ratio, new.quota.warn_ratio
+            ));
+        }
+        if self.watchdog != new.watchdog {
+            changes.push(format!(
+                "watchdog.deadline_ms {} -> {}",
+                self.watchdog.deadline_ms, new.watchdog.deadline_ms
+            ));
+        }
+        if self.stats.window_minutes != new.stats.window_minutes {
+            changes.push(format!(
+                "stats.window_minutes {} -> {}",
+                self.stats.window_minutes, new.stats.window_minutes
+            ));
+        }
+        if (&self.storage.backend, &self.storage.path) != (&new.storage.backend, &new.storage.path)
+        {
+            changes.push(format!(
+                "storage {:?} at {} -> {:?} at {} (on restart)",
+                self.storage.backend,
+                self.storage.path.display(),
+                new.storage.backend,
+                new.storage.path.display()
+            ));
+        }
+        if self.storage.room_history != new.storage.room_history {
+            changes.push(format!(
+                "storage.room_history {} -> {}",
+                self.storage.room_history, new.storage.room_history
+            ));
+        }
+        if self.memory != new.memory {
+            changes.push(format!(
+                "memory {} bytes ({:?}) -> {} bytes ({:?})",
+                self.memory.budget_bytes,
+                self.memory.policy,
+                new.memory.budget_bytes,
+                new.memory.policy
+            ));
+        }
+        if self.pipelines.interval_ms != new.pipelines.interval_ms {
+            changes.push(format!(
+                "pipelines.interval_ms {} -> {}",
+                self.pipelines.interval_ms, new.pipelines.interval_ms
+            ));
+        }
+        if self.fanout.workers != new.fanout.workers {
+            changes.push(format!(
+                "fanout.workers {} -> {} (on restart)",
+                self.fanout.workers, new.fanout.workers
+            ));
+        }
+        if self.fanout.queue_len != new.fanout.queue_len {
+            changes.push(format!(
+                "fanout.queue_len {} -> {}",
+                self.fanout.queue_len, new.fanout.queue_len
+            ));
+        }
+        if self.middleware.layers != new.middleware.layers {
+            let names =
+                |layers: &[Layer]| layers.iter().map(|layer| layer.name()).collect::<Vec<_>>();
+            changes.push(format!(
+                "middleware.layers {:?} -> {:?}",
+                names(&self.middleware.layers),
+                names(&new.middleware.layers)
+            ));
+        }
+        // The tokens themselves stay out of the log
+        if self.middleware.tokens != new.middleware.tokens {
+            changes.push(format!(
+                "middleware.tokens: {} -> {} tokens",
+                self.middleware.tokens.len(),
+                new.middleware.tokens.len()
+            ));
+        }
+        if self.journal.path != new.journal.path {
+            changes.push(format!(
+                "journal.path {:?} -> {:?} (on restart)",
+                self.journal.path, new.journal.path
+            ));
+        }
+        if self.journal.fsync != new.journal.fsync {
+            changes.push(format!(
+                "journal.fsync {:?} -> {:?}",
+                self.journal.fsync, new.journal.fsync
+            ));
+        }
+        if self.journal.fsync_interval_ms != new.journal.fsync_interval_ms {
+            changes.push(format!(
+                "journal.fsync_interval_ms {} -> {}",
+                self.journal.fsync_interval_ms, new.journal.fsync_interval_ms
+            ));
+        }
+        if self.journal.checkpoint_secs != new.journal.checkpoint_secs {
+            changes.push(format!(
+                "journal.checkpoint_secs {} -> {}",
+                self.journal.checkpoint_secs, new.journal.checkpoint_secs
+            ));
+        }
+        if self.transfers.max_size != new.transfers.max_size {
+            changes.push(format!(
+                "transfers.max_size {} -> {}",
+                self.transfers.max_size, new.transfers.max_size
+            ));
+        }
+        if self.transfers.keep_secs != new.transfers.keep_secs {
+            changes.push(format!(
+                "transfers.keep_secs {} -> {}",
+                self.transfers.keep_secs, new.transfers.keep_secs
+            ));
+        }
+        if self.admin != new.admin {
+            changes.push(format!(
+                "admin socket {:?}, http {} -> socket {:?}, http {} (on restart)",
+                self.admin.socket, self.admin.http, new.admin.socket, new.admin.http
+            ));
+        }
+        if self.schedule != new.schedule {
+            let names: Vec<&str> = new.schedule.iter().map(|job| job.name.as_str()).collect();
+            changes.push(format!("schedule -> {:?}", names));
+        }
+        if self.cert_pins != new.cert_pins {
+            let values: Vec<&str> = new.cert_pins.iter().map(|pin| pin.value.as_str()).collect();
+            changes.push(format!("cert_pins -> {:?}", values));
+        }
+        changes
+    }
+}
+
+// Poll the config file and apply what changed. A file that fails to load is
+// reported and ignored; the previous config stays in force.
+pub async fn watch(path: PathBuf, state: Arc<State>, log: LogHandle) {
+    let mut last_modified = modified(&path).await;
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let modified = modified(&path).await;
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        let new = match Config::load(&path).await {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("Config reload failed, keeping current settings: {:#}", e);
+                continue;
+            }
+        };
+        let old = state.config.borrow().clone();
+        let changes = old.changes(&new);
+        if changes.is_empty() {
+            continue;
+        }
+
+        apply(&state, &log, &old, &new);
+        for change in &changes {
+            info!("Config reloaded: {}", change);
+        }
+        state.config.send_replace(new);
+        state.admin.publish(AdminEvent::ConfigReloaded { changes });
+    }
+}
+
+fn apply(state: &State, log: &LogHandle, old: &Config, new: &Config) {
+    if old.log_level != new.log_level {
+        // Validated on load
+        if let Ok(filter) = new.log_filter()
+            && let Err(e) = log.reload(filter)
+        {
+            warn!("Failed to change log level: {}", e);
+        }
+    }
+    if old.memory.budget_bytes != new.memory.budget_bytes {
+        state.memory.set_limit(new.memory.budget_bytes);
+    }
+    // Only touch impairment when the file changed it, so a scenario's inject_loss isn't undone
+    if old.impairment.loss != new.impairment.loss {
+        state.impairment.set_loss(new.impairment.loss);
+    }
+}
+
+pub async fn modified(path: &Path) -> Option<SystemTime> {
+    tokio::fs::metadata(path).await.ok()?.modified().ok()
+}
+
+// The 1-based line in `text` that `key` (e.g. `schedule[1].name`) is set on,
+// or failing that the line of the nearest table it's in. None when the file
+// leaves it at its default
+fn line_of(text: &str, key: &str) -> Option<usize> {
+    let document = DeTable::parse(text).ok()?;
+    let mut span = None;
+    let mut value = DeValue::Table(document.into_inner());
+    for part in key.split('.') {
+        let (name, index) = match part.split_once('[') {
+            Some((name, index)) => (name, index.trim_end_matches(']').parse::<usize>().ok()),
+            None => (part, None),
+        };
+        let Some(mut found) = value.get(name).cloned() else {
+            break;
+        };
+        if let Some(index) = index {
+            span = Some(found.span());
+            match found.get_ref().get(index).cloned() {
+                Some(element) => found = element,
+                None => break,
+            }
+        }
+        span = Some(found.span());
+        value = found.into_inner();
+    }
+    Some(text[..span?.start].matches('\n').count() + 1)
+}
@@ -0,0 +1,100 @@
+use crate::registry::ConnectionId;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Message ids remembered per session; a copy resent after this many newer
+/// messages is handled again.
+const WINDOW: usize = 1024;
+
+/// The message ids one session sent most recently.
+#[derive(Debug, Default)]
+pub struct Seen {
+    ids: HashSet<String>,
+    /// Oldest first, to forget in order.
+    order: VecDeque<String>,
+}
+
+impl Seen {
+    // Remember `id`; false if it's already remembered
+    fn insert(&mut self, id: &str) -> bool {
+        if self.ids.contains(id) {
+            return false;
+        }
+        self.ids.insert(id.to_string());
+        self.order.push_back(id.to_string());
+        if self.order.len() > WINDOW
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.ids.remove(&oldest);
+        }
+        true
+    }
+}
+
+/// Dedupe figures for `/stats`.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// Messages and relays dropped because their session had already sent their id.
+    pub duplicates: u64,
+    /// Live connections with ids remembered.
+    pub sessions: usize,
+}
+
+// Ids of the messages and relays each connection sent, so one sent again, by a
+// client retrying it or after resuming its session, is only handled once
+pub struct Dedup {
+    seen: Mutex<HashMap<ConnectionId, Seen>>,
+    duplicates: AtomicU64,
+}
+
+impl Dedup {
+    pub fn new() -> Self {
+        Self {
+            seen: Mutex::new(HashMap::new()),
+            duplicates: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether `connection` sends message `id` for the first time. A repeat is
+    /// counted as a duplicate.
+    pub fn first(&self, connection: ConnectionId, id: &str) -> bool {
+        let first = self
+            .seen
+            .lock()
+            .unwrap()
+            .entry(connection)
+            .or_default()
+            .insert(id);
+        if !first {
+            self.duplicates.fetch_add(1, Ordering::Relaxed);
+        }
+        first
+    }
+
+    /// Take the ids `connection` sent, e.g. to park them with its session.
+    pub fn take(&self, connection: ConnectionId) -> Seen {
+        self.seen
+            .lock()
+            .unwrap()
+            .remove(&connection)
+            .unwrap_or_default()
+    }
+
+    /// Carry ids taken from a resumed session over to `connection`.
+    pub fn restore(&self, connection: ConnectionId, seen: Seen) {
+        let mut windows = self.seen.lock().unwrap();
+        let window = windows.entry(connection).or_default();
+        for id in seen.order {
+            window.insert(&id);
+        }
+    }
+
+    pub fn report(&self) -> Report {
+        Report {
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+            sessions: self.seen.lock().unwrap().len(),
+        }
+    }
+}
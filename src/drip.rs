@@ -0,0 +1,103 @@
+use crate::registry::ConnectionId;
+use protocol::rpc::{DripParams, DripResult};
+use protocol::{Frame, Framing, PlaygroundError};
+use std::time::Duration;
+use tracing::{debug, info};
+use wtransport::Connection;
+
+/// Longest text a drip sends; its frame stays under `protocol::MAX_FRAME_LEN`.
+const MAX_TEXT_BYTES: u32 = 60 * 1024;
+
+/// Longest pause between two writes.
+const MAX_DELAY_MS: u32 = 10_000;
+
+/// Longest a whole drip may take.
+const MAX_DURATION_MS: u64 = 5 * 60 * 1000;
+
+// Validate a drip request and start writing it to the connection in the
+// background: one `message` frame of `text_bytes` letters, `chunk_bytes` at a
+// time with `delay_ms` between writes, on a new unidirectional stream. The frame
+// is split without regard to its header or JSON, so the client's decoder sees
+// partial frames the whole way through.
+pub fn start(
+    id: ConnectionId,
+    connection: Connection,
+    framing: Framing,
+    params: DripParams,
+) -> Result<DripResult, PlaygroundError> {
+    if !(1..=MAX_TEXT_BYTES).contains(&params.text_bytes) {
+        return Err(PlaygroundError::InvalidParams(format!(
+            "text_bytes must be between 1 and {}",
+            MAX_TEXT_BYTES
+        )));
+    }
+    if params.chunk_bytes == 0 {
+        return Err(PlaygroundError::InvalidParams(
+            "chunk_bytes must be at least 1".to_string(),
+        ));
+    }
+    if params.delay_ms > MAX_DELAY_MS {
+        return Err(PlaygroundError::InvalidParams(format!(
+            "delay_ms must be at most {}",
+            MAX_DELAY_MS
+        )));
+    }
+
+    let text = (0..params.text_bytes)
+        .map(|i| (b'a' + (i % 26) as u8) as char)
+        .collect();
+    let bytes = framing.encode(&Frame::Message {
+        text,
+        seq: None,
+        id: None,
+        timing: None,
+    });
+    let chunk_bytes = params.chunk_bytes as usize;
+    let chunks = bytes.len().div_ceil(chunk_bytes) as u32;
+    let duration_ms = (chunks - 1) as u64 * params.delay_ms as u64;
+    if duration_ms > MAX_DURATION_MS {
+        return Err(PlaygroundError::InvalidParams(format!(
+            "drip would take {} s; the limit is {} s",
+            duration_ms / 1000,
+            MAX_DURATION_MS / 1000
+        )));
+    }
+
+    info!(
+        "Dripping {} bytes to connection {} in {} chunks over {} ms",
+        bytes.len(),
+        id,
+        chunks,
+        duration_ms
+    );
+    let result = DripResult {
+        total_bytes: bytes.len() as u32,
+        chunks,
+        duration_ms,
+    };
+    let delay = Duration::from_millis(params.delay_ms as u64);
+    tokio::spawn(async move {
+        match run(&connection, &bytes, chunk_bytes, delay).await {
+            Ok(()) => info!("Drip to connection {} finished", id),
+            Err(e) => debug!("Drip to connection {} stopped: {}", id, e),
+        }
+    });
+    Ok(result)
+}
+
+async fn run(
+    connection: &Connection,
+    bytes: &[u8],
+    chunk_bytes: usize,
+    delay: Duration,
+) -> anyhow::Result<()> {
+    let mut stream = connection.open_uni().await?.await?;
+    for (i, chunk) in bytes.chunks(chunk_bytes).enumerate() {
+        if i > 0 {
+            tokio::time::sleep(delay).await;
+        }
+        stream.write_all(chunk).await?;
+    }
+    stream.finish().await?;
+    Ok(())
+}
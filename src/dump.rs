@@ -0,0 +1,253 @@
+use crate::config::Config;
+use crate::registry::SessionInfo;
+use crate::resume::ParkedSession;
+use crate::rooms::RoomReport;
+use crate::state::State;
+use crate::{impairment, memory, moderation, stats, violations};
+use anyhow::{Context, Result};
+use protocol::Framing;
+use serde::Serialize;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Stands in for every redacted value.
+const REDACTED: &str = "[redacted]";
+
+/// Keys whose values are redacted wherever they appear, as session tags,
+/// URL query parameters or fields, matched case-insensitively as substrings.
+/// A map under one keeps its keys, e.g. room names in `rooms.owners`.
+const SECRET_KEYS: &[&str] = &[
+    "token",
+    "owners",
+    "secret",
+    "password",
+    "passwd",
+    "auth",
+    "api_key",
+    "apikey",
+    "credential",
+    "cookie",
+];
+
+/// Everything the server holds, as written by `POST /admin/dump-state`.
+#[derive(Serialize)]
+struct Snapshot {
+    taken_at_unix_ms: u64,
+    uptime_ms: u64,
+    config: Config,
+    connections: Vec<Connection>,
+    rooms: Vec<RoomReport>,
+    parked_sessions: Vec<ParkedSession>,
+    profiles: impairment::Report,
+    memory: memory::Report,
+    violations: violations::Report,
+    moderation: moderation::Report,
+    stats: stats::Report,
+}
+
+#[derive(Serialize)]
+struct Connection {
+    #[serde(flatten)]
+    session: SessionInfo,
+    framing: Framing,
+    url: Option<String>,
+    rooms: Vec<String>,
+    /// Whether it was issued a session token; the token itself stays out.
+    resumable: bool,
+}
+
+/// Where a dump was written.
+#[derive(Debug, Serialize)]
+pub struct Written {
+    pub path: PathBuf,
+    pub bytes: usize,
+}
+
+/// Write a snapshot of the server's state to `state-<unix ms>.json` in the
+/// dump directory, with secrets redacted.
+pub async fn write(state: &State) -> Result<Written> {
+    let snapshot = snapshot(state);
+    let taken_at = snapshot.taken_at_unix_ms;
+    let json = serde_json::to_vec_pretty(&redact(serde_json::to_value(snapshot)?))?;
+    tokio::fs::create_dir_all(&state.dump_dir)
+        .await
+        .with_context(|| format!("Failed to create {}", state.dump_dir.display()))?;
+    let path = state.dump_dir.join(format!("state-{}.json", taken_at));
+    tokio::fs::write(&path, &json)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    info!(
+        "Dumped server state to {} ({} bytes)",
+        path.display(),
+        json.len()
+    );
+    Ok(Written {
+        path,
+        bytes: json.len(),
+    })
+}
+
+/// The config in force, with secrets redacted as in a dump, for `/admin/config`.
+pub fn config(state: &State) -> Value {
+    let config = serde_json::to_value(&*state.config.borrow()).unwrap_or_default();
+    redact(config)
+}
+
+fn snapshot(state: &State) -> Snapshot {
+    let connections = state
+        .registry
+        .sessions(&[])
+        .into_iter()
+        .map(|session| Connection {
+            framing: state.registry.framing(session.id),
+            url: state.registry.url(session.id),
+            rooms: state.rooms.rooms_of(session.id),
+            resumable: state.resume.token(session.id).is_some(),
+            session,
+        })
+        .collect();
+    Snapshot {
+        taken_at_unix_ms: now_unix_ms(),
+        uptime_ms: state.started_at.elapsed().as_millis() as u64,
+        config: state.config.borrow().clone(),
+        connections,
+        rooms: state.rooms.report(),
+        parked_sessions: state.resume.parked(),
+        profiles: state.impairment.report(),
+        memory: state.memory.report(),
+        violations: state.violations.report(),
+        moderation: state.moderation.report(),
+        stats: stats::Report::collect(state),
+    }
+}
+
+// Replace the value of every secret-looking key, and secret-looking query
+// parameters in URLs
+fn redact(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| {
+                    let value = if is_secret(&key) {
+                        mask(value)
+                    } else if key == "url" {
+                        match value {
+                            Value::String(url) => Value::String(redact_url(&url)),
+                            value => value,
+                        }
+                    } else {
+                        redact(value)
+                    };
+                    (key, value)
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(redact).collect()),
+        value => value,
+    }
+}
+
+// Replace a secret key's value, or each value of a map of them
+fn mask(value: Value) -> Value {
+    match value {
+        Value::Null => Value::Null,
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (key, mask(value)))
+                .collect(),
+        ),
+        _ => Value::String(REDACTED.to_string()),
+    }
+}
+
+fn redact_url(url: &str) -> String {
+    let Some((base, query)) = url.split_once('?') else {
+        return url.to_string();
+    };
+    let query: Vec<_> = query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, _)) if is_secret(key) => format!("{}={}", key, REDACTED),
+            _ => pair.to_string(),
+        })
+        .collect();
+    format!("{}?{}", base, query.join("&"))
+}
+
+fn is_secret(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    SECRET_KEYS.iter().any(|secret| key.contains(secret))
+}
+
+fn now_unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn masks_room_owner_tokens() {
+        let mut config = Config::default();
+        config.rooms.owners.insert("vip".into(), "s3cret".into());
+        let redacted = redact(serde_json::to_value(config).unwrap());
+        assert_eq!(redacted["rooms"]["owners"], json!({ "vip": REDACTED }));
+        assert!(!redacted.to_string().contains("s3cret"));
+    }
+
+    #[test]
+    fn masks_secret_keys_at_any_depth() {
+        let value = json!({
+            "connections": [{
+                "id": 1,
+                "tags": { "nick": "alice", "Auth-Token": "t0k3n", "note": null },
+                "middleware": { "tokens": ["a", "b"], "layers": ["log"] },
+            }],
+            "password": null,
+        });
+        assert_eq!(
+            redact(value),
+            json!({
+                "connections": [{
+                    "id": 1,
+                    "tags": { "nick": "alice", "Auth-Token": REDACTED, "note": null },
+                    "middleware": { "tokens": REDACTED, "layers": ["log"] },
+                }],
+                "password": null,
+            })
+        );
+    }
+
+    #[test]
+    fn masks_each_value_of_a_secret_map() {
+        let value = json!({ "client_secrets": { "a": "x", "b": { "c": "y" } } });
+        assert_eq!(
+            redact(value),
+            json!({ "client_secrets": { "a": REDACTED, "b": { "c": REDACTED } } })
+        );
+    }
+
+    #[test]
+    fn masks_secret_query_parameters() {
+        assert_eq!(
+            redact_url("https://localhost:8765/jsonl?nick=bob&token=abc&API_KEY=k&room"),
+            "https://localhost:8765/jsonl?nick=bob&token=[redacted]&API_KEY=[redacted]&room"
+        );
+        assert_eq!(
+            redact_url("https://localhost:8765/"),
+            "https://localhost:8765/"
+        );
+        let value = json!({ "session": { "url": "https://h/?session_token=s&room=a" } });
+        assert_eq!(
+            redact(value),
+            json!({ "session": { "url": "https://h/?session_token=[redacted]&room=a" } })
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use crate::registry::ConnectionId;
+use crate::state::State;
+use protocol::{Frame, Framing};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+use wtransport::Connection;
+
+/// Most frames a worker sends to one subscriber before moving on to the next
+/// one waiting.
+const BATCH: usize = 16;
+
+/// How long a worker waits on one subscriber's stream before giving up on the
+/// frame and moving on, so a subscriber that stopped reading can't keep it.
+const SEND_TIMEOUT: Duration = Duration::from_secs(1);
+
+struct Queued {
+    frame: Arc<Frame>,
+    queued_at: Instant,
+}
+
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Totals {
+    pub delivered: u64,
+    /// Frames pushed out of a full queue, or still queued when the connection
+    /// closed.
+    pub dropped: u64,
+    /// Frames whose send failed or took over a second.
+    pub failed: u64,
+}
+
+#[derive(Default)]
+struct Subscriber {
+    queue: VecDeque<Queued>,
+    /// Whether a worker has it, or it's waiting for one.
+    scheduled: bool,
+    totals: Totals,
+    /// Time the last delivered frame spent queued, and the longest any did.
+    last_lag: Duration,
+    max_lag: Duration,
+}
+
+/// One subscriber's queue, for `/stats`.
+#[derive(Debug, Serialize)]
+pub struct SubscriberReport {
+    pub connection: ConnectionId,
+    pub queued: usize,
+    /// How long the oldest queued frame has waited.
+    pub lag_ms: u64,
+    pub last_lag_ms: u64,
+    pub max_lag_ms: u64,
+    #[serde(flatten)]
+    pub totals: Totals,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub workers: usize,
+    /// Frames queued across subscribers, and the longest any has waited.
+    pub queued: usize,
+    pub lag_ms: u64,
+    /// Across every subscriber, closed ones included.
+    pub totals: Totals,
+    pub subscribers: Vec<SubscriberReport>,
+}
+
+// Frames fanned out to many connections at once, i.e. room relays and pipeline
+// aggregates. Each recipient gets its own bounded queue, sent in order by
+// whichever worker picks it up, so a slow one only backs up its own queue.
+// A connection is scheduled on `ready` whenever its queue goes from idle to
+// non-empty, and rescheduled after each batch while frames remain.
+pub struct Fanout {
+    subscribers: Mutex<HashMap<ConnectionId, Subscriber>>,
+    ready: mpsc::UnboundedSender<ConnectionId>,
+    /// Taken by `start`; workers share it.
+    waiting: Mutex<Option<mpsc::UnboundedReceiver<ConnectionId>>>,
+    /// Totals of subscribers gone since.
+    closed: Mutex<Totals>,
+    workers: AtomicUsize,
+}
+
+impl Fanout {
+    pub fn new() -> Self {
+        let (ready, waiting) = mpsc::unbounded_channel();
+        Self {
+            subscribers: Mutex::new(HashMap::new()),
+            ready,
+            waiting: Mutex::new(Some(waiting)),
+            closed: Mutex::new(Totals::default()),
+            workers: AtomicUsize::new(0),
+        }
+    }
+
+    /// Queue `frame` for `connection`, dropping its oldest queued frame if it
+    /// already has `queue_len`.
+    pub fn send(&self, connection: ConnectionId, frame: Arc<Frame>, queue_len: usize) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let subscriber = subscribers.entry(connection).or_default();
+        while subscriber.queue.len() >= queue_len.max(1) {
+            subscriber.queue.pop_front();
+            subscriber.totals.dropped += 1;
+        }
+        subscriber.queue.push_back(Queued {
+            frame,
+            queued_at: Instant::now(),
+        });
+        if !subscriber.scheduled {
+            subscriber.scheduled = true;
+            let _ = self.ready.send(connection);
+        }
+    }
+
+    /// Forget a closed connection, and anything still queued for it.
+    pub fn remove(&self, connection: ConnectionId) {
+        let removed = self.subscribers.lock().unwrap().remove(&connection);
+        if let Some(subscriber) = removed {
+            let mut closed = self.closed.lock().unwrap();
+            closed.delivered += subscriber.totals.delivered;
+            closed.dropped += subscriber.totals.dropped + subscriber.queue.len() as u64;
+            closed.failed += subscriber.totals.failed;
+        }
+    }
+
+    fn next(&self, connection: ConnectionId) -> Option<Queued> {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .get_mut(&connection)?
+            .queue
+            .pop_front()
+    }
+
+    fn delivered(&self, connection: ConnectionId, lag: Duration) {
+        if let Some(subscriber) = self.subscribers.lock().unwrap().get_mut(&connection) {
+            subscriber.totals.delivered += 1;
+            subscriber.last_lag = lag;
+            subscriber.max_lag = subscriber.max_lag.max(lag);
+        }
+    }
+
+    fn failed(&self, connection: ConnectionId) {
+        if let Some(subscriber) = self.subscribers.lock().unwrap().get_mut(&connection) {
+            subscriber.totals.failed += 1;
+        }
+    }
+
+    // Hand `connection` back to the workers after a batch, unless its queue ran dry
+    fn yield_turn(&self, connection: ConnectionId) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let Some(subscriber) = subscribers.get_mut(&connection) else {
+            return;
+        };
+        if subscriber.queue.is_empty() {
+            subscriber.scheduled = false;
+        } else {
+            let _ = self.ready.send(connection);
+        }
+    }
+
+    pub fn report(&self) -> Report {
+        let subscribers = self.subscribers.lock().unwrap();
+        let mut totals = *self.closed.lock().unwrap();
+        let mut report: Vec<SubscriberReport> = subscribers
+            .iter()
+            .map(|(&connection, s)| {
+                totals.delivered += s.totals.delivered;
+                totals.dropped += s.totals.dropped;
+                totals.failed += s.totals.failed;
+                SubscriberReport {
+                    connection,
+                    queued: s.queue.len(),
+                    lag_ms: s
+                        .queue
+                        .front()
+                        .map_or(0, |queued| queued.queued_at.elapsed().as_millis() as u64),
+                    last_lag_ms: s.last_lag.as_millis() as u64,
+                    max_lag_ms: s.max_lag.as_millis() as u64,
+                    totals: s.totals,
+                }
+            })
+            .collect();
+        report.sort_by_key(|s| s.connection);
+        Report {
+            workers: self.workers.load(Ordering::Relaxed),
+            queued: report.iter().map(|s| s.queued).sum(),
+            lag_ms: report.iter().map(|s| s.lag_ms).max().unwrap_or(0),
+            totals,
+            subscribers: report,
+        }
+    }
+}
+
+/// Start `fanout.workers` workers sending queued frames.
+pub fn start(state: &Arc<State>) {
+    let Some(waiting) = state.fanout.waiting.lock().unwrap().take() else {
+        return;
+    };
+    let workers = state.config.borrow().fanout.workers;
+    state.fanout.workers.store(workers, Ordering::Relaxed);
+    let waiting = Arc::new(tokio::sync::Mutex::new(waiting));
+    for _ in 0..workers {
+        tokio::spawn(work(state.clone(), waiting.clone()));
+    }
+}
+
+async fn work(
+    state: Arc<State>,
+    waiting: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<ConnectionId>>>,
+) {
+    loop {
+        let Some(id) = waiting.lock().await.recv().await else {
+            return;
+        };
+        // Queued for a connection that closed as it was sent to
+        let Some(connection) = state.registry.get(id) else {
+            state.fanout.remove(id);
+            continue;
+        };
+        let framing = state.registry.framing(id);
+        for _ in 0..BATCH {
+            let Some(queued) = state.fanout.next(id) else {
+                break;
+            };
+            let sent = tokio::time::timeout(
+                SEND_TIMEOUT,
+                push(&state, &connection, framing, &queued.frame),
+            )
+            .await;
+            match sent {
+                Ok(Ok(())) => state.fanout.delivered(id, queued.queued_at.elapsed()),
+                Ok(Err(e)) => {
+                    state.fanout.failed(id);
+                    warn!("Failed to fan out to connection {}: {}", id, e);
+                }
+                Err(_) => {
+                    state.fanout.failed(id);
+                    debug!(
+                        "Connection {} took over {:?} to take a frame; moving on",
+                        id, SEND_TIMEOUT
+                    );
+                    break;
+                }
+            }
+        }
+        state.fanout.yield_turn(id);
+    }
+}
+
+// Send `frame` on a new stream, stamped with its send time if it carries
+// timing. Its place in the connection's order is set once it's written, so the
+// peer's acknowledgement, a round trip or more away, is waited for on the side
+// rather than holding up the next frame.
+async fn push(
+    state: &State,
+    connection: &Connection,
+    framing: Framing,
+    frame: &Frame,
+) -> anyhow::Result<()> {
+    let mut stream = connection.open_uni().await?.await?;
+    let mut frame = frame.clone();
+    frame.stamp_sent(state.clock_us(Instant::now()));
+    stream.write_all(&framing.encode(&frame)).await?;
+    tokio::spawn(async move {
+        if let Err(e) = stream.finish().await {
+            debug!("Fanned-out frame wasn't acknowledged: {}", e);
+        }
+    });
+    Ok(())
+}
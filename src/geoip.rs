@@ -0,0 +1,97 @@
+//! Where clients connect from, with the `geoip` feature: each session's remote
+//! address is looked up in a local MaxMind database (GeoLite2 or GeoIP2 City or
+//! Country, in MMDB format) given with `--geoip-db`, and the result listed with
+//! the session in `/connections`. Addresses the database doesn't cover, such as
+//! loopback and private ones, have no location.
+
+use anyhow::Result;
+#[cfg(not(feature = "geoip"))]
+use anyhow::bail;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// A client's approximate location. Fields the database doesn't have are null,
+/// e.g. everything but `continent` and `country` with a Country database.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Location {
+    /// Two-letter continent code, e.g. `EU`.
+    pub continent: Option<String>,
+    /// ISO 3166-1 alpha-2 country code, e.g. `DE`.
+    pub country: Option<String>,
+    /// English city name.
+    pub city: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// Kilometres around the coordinates the client likely is within.
+    pub accuracy_radius_km: Option<u16>,
+}
+
+// The database, if one was given
+pub struct GeoIp {
+    #[cfg(feature = "geoip")]
+    reader: Option<maxminddb::Reader<Vec<u8>>>,
+}
+
+impl GeoIp {
+    /// No database: nothing has a location.
+    pub fn new() -> Self {
+        Self {
+            #[cfg(feature = "geoip")]
+            reader: None,
+        }
+    }
+
+    /// Load the database at `path` into memory.
+    #[cfg(feature = "geoip")]
+    pub fn open(path: &Path) -> Result<Self> {
+        use anyhow::Context;
+
+        let reader = maxminddb::Reader::open_readfile(path)
+            .with_context(|| format!("Failed to open GeoIP database {}", path.display()))?;
+        tracing::info!(
+            "Looking up client locations in {} ({})",
+            path.display(),
+            reader.metadata().database_type
+        );
+        Ok(Self {
+            reader: Some(reader),
+        })
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    pub fn open(_: &Path) -> Result<Self> {
+        bail!("--geoip-db needs a build with the geoip feature")
+    }
+
+    /// Where `ip` is, if there's a database and it knows.
+    #[cfg(feature = "geoip")]
+    pub fn lookup(&self, ip: IpAddr) -> Option<Location> {
+        use maxminddb::geoip2::City;
+
+        let reader = self.reader.as_ref()?;
+        // Dual-stack sockets report IPv4 clients as IPv4-mapped IPv6
+        let ip = ip.to_canonical();
+        // A Country database decodes as a City record with no city in it
+        let record = match reader.lookup(ip).and_then(|result| result.decode::<City>()) {
+            Ok(record) => record?,
+            Err(e) => {
+                tracing::debug!("GeoIP lookup of {} failed: {}", ip, e);
+                return None;
+            }
+        };
+        Some(Location {
+            continent: record.continent.code.map(str::to_string),
+            country: record.country.iso_code.map(str::to_string),
+            city: record.city.names.english.map(str::to_string),
+            latitude: record.location.latitude,
+            longitude: record.location.longitude,
+            accuracy_radius_km: record.location.accuracy_radius,
+        })
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    pub fn lookup(&self, _: IpAddr) -> Option<Location> {
+        None
+    }
+}
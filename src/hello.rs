@@ -0,0 +1,65 @@
+use crate::registry::ConnectionId;
+use crate::state::State;
+use protocol::hello::{BuildInfo, Limits, PROTOCOL_VERSION, ServerInfo};
+use protocol::{Frame, Framing, MAX_FRAME_LEN};
+use std::sync::Arc;
+use tracing::{debug, warn};
+use wtransport::Connection;
+
+/// What this server can do and allows on `connection`, as of now, and the
+/// token connection `id` can be resumed with.
+pub fn server_info(state: &State, id: ConnectionId, connection: &Connection) -> ServerInfo {
+    let config = state.config.borrow();
+    let mut features = vec![
+        "json_lines",
+        "rpc",
+        "pipelines",
+        "rooms",
+        "room_datagrams",
+        "reliable_datagrams",
+        "mtu_probes",
+        "pings",
+        "bandwidth_estimates",
+        "transfers",
+    ];
+    let session_token = state.resume.token(id);
+    if session_token.is_some() {
+        features.push("resume");
+    }
+    if cfg!(feature = "protobuf") {
+        features.push("protobuf");
+    }
+
+    ServerInfo {
+        protocol_versions: vec![PROTOCOL_VERSION],
+        max_datagram_size: connection.max_datagram_size().map(|size| size as u64),
+        limits: Limits {
+            max_frame_len: MAX_FRAME_LEN as u64,
+            messages_per_second: config.limits.messages_per_second,
+            violation_strikes: config.violations.strikes,
+            max_bi_streams: config.limits.max_bi_streams,
+            max_uni_streams: config.limits.max_uni_streams,
+        },
+        features: features.into_iter().map(String::from).collect(),
+        build: BuildInfo {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            profile: if cfg!(debug_assertions) {
+                "debug"
+            } else {
+                "release"
+            }
+            .to_string(),
+        },
+        session_token,
+    }
+}
+
+// Greet a newly accepted session with a hello frame on its own unidirectional stream
+pub async fn send(state: Arc<State>, id: ConnectionId, connection: Connection, framing: Framing) {
+    let info = server_info(&state, id, &connection);
+    debug!("Connection {} hello: {:?}", id, info);
+    if let Err(e) = State::push_frame(&connection, framing, &Frame::Hello(info)).await {
+        warn!("Failed to send hello to connection {}: {}", id, e);
+    }
+}
@@ -0,0 +1,637 @@
+use crate::backoff;
+use crate::impairment::Preset;
+use crate::metadata::{self, TagFilter};
+use crate::state::State;
+use crate::{dump, profiling, prometheus, rooms, stats, timeseries};
+use anyhow::{Result, bail};
+use protocol::certs::CertPins;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+/// Longest request line or header line we accept.
+const MAX_LINE_LEN: usize = 8 * 1024;
+
+/// Most headers we accept on one request.
+const MAX_HEADERS: usize = 64;
+
+/// Largest request body we read.
+const MAX_BODY_LEN: usize = 1024 * 1024;
+
+/// How long a kept-alive connection may sit idle before we close it.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where `wasm-pack build --target web` puts the WASM client bundle.
+const WASM_PKG_DIR: &str = "wasm-client/pkg";
+
+pub async fn start_http_server(state: Arc<State>) -> Result<()> {
+    let addr: SocketAddr = "127.0.0.1:7654".parse()?;
+    let listener = TcpListener::bind(addr).await?;
+    info!("HTTP server listening on http://{}", addr);
+    info!("Open http://127.0.0.1:7654 in your browser to test");
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                backoff::http_accept_failed(&state, e).await?;
+                continue;
+            }
+        };
+        state.accept_backoff.succeeded();
+        let state = state.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(stream, &state).await {
+                warn!("HTTP connection error from {}: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Serve the same API on a UNIX socket at `path`, readable and writable by
+/// this user only, e.g. `curl --unix-socket <path> http://localhost/stats`. A
+/// socket left at `path` by an earlier run is replaced.
+#[cfg(unix)]
+pub async fn start_unix_server(state: Arc<State>, path: std::path::PathBuf) -> Result<()> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use tokio::net::UnixListener;
+
+    if let Ok(metadata) = tokio::fs::symlink_metadata(&path).await {
+        if !metadata.file_type().is_socket() {
+            bail!("{} exists and isn't a socket", path.display());
+        }
+        tokio::fs::remove_file(&path).await?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).await?;
+    info!("HTTP API listening on UNIX socket {}", path.display());
+
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                backoff::http_accept_failed(&state, e).await?;
+                continue;
+            }
+        };
+        state.accept_backoff.succeeded();
+        let state = state.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(stream, &state).await {
+                warn!("HTTP connection error on UNIX socket: {}", e);
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn start_unix_server(_: Arc<State>, path: std::path::PathBuf) -> Result<()> {
+    bail!(
+        "can't serve on {}: UNIX sockets need a unix platform",
+        path.display()
+    )
+}
+
+pub struct Request {
+    pub method: String,
+    /// Path without the query string.
+    pub path: String,
+    /// Raw query string, without the `?`.
+    pub query: String,
+    keep_alive: bool,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            content_type,
+            body: body.into(),
+        }
+    }
+
+    pub fn json<T: Serialize>(value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => Self::new(200, "application/json", body),
+            Err(e) => Self::new(500, "text/plain", format!("Serialization error: {}", e)),
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(404, "text/plain", "Not Found")
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        501 => "Not Implemented",
+        _ => "Internal Server Error",
+    }
+}
+
+/// A byte stream requests arrive on: a TCP connection or a UNIX socket one.
+trait HttpStream: AsyncRead + AsyncWrite + Unpin {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> HttpStream for S {}
+
+// Serve requests on one connection until the client closes it, asks for
+// `Connection: close`, or goes idle.
+async fn serve_connection<S: HttpStream>(stream: S, state: &State) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+
+    loop {
+        let request = match tokio::time::timeout(IDLE_TIMEOUT, read_request(&mut stream)).await {
+            Ok(Ok(Some(request))) => request,
+            Ok(Ok(None)) | Err(_) => return Ok(()),
+            Ok(Err(e)) => {
+                let response = Response::new(400, "text/plain", e.to_string());
+                write_response(stream.get_mut(), &response, false, false).await?;
+                return Ok(());
+            }
+        };
+
+        debug!("HTTP {} {}", request.method, request.path);
+        if request.method == "GET" && request.path == "/admin/events" {
+            return stream_admin_events(stream.get_mut(), state).await;
+        }
+        if request.method == "GET" && request.path == "/dashboard/events" {
+            return stream_samples(stream.get_mut(), state).await;
+        }
+        if request.method == "GET" && request.path == "/rooms/events" {
+            return stream_room_events(stream.get_mut(), state, &request.query).await;
+        }
+
+        let head_only = request.method == "HEAD";
+        let response = route(state, &request).await;
+        write_response(stream.get_mut(), &response, request.keep_alive, head_only).await?;
+
+        if !request.keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+/// Read one request. `None` means the client closed the connection cleanly between requests.
+async fn read_request<S: HttpStream>(stream: &mut BufReader<S>) -> Result<Option<Request>> {
+    let Some(request_line) = read_line(stream).await? else {
+        return Ok(None);
+    };
+
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        bail!("Malformed request line");
+    };
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut headers = HashMap::new();
+    loop {
+        let Some(line) = read_line(stream).await? else {
+            bail!("Connection closed mid-headers");
+        };
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            bail!("Too many headers");
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            bail!("Malformed header line");
+        };
+        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+
+    let body_len = match headers.get("content-length") {
+        Some(len) => len.parse::<usize>()?,
+        None => 0,
+    };
+    if body_len > MAX_BODY_LEN {
+        bail!("Request body too large");
+    }
+    // No route takes a body, but it must be consumed to find the next request
+    let mut body = vec![0; body_len];
+    stream.read_exact(&mut body).await?;
+
+    // HTTP/1.1 keeps connections alive unless told otherwise; HTTP/1.0 is the reverse
+    let connection = headers
+        .get("connection")
+        .map(|v| v.to_ascii_lowercase())
+        .unwrap_or_default();
+    let keep_alive = match version {
+        "HTTP/1.1" => connection != "close",
+        _ => connection == "keep-alive",
+    };
+
+    Ok(Some(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: query.to_string(),
+        keep_alive,
+    }))
+}
+
+/// Read a CRLF-terminated line without the terminator. `None` on EOF before any bytes.
+async fn read_line<S: HttpStream>(stream: &mut BufReader<S>) -> Result<Option<String>> {
+    let mut line = Vec::new();
+    let read = (&mut *stream)
+        .take(MAX_LINE_LEN as u64 + 2)
+        .read_until(b'\n', &mut line)
+        .await?;
+
+    if read == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with(b"\n") {
+        bail!("Line too long or truncated");
+    }
+
+    let line = String::from_utf8(line)?;
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+async fn write_response<S: HttpStream>(
+    stream: &mut S,
+    response: &Response,
+    keep_alive: bool,
+    head_only: bool,
+) -> Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len(),
+        if keep_alive { "keep-alive" } else { "close" },
+    );
+
+    stream.write_all(head.as_bytes()).await?;
+    if !head_only {
+        stream.write_all(&response.body).await?;
+    }
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn route(state: &State, request: &Request) -> Response {
+    if request.method == "POST" && request.path == "/admin/dump-state" {
+        return match dump::write(state).await {
+            Ok(written) => Response::json(&written),
+            Err(e) => {
+                warn!("State dump failed: {:#}", e);
+                Response::new(500, "text/plain", format!("{:#}", e))
+            }
+        };
+    }
+    if request.method == "POST" && request.path == "/admin/profile" {
+        return set_profile(state, &request.query);
+    }
+    if request.method != "GET" && request.method != "HEAD" {
+        return Response::new(405, "text/plain", "Method Not Allowed");
+    }
+
+    match request.path.as_str() {
+        "/" | "/index.html" => Response::new(
+            200,
+            "text/html; charset=utf-8",
+            include_str!("../client.html"),
+        ),
+        // index.html loads ./pkg/wasm_client.js, which resolves to /pkg/ from here
+        "/dashboard" => Response::new(
+            200,
+            "text/html; charset=utf-8",
+            include_str!("../dashboard.html"),
+        ),
+        "/wasm" => serve_file(Path::new("wasm-client/index.html")).await,
+        "/stats" => Response::json(&stats::Report::collect(state)),
+        "/stats/timeseries" => Response::json(&timeseries_report(state, &request.query)),
+        "/metrics" => Response::new(200, "text/plain; version=0.0.4", prometheus::render(state)),
+        "/cert-hash" => Response::json(&cert_pins(state)),
+        "/admin/moderation" => Response::json(&state.moderation.report()),
+        "/admin/violations" => Response::json(&state.violations.report()),
+        "/admin/rooms" => Response::json(&state.rooms.report()),
+        "/rooms/history" => room_history(state, &request.query),
+        "/admin/burst" => Response::json(&state.burst.last_report()),
+        "/admin/config" => Response::json(&dump::config(state)),
+        "/admin/memory" => Response::json(&state.memory.report()),
+        "/admin/profiles" => Response::json(&state.impairment.report()),
+        "/admin/journal" => {
+            let fsync = state.config.borrow().journal.fsync;
+            Response::json(&state.journal.as_ref().map(|journal| journal.report(fsync)))
+        }
+        "/admin/transfers" => Response::json(&state.transfers.report()),
+        "/admin/flamegraph" => flamegraph(&request.query).await,
+        "/connections" => Response::json(&state.registry.sessions(&tag_filters(&request.query))),
+        path => match path.strip_prefix("/pkg/") {
+            Some(file) => serve_pkg(file).await,
+            None => Response::not_found(),
+        },
+    }
+}
+
+// Server-sent events: hold the connection open and write each admin event as it happens
+async fn stream_admin_events<S: HttpStream>(stream: &mut S, state: &State) -> Result<()> {
+    let mut events = state.admin.subscribe();
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+    stream.write_all(head.as_bytes()).await?;
+    stream.flush().await?;
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!("Admin console fell behind, skipped {} events", missed);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+
+        let data = serde_json::to_string(&event)?;
+        stream
+            .write_all(format!("data: {}\n\n", data).as_bytes())
+            .await?;
+        stream.flush().await?;
+    }
+}
+
+// Server-sent events for /dashboard: each per-second stats sample as it's taken
+async fn stream_samples<S: HttpStream>(stream: &mut S, state: &State) -> Result<()> {
+    let mut samples = state.timeseries.subscribe();
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+    stream.write_all(head.as_bytes()).await?;
+    stream.flush().await?;
+
+    loop {
+        let sample = match samples.recv().await {
+            Ok(sample) => sample,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                debug!("Dashboard fell behind, skipped {} samples", missed);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+
+        let data = serde_json::to_string(&sample)?;
+        stream
+            .write_all(format!("data: {}\n\n", data).as_bytes())
+            .await?;
+        stream.flush().await?;
+    }
+}
+
+// Server-sent events mirroring room traffic read-only, for observers without
+// WebTransport. `?room=lobby` watches one room; without it, every room
+async fn stream_room_events<S: HttpStream>(
+    stream: &mut S,
+    state: &State,
+    query: &str,
+) -> Result<()> {
+    let room = metadata::parse_query(query)
+        .into_iter()
+        .find(|(key, _)| key == "room")
+        .map(|(_, room)| room);
+    if let Some(room) = room
+        .as_deref()
+        .filter(|room| !protocol::room::valid_room(room))
+    {
+        let message = format!(
+            "room {:?} must be 1 to {} bytes",
+            room,
+            protocol::room::MAX_ROOM_LEN
+        );
+        let response = Response::new(400, "text/plain", message);
+        return write_response(stream, &response, false, false).await;
+    }
+
+    let mut events = state.rooms.observe();
+    let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+    stream.write_all(head.as_bytes()).await?;
+    stream.flush().await?;
+    info!(
+        "Room observer connected ({})",
+        room.as_deref().unwrap_or("every room")
+    );
+
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!("Room observer fell behind, skipped {} events", missed);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+        if room.as_deref().is_some_and(|room| room != event.room()) {
+            continue;
+        }
+
+        let data = serde_json::to_string(&event)?;
+        stream
+            .write_all(format!("data: {}\n\n", data).as_bytes())
+            .await?;
+        stream.flush().await?;
+    }
+}
+
+// `?tag=room:blue&tag=role`: sessions must match every tag
+fn tag_filters(query: &str) -> Vec<TagFilter> {
+    metadata::parse_query(query)
+        .into_iter()
+        .filter(|(key, _)| key == "tag")
+        .map(|(_, tag)| TagFilter::parse(&tag))
+        .collect()
+}
+
+// `?room=lobby&limit=50`: the room's latest messages, up to `storage.room_history`
+// of them. Without `room`, the names of the rooms with history
+fn room_history(state: &State, query: &str) -> Response {
+    let params = metadata::parse_query(query);
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let result = match param("room") {
+        Some(room) if !protocol::room::valid_room(room) => {
+            let message = format!(
+                "room {:?} must be 1 to {} bytes",
+                room,
+                protocol::room::MAX_ROOM_LEN
+            );
+            return Response::new(400, "text/plain", message);
+        }
+        Some(room) => {
+            let limit = param("limit")
+                .and_then(|limit| limit.parse().ok())
+                .unwrap_or(usize::MAX);
+            rooms::history(state, room, limit).map(|history| Response::json(&history))
+        }
+        None => rooms::rooms_with_history(state).map(|rooms| Response::json(&rooms)),
+    };
+    result.unwrap_or_else(|e| {
+        warn!("Failed to read room history: {:#}", e);
+        Response::new(500, "text/plain", "Storage error")
+    })
+}
+
+// `?connection=3&preset=satellite`: switch a live connection's network profile,
+// or clear it with `preset=none`
+fn set_profile(state: &State, query: &str) -> Response {
+    let params = metadata::parse_query(query);
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let Some(id) = param("connection").and_then(|id| id.parse().ok()) else {
+        return Response::new(400, "text/plain", "connection must be a connection id");
+    };
+    let preset = match param("preset") {
+        Some("none") => None,
+        Some(name) => match Preset::parse(name) {
+            Some(preset) => Some(preset),
+            None => {
+                let names: Vec<_> = Preset::ALL.iter().map(|preset| preset.name()).collect();
+                let message = format!("preset must be none or one of {}", names.join(", "));
+                return Response::new(400, "text/plain", message);
+            }
+        },
+        None => return Response::new(400, "text/plain", "preset is required"),
+    };
+    if state.registry.get(id).is_none() {
+        return Response::not_found();
+    }
+    state.impairment.set_preset(id, preset);
+    info!(
+        "Connection {} switched to the {} preset",
+        id,
+        preset.map_or("none", Preset::name)
+    );
+    Response::json(&state.impairment.report())
+}
+
+// `?seconds=30&frequency=199`: profile the server's CPU for that long and
+// answer with a flamegraph
+async fn flamegraph(query: &str) -> Response {
+    if cfg!(not(feature = "profiling")) {
+        let message = "profiling needs a build with the profiling feature";
+        return Response::new(501, "text/plain", message);
+    }
+    let params = metadata::parse_query(query);
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.parse::<u64>().ok())
+    };
+    let max_seconds = profiling::MAX_DURATION.as_secs();
+    let duration = match param("seconds") {
+        None => profiling::DEFAULT_DURATION,
+        Some(Some(seconds)) if (1..=max_seconds).contains(&seconds) => Duration::from_secs(seconds),
+        Some(_) => {
+            let message = format!("seconds must be from 1 to {}", max_seconds);
+            return Response::new(400, "text/plain", message);
+        }
+    };
+    let max_frequency = profiling::MAX_FREQUENCY as u64;
+    let frequency = match param("frequency") {
+        None => profiling::DEFAULT_FREQUENCY,
+        Some(Some(frequency)) if (1..=max_frequency).contains(&frequency) => frequency as i32,
+        Some(_) => {
+            let message = format!("frequency must be from 1 to {}", max_frequency);
+            return Response::new(400, "text/plain", message);
+        }
+    };
+
+    if profiling::running() {
+        return Response::new(409, "text/plain", "a profile is already running");
+    }
+    info!("Profiling for {} s at {} Hz", duration.as_secs(), frequency);
+    match profiling::flamegraph(duration, frequency).await {
+        Ok(svg) => Response::new(200, "image/svg+xml", svg),
+        Err(e) => {
+            warn!("Profile failed: {:#}", e);
+            Response::new(500, "text/plain", format!("{:#}", e))
+        }
+    }
+}
+
+// `?seconds=60`: only the newest samples
+fn timeseries_report(state: &State, query: &str) -> timeseries::Report {
+    let seconds = metadata::parse_query(query)
+        .into_iter()
+        .find(|(key, _)| key == "seconds")
+        .and_then(|(_, seconds)| seconds.parse().ok());
+    state
+        .timeseries
+        .report(timeseries::window_secs(state), seconds)
+}
+
+// The presented certificate first, then any extra pins from the config
+fn cert_pins(state: &State) -> CertPins {
+    let pin = &state.cert.pin;
+    let mut hashes = vec![pin.clone()];
+    hashes.extend(
+        state
+            .config
+            .borrow()
+            .cert_pins
+            .iter()
+            .filter(|extra| extra.value != pin.value)
+            .cloned(),
+    );
+
+    CertPins {
+        algorithm: pin.algorithm.clone(),
+        value: pin.value.clone(),
+        hashes,
+        spki_sha256: state.cert.spki_sha256.clone(),
+    }
+}
+
+// Files produced by wasm-pack, so the WASM client can be loaded from this server too
+async fn serve_pkg(file: &str) -> Response {
+    // Flat directory: refuse anything that could escape it
+    if file.is_empty() || file.contains(['/', '\\']) || file.starts_with('.') {
+        return Response::not_found();
+    }
+
+    serve_file(&Path::new(WASM_PKG_DIR).join(file)).await
+}
+
+async fn serve_file(path: &Path) -> Response {
+    let content_type = match path.extension().and_then(|e| e.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "application/javascript",
+        Some("wasm") => "application/wasm",
+        Some("json") => "application/json",
+        _ => "application/octet-stream",
+    };
+
+    match tokio::fs::read(path).await {
+        Ok(body) => Response::new(200, content_type, body),
+        Err(_) => Response::not_found(),
+    }
+}
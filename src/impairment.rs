@@ -0,0 +1,154 @@
+use crate::registry::ConnectionId;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// A named network profile a connection can be switched to, with `?profile=`
+/// on its URL or `POST /admin/profile`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    Wifi,
+    #[serde(rename = "3g")]
+    ThreeG,
+    Satellite,
+    LossyLan,
+}
+
+/// Delay and loss a preset adds to what the server sends a connection.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Profile {
+    /// Added to every echo and datagram, in milliseconds.
+    pub latency_ms: u64,
+    /// Each delay is `latency_ms` give or take up to this much, in milliseconds.
+    pub jitter_ms: u64,
+    /// Probability (0.0..=1.0) that an outgoing datagram is dropped, on top of
+    /// `impairment.loss`.
+    pub loss: f64,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 4] = [
+        Preset::Wifi,
+        Preset::ThreeG,
+        Preset::Satellite,
+        Preset::LossyLan,
+    ];
+
+    pub fn profile(self) -> Profile {
+        let (latency_ms, jitter_ms, loss) = match self {
+            Preset::Wifi => (15, 10, 0.01),
+            Preset::ThreeG => (150, 50, 0.02),
+            Preset::Satellite => (600, 50, 0.005),
+            Preset::LossyLan => (1, 1, 0.1),
+        };
+        Profile {
+            latency_ms,
+            jitter_ms,
+            loss,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Wifi => "wifi",
+            Preset::ThreeG => "3g",
+            Preset::Satellite => "satellite",
+            Preset::LossyLan => "lossy-lan",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|preset| preset.name() == name)
+    }
+}
+
+/// The presets and which connections use them, as listed by `/admin/profiles`.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub presets: BTreeMap<&'static str, Profile>,
+    pub connections: BTreeMap<ConnectionId, Preset>,
+}
+
+// Artificial network impairment applied to outgoing datagrams, and to echoes
+// of connections given a preset.
+// The loss rate is stored as f64 bits so it can be changed at runtime without a lock.
+pub struct Impairment {
+    loss: AtomicU64,
+    presets: Mutex<HashMap<ConnectionId, Preset>>,
+}
+
+impl Impairment {
+    pub fn new() -> Self {
+        Self {
+            loss: AtomicU64::new(0f64.to_bits()),
+            presets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Probability (0.0..=1.0) that an outgoing datagram is silently dropped.
+    pub fn loss(&self) -> f64 {
+        f64::from_bits(self.loss.load(Ordering::Relaxed))
+    }
+
+    pub fn set_loss(&self, rate: f64) {
+        self.loss
+            .store(rate.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Switch connection `id` to `preset`, or back to no profile with `None`.
+    pub fn set_preset(&self, id: ConnectionId, preset: Option<Preset>) {
+        let mut presets = self.presets.lock().unwrap();
+        match preset {
+            Some(preset) => presets.insert(id, preset),
+            None => presets.remove(&id),
+        };
+    }
+
+    pub fn preset(&self, id: ConnectionId) -> Option<Preset> {
+        self.presets.lock().unwrap().get(&id).copied()
+    }
+
+    /// Forget a closed connection's preset.
+    pub fn remove(&self, id: ConnectionId) {
+        self.set_preset(id, None);
+    }
+
+    /// Roll the dice for one datagram to connection `id`; true means drop it.
+    pub fn should_drop(&self, id: ConnectionId) -> bool {
+        let loss = self.loss();
+        let preset_loss = self.preset(id).map_or(0.0, |preset| preset.profile().loss);
+        // Either loss alone drops it
+        let loss = 1.0 - (1.0 - loss) * (1.0 - preset_loss);
+        loss > 0.0 && rand::random::<f64>() < loss
+    }
+
+    /// How long to hold back the next echo or datagram to connection `id`:
+    /// its preset's latency plus or minus a random jitter, or zero without one.
+    pub fn delay(&self, id: ConnectionId) -> Duration {
+        let Some(profile) = self.preset(id).map(Preset::profile) else {
+            return Duration::ZERO;
+        };
+        let jitter = profile.jitter_ms as i64;
+        let ms = profile.latency_ms as i64 + rand::random_range(-jitter..=jitter);
+        Duration::from_millis(ms.max(0) as u64)
+    }
+
+    pub fn report(&self) -> Report {
+        Report {
+            presets: Preset::ALL
+                .into_iter()
+                .map(|preset| (preset.name(), preset.profile()))
+                .collect(),
+            connections: self
+                .presets
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(id, preset)| (*id, *preset))
+                .collect(),
+        }
+    }
+}
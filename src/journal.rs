@@ -0,0 +1,468 @@
+use crate::registry::ConnectionId;
+use crate::state::State;
+use crate::summary::Tally;
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+
+/// How often `journal.checkpoint_secs` and `journal.fsync_interval_ms` are
+/// looked at again while their task has nothing to do.
+const IDLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the journal forces what it wrote onto disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fsync {
+    /// After every record, before the next is written; nothing that reached
+    /// the journal's writer is lost to a crash, at the cost of a disk flush
+    /// per record.
+    Always,
+    /// Every `journal.fsync_interval_ms`, if anything was written since.
+    #[default]
+    Interval,
+    /// Whenever the OS gets to it. Survives the server crashing, but not the
+    /// machine.
+    Never,
+}
+
+/// One line of the journal.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    /// The server started; what follows, up to the next `start`, is this run.
+    Start {
+        pid: u32,
+    },
+    Connect {
+        id: ConnectionId,
+    },
+    Join {
+        id: ConnectionId,
+        room: String,
+    },
+    /// Also written for a member kicked out of the room.
+    Leave {
+        id: ConnectionId,
+        room: String,
+    },
+    /// Messages and datagrams a connection has sent so far.
+    Checkpoint {
+        id: ConnectionId,
+        messages: u64,
+        datagrams: u64,
+    },
+    /// A connection closed, with what it sent in all; it left its rooms too.
+    Disconnect {
+        id: ConnectionId,
+        messages: u64,
+        datagrams: u64,
+    },
+    /// The server shut down cleanly, so there's nothing to recover.
+    Stop,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Entry {
+    at_unix_ms: u64,
+    #[serde(flatten)]
+    record: Record,
+}
+
+/// What a run that ended without a `stop` left behind: the sessions it had
+/// open and the rooms they were in, as of the last record that reached disk.
+#[derive(Debug, Clone, Serialize)]
+pub struct Recovery {
+    pub started_at_unix_ms: u64,
+    pub last_record_unix_ms: u64,
+    /// Lines that couldn't be read, e.g. one torn by the crash.
+    pub unreadable: usize,
+    pub sessions: Vec<RecoveredSession>,
+    /// Members of each room, by room name.
+    pub rooms: BTreeMap<String, Vec<ConnectionId>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecoveredSession {
+    /// The connection's id in that run; ids start over in this one.
+    pub id: ConnectionId,
+    pub connected_at_unix_ms: u64,
+    /// As of its last checkpoint.
+    pub messages: u64,
+    pub datagrams: u64,
+    pub rooms: Vec<String>,
+}
+
+impl Recovery {
+    // Replay the last run in `text`. None if it stopped cleanly, or there's no run
+    fn replay(text: &str) -> Option<Self> {
+        let mut recovery: Option<Recovery> = None;
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let Ok(entry) = serde_json::from_str::<Entry>(line) else {
+                if let Some(run) = recovery.as_mut() {
+                    run.unreadable += 1;
+                }
+                continue;
+            };
+            if let Record::Start { .. } = entry.record {
+                recovery = Some(Recovery {
+                    started_at_unix_ms: entry.at_unix_ms,
+                    last_record_unix_ms: entry.at_unix_ms,
+                    unreadable: 0,
+                    sessions: Vec::new(),
+                    rooms: BTreeMap::new(),
+                });
+                continue;
+            }
+            let Some(run) = recovery.as_mut() else {
+                continue;
+            };
+            run.last_record_unix_ms = entry.at_unix_ms;
+            match entry.record {
+                Record::Start { .. } => {}
+                Record::Connect { id } => run.sessions.push(RecoveredSession {
+                    id,
+                    connected_at_unix_ms: entry.at_unix_ms,
+                    messages: 0,
+                    datagrams: 0,
+                    rooms: Vec::new(),
+                }),
+                Record::Join { id, room } => {
+                    if let Some(session) = run.session(id)
+                        && !session.rooms.contains(&room)
+                    {
+                        session.rooms.push(room);
+                    }
+                }
+                Record::Leave { id, room } => {
+                    if let Some(session) = run.session(id) {
+                        session.rooms.retain(|r| *r != room);
+                    }
+                }
+                Record::Checkpoint {
+                    id,
+                    messages,
+                    datagrams,
+                } => {
+                    if let Some(session) = run.session(id) {
+                        session.messages = messages;
+                        session.datagrams = datagrams;
+                    }
+                }
+                Record::Disconnect { id, .. } => run.sessions.retain(|s| s.id != id),
+                Record::Stop => recovery = None,
+            }
+        }
+
+        let mut recovery = recovery?;
+        let mut rooms: BTreeMap<String, BTreeSet<ConnectionId>> = BTreeMap::new();
+        for session in &recovery.sessions {
+            for room in &session.rooms {
+                rooms.entry(room.clone()).or_default().insert(session.id);
+            }
+        }
+        recovery.rooms = rooms
+            .into_iter()
+            .map(|(room, members)| (room, members.into_iter().collect()))
+            .collect();
+        Some(recovery)
+    }
+
+    fn session(&mut self, id: ConnectionId) -> Option<&mut RecoveredSession> {
+        self.sessions.iter_mut().find(|s| s.id == id)
+    }
+
+    fn log(&self) {
+        warn!(
+            "The previous run, started at {} ms, ended without a clean stop after {} ms: {} sessions and {} rooms were open",
+            self.started_at_unix_ms,
+            self.last_record_unix_ms
+                .saturating_sub(self.started_at_unix_ms),
+            self.sessions.len(),
+            self.rooms.len()
+        );
+        for (room, members) in &self.rooms {
+            info!("Room '{}' had members {:?}", room, members);
+        }
+        if self.unreadable > 0 {
+            warn!("Skipped {} unreadable journal lines", self.unreadable);
+        }
+    }
+}
+
+/// Key session events of every run, appended as JSON Lines to
+/// `journal.path`, so what the server was doing survives a crash. Records are
+/// written and synced by a thread of the journal's own, in the order they
+/// were made, so no async task waits on the disk.
+pub struct Journal {
+    path: PathBuf,
+    writes: mpsc::UnboundedSender<Work>,
+    counters: Arc<Counters>,
+    /// What the previous run left behind, if it didn't stop cleanly.
+    recovered: Option<Recovery>,
+}
+
+#[derive(Default)]
+struct Counters {
+    /// Records written since the last fsync.
+    unsynced: AtomicU64,
+    written: AtomicU64,
+    syncs: AtomicU64,
+}
+
+// Work for the writer thread
+enum Work {
+    /// A record's line, synced under `fsync`; `done` hears once it's on disk
+    /// as far as `fsync` goes.
+    Line {
+        line: String,
+        fsync: Fsync,
+        done: Option<oneshot::Sender<()>>,
+    },
+    /// Force the records written since the last fsync onto disk.
+    Sync,
+}
+
+// The journal file, owned by the writer thread
+struct Writer {
+    path: PathBuf,
+    file: File,
+    counters: Arc<Counters>,
+}
+
+impl Writer {
+    fn run(mut self, mut writes: mpsc::UnboundedReceiver<Work>) {
+        while let Some(work) = writes.blocking_recv() {
+            let result = match work {
+                Work::Line { line, fsync, done } => {
+                    let result = self.write(&line, fsync);
+                    if let Some(done) = done {
+                        let _ = done.send(());
+                    }
+                    result
+                }
+                Work::Sync => self.sync(),
+            };
+            if let Err(e) = result {
+                warn!("Failed to journal to {}: {:#}", self.path.display(), e);
+            }
+        }
+    }
+
+    fn write(&mut self, line: &str, fsync: Fsync) -> Result<()> {
+        self.file.write_all(line.as_bytes())?;
+        self.counters.written.fetch_add(1, Ordering::Relaxed);
+        if fsync == Fsync::Always {
+            self.file.sync_data()?;
+            self.counters.unsynced.store(0, Ordering::Relaxed);
+            self.counters.syncs.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.counters.unsynced.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        if self.counters.unsynced.swap(0, Ordering::Relaxed) > 0 {
+            self.file.sync_data()?;
+            self.counters.syncs.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+/// Body of the `/admin/journal` endpoint.
+#[derive(Debug, Serialize)]
+pub struct Report<'a> {
+    pub path: &'a Path,
+    pub fsync: Fsync,
+    /// Records written by this run, and fsyncs made.
+    pub written: u64,
+    pub syncs: u64,
+    pub unsynced: u64,
+    pub recovered: Option<&'a Recovery>,
+}
+
+impl Journal {
+    /// Recover the last run from the journal at `path`, if it ended without
+    /// a `stop`, then start this one at the end of it.
+    pub fn open(path: &Path) -> Result<Self> {
+        let recovered = match fs::read(path) {
+            Ok(bytes) => Recovery::replay(&String::from_utf8_lossy(&bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        if let Some(recovery) = &recovered {
+            recovery.log();
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        // A line torn by a crash stays, but the next record starts on its own
+        if file.metadata()?.len() > 0 {
+            let mut last = [0];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                file.write_all(b"\n")?;
+            }
+        }
+
+        let counters = Arc::new(Counters::default());
+        let mut writer = Writer {
+            path: path.to_path_buf(),
+            file,
+            counters: counters.clone(),
+        };
+        let start = line(&Record::Start {
+            pid: std::process::id(),
+        })?;
+        writer.write(&start, Fsync::Always)?;
+        let (writes, queued) = mpsc::unbounded_channel();
+        thread::Builder::new()
+            .name("journal-writer".into())
+            .spawn(move || writer.run(queued))
+            .context("Failed to start the journal writer")?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            writes,
+            counters,
+            recovered,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Queue `record` for the writer, stamped now
+    fn append(
+        &self,
+        record: &Record,
+        fsync: Fsync,
+        done: Option<oneshot::Sender<()>>,
+    ) -> Result<()> {
+        let line = line(record)?;
+        self.writes
+            .send(Work::Line { line, fsync, done })
+            .map_err(|_| anyhow!("the journal writer stopped"))
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.writes
+            .send(Work::Sync)
+            .map_err(|_| anyhow!("the journal writer stopped"))
+    }
+
+    pub fn report(&self, fsync: Fsync) -> Report<'_> {
+        Report {
+            path: &self.path,
+            fsync,
+            written: self.counters.written.load(Ordering::Relaxed),
+            syncs: self.counters.syncs.load(Ordering::Relaxed),
+            unsynced: self.counters.unsynced.load(Ordering::Relaxed),
+            recovered: self.recovered.as_ref(),
+        }
+    }
+}
+
+// A record's journal line, stamped now
+fn line(record: &Record) -> Result<String> {
+    let entry = Entry {
+        at_unix_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        record: record.clone(),
+    };
+    Ok(serde_json::to_string(&entry)? + "\n")
+}
+
+/// Append `record` to the journal, if there is one, under the `journal.fsync`
+/// policy. A failed write is logged and the server goes on without it.
+pub fn record(state: &State, record: Record) {
+    let Some(journal) = &state.journal else {
+        return;
+    };
+    let fsync = state.config.borrow().journal.fsync;
+    if let Err(e) = journal.append(&record, fsync, None) {
+        warn!("Failed to journal to {}: {:#}", journal.path().display(), e);
+    }
+}
+
+/// Record a clean stop, on disk whatever `journal.fsync` says, so the next
+/// run has nothing to recover. Returns once the writer has synced it.
+pub async fn stop(state: &State) {
+    let Some(journal) = &state.journal else {
+        return;
+    };
+    let (done, synced) = oneshot::channel();
+    if let Err(e) = journal.append(&Record::Stop, Fsync::Always, Some(done)) {
+        warn!("Failed to journal to {}: {:#}", journal.path().display(), e);
+        return;
+    }
+    let _ = synced.await;
+}
+
+// fsync the journal every `journal.fsync_interval_ms` under the `interval`
+// policy. Runs for the server's lifetime
+pub async fn sync(state: Arc<State>) {
+    let Some(journal) = &state.journal else {
+        return;
+    };
+    loop {
+        let config = state.config.borrow().journal.clone();
+        if config.fsync != Fsync::Interval {
+            tokio::time::sleep(IDLE_INTERVAL).await;
+            continue;
+        }
+        tokio::time::sleep(Duration::from_millis(config.fsync_interval_ms)).await;
+        if let Err(e) = journal.sync() {
+            warn!("Failed to fsync {}: {:#}", journal.path().display(), e);
+        }
+    }
+}
+
+// Checkpoint connection `id`'s counts every `journal.checkpoint_secs` while
+// they change. Runs until aborted with the connection
+pub async fn checkpoints(state: Arc<State>, id: ConnectionId, tally: Arc<Tally>) {
+    if state.journal.is_none() {
+        return;
+    }
+    let mut last = (0, 0);
+    loop {
+        let secs = state.config.borrow().journal.checkpoint_secs;
+        if secs == 0 {
+            tokio::time::sleep(IDLE_INTERVAL).await;
+            continue;
+        }
+        tokio::time::sleep(Duration::from_secs(secs)).await;
+        let counts = (tally.messages(), tally.datagrams());
+        if counts != last {
+            last = counts;
+            record(
+                &state,
+                Record::Checkpoint {
+                    id,
+                    messages: counts.0,
+                    datagrams: counts.1,
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+// Token bucket shared by all of one connection's streams and datagrams.
+// The rate is passed on each check so config reloads apply immediately; the
+// bucket holds at most one second's worth of tokens.
+pub struct RateLimiter {
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self {
+            bucket: Mutex::new(Bucket {
+                // Starts full; clamped to the current rate on first use
+                tokens: f64::INFINITY,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token for one message at `per_second`; false means over the limit.
+    /// A rate of 0 means unlimited.
+    pub fn allow(&self, per_second: u32) -> bool {
+        if per_second == 0 {
+            return true;
+        }
+
+        let mut bucket = self.bucket.lock().unwrap();
+        let now = Instant::now();
+        let capacity = per_second as f64;
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * capacity).min(capacity);
+        bucket.refilled_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Count of one connection's open streams in one direction, checked against
+// `limits.max_bi_streams` or `limits.max_uni_streams` as each stream arrives
+pub struct StreamSlots {
+    open: Arc<AtomicU32>,
+}
+
+/// Held for as long as its stream is open; frees the slot when dropped.
+pub struct StreamSlot {
+    open: Arc<AtomicU32>,
+}
+
+impl StreamSlots {
+    pub fn new() -> Self {
+        Self {
+            open: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    /// Take a slot for a new stream, or `None` if `max` are already open. A
+    /// max of 0 means unlimited.
+    pub fn acquire(&self, max: u32) -> Option<StreamSlot> {
+        self.open
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |open| {
+                (max == 0 || open < max).then_some(open + 1)
+            })
+            .ok()?;
+        Some(StreamSlot {
+            open: self.open.clone(),
+        })
+    }
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        self.open.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::registry::ConnectionId;
+use crate::state::State;
+use protocol::{CloseCode, Frame, Framing};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::time::{sleep, timeout};
+use tracing::{debug, warn};
+use wtransport::{Connection, VarInt};
+
+/// How often each connection's silence is checked against `liveness.silent_secs`.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When a connection last sent anything: a stream, a read on one, or a datagram.
+pub struct Activity {
+    since: Instant,
+    /// Milliseconds after `since`.
+    last_ms: AtomicU64,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self {
+            since: Instant::now(),
+            last_ms: AtomicU64::new(0),
+        }
+    }
+
+    pub fn touch(&self) {
+        let ms = self.since.elapsed().as_millis() as u64;
+        self.last_ms.fetch_max(ms, Ordering::Relaxed);
+    }
+
+    fn last(&self) -> u64 {
+        self.last_ms.load(Ordering::Relaxed)
+    }
+
+    fn quiet(&self) -> Duration {
+        self.since.elapsed() - Duration::from_millis(self.last())
+    }
+}
+
+// Ping the connection whenever it has sent nothing for `liveness.silent_secs`.
+// An idle client answers with a pong (or anything else) within
+// `liveness.deadline_ms`; one that doesn't is half-open or hung, and is closed
+// as unresponsive. Runs until then or until aborted with the connection.
+pub async fn probe(
+    state: Arc<State>,
+    id: ConnectionId,
+    connection: Connection,
+    framing: Framing,
+    activity: Arc<Activity>,
+) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut nonce = 0;
+    loop {
+        interval.tick().await;
+        let config = state.config.borrow().liveness.clone();
+        let quiet = activity.quiet();
+        if config.silent_secs == 0 || quiet < Duration::from_secs(config.silent_secs) {
+            continue;
+        }
+
+        nonce += 1;
+        let deadline = Duration::from_millis(config.deadline_ms);
+        let seen = activity.last();
+        let started_at = Instant::now();
+        debug!(
+            "Probing connection {}, quiet for {:?}, with ping {}",
+            id, quiet, nonce
+        );
+        let ping = Frame::Ping {
+            nonce,
+            deadline_ms: config.deadline_ms,
+        };
+        let sent = timeout(deadline, async {
+            let mut stream = connection.open_uni().await?.await?;
+            stream.write_all(&framing.encode(&ping)).await?;
+            stream.finish().await?;
+            anyhow::Ok(())
+        })
+        .await;
+        if let Ok(Err(e)) = sent {
+            debug!("Failed to ping connection {}: {}", id, e);
+        }
+        sleep(deadline.saturating_sub(started_at.elapsed())).await;
+
+        // Closed some other way meanwhile
+        if connection.quic_connection().close_reason().is_some() {
+            return;
+        }
+        if activity.last() != seen {
+            state.stats.liveness.record(true);
+            continue;
+        }
+        state.stats.liveness.record(false);
+        warn!(
+            "Closing connection {}: no answer to ping {} within {:?} after {:?} of silence",
+            id, nonce, deadline, quiet
+        );
+        let reason = format!("no answer to ping within {} ms", config.deadline_ms);
+        connection.close(
+            VarInt::from_u32(CloseCode::Unresponsive.code()),
+            reason.as_bytes(),
+        );
+        return;
+    }
+}
@@ -1,94 +1,816 @@
-use anyhow::Result;
-use tracing::{info, warn};
-use wtransport::{Endpoint, Identity, ServerConfig};
+mod admin;
+mod backoff;
+mod bandwidth;
+mod burst;
+mod certs;
+mod chaos;
+mod config;
+mod dedup;
+mod drip;
+mod dump;
+mod fanout;
+mod geoip;
+mod hello;
+mod http;
+mod impairment;
+mod journal;
+mod limits;
+mod liveness;
+mod memory;
+mod metadata;
+mod middleware;
+mod moderation;
+mod otel;
+mod pipelines;
+mod pool;
+mod profiling;
+mod prometheus;
+mod qlog;
+mod quota;
+mod rebind;
+mod registry;
+mod resume;
+mod rooms;
+mod rpc;
+mod scenario;
+mod schedule;
+mod script;
+mod selftest;
+mod shards;
+mod state;
+mod stats;
+mod storage;
+mod summary;
+mod timeseries;
+mod tls;
+mod transfers;
+mod violations;
+mod watchdog;
+
+use anyhow::{Result, anyhow};
+use backoff::ErrorKind;
+use certs::Fingerprint;
+use chaos::Action;
+use clap::Parser;
+use config::Config;
+use geoip::GeoIp;
+use impairment::Preset;
+use journal::{Journal, Record};
+use limits::{RateLimiter, StreamSlot, StreamSlots};
+use liveness::Activity;
+use middleware::Chain;
+use moderation::Moderation;
+use protocol::hello::STREAM_REFUSED_CODE;
+use protocol::raw::RAW_PREAMBLE;
+use protocol::telemetry::{
+    PROBE_PREFIX, ServerTiming, decode_mtu_probe, decode_ping, encode_mtu_probe,
+};
+use protocol::{
+    CloseCode, DecodeError, Frame, FrameDecoder, Framing, MAX_MESSAGE_ID_LEN, PlaygroundError,
+};
+use protocol::{batch, expiry, reliable};
+use registry::ConnectionId;
+use rooms::Control;
+use scenario::Scenario;
+use script::{Route, Scripting};
+use state::State;
+use std::borrow::Cow;
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use summary::{SessionLog, Summary, Tally};
+use tokio::task::JoinSet;
+use tracing::level_filters::LevelFilter;
+use tracing::{Instrument, debug, info, info_span, warn};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::reload;
+use tracing_subscriber::util::SubscriberInitExt;
+use violations::{Kind, Strikes};
+use watchdog::Heartbeat;
+use wtransport::config::TlsServerConfig;
+use wtransport::endpoint::endpoint_side::Server;
+use wtransport::tls::rustls::KeyLogFile;
+use wtransport::tls::server::build_default_tls_config;
+use wtransport::{Connection, Endpoint, Identity, RecvStream, SendStream, ServerConfig, VarInt};
+
+#[derive(Parser)]
+#[command(about = "WebTransport playground server")]
+struct Args {
+    /// TOML config file; watched and re-applied live when it changes
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Validate the config file, print every problem found and exit, without
+    /// binding any sockets
+    #[arg(long, requires = "config")]
+    check_config: bool,
+
+    /// YAML scenario to run against live connections once the server is up
+    #[arg(long)]
+    scenario: Option<PathBuf>,
+
+    /// YAML file of regex moderation rules applied to messages before they are relayed
+    #[arg(long)]
+    moderation: Option<PathBuf>,
+
+    /// Rhai script whose `on_message` handles stream messages; reloaded when it
+    /// changes (needs the `scripting` feature)
+    #[arg(long)]
+    script: Option<PathBuf>,
+
+    /// MaxMind database (.mmdb) to look up where clients connect from, listed
+    /// in /connections (needs the `geoip` feature)
+    #[arg(long)]
+    geoip_db: Option<PathBuf>,
+
+    /// Log TLS session secrets to the file named by SSLKEYLOGFILE, so captures can
+    /// be decrypted in Wireshark. Anyone with the file can read the traffic.
+    #[arg(long)]
+    keylog: bool,
+
+    /// Directory to write a qlog-format trace of each session's sampled path stats to,
+    /// as run-<start unix ms>/connection-<id>.sqlog
+    #[arg(long)]
+    qlog_dir: Option<PathBuf>,
+
+    /// File to append a summary of each closed session to: CSV if it ends in .csv,
+    /// JSON Lines otherwise
+    #[arg(long)]
+    session_log: Option<PathBuf>,
+
+    /// Directory `POST /admin/dump-state` writes state snapshots to
+    #[arg(long, default_value = "dumps")]
+    dump_dir: PathBuf,
+
+    /// Endpoints sharing the WebTransport port (SO_REUSEPORT), each accepting and
+    /// running its sessions on its own thread
+    #[arg(long, default_value_t = 1)]
+    shards: usize,
+
+    /// OTLP/HTTP collector to export spans and metrics to, e.g. http://localhost:4318
+    /// (needs the `otel` feature)
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+
+    /// Run N in-process client sessions against this server through every check,
+    /// print a pass/fail report and exit, with status 1 if any check failed
+    #[arg(long, value_name = "N")]
+    selftest: Option<usize>,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
+    let args = Args::parse();
+
+    // clap makes --check-config require --config
+    if args.check_config
+        && let Some(path) = &args.config
+    {
+        match Config::load(path).await {
+            Ok(_) => println!("{} is valid", path.display()),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    let config = match &args.config {
+        Some(path) => Config::load(path).await?,
+        None => Config::default(),
+    };
+
+    // The level filter sits behind a reload layer so config reloads can change it
+    let (level, log_handle) = reload::Layer::new(config.log_filter().unwrap_or(LevelFilter::INFO));
+    let subscriber = tracing_subscriber::registry()
+        .with(level)
+        .with(tracing_subscriber::fmt::layer());
+    // Shut down once the server stops, flushing what they hold
+    #[cfg(feature = "otel")]
+    let (subscriber, exporters) = match &args.otlp_endpoint {
+        Some(endpoint) => {
+            let (layer, exporters) = otel::init(endpoint)?;
+            (subscriber.with(Some(layer)), Some(exporters))
+        }
+        None => (subscriber.with(None), None),
+    };
+    #[cfg(not(feature = "otel"))]
+    if args.otlp_endpoint.is_some() {
+        return Err(anyhow!(
+            "--otlp-endpoint needs a build with the otel feature"
+        ));
+    }
+    subscriber.init();
+    if let Some(endpoint) = &args.otlp_endpoint {
+        info!("Exporting spans and metrics to {}", endpoint);
+    }
+
+    if args.selftest == Some(0) {
+        return Err(anyhow!("--selftest needs at least one session"));
+    }
+
+    // Load the scenario up front so a broken script fails fast
+    let scenario = match &args.scenario {
+        Some(path) => Some(Scenario::load(path).await?),
+        None => None,
+    };
+
+    let moderation = match &args.moderation {
+        Some(path) => Moderation::load(path).await?,
+        None => Moderation::new(),
+    };
+    let script = match args.script.clone() {
+        Some(path) => Scripting::load(path).await?,
+        None => Scripting::new(),
+    };
+    let geoip = match &args.geoip_db {
+        Some(path) => GeoIp::open(path)?,
+        None => GeoIp::new(),
+    };
+    let storage = storage::open(&config.storage)?;
 
     info!("Starting WebTransport server...");
 
+    let identity = Identity::load_pemfiles("cert.pem", "key.pem")
+        .await
+        .expect("Failed to load certificates. Run: openssl req -x509 -newkey rsa:4096 -keyout key.pem -out cert.pem -sha256 -days 365 -nodes -subj '/CN=localhost'");
+
+    let cert = Fingerprint::of(&identity);
+    cert.log();
+
     // Create server configuration
-    let config = ServerConfig::builder()
-        .with_bind_default(8765)
-        .with_identity(
-            Identity::load_pemfiles("cert.pem", "key.pem")
-                .await
-                .expect("Failed to load certificates. Run: openssl req -x509 -newkey rsa:4096 -keyout key.pem -out cert.pem -sha256 -days 365 -nodes -subj '/CN=localhost'")
+    let mut tls_config = build_default_tls_config(identity);
+    if args.keylog {
+        enable_keylog(&mut tls_config);
+    }
+    let port = config.port;
+    let shards = if args.shards > 1 && !shards::SUPPORTED {
+        warn!(
+            "This platform can't share a port between endpoints; running 1 instead of {}",
+            args.shards
+        );
+        1
+    } else {
+        args.shards.max(1)
+    };
+    // Bind every shard's socket up front, so a port in use fails fast
+    let sockets = (0..shards)
+        .map(|_| shards::socket(port, shards > 1))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    if shards > 1 {
+        info!(
+            "WebTransport server listening on https://localhost:{} ({} endpoints on their own threads)",
+            port, shards
+        );
+    } else {
+        info!(
+            "WebTransport server listening on https://localhost:{}",
+            port
+        );
+    }
+
+    let session_log = args.session_log.clone().map(SessionLog::new);
+    if let Some(log) = &session_log {
+        info!("Appending session summaries to {}", log.path().display());
+    }
+
+    let journal = match &config.journal.path {
+        Some(path) => {
+            let journal = Journal::open(path)?;
+            info!("Journaling sessions to {}", path.display());
+            Some(journal)
+        }
+        None => None,
+    };
+
+    let state = Arc::new(State {
+        script,
+        geoip,
+        journal,
+        ..State::new(
+            config,
+            moderation,
+            storage,
+            session_log,
+            args.dump_dir.clone(),
+            cert,
+            shards,
         )
-        .build();
+    });
+
+    if let Some(path) = args.config {
+        tokio::spawn(config::watch(path, state.clone(), log_handle));
+    }
 
-    let server = Endpoint::server(config)?;
-    info!("WebTransport server listening on https://localhost:8765");
+    // Also start a simple HTTP server for serving the client HTML and admin API,
+    // on TCP and/or a UNIX socket
+    let admin = state.config.borrow().admin.clone();
+    if admin.http {
+        tokio::spawn({
+            let state = state.clone();
+            async move {
+                if let Err(e) = http::start_http_server(state).await {
+                    warn!("HTTP server error: {}", e);
+                }
+            }
+        });
+    }
+    if let Some(path) = admin.socket {
+        tokio::spawn({
+            let state = state.clone();
+            async move {
+                if let Err(e) = http::start_unix_server(state, path).await {
+                    warn!("UNIX socket server error: {:#}", e);
+                }
+            }
+        });
+    }
 
-    // Also start a simple HTTP server for serving the client HTML
-    tokio::spawn(async {
-        if let Err(e) = start_http_server().await {
-            warn!("HTTP server error: {}", e);
+    tokio::spawn(script::watch(state.clone()));
+    tokio::spawn(pipelines::run(state.clone()));
+    fanout::start(&state);
+    tokio::spawn(schedule::run(state.clone()));
+    tokio::spawn(timeseries::run(state.clone()));
+    if state.journal.is_some() {
+        tokio::spawn(journal::sync(state.clone()));
+    }
+    #[cfg(all(unix, feature = "profiling"))]
+    tokio::spawn({
+        let dir = args.dump_dir.clone();
+        async move {
+            if let Err(e) = profiling::on_signal(dir).await {
+                warn!("Can't profile on SIGUSR1: {:#}", e);
+            }
         }
     });
 
-    // Accept connections
+    if let Some(scenario) = scenario {
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = scenario.run(state).await {
+                warn!("Scenario error: {:#}", e);
+            }
+        });
+    }
+
+    let selftest = async {
+        match args.selftest {
+            Some(sessions) => selftest::run(state.clone(), sessions).await,
+            None => std::future::pending().await,
+        }
+    };
+
+    // Each run traces into a directory of its own, as connection ids start
+    // over on restart
+    let qlog_dir = match &args.qlog_dir {
+        Some(dir) => {
+            let started = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis();
+            let run = dir.join(format!("run-{}", started));
+            tokio::fs::create_dir_all(&run).await?;
+            info!("Writing qlog traces to {}", run.display());
+            Some(run)
+        }
+        None => None,
+    };
+
+    let served = async {
+        if shards == 1 {
+            let socket = sockets.into_iter().next().expect("one socket per shard");
+            let state = state.clone();
+            return serve(0, socket, port, tls_config, false, state, qlog_dir).await;
+        }
+        let mut running = JoinSet::new();
+        for (shard, socket) in sockets.into_iter().enumerate() {
+            let tls_config = tls_config.clone();
+            let state = state.clone();
+            let qlog_dir = qlog_dir.clone();
+            running.spawn(shards::spawn(shard, move || {
+                serve(shard, socket, port, tls_config, true, state, qlog_dir)
+            })?);
+        }
+        // Shards only return if their endpoint can't be created
+        match running.join_next().await.expect("at least one shard")? {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!("a shard's thread panicked")),
+        }
+    };
+    let result = tokio::select! {
+        result = served => result,
+        passed = selftest => {
+            journal::stop(&state).await;
+            match passed {
+                true => Ok(()),
+                false => Err(anyhow!("self-test failed")),
+            }
+        }
+        () = shutdown_signal() => {
+            info!("Shutting down");
+            journal::stop(&state).await;
+            Ok(())
+        }
+    };
+    #[cfg(feature = "otel")]
+    if let Some(exporters) = exporters {
+        exporters.shutdown();
+    }
+    result
+}
+
+// Ctrl-C, or SIGTERM on unix. Never resolves if neither can be followed
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                warn!("Can't follow SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => {
+            if let Err(e) = result {
+                warn!("Can't follow Ctrl-C: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+        _ = terminate => {}
+    }
+}
+
+// Accept sessions on `shard`'s endpoint, moving to a new endpoint when the
+// config changes the port. With `reuse_port`, other shards share the port.
+async fn serve(
+    shard: usize,
+    socket: UdpSocket,
+    mut port: u16,
+    tls_config: TlsServerConfig,
+    reuse_port: bool,
+    state: Arc<State>,
+    qlog_dir: Option<PathBuf>,
+) -> Result<()> {
+    let mut server = endpoint(socket, &tls_config, &state)?;
+    let mut config = state.config.subscribe();
     loop {
-        let incoming_session = server.accept().await;
+        // Out of file descriptors, memory or connection ids, accepting more
+        // sessions right away would only fail them too
+        if let Some(delay) = state.accept_backoff.delay() {
+            tokio::time::sleep(delay).await;
+        }
+        let incoming_session = tokio::select! {
+            incoming = server.accept() => incoming,
+            Ok(()) = config.changed() => {
+                let new_port = config.borrow_and_update().port;
+                if new_port == port {
+                    continue;
+                }
+                let bound = shards::socket(new_port, reuse_port)
+                    .and_then(|socket| endpoint(socket, &tls_config, &state));
+                match bound {
+                    Ok(new_server) => {
+                        if shard == 0 {
+                            info!("WebTransport server listening on https://localhost:{}", new_port);
+                        }
+                        let old_server = std::mem::replace(&mut server, new_server);
+                        tokio::spawn(rebind::drain(old_server, shard, port, new_port, state.clone()));
+                        port = new_port;
+                    }
+                    Err(e) => warn!(
+                        "Shard {} can't listen on port {}, staying on {}: {}",
+                        shard, new_port, port, e
+                    ),
+                }
+                continue;
+            }
+        };
+        let state = state.clone();
+        let qlog_dir = qlog_dir.clone();
 
         tokio::spawn(async move {
             match incoming_session.await {
                 Ok(incoming_request) => {
-                    info!("New session request from: {:?}", incoming_request.origin());
+                    let origin = incoming_request.origin();
+                    info!("New session request from: {:?}", origin);
+                    let metadata = metadata::from_path(incoming_request.path());
+                    if !metadata.is_empty() {
+                        info!("Session tags: {:?}", metadata);
+                    }
+                    let Some(framing) = Framing::for_path(incoming_request.path()) else {
+                        warn!(
+                            "Rejecting session on {}: encoding not supported by this build",
+                            incoming_request.path()
+                        );
+                        incoming_request.not_found().await;
+                        return;
+                    };
+                    let preset = match metadata.get("profile").map(|name| Preset::parse(name)) {
+                        Some(None) => {
+                            warn!(
+                                "Rejecting session on {}: unknown network profile",
+                                incoming_request.path()
+                            );
+                            incoming_request.not_found().await;
+                            return;
+                        }
+                        Some(preset) => preset,
+                        None => None,
+                    };
+                    let url = format!(
+                        "https://{}{}",
+                        incoming_request.authority(),
+                        incoming_request.path()
+                    );
+
+                    if !state.config.borrow().allows_origin(origin) {
+                        warn!("Rejecting session from disallowed origin {:?}", origin);
+                        incoming_request.forbidden().await;
+                        return;
+                    }
 
                     match incoming_request.accept().await {
                         Ok(connection) => {
-                            info!("Connection accepted");
-                            handle_connection(connection).await;
+                            state.stats.shards[shard].record_accept();
+                            state.accept_backoff.succeeded();
+                            let id = state.registry.register(
+                                connection.clone(),
+                                metadata,
+                                framing,
+                                port,
+                                shard,
+                                url,
+                            );
+                            info!("Connection {} accepted ({:?} framing)", id, framing);
+                            journal::record(&state, Record::Connect { id });
+                            if let Some(location) =
+                                state.geoip.lookup(connection.remote_address().ip())
+                            {
+                                info!(
+                                    "Connection {} is from {}",
+                                    id,
+                                    location.country.as_deref().unwrap_or("an unknown country")
+                                );
+                                state.registry.set_location(id, location);
+                            }
+                            if let Some(tls) = state.handshakes.take(&connection) {
+                                state.registry.set_tls(id, tls);
+                            }
+                            if let Some(preset) = preset {
+                                info!("Connection {} uses the {} preset", id, preset.name());
+                                state.impairment.set_preset(id, Some(preset));
+                            }
+                            state.resume.issue(&state, id);
+                            tokio::spawn(hello::send(
+                                state.clone(),
+                                id,
+                                connection.clone(),
+                                framing,
+                            ));
+                            let reporter =
+                                tokio::spawn(bandwidth::report(id, connection.clone(), framing));
+                            if let Some(dir) = qlog_dir {
+                                tokio::spawn(qlog::record(dir, id, connection.clone()));
+                            }
+                            let connected_at = Instant::now();
+                            let tally = Arc::new(Tally::default());
+                            let activity = Arc::new(Activity::new());
+                            let prober = tokio::spawn(liveness::probe(
+                                state.clone(),
+                                id,
+                                connection.clone(),
+                                framing,
+                                activity.clone(),
+                            ));
+                            let enforcer = tokio::spawn(quota::enforce(
+                                state.clone(),
+                                id,
+                                connection.clone(),
+                                framing,
+                            ));
+                            let checkpoints = tokio::spawn(journal::checkpoints(
+                                state.clone(),
+                                id,
+                                tally.clone(),
+                            ));
+                            let heartbeat = Arc::new(Heartbeat::new());
+                            let span = info_span!("connection", id, shard, framing = ?framing);
+                            // The handler runs as its own task so the watchdog
+                            // can abort it if it wedges, and a panic in it
+                            // still gets the connection cleaned up
+                            let mut handler = tokio::spawn(
+                                handle_connection(
+                                    state.clone(),
+                                    id,
+                                    connection.clone(),
+                                    framing,
+                                    tally.clone(),
+                                    activity,
+                                    heartbeat.clone(),
+                                )
+                                .instrument(span),
+                            );
+                            tokio::select! {
+                                handled = &mut handler => {
+                                    if let Err(e) = handled
+                                        && e.is_panic()
+                                    {
+                                        watchdog::close(&state, id, &connection, "panicked");
+                                    }
+                                }
+                                stalled = watchdog::expired(&state, &heartbeat) => {
+                                    handler.abort();
+                                    let reason = format!("made no progress for {} ms", stalled.as_millis());
+                                    watchdog::close(&state, id, &connection, &reason);
+                                }
+                            }
+                            prober.abort();
+                            enforcer.abort();
+                            checkpoints.abort();
+                            otel::record_connection(connected_at.elapsed());
+                            reporter.abort();
+                            state.pipelines.remove(id);
+                            resume::park(&state, id);
+                            rooms::remove(&state, id);
+                            state.fanout.remove(id);
+                            state.impairment.remove(id);
+                            state.stats.datagram_echo_latency.remove(id);
+                            state.registry.unregister(id);
+                            journal::record(
+                                &state,
+                                Record::Disconnect {
+                                    id,
+                                    messages: tally.messages(),
+                                    datagrams: tally.datagrams(),
+                                },
+                            );
+
+                            let summary =
+                                Summary::of(id, &connection, connected_at.elapsed(), &tally).await;
+                            summary.log();
+                            if let Some(log) = &state.session_log
+                                && let Err(e) = log.append(&summary).await
+                            {
+                                warn!("Failed to append to {}: {}", log.path().display(), e);
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Failed to accept connection: {}", e);
+                            backoff::failed(&state, Some(shard), ErrorKind::of(&e), &e.to_string());
                         }
-                        Err(e) => warn!("Failed to accept connection: {}", e),
                     }
                 }
-                Err(e) => warn!("Session error: {}", e),
+                Err(e) => {
+                    warn!("Session error: {}", e);
+                    backoff::failed(&state, Some(shard), ErrorKind::of(&e), &e.to_string());
+                }
             }
         });
     }
 }
 
-async fn handle_connection(connection: wtransport::Connection) {
-    info!("Handling connection");
+// A WebTransport endpoint on `socket`. Every endpoint shares the TLS config, so
+// one moved to a new port (or another shard) presents the same certificate (and
+// logs keys alike), and reads its handshakes into `state.handshakes`
+fn endpoint(
+    socket: UdpSocket,
+    tls_config: &TlsServerConfig,
+    state: &State,
+) -> std::io::Result<Endpoint<Server>> {
+    let mut server_config = ServerConfig::builder()
+        .with_bind_socket(socket)
+        .with_custom_tls(tls_config.clone())
+        .build();
+    let quic_config = server_config.quic_config_mut();
+    quic_config.crypto = Arc::new(tls::Capture::new(
+        quic_config.crypto.clone(),
+        state.handshakes.clone(),
+    ));
+    Endpoint::server(server_config)
+}
+
+// Write TLS secrets in the NSS key log format to $SSLKEYLOGFILE
+fn enable_keylog(tls_config: &mut TlsServerConfig) {
+    match std::env::var_os("SSLKEYLOGFILE") {
+        Some(path) => warn!(
+            "TLS KEY LOGGING ENABLED: session secrets are written to {}; anyone with this file can decrypt the traffic. Never use --keylog outside a playground.",
+            path.to_string_lossy()
+        ),
+        None => {
+            warn!("--keylog given but SSLKEYLOGFILE is not set; no keys will be logged");
+            return;
+        }
+    }
+    tls_config.key_log = Arc::new(KeyLogFile::new());
+}
+
+// Limits on one connection's behaviour, shared by its streams and datagrams,
+// and the counts for its summary
+struct Policing {
+    limiter: RateLimiter,
+    strikes: Strikes,
+    tally: Arc<Tally>,
+    /// When the client last sent anything, for liveness probes.
+    activity: Arc<Activity>,
+    bi_streams: StreamSlots,
+    uni_streams: StreamSlots,
+}
+
+impl Policing {
+    // Count an error frame or error response about to be sent to the client
+    fn sent_error(&self, state: &State, error: &PlaygroundError) {
+        state.stats.errors.record(error);
+        self.tally.error();
+    }
+}
+
+async fn handle_connection(
+    state: Arc<State>,
+    id: ConnectionId,
+    connection: Connection,
+    framing: Framing,
+    tally: Arc<Tally>,
+    activity: Arc<Activity>,
+    heartbeat: Arc<Heartbeat>,
+) {
+    info!("Handling connection {}", id);
+    let policing = Arc::new(Policing {
+        limiter: RateLimiter::new(),
+        strikes: Strikes::new(),
+        tally,
+        activity,
+        bi_streams: StreamSlots::new(),
+        uni_streams: StreamSlots::new(),
+    });
+    let mut heartbeats = tokio::time::interval(watchdog::HEARTBEAT_INTERVAL);
 
     loop {
         tokio::select! {
+            // Proof for the watchdog that this loop still gets polled
+            _ = heartbeats.tick() => heartbeat.beat(),
+
             // Handle incoming bidirectional streams
             stream = connection.accept_bi() => {
                 match stream {
-                    Ok((mut send, mut recv)) => {
+                    Ok((mut send, recv)) => {
                         info!("New bidirectional stream opened");
-
-                        tokio::spawn(async move {
-                            // Read data from the stream
-                            let mut buffer = vec![0u8; 1024];
-                            loop {
-                                match recv.read(&mut buffer).await {
-                                    Ok(Some(bytes_read)) => {
-                                        let message = String::from_utf8_lossy(&buffer[..bytes_read]);
-                                        info!("Received: {}", message);
-
-                                        // Echo back
-                                        let response = format!("Server echo: {}", message);
-                                        if let Err(e) = send.write_all(response.as_bytes()).await {
-                                            warn!("Failed to send response: {}", e);
-                                            break;
-                                        }
-                                    }
-                                    Ok(None) => {
-                                        info!("Stream finished");
-                                        break;
-                                    }
-                                    Err(e) => {
-                                        warn!("Error reading from stream: {}", e);
-                                        break;
-                                    }
-                                }
+                        policing.tally.stream();
+                        policing.activity.touch();
+                        let max = state.config.borrow().limits.max_bi_streams;
+                        let Some(slot) = policing.bi_streams.acquire(max) else {
+                            info!("Refusing stream: {} bidirectional streams already open", max);
+                            let code = VarInt::from_u32(STREAM_REFUSED_CODE.into());
+                            let _ = send.reset(code);
+                            recv.stop(code);
+                            continue;
+                        };
+                        let span = info_span!("stream", stream = %send.id());
+                        let stream = handle_stream(
+                            state.clone(),
+                            id,
+                            connection.clone(),
+                            framing,
+                            policing.clone(),
+                            send,
+                            recv,
+                        );
+                        // The slot is freed once the stream is done with
+                        tokio::spawn(
+                            async move {
+                                stream.await;
+                                drop(slot);
                             }
-                        });
+                            .instrument(span),
+                        );
+                    }
+                    Err(e) => {
+                        warn!("Failed to accept stream: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            // Clients have no use for unidirectional streams towards us, but
+            // they count against limits.max_uni_streams while open
+            stream = connection.accept_uni() => {
+                match stream {
+                    Ok(recv) => {
+                        info!("New unidirectional stream opened");
+                        policing.activity.touch();
+                        let max = state.config.borrow().limits.max_uni_streams;
+                        let Some(slot) = policing.uni_streams.acquire(max) else {
+                            info!("Refusing stream: {} unidirectional streams already open", max);
+                            recv.stop(VarInt::from_u32(STREAM_REFUSED_CODE.into()));
+                            continue;
+                        };
+                        tokio::spawn(discard_uni(policing.clone(), recv, slot));
                     }
                     Err(e) => {
                         warn!("Failed to accept stream: {}", e);
@@ -101,14 +823,88 @@ async fn handle_connection(connection: wtransport::Connection) {
             datagram = connection.receive_datagram() => {
                 match datagram {
                     Ok(data) => {
+                        let received = Instant::now();
+                        state.stats.datagram.record(data.len());
+                        policing.tally.datagram();
+                        policing.activity.touch();
+                        let mut data = data.payload();
+                        if let Some(expiring) = expiry::decode(&data) {
+                            let now_ms = SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap_or_default()
+                                .as_millis() as u64;
+                            let age_ms = expiring.age_ms(now_ms);
+                            let late_ms = expiring
+                                .is_expired(now_ms)
+                                .then(|| age_ms - expiring.max_age_ms as u64);
+                            state.stats.datagram_expiry.record(late_ms);
+                            if late_ms.is_some() {
+                                debug!(
+                                    "Dropped datagram from connection {}: {} ms old, max age {} ms",
+                                    id, age_ms, expiring.max_age_ms
+                                );
+                                continue;
+                            }
+                            // Handled from here on as if it had come without an expiry
+                            data = data.slice(expiry::HEADER_LEN..);
+                        }
+                        if data.starts_with(PROBE_PREFIX) {
+                            continue;
+                        }
+                        if let Some(probe_id) = decode_mtu_probe(&data) {
+                            // Bypasses impairment: injected loss would read as an MTU limit
+                            if let Err(e) = connection.send_datagram(encode_mtu_probe(probe_id, 0)) {
+                                debug!("Failed to ack MTU probe: {}", e);
+                            }
+                            continue;
+                        }
+                        if decode_ping(&data).is_some() {
+                            // Impaired like any datagram, so the client sees the path it's testing
+                            state.send_datagram(id, &connection, &data);
+                            continue;
+                        }
+                        if let Some((burst_id, seq)) = protocol::burst::decode_datagram(&data) {
+                            state.burst.record(id, burst_id, seq);
+                            continue;
+                        }
+                        if let Some(Action::CloseConnection(code)) = chaos::roll(&state.config.borrow().chaos, false) {
+                            info!("Chaos: closing connection {} with code {}", id, code);
+                            connection.close(VarInt::from_u32(code), b"chaos");
+                            continue;
+                        }
+                        if let Some(messages) = batch::decode(&data) {
+                            echo_batch(&state, id, &connection, &policing, messages, received);
+                            continue;
+                        }
+                        if !policing.limiter.allow(state.config.borrow().limits.messages_per_second) {
+                            debug!("Rate limited datagram from connection {}", id);
+                            continue;
+                        }
+                        if let Some(packet) = reliable::decode(&data) {
+                            reflect_reliable(&state, id, &connection, &data, packet, received);
+                            continue;
+                        }
+
                         let message = String::from_utf8_lossy(&data);
-                        info!("Received datagram: {}", message);
+                        let Some(moderated) = state.moderation.apply(id, "datagram", &message) else {
+                            continue;
+                        };
+                        info!("Received datagram: {}", moderated);
 
-                        // Echo back via datagram
-                        let response = format!("Server datagram echo: {}", message);
-                        if let Err(e) = connection.send_datagram(response.as_bytes()) {
-                            warn!("Failed to send datagram: {}", e);
+                        // Echo back via datagram. Unless it needs a prefix or was
+                        // redacted, the received payload goes back as it is
+                        if state.config.borrow().datagrams.echo_prefix {
+                            let response = format!("Server datagram echo: {}", moderated);
+                            state.stats.datagram_echo.record(true);
+                            state.send_datagram(id, &connection, response.as_bytes());
+                        } else if let (Cow::Borrowed(_), Cow::Borrowed(_)) = (&message, &moderated) {
+                            state.stats.datagram_echo.record(false);
+                            state.send_datagram(id, &connection, &data);
+                        } else {
+                            state.stats.datagram_echo.record(true);
+                            state.send_datagram(id, &connection, moderated.as_bytes());
                         }
+                        state.stats.datagram_echo_latency.record(id, received);
                     }
                     Err(e) => {
                         warn!("Error receiving datagram: {}", e);
@@ -120,28 +916,605 @@ async fn handle_connection(connection: wtransport::Connection) {
     }
 }
 
-async fn start_http_server() -> Result<()> {
-    use std::net::SocketAddr;
-    use tokio::io::AsyncWriteExt;
-    use tokio::net::TcpListener;
+// Echo a batch datagram's messages back packed into as few batches as fit the
+// connection's datagram size. Each message counts against the rate limit and is
+// moderated and prefixed as if it had arrived alone
+fn echo_batch(
+    state: &State,
+    id: ConnectionId,
+    connection: &Connection,
+    policing: &Policing,
+    messages: Vec<&[u8]>,
+    received: Instant,
+) {
+    info!("Received batch of {} datagram messages", messages.len());
+    state.stats.datagram_batches.record(messages.len());
 
-    let addr: SocketAddr = "127.0.0.1:7654".parse()?;
-    let listener = TcpListener::bind(addr).await?;
-    info!("HTTP server listening on http://{}", addr);
-    info!("Open http://127.0.0.1:7654 in your browser to test");
+    let (messages_per_second, echo_prefix) = {
+        let config = state.config.borrow();
+        (
+            config.limits.messages_per_second,
+            config.datagrams.echo_prefix,
+        )
+    };
+    let mut echoes = Vec::with_capacity(messages.len());
+    for data in messages {
+        if !policing.limiter.allow(messages_per_second) {
+            debug!("Rate limited batched datagram from connection {}", id);
+            continue;
+        }
+        let message = String::from_utf8_lossy(data);
+        let Some(moderated) = state.moderation.apply(id, "datagram", &message) else {
+            continue;
+        };
+        state.stats.datagram_echo.record(true);
+        echoes.push(if echo_prefix {
+            format!("Server datagram echo: {}", moderated)
+        } else {
+            moderated.into_owned()
+        });
+    }
+
+    let max_len = connection.max_datagram_size().unwrap_or(0);
+    for datagram in batch::pack(&echoes, max_len) {
+        state.send_datagram(id, connection, &datagram);
+    }
+    if !echoes.is_empty() {
+        state.stats.datagram_echo_latency.record(id, received);
+    }
+}
 
+// Send a reliable-layer datagram back so the client's receiver sees its own
+// sender's sequence: NACKs untouched, messages moderated but never prefixed,
+// since the prefix would land inside the sequence header
+fn reflect_reliable(
+    state: &State,
+    id: ConnectionId,
+    connection: &Connection,
+    data: &[u8],
+    packet: reliable::Packet,
+    received: Instant,
+) {
+    let reliable::Packet::Data { seq, payload } = packet else {
+        state.send_datagram(id, connection, data);
+        state.stats.datagram_echo_latency.record(id, received);
+        return;
+    };
+    let message = String::from_utf8_lossy(payload);
+    let Some(moderated) = state.moderation.apply(id, "datagram", &message) else {
+        return;
+    };
+    info!("Received reliable datagram {}: {}", seq, moderated);
+    if let (Cow::Borrowed(_), Cow::Borrowed(_)) = (&message, &moderated) {
+        state.stats.datagram_echo.record(false);
+        state.send_datagram(id, connection, data);
+    } else {
+        state.stats.datagram_echo.record(true);
+        state.send_datagram(
+            id,
+            connection,
+            &reliable::encode_data(seq, moderated.as_bytes()),
+        );
+    }
+    state.stats.datagram_echo_latency.record(id, received);
+}
+
+async fn handle_stream(
+    state: Arc<State>,
+    id: ConnectionId,
+    connection: Connection,
+    framing: Framing,
+    policing: Arc<Policing>,
+    mut send: SendStream,
+    mut recv: RecvStream,
+) {
+    // Read frames from the stream
+    let mut buffer = state.read_buffers.take();
+    let mut decoder = FrameDecoder::with_framing(framing);
+    let mut reservation = state.memory.reservation(id);
+    let mut frames = 0u64;
+    let mut last_seq = None;
+    // When the bytes of the frames being handled were read, for their echoes' timing
+    let mut read_at = Instant::now();
+    // The stream's first bytes, held back until they show whether it's raw
+    let mut start = Some(Vec::new());
     loop {
-        let (mut stream, _) = listener.accept().await?;
+        // Hold room for the partial frame the decoder keeps plus the next read
+        let needed = decoder.buffered() + buffer.len();
+        if !memory::reserve_for_stream(&state, &connection, &mut reservation, needed).await {
+            let error = PlaygroundError::Overloaded("server memory budget exhausted".to_string());
+            warn!("Dropping stream of connection {}: {}", id, error);
+            policing.sent_error(&state, &error);
+            let _ = send
+                .write_all(&framing.encode(&Frame::Error { error }))
+                .await;
+            return;
+        }
 
-        tokio::spawn(async move {
-            let html = include_str!("../client.html");
-            let response = format!(
-                "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\n\r\n{}",
-                html.len(),
-                html
-            );
-
-            let _ = stream.write_all(response.as_bytes()).await;
-        });
+        let read = recv.read(&mut buffer).await;
+        if let Ok(Some(_)) = read {
+            read_at = Instant::now();
+            policing.activity.touch();
+        }
+        match read {
+            Ok(Some(bytes_read)) => match &mut start {
+                Some(held) => {
+                    held.extend_from_slice(&buffer[..bytes_read]);
+                    let len = held.len().min(RAW_PREAMBLE.len());
+                    if held[..len] != RAW_PREAMBLE[..len] {
+                        decoder.push(held);
+                        start = None;
+                    } else if held.len() >= RAW_PREAMBLE.len() {
+                        let payload = &held[RAW_PREAMBLE.len()..];
+                        echo_raw(
+                            id,
+                            &policing.activity,
+                            &mut send,
+                            &mut recv,
+                            &mut buffer,
+                            payload,
+                        )
+                        .await;
+                        return;
+                    }
+                }
+                None => decoder.push(&buffer[..bytes_read]),
+            },
+            Ok(None) => {
+                // The client half-closed; our side stays writable until we finish it
+                info!("Client finished stream (FIN) after {} frames", frames);
+                let goodbye = Frame::Message {
+                    text: format!("Server saw your FIN after {} frames; finishing", frames),
+                    seq: None,
+                    id: None,
+                    timing: None,
+                };
+                if let Err(e) = send.write_all(&framing.encode(&goodbye)).await {
+                    warn!("Failed to write after client FIN: {}", e);
+                    return;
+                }
+                match send.finish().await {
+                    Ok(()) => info!("Server finished its side of the stream"),
+                    Err(e) => warn!("Failed to finish stream: {}", e),
+                }
+                break;
+            }
+            Err(e) => {
+                warn!("Error reading from stream: {}", e);
+                break;
+            }
+        }
+
+        loop {
+            let frame = match decoder.next_frame() {
+                Ok(Some(frame)) => {
+                    frames += 1;
+                    frame
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    // A malformed body was consumed and decoding can go on; past
+                    // an oversized one the stream can't be resynchronized
+                    let kind = match e {
+                        DecodeError::TooLarge(_) | DecodeError::BufferFull(_) => Kind::Oversized,
+                        _ => Kind::MalformedFrame,
+                    };
+                    // Tell the peer why before striking, which may close the connection
+                    let error = PlaygroundError::from(e);
+                    policing.sent_error(&state, &error);
+                    let _ = send
+                        .write_all(&framing.encode(&Frame::Error {
+                            error: error.clone(),
+                        }))
+                        .await;
+                    if strike(&state, &policing.strikes, id, &connection, kind, &error)
+                        || kind == Kind::Oversized
+                    {
+                        return;
+                    }
+                    continue;
+                }
+            };
+            match chaos::roll(&state.config.borrow().chaos, true) {
+                Some(Action::ResetStream(code)) => {
+                    info!(
+                        "Chaos: resetting stream of connection {} with code {}",
+                        id, code
+                    );
+                    let _ = send.reset(VarInt::from_u32(code));
+                    recv.stop(VarInt::from_u32(code));
+                    return;
+                }
+                Some(Action::CloseConnection(code)) => {
+                    info!("Chaos: closing connection {} with code {}", id, code);
+                    connection.close(VarInt::from_u32(code), b"chaos");
+                    return;
+                }
+                None => {}
+            }
+
+            // The timer covers the response write; the span stays open as long,
+            // but is only entered where nothing awaits
+            let _timer = otel::FrameTimer::start(frame.kind());
+            let span = info_span!("frame", kind = frame.kind());
+
+            // Numbered messages count up on their stream; 1 starts a new run
+            if let Frame::Message { seq: Some(seq), .. } = frame {
+                if let Some(previous) = last_seq.filter(|&previous| seq != 1 && seq <= previous) {
+                    let error = PlaygroundError::UnexpectedFrame(format!(
+                        "message seq {} after {}",
+                        seq, previous
+                    ));
+                    policing.sent_error(&state, &error);
+                    let _ = send
+                        .write_all(&framing.encode(&Frame::Error {
+                            error: error.clone(),
+                        }))
+                        .await;
+                    let kind = Kind::BadSequence;
+                    if strike(&state, &policing.strikes, id, &connection, kind, &error) {
+                        return;
+                    }
+                    continue;
+                }
+                last_seq = Some(seq);
+            }
+
+            let entered = span.enter();
+            let cx = middleware::Context {
+                state: &state,
+                id,
+                policing: &policing,
+            };
+            let mut chain = Chain::current(&state);
+
+            let mut response = match chain.request(&cx, &frame) {
+                Some(response) => response,
+                None => match frame {
+                    Frame::Message {
+                        text,
+                        seq,
+                        id: message_id,
+                        ..
+                    } => match first_send(&state, id, message_id.as_deref()) {
+                        Err(error) => Frame::Error { error },
+                        Ok(false) => continue,
+                        Ok(true) => {
+                            state.stats.stream.record(text.len());
+                            policing.tally.message();
+                            let Some(text) = state.moderation.apply(id, "stream", &text) else {
+                                continue;
+                            };
+                            info!("Received: {}", text);
+
+                            let timing = Some(ServerTiming::received(state.clock_us(read_at)));
+                            match script::on_message(&state, id, seq, &text) {
+                                // Echo back
+                                script::Outcome::Echo => Frame::Message {
+                                    text: format!("Server echo: {}", text),
+                                    seq,
+                                    id: message_id,
+                                    timing,
+                                },
+                                script::Outcome::Handled { reply, relay } => {
+                                    if let Some(Route { room, text }) = relay
+                                        && let Err(error) = rooms::relay(
+                                            &state,
+                                            id,
+                                            room,
+                                            text,
+                                            message_id.clone(),
+                                            read_at,
+                                        )
+                                    {
+                                        Frame::Error { error }
+                                    } else if let Some(text) = reply {
+                                        Frame::Message {
+                                            text,
+                                            seq,
+                                            id: message_id,
+                                            timing,
+                                        }
+                                    } else {
+                                        continue;
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    Frame::Request {
+                        id: request_id,
+                        method,
+                        params,
+                    } => {
+                        info!("Received request {}: {}", request_id, method);
+                        let call = rpc::Call {
+                            state: &state,
+                            connection: id,
+                        };
+                        Frame::Response {
+                            id: request_id,
+                            outcome: state.rpc.dispatch(&call, &method, params),
+                        }
+                    }
+                    Frame::Response { id, .. } => {
+                        warn!("Unsolicited response {}", id);
+                        Frame::Error {
+                            error: PlaygroundError::UnexpectedFrame(format!(
+                                "response {} without a request",
+                                id
+                            )),
+                        }
+                    }
+                    Frame::Error { error } => {
+                        warn!("Client reported error: {}", error);
+                        continue;
+                    }
+                    Frame::Subscribe { topic } => match state.pipelines.subscribe(id, &topic) {
+                        Ok(()) => {
+                            info!("Connection {} subscribed to pipeline '{}'", id, topic);
+                            continue;
+                        }
+                        Err(error) => Frame::Error { error },
+                    },
+                    Frame::Unsubscribe { topic } => {
+                        state.pipelines.unsubscribe(id, &topic);
+                        continue;
+                    }
+                    Frame::Publish { topic, text } => {
+                        state.stats.stream.record(text.len());
+                        policing.tally.message();
+                        let Some(text) = state.moderation.apply(id, "pipeline", &text) else {
+                            continue;
+                        };
+                        match state.pipelines.publish(&state.memory, id, &topic, text.into_owned()) {
+                            Ok(true) => continue,
+                            Ok(false) => {
+                                debug!("Dropped publish to pipeline '{}' with no subscribers", topic);
+                                continue;
+                            }
+                            Err(error) => {
+                                state.memory.record_rejection(memory::Buffer::Pipeline, true);
+                                Frame::Error { error }
+                            }
+                        }
+                    }
+                    Frame::Join { room, transport } => match rooms::join(&state, id, &room, transport) {
+                        Ok(()) => {
+                            info!("Connection {} joined room '{}'", id, room);
+                            continue;
+                        }
+                        Err(error) => Frame::Error { error },
+                    },
+                    Frame::Leave { room } => {
+                        rooms::leave(&state, id, &room);
+                        continue;
+                    }
+                    Frame::Kick { room, member } => match rooms::control(&state, id, &room, member, Control::Kick) {
+                        Ok(()) => continue,
+                        Err(error) => Frame::Error { error },
+                    },
+                    Frame::Mute { room, member } => match rooms::control(&state, id, &room, member, Control::Mute) {
+                        Ok(()) => continue,
+                        Err(error) => Frame::Error { error },
+                    },
+                    Frame::Unmute { room, member } => match rooms::control(&state, id, &room, member, Control::Unmute) {
+                        Ok(()) => continue,
+                        Err(error) => Frame::Error { error },
+                    },
+                    Frame::Relay {
+                        room,
+                        text,
+                        id: message_id,
+                        ..
+                    } => match first_send(&state, id, message_id.as_deref()) {
+                        Err(error) => Frame::Error { error },
+                        Ok(false) => continue,
+                        Ok(true) => {
+                            state.stats.stream.record(text.len());
+                            policing.tally.message();
+                            let Some(text) = state.moderation.apply(id, "room", &text) else {
+                                continue;
+                            };
+                            match rooms::relay(
+                                &state,
+                                id,
+                                room,
+                                text.into_owned(),
+                                message_id,
+                                read_at,
+                            ) {
+                                Ok(_) => continue,
+                                Err(error) => Frame::Error { error },
+                            }
+                        }
+                    },
+                    Frame::Resume { session_token } => {
+                        match resume::resume(&state, id, &session_token) {
+                            Ok(resumed) => resumed,
+                            Err(error) => Frame::Error { error },
+                        }
+                    }
+                    Frame::Pong { nonce } => {
+                        debug!("Connection {} answered ping {}", id, nonce);
+                        continue;
+                    }
+                    Frame::TransferStart {
+                        id: transfer,
+                        name,
+                        size,
+                    } => {
+                        let config = state.config.borrow().transfers.clone();
+                        match state.transfers.start(id, &transfer, &name, size, &config) {
+                            Ok(ack) => ack,
+                            Err(error) => Frame::Error { error },
+                        }
+                    }
+                    Frame::TransferChunk {
+                        id: transfer,
+                        offset,
+                        data,
+                    } => {
+                        let config = state.config.borrow().transfers.clone();
+                        match state.transfers.chunk(id, &transfer, offset, &data, &config) {
+                            Ok(ack) => ack,
+                            Err(error) => Frame::Error { error },
+                        }
+                    }
+                    Frame::ResumeTransfer { id: transfer } => {
+                        let config = state.config.borrow().transfers.clone();
+                        match state.transfers.resume(id, &transfer, &config) {
+                            Ok(ack) => ack,
+                            Err(error) => Frame::Error { error },
+                        }
+                    }
+                    Frame::BandwidthEstimate(_)
+                    | Frame::Burst(_)
+                    | Frame::Aggregate(_)
+                    | Frame::Redirect { .. }
+                    | Frame::Hello(_)
+                    | Frame::Resumed { .. }
+                    | Frame::Ping { .. }
+                    | Frame::RoomNotice { .. }
+                    | Frame::TransferAck { .. }
+                    | Frame::QuotaWarning { .. } => {
+                        Frame::Error {
+                            error: PlaygroundError::UnexpectedFrame(
+                                "only the server sends bandwidth estimates, burst instructions, aggregates, redirects, hellos, resumptions, pings, room notices, transfer acks and quota warnings"
+                                    .to_string(),
+                            ),
+                        }
+                    }
+                },
+            };
+
+            drop(entered);
+
+            let delay = chain.response(&cx, &mut response);
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            response.stamp_sent(state.clock_us(Instant::now()));
+            if let Err(e) = send.write_all(&framing.encode(&response)).await {
+                warn!("Failed to send response: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+// Read a client's unidirectional stream to its end, throwing the bytes away
+async fn discard_uni(policing: Arc<Policing>, mut recv: RecvStream, _slot: StreamSlot) {
+    let mut buffer = [0u8; 4096];
+    let mut discarded = 0;
+    loop {
+        match recv.read(&mut buffer).await {
+            Ok(Some(bytes_read)) => {
+                policing.activity.touch();
+                discarded += bytes_read;
+            }
+            Ok(None) => break,
+            Err(e) => {
+                debug!("Unidirectional stream ended: {}", e);
+                break;
+            }
+        }
+    }
+    debug!("Discarded {} bytes of a unidirectional stream", discarded);
+}
+
+// Echo a raw stream's payload byte for byte, then finish when the client does.
+// Raw bytes aren't frames, so nothing is moderated, rate limited or counted
+async fn echo_raw(
+    id: ConnectionId,
+    activity: &Activity,
+    send: &mut SendStream,
+    recv: &mut RecvStream,
+    buffer: &mut [u8],
+    payload: &[u8],
+) {
+    info!("Connection {} opened a raw stream", id);
+    let mut echoed = payload.len();
+    if let Err(e) = send.write_all(payload).await {
+        warn!("Failed to echo raw stream: {}", e);
+        return;
+    }
+    loop {
+        match recv.read(buffer).await {
+            Ok(Some(bytes_read)) => {
+                activity.touch();
+                if let Err(e) = send.write_all(&buffer[..bytes_read]).await {
+                    warn!("Failed to echo raw stream: {}", e);
+                    return;
+                }
+                echoed += bytes_read;
+            }
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Error reading from raw stream: {}", e);
+                return;
+            }
+        }
+    }
+    info!("Client finished raw stream after {} bytes", echoed);
+    if let Err(e) = send.finish().await {
+        warn!("Failed to finish raw stream: {}", e);
+    }
+}
+
+// Count a protocol violation against the connection, closing it once it reaches
+// the configured strikes. Returns whether it was closed.
+fn strike(
+    state: &State,
+    strikes: &Strikes,
+    id: ConnectionId,
+    connection: &Connection,
+    kind: Kind,
+    error: &PlaygroundError,
+) -> bool {
+    let threshold = state.config.borrow().violations.strikes;
+    let detail = error.to_string();
+    let Some(strike) = state
+        .violations
+        .record(strikes, id, kind, detail.clone(), threshold)
+    else {
+        warn!("Protocol violation on connection {}: {}", id, detail);
+        return false;
+    };
+    warn!(
+        "Closing connection {} after {} protocol violations, last: {}",
+        id, strike, detail
+    );
+    connection.close(
+        VarInt::from_u32(CloseCode::ProtocolViolation.code()),
+        detail.as_bytes(),
+    );
+    true
+}
+
+// Whether a message or relay should be handled: false for one `connection`'s
+// session already sent under the same `message_id`. Frames without an id are
+// always handled
+fn first_send(
+    state: &State,
+    connection: ConnectionId,
+    message_id: Option<&str>,
+) -> Result<bool, PlaygroundError> {
+    let Some(message_id) = message_id else {
+        return Ok(true);
+    };
+    if message_id.is_empty() || message_id.len() > MAX_MESSAGE_ID_LEN {
+        return Err(PlaygroundError::InvalidParams(format!(
+            "message id must be 1 to {} bytes",
+            MAX_MESSAGE_ID_LEN
+        )));
+    }
+    let first = state.dedup.first(connection, message_id);
+    if !first {
+        debug!(
+            "Connection {} resent message {}; not handled again",
+            connection, message_id
+        );
     }
+    Ok(first)
 }
@@ -0,0 +1,207 @@
+use crate::registry::ConnectionId;
+use crate::state::State;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tokio::sync::futures::Notified;
+use wtransport::Connection;
+
+/// What to do with input that would take buffered messages past the budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Policy {
+    /// Stop reading the stream until memory frees up, so QUIC flow control
+    /// slows the sender. Input that can't wait (pipeline publishes) is dropped.
+    #[default]
+    Backpressure,
+    /// Reject the input: streams get an `Overloaded` error and are dropped,
+    /// publishes are dropped.
+    Drop,
+}
+
+/// Which kind of buffer a reservation is for, as reported in metrics.
+#[derive(Debug, Clone, Copy)]
+pub enum Buffer {
+    /// Partial frames held by a stream's decoder.
+    Reassembly,
+    /// Messages waiting in a pipeline window.
+    Pipeline,
+}
+
+#[derive(Default)]
+struct Counters {
+    reassembly_delayed: AtomicU64,
+    reassembly_dropped: AtomicU64,
+    pipeline_dropped: AtomicU64,
+}
+
+struct Inner {
+    limit: AtomicUsize,
+    used: AtomicUsize,
+    per_connection: Mutex<BTreeMap<ConnectionId, usize>>,
+    counters: Counters,
+    /// Woken whenever bytes are given back or the limit changes, for streams
+    /// held back by backpressure.
+    released: Notify,
+}
+
+// Global budget for bytes the server buffers on clients' behalf. Every buffer
+// holds a Reservation sized to its contents; growing one past the limit fails,
+// and dropping it gives the bytes back.
+pub struct MemoryBudget {
+    inner: Arc<Inner>,
+}
+
+/// Body of `/admin/memory`.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub budget_bytes: usize,
+    pub used_bytes: usize,
+    pub connections: BTreeMap<ConnectionId, usize>,
+    pub reassembly_delayed: u64,
+    pub reassembly_dropped: u64,
+    pub pipeline_dropped: u64,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                limit: AtomicUsize::new(limit),
+                used: AtomicUsize::new(0),
+                per_connection: Mutex::new(BTreeMap::new()),
+                counters: Counters::default(),
+                released: Notify::new(),
+            }),
+        }
+    }
+
+    pub fn set_limit(&self, limit: usize) {
+        self.inner.limit.store(limit, Ordering::Relaxed);
+        self.inner.released.notify_waiters();
+    }
+
+    // Resolves the next time bytes are given back or the limit changes
+    fn released(&self) -> Notified<'_> {
+        self.inner.released.notified()
+    }
+
+    /// An empty reservation charged to `connection`.
+    pub fn reservation(&self, connection: ConnectionId) -> Reservation {
+        Reservation {
+            inner: self.inner.clone(),
+            connection,
+            bytes: 0,
+        }
+    }
+
+    /// Count input that was delayed (`dropped == false`) or dropped for lack of memory.
+    pub fn record_rejection(&self, buffer: Buffer, dropped: bool) {
+        let counters = &self.inner.counters;
+        let counter = match (buffer, dropped) {
+            (Buffer::Reassembly, false) => &counters.reassembly_delayed,
+            (Buffer::Reassembly, true) => &counters.reassembly_dropped,
+            (Buffer::Pipeline, _) => &counters.pipeline_dropped,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn report(&self) -> Report {
+        let counters = &self.inner.counters;
+        Report {
+            budget_bytes: self.inner.limit.load(Ordering::Relaxed),
+            used_bytes: self.inner.used.load(Ordering::Relaxed),
+            connections: self.inner.per_connection.lock().unwrap().clone(),
+            reassembly_delayed: counters.reassembly_delayed.load(Ordering::Relaxed),
+            reassembly_dropped: counters.reassembly_dropped.load(Ordering::Relaxed),
+            pipeline_dropped: counters.pipeline_dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Bytes held against the budget by one buffer; released on drop.
+pub struct Reservation {
+    inner: Arc<Inner>,
+    connection: ConnectionId,
+    bytes: usize,
+}
+
+impl Reservation {
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Resize to `bytes`. Shrinking always succeeds; growing fails if it would
+    /// take total usage past the budget.
+    pub fn resize(&mut self, bytes: usize) -> bool {
+        if bytes > self.bytes {
+            let grow = bytes - self.bytes;
+            let limit = self.inner.limit.load(Ordering::Relaxed);
+            let reserved =
+                self.inner
+                    .used
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                        used.checked_add(grow).filter(|total| *total <= limit)
+                    });
+            if reserved.is_err() {
+                return false;
+            }
+        } else if bytes < self.bytes {
+            self.inner
+                .used
+                .fetch_sub(self.bytes - bytes, Ordering::Relaxed);
+            self.inner.released.notify_waiters();
+        }
+
+        let mut per_connection = self.inner.per_connection.lock().unwrap();
+        let held = per_connection.entry(self.connection).or_default();
+        *held = *held + bytes - self.bytes;
+        if *held == 0 {
+            per_connection.remove(&self.connection);
+        }
+        self.bytes = bytes;
+        true
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.resize(0);
+    }
+}
+
+/// Grow a stream's reassembly reservation to `bytes`, waiting for memory to
+/// free up under the backpressure policy. False means the stream should be
+/// dropped: the policy is `drop`, or the connection closed while waiting.
+pub async fn reserve_for_stream(
+    state: &State,
+    connection: &Connection,
+    reservation: &mut Reservation,
+    bytes: usize,
+) -> bool {
+    if reservation.resize(bytes) {
+        return true;
+    }
+
+    let drop = state.config.borrow().memory.policy == Policy::Drop;
+    state.memory.record_rejection(Buffer::Reassembly, drop);
+    if drop {
+        return false;
+    }
+
+    loop {
+        // Listening before retrying, so bytes given back in between aren't missed
+        let released = state.memory.released();
+        tokio::pin!(released);
+        released.as_mut().enable();
+        if reservation.resize(bytes) {
+            return true;
+        }
+        tokio::select! {
+            _ = connection.closed() => return false,
+            _ = released => {}
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+use tracing::warn;
+
+/// Most tags one session may carry; the rest are ignored.
+const MAX_TAGS: usize = 16;
+
+/// Longest tag key and value we keep, in bytes.
+const MAX_KEY_LEN: usize = 64;
+const MAX_VALUE_LEN: usize = 256;
+
+/// Key/value tags a client attaches to its session at connect time.
+pub type Metadata = BTreeMap<String, String>;
+
+/// Session metadata from the query string of the WebTransport URL,
+/// e.g. `https://localhost:8765/?room=blue&role=viewer`.
+pub fn from_path(path: &str) -> Metadata {
+    let Some((_, query)) = path.split_once('?') else {
+        return Metadata::new();
+    };
+
+    let mut metadata = Metadata::new();
+    for (key, value) in parse_query(query) {
+        if key.is_empty() || key.len() > MAX_KEY_LEN || value.len() > MAX_VALUE_LEN {
+            warn!("Ignoring oversized or empty session tag {:?}", key);
+            continue;
+        }
+        if metadata.len() == MAX_TAGS && !metadata.contains_key(&key) {
+            warn!("Ignoring session tags beyond the first {}", MAX_TAGS);
+            break;
+        }
+        metadata.insert(key, value);
+    }
+    metadata
+}
+
+/// A `key` or `key:value` filter, as in `/connections?tag=room:blue`.
+pub struct TagFilter {
+    key: String,
+    value: Option<String>,
+}
+
+impl TagFilter {
+    pub fn parse(tag: &str) -> Self {
+        match tag.split_once(':') {
+            Some((key, value)) => Self {
+                key: key.to_string(),
+                value: Some(value.to_string()),
+            },
+            None => Self {
+                key: tag.to_string(),
+                value: None,
+            },
+        }
+    }
+
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        match (metadata.get(&self.key), &self.value) {
+            (Some(actual), Some(expected)) => actual == expected,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+}
+
+/// Decoded `key=value` pairs of a URL query string, in order.
+pub fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+// Form-style decoding: `+` is a space and `%XX` a byte; malformed escapes are kept as-is
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+            (b'+', _) => out.push(b' '),
+            (byte, _) => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,208 @@
+use crate::Policing;
+use crate::registry::ConnectionId;
+use crate::state::State;
+use protocol::rpc::Outcome;
+use protocol::{Frame, PlaygroundError};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::debug;
+
+/// Session tag the `auth` layer checks against `middleware.tokens`, e.g.
+/// `https://localhost:8765/?token=s3cret`.
+pub const TOKEN_TAG: &str = "token";
+
+/// A layer frames from clients' streams pass through on their way to the
+/// handler, and responses on their way back. Layers are stateless; what they
+/// need is in the [`Context`].
+pub trait Middleware: Sync {
+    /// Look at a frame before the layers inside this one do. Returning a
+    /// response answers the frame in place of the handler.
+    fn request(&self, _cx: &Context, _frame: &Frame) -> Option<Frame> {
+        None
+    }
+
+    /// Look at the response to a frame, or change it, before the layers
+    /// outside this one do. Returns how long to hold it back.
+    fn response(&self, _cx: &Context, _response: &mut Frame) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// What a layer gets to see of the connection a frame came from.
+pub struct Context<'a> {
+    pub state: &'a State,
+    pub id: ConnectionId,
+    pub policing: &'a Policing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layer {
+    /// Log each frame's kind and its response's at debug level.
+    Log,
+    /// Answer every frame with an error unless the session's `token` tag is
+    /// one of `middleware.tokens`.
+    Auth,
+    /// Hold messages, requests, publishes and relays to
+    /// `limits.messages_per_second`.
+    RateLimit,
+    /// Count error responses, for `/stats` and the session summary.
+    Metrics,
+    /// Delay responses by the connection's network preset's latency.
+    Impairment,
+}
+
+impl Layer {
+    pub fn name(self) -> &'static str {
+        match self {
+            Layer::Log => "log",
+            Layer::Auth => "auth",
+            Layer::RateLimit => "rate_limit",
+            Layer::Metrics => "metrics",
+            Layer::Impairment => "impairment",
+        }
+    }
+
+    fn middleware(self) -> &'static dyn Middleware {
+        match self {
+            Layer::Log => &Log,
+            Layer::Auth => &Auth,
+            Layer::RateLimit => &RateLimit,
+            Layer::Metrics => &Metrics,
+            Layer::Impairment => &Impairment,
+        }
+    }
+}
+
+/// One frame's trip through the layers of `middleware.layers`, outermost
+/// first, as they were when it arrived; a config change applies from the next
+/// frame.
+pub struct Chain {
+    layers: Vec<Layer>,
+    /// Layers the frame got past, and so its response goes back out through.
+    passed: usize,
+}
+
+impl Chain {
+    pub fn current(state: &State) -> Self {
+        Self {
+            layers: state.config.borrow().middleware.layers.clone(),
+            passed: 0,
+        }
+    }
+
+    /// Pass `frame` inwards through the layers. A response is the answer of
+    /// the layer that stopped it, sent instead of handling the frame.
+    pub fn request(&mut self, cx: &Context, frame: &Frame) -> Option<Frame> {
+        for layer in &self.layers {
+            if let Some(response) = layer.middleware().request(cx, frame) {
+                cx.state.stats.middleware.record(layer.name());
+                return Some(response);
+            }
+            self.passed += 1;
+        }
+        None
+    }
+
+    /// Pass the frame's response back out through the layers it got past.
+    /// Returns how long they hold it back.
+    pub fn response(&self, cx: &Context, response: &mut Frame) -> Duration {
+        self.layers[..self.passed]
+            .iter()
+            .rev()
+            .map(|layer| layer.middleware().response(cx, response))
+            .sum()
+    }
+}
+
+struct Log;
+
+impl Middleware for Log {
+    fn request(&self, cx: &Context, frame: &Frame) -> Option<Frame> {
+        debug!("Connection {} sent a {} frame", cx.id, frame.kind());
+        None
+    }
+
+    fn response(&self, cx: &Context, response: &mut Frame) -> Duration {
+        debug!(
+            "Answering connection {} with a {} frame",
+            cx.id,
+            response.kind()
+        );
+        Duration::ZERO
+    }
+}
+
+struct Auth;
+
+impl Middleware for Auth {
+    fn request(&self, cx: &Context, _frame: &Frame) -> Option<Frame> {
+        let token = cx.state.registry.tag(cx.id, TOKEN_TAG);
+        let config = cx.state.config.borrow();
+        if token.is_some_and(|token| config.middleware.tokens.contains(&token)) {
+            return None;
+        }
+        Some(Frame::Error {
+            error: PlaygroundError::Unauthorized(format!(
+                "session needs a valid '{}' tag",
+                TOKEN_TAG
+            )),
+        })
+    }
+}
+
+struct RateLimit;
+
+impl Middleware for RateLimit {
+    fn request(&self, cx: &Context, frame: &Frame) -> Option<Frame> {
+        if !matches!(
+            frame,
+            Frame::Message { .. }
+                | Frame::Request { .. }
+                | Frame::Publish { .. }
+                | Frame::Relay { .. }
+        ) || cx
+            .policing
+            .limiter
+            .allow(cx.state.config.borrow().limits.messages_per_second)
+        {
+            return None;
+        }
+        Some(match frame {
+            Frame::Request { id, .. } => Frame::Response {
+                id: *id,
+                outcome: Outcome::Error(PlaygroundError::RateLimited(
+                    "request rejected".to_string(),
+                )),
+            },
+            _ => Frame::Error {
+                error: PlaygroundError::RateLimited("message dropped".to_string()),
+            },
+        })
+    }
+}
+
+struct Metrics;
+
+impl Middleware for Metrics {
+    fn response(&self, cx: &Context, response: &mut Frame) -> Duration {
+        if let Frame::Error { error }
+        | Frame::Response {
+            outcome: Outcome::Error(error),
+            ..
+        } = response
+        {
+            cx.policing.sent_error(cx.state, error);
+        }
+        Duration::ZERO
+    }
+}
+
+struct Impairment;
+
+impl Middleware for Impairment {
+    // A preset's latency holds the echo back; the stream keeps it in order
+    fn response(&self, cx: &Context, _response: &mut Frame) -> Duration {
+        cx.state.impairment.delay(cx.id)
+    }
+}
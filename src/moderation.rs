@@ -0,0 +1,298 @@
+use crate::registry::ConnectionId;
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many moderation log entries are kept for the admin API.
+const LOG_CAPACITY: usize = 256;
+
+/// Longest message excerpt stored in a log entry.
+const EXCERPT_LEN: usize = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Don't relay the message at all.
+    Drop,
+    /// Relay the message with every match replaced by `***`.
+    Redact,
+    /// Relay the message unchanged but record it in the moderation log.
+    Flag,
+}
+
+/// Outcome of running a message through a filter.
+pub enum Verdict {
+    Allow,
+    Matched { action: Action, rule: String },
+}
+
+/// A moderation check. Implement this to plug custom logic into the relay pipeline.
+pub trait Filter: Send + Sync {
+    fn check(&self, message: &str) -> Verdict;
+
+    /// Rewrite a message the filter asked to redact.
+    fn redact<'a>(&self, message: &'a str) -> Cow<'a, str> {
+        Cow::Borrowed(message)
+    }
+}
+
+/// Regex rule as written in the moderation rules file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleSpec {
+    pattern: String,
+    action: Action,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    rules: Vec<RuleSpec>,
+}
+
+pub struct RegexFilter {
+    regex: Regex,
+    action: Action,
+}
+
+impl RegexFilter {
+    pub fn new(pattern: &str, action: Action) -> Result<Self> {
+        let regex = Regex::new(pattern).with_context(|| format!("Invalid pattern {pattern:?}"))?;
+        Ok(Self { regex, action })
+    }
+}
+
+impl Filter for RegexFilter {
+    fn check(&self, message: &str) -> Verdict {
+        if self.regex.is_match(message) {
+            Verdict::Matched {
+                action: self.action,
+                rule: self.regex.as_str().to_string(),
+            }
+        } else {
+            Verdict::Allow
+        }
+    }
+
+    fn redact<'a>(&self, message: &'a str) -> Cow<'a, str> {
+        self.regex.replace_all(message, "***")
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    pub timestamp_ms: u64,
+    pub connection: ConnectionId,
+    pub transport: &'static str,
+    pub action: Action,
+    pub rule: String,
+    /// The start of the message with every redaction applied, so redacted text
+    /// never appears.
+    pub excerpt: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Counters {
+    pub checked: u64,
+    pub dropped: u64,
+    pub redacted: u64,
+    pub flagged: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub counters: Counters,
+    pub log: Vec<LogEntry>,
+}
+
+// Content filter stage run on every client message before the server relays it.
+// Filters run in order; a drop short-circuits, redactions accumulate.
+pub struct Moderation {
+    filters: Vec<Box<dyn Filter>>,
+    checked: AtomicU64,
+    dropped: AtomicU64,
+    redacted: AtomicU64,
+    flagged: AtomicU64,
+    log: Mutex<VecDeque<LogEntry>>,
+}
+
+impl Moderation {
+    pub fn new() -> Self {
+        Self {
+            filters: Vec::new(),
+            checked: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            redacted: AtomicU64::new(0),
+            flagged: AtomicU64::new(0),
+            log: Mutex::new(VecDeque::with_capacity(LOG_CAPACITY)),
+        }
+    }
+
+    /// Load regex rules from a YAML file:
+    ///
+    /// ```yaml
+    /// rules:
+    ///   - { pattern: "(?i)spam", action: drop }
+    ///   - { pattern: "\\d{4}-\\d{4}-\\d{4}-\\d{4}", action: redact }
+    /// ```
+    pub async fn load(path: &Path) -> Result<Self> {
+        let text = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read moderation rules {}", path.display()))?;
+        let file: RulesFile = serde_yaml::from_str(&text)
+            .with_context(|| format!("Invalid moderation rules {}", path.display()))?;
+
+        let mut moderation = Self::new();
+        for rule in file.rules {
+            moderation.add_filter(RegexFilter::new(&rule.pattern, rule.action)?);
+        }
+        Ok(moderation)
+    }
+
+    pub fn add_filter(&mut self, filter: impl Filter + 'static) {
+        self.filters.push(Box::new(filter));
+    }
+
+    /// Run `message` through every filter. Returns the text to relay, or `None` to drop it.
+    pub fn apply<'a>(
+        &self,
+        connection: ConnectionId,
+        transport: &'static str,
+        message: &'a str,
+    ) -> Option<Cow<'a, str>> {
+        self.checked.fetch_add(1, Ordering::Relaxed);
+
+        let mut output = Cow::Borrowed(message);
+        let mut matched = Vec::new();
+        let mut dropped = false;
+        for filter in &self.filters {
+            let Verdict::Matched { action, rule } = filter.check(&output) else {
+                continue;
+            };
+
+            let counter = match action {
+                Action::Drop => &self.dropped,
+                Action::Redact => &self.redacted,
+                Action::Flag => &self.flagged,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+            matched.push((action, rule));
+
+            match action {
+                Action::Drop => {
+                    dropped = true;
+                    break;
+                }
+                Action::Redact => output = Cow::Owned(filter.redact(&output).into_owned()),
+                Action::Flag => {}
+            }
+        }
+
+        // Logged only once every redaction has run, including those after a
+        // drop, so the log never holds what a redaction removes
+        if !matched.is_empty() {
+            let logged = match dropped {
+                true => self.redact_all(&output),
+                false => output.clone(),
+            };
+            for (action, rule) in matched {
+                self.record(connection, transport, action, rule, &logged);
+            }
+        }
+
+        (!dropped).then_some(output)
+    }
+
+    // `message` with every redaction that matches applied, whatever the other rules say
+    fn redact_all<'a>(&self, message: &'a str) -> Cow<'a, str> {
+        let mut output = Cow::Borrowed(message);
+        for filter in &self.filters {
+            if let Verdict::Matched {
+                action: Action::Redact,
+                ..
+            } = filter.check(&output)
+            {
+                output = Cow::Owned(filter.redact(&output).into_owned());
+            }
+        }
+        output
+    }
+
+    fn record(
+        &self,
+        connection: ConnectionId,
+        transport: &'static str,
+        action: Action,
+        rule: String,
+        message: &str,
+    ) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        let mut log = self.log.lock().unwrap();
+        if log.len() == LOG_CAPACITY {
+            log.pop_front();
+        }
+        log.push_back(LogEntry {
+            timestamp_ms,
+            connection,
+            transport,
+            action,
+            rule,
+            excerpt: message.chars().take(EXCERPT_LEN).collect(),
+        });
+    }
+
+    pub fn report(&self) -> Report {
+        Report {
+            counters: Counters {
+                checked: self.checked.load(Ordering::Relaxed),
+                dropped: self.dropped.load(Ordering::Relaxed),
+                redacted: self.redacted.load(Ordering::Relaxed),
+                flagged: self.flagged.load(Ordering::Relaxed),
+            },
+            log: self.log.lock().unwrap().iter().cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moderation(rules: &[(&str, Action)]) -> Moderation {
+        let mut moderation = Moderation::new();
+        for (pattern, action) in rules {
+            moderation.add_filter(RegexFilter::new(pattern, *action).unwrap());
+        }
+        moderation
+    }
+
+    #[test]
+    fn log_never_holds_redacted_text() {
+        let moderation = moderation(&[
+            ("(?i)card", Action::Flag),
+            (r"\d{4}-\d{4}", Action::Redact),
+            ("(?i)spam", Action::Drop),
+            (r"\d{3}-\d{3}", Action::Redact),
+        ]);
+
+        let relayed = moderation.apply(1, "stream", "card 1234-5678");
+        assert_eq!(relayed.as_deref(), Some("card ***"));
+        assert!(moderation.apply(1, "stream", "spam 123-456").is_none());
+
+        let log = moderation.report().log;
+        assert_eq!(log.len(), 3);
+        for entry in &log {
+            assert!(!entry.excerpt.contains("1234") && !entry.excerpt.contains("123-456"));
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! OpenTelemetry export, with the `otel` feature: the server's tracing spans
+//! (one per connection, stream and frame) and a few metrics go to an OTLP/HTTP
+//! collector such as Jaeger or Grafana Alloy. Without the feature, or without
+//! `--otlp-endpoint`, the recording functions do nothing.
+
+use std::time::{Duration, Instant};
+
+/// Service name spans and metrics are reported under.
+#[cfg(feature = "otel")]
+const SERVICE_NAME: &str = "wtransport-playground";
+
+/// A connection closed after being open for `lifetime`.
+pub fn record_connection(lifetime: Duration) {
+    #[cfg(feature = "otel")]
+    instruments()
+        .connection_duration
+        .record(lifetime.as_secs_f64(), &[]);
+    #[cfg(not(feature = "otel"))]
+    let _ = lifetime;
+}
+
+/// Times one client frame's processing, its response included, and records it
+/// when dropped.
+pub struct FrameTimer {
+    kind: &'static str,
+    started: Instant,
+}
+
+impl FrameTimer {
+    pub fn start(kind: &'static str) -> Self {
+        Self {
+            kind,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Drop for FrameTimer {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        instruments().frame_duration.record(
+            self.started.elapsed().as_secs_f64(),
+            &[opentelemetry::KeyValue::new("kind", self.kind)],
+        );
+        #[cfg(not(feature = "otel"))]
+        let _ = (self.kind, self.started);
+    }
+}
+
+#[cfg(feature = "otel")]
+pub use export::init;
+
+#[cfg(feature = "otel")]
+struct Instruments {
+    connection_duration: opentelemetry::metrics::Histogram<f64>,
+    frame_duration: opentelemetry::metrics::Histogram<f64>,
+}
+
+// Created on first use from the global meter, which is a no-op until `init` installs one
+#[cfg(feature = "otel")]
+fn instruments() -> &'static Instruments {
+    static INSTRUMENTS: std::sync::OnceLock<Instruments> = std::sync::OnceLock::new();
+    INSTRUMENTS.get_or_init(|| {
+        let meter = opentelemetry::global::meter(SERVICE_NAME);
+        Instruments {
+            connection_duration: meter
+                .f64_histogram("playground.connection.duration")
+                .with_unit("s")
+                .with_description("How long sessions stayed open")
+                .build(),
+            frame_duration: meter
+                .f64_histogram("playground.frame.duration")
+                .with_unit("s")
+                .with_description("Time to process a client frame, by frame type")
+                .build(),
+        }
+    })
+}
+
+#[cfg(feature = "otel")]
+mod export {
+    use super::SERVICE_NAME;
+    use anyhow::{Context, Result};
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::Resource;
+    use opentelemetry_sdk::metrics::SdkMeterProvider;
+    use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+    use tracing::Subscriber;
+    use tracing::warn;
+    use tracing_opentelemetry::OpenTelemetryLayer;
+    use tracing_subscriber::registry::LookupSpan;
+
+    /// The installed exporters, flushed by [`Exporters::shutdown`] when the server stops.
+    pub struct Exporters {
+        tracer_provider: SdkTracerProvider,
+        meter_provider: SdkMeterProvider,
+    }
+
+    impl Exporters {
+        /// Flush the spans and metrics still held, and stop exporting.
+        pub fn shutdown(self) {
+            if let Err(e) = self.tracer_provider.shutdown() {
+                warn!("Failed to flush spans: {}", e);
+            }
+            if let Err(e) = self.meter_provider.shutdown() {
+                warn!("Failed to flush metrics: {}", e);
+            }
+        }
+    }
+
+    /// Export to the OTLP/HTTP collector at `endpoint` (e.g. `http://localhost:4318`):
+    /// metrics through the global meter, spans through the returned layer.
+    pub fn init<S>(endpoint: &str) -> Result<(OpenTelemetryLayer<S, SdkTracer>, Exporters)>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let endpoint = endpoint.trim_end_matches('/');
+        let resource = Resource::builder().with_service_name(SERVICE_NAME).build();
+
+        let spans = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint))
+            .build()
+            .context("Failed to create OTLP span exporter")?;
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_batch_exporter(spans)
+            .with_resource(resource.clone())
+            .build();
+
+        let metrics = MetricExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/metrics", endpoint))
+            .build()
+            .context("Failed to create OTLP metric exporter")?;
+        let meter_provider = SdkMeterProvider::builder()
+            .with_periodic_exporter(metrics)
+            .with_resource(resource)
+            .build();
+        opentelemetry::global::set_meter_provider(meter_provider.clone());
+
+        let layer =
+            tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer(SERVICE_NAME));
+        Ok((
+            layer,
+            Exporters {
+                tracer_provider,
+                meter_provider,
+            },
+        ))
+    }
+}
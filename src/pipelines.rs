@@ -0,0 +1,185 @@
+use crate::memory::{MemoryBudget, Reservation};
+use crate::registry::ConnectionId;
+use crate::state::State;
+use protocol::pipeline::{self, Aggregate, MAX_AGGREGATE_BYTES};
+use protocol::{Frame, PlaygroundError};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::debug;
+
+/// Most topics one connection may subscribe to.
+const MAX_SUBSCRIPTIONS: usize = 64;
+
+// Messages published to a topic since its last flush.
+#[derive(Default)]
+struct Window {
+    count: u64,
+    payloads: Vec<String>,
+    payload_bytes: usize,
+    truncated: u64,
+    /// Payload bytes held for each publisher, charged to the memory budget.
+    publishers: BTreeMap<ConnectionId, Reservation>,
+}
+
+#[derive(Default)]
+struct Topic {
+    subscribers: BTreeSet<ConnectionId>,
+    window: Window,
+}
+
+// Named fan-in pipelines: many clients publish to a topic, and once per interval
+// the server sends each subscriber a single aggregate of what was published.
+// A topic exists only while it has subscribers; publishing to any other is a no-op.
+pub struct Pipelines {
+    topics: Mutex<HashMap<String, Topic>>,
+}
+
+impl Pipelines {
+    pub fn new() -> Self {
+        Self {
+            topics: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn subscribe(&self, connection: ConnectionId, topic: &str) -> Result<(), PlaygroundError> {
+        if !pipeline::valid_topic(topic) {
+            return Err(invalid_topic(topic));
+        }
+
+        let mut topics = self.topics.lock().unwrap();
+        let subscriptions = topics
+            .values()
+            .filter(|t| t.subscribers.contains(&connection))
+            .count();
+        let subscribed = topics
+            .get(topic)
+            .is_some_and(|t| t.subscribers.contains(&connection));
+        if subscriptions >= MAX_SUBSCRIPTIONS && !subscribed {
+            return Err(PlaygroundError::InvalidParams(format!(
+                "at most {} subscriptions per connection",
+                MAX_SUBSCRIPTIONS
+            )));
+        }
+
+        topics
+            .entry(topic.to_string())
+            .or_default()
+            .subscribers
+            .insert(connection);
+        Ok(())
+    }
+
+    pub fn unsubscribe(&self, connection: ConnectionId, topic: &str) {
+        let mut topics = self.topics.lock().unwrap();
+        if let Some(t) = topics.get_mut(topic) {
+            t.subscribers.remove(&connection);
+            if t.subscribers.is_empty() {
+                topics.remove(topic);
+            }
+        }
+    }
+
+    /// Drop every subscription of a closed connection.
+    pub fn remove(&self, connection: ConnectionId) {
+        let mut topics = self.topics.lock().unwrap();
+        topics.retain(|_, t| {
+            t.subscribers.remove(&connection);
+            !t.subscribers.is_empty()
+        });
+    }
+
+    /// Add `text` to the current window of `topic`. Returns false if nobody
+    /// subscribes to it, or `Overloaded` if the memory budget can't hold it.
+    pub fn publish(
+        &self,
+        memory: &MemoryBudget,
+        connection: ConnectionId,
+        topic: &str,
+        text: String,
+    ) -> Result<bool, PlaygroundError> {
+        let mut topics = self.topics.lock().unwrap();
+        let Some(t) = topics.get_mut(topic) else {
+            return Ok(false);
+        };
+
+        let window = &mut t.window;
+        let reservation = window
+            .publishers
+            .entry(connection)
+            .or_insert_with(|| memory.reservation(connection));
+
+        // Measured as encoded, since escaping can make a payload several times longer
+        let encoded_len = serde_json::to_string(&text).map_or(usize::MAX, |json| json.len());
+        if window.payload_bytes.saturating_add(encoded_len) > MAX_AGGREGATE_BYTES {
+            window.count += 1;
+            window.truncated += 1;
+            return Ok(true);
+        }
+        if !reservation.resize(reservation.bytes() + encoded_len) {
+            return Err(PlaygroundError::Overloaded(format!(
+                "pipeline '{}' is over the memory budget",
+                topic
+            )));
+        }
+
+        window.count += 1;
+        window.payload_bytes += encoded_len;
+        window.payloads.push(text);
+        Ok(true)
+    }
+
+    // Close every topic's window, returning the non-empty ones with their subscribers
+    fn flush(&self, window_ms: u64) -> Vec<(Aggregate, Vec<ConnectionId>)> {
+        let mut topics = self.topics.lock().unwrap();
+        topics
+            .iter_mut()
+            .filter(|(_, t)| t.window.count > 0)
+            .map(|(name, t)| {
+                let window = std::mem::take(&mut t.window);
+                let aggregate = Aggregate {
+                    topic: name.clone(),
+                    window_ms,
+                    count: window.count,
+                    publishers: window.publishers.len(),
+                    payloads: window.payloads,
+                    truncated: window.truncated,
+                };
+                (aggregate, t.subscribers.iter().copied().collect())
+            })
+            .collect()
+    }
+}
+
+fn invalid_topic(topic: &str) -> PlaygroundError {
+    PlaygroundError::InvalidParams(format!(
+        "topic {:?} must be 1 to {} bytes",
+        topic,
+        pipeline::MAX_TOPIC_LEN
+    ))
+}
+
+// Fan out each topic's aggregate once per `pipelines.interval_ms`, re-read every
+// window so config reloads apply from the next one
+pub async fn run(state: Arc<State>) {
+    loop {
+        let window_ms = state.config.borrow().pipelines.interval_ms;
+        tokio::time::sleep(Duration::from_millis(window_ms)).await;
+
+        for (aggregate, subscribers) in state.pipelines.flush(window_ms) {
+            debug!(
+                "Pipeline '{}': {} messages from {} publishers to {} subscribers",
+                aggregate.topic,
+                aggregate.count,
+                aggregate.publishers,
+                subscribers.len()
+            );
+
+            let frame = Arc::new(Frame::Aggregate(aggregate));
+            let queue_len = state.config.borrow().fanout.queue_len;
+            for id in subscribers {
+                state.fanout.send(id, frame.clone(), queue_len);
+            }
+        }
+    }
+}
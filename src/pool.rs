@@ -0,0 +1,96 @@
+use bytes::BytesMut;
+use serde::Serialize;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Size of each stream read buffer.
+pub const READ_BUFFER_LEN: usize = 1024;
+
+/// Most idle buffers kept. Streams beyond this many at once still get a
+/// buffer, it just isn't kept when they end.
+const MAX_POOLED: usize = 256;
+
+// Read buffers shared by every stream handler. A stream takes one for its
+// lifetime and hands it back when it ends, so a stream only allocates when
+// every pooled buffer is in use.
+pub struct BufferPool {
+    free: Mutex<Vec<BytesMut>>,
+    allocated: AtomicU64,
+    reused: AtomicU64,
+}
+
+/// Read buffer counters, as reported in `/stats`.
+#[derive(Debug, Serialize)]
+pub struct PoolSnapshot {
+    pub allocated: u64,
+    pub reused: u64,
+    pub idle: usize,
+}
+
+impl BufferPool {
+    pub fn new() -> Self {
+        Self {
+            free: Mutex::new(Vec::new()),
+            allocated: AtomicU64::new(0),
+            reused: AtomicU64::new(0),
+        }
+    }
+
+    /// A [`READ_BUFFER_LEN`]-byte buffer, back in the pool when dropped. Reused
+    /// buffers keep their allocation and old contents; only new ones are zeroed.
+    pub fn take(&self) -> PooledBuffer<'_> {
+        let buffer = match self.free.lock().unwrap().pop() {
+            Some(buffer) => {
+                self.reused.fetch_add(1, Ordering::Relaxed);
+                buffer
+            }
+            None => {
+                self.allocated.fetch_add(1, Ordering::Relaxed);
+                BytesMut::zeroed(READ_BUFFER_LEN)
+            }
+        };
+        PooledBuffer { pool: self, buffer }
+    }
+
+    fn give_back(&self, buffer: BytesMut) {
+        let mut free = self.free.lock().unwrap();
+        if free.len() < MAX_POOLED {
+            free.push(buffer);
+        }
+    }
+
+    pub fn snapshot(&self) -> PoolSnapshot {
+        PoolSnapshot {
+            allocated: self.allocated.load(Ordering::Relaxed),
+            reused: self.reused.load(Ordering::Relaxed),
+            idle: self.free.lock().unwrap().len(),
+        }
+    }
+}
+
+/// A buffer on loan from a [`BufferPool`].
+pub struct PooledBuffer<'a> {
+    pool: &'a BufferPool,
+    buffer: BytesMut,
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        self.pool.give_back(std::mem::take(&mut self.buffer));
+    }
+}
@@ -0,0 +1,107 @@
+//! CPU profiling, with the `profiling` feature: the whole server's stacks are
+//! sampled for a while and rendered as a flamegraph SVG, served by
+//! `GET /admin/flamegraph` or written to the dump directory on SIGUSR1, so
+//! hotspots can be caught in the middle of a load test. Without the feature
+//! the endpoint reports that profiling isn't built in.
+
+use anyhow::{Result, bail};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// How long a profile samples for unless asked otherwise.
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+
+/// Longest a profile may sample for.
+pub const MAX_DURATION: Duration = Duration::from_secs(120);
+
+/// Samples per second unless asked otherwise; off the round 100 so sampling
+/// doesn't fall into step with timers.
+pub const DEFAULT_FREQUENCY: i32 = 99;
+
+/// Most samples per second a profile may take.
+pub const MAX_FREQUENCY: i32 = 1000;
+
+// Set while a profile is sampling
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Whether a profile is sampling now.
+pub fn running() -> bool {
+    RUNNING.load(Ordering::Acquire)
+}
+
+/// Sample every thread's stack `frequency` times a second for `duration`, and
+/// render the samples as a flamegraph SVG. Only one profile runs at a time.
+pub async fn flamegraph(duration: Duration, frequency: i32) -> Result<Vec<u8>> {
+    #[cfg(feature = "profiling")]
+    return tokio::task::spawn_blocking(move || sample(duration, frequency)).await?;
+    #[cfg(not(feature = "profiling"))]
+    {
+        let _ = (duration, frequency);
+        bail!("profiling needs a build with the profiling feature")
+    }
+}
+
+// The profiler samples in a signal handler, so its guard blocks the thread it's
+// on rather than being held across awaits
+#[cfg(feature = "profiling")]
+fn sample(duration: Duration, frequency: i32) -> Result<Vec<u8>> {
+    if RUNNING.swap(true, Ordering::AcqRel) {
+        bail!("a profile is already running");
+    }
+    let svg = (|| {
+        let guard = pprof::ProfilerGuardBuilder::default()
+            .frequency(frequency)
+            // Unwinding through these can deadlock or crash
+            .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+            .build()?;
+        std::thread::sleep(duration);
+        let report = guard.report().build()?;
+        // CPU time drives the sampling, so an idle server gives none
+        if report.data.is_empty() {
+            bail!("no samples were taken; the server was idle");
+        }
+        let mut svg = Vec::new();
+        report.flamegraph(&mut svg)?;
+        Ok(svg)
+    })();
+    RUNNING.store(false, Ordering::Release);
+    svg
+}
+
+/// Write a [`DEFAULT_DURATION`] flamegraph to `flamegraph-<unix ms>.svg` in
+/// `dir` each time the process gets SIGUSR1.
+#[cfg(all(unix, feature = "profiling"))]
+pub async fn on_signal(dir: std::path::PathBuf) -> Result<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+    use tracing::{info, warn};
+
+    let mut signals = signal(SignalKind::user_defined1())?;
+    while signals.recv().await.is_some() {
+        info!("SIGUSR1: profiling for {} s", DEFAULT_DURATION.as_secs());
+        match write(&dir).await {
+            Ok(path) => info!("Wrote flamegraph to {}", path.display()),
+            Err(e) => warn!("Profile failed: {:#}", e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(unix, feature = "profiling"))]
+async fn write(dir: &std::path::Path) -> Result<std::path::PathBuf> {
+    use anyhow::Context;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let svg = flamegraph(DEFAULT_DURATION, DEFAULT_FREQUENCY).await?;
+    tokio::fs::create_dir_all(dir)
+        .await
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    let taken_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = dir.join(format!("flamegraph-{}.svg", taken_at));
+    tokio::fs::write(&path, svg)
+        .await
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
@@ -0,0 +1,519 @@
+use crate::moderation;
+use crate::state::State;
+use crate::stats::{self, HistogramSnapshot};
+use std::fmt::Write;
+
+/// Render server metrics in the Prometheus text exposition format.
+pub fn render(state: &State) -> String {
+    let report = stats::Report::collect(state);
+    let mut out = String::new();
+
+    gauge(
+        &mut out,
+        "playground_connections",
+        "Live WebTransport sessions",
+        report.connections as u64,
+    );
+    gauge(
+        &mut out,
+        "playground_uptime_seconds",
+        "Seconds since the server started",
+        report.uptime_ms / 1000,
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP playground_messages_received_total Messages received from clients"
+    );
+    let _ = writeln!(out, "# TYPE playground_messages_received_total counter");
+    for (transport, snapshot) in [("stream", &report.stream), ("datagram", &report.datagram)] {
+        let _ = writeln!(
+            out,
+            "playground_messages_received_total{{transport=\"{}\"}} {}",
+            transport, snapshot.messages
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP playground_message_size_bytes Size of messages received from clients"
+    );
+    let _ = writeln!(out, "# TYPE playground_message_size_bytes histogram");
+    for (transport, snapshot) in [("stream", &report.stream), ("datagram", &report.datagram)] {
+        histogram(
+            &mut out,
+            "playground_message_size_bytes",
+            transport,
+            &snapshot.message_sizes,
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP playground_max_datagram_size_bytes Usable datagram size found by client MTU discovery"
+    );
+    let _ = writeln!(out, "# TYPE playground_max_datagram_size_bytes histogram");
+    histogram(
+        &mut out,
+        "playground_max_datagram_size_bytes",
+        "datagram",
+        &report.max_datagram_size,
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP playground_datagram_echoes_total Datagram echoes built as a new payload or sent back unchanged"
+    );
+    let _ = writeln!(out, "# TYPE playground_datagram_echoes_total counter");
+    for (path, value) in [
+        ("rebuilt", report.datagram_echo.rebuilt),
+        ("unchanged", report.datagram_echo.unchanged),
+    ] {
+        let _ = writeln!(
+            out,
+            "playground_datagram_echoes_total{{path=\"{}\"}} {}",
+            path, value
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP playground_datagram_batches_total Batch datagrams received from clients"
+    );
+    let _ = writeln!(out, "# TYPE playground_datagram_batches_total counter");
+    let _ = writeln!(
+        out,
+        "playground_datagram_batches_total {}",
+        report.datagram_batches.batches
+    );
+    let _ = writeln!(
+        out,
+        "# HELP playground_datagram_batched_messages_total Messages packed into batch datagrams"
+    );
+    let _ = writeln!(
+        out,
+        "# TYPE playground_datagram_batched_messages_total counter"
+    );
+    let _ = writeln!(
+        out,
+        "playground_datagram_batched_messages_total {}",
+        report.datagram_batches.messages
+    );
+    let _ = writeln!(
+        out,
+        "# HELP playground_expiring_datagrams_total Datagrams sent with a max age, delivered or dropped as expired"
+    );
+    let _ = writeln!(out, "# TYPE playground_expiring_datagrams_total counter");
+    let expiry = &report.datagram_expiry;
+    for (outcome, value) in [
+        ("delivered", expiry.received - expiry.expired),
+        ("expired", expiry.expired),
+    ] {
+        let _ = writeln!(
+            out,
+            "playground_expiring_datagrams_total{{outcome=\"{}\"}} {}",
+            outcome, value
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP playground_room_relays_total Copies of room relays sent to members, by transport"
+    );
+    let _ = writeln!(out, "# TYPE playground_room_relays_total counter");
+    for (transport, value) in [
+        ("stream", report.room_relays.streams),
+        ("datagram", report.room_relays.datagrams),
+    ] {
+        let _ = writeln!(
+            out,
+            "playground_room_relays_total{{transport=\"{}\"}} {}",
+            transport, value
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP playground_room_relay_fallbacks_total Room relays meant for datagrams sent on a stream instead"
+    );
+    let _ = writeln!(out, "# TYPE playground_room_relay_fallbacks_total counter");
+    let _ = writeln!(
+        out,
+        "playground_room_relay_fallbacks_total {}",
+        report.room_relays.fallbacks
+    );
+
+    let fanout = &report.fanout;
+    let _ = writeln!(
+        out,
+        "# HELP playground_fanout_frames_total Room relays and pipeline aggregates queued for connections, by outcome"
+    );
+    let _ = writeln!(out, "# TYPE playground_fanout_frames_total counter");
+    for (outcome, value) in [
+        ("delivered", fanout.totals.delivered),
+        ("dropped", fanout.totals.dropped),
+        ("failed", fanout.totals.failed),
+    ] {
+        let _ = writeln!(
+            out,
+            "playground_fanout_frames_total{{outcome=\"{}\"}} {}",
+            outcome, value
+        );
+    }
+    gauge(
+        &mut out,
+        "playground_fanout_queued_frames",
+        "Frames waiting in connections' fan-out queues",
+        fanout.queued as u64,
+    );
+    let _ = writeln!(
+        out,
+        "# HELP playground_fanout_lag_ms How long each connection's oldest queued frame has waited"
+    );
+    let _ = writeln!(out, "# TYPE playground_fanout_lag_ms gauge");
+    for subscriber in &fanout.subscribers {
+        let _ = writeln!(
+            out,
+            "playground_fanout_lag_ms{{connection=\"{}\"}} {}",
+            subscriber.connection, subscriber.lag_ms
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP playground_errors_sent_total Error frames and error responses sent to clients"
+    );
+    let _ = writeln!(out, "# TYPE playground_errors_sent_total counter");
+    for (kind, count) in &report.errors {
+        let _ = writeln!(
+            out,
+            "playground_errors_sent_total{{kind=\"{}\"}} {}",
+            kind, count
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP playground_middleware_answered_total Frames a middleware layer answered instead of passing on"
+    );
+    let _ = writeln!(out, "# TYPE playground_middleware_answered_total counter");
+    for (layer, count) in &report.middleware.answered {
+        let _ = writeln!(
+            out,
+            "playground_middleware_answered_total{{layer=\"{}\"}} {}",
+            layer, count
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP playground_read_buffers_total Stream read buffers allocated or reused from the pool"
+    );
+    let _ = writeln!(out, "# TYPE playground_read_buffers_total counter");
+    for (outcome, value) in [
+        ("allocated", report.read_buffers.allocated),
+        ("reused", report.read_buffers.reused),
+    ] {
+        let _ = writeln!(
+            out,
+            "playground_read_buffers_total{{outcome=\"{}\"}} {}",
+            outcome, value
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP playground_shard_sessions_accepted_total Sessions accepted by each endpoint sharing the port"
+    );
+    let _ = writeln!(
+        out,
+        "# TYPE playground_shard_sessions_accepted_total counter"
+    );
+    for shard in &report.shards {
+        let _ = writeln!(
+            out,
+            "playground_shard_sessions_accepted_total{{shard=\"{}\"}} {}",
+            shard.shard, shard.accepted
+        );
+    }
+
+    gauge(
+        &mut out,
+        "playground_background_clients",
+        "Clients whose page is hidden, by their own report",
+        report.visibility.background_clients as u64,
+    );
+    let _ = writeln!(
+        out,
+        "# HELP playground_visibility_changes_total Clients reporting their page hidden or visible again"
+    );
+    let _ = writeln!(out, "# TYPE playground_visibility_changes_total counter");
+    for (to, value) in [
+        ("hidden", report.visibility.hidden),
+        ("visible", report.visibility.visible),
+    ] {
+        let _ = writeln!(
+            out,
+            "playground_visibility_changes_total{{to=\"{}\"}} {}",
+            to, value
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP playground_heartbeats_total Client heartbeats, late if well past their announced interval"
+    );
+    let _ = writeln!(out, "# TYPE playground_heartbeats_total counter");
+    for (timing, value) in [
+        (
+            "on_time",
+            report.visibility.heartbeats - report.visibility.late_heartbeats,
+        ),
+        ("late", report.visibility.late_heartbeats),
+    ] {
+        let _ = writeln!(
+            out,
+            "playground_heartbeats_total{{timing=\"{}\"}} {}",
+            timing, value
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP playground_liveness_probes_total Pings sent to quiet connections, answered in time or not"
+    );
+    let _ = writeln!(out, "# TYPE playground_liveness_probes_total counter");
+    for (outcome, value) in [
+        ("answered", report.liveness.answered),
+        ("unanswered", report.liveness.unanswered),
+    ] {
+        let _ = writeln!(
+            out,
+            "playground_liveness_probes_total{{outcome=\"{}\"}} {}",
+            outcome, value
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP playground_dead_connections_closed_total Connections closed as unresponsive after an unanswered ping"
+    );
+    let _ = writeln!(
+        out,
+        "# TYPE playground_dead_connections_closed_total counter"
+    );
+    let _ = writeln!(
+        out,
+        "playground_dead_connections_closed_total {}",
+        report.liveness.unanswered
+    );
+    let _ = writeln!(
+        out,
+        "# HELP playground_quota_limit_bytes Bytes each connection may send and receive; 0 means no cap"
+    );
+    let _ = writeln!(out, "# TYPE playground_quota_limit_bytes gauge");
+    let _ = writeln!(
+        out,
+        "playground_quota_limit_bytes {}",
+        report.quota.limit_bytes
+    );
+    let _ = writeln!(
+        out,
+        "# HELP playground_quota_events_total Connections warned of nearing their byte quota, or closed on reaching it"
+    );
+    let _ = writeln!(out, "# TYPE playground_quota_events_total counter");
+    for (event, value) in [
+        ("warned", report.quota.warned),
+        ("exceeded", report.quota.exceeded),
+    ] {
+        let _ = writeln!(
+            out,
+            "playground_quota_events_total{{event=\"{}\"}} {}",
+            event, value
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP playground_accept_errors_total Sessions that failed to set up, by why"
+    );
+    let _ = writeln!(out, "# TYPE playground_accept_errors_total counter");
+    for (kind, count) in &report.accept_errors.errors {
+        let _ = writeln!(
+            out,
+            "playground_accept_errors_total{{kind=\"{}\"}} {}",
+            kind.as_str(),
+            count
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP playground_accept_backoffs_total Pauses before accepting sessions after running out of resources"
+    );
+    let _ = writeln!(out, "# TYPE playground_accept_backoffs_total counter");
+    let _ = writeln!(
+        out,
+        "playground_accept_backoffs_total {}",
+        report.accept_errors.backoffs
+    );
+
+    let _ = writeln!(
+        out,
+        "# HELP playground_wedged_connections_closed_total Connections closed as server errors after their handler stalled or panicked"
+    );
+    let _ = writeln!(
+        out,
+        "# TYPE playground_wedged_connections_closed_total counter"
+    );
+    let _ = writeln!(
+        out,
+        "playground_wedged_connections_closed_total {}",
+        report.wedged_connections
+    );
+
+    let resume = &report.resume;
+    gauge(
+        &mut out,
+        "playground_resume_parked_sessions",
+        "Closed sessions waiting to be resumed",
+        resume.parked_sessions as u64,
+    );
+    gauge(
+        &mut out,
+        "playground_resume_queue_depth",
+        "Messages queued for parked sessions",
+        resume.queue_depth as u64,
+    );
+    gauge(
+        &mut out,
+        "playground_resume_largest_queue",
+        "Messages queued for the parked session with the most",
+        resume.largest_queue as u64,
+    );
+    let _ = writeln!(
+        out,
+        "# HELP playground_resume_sessions_total Sessions parked on close, resumed, expired unresumed or taken over while live"
+    );
+    let _ = writeln!(out, "# TYPE playground_resume_sessions_total counter");
+    for (outcome, value) in [
+        ("parked", resume.totals.parked),
+        ("resumed", resume.totals.resumed),
+        ("expired", resume.totals.expired),
+        ("taken_over", resume.totals.taken_over),
+    ] {
+        let _ = writeln!(
+            out,
+            "playground_resume_sessions_total{{outcome=\"{}\"}} {}",
+            outcome, value
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP playground_resume_messages_total Messages queued for parked sessions and replayed after a resume"
+    );
+    let _ = writeln!(out, "# TYPE playground_resume_messages_total counter");
+    for (outcome, value) in [
+        ("queued", resume.totals.queued),
+        ("replayed", resume.totals.replayed),
+    ] {
+        let _ = writeln!(
+            out,
+            "playground_resume_messages_total{{outcome=\"{}\"}} {}",
+            outcome, value
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP playground_resume_dropped_total Queued messages dropped from a full queue or with an expired session"
+    );
+    let _ = writeln!(out, "# TYPE playground_resume_dropped_total counter");
+    for (reason, value) in [
+        ("full", resume.totals.dropped_full),
+        ("expired", resume.totals.dropped_expired),
+    ] {
+        let _ = writeln!(
+            out,
+            "playground_resume_dropped_total{{reason=\"{}\"}} {}",
+            reason, value
+        );
+    }
+
+    let memory = state.memory.report();
+    gauge(
+        &mut out,
+        "playground_memory_budget_bytes",
+        "Budget for bytes buffered on clients' behalf",
+        memory.budget_bytes as u64,
+    );
+    gauge(
+        &mut out,
+        "playground_memory_used_bytes",
+        "Bytes currently buffered on clients' behalf",
+        memory.used_bytes as u64,
+    );
+    let _ = writeln!(
+        out,
+        "# HELP playground_memory_rejected_total Input delayed or dropped because the memory budget was exhausted"
+    );
+    let _ = writeln!(out, "# TYPE playground_memory_rejected_total counter");
+    for (buffer, action, value) in [
+        ("reassembly", "delayed", memory.reassembly_delayed),
+        ("reassembly", "dropped", memory.reassembly_dropped),
+        ("pipeline", "dropped", memory.pipeline_dropped),
+    ] {
+        let _ = writeln!(
+            out,
+            "playground_memory_rejected_total{{buffer=\"{}\",action=\"{}\"}} {}",
+            buffer, action, value
+        );
+    }
+
+    let moderation::Report { counters, .. } = state.moderation.report();
+    let _ = writeln!(
+        out,
+        "# HELP playground_moderation_total Moderation filter outcomes"
+    );
+    let _ = writeln!(out, "# TYPE playground_moderation_total counter");
+    for (outcome, value) in [
+        ("checked", counters.checked),
+        ("dropped", counters.dropped),
+        ("redacted", counters.redacted),
+        ("flagged", counters.flagged),
+    ] {
+        let _ = writeln!(
+            out,
+            "playground_moderation_total{{outcome=\"{}\"}} {}",
+            outcome, value
+        );
+    }
+
+    out
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn histogram(out: &mut String, name: &str, transport: &str, snapshot: &HistogramSnapshot) {
+    // Prometheus buckets are cumulative
+    let mut cumulative = 0;
+    for bucket in &snapshot.buckets {
+        cumulative += bucket.count;
+        let le = bucket
+            .le
+            .map_or_else(|| "+Inf".to_string(), |le| le.to_string());
+        let _ = writeln!(
+            out,
+            "{}_bucket{{transport=\"{}\",le=\"{}\"}} {}",
+            name, transport, le, cumulative
+        );
+    }
+    let _ = writeln!(
+        out,
+        "{}_sum{{transport=\"{}\"}} {}",
+        name, transport, snapshot.sum
+    );
+    let _ = writeln!(
+        out,
+        "{}_count{{transport=\"{}\"}} {}",
+        name, transport, snapshot.count
+    );
+}
@@ -0,0 +1,160 @@
+use crate::registry::ConnectionId;
+use anyhow::Result;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tracing::{info, warn};
+use wtransport::Connection;
+use wtransport::error::ConnectionError;
+
+/// How often the connection's QUIC stats are sampled.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Record separator that starts every JSON-SEQ record.
+const RS: u8 = 0x1e;
+
+// Trace one session into `<dir>/connection-<id>.sqlog`, named by its registry
+// id rather than a QUIC connection ID, in qlog 0.3 JSON-SEQ form that qvis
+// loads. Only the container is qlog: wtransport doesn't expose quinn's
+// packet-level events, so the trace holds quinn's path stats sampled every
+// SAMPLE_INTERVAL while they change, with RTT and congestion window as
+// `recovery:metrics_updated` and loss and congestion as `playground:path_stats`.
+// No packet is ever logged. Never overwrites a file. Runs until the session
+// closes.
+pub async fn record(dir: PathBuf, id: ConnectionId, connection: Connection) {
+    let path = dir.join(format!("connection-{}.sqlog", id));
+    match trace(&path, id, &connection).await {
+        Ok(()) => info!(
+            "qlog trace of connection {} written to {}",
+            id,
+            path.display()
+        ),
+        Err(e) => warn!("qlog trace of connection {} failed: {:#}", id, e),
+    }
+}
+
+async fn trace(path: &Path, id: ConnectionId, connection: &Connection) -> Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await?;
+    let mut out = BufWriter::new(file);
+    let started = Instant::now();
+    let reference_time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+
+    let header = json!({
+        "qlog_version": "0.3",
+        "qlog_format": "JSON-SEQ",
+        "title": format!("wtransport-playground connection {}", id),
+        "trace": {
+            "vantage_point": { "type": "server" },
+            "common_fields": {
+                "time_format": "relative",
+                "reference_time": reference_time,
+            },
+        },
+    });
+    write_record(&mut out, &header).await?;
+
+    let remote = connection.remote_address();
+    let started_event = json!({
+        "ip_version": if remote.is_ipv4() { "ipv4" } else { "ipv6" },
+        "dst_ip": remote.ip().to_string(),
+        "dst_port": remote.port(),
+    });
+    write_event(
+        &mut out,
+        started,
+        "transport:connection_started",
+        started_event,
+    )
+    .await?;
+
+    let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+    let mut last_metrics = Value::Null;
+    let mut last_path = Value::Null;
+    let closed = loop {
+        tokio::select! {
+            error = connection.closed() => break error,
+            _ = interval.tick() => {}
+        }
+
+        let stats = connection.quic_connection().stats();
+        let metrics = json!({
+            "min_rtt": ms(stats.path.min_rtt),
+            "smoothed_rtt": ms(stats.path.rtt),
+            "congestion_window": stats.path.cwnd,
+        });
+        if metrics != last_metrics {
+            write_event(
+                &mut out,
+                started,
+                "recovery:metrics_updated",
+                metrics.clone(),
+            )
+            .await?;
+            last_metrics = metrics;
+        }
+
+        let path_stats = json!({
+            "sent_packets": stats.path.sent_packets,
+            "lost_packets": stats.path.lost_packets,
+            "lost_bytes": stats.path.lost_bytes,
+            "congestion_events": stats.path.congestion_events,
+            "current_mtu": stats.path.current_mtu,
+            "udp_tx_bytes": stats.udp_tx.bytes,
+            "udp_rx_bytes": stats.udp_rx.bytes,
+        });
+        if path_stats != last_path {
+            write_event(
+                &mut out,
+                started,
+                "playground:path_stats",
+                path_stats.clone(),
+            )
+            .await?;
+            last_path = path_stats;
+        }
+        out.flush().await?;
+    };
+
+    let owner = match closed {
+        ConnectionError::LocallyClosed | ConnectionError::LocalH3Error(_) => "local",
+        ConnectionError::ConnectionClosed(_) | ConnectionError::ApplicationClosed(_) => "remote",
+        _ => "unknown",
+    };
+    let closed_event = json!({ "owner": owner, "reason": closed.to_string() });
+    write_event(
+        &mut out,
+        started,
+        "transport:connection_closed",
+        closed_event,
+    )
+    .await?;
+    out.flush().await?;
+    Ok(())
+}
+
+async fn write_event(
+    out: &mut BufWriter<File>,
+    started: Instant,
+    name: &str,
+    data: Value,
+) -> Result<()> {
+    let event = json!({ "time": ms(started.elapsed()), "name": name, "data": data });
+    write_record(out, &event).await
+}
+
+async fn write_record(out: &mut BufWriter<File>, record: &Value) -> Result<()> {
+    out.write_u8(RS).await?;
+    out.write_all(&serde_json::to_vec(record)?).await?;
+    out.write_u8(b'\n').await?;
+    Ok(())
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
@@ -0,0 +1,85 @@
+use crate::registry::ConnectionId;
+use crate::state::State;
+use protocol::{CloseCode, Frame, Framing};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+use wtransport::{Connection, VarInt};
+
+/// How often each connection's traffic is checked against `quota.bytes`; it
+/// may overshoot the cap by what it moves in this long.
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long sending a warning may take before it's given up on.
+const WARNING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Bytes `connection` has sent and received, as UDP payload with QUIC and
+/// HTTP/3 overhead included: what a metered link would bill.
+pub fn used(connection: &Connection) -> u64 {
+    let stats = connection.quic_connection().stats();
+    stats.udp_tx.bytes + stats.udp_rx.bytes
+}
+
+// Hold the connection to `quota.bytes`. Once it has used `quota.warn_ratio` of
+// them it's sent a `quota_warning`, once; a cap raised past its usage lets it
+// be warned again later. At the cap it's closed as `quota_exceeded`. Runs until
+// then or until aborted with the connection.
+pub async fn enforce(
+    state: Arc<State>,
+    id: ConnectionId,
+    connection: Connection,
+    framing: Framing,
+) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    let mut warned = false;
+    loop {
+        interval.tick().await;
+        let config = state.config.borrow().quota.clone();
+        if config.bytes == 0 {
+            continue;
+        }
+        let used = used(&connection);
+        if used >= config.bytes {
+            state.stats.quota.record(false);
+            warn!(
+                "Closing connection {}: used {} of its {} byte quota",
+                id, used, config.bytes
+            );
+            let reason = format!("used {} of {} bytes", used, config.bytes);
+            connection.close(
+                VarInt::from_u32(CloseCode::QuotaExceeded.code()),
+                reason.as_bytes(),
+            );
+            return;
+        }
+        let warn_at = (config.bytes as f64 * config.warn_ratio) as u64;
+        if used < warn_at {
+            warned = false;
+            continue;
+        }
+        if warned {
+            continue;
+        }
+        warned = true;
+        state.stats.quota.record(true);
+        info!(
+            "Connection {} used {} of its {} byte quota",
+            id, used, config.bytes
+        );
+        let warning = Frame::QuotaWarning {
+            used,
+            limit: config.bytes,
+        };
+        let sent = timeout(WARNING_TIMEOUT, async {
+            let mut stream = connection.open_uni().await?.await?;
+            stream.write_all(&framing.encode(&warning)).await?;
+            stream.finish().await?;
+            anyhow::Ok(())
+        })
+        .await;
+        if let Ok(Err(e)) = sent {
+            debug!("Failed to warn connection {} of its quota: {}", id, e);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use crate::admin::AdminEvent;
+use crate::state::State;
+use protocol::{CloseCode, Frame};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+use wtransport::endpoint::endpoint_side::Server;
+use wtransport::{Endpoint, VarInt};
+
+/// How long redirected sessions get to reconnect before they're closed.
+const GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+// Retire the endpoint `shard` just moved off: refuse new connections on it,
+// redirect every session it accepted on `from_port` to the URL it connected with
+// but on `to_port`, and close whichever are still there after GRACE_PERIOD.
+// The endpoint (and its socket) is dropped once its connections are gone.
+pub async fn drain(
+    endpoint: Endpoint<Server>,
+    shard: usize,
+    from_port: u16,
+    to_port: u16,
+    state: Arc<State>,
+) {
+    let sessions = state.registry.on_endpoint(from_port, shard);
+    info!(
+        "Shard {} moved from port {} to {}; redirecting {} sessions",
+        shard,
+        from_port,
+        to_port,
+        sessions.len()
+    );
+    state.admin.publish(AdminEvent::EndpointMoved {
+        shard,
+        from_port,
+        to_port,
+        redirected: sessions.len(),
+    });
+
+    let mut closed = JoinSet::new();
+    for (id, connection) in sessions {
+        let framing = state.registry.framing(id);
+        let url = state.registry.url(id).unwrap_or_default();
+        let redirect = Frame::Redirect {
+            url: with_port(&url, to_port),
+        };
+        if let Err(e) = State::push_frame(&connection, framing, &redirect).await {
+            warn!("Failed to redirect connection {}: {}", id, e);
+        }
+        closed.spawn(async move { connection.closed().await });
+    }
+
+    let grace = tokio::time::sleep(GRACE_PERIOD);
+    tokio::pin!(grace);
+    loop {
+        tokio::select! {
+            incoming = endpoint.accept() => incoming.refuse(),
+            left = closed.join_next() => {
+                if left.is_none() {
+                    break;
+                }
+            }
+            _ = &mut grace => break,
+        }
+    }
+
+    // Includes any session that finished its handshake here after the redirects went out
+    let stragglers = state.registry.on_endpoint(from_port, shard);
+    if !stragglers.is_empty() {
+        info!(
+            "Closing {} sessions still on port {}",
+            stragglers.len(),
+            from_port
+        );
+    }
+    let reason = format!("server moved to port {}", to_port);
+    for (_, connection) in stragglers {
+        connection.close(
+            VarInt::from_u32(CloseCode::Redirected.code()),
+            reason.as_bytes(),
+        );
+    }
+    endpoint.wait_idle().await;
+    info!("Endpoint on port {} closed", from_port);
+}
+
+// `url` with its port replaced, e.g. https://localhost:8765/?room=blue on 8766
+// becomes https://localhost:8766/?room=blue
+fn with_port(url: &str, port: u16) -> String {
+    let rest = url.strip_prefix("https://").unwrap_or(url);
+    let (authority, path) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+    // The host ends at the last ':' unless that's inside an IPv6 literal
+    let host = match authority.rsplit_once(':') {
+        Some((host, _)) if !authority.ends_with(']') => host,
+        _ => authority,
+    };
+    format!("https://{}:{}{}", host, port, path)
+}
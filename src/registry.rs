@@ -0,0 +1,261 @@
+use crate::geoip::Location;
+use crate::metadata::{Metadata, TagFilter};
+use crate::quota;
+use crate::tls::Tls;
+use protocol::Framing;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use wtransport::Connection;
+
+/// Identifier assigned to each accepted session, in accept order starting at 1.
+pub type ConnectionId = u64;
+
+struct Entry {
+    connection: Connection,
+    metadata: Metadata,
+    framing: Framing,
+    /// WebTransport port the session was accepted on.
+    port: u16,
+    /// Shard whose endpoint accepted the session.
+    shard: usize,
+    /// URL the client opened the session with.
+    url: String,
+    connected_at: Instant,
+    /// The client reported its page hidden and hasn't reported it visible since.
+    hidden: bool,
+    /// When the last heartbeat came and the interval it announced.
+    last_heartbeat: Option<(Instant, Duration)>,
+    /// Where the client connected from, if the GeoIP database knows.
+    location: Option<Location>,
+    /// What the TLS handshake negotiated, if it was read.
+    tls: Option<Tls>,
+}
+
+/// One live session, as listed by `/connections`.
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    pub id: ConnectionId,
+    pub remote_address: SocketAddr,
+    pub port: u16,
+    pub shard: usize,
+    pub connected_secs: u64,
+    /// Bytes sent and received so far, as counted against `quota.bytes`.
+    pub bytes_used: u64,
+    pub metadata: Metadata,
+    /// Whether the client last reported its page hidden.
+    pub hidden: bool,
+    /// Where the client connected from, with `--geoip-db`.
+    pub location: Option<Location>,
+    /// Negotiated TLS parameters and what the client offered.
+    pub tls: Option<Tls>,
+}
+
+// Live connections, shared between the accept loop and anything that needs to
+// act on sessions it didn't accept itself (e.g. the scenario engine).
+// Connection is cloneable, so we hand out clones instead of holding the lock.
+pub struct Registry {
+    next_id: AtomicU64,
+    connections: Mutex<HashMap<ConnectionId, Entry>>,
+    count: watch::Sender<usize>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            connections: Mutex::new(HashMap::new()),
+            count: watch::Sender::new(0),
+        }
+    }
+
+    pub fn register(
+        &self,
+        connection: Connection,
+        metadata: Metadata,
+        framing: Framing,
+        port: u16,
+        shard: usize,
+        url: String,
+    ) -> ConnectionId {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut connections = self.connections.lock().unwrap();
+        connections.insert(
+            id,
+            Entry {
+                connection,
+                metadata,
+                framing,
+                port,
+                shard,
+                url,
+                connected_at: Instant::now(),
+                hidden: false,
+                last_heartbeat: None,
+                location: None,
+                tls: None,
+            },
+        );
+        self.count.send_replace(connections.len());
+        id
+    }
+
+    pub fn unregister(&self, id: ConnectionId) {
+        let mut connections = self.connections.lock().unwrap();
+        connections.remove(&id);
+        self.count.send_replace(connections.len());
+    }
+
+    pub fn get(&self, id: ConnectionId) -> Option<Connection> {
+        self.connections
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|entry| entry.connection.clone())
+    }
+
+    /// How frames are delimited on the connection's streams.
+    pub fn framing(&self, id: ConnectionId) -> Framing {
+        self.connections
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|entry| entry.framing)
+            .unwrap_or_default()
+    }
+
+    /// Record the client's page visibility; false if it was already so (or the
+    /// connection is gone).
+    pub fn set_hidden(&self, id: ConnectionId, hidden: bool) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        match connections.get_mut(&id) {
+            Some(entry) if entry.hidden != hidden => {
+                entry.hidden = hidden;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Live connections whose client reported its page hidden.
+    pub fn hidden_count(&self) -> usize {
+        let connections = self.connections.lock().unwrap();
+        connections.values().filter(|entry| entry.hidden).count()
+    }
+
+    /// Record a heartbeat announcing the next one in `interval`. Returns the
+    /// time since the previous heartbeat and the interval that one announced.
+    pub fn heartbeat(&self, id: ConnectionId, interval: Duration) -> Option<(Duration, Duration)> {
+        let mut connections = self.connections.lock().unwrap();
+        let entry = connections.get_mut(&id)?;
+        let now = Instant::now();
+        entry
+            .last_heartbeat
+            .replace((now, interval))
+            .map(|(last, announced)| (now.duration_since(last), announced))
+    }
+
+    /// Snapshot of every live connection, sorted by id.
+    pub fn all(&self) -> Vec<(ConnectionId, Connection)> {
+        let connections = self.connections.lock().unwrap();
+        let mut all: Vec<_> = connections
+            .iter()
+            .map(|(id, entry)| (*id, entry.connection.clone()))
+            .collect();
+        all.sort_by_key(|(id, _)| *id);
+        all
+    }
+
+    /// The value of session tag `key`, if the connection has it.
+    pub fn tag(&self, id: ConnectionId, key: &str) -> Option<String> {
+        self.connections
+            .lock()
+            .unwrap()
+            .get(&id)
+            .and_then(|entry| entry.metadata.get(key).cloned())
+    }
+
+    /// Set session tag `key` to `value`, e.g. a nickname carried over from a
+    /// resumed session.
+    pub fn set_tag(&self, id: ConnectionId, key: &str, value: &str) {
+        if let Some(entry) = self.connections.lock().unwrap().get_mut(&id) {
+            entry.metadata.insert(key.to_string(), value.to_string());
+        }
+    }
+
+    /// Record where the client connected from.
+    pub fn set_location(&self, id: ConnectionId, location: Location) {
+        if let Some(entry) = self.connections.lock().unwrap().get_mut(&id) {
+            entry.location = Some(location);
+        }
+    }
+
+    /// Record what the session's TLS handshake negotiated.
+    pub fn set_tls(&self, id: ConnectionId, tls: Tls) {
+        if let Some(entry) = self.connections.lock().unwrap().get_mut(&id) {
+            entry.tls = Some(tls);
+        }
+    }
+
+    /// URL the client opened the session with, e.g. `https://localhost:8765/?room=blue`.
+    pub fn url(&self, id: ConnectionId) -> Option<String> {
+        self.connections
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|entry| entry.url.clone())
+    }
+
+    /// Live connections accepted on `port` by `shard`'s endpoint.
+    pub fn on_endpoint(&self, port: u16, shard: usize) -> Vec<(ConnectionId, Connection)> {
+        let connections = self.connections.lock().unwrap();
+        connections
+            .iter()
+            .filter(|(_, entry)| entry.port == port && entry.shard == shard)
+            .map(|(id, entry)| (*id, entry.connection.clone()))
+            .collect()
+    }
+
+    /// Live sessions matching every filter, sorted by id.
+    pub fn sessions(&self, filters: &[TagFilter]) -> Vec<SessionInfo> {
+        let connections = self.connections.lock().unwrap();
+        let mut sessions: Vec<_> = connections
+            .iter()
+            .filter(|(_, entry)| filters.iter().all(|filter| filter.matches(&entry.metadata)))
+            .map(|(id, entry)| SessionInfo {
+                id: *id,
+                remote_address: entry.connection.remote_address(),
+                port: entry.port,
+                shard: entry.shard,
+                connected_secs: entry.connected_at.elapsed().as_secs(),
+                bytes_used: quota::used(&entry.connection),
+                metadata: entry.metadata.clone(),
+                hidden: entry.hidden,
+                location: entry.location.clone(),
+                tls: entry.tls.clone(),
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.id);
+        sessions
+    }
+
+    /// Live connections accepted by each of `shards` shards.
+    pub fn per_shard(&self, shards: usize) -> Vec<usize> {
+        let mut counts = vec![0; shards];
+        for entry in self.connections.lock().unwrap().values() {
+            if let Some(count) = counts.get_mut(entry.shard) {
+                *count += 1;
+            }
+        }
+        counts
+    }
+
+    /// Watch the number of live connections.
+    pub fn count(&self) -> watch::Receiver<usize> {
+        self.count.subscribe()
+    }
+}
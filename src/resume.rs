@@ -0,0 +1,381 @@
+use crate::dedup::Seen;
+use crate::registry::ConnectionId;
+use crate::rooms;
+use crate::state::State;
+use protocol::room::RelayTransport;
+use protocol::{CloseCode, Frame, PlaygroundError};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+use wtransport::VarInt;
+
+// A closed session waiting to be resumed
+struct Parked {
+    rooms: Vec<String>,
+    /// Transports it chose for itself in its rooms.
+    transports: HashMap<String, RelayTransport>,
+    nick: Option<String>,
+    /// Relays to its rooms since it closed, oldest first, marked replayed.
+    queue: VecDeque<Frame>,
+    /// Relays dropped from the front of a full queue.
+    dropped: u64,
+    /// Ids of the messages it sent, so resending them after resuming is safe.
+    seen: Seen,
+    expires_at: Instant,
+}
+
+/// Running totals since the server started.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Totals {
+    /// Sessions parked as they closed, and of those, resumed and expired.
+    pub parked: u64,
+    pub resumed: u64,
+    pub expired: u64,
+    /// Live sessions closed because another connection resumed them.
+    pub taken_over: u64,
+    /// Messages queued for parked sessions, and replayed after a resume.
+    pub queued: u64,
+    pub replayed: u64,
+    /// Messages dropped because a session's queue was full, and ones still
+    /// queued when its session expired.
+    pub dropped_full: u64,
+    pub dropped_expired: u64,
+}
+
+/// Resumption figures for `/stats`.
+#[derive(Debug, Serialize)]
+pub struct ResumeReport {
+    /// Closed sessions waiting to be resumed.
+    pub parked_sessions: usize,
+    /// Messages queued across them, and in the longest queue.
+    pub queue_depth: usize,
+    pub largest_queue: usize,
+    pub totals: Totals,
+}
+
+/// One parked session, as dumped by `/admin/dump-state`. Its token stays out.
+#[derive(Debug, Serialize)]
+pub struct ParkedSession {
+    pub rooms: Vec<String>,
+    pub nick: Option<String>,
+    pub queued: usize,
+    pub dropped: u64,
+    pub expires_in_ms: u64,
+}
+
+#[derive(Default)]
+struct Sessions {
+    /// Token of each live connection that was issued one.
+    tokens: HashMap<ConnectionId, String>,
+    /// Live connection each outstanding token was issued to.
+    live: HashMap<String, ConnectionId>,
+    parked: HashMap<String, Parked>,
+    totals: Totals,
+}
+
+impl Sessions {
+    // Forget parked sessions whose grace period ran out
+    fn purge(&mut self) {
+        let now = Instant::now();
+        let totals = &mut self.totals;
+        self.parked.retain(|_, parked| {
+            let keep = parked.expires_at > now;
+            if !keep {
+                debug!(
+                    "Parked session in {} room(s) expired with {} queued message(s)",
+                    parked.rooms.len(),
+                    parked.queue.len()
+                );
+                totals.expired += 1;
+                totals.dropped_expired += parked.queue.len() as u64;
+            }
+            keep
+        });
+    }
+}
+
+// What a resumed session gets back
+struct Taken {
+    rooms: Vec<String>,
+    transports: HashMap<String, RelayTransport>,
+    nick: Option<String>,
+    queue: VecDeque<Frame>,
+    dropped: u64,
+    seen: Seen,
+}
+
+// Session tokens and the sessions waiting under them. Each accepted connection
+// is issued a token in its hello. When it closes, its rooms and nickname are
+// parked under the token for `resume.grace_secs`, queueing what's relayed to
+// those rooms meanwhile (up to `resume.max_queued`), until a connection
+// presents the token in a `resume` frame and the queue is replayed to it. A live connection's token takes it over instead, closing it as
+// `taken_over`. Tokens are single-use: the resuming connection has its own.
+pub struct Resumption {
+    sessions: Mutex<Sessions>,
+}
+
+impl Resumption {
+    pub fn new() -> Self {
+        Self {
+            sessions: Mutex::new(Sessions::default()),
+        }
+    }
+
+    /// Issue `connection` a fresh token, unless `resume.grace_secs` is 0.
+    pub fn issue(&self, state: &State, connection: ConnectionId) -> Option<String> {
+        if state.config.borrow().resume.grace_secs == 0 {
+            return None;
+        }
+        let token = format!("{:032x}", rand::random::<u128>());
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.tokens.insert(connection, token.clone());
+        sessions.live.insert(token.clone(), connection);
+        Some(token)
+    }
+
+    /// The token `connection` was issued, if any.
+    pub fn token(&self, connection: ConnectionId) -> Option<String> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .tokens
+            .get(&connection)
+            .cloned()
+    }
+
+    /// Queue a message relayed to `room` for every session parked in it, as
+    /// replayed, dropping the oldest past `resume.max_queued`.
+    pub fn hold(&self, state: &State, room: &str, frame: &Frame) {
+        let max_queued = state.config.borrow().resume.max_queued;
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.purge();
+        let Sessions { parked, totals, .. } = &mut *sessions;
+        let mut replay = None;
+        for parked in parked.values_mut() {
+            if !parked.rooms.iter().any(|r| r == room) {
+                continue;
+            }
+            let replay = replay.get_or_insert_with(|| {
+                let mut replay = frame.clone();
+                if let Frame::Relay { replayed, .. } = &mut replay {
+                    *replayed = true;
+                }
+                replay
+            });
+            parked.queue.push_back(replay.clone());
+            totals.queued += 1;
+            while parked.queue.len() > max_queued {
+                parked.queue.pop_front();
+                parked.dropped += 1;
+                totals.dropped_full += 1;
+            }
+        }
+    }
+
+    /// Every session waiting to be resumed, soonest to expire first.
+    pub fn parked(&self) -> Vec<ParkedSession> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.purge();
+        let now = Instant::now();
+        let mut parked: Vec<_> = sessions
+            .parked
+            .values()
+            .map(|parked| ParkedSession {
+                rooms: parked.rooms.clone(),
+                nick: parked.nick.clone(),
+                queued: parked.queue.len(),
+                dropped: parked.dropped,
+                expires_in_ms: parked.expires_at.duration_since(now).as_millis() as u64,
+            })
+            .collect();
+        parked.sort_by_key(|parked| parked.expires_in_ms);
+        parked
+    }
+
+    pub fn report(&self) -> ResumeReport {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.purge();
+        let depths = sessions.parked.values().map(|parked| parked.queue.len());
+        ResumeReport {
+            parked_sessions: sessions.parked.len(),
+            queue_depth: depths.clone().sum(),
+            largest_queue: depths.max().unwrap_or(0),
+            totals: sessions.totals,
+        }
+    }
+}
+
+/// Park a closing connection's rooms, nickname and message ids under its
+/// token, for `resume.grace_secs`. Call before it's taken out of its rooms.
+pub fn park(state: &State, connection: ConnectionId) {
+    let grace = Duration::from_secs(state.config.borrow().resume.grace_secs);
+    let rooms = state.rooms.rooms_of(connection);
+    let transports = state.rooms.transports_of(connection);
+    let nick = state.registry.tag(connection, "nick");
+    let seen = state.dedup.take(connection);
+    let mut sessions = state.resume.sessions.lock().unwrap();
+    // Already taken over, or never issued a token
+    let Some(token) = sessions.tokens.remove(&connection) else {
+        return;
+    };
+    sessions.live.remove(&token);
+    if grace.is_zero() {
+        return;
+    }
+    debug!(
+        "Parking connection {} in {} room(s) for {:?}",
+        connection,
+        rooms.len(),
+        grace
+    );
+    sessions.totals.parked += 1;
+    sessions.parked.insert(
+        token,
+        Parked {
+            rooms,
+            transports,
+            nick,
+            queue: VecDeque::new(),
+            dropped: 0,
+            seen,
+            expires_at: Instant::now() + grace,
+        },
+    );
+}
+
+/// Move the session `token` was issued to onto `connection`: rejoin its rooms,
+/// take its nickname and replay the messages queued for it, closing the session
+/// first if it's still live. Returns the `resumed` frame to answer with.
+pub fn resume(
+    state: &State,
+    connection: ConnectionId,
+    token: &str,
+) -> Result<Frame, PlaygroundError> {
+    let (mut taken, previous) = take(state, connection, token)?;
+
+    let mut rooms = Vec::with_capacity(taken.rooms.len());
+    for room in taken.rooms {
+        let transport = taken.transports.get(&room).copied();
+        match rooms::join(state, connection, &room, transport) {
+            Ok(()) => rooms.push(room),
+            Err(error) => warn!(
+                "Connection {} not rejoined to room '{}': {}",
+                connection, room, error
+            ),
+        }
+    }
+    if let Some(nick) = &taken.nick {
+        state.registry.set_tag(connection, "nick", nick);
+    }
+    state.dedup.restore(connection, taken.seen);
+    match previous {
+        Some(previous) => info!(
+            "Connection {} took over connection {} in {} room(s)",
+            connection,
+            previous,
+            rooms.len()
+        ),
+        None => info!(
+            "Connection {} resumed a session in {} room(s), replaying {} message(s), {} dropped",
+            connection,
+            rooms.len(),
+            taken.queue.len(),
+            taken.dropped
+        ),
+    }
+
+    let missed = taken.queue.len() as u64;
+    if !taken.queue.is_empty()
+        && let Some(transport) = state.registry.get(connection)
+    {
+        let framing = state.registry.framing(connection);
+        let sent_us = state.clock_us(Instant::now());
+        for frame in &mut taken.queue {
+            frame.stamp_sent(sent_us);
+        }
+        tokio::spawn(async move {
+            let sent = async {
+                let mut stream = transport.open_uni().await?.await?;
+                for frame in &taken.queue {
+                    stream.write_all(&framing.encode(frame)).await?;
+                }
+                stream.finish().await?;
+                anyhow::Ok(())
+            };
+            if let Err(e) = sent.await {
+                warn!(
+                    "Failed to replay queued messages to connection {}: {}",
+                    connection, e
+                );
+            }
+        });
+    }
+
+    Ok(Frame::Resumed {
+        rooms,
+        nick: taken.nick,
+        missed,
+        dropped: taken.dropped,
+    })
+}
+
+// Take the session under `token`: a parked one, or a live one other than
+// `connection`, which is taken out of its rooms and closed. Also returns the
+// live connection taken over, if any.
+fn take(
+    state: &State,
+    connection: ConnectionId,
+    token: &str,
+) -> Result<(Taken, Option<ConnectionId>), PlaygroundError> {
+    let mut sessions = state.resume.sessions.lock().unwrap();
+    sessions.purge();
+    if let Some(parked) = sessions.parked.remove(token) {
+        sessions.totals.resumed += 1;
+        sessions.totals.replayed += parked.queue.len() as u64;
+        let taken = Taken {
+            rooms: parked.rooms,
+            transports: parked.transports,
+            nick: parked.nick,
+            queue: parked.queue,
+            dropped: parked.dropped,
+            seen: parked.seen,
+        };
+        return Ok((taken, None));
+    }
+
+    let previous = match sessions.live.get(token) {
+        Some(&previous) if previous != connection => previous,
+        Some(_) => {
+            let error = "can't resume the session the token was issued to".to_string();
+            return Err(PlaygroundError::InvalidParams(error));
+        }
+        None => {
+            let error = "unknown or expired session token".to_string();
+            return Err(PlaygroundError::InvalidParams(error));
+        }
+    };
+    sessions.live.remove(token);
+    sessions.tokens.remove(&previous);
+    sessions.totals.taken_over += 1;
+    drop(sessions);
+
+    let taken = Taken {
+        rooms: state.rooms.rooms_of(previous),
+        transports: state.rooms.transports_of(previous),
+        nick: state.registry.tag(previous, "nick"),
+        queue: VecDeque::new(),
+        dropped: 0,
+        seen: state.dedup.take(previous),
+    };
+    rooms::remove(state, previous);
+    if let Some(transport) = state.registry.get(previous) {
+        let reason = format!("resumed by connection {}", connection);
+        transport.close(
+            VarInt::from_u32(CloseCode::TakenOver.code()),
+            reason.as_bytes(),
+        );
+    }
+    Ok((taken, Some(previous)))
+}
@@ -0,0 +1,810 @@
+use crate::config::RoomQuotas;
+use crate::journal::{self, Record};
+use crate::limits::RateLimiter;
+use crate::middleware::TOKEN_TAG;
+use crate::registry::ConnectionId;
+use crate::state::State;
+use crate::storage;
+use protocol::room::{self, MAX_ROOMS, RelayTransport, RoomAction};
+use protocol::telemetry::ServerTiming;
+use protocol::{Frame, PlaygroundError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+/// Span of the `bytes_per_minute` quota's window.
+const BYTE_WINDOW: Duration = Duration::from_secs(60);
+
+/// How many room events a slow observer may fall behind before it starts missing them.
+const OBSERVER_CAPACITY: usize = 256;
+
+/// Room traffic mirrored to read-only observers following `/rooms/events`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RoomEvent {
+    Joined {
+        room: String,
+        connection: ConnectionId,
+    },
+    /// Also sent for each room of a connection that closed.
+    Left {
+        room: String,
+        connection: ConnectionId,
+    },
+    /// A message relayed to the room's members.
+    Message {
+        room: String,
+        from: ConnectionId,
+        nick: Option<String>,
+        text: String,
+    },
+    /// A member became the owner, or was kicked, muted or unmuted by it. A
+    /// kick is followed by the member's `left`.
+    Notice {
+        room: String,
+        action: RoomAction,
+        member: ConnectionId,
+        by: Option<ConnectionId>,
+    },
+}
+
+impl RoomEvent {
+    pub fn room(&self) -> &str {
+        match self {
+            Self::Joined { room, .. }
+            | Self::Left { room, .. }
+            | Self::Message { room, .. }
+            | Self::Notice { room, .. } => room,
+        }
+    }
+}
+
+/// What a room's owner can do to another member.
+#[derive(Debug, Clone, Copy)]
+pub enum Control {
+    Kick,
+    Mute,
+    Unmute,
+}
+
+impl Control {
+    fn action(self) -> RoomAction {
+        match self {
+            Control::Kick => RoomAction::Kicked,
+            Control::Mute => RoomAction::Muted,
+            Control::Unmute => RoomAction::Unmuted,
+        }
+    }
+}
+
+/// A `room_notice` due to the members of its room.
+pub struct Notice {
+    room: String,
+    action: RoomAction,
+    member: ConnectionId,
+    by: Option<ConnectionId>,
+    members: Vec<ConnectionId>,
+}
+
+/// A relayed message as kept in its room's history.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub at_unix_ms: u64,
+    pub from: ConnectionId,
+    pub nick: Option<String>,
+    pub text: String,
+}
+
+/// Relays and joins a room turned away for each quota.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Rejections {
+    pub messages_per_second: u64,
+    pub bytes_per_minute: u64,
+    pub max_members: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoomReport {
+    pub room: String,
+    pub members: usize,
+    /// Messages and bytes relayed since the room was created.
+    pub messages: u64,
+    pub bytes: u64,
+    pub rejected: Rejections,
+    pub owner: Option<ConnectionId>,
+    pub muted: Vec<ConnectionId>,
+    pub kicked: Vec<ConnectionId>,
+}
+
+struct Room {
+    members: BTreeSet<ConnectionId>,
+    /// The member that can kick and mute the others.
+    owner: Option<ConnectionId>,
+    /// Whether `rooms.owners` has a token for the room, so only its holder
+    /// owns it rather than whoever created it.
+    reserved: bool,
+    /// Members whose relays are turned away.
+    muted: BTreeSet<ConnectionId>,
+    /// Connections kicked out, kept out for as long as the room lasts.
+    kicked: BTreeSet<ConnectionId>,
+    /// How members that chose for themselves get relays.
+    transports: HashMap<ConnectionId, RelayTransport>,
+    limiter: RateLimiter,
+    /// When the current byte window started, and the bytes relayed in it.
+    window_started: Instant,
+    window_bytes: u64,
+    messages: u64,
+    bytes: u64,
+    rejected: Rejections,
+}
+
+impl Room {
+    fn new() -> Self {
+        Self {
+            members: BTreeSet::new(),
+            owner: None,
+            reserved: false,
+            muted: BTreeSet::new(),
+            kicked: BTreeSet::new(),
+            transports: HashMap::new(),
+            limiter: RateLimiter::new(),
+            window_started: Instant::now(),
+            window_bytes: 0,
+            messages: 0,
+            bytes: 0,
+            rejected: Rejections::default(),
+        }
+    }
+
+    // Count a `len`-byte message against the quotas, or say which it would exceed
+    fn admit(&mut self, name: &str, len: u64, quotas: &RoomQuotas) -> Result<(), PlaygroundError> {
+        if self.window_started.elapsed() >= BYTE_WINDOW {
+            self.window_started = Instant::now();
+            self.window_bytes = 0;
+        }
+        if quotas.bytes_per_minute > 0 && self.window_bytes + len > quotas.bytes_per_minute {
+            self.rejected.bytes_per_minute += 1;
+            return Err(PlaygroundError::QuotaExceeded(format!(
+                "room {:?} is over its quota of {} bytes per minute",
+                name, quotas.bytes_per_minute
+            )));
+        }
+        if !self.limiter.allow(quotas.messages_per_second) {
+            self.rejected.messages_per_second += 1;
+            return Err(PlaygroundError::QuotaExceeded(format!(
+                "room {:?} is over its quota of {} messages per second",
+                name, quotas.messages_per_second
+            )));
+        }
+
+        self.window_bytes += len;
+        self.messages += 1;
+        self.bytes += len;
+        Ok(())
+    }
+
+    fn choose(&mut self, member: ConnectionId, transport: Option<RelayTransport>) {
+        match transport {
+            Some(transport) => self.transports.insert(member, transport),
+            None => self.transports.remove(&member),
+        };
+    }
+
+    fn notice(
+        &self,
+        name: &str,
+        action: RoomAction,
+        member: ConnectionId,
+        by: Option<ConnectionId>,
+    ) -> Notice {
+        Notice {
+            room: name.to_string(),
+            action,
+            member,
+            by,
+            members: self.members.iter().copied().collect(),
+        }
+    }
+
+    // Make `member` the owner, if it may claim the room and doesn't own it yet
+    fn claim(&mut self, name: &str, member: ConnectionId, claim: bool) -> Option<Notice> {
+        if !claim || self.owner == Some(member) {
+            return None;
+        }
+        self.owner = Some(member);
+        self.muted.remove(&member);
+        Some(self.notice(name, RoomAction::Owner, member, None))
+    }
+
+    // Take `member` out, handing the room on to the longest-connected member,
+    // the lowest id, if it was the owner and the room has no owner token
+    fn depart(&mut self, name: &str, member: ConnectionId) -> Option<Notice> {
+        self.members.remove(&member);
+        self.transports.remove(&member);
+        self.muted.remove(&member);
+        if self.owner != Some(member) {
+            return None;
+        }
+        self.owner = match self.reserved {
+            true => None,
+            false => self.members.first().copied(),
+        };
+        Some(self.notice(name, RoomAction::Owner, self.owner?, None))
+    }
+}
+
+// Room membership and quotas. Unlike pipelines, messages are relayed one by one
+// as they arrive; a room, and its counters, owner, mutes and kicks, exist only
+// while it has members. Joins, leaves, relayed messages and notices are also
+// fanned out to observers, who watch over HTTP without being members. Methods
+// changing a room's owner or members' standing return the notices due, for the
+// free functions wrapping them to send.
+pub struct Rooms {
+    rooms: Mutex<HashMap<String, Room>>,
+    observers: broadcast::Sender<RoomEvent>,
+    /// For ids given to relays sent without one.
+    next_id: AtomicU64,
+}
+
+impl Rooms {
+    pub fn new() -> Self {
+        Self {
+            rooms: Mutex::new(HashMap::new()),
+            observers: broadcast::channel(OBSERVER_CAPACITY).0,
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Every room event from now on, in every room.
+    pub fn observe(&self) -> broadcast::Receiver<RoomEvent> {
+        self.observers.subscribe()
+    }
+
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    // Built only if someone is watching
+    fn mirror(&self, event: impl FnOnce() -> RoomEvent) {
+        if self.observers.receiver_count() > 0 {
+            let _ = self.observers.send(event());
+        }
+    }
+
+    /// Add `connection` to `room`, unless it's in too many rooms, `room` is
+    /// full under `quotas` or it was kicked out. It gets relays over
+    /// `transport`, or however the room relays if `None`; a member joining
+    /// again just changes that. It owns the room if it may `claim` it, which
+    /// also lets it back in after a kick, or if it created it and
+    /// `rooms.owners` has no token for the room.
+    pub fn join(
+        &self,
+        connection: ConnectionId,
+        room: &str,
+        transport: Option<RelayTransport>,
+        quotas: &RoomQuotas,
+        claim: bool,
+    ) -> Result<Option<Notice>, PlaygroundError> {
+        if !room::valid_room(room) {
+            return Err(PlaygroundError::InvalidParams(format!(
+                "room {:?} must be 1 to {} bytes",
+                room,
+                room::MAX_ROOM_LEN
+            )));
+        }
+
+        let mut rooms = self.rooms.lock().unwrap();
+        let joined = rooms
+            .values()
+            .filter(|r| r.members.contains(&connection))
+            .count();
+        let mut existing = rooms.get_mut(room);
+        if let Some(r) = existing.as_deref_mut()
+            && r.members.contains(&connection)
+        {
+            r.choose(connection, transport);
+            return Ok(r.claim(room, connection, claim));
+        }
+        if joined >= MAX_ROOMS {
+            return Err(PlaygroundError::InvalidParams(format!(
+                "at most {} rooms per connection",
+                MAX_ROOMS
+            )));
+        }
+        if let Some(r) = existing.as_deref()
+            && r.kicked.contains(&connection)
+            && !claim
+        {
+            return Err(PlaygroundError::Unauthorized(format!(
+                "kicked out of room {:?}",
+                room
+            )));
+        }
+        if let Some(r) = existing
+            && quotas.max_members > 0
+            && r.members.len() >= quotas.max_members
+        {
+            r.rejected.max_members += 1;
+            return Err(PlaygroundError::QuotaExceeded(format!(
+                "room {:?} is full at {} members",
+                room, quotas.max_members
+            )));
+        }
+
+        let r = rooms.entry(room.to_string()).or_insert_with(Room::new);
+        r.members.insert(connection);
+        r.choose(connection, transport);
+        r.reserved = quotas.owners.contains_key(room);
+        self.mirror(|| RoomEvent::Joined {
+            room: room.to_string(),
+            connection,
+        });
+        let claim = claim || (r.owner.is_none() && !r.reserved);
+        Ok(r.claim(room, connection, claim))
+    }
+
+    pub fn leave(&self, connection: ConnectionId, room: &str) -> Option<Notice> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let r = rooms.get_mut(room)?;
+        let mut notice = None;
+        if r.members.contains(&connection) {
+            notice = r.depart(room, connection);
+            self.mirror(|| RoomEvent::Left {
+                room: room.to_string(),
+                connection,
+            });
+        }
+        if r.members.is_empty() {
+            rooms.remove(room);
+        }
+        notice
+    }
+
+    /// Take a closed connection out of every room.
+    pub fn remove(&self, connection: ConnectionId) -> Vec<Notice> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let mut notices = Vec::new();
+        rooms.retain(|name, r| {
+            if r.members.contains(&connection) {
+                notices.extend(r.depart(name, connection));
+                self.mirror(|| RoomEvent::Left {
+                    room: name.clone(),
+                    connection,
+                });
+            }
+            !r.members.is_empty()
+        });
+        notices
+    }
+
+    /// Have `by`, `room`'s owner, kick, mute or unmute `member`, another
+    /// member. A kicked member leaves the room, but still gets the notice.
+    pub fn control(
+        &self,
+        by: ConnectionId,
+        room: &str,
+        member: ConnectionId,
+        control: Control,
+    ) -> Result<Notice, PlaygroundError> {
+        let action = control.action();
+        let mut rooms = self.rooms.lock().unwrap();
+        let r = match rooms.get_mut(room) {
+            Some(r) if r.members.contains(&by) => r,
+            _ => {
+                return Err(PlaygroundError::InvalidParams(format!(
+                    "not in room {:?}",
+                    room
+                )));
+            }
+        };
+        if r.owner != Some(by) {
+            return Err(PlaygroundError::Unauthorized(format!(
+                "only the owner of room {:?} can have a member {}",
+                room,
+                action.as_str()
+            )));
+        }
+        if member == by {
+            return Err(PlaygroundError::InvalidParams(format!(
+                "the owner of room {:?} can't be {}",
+                room,
+                action.as_str()
+            )));
+        }
+        if !r.members.contains(&member) {
+            return Err(PlaygroundError::InvalidParams(format!(
+                "connection {} isn't in room {:?}",
+                member, room
+            )));
+        }
+
+        let notice = r.notice(room, action, member, Some(by));
+        match control {
+            Control::Kick => {
+                r.depart(room, member);
+                r.kicked.insert(member);
+                self.mirror(|| RoomEvent::Left {
+                    room: room.to_string(),
+                    connection: member,
+                });
+            }
+            Control::Mute => {
+                r.muted.insert(member);
+            }
+            Control::Unmute => {
+                r.muted.remove(&member);
+            }
+        }
+        Ok(notice)
+    }
+
+    /// Rooms `connection` is in, by name.
+    pub fn rooms_of(&self, connection: ConnectionId) -> Vec<String> {
+        let rooms = self.rooms.lock().unwrap();
+        let mut names: Vec<String> = rooms
+            .iter()
+            .filter(|(_, r)| r.members.contains(&connection))
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Transports `connection` chose for itself, by room.
+    pub fn transports_of(&self, connection: ConnectionId) -> HashMap<String, RelayTransport> {
+        let rooms = self.rooms.lock().unwrap();
+        rooms
+            .iter()
+            .filter_map(|(name, r)| Some((name.clone(), *r.transports.get(&connection)?)))
+            .collect()
+    }
+
+    /// Who a `len`-byte message from `connection` to `room` goes to, and how:
+    /// every other member, over the transport it chose or else the room's.
+    /// Only members may send to a room, and only within its `quotas`; a
+    /// message over one is counted in the room's rejections. Muted members'
+    /// messages go nowhere.
+    pub fn recipients(
+        &self,
+        connection: ConnectionId,
+        room: &str,
+        len: usize,
+        quotas: &RoomQuotas,
+    ) -> Result<Vec<(ConnectionId, RelayTransport)>, PlaygroundError> {
+        let mut rooms = self.rooms.lock().unwrap();
+        match rooms.get_mut(room) {
+            Some(r) if r.muted.contains(&connection) => Err(PlaygroundError::Unauthorized(
+                format!("muted in room {:?}", room),
+            )),
+            Some(r) if r.members.contains(&connection) => {
+                r.admit(room, len as u64, quotas)?;
+                let default = quotas.transport(room);
+                Ok(r.members
+                    .iter()
+                    .filter(|id| **id != connection)
+                    .map(|id| (*id, r.transports.get(id).copied().unwrap_or(default)))
+                    .collect())
+            }
+            _ => Err(PlaygroundError::InvalidParams(format!(
+                "not in room {:?}",
+                room
+            ))),
+        }
+    }
+
+    /// Every live room's members, traffic and rejections, by name.
+    pub fn report(&self) -> Vec<RoomReport> {
+        let rooms = self.rooms.lock().unwrap();
+        let mut report: Vec<RoomReport> = rooms
+            .iter()
+            .map(|(name, r)| RoomReport {
+                room: name.clone(),
+                members: r.members.len(),
+                messages: r.messages,
+                bytes: r.bytes,
+                rejected: r.rejected,
+                owner: r.owner,
+                muted: r.muted.iter().copied().collect(),
+                kicked: r.kicked.iter().copied().collect(),
+            })
+            .collect();
+        report.sort_by(|a, b| a.room.cmp(&b.room));
+        report
+    }
+}
+
+/// Add `connection` to `room` (see [`Rooms::join`]), claiming it if its
+/// `token` tag is the room's token in `rooms.owners`, and tell the members if
+/// it now owns the room.
+pub fn join(
+    state: &State,
+    connection: ConnectionId,
+    room: &str,
+    transport: Option<RelayTransport>,
+) -> Result<(), PlaygroundError> {
+    let config = state.config.borrow();
+    let claim = config
+        .rooms
+        .owners
+        .get(room)
+        .is_some_and(|token| state.registry.tag(connection, TOKEN_TAG).as_ref() == Some(token));
+    let joined = state
+        .rooms
+        .join(connection, room, transport, &config.rooms, claim);
+    drop(config);
+    notify(state, joined?);
+    journal::record(
+        state,
+        Record::Join {
+            id: connection,
+            room: room.to_string(),
+        },
+    );
+    Ok(())
+}
+
+/// Take `connection` out of `room`, telling the rest who owns it now if it did.
+pub fn leave(state: &State, connection: ConnectionId, room: &str) {
+    let notice = state.rooms.leave(connection, room);
+    notify(state, notice);
+    journal::record(
+        state,
+        Record::Leave {
+            id: connection,
+            room: room.to_string(),
+        },
+    );
+}
+
+/// Take a closed connection out of every room, as [`leave`] does.
+pub fn remove(state: &State, connection: ConnectionId) {
+    let notices = state.rooms.remove(connection);
+    notify(state, notices);
+}
+
+/// Have `by` kick, mute or unmute `member` in `room` (see [`Rooms::control`]),
+/// and tell the members.
+pub fn control(
+    state: &State,
+    by: ConnectionId,
+    room: &str,
+    member: ConnectionId,
+    control: Control,
+) -> Result<(), PlaygroundError> {
+    let notice = state.rooms.control(by, room, member, control)?;
+    info!(
+        "Connection {} had connection {} {} in room '{}'",
+        by,
+        member,
+        notice.action.as_str(),
+        room
+    );
+    notify(state, Some(notice));
+    if let Control::Kick = control {
+        journal::record(
+            state,
+            Record::Leave {
+                id: member,
+                room: room.to_string(),
+            },
+        );
+    }
+    Ok(())
+}
+
+// Send each notice's `room_notice` to the members it's due to, queued with
+// their other fanned-out frames, and mirror it to observers
+fn notify(state: &State, notices: impl IntoIterator<Item = Notice>) {
+    let queue_len = state.config.borrow().fanout.queue_len;
+    for notice in notices {
+        state.rooms.mirror(|| RoomEvent::Notice {
+            room: notice.room.clone(),
+            action: notice.action,
+            member: notice.member,
+            by: notice.by,
+        });
+        let frame = Arc::new(Frame::RoomNotice {
+            room: notice.room,
+            action: notice.action,
+            member: notice.member,
+            by: notice.by,
+        });
+        for id in notice.members {
+            state.fanout.send(id, frame.clone(), queue_len);
+        }
+    }
+}
+
+/// Relay `text` from `from` to the other members of `room`, tagged with the room
+/// and sender (and the sender's nickname, from its `nick` session tag), and
+/// hold it for sessions parked in the room. `message_id`, the sender's id for
+/// the message, goes along so recipients can drop copies. Each member gets it
+/// over its transport (see [`Rooms::recipients`]). Datagrams go only to members
+/// whose session negotiated them, and only if the relay fits in one; otherwise
+/// it goes on a stream, queued with the member's other fanned-out frames (see
+/// [`crate::fanout`]). Its `timing` starts at `received`, when the server read
+/// it, and is stamped with each copy's send time. Returns how many members it
+/// was sent to.
+pub fn relay(
+    state: &State,
+    from: ConnectionId,
+    room: String,
+    text: String,
+    message_id: Option<String>,
+    received: Instant,
+) -> Result<usize, PlaygroundError> {
+    let quotas = state.config.borrow().rooms.clone();
+    let recipients = match state.rooms.recipients(from, &room, text.len(), &quotas) {
+        Ok(recipients) => recipients,
+        Err(error) => {
+            debug!(
+                "Connection {} not relayed to room '{}': {}",
+                from, room, error
+            );
+            return Err(error);
+        }
+    };
+    let nick = state.registry.tag(from, "nick");
+    remember(state, &room, from, &nick, &text);
+    state.rooms.mirror(|| RoomEvent::Message {
+        room: room.clone(),
+        from,
+        nick: nick.clone(),
+        text: text.clone(),
+    });
+    // Members getting both copies drop the second by id, so then it needs one
+    let message_id = message_id.or_else(|| {
+        recipients
+            .iter()
+            .any(|(_, transport)| *transport == RelayTransport::Both)
+            .then(|| format!("relay-{}-{}", from, state.rooms.next_id()))
+    });
+    let mut frame = Frame::Relay {
+        room: room.clone(),
+        from: Some(from),
+        nick,
+        text,
+        replayed: false,
+        id: message_id,
+        timing: Some(ServerTiming::received(state.clock_us(received))),
+    };
+    state.resume.hold(state, &room, &frame);
+
+    let queue_len = state.config.borrow().fanout.queue_len;
+    // Datagrams go out now; stream copies are stamped again as they're sent
+    frame.stamp_sent(state.clock_us(Instant::now()));
+    let datagram = recipients
+        .iter()
+        .any(|(_, transport)| transport.uses_datagrams())
+        .then(|| room::encode_datagram(&frame));
+    let frame = Arc::new(frame);
+    for &(id, transport) in &recipients {
+        let Some(connection) = state.registry.get(id) else {
+            continue;
+        };
+        if let Some(datagram) = &datagram
+            && transport.uses_datagrams()
+        {
+            let fits = connection
+                .max_datagram_size()
+                .is_some_and(|max| datagram.len() <= max);
+            if fits {
+                state.send_datagram(id, &connection, datagram);
+                state.stats.room_relays.datagram();
+                if transport == RelayTransport::Datagram {
+                    continue;
+                }
+            } else {
+                debug!(
+                    "Relaying to connection {} on a stream: {}-byte relay datagram, max {:?}",
+                    id,
+                    datagram.len(),
+                    connection.max_datagram_size()
+                );
+                state.stats.room_relays.fallback();
+            }
+        }
+        state.stats.room_relays.stream();
+        state.fanout.send(id, frame.clone(), queue_len);
+    }
+    Ok(recipients.len())
+}
+
+// Storage log key of `room`'s history
+fn history_key(room: &str) -> String {
+    storage::name_key("rooms", room)
+}
+
+// Queue a relayed message for the room's history, unless `storage.room_history`
+// is 0. A storage failure is logged and the message relayed anyway.
+fn remember(state: &State, room: &str, from: ConnectionId, nick: &Option<String>, text: &str) {
+    let keep = state.config.borrow().storage.room_history;
+    if keep == 0 {
+        return;
+    }
+    let entry = HistoryEntry {
+        at_unix_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        from,
+        nick: nick.clone(),
+        text: text.to_string(),
+    };
+    let result = serde_json::to_vec(&entry)
+        .map_err(anyhow::Error::from)
+        .and_then(|record| state.storage_writer.append(history_key(room), record, keep));
+    if let Err(e) = result {
+        warn!("Failed to keep history of room '{}': {:#}", room, e);
+    }
+}
+
+/// The last `limit` messages relayed to `room`, oldest first, from whichever
+/// backend `storage.backend` names, so they may predate a restart.
+pub fn history(state: &State, room: &str, limit: usize) -> anyhow::Result<Vec<HistoryEntry>> {
+    state
+        .storage
+        .tail(&history_key(room), limit)?
+        .iter()
+        .map(|record| Ok(serde_json::from_slice(record)?))
+        .collect()
+}
+
+/// Names of the rooms with history, sorted by their encoding.
+pub fn rooms_with_history(state: &State) -> anyhow::Result<Vec<String>> {
+    let keys = state.storage.logs("rooms/")?;
+    Ok(keys
+        .iter()
+        .filter_map(|key| storage::key_name("rooms", key))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owner(rooms: &Rooms, room: &str) -> Option<ConnectionId> {
+        rooms
+            .report()
+            .into_iter()
+            .find(|r| r.room == room)
+            .and_then(|r| r.owner)
+    }
+
+    #[test]
+    fn token_room_is_not_claimed_by_tokenless_joiner() {
+        let mut quotas = RoomQuotas::default();
+        quotas.owners.insert("vip".into(), "secret".into());
+        let rooms = Rooms::new();
+
+        assert!(
+            rooms
+                .join(1, "vip", None, &quotas, false)
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(owner(&rooms, "vip"), None);
+
+        rooms.join(2, "vip", None, &quotas, true).unwrap();
+        assert_eq!(owner(&rooms, "vip"), Some(2));
+
+        assert!(rooms.leave(2, "vip").is_none());
+        assert_eq!(owner(&rooms, "vip"), None);
+    }
+
+    #[test]
+    fn open_room_passes_to_next_member() {
+        let quotas = RoomQuotas::default();
+        let rooms = Rooms::new();
+        rooms.join(1, "lobby", None, &quotas, false).unwrap();
+        rooms.join(2, "lobby", None, &quotas, false).unwrap();
+        assert_eq!(owner(&rooms, "lobby"), Some(1));
+
+        rooms.leave(1, "lobby");
+        assert_eq!(owner(&rooms, "lobby"), Some(2));
+    }
+}
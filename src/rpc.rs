@@ -0,0 +1,183 @@
+use crate::drip;
+use crate::registry::ConnectionId;
+use crate::state::State;
+use protocol::PlaygroundError;
+use protocol::rpc::{self, Outcome};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// What a handler knows about the call it is serving.
+pub struct Call<'a> {
+    pub state: &'a State,
+    pub connection: ConnectionId,
+}
+
+type Handler = Box<dyn Fn(&Call, Value) -> Result<Value, PlaygroundError> + Send + Sync>;
+
+// Method name -> handler. Handlers are written against the typed params/results
+// from the protocol crate; the router does the JSON conversion on both sides.
+pub struct Router {
+    handlers: HashMap<&'static str, Handler>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self {
+            handlers: HashMap::new(),
+        }
+    }
+
+    pub fn register<P, R, F>(&mut self, method: &'static str, handler: F)
+    where
+        P: DeserializeOwned,
+        R: Serialize,
+        F: Fn(&Call, P) -> Result<R, PlaygroundError> + Send + Sync + 'static,
+    {
+        let handler = move |call: &Call, params: Value| {
+            // Absent params arrive as null, which should mean "all defaults"
+            let params = match params {
+                Value::Null => Value::Object(Default::default()),
+                params => params,
+            };
+            let params = serde_json::from_value(params)
+                .map_err(|e| PlaygroundError::InvalidParams(e.to_string()))?;
+            let result = handler(call, params)?;
+            serde_json::to_value(result).map_err(|e| PlaygroundError::Internal(e.to_string()))
+        };
+        self.handlers.insert(method, Box::new(handler));
+    }
+
+    pub fn dispatch(&self, call: &Call, method: &str, params: Value) -> Outcome {
+        let Some(handler) = self.handlers.get(method) else {
+            return Outcome::Error(PlaygroundError::MethodNotFound(method.to_string()));
+        };
+
+        match handler(call, params) {
+            Ok(result) => Outcome::Result(result),
+            Err(e) => Outcome::Error(e),
+        }
+    }
+
+    /// Router with the playground's built-in methods.
+    pub fn with_builtins() -> Self {
+        let mut router = Self::new();
+        router.register(rpc::ECHO, echo);
+        router.register(rpc::TIME, time);
+        router.register(rpc::STATS, stats);
+        router.register(rpc::ROLL_DICE, roll_dice);
+        router.register(rpc::REPORT_MTU, report_mtu);
+        router.register(rpc::HEARTBEAT, heartbeat);
+        router.register(rpc::REPORT_VISIBILITY, report_visibility);
+        router.register(rpc::DRIP, start_drip);
+        router
+    }
+}
+
+fn echo(_: &Call, params: rpc::EchoParams) -> Result<rpc::EchoResult, PlaygroundError> {
+    Ok(rpc::EchoResult { text: params.text })
+}
+
+fn time(_: &Call, _: Value) -> Result<rpc::TimeResult, PlaygroundError> {
+    let unix_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| PlaygroundError::Internal(e.to_string()))?
+        .as_millis() as u64;
+    Ok(rpc::TimeResult { unix_ms })
+}
+
+fn stats(call: &Call, _: Value) -> Result<rpc::StatsResult, PlaygroundError> {
+    Ok(rpc::StatsResult {
+        connection_id: call.connection,
+        connections: *call.state.registry.count().borrow(),
+        uptime_ms: call.state.started_at.elapsed().as_millis() as u64,
+    })
+}
+
+fn roll_dice(
+    _: &Call,
+    params: rpc::RollDiceParams,
+) -> Result<rpc::RollDiceResult, PlaygroundError> {
+    if !(2..=1000).contains(&params.sides) {
+        return Err(PlaygroundError::InvalidParams(
+            "sides must be between 2 and 1000".to_string(),
+        ));
+    }
+    if !(1..=100).contains(&params.count) {
+        return Err(PlaygroundError::InvalidParams(
+            "count must be between 1 and 100".to_string(),
+        ));
+    }
+
+    let rolls: Vec<u32> = (0..params.count)
+        .map(|_| rand::random_range(1..=params.sides))
+        .collect();
+    let total = rolls.iter().sum();
+    Ok(rpc::RollDiceResult { rolls, total })
+}
+
+fn report_mtu(call: &Call, params: rpc::MtuReport) -> Result<(), PlaygroundError> {
+    if params.max_datagram_size > u16::MAX as usize {
+        return Err(PlaygroundError::InvalidParams(
+            "max_datagram_size can't exceed 65535".to_string(),
+        ));
+    }
+
+    info!(
+        "Connection {} max datagram size: {} bytes",
+        call.connection, params.max_datagram_size
+    );
+    call.state
+        .stats
+        .max_datagram_size
+        .observe(params.max_datagram_size as u64);
+    Ok(())
+}
+
+fn heartbeat(call: &Call, params: rpc::HeartbeatParams) -> Result<(), PlaygroundError> {
+    let interval = Duration::from_millis(params.interval_ms as u64);
+    let late = call
+        .state
+        .registry
+        .heartbeat(call.connection, interval)
+        .filter(|(gap, announced)| *gap > *announced * 2);
+    if let Some((gap, announced)) = late {
+        info!(
+            "Connection {} heartbeat came after {} ms, expected {} ms; suspected background tab",
+            call.connection,
+            gap.as_millis(),
+            announced.as_millis()
+        );
+    }
+    call.state.stats.visibility.record_heartbeat(late.is_some());
+    Ok(())
+}
+
+fn report_visibility(call: &Call, params: rpc::VisibilityReport) -> Result<(), PlaygroundError> {
+    if call
+        .state
+        .registry
+        .set_hidden(call.connection, !params.visible)
+    {
+        info!(
+            "Connection {} page {}",
+            call.connection,
+            if params.visible { "visible" } else { "hidden" }
+        );
+        call.state.stats.visibility.record_change(params.visible);
+    }
+    Ok(())
+}
+
+fn start_drip(call: &Call, params: rpc::DripParams) -> Result<rpc::DripResult, PlaygroundError> {
+    let connection = call
+        .state
+        .registry
+        .get(call.connection)
+        .ok_or_else(|| PlaygroundError::Internal("connection not registered".to_string()))?;
+    let framing = call.state.registry.framing(call.connection);
+    drip::start(call.connection, connection, framing, params)
+}
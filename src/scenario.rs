@@ -0,0 +1,225 @@
+use crate::registry::ConnectionId;
+use crate::state::State;
+use anyhow::{Context, Result};
+use protocol::{CloseCode, Frame};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+use wtransport::VarInt;
+
+/// A scripted sequence of server actions, loaded from YAML:
+///
+/// ```yaml
+/// name: smoke
+/// steps:
+///   - wait_for_connections: { count: 2 }
+///   - broadcast: { message: "hello everyone" }
+///   - inject_loss: { rate: 0.5 }
+///   - wait: { ms: 3000 }
+///   - inject_loss: { rate: 0.0 }
+///   - open_stream_to_all: { message: "server-initiated stream" }
+///   - burst: { count: 50 }
+///   - redirect: { url: "https://localhost:8766/" }
+///   - close_connection: { id: 1, code: normal, reason: "scenario done" }
+/// ```
+#[derive(Debug, Deserialize)]
+pub struct Scenario {
+    #[serde(default)]
+    pub name: Option<String>,
+    // Steps are written as `- variant: { ... }` maps rather than serde_yaml's default `!variant` tags
+    #[serde(deserialize_with = "serde_yaml::with::singleton_map_recursive::deserialize")]
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Step {
+    /// Sleep for a fixed amount of time.
+    Wait { ms: u64 },
+    /// Block until at least `count` sessions are connected.
+    WaitForConnections {
+        count: usize,
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+    },
+    /// Send a datagram to every live connection.
+    Broadcast { message: String },
+    /// Set the outgoing datagram loss rate (0.0 disables it).
+    InjectLoss { rate: f64 },
+    /// Close one connection by id, by default as `kicked`.
+    CloseConnection {
+        id: ConnectionId,
+        #[serde(default = "default_close_code")]
+        code: CloseCode,
+        #[serde(default)]
+        reason: String,
+    },
+    /// Tell every live connection, or just `id`, to reconnect to `url`.
+    Redirect {
+        url: String,
+        #[serde(default)]
+        id: Option<ConnectionId>,
+    },
+    /// Open a unidirectional stream to every live connection and write `message` on it as a frame.
+    OpenStreamToAll { message: String },
+    /// Have every live connection send `count` datagrams at the same instant,
+    /// `lead_ms` from now, then collect arrivals for `collect_ms` and report.
+    Burst {
+        count: u32,
+        #[serde(default = "default_burst_payload")]
+        payload_bytes: usize,
+        #[serde(default = "default_burst_lead")]
+        lead_ms: u64,
+        #[serde(default = "default_burst_collect")]
+        collect_ms: u64,
+    },
+}
+
+fn default_close_code() -> CloseCode {
+    CloseCode::Kicked
+}
+
+fn default_burst_payload() -> usize {
+    256
+}
+
+fn default_burst_lead() -> u64 {
+    1000
+}
+
+fn default_burst_collect() -> u64 {
+    2000
+}
+
+impl Scenario {
+    pub async fn load(path: &Path) -> Result<Self> {
+        let text = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read scenario {}", path.display()))?;
+        serde_yaml::from_str(&text).with_context(|| format!("Invalid scenario {}", path.display()))
+    }
+
+    pub async fn run(&self, state: Arc<State>) -> Result<()> {
+        let name = self.name.as_deref().unwrap_or("unnamed");
+        info!("Running scenario '{}' ({} steps)", name, self.steps.len());
+
+        for (index, step) in self.steps.iter().enumerate() {
+            info!("Scenario step {}: {:?}", index + 1, step);
+            step.run(&state)
+                .await
+                .with_context(|| format!("Scenario step {} failed", index + 1))?;
+        }
+
+        info!("Scenario '{}' finished", name);
+        Ok(())
+    }
+}
+
+impl Step {
+    pub async fn run(&self, state: &State) -> Result<()> {
+        match self {
+            Step::Wait { ms } => {
+                tokio::time::sleep(Duration::from_millis(*ms)).await;
+            }
+            Step::WaitForConnections { count, timeout_ms } => {
+                let mut live = state.registry.count();
+                let wait = live.wait_for(|live| *live >= *count);
+                match timeout_ms {
+                    Some(ms) => {
+                        tokio::time::timeout(Duration::from_millis(*ms), wait)
+                            .await
+                            .context("Timed out waiting for connections")??;
+                    }
+                    None => {
+                        wait.await?;
+                    }
+                }
+            }
+            Step::Broadcast { message } => {
+                for (id, connection) in state.registry.all() {
+                    state.send_datagram(id, &connection, message.as_bytes());
+                }
+            }
+            Step::InjectLoss { rate } => {
+                state.impairment.set_loss(*rate);
+            }
+            Step::CloseConnection { id, code, reason } => match state.registry.get(*id) {
+                Some(connection) => {
+                    connection.close(VarInt::from_u32(code.code()), reason.as_bytes());
+                }
+                None => warn!("Scenario: no connection with id {}", id),
+            },
+            Step::Redirect { url, id } => {
+                let frame = Frame::Redirect { url: url.clone() };
+                for (conn_id, connection) in state.registry.all() {
+                    if id.is_some_and(|id| id != conn_id) {
+                        continue;
+                    }
+                    let framing = state.registry.framing(conn_id);
+                    if let Err(e) = State::push_frame(&connection, framing, &frame).await {
+                        warn!("Scenario: failed to redirect connection {}: {}", conn_id, e);
+                    }
+                }
+            }
+            Step::OpenStreamToAll { message } => {
+                for (id, connection) in state.registry.all() {
+                    let frame = Frame::Message {
+                        text: message.clone(),
+                        seq: None,
+                        id: None,
+                        timing: None,
+                    };
+                    let framing = state.registry.framing(id);
+                    if let Err(e) = State::push_frame(&connection, framing, &frame).await {
+                        warn!(
+                            "Scenario: failed to open stream to connection {}: {}",
+                            id, e
+                        );
+                    }
+                }
+            }
+            Step::Burst {
+                count,
+                payload_bytes,
+                lead_ms,
+                collect_ms,
+            } => {
+                state
+                    .burst
+                    .run(
+                        state,
+                        *count,
+                        *payload_bytes,
+                        Duration::from_millis(*lead_ms),
+                        Duration::from_millis(*collect_ms),
+                    )
+                    .await;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_map_style_steps() {
+        let yaml = "steps:\n  - inject_loss: { rate: 0.5 }\n  - redirect: { url: \"https://localhost:8766/\" }\n";
+        let scenario: Scenario = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            scenario.steps,
+            vec![
+                Step::InjectLoss { rate: 0.5 },
+                Step::Redirect {
+                    url: "https://localhost:8766/".into(),
+                    id: None
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use crate::scenario::Step;
+use crate::state::State;
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A recurring job from the `[[schedule]]` config tables:
+///
+/// ```toml
+/// [[schedule]]
+/// name = "heartbeat"
+/// every_ms = 30000
+/// announce = "Still here"
+///
+/// [[schedule]]
+/// name = "lossy-lunch"
+/// at = "12:00"  # daily, UTC
+/// step = { inject_loss = { rate = 0.2 } }
+/// ```
+///
+/// A job runs either `every_ms` or daily `at` a UTC time of day (`HH:MM` or
+/// `HH:MM:SS`), and either sends `announce` to every connection on a
+/// server-opened stream or runs one scenario `step`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub every_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub announce: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<Step>,
+}
+
+impl Job {
+    pub fn validate(&self) -> Result<()> {
+        match (self.every_ms, &self.at) {
+            (Some(0), None) => bail!("schedule '{}': every_ms must be positive", self.name),
+            (Some(_), None) => {}
+            (None, Some(at)) => {
+                time_of_day(at).with_context(|| format!("schedule '{}'", self.name))?;
+            }
+            _ => bail!(
+                "schedule '{}' needs exactly one of every_ms and at",
+                self.name
+            ),
+        }
+        if self.announce.is_some() == self.step.is_some() {
+            bail!(
+                "schedule '{}' needs exactly one of announce and step",
+                self.name
+            );
+        }
+        Ok(())
+    }
+
+    // Time until the job is next due
+    fn delay(&self) -> Duration {
+        if let Some(ms) = self.every_ms {
+            return Duration::from_millis(ms);
+        }
+        // Validated on load
+        let at = self.at.as_deref().and_then(|at| time_of_day(at).ok());
+        let since_midnight = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+            % DAY.as_millis();
+        let since_midnight = Duration::from_millis(since_midnight as u64);
+        match at {
+            Some(at) if at > since_midnight => at - since_midnight,
+            Some(at) => at + DAY - since_midnight,
+            None => DAY,
+        }
+    }
+
+    fn step(&self) -> Option<Step> {
+        let announcement = self.announce.as_ref().map(|message| Step::OpenStreamToAll {
+            message: message.clone(),
+        });
+        announcement.or_else(|| self.step.clone())
+    }
+}
+
+// Parse "HH:MM" or "HH:MM:SS" into time since midnight
+fn time_of_day(at: &str) -> Result<Duration> {
+    let parts: Vec<&str> = at.split(':').collect();
+    let fields: Option<Vec<u64>> = parts.iter().map(|p| p.parse().ok()).collect();
+    match fields.as_deref() {
+        Some([h, m]) if *h < 24 && *m < 60 => Ok(Duration::from_secs(h * 3600 + m * 60)),
+        Some([h, m, s]) if *h < 24 && *m < 60 && *s < 60 => {
+            Ok(Duration::from_secs(h * 3600 + m * 60 + s))
+        }
+        _ => bail!("at {:?} is not a time of day (HH:MM or HH:MM:SS)", at),
+    }
+}
+
+// Run the configured jobs as they come due. The schedule is re-read when the
+// config changes; an unchanged schedule keeps its timers.
+pub async fn run(state: Arc<State>) {
+    let mut config = state.config.subscribe();
+    loop {
+        let jobs = config.borrow_and_update().schedule.clone();
+        if !jobs.is_empty() {
+            info!("Schedule: {} jobs", jobs.len());
+        }
+        let mut due: Vec<Instant> = jobs
+            .iter()
+            .map(|job| Instant::now() + job.delay())
+            .collect();
+
+        loop {
+            let next = due.iter().copied().enumerate().min_by_key(|(_, at)| *at);
+            let sleep = async {
+                match next {
+                    Some((_, at)) => tokio::time::sleep_until(at).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            tokio::select! {
+                changed = config.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                    if config.borrow().schedule != jobs {
+                        break;
+                    }
+                }
+                _ = sleep => {
+                    let Some((index, _)) = next else { continue };
+                    let job = &jobs[index];
+                    due[index] = Instant::now() + job.delay();
+                    fire(&state, job);
+                }
+            }
+        }
+    }
+}
+
+// Run the job's step in its own task, so a slow step (a burst, a wait) doesn't
+// hold up the others
+fn fire(state: &Arc<State>, job: &Job) {
+    let Some(step) = job.step() else {
+        return;
+    };
+    info!("Schedule: running '{}'", job.name);
+    let state = state.clone();
+    let name = job.name.clone();
+    tokio::spawn(async move {
+        if let Err(e) = step.run(&state).await {
+            warn!("Schedule: '{}' failed: {:#}", name, e);
+        }
+    });
+}
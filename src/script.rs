@@ -0,0 +1,317 @@
+//! Message handling scripted in Rhai, with the `scripting` feature, so server
+//! behaviour can be tried out without recompiling. The script passed with
+//! `--script` may define `on_message(text, ctx)`, which is called with each
+//! stream message after moderation. `ctx` is a map of the sender's
+//! `connection` id, `nick` (or `()`), the `rooms` it's in and the message's
+//! `seq` (or `()`). What the handler returns decides the answer:
+//!
+//! - `()` echoes the message as usual
+//! - a string is sent back instead of the echo
+//! - `false` sends nothing back
+//! - a map may set `reply` (sent back instead of the echo) and `room`, to
+//!   relay the message, or the map's `text`, into that room as the sender
+//!
+//! ```rhai
+//! fn on_message(text, ctx) {
+//!     if text == "ping" { return "pong"; }
+//!     if text.starts_with("#") { return #{ room: "lobby", text: text.sub_string(1) }; }
+//!     text.to_upper()
+//! }
+//! ```
+//!
+//! The file is polled and recompiled when it changes. One that fails to compile
+//! is reported and the previous script stays in force. A handler that fails or
+//! runs past [`MAX_OPERATIONS`] is logged and the message echoed as usual.
+
+use crate::admin::AdminEvent;
+use crate::config;
+use crate::registry::ConnectionId;
+use crate::state::State;
+use anyhow::Result;
+#[cfg(not(feature = "scripting"))]
+use anyhow::bail;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::{info, warn};
+
+/// Most operations one handler call may run before it's stopped, so a script
+/// stuck in a loop can't stall its connection.
+#[cfg(feature = "scripting")]
+pub const MAX_OPERATIONS: u64 = 100_000;
+
+/// The function called for each stream message.
+#[cfg(feature = "scripting")]
+const ON_MESSAGE: &str = "on_message";
+
+/// What to do with a stream message.
+pub enum Outcome {
+    /// Echo it as usual.
+    Echo,
+    /// Send `reply`, if any, instead of the echo, and relay into `relay`'s room.
+    #[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+    Handled {
+        reply: Option<String>,
+        relay: Option<Route>,
+    },
+}
+
+/// A message a script routed into a room.
+pub struct Route {
+    pub room: String,
+    pub text: String,
+}
+
+/// Script figures for `/stats`.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// The script file, if one was loaded.
+    pub path: Option<PathBuf>,
+    /// Handler calls, and how many of them failed.
+    pub calls: u64,
+    pub errors: u64,
+    /// Times the file changed and compiled.
+    pub reloads: u64,
+}
+
+#[cfg(feature = "scripting")]
+struct Compiled {
+    ast: rhai::AST,
+    handles_messages: bool,
+}
+
+// The loaded script and its engine, swapped out whole when the file changes
+pub struct Scripting {
+    path: Option<PathBuf>,
+    #[cfg(feature = "scripting")]
+    engine: rhai::Engine,
+    #[cfg(feature = "scripting")]
+    compiled: std::sync::RwLock<Option<Arc<Compiled>>>,
+    calls: AtomicU64,
+    errors: AtomicU64,
+    reloads: AtomicU64,
+}
+
+impl Scripting {
+    /// No script: every message is echoed.
+    pub fn new() -> Self {
+        Self {
+            path: None,
+            #[cfg(feature = "scripting")]
+            engine: engine(),
+            #[cfg(feature = "scripting")]
+            compiled: std::sync::RwLock::new(None),
+            calls: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            reloads: AtomicU64::new(0),
+        }
+    }
+
+    /// Load and compile the script at `path`.
+    pub async fn load(path: PathBuf) -> Result<Self> {
+        let scripting = Self {
+            path: Some(path),
+            ..Self::new()
+        };
+        scripting.compile().await?;
+        Ok(scripting)
+    }
+
+    /// Recompile the script file, keeping the previous script if it fails.
+    #[cfg(feature = "scripting")]
+    async fn compile(&self) -> Result<()> {
+        use anyhow::{Context, bail};
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let text = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read script {}", path.display()))?;
+        let ast = self
+            .engine
+            .compile(&text)
+            .with_context(|| format!("Invalid script {}", path.display()))?;
+        let handler = ast.iter_functions().find(|f| f.name == ON_MESSAGE);
+        match &handler {
+            Some(handler) if handler.params.len() != 2 => bail!(
+                "Invalid script {}: {} must take (text, ctx)",
+                path.display(),
+                ON_MESSAGE
+            ),
+            Some(_) => {}
+            None => warn!(
+                "Script {} defines no {}; messages are echoed as usual",
+                path.display(),
+                ON_MESSAGE
+            ),
+        }
+        let compiled = Compiled {
+            handles_messages: handler.is_some(),
+            ast,
+        };
+        *self.compiled.write().unwrap() = Some(Arc::new(compiled));
+        Ok(())
+    }
+
+    #[cfg(not(feature = "scripting"))]
+    async fn compile(&self) -> Result<()> {
+        bail!("--script needs a build with the scripting feature")
+    }
+
+    pub fn report(&self) -> Report {
+        Report {
+            path: self.path.clone(),
+            calls: self.calls.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            reloads: self.reloads.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(feature = "scripting")]
+fn engine() -> rhai::Engine {
+    let mut engine = rhai::Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(32);
+    // Debug builds of rhai default to half these
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(protocol::MAX_FRAME_LEN);
+    engine.on_print(|text| info!("Script: {}", text));
+    engine.on_debug(|text, _, position| tracing::debug!("Script {}: {}", position, text));
+    engine
+}
+
+/// Run the script's `on_message` on a stream message from `connection`.
+#[cfg(feature = "scripting")]
+pub fn on_message(
+    state: &State,
+    connection: ConnectionId,
+    seq: Option<u64>,
+    text: &str,
+) -> Outcome {
+    use rhai::{Dynamic, Map};
+
+    let scripting = &state.script;
+    let Some(compiled) = scripting.compiled.read().unwrap().clone() else {
+        return Outcome::Echo;
+    };
+    if !compiled.handles_messages {
+        return Outcome::Echo;
+    }
+    scripting.calls.fetch_add(1, Ordering::Relaxed);
+
+    let mut ctx = Map::new();
+    ctx.insert("connection".into(), Dynamic::from_int(connection as i64));
+    ctx.insert(
+        "nick".into(),
+        state
+            .registry
+            .tag(connection, "nick")
+            .map_or(Dynamic::UNIT, Dynamic::from),
+    );
+    ctx.insert("rooms".into(), state.rooms.rooms_of(connection).into());
+    ctx.insert(
+        "seq".into(),
+        seq.map_or(Dynamic::UNIT, |seq| Dynamic::from_int(seq as i64)),
+    );
+
+    let returned = scripting
+        .engine
+        .call_fn::<Dynamic>(
+            &mut rhai::Scope::new(),
+            &compiled.ast,
+            ON_MESSAGE,
+            (text.to_string(), ctx),
+        )
+        .map_err(|e| e.to_string())
+        .and_then(|returned| outcome(returned, text));
+    match returned {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            scripting.errors.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Script {} failed on connection {}, echoing instead: {}",
+                ON_MESSAGE, connection, e
+            );
+            Outcome::Echo
+        }
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+pub fn on_message(_: &State, _: ConnectionId, _: Option<u64>, _: &str) -> Outcome {
+    Outcome::Echo
+}
+
+// What a handler's return value asks for
+#[cfg(feature = "scripting")]
+fn outcome(returned: rhai::Dynamic, text: &str) -> Result<Outcome, String> {
+    if returned.is_unit() {
+        return Ok(Outcome::Echo);
+    }
+    if returned.as_bool() == Ok(false) {
+        return Ok(Outcome::Handled {
+            reply: None,
+            relay: None,
+        });
+    }
+    if returned.is_string() {
+        return Ok(Outcome::Handled {
+            reply: returned.into_string().ok(),
+            relay: None,
+        });
+    }
+    let type_name = returned.type_name();
+    let Some(mut map) = returned.try_cast::<rhai::Map>() else {
+        return Err(format!("{} returned a {}", ON_MESSAGE, type_name));
+    };
+    let mut string = |key: &str| match map.remove(key) {
+        None => Ok(None),
+        Some(value) => value
+            .into_string()
+            .map(Some)
+            .map_err(|found| format!("{} returned a {} for {:?}", ON_MESSAGE, found, key)),
+    };
+    let reply = string("reply")?;
+    let room = string("room")?;
+    let relayed = string("text")?;
+    Ok(Outcome::Handled {
+        reply,
+        relay: room.map(|room| Route {
+            room,
+            text: relayed.unwrap_or_else(|| text.to_string()),
+        }),
+    })
+}
+
+/// Recompile the script whenever its file changes.
+pub async fn watch(state: Arc<State>) {
+    let Some(path) = state.script.path.clone() else {
+        return;
+    };
+    let mut last_modified = config::modified(&path).await;
+    let mut interval = tokio::time::interval(config::POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let modified = config::modified(&path).await;
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        match state.script.compile().await {
+            Ok(()) => {
+                state.script.reloads.fetch_add(1, Ordering::Relaxed);
+                info!("Script reloaded from {}", path.display());
+                state
+                    .admin
+                    .publish(AdminEvent::ScriptReloaded { path: path.clone() });
+            }
+            Err(e) => warn!("Script reload failed, keeping the previous one: {:#}", e),
+        }
+    }
+}
@@ -0,0 +1,401 @@
+use crate::state::State;
+use anyhow::{Context, Result, anyhow, bail};
+use protocol::room::RelayTransport;
+use protocol::rpc::{self, Outcome};
+use protocol::telemetry::{decode_ping, encode_ping};
+use protocol::{CloseCode, Frame, FrameDecoder};
+use serde_json::json;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Barrier, mpsc};
+use tokio::task::JoinSet;
+use tracing::info;
+use wtransport::{ClientConfig, Connection, Endpoint, RecvStream, VarInt};
+
+/// How long each check waits for the server.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Numbered messages sent in the stream echo check.
+const MESSAGES: u64 = 10;
+
+/// Datagrams and pings are sent up to this many times before the check fails,
+/// since either may be lost.
+const DATAGRAM_ATTEMPTS: u32 = 3;
+
+/// Sessions per room in the relay check.
+const ROOM_SIZE: usize = 8;
+
+/// The checks each session runs, in order.
+const CHECKS: [&str; 7] = [
+    "connect",
+    "hello",
+    "rpc",
+    "stream_echo",
+    "datagram_echo",
+    "ping",
+    "room_relay",
+];
+
+enum Incoming {
+    Frame(Frame),
+    Datagram(Vec<u8>),
+}
+
+// What one check came to for one session
+enum Verdict {
+    Passed,
+    Failed(String),
+    /// Not run, e.g. because the session never connected.
+    Skipped,
+}
+
+#[derive(Default)]
+struct Tally {
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+    /// The first session to fail and why.
+    first_failure: Option<(usize, String)>,
+}
+
+// Connect `sessions` native clients to this server's own WebTransport port and
+// run every check on each, then print a pass/fail report. True if every check
+// passed for every session.
+pub async fn run(state: Arc<State>, sessions: usize) -> bool {
+    let port = state.config.borrow().port;
+    let url = format!("https://localhost:{}", port);
+    info!("Self-test: {} sessions against {}", sessions, url);
+    let started = Instant::now();
+
+    // Every session reaches the barrier, connected or not, so relays only
+    // start once every room has all its members
+    let joined = Arc::new(Barrier::new(sessions));
+    let mut running = JoinSet::new();
+    let mut tasks = HashMap::new();
+    for session in 0..sessions {
+        let room_size = ROOM_SIZE.min(sessions - session / ROOM_SIZE * ROOM_SIZE);
+        let task = running.spawn(client(session, url.clone(), room_size, joined.clone()));
+        tasks.insert(task.id(), session);
+    }
+    let mut tallies: Vec<Tally> = CHECKS.iter().map(|_| Tally::default()).collect();
+    while let Some(result) = running.join_next().await {
+        // A session that panicked fails every check, with the panic as the reason
+        let (session, verdicts) = match result {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                let reason = e.to_string();
+                let verdicts = CHECKS.iter().map(|_| Verdict::Failed(reason.clone()));
+                (tasks[&e.id()], verdicts.collect())
+            }
+        };
+        for (tally, verdict) in tallies.iter_mut().zip(verdicts) {
+            match verdict {
+                Verdict::Passed => tally.passed += 1,
+                Verdict::Skipped => tally.skipped += 1,
+                Verdict::Failed(reason) => {
+                    tally.failed += 1;
+                    if tally
+                        .first_failure
+                        .as_ref()
+                        .is_none_or(|(s, _)| session < *s)
+                    {
+                        tally.first_failure = Some((session, reason));
+                    }
+                }
+            }
+        }
+    }
+
+    println!("Self-test: {} sessions against {}", sessions, url);
+    for (name, tally) in CHECKS.iter().zip(&tallies) {
+        let mut line = format!("  {:<14} {}/{} passed", name, tally.passed, sessions);
+        if tally.skipped > 0 {
+            line += &format!(", {} skipped", tally.skipped);
+        }
+        if let Some((session, reason)) = &tally.first_failure {
+            line += &format!(" (session {}: {})", session, reason);
+        }
+        println!("{}", line);
+    }
+    let passed = tallies.iter().all(|tally| tally.failed == 0);
+    println!(
+        "{} in {:.1} s",
+        if passed { "PASS" } else { "FAIL" },
+        started.elapsed().as_secs_f64()
+    );
+    passed
+}
+
+// One session's run through the checks, by index in CHECKS
+async fn client(
+    session: usize,
+    url: String,
+    room_size: usize,
+    joined: Arc<Barrier>,
+) -> (usize, Vec<Verdict>) {
+    let mut verdicts = Vec::with_capacity(CHECKS.len());
+    let mut client = match Client::connect(&url, session).await {
+        Ok(client) => client,
+        Err(e) => {
+            verdicts.push(Verdict::Failed(format!("{:#}", e)));
+            verdicts.extend(CHECKS[1..].iter().map(|_| Verdict::Skipped));
+            joined.wait().await;
+            return (session, verdicts);
+        }
+    };
+    verdicts.push(Verdict::Passed);
+
+    let room = format!("selftest-{}", session / ROOM_SIZE);
+    verdicts.push(check(client.hello()).await);
+    verdicts.push(check(client.rpc(&room, room_size > 1)).await);
+    verdicts.push(check(client.stream_echo()).await);
+    verdicts.push(check(client.datagram_echo(session)).await);
+    verdicts.push(check(client.ping(session as u32)).await);
+    joined.wait().await;
+    verdicts.push(if room_size > 1 {
+        check(client.room_relay(&room, session, room_size - 1)).await
+    } else {
+        Verdict::Skipped
+    });
+
+    client.connection.close(
+        VarInt::from_u32(CloseCode::Normal.code()),
+        b"self-test done",
+    );
+    (session, verdicts)
+}
+
+async fn check(run: impl Future<Output = Result<()>>) -> Verdict {
+    match tokio::time::timeout(CHECK_TIMEOUT, run).await {
+        Ok(Ok(())) => Verdict::Passed,
+        Ok(Err(e)) => Verdict::Failed(format!("{:#}", e)),
+        Err(_) => Verdict::Failed(format!("timed out after {:?}", CHECK_TIMEOUT)),
+    }
+}
+
+// A native client session and its main bidirectional stream, with everything
+// the server sends gathered into `incoming`
+struct Client {
+    connection: Connection,
+    send: wtransport::SendStream,
+    incoming: mpsc::UnboundedReceiver<Incoming>,
+    next_request: u64,
+}
+
+impl Client {
+    async fn connect(url: &str, session: usize) -> Result<Self> {
+        // The server connects to itself, so there's no certificate to check
+        let config = ClientConfig::builder()
+            .with_bind_default()
+            .with_no_cert_validation()
+            .build();
+        let connect = async {
+            Endpoint::client(config)?
+                .connect(format!("{}/?selftest={}", url, session))
+                .await
+                .context("can't connect")
+        };
+        let connection = tokio::time::timeout(CHECK_TIMEOUT, connect)
+            .await
+            .map_err(|_| anyhow!("timed out connecting"))??;
+        let (send, recv) = connection.open_bi().await?.await?;
+
+        let (tx, incoming) = mpsc::unbounded_channel();
+        tokio::spawn(read_frames(recv, tx.clone()));
+        tokio::spawn(receive(connection.clone(), tx));
+        Ok(Self {
+            connection,
+            send,
+            incoming,
+            next_request: 1,
+        })
+    }
+
+    async fn send_frame(&mut self, frame: &Frame) -> Result<()> {
+        self.send.write_all(&protocol::encode(frame)).await?;
+        Ok(())
+    }
+
+    // Wait for the first incoming frame or datagram `wanted` picks, dropping any
+    // before it
+    async fn expect<T>(&mut self, mut wanted: impl FnMut(Incoming) -> Option<T>) -> Result<T> {
+        loop {
+            let incoming = self.incoming.recv().await.context("session closed")?;
+            if let Some(found) = wanted(incoming) {
+                return Ok(found);
+            }
+        }
+    }
+
+    async fn hello(&mut self) -> Result<()> {
+        self.expect(|incoming| match incoming {
+            Incoming::Frame(Frame::Hello(_)) => Some(()),
+            _ => None,
+        })
+        .await
+    }
+
+    // Call `echo`, first joining `room` if there's anyone to relay to. Frames on
+    // the stream are handled in order, so the response also confirms the join
+    async fn rpc(&mut self, room: &str, join: bool) -> Result<()> {
+        if join {
+            let join = Frame::Join {
+                room: room.to_string(),
+                transport: Some(RelayTransport::Stream),
+            };
+            self.send_frame(&join).await?;
+        }
+        let id = self.next_request;
+        self.next_request += 1;
+        let text = "self-test";
+        let request = Frame::Request {
+            id,
+            method: rpc::ECHO.to_string(),
+            params: json!({ "text": text }),
+        };
+        self.send_frame(&request).await?;
+        let outcome = self
+            .expect(|incoming| match incoming {
+                Incoming::Frame(Frame::Response { id: got, outcome }) if got == id => Some(outcome),
+                Incoming::Frame(Frame::Error { error }) => Some(Outcome::Error(error)),
+                _ => None,
+            })
+            .await?;
+        match outcome {
+            Outcome::Result(result) if result["text"] == text => Ok(()),
+            Outcome::Result(result) => bail!("echo answered {}", result),
+            Outcome::Error(error) => bail!("echo failed: {}", error),
+        }
+    }
+
+    // Send numbered messages and check their echoes come back in order, once each
+    async fn stream_echo(&mut self) -> Result<()> {
+        for seq in 1..=MESSAGES {
+            let message = Frame::Message {
+                text: format!("self-test {}", seq),
+                seq: Some(seq),
+                id: None,
+                timing: None,
+            };
+            self.send_frame(&message).await?;
+        }
+        for expected in 1..=MESSAGES {
+            let seq = self
+                .expect(|incoming| match incoming {
+                    Incoming::Frame(Frame::Message { seq: Some(seq), .. }) => Some(Ok(seq)),
+                    Incoming::Frame(Frame::Error { error }) => Some(Err(error)),
+                    _ => None,
+                })
+                .await?
+                .map_err(|error| anyhow!("server sent an error: {}", error))?;
+            if seq != expected {
+                bail!("echo {} came when {} was due", seq, expected);
+            }
+        }
+        Ok(())
+    }
+
+    async fn datagram_echo(&mut self, session: usize) -> Result<()> {
+        let text = format!("self-test datagram {}", session);
+        self.datagram_round_trip(text.as_bytes(), |datagram| {
+            String::from_utf8_lossy(datagram).ends_with(&text)
+        })
+        .await
+        .context("no datagram echo")
+    }
+
+    async fn ping(&mut self, ping_id: u32) -> Result<()> {
+        self.datagram_round_trip(&encode_ping(ping_id), |datagram| {
+            decode_ping(datagram) == Some(ping_id)
+        })
+        .await
+        .context("no ping echo")
+    }
+
+    // Send `datagram` until a reply `is_reply` picks comes back
+    async fn datagram_round_trip(
+        &mut self,
+        datagram: &[u8],
+        is_reply: impl Fn(&[u8]) -> bool,
+    ) -> Result<()> {
+        let wait = CHECK_TIMEOUT / (DATAGRAM_ATTEMPTS + 1);
+        for _ in 0..DATAGRAM_ATTEMPTS {
+            self.connection.send_datagram(datagram)?;
+            let reply = self.expect(|incoming| match incoming {
+                Incoming::Datagram(data) if is_reply(&data) => Some(()),
+                _ => None,
+            });
+            if tokio::time::timeout(wait, reply).await.is_ok() {
+                return Ok(());
+            }
+        }
+        bail!("{} sent", DATAGRAM_ATTEMPTS)
+    }
+
+    // Relay a message to `room` and check one comes from each of the `others`
+    async fn room_relay(&mut self, room: &str, session: usize, others: usize) -> Result<()> {
+        let relay = Frame::Relay {
+            room: room.to_string(),
+            from: None,
+            nick: None,
+            text: format!("self-test relay from {}", session),
+            replayed: false,
+            id: None,
+            timing: None,
+        };
+        self.send_frame(&relay).await?;
+        let mut received = 0;
+        let gathered = async {
+            while received < others {
+                self.expect(|incoming| match incoming {
+                    Incoming::Frame(Frame::Relay { room: to, .. }) if to == room => Some(()),
+                    _ => None,
+                })
+                .await?;
+                received += 1;
+            }
+            anyhow::Ok(())
+        };
+        // Leave a moment at the end to report how many came
+        let gathered =
+            tokio::time::timeout(CHECK_TIMEOUT - Duration::from_millis(100), gathered).await;
+        match gathered {
+            Ok(result) => result,
+            Err(_) => bail!("got {} of {} relays", received, others),
+        }
+    }
+}
+
+// Accept the server's unidirectional streams and datagrams until the session closes
+async fn receive(connection: Connection, incoming: mpsc::UnboundedSender<Incoming>) {
+    loop {
+        tokio::select! {
+            stream = connection.accept_uni() => match stream {
+                Ok(recv) => {
+                    tokio::spawn(read_frames(recv, incoming.clone()));
+                }
+                Err(_) => return,
+            },
+            datagram = connection.receive_datagram() => match datagram {
+                Ok(datagram) => {
+                    let _ = incoming.send(Incoming::Datagram(datagram.payload().to_vec()));
+                }
+                Err(_) => return,
+            },
+        }
+    }
+}
+
+// Decode frames off one stream until it ends
+async fn read_frames(mut recv: RecvStream, incoming: mpsc::UnboundedSender<Incoming>) {
+    let mut decoder = FrameDecoder::new();
+    let mut buffer = vec![0u8; 4096];
+    while let Ok(Some(n)) = recv.read(&mut buffer).await {
+        decoder.push(&buffer[..n]);
+        while let Ok(Some(frame)) = decoder.next_frame() {
+            let _ = incoming.send(Incoming::Frame(frame));
+        }
+    }
+}
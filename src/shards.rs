@@ -0,0 +1,79 @@
+//! Sharding the WebTransport port across several endpoints, each on its own
+//! worker thread. Every endpoint binds the same port with `SO_REUSEPORT`, so
+//! the kernel spreads incoming connections across them by address hash.
+//!
+//! Kept free of server types so benches can include it with `#[path]`.
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::future::Future;
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr, UdpSocket};
+use tokio::sync::oneshot;
+
+/// Whether this platform lets several sockets share a port, which sharding needs.
+pub const SUPPORTED: bool = cfg!(all(
+    unix,
+    not(any(
+        target_os = "solaris",
+        target_os = "illumos",
+        target_os = "cygwin"
+    ))
+));
+
+/// A UDP socket on `port` of every interface, IPv4 and IPv6, as wtransport's
+/// `with_bind_default` binds. With `reuse_port`, other sockets may bind the
+/// same port and share its connections.
+pub fn socket(port: u16, reuse_port: bool) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(false)?;
+    if reuse_port {
+        set_reuse_port(&socket)?;
+    }
+    socket.bind(&SockAddr::from(SocketAddr::from((
+        Ipv6Addr::UNSPECIFIED,
+        port,
+    ))))?;
+    Ok(socket.into())
+}
+
+#[cfg(all(
+    unix,
+    not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))
+))]
+fn set_reuse_port(socket: &Socket) -> io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(all(
+    unix,
+    not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))
+)))]
+fn set_reuse_port(_socket: &Socket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
+
+/// Run the future `run` makes on a new thread named `shard-<shard>`, with a
+/// single-threaded runtime of its own, so everything it spawns stays on that
+/// thread. The receiver gets its output, or is dropped if it panics.
+pub fn spawn<F>(
+    shard: usize,
+    run: impl FnOnce() -> F + Send + 'static,
+) -> io::Result<oneshot::Receiver<F::Output>>
+where
+    F: Future + 'static,
+    F::Output: Send + 'static,
+{
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let (done, output) = oneshot::channel();
+    std::thread::Builder::new()
+        .name(format!("shard-{}", shard))
+        .spawn(move || {
+            let _ = done.send(runtime.block_on(run()));
+        })?;
+    Ok(output)
+}
@@ -0,0 +1,181 @@
+use crate::admin::Admin;
+use crate::backoff::AcceptBackoff;
+use crate::burst::Coordinator;
+use crate::certs::Fingerprint;
+use crate::config::Config;
+use crate::dedup::Dedup;
+use crate::fanout::Fanout;
+use crate::geoip::GeoIp;
+use crate::impairment::Impairment;
+use crate::journal::Journal;
+use crate::memory::MemoryBudget;
+use crate::moderation::Moderation;
+use crate::pipelines::Pipelines;
+use crate::pool::BufferPool;
+use crate::registry::{ConnectionId, Registry};
+use crate::resume::Resumption;
+use crate::rooms::Rooms;
+use crate::rpc::Router;
+use crate::script::Scripting;
+use crate::stats::Stats;
+use crate::storage::{Storage, Writer};
+use crate::summary::SessionLog;
+use crate::timeseries::TimeSeries;
+use crate::tls::Handshakes;
+use crate::transfers::Transfers;
+use crate::violations::Violations;
+use protocol::{Frame, Framing};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::watch;
+use tracing::{debug, warn};
+use wtransport::Connection;
+
+// Server-wide state shared by every connection handler.
+pub struct State {
+    /// Live config; replaced when the config file is reloaded.
+    pub config: watch::Sender<Config>,
+    pub admin: Admin,
+    pub registry: Registry,
+    pub impairment: Impairment,
+    pub memory: MemoryBudget,
+    pub moderation: Moderation,
+    /// The `--script` message handler, if any.
+    pub script: Scripting,
+    /// The `--geoip-db` database clients' addresses are looked up in, if any.
+    pub geoip: GeoIp,
+    /// TLS parameters of finished handshakes, until their session is accepted.
+    pub handshakes: Arc<Handshakes>,
+    pub violations: Violations,
+    pub rpc: Router,
+    pub stats: Stats,
+    /// Persisted data, on the backend `storage.backend` named at startup.
+    pub storage: Arc<dyn Storage>,
+    /// Appends to `storage` from async tasks.
+    pub storage_writer: Writer,
+    /// Where closed sessions' summaries are appended, with `--session-log`.
+    pub session_log: Option<SessionLog>,
+    /// Where session events are journaled, with `journal.path`.
+    pub journal: Option<Journal>,
+    /// Where `/admin/dump-state` writes snapshots, from `--dump-dir`.
+    pub dump_dir: PathBuf,
+    /// Per-second aggregates behind `/stats/timeseries`.
+    pub timeseries: TimeSeries,
+    pub burst: Coordinator,
+    pub pipelines: Pipelines,
+    pub rooms: Rooms,
+    /// Per-connection queues for room relays and pipeline aggregates.
+    pub fanout: Fanout,
+    /// Session tokens, and closed sessions waiting to be resumed.
+    pub resume: Resumption,
+    /// Ids of the messages each session sent, so resent ones are handled once.
+    pub dedup: Dedup,
+    /// File uploads in progress, resumable from a later session.
+    pub transfers: Transfers,
+    /// Read buffers for stream handlers, reused across streams.
+    pub read_buffers: BufferPool,
+    /// Sessions that failed to set up, and the pause before accepting more.
+    pub accept_backoff: AcceptBackoff,
+    pub started_at: Instant,
+    /// Digests of the certificate this server presents, for `serverCertificateHashes`.
+    pub cert: Fingerprint,
+}
+
+impl State {
+    pub fn new(
+        config: Config,
+        moderation: Moderation,
+        storage: Arc<dyn Storage>,
+        session_log: Option<SessionLog>,
+        dump_dir: PathBuf,
+        cert: Fingerprint,
+        shards: usize,
+    ) -> Self {
+        let impairment = Impairment::new();
+        impairment.set_loss(config.impairment.loss);
+
+        let memory = MemoryBudget::new(config.memory.budget_bytes);
+        let storage_writer = Writer::spawn(storage.clone());
+        let transfers =
+            Transfers::load(storage.as_ref(), storage_writer.clone(), &config.transfers);
+
+        Self {
+            config: watch::Sender::new(config),
+            admin: Admin::new(),
+            registry: Registry::new(),
+            impairment,
+            memory,
+            moderation,
+            script: Scripting::new(),
+            geoip: GeoIp::new(),
+            handshakes: Arc::new(Handshakes::new()),
+            violations: Violations::new(),
+            rpc: Router::with_builtins(),
+            stats: Stats::new(shards),
+            storage_writer,
+            storage,
+            session_log,
+            journal: None,
+            dump_dir,
+            timeseries: TimeSeries::new(),
+            burst: Coordinator::new(),
+            pipelines: Pipelines::new(),
+            rooms: Rooms::new(),
+            fanout: Fanout::new(),
+            resume: Resumption::new(),
+            dedup: Dedup::new(),
+            transfers,
+            read_buffers: BufferPool::new(),
+            accept_backoff: AcceptBackoff::new(),
+            started_at: Instant::now(),
+            cert,
+        }
+    }
+
+    /// Send a datagram to connection `id`, subject to the configured impairment
+    /// and the connection's preset, if any.
+    pub fn send_datagram(&self, id: ConnectionId, connection: &Connection, payload: &[u8]) {
+        if self.impairment.should_drop(id) {
+            debug!(
+                "Impairment dropped outgoing datagram ({} bytes)",
+                payload.len()
+            );
+            return;
+        }
+
+        let delay = self.impairment.delay(id);
+        if delay.is_zero() {
+            if let Err(e) = connection.send_datagram(payload) {
+                warn!("Failed to send datagram: {}", e);
+            }
+            return;
+        }
+        let connection = connection.clone();
+        let payload = payload.to_vec();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if let Err(e) = connection.send_datagram(payload) {
+                debug!("Failed to send delayed datagram: {}", e);
+            }
+        });
+    }
+
+    /// `at` on the clock [`ServerTiming`](protocol::telemetry::ServerTiming) stamps are read from: microseconds
+    /// since the server started.
+    pub fn clock_us(&self, at: Instant) -> u64 {
+        at.saturating_duration_since(self.started_at).as_micros() as u64
+    }
+
+    /// Send one frame on a fresh server-opened unidirectional stream.
+    pub async fn push_frame(
+        connection: &Connection,
+        framing: Framing,
+        frame: &Frame,
+    ) -> anyhow::Result<()> {
+        let mut stream = connection.open_uni().await?.await?;
+        stream.write_all(&framing.encode(frame)).await?;
+        stream.finish().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,806 @@
+use crate::backoff;
+use crate::dedup;
+use crate::fanout;
+use crate::pool::PoolSnapshot;
+use crate::registry::ConnectionId;
+use crate::resume::ResumeReport;
+use crate::script;
+use crate::state::State;
+use protocol::PlaygroundError;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Upper bounds (inclusive, bytes) of the message size buckets: 16 B doubling up to 64 KiB.
+/// Anything larger lands in the implicit +Inf bucket.
+pub const SIZE_BUCKETS: [u64; 13] = [
+    16, 32, 64, 128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536,
+];
+
+/// Latest echo latencies kept per connection for its percentiles.
+const LATENCY_WINDOW: usize = 1024;
+
+/// Latest echo latencies kept across all connections, closed ones included.
+const LATENCY_WINDOW_ALL: usize = 4096;
+
+// Exponential-bucket histogram. Buckets are stored non-cumulatively; the
+// snapshot and the Prometheus exporter accumulate them as needed.
+pub struct Histogram {
+    buckets: [AtomicU64; SIZE_BUCKETS.len() + 1],
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: u64) {
+        let index = SIZE_BUCKETS.partition_point(|bound| *bound < value);
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+
+        let buckets = SIZE_BUCKETS
+            .iter()
+            .zip(&counts)
+            .map(|(le, count)| Bucket {
+                le: Some(*le),
+                count: *count,
+            })
+            .chain(std::iter::once(Bucket {
+                le: None,
+                count: counts[SIZE_BUCKETS.len()],
+            }))
+            .collect();
+
+        HistogramSnapshot {
+            count: counts.iter().sum(),
+            sum: self.sum.load(Ordering::Relaxed),
+            buckets,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Bucket {
+    /// Inclusive upper bound in bytes; `null` for the overflow bucket.
+    pub le: Option<u64>,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistogramSnapshot {
+    pub count: u64,
+    pub sum: u64,
+    pub buckets: Vec<Bucket>,
+}
+
+/// Received-message counters for one transport (streams or datagrams).
+pub struct TransportStats {
+    messages: AtomicU64,
+    bytes: AtomicU64,
+    sizes: Histogram,
+}
+
+impl TransportStats {
+    pub fn new() -> Self {
+        Self {
+            messages: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            sizes: Histogram::new(),
+        }
+    }
+
+    pub fn record(&self, size: usize) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(size as u64, Ordering::Relaxed);
+        self.sizes.observe(size as u64);
+    }
+
+    /// `(messages, bytes)` received so far.
+    pub fn totals(&self) -> (u64, u64) {
+        (
+            self.messages.load(Ordering::Relaxed),
+            self.bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    pub fn snapshot(&self) -> TransportSnapshot {
+        TransportSnapshot {
+            messages: self.messages.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            message_sizes: self.sizes.snapshot(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransportSnapshot {
+    pub messages: u64,
+    pub bytes: u64,
+    pub message_sizes: HistogramSnapshot,
+}
+
+/// How datagram echoes were built. wtransport copies every outgoing datagram
+/// into its own buffer, so this counts echoes that needed a payload of their
+/// own, not copies.
+pub struct EchoStats {
+    rebuilt: AtomicU64,
+    unchanged: AtomicU64,
+}
+
+impl EchoStats {
+    pub fn new() -> Self {
+        Self {
+            rebuilt: AtomicU64::new(0),
+            unchanged: AtomicU64::new(0),
+        }
+    }
+
+    /// Count one echo; `rebuilt` if it was built as a new payload rather than
+    /// sent as the received one.
+    pub fn record(&self, rebuilt: bool) {
+        let counter = if rebuilt {
+            &self.rebuilt
+        } else {
+            &self.unchanged
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> EchoSnapshot {
+        EchoSnapshot {
+            rebuilt: self.rebuilt.load(Ordering::Relaxed),
+            unchanged: self.unchanged.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EchoSnapshot {
+    pub rebuilt: u64,
+    pub unchanged: u64,
+}
+
+// The latest latencies in microseconds, oldest first
+struct Samples {
+    window: usize,
+    latest: VecDeque<u64>,
+}
+
+impl Samples {
+    fn new(window: usize) -> Self {
+        Self {
+            window,
+            latest: VecDeque::with_capacity(window),
+        }
+    }
+
+    fn push(&mut self, micros: u64) {
+        if self.latest.len() == self.window {
+            self.latest.pop_front();
+        }
+        self.latest.push_back(micros);
+    }
+
+    fn percentiles(&self) -> Percentiles {
+        let mut sorted: Vec<u64> = self.latest.iter().copied().collect();
+        sorted.sort_unstable();
+        // Nearest rank
+        let rank = |p: f64| {
+            let index = ((p * sorted.len() as f64).ceil() as usize).saturating_sub(1);
+            sorted.get(index).copied().unwrap_or(0)
+        };
+        Percentiles {
+            samples: sorted.len(),
+            p50_us: rank(0.5),
+            p90_us: rank(0.9),
+            p99_us: rank(0.99),
+            max_us: sorted.last().copied().unwrap_or(0),
+        }
+    }
+}
+
+/// How long datagrams took from being received to their echo being handed to
+/// the connection, per connection and overall. This is the server's own share
+/// of a round trip; injected impairment delay comes after it.
+pub struct EchoLatencyStats {
+    all: Mutex<Samples>,
+    connections: Mutex<HashMap<ConnectionId, Samples>>,
+}
+
+impl EchoLatencyStats {
+    pub fn new() -> Self {
+        Self {
+            all: Mutex::new(Samples::new(LATENCY_WINDOW_ALL)),
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count an echo to `connection` of a datagram received at `received`.
+    pub fn record(&self, connection: ConnectionId, received: Instant) {
+        let micros = received.elapsed().as_micros() as u64;
+        self.all.lock().unwrap().push(micros);
+        self.connections
+            .lock()
+            .unwrap()
+            .entry(connection)
+            .or_insert_with(|| Samples::new(LATENCY_WINDOW))
+            .push(micros);
+    }
+
+    /// Forget a closed connection's latencies; they stay in the overall ones.
+    pub fn remove(&self, connection: ConnectionId) {
+        self.connections.lock().unwrap().remove(&connection);
+    }
+
+    pub fn snapshot(&self) -> EchoLatencySnapshot {
+        let mut connections: Vec<ConnectionLatency> = self
+            .connections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(connection, samples)| ConnectionLatency {
+                connection: *connection,
+                latency: samples.percentiles(),
+            })
+            .collect();
+        connections.sort_by_key(|latency| latency.connection);
+        EchoLatencySnapshot {
+            all: self.all.lock().unwrap().percentiles(),
+            connections,
+        }
+    }
+}
+
+/// Percentiles of a window of latencies, by nearest rank; all 0 when empty.
+#[derive(Debug, Serialize)]
+pub struct Percentiles {
+    pub samples: usize,
+    pub p50_us: u64,
+    pub p90_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionLatency {
+    pub connection: ConnectionId,
+    #[serde(flatten)]
+    pub latency: Percentiles,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EchoLatencySnapshot {
+    /// The latest echoes across every connection, closed ones included.
+    pub all: Percentiles,
+    /// The latest echoes of each live connection.
+    pub connections: Vec<ConnectionLatency>,
+}
+
+/// Page visibility reports and heartbeats from browser clients.
+pub struct VisibilityStats {
+    hidden: AtomicU64,
+    visible: AtomicU64,
+    heartbeats: AtomicU64,
+    late_heartbeats: AtomicU64,
+}
+
+impl VisibilityStats {
+    pub fn new() -> Self {
+        Self {
+            hidden: AtomicU64::new(0),
+            visible: AtomicU64::new(0),
+            heartbeats: AtomicU64::new(0),
+            late_heartbeats: AtomicU64::new(0),
+        }
+    }
+
+    /// Count a client's page going hidden or becoming visible again.
+    pub fn record_change(&self, visible: bool) {
+        let counter = if visible { &self.visible } else { &self.hidden };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a heartbeat; `late` if it came well after the client said it would.
+    pub fn record_heartbeat(&self, late: bool) {
+        self.heartbeats.fetch_add(1, Ordering::Relaxed);
+        if late {
+            self.late_heartbeats.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self, background_clients: usize) -> VisibilitySnapshot {
+        VisibilitySnapshot {
+            background_clients,
+            hidden: self.hidden.load(Ordering::Relaxed),
+            visible: self.visible.load(Ordering::Relaxed),
+            heartbeats: self.heartbeats.load(Ordering::Relaxed),
+            late_heartbeats: self.late_heartbeats.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct VisibilitySnapshot {
+    /// Live clients whose page is hidden, by their own report.
+    pub background_clients: usize,
+    /// Times a client reported its page hidden.
+    pub hidden: u64,
+    /// Times a client reported its page visible again.
+    pub visible: u64,
+    pub heartbeats: u64,
+    /// Heartbeats more than twice their announced interval after the previous
+    /// one, as when a background tab's timers are throttled.
+    pub late_heartbeats: u64,
+}
+
+/// Error frames and error outcomes sent to clients, by error kind.
+pub struct ErrorStats {
+    by_kind: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl ErrorStats {
+    pub fn new() -> Self {
+        Self {
+            by_kind: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn record(&self, error: &PlaygroundError) {
+        *self
+            .by_kind
+            .lock()
+            .unwrap()
+            .entry(error.kind())
+            .or_default() += 1;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.by_kind.lock().unwrap().values().sum()
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<&'static str, u64> {
+        self.by_kind.lock().unwrap().clone()
+    }
+}
+
+/// Frames a middleware layer answered itself instead of passing them on, by
+/// layer.
+pub struct MiddlewareStats {
+    by_layer: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl MiddlewareStats {
+    pub fn new() -> Self {
+        Self {
+            by_layer: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn record(&self, layer: &'static str) {
+        *self.by_layer.lock().unwrap().entry(layer).or_default() += 1;
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<&'static str, u64> {
+        self.by_layer.lock().unwrap().clone()
+    }
+}
+
+/// Batch datagrams from clients and the messages packed into them.
+pub struct BatchStats {
+    batches: AtomicU64,
+    messages: AtomicU64,
+    largest: AtomicU64,
+}
+
+impl BatchStats {
+    pub fn new() -> Self {
+        Self {
+            batches: AtomicU64::new(0),
+            messages: AtomicU64::new(0),
+            largest: AtomicU64::new(0),
+        }
+    }
+
+    /// Count a batch of `messages` messages.
+    pub fn record(&self, messages: usize) {
+        self.batches.fetch_add(1, Ordering::Relaxed);
+        self.messages.fetch_add(messages as u64, Ordering::Relaxed);
+        self.largest.fetch_max(messages as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> BatchSnapshot {
+        let batches = self.batches.load(Ordering::Relaxed);
+        let messages = self.messages.load(Ordering::Relaxed);
+        BatchSnapshot {
+            batches,
+            messages,
+            avg_messages: if batches == 0 {
+                0.0
+            } else {
+                messages as f64 / batches as f64
+            },
+            largest: self.largest.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchSnapshot {
+    pub batches: u64,
+    /// Messages across all batches.
+    pub messages: u64,
+    /// Messages per batch.
+    pub avg_messages: f64,
+    /// Messages in the biggest batch.
+    pub largest: u64,
+}
+
+/// Datagrams sent with a max age, and those that arrived past it.
+pub struct ExpiryStats {
+    received: AtomicU64,
+    expired: AtomicU64,
+    max_late_ms: AtomicU64,
+}
+
+impl ExpiryStats {
+    pub fn new() -> Self {
+        Self {
+            received: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
+            max_late_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Count an expiring datagram; `late_ms` past its max age if it expired.
+    pub fn record(&self, late_ms: Option<u64>) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        if let Some(late_ms) = late_ms {
+            self.expired.fetch_add(1, Ordering::Relaxed);
+            self.max_late_ms.fetch_max(late_ms, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> ExpirySnapshot {
+        ExpirySnapshot {
+            received: self.received.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+            max_late_ms: self.max_late_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExpirySnapshot {
+    /// Datagrams that carried a max age.
+    pub received: u64,
+    /// Of those, dropped for arriving older than it.
+    pub expired: u64,
+    /// How far past its max age the stalest dropped datagram was.
+    pub max_late_ms: u64,
+}
+
+/// Liveness probes sent to quiet connections, and how they ended.
+pub struct LivenessStats {
+    answered: AtomicU64,
+    unanswered: AtomicU64,
+}
+
+impl LivenessStats {
+    pub fn new() -> Self {
+        Self {
+            answered: AtomicU64::new(0),
+            unanswered: AtomicU64::new(0),
+        }
+    }
+
+    /// Count one probe; an unanswered one closed its connection as dead.
+    pub fn record(&self, answered: bool) {
+        let counter = if answered {
+            &self.answered
+        } else {
+            &self.unanswered
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> LivenessSnapshot {
+        LivenessSnapshot {
+            answered: self.answered.load(Ordering::Relaxed),
+            unanswered: self.unanswered.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LivenessSnapshot {
+    /// Probes answered in time: the connection was merely idle.
+    pub answered: u64,
+    /// Probes left unanswered, each closing a dead connection.
+    pub unanswered: u64,
+}
+
+/// Connections warned of nearing `quota.bytes`, and closed on reaching it.
+pub struct QuotaStats {
+    warned: AtomicU64,
+    exceeded: AtomicU64,
+}
+
+impl QuotaStats {
+    pub fn new() -> Self {
+        Self {
+            warned: AtomicU64::new(0),
+            exceeded: AtomicU64::new(0),
+        }
+    }
+
+    /// Count one warning, or one connection closed at its cap.
+    pub fn record(&self, warning: bool) {
+        let counter = if warning {
+            &self.warned
+        } else {
+            &self.exceeded
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self, limit_bytes: u64) -> QuotaSnapshot {
+        QuotaSnapshot {
+            limit_bytes,
+            warned: self.warned.load(Ordering::Relaxed),
+            exceeded: self.exceeded.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct QuotaSnapshot {
+    /// The cap in force, `quota.bytes`; 0 means none.
+    pub limit_bytes: u64,
+    /// `quota_warning`s sent.
+    pub warned: u64,
+    /// Connections closed as `quota_exceeded`.
+    pub exceeded: u64,
+}
+
+/// Copies of room relays sent to members, by how they went.
+pub struct RelayStats {
+    streams: AtomicU64,
+    datagrams: AtomicU64,
+    fallbacks: AtomicU64,
+}
+
+impl RelayStats {
+    pub fn new() -> Self {
+        Self {
+            streams: AtomicU64::new(0),
+            datagrams: AtomicU64::new(0),
+            fallbacks: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stream(&self) {
+        self.streams.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn datagram(&self) {
+        self.datagrams.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a relay meant to go as a datagram that couldn't.
+    pub fn fallback(&self) {
+        self.fallbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> RelaySnapshot {
+        RelaySnapshot {
+            streams: self.streams.load(Ordering::Relaxed),
+            datagrams: self.datagrams.load(Ordering::Relaxed),
+            fallbacks: self.fallbacks.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelaySnapshot {
+    /// Relays pushed on streams, including fallbacks.
+    pub streams: u64,
+    /// Relays sent as datagrams, before any loss.
+    pub datagrams: u64,
+    /// Relays meant for datagrams that went on a stream instead: the member's
+    /// session had no datagrams, or the relay didn't fit in one.
+    pub fallbacks: u64,
+}
+
+/// Sessions accepted by one shard's endpoint.
+pub struct ShardStats {
+    accepted: AtomicU64,
+}
+
+impl ShardStats {
+    pub fn new() -> Self {
+        Self {
+            accepted: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_accept(&self) {
+        self.accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self, shard: usize, live: usize) -> ShardSnapshot {
+        ShardSnapshot {
+            shard,
+            accepted: self.accepted.load(Ordering::Relaxed),
+            live,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShardSnapshot {
+    pub shard: usize,
+    /// Sessions accepted since the server started.
+    pub accepted: u64,
+    /// Of those, sessions still open.
+    pub live: usize,
+}
+
+pub struct Stats {
+    pub stream: TransportStats,
+    pub datagram: TransportStats,
+    pub datagram_echo: EchoStats,
+    pub datagram_echo_latency: EchoLatencyStats,
+    pub datagram_batches: BatchStats,
+    pub datagram_expiry: ExpiryStats,
+    pub errors: ErrorStats,
+    pub middleware: MiddlewareStats,
+    pub visibility: VisibilityStats,
+    pub liveness: LivenessStats,
+    pub quota: QuotaStats,
+    pub room_relays: RelayStats,
+    /// Connections closed because their handler stopped making progress or
+    /// panicked.
+    pub wedged: AtomicU64,
+    /// Usable datagram sizes found by clients' MTU discovery.
+    pub max_datagram_size: Histogram,
+    /// One per endpoint sharing the WebTransport port; just one unless sharded.
+    pub shards: Vec<ShardStats>,
+}
+
+impl Stats {
+    pub fn new(shards: usize) -> Self {
+        Self {
+            stream: TransportStats::new(),
+            datagram: TransportStats::new(),
+            datagram_echo: EchoStats::new(),
+            datagram_echo_latency: EchoLatencyStats::new(),
+            datagram_batches: BatchStats::new(),
+            datagram_expiry: ExpiryStats::new(),
+            errors: ErrorStats::new(),
+            middleware: MiddlewareStats::new(),
+            visibility: VisibilityStats::new(),
+            liveness: LivenessStats::new(),
+            quota: QuotaStats::new(),
+            room_relays: RelayStats::new(),
+            wedged: AtomicU64::new(0),
+            max_datagram_size: Histogram::new(),
+            shards: (0..shards).map(|_| ShardStats::new()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MiddlewareReport {
+    pub layers: Vec<&'static str>,
+    pub answered: BTreeMap<&'static str, u64>,
+}
+
+/// Body of the `/stats` endpoint.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub connections: usize,
+    pub uptime_ms: u64,
+    pub stream: TransportSnapshot,
+    pub datagram: TransportSnapshot,
+    pub datagram_echo: EchoSnapshot,
+    /// Receive-to-echo times of datagrams, per connection and overall.
+    pub datagram_echo_latency: EchoLatencySnapshot,
+    pub datagram_batches: BatchSnapshot,
+    /// Datagrams sent with a max age, and how many arrived too late.
+    pub datagram_expiry: ExpirySnapshot,
+    /// Errors sent to clients, by kind.
+    pub errors: BTreeMap<&'static str, u64>,
+    /// The middleware layers frames pass through, and the frames each
+    /// answered itself, by layer.
+    pub middleware: MiddlewareReport,
+    pub visibility: VisibilitySnapshot,
+    pub liveness: LivenessSnapshot,
+    /// The per-connection byte cap, and connections warned and closed by it.
+    pub quota: QuotaSnapshot,
+    /// Copies of room relays sent to members, by transport.
+    pub room_relays: RelaySnapshot,
+    /// Room relays and pipeline aggregates queued for each connection, and
+    /// how long they waited.
+    pub fanout: fanout::Report,
+    /// Connections the watchdog closed as `server_error`.
+    pub wedged_connections: u64,
+    pub max_datagram_size: HistogramSnapshot,
+    pub read_buffers: PoolSnapshot,
+    pub shards: Vec<ShardSnapshot>,
+    /// Closed sessions waiting to be resumed, and their queued messages.
+    pub resume: ResumeReport,
+    /// Resent messages dropped by id.
+    pub dedup: dedup::Report,
+    /// The `--script` handler's calls and reloads.
+    pub script: script::Report,
+    /// Sessions that failed to set up, by why, and accept pauses they caused.
+    pub accept_errors: backoff::Report,
+}
+
+impl Report {
+    pub fn collect(state: &State) -> Self {
+        Self {
+            connections: *state.registry.count().borrow(),
+            uptime_ms: state.started_at.elapsed().as_millis() as u64,
+            stream: state.stats.stream.snapshot(),
+            datagram: state.stats.datagram.snapshot(),
+            datagram_echo: state.stats.datagram_echo.snapshot(),
+            datagram_echo_latency: state.stats.datagram_echo_latency.snapshot(),
+            datagram_batches: state.stats.datagram_batches.snapshot(),
+            datagram_expiry: state.stats.datagram_expiry.snapshot(),
+            errors: state.stats.errors.snapshot(),
+            middleware: MiddlewareReport {
+                layers: state
+                    .config
+                    .borrow()
+                    .middleware
+                    .layers
+                    .iter()
+                    .map(|layer| layer.name())
+                    .collect(),
+                answered: state.stats.middleware.snapshot(),
+            },
+            visibility: state
+                .stats
+                .visibility
+                .snapshot(state.registry.hidden_count()),
+            liveness: state.stats.liveness.snapshot(),
+            quota: state
+                .stats
+                .quota
+                .snapshot(state.config.borrow().quota.bytes),
+            room_relays: state.stats.room_relays.snapshot(),
+            fanout: state.fanout.report(),
+            wedged_connections: state.stats.wedged.load(Ordering::Relaxed),
+            max_datagram_size: state.stats.max_datagram_size.snapshot(),
+            read_buffers: state.read_buffers.snapshot(),
+            shards: state
+                .stats
+                .shards
+                .iter()
+                .zip(state.registry.per_shard(state.stats.shards.len()))
+                .enumerate()
+                .map(|(shard, (stats, live))| stats.snapshot(shard, live))
+                .collect(),
+            resume: state.resume.report(),
+            dedup: state.dedup.report(),
+            script: state.script.report(),
+            accept_errors: state.accept_backoff.report(),
+        }
+    }
+}
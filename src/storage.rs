@@ -0,0 +1,434 @@
+//! Pluggable persistence for playground features, so none of them hard-codes a
+//! backend. Room history and transfer progress use it; the session journal
+//! keeps its own file, since it controls when that's synced. `storage.backend`
+//! picks one at startup: `memory` (the default, gone on restart), `filesystem`
+//! (files under `storage.path`) or `sqlite` (a database at `storage.path`,
+//! with the `sqlite` feature).
+//!
+//! Data is kept as logs under `/`-separated keys, appended to one record at a
+//! time and read back from the end. Calls block on the backend's I/O, so
+//! features keep records small, and those appending from async tasks queue
+//! records for a [`Writer`] instead.
+
+use crate::config::StorageConfig;
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+/// Changes queued for a [`Writer`] at once, beyond which more are refused.
+const WRITE_QUEUE: usize = 1024;
+
+/// Which backend stores the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    #[default]
+    Memory,
+    Filesystem,
+    Sqlite,
+}
+
+pub trait Storage: Send + Sync {
+    /// Add `record` to the end of log `key`, keeping at least its last `keep`
+    /// records (0 keeps them all). Older ones may go at any time.
+    fn append(&self, key: &str, record: &[u8], keep: usize) -> Result<()>;
+
+    /// The last `limit` records of log `key`, oldest first.
+    fn tail(&self, key: &str, limit: usize) -> Result<Vec<Vec<u8>>>;
+
+    /// Keys of the logs starting with `prefix`, sorted.
+    fn logs(&self, prefix: &str) -> Result<Vec<String>>;
+
+    /// Delete log `key` and its records, if it exists.
+    fn remove(&self, key: &str) -> Result<()>;
+}
+
+/// Open the backend `config` names.
+pub fn open(config: &StorageConfig) -> Result<Arc<dyn Storage>> {
+    match config.backend {
+        Backend::Memory => info!("Storing data in memory"),
+        backend => info!("Storing data at {} ({:?})", config.path.display(), backend),
+    }
+    match config.backend {
+        Backend::Memory => Ok(Arc::new(MemoryStorage::new())),
+        Backend::Filesystem => Ok(Arc::new(FileStorage::open(&config.path)?)),
+        #[cfg(feature = "sqlite")]
+        Backend::Sqlite => Ok(Arc::new(sqlite::SqliteStorage::open(&config.path)?)),
+        #[cfg(not(feature = "sqlite"))]
+        Backend::Sqlite => bail!("storage.backend \"sqlite\" needs the sqlite feature"),
+    }
+}
+
+// A change waiting for the writer
+enum Change {
+    Append {
+        key: String,
+        record: Vec<u8>,
+        keep: usize,
+    },
+    Remove {
+        key: String,
+    },
+}
+
+/// Changes a [`Storage`] on a thread of its own, in the order they were
+/// queued, so async tasks don't wait on the backend's I/O. Changes that fail
+/// there are logged.
+#[derive(Clone)]
+pub struct Writer {
+    queue: mpsc::Sender<Change>,
+}
+
+impl Writer {
+    pub fn spawn(storage: Arc<dyn Storage>) -> Self {
+        let (queue, mut changes) = mpsc::channel::<Change>(WRITE_QUEUE);
+        thread::Builder::new()
+            .name("storage-writer".into())
+            .spawn(move || {
+                while let Some(change) = changes.blocking_recv() {
+                    let (key, result) = match change {
+                        Change::Append { key, record, keep } => {
+                            let result = storage.append(&key, &record, keep);
+                            (key, result)
+                        }
+                        Change::Remove { key } => {
+                            let result = storage.remove(&key);
+                            (key, result)
+                        }
+                    };
+                    if let Err(e) = result {
+                        warn!("Failed to write storage log {}: {:#}", key, e);
+                    }
+                }
+            })
+            .expect("Failed to start the storage writer");
+        Self { queue }
+    }
+
+    /// Queue `record` for [`Storage::append`], or fail if the writer is
+    /// [`WRITE_QUEUE`] changes behind.
+    pub fn append(&self, key: String, record: Vec<u8>, keep: usize) -> Result<()> {
+        check_key(&key)?;
+        self.queue(Change::Append { key, record, keep })
+    }
+
+    /// Queue log `key` for [`Storage::remove`], failing as [`Writer::append`] does.
+    pub fn remove(&self, key: String) -> Result<()> {
+        check_key(&key)?;
+        self.queue(Change::Remove { key })
+    }
+
+    fn queue(&self, change: Change) -> Result<()> {
+        self.queue
+            .try_send(change)
+            .map_err(|_| anyhow!("storage writer is {} changes behind", WRITE_QUEUE))
+    }
+}
+
+// Keys are `/`-separated segments of ASCII letters, digits, `.`, `_` and `-`,
+// so they map onto file paths without escaping anything
+fn check_key(key: &str) -> Result<()> {
+    let valid = key.split('/').all(|segment| {
+        !segment.is_empty()
+            && segment != "."
+            && segment != ".."
+            && segment
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+    });
+    if !valid {
+        bail!("invalid storage key {:?}", key);
+    }
+    Ok(())
+}
+
+/// Key of `name`'s log under `prefix`. Names may hold any character, so
+/// they're hex-encoded.
+pub fn name_key(prefix: &str, name: &str) -> String {
+    let mut key = format!("{}/", prefix);
+    for b in name.bytes() {
+        let _ = write!(key, "{:02x}", b);
+    }
+    key
+}
+
+/// The name [`name_key`] encoded in `key`, if it's under `prefix`.
+pub fn key_name(prefix: &str, key: &str) -> Option<String> {
+    let hex = key.strip_prefix(prefix)?.strip_prefix('/')?;
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Logs in memory, lost on restart.
+pub struct MemoryStorage {
+    logs: Mutex<BTreeMap<String, VecDeque<Vec<u8>>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self {
+            logs: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn append(&self, key: &str, record: &[u8], keep: usize) -> Result<()> {
+        check_key(key)?;
+        let mut logs = self.logs.lock().unwrap();
+        let log = logs.entry(key.to_string()).or_default();
+        log.push_back(record.to_vec());
+        while keep > 0 && log.len() > keep {
+            log.pop_front();
+        }
+        Ok(())
+    }
+
+    fn tail(&self, key: &str, limit: usize) -> Result<Vec<Vec<u8>>> {
+        check_key(key)?;
+        let logs = self.logs.lock().unwrap();
+        let Some(log) = logs.get(key) else {
+            return Ok(Vec::new());
+        };
+        Ok(log
+            .iter()
+            .skip(log.len().saturating_sub(limit))
+            .cloned()
+            .collect())
+    }
+
+    fn logs(&self, prefix: &str) -> Result<Vec<String>> {
+        let logs = self.logs.lock().unwrap();
+        Ok(logs
+            .range(prefix.to_string()..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        check_key(key)?;
+        self.logs.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+/// Each log as a file of length-prefixed records, at its key under a directory.
+pub struct FileStorage {
+    root: PathBuf,
+    /// Records in each log appended to since startup, so trimming doesn't
+    /// have to read the log on every append.
+    counts: Mutex<HashMap<String, usize>>,
+}
+
+impl FileStorage {
+    pub fn open(root: &Path) -> Result<Self> {
+        fs::create_dir_all(root).with_context(|| format!("Failed to create {}", root.display()))?;
+        Ok(Self {
+            root: root.to_path_buf(),
+            counts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn read_log(path: &Path) -> Result<Vec<Vec<u8>>> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut records = Vec::new();
+        let mut rest = bytes.as_slice();
+        // A record cut short by a crash mid-append is dropped
+        while let Some(len) = rest.get(..4) {
+            let len = u32::from_be_bytes(len.try_into()?) as usize;
+            let Some(record) = rest.get(4..4 + len) else {
+                break;
+            };
+            records.push(record.to_vec());
+            rest = &rest[4 + len..];
+        }
+        Ok(records)
+    }
+}
+
+fn encode_record(record: &[u8]) -> Result<Vec<u8>> {
+    let len = u32::try_from(record.len()).context("log record too large")?;
+    let mut encoded = Vec::with_capacity(4 + record.len());
+    encoded.extend_from_slice(&len.to_be_bytes());
+    encoded.extend_from_slice(record);
+    Ok(encoded)
+}
+
+impl Storage for FileStorage {
+    fn append(&self, key: &str, record: &[u8], keep: usize) -> Result<()> {
+        check_key(key)?;
+        let path = self.root.join(key);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut counts = self.counts.lock().unwrap();
+        let count = match counts.get(key) {
+            Some(count) => *count,
+            None => Self::read_log(&path)?.len(),
+        };
+
+        // Let the log grow to twice `keep` before rewriting it, so trimming
+        // costs one rewrite per `keep` appends. The rewrite goes through a
+        // temporary file, so a crash leaves the old log or the new one.
+        if keep > 0 && count + 1 > keep * 2 {
+            let mut records = Self::read_log(&path)?;
+            records.push(record.to_vec());
+            let kept = &records[records.len().saturating_sub(keep)..];
+            let mut contents = Vec::new();
+            for record in kept {
+                contents.extend(encode_record(record)?);
+            }
+            let mut temporary = path.clone().into_os_string();
+            temporary.push("~");
+            fs::write(&temporary, &contents)?;
+            fs::rename(&temporary, &path)?;
+            counts.insert(key.to_string(), kept.len());
+            return Ok(());
+        }
+
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?
+            .write_all(&encode_record(record)?)?;
+        counts.insert(key.to_string(), count + 1);
+        Ok(())
+    }
+
+    fn tail(&self, key: &str, limit: usize) -> Result<Vec<Vec<u8>>> {
+        check_key(key)?;
+        let mut records = Self::read_log(&self.root.join(key))?;
+        Ok(records.split_off(records.len().saturating_sub(limit)))
+    }
+
+    fn logs(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut dirs = vec![(self.root.clone(), String::new())];
+        while let Some((dir, key_prefix)) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let key = format!("{}{}", key_prefix, name);
+                if entry.file_type()?.is_dir() {
+                    dirs.push((entry.path(), format!("{}/", key)));
+                // Skips leftover temporary files, which end in `~`
+                } else if key.starts_with(prefix) && check_key(&key).is_ok() {
+                    keys.push(key);
+                }
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        check_key(key)?;
+        let mut counts = self.counts.lock().unwrap();
+        match fs::remove_file(self.root.join(key)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        counts.remove(key);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{Storage, check_key};
+    use anyhow::{Context, Result};
+    use rusqlite::{Connection, params};
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    /// Logs as rows of one SQLite table.
+    pub struct SqliteStorage {
+        connection: Mutex<Connection>,
+    }
+
+    impl SqliteStorage {
+        pub fn open(path: &Path) -> Result<Self> {
+            let connection = Connection::open(path)
+                .with_context(|| format!("Failed to open {}", path.display()))?;
+            connection.execute_batch(
+                "CREATE TABLE IF NOT EXISTS logs (
+                     seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                     key TEXT NOT NULL,
+                     record BLOB NOT NULL
+                 );
+                 CREATE INDEX IF NOT EXISTS logs_key ON logs (key, seq);",
+            )?;
+            Ok(Self {
+                connection: Mutex::new(connection),
+            })
+        }
+    }
+
+    impl Storage for SqliteStorage {
+        fn append(&self, key: &str, record: &[u8], keep: usize) -> Result<()> {
+            check_key(key)?;
+            let connection = self.connection.lock().unwrap();
+            connection.execute(
+                "INSERT INTO logs (key, record) VALUES (?1, ?2)",
+                params![key, record],
+            )?;
+            if keep > 0 {
+                connection.execute(
+                    "DELETE FROM logs WHERE key = ?1 AND seq <= (
+                         SELECT seq FROM logs WHERE key = ?1
+                         ORDER BY seq DESC LIMIT 1 OFFSET ?2
+                     )",
+                    params![key, keep as i64],
+                )?;
+            }
+            Ok(())
+        }
+
+        fn tail(&self, key: &str, limit: usize) -> Result<Vec<Vec<u8>>> {
+            check_key(key)?;
+            let connection = self.connection.lock().unwrap();
+            let mut statement = connection
+                .prepare("SELECT record FROM logs WHERE key = ?1 ORDER BY seq DESC LIMIT ?2")?;
+            let mut records = statement
+                .query_map(params![key, limit as i64], |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<Vec<u8>>>>()?;
+            records.reverse();
+            Ok(records)
+        }
+
+        fn logs(&self, prefix: &str) -> Result<Vec<String>> {
+            let connection = self.connection.lock().unwrap();
+            let mut statement = connection.prepare(
+                "SELECT DISTINCT key FROM logs WHERE substr(key, 1, length(?1)) = ?1 ORDER BY key",
+            )?;
+            let keys = statement
+                .query_map(params![prefix], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(keys)
+        }
+
+        fn remove(&self, key: &str) -> Result<()> {
+            check_key(key)?;
+            let connection = self.connection.lock().unwrap();
+            connection.execute("DELETE FROM logs WHERE key = ?1", params![key])?;
+            Ok(())
+        }
+    }
+}
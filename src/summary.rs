@@ -0,0 +1,177 @@
+use crate::registry::ConnectionId;
+use anyhow::Result;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::info;
+use wtransport::Connection;
+
+/// Columns of a CSV session log, in `Summary` field order.
+const CSV_HEADER: &str = "id,closed_at_unix_ms,duration_ms,bytes_sent,bytes_received,streams,datagrams,errors,close_reason\n";
+
+/// What one session has done so far, counted by its handlers.
+#[derive(Default)]
+pub struct Tally {
+    streams: AtomicU64,
+    datagrams: AtomicU64,
+    /// Message, publish and relay frames from its streams.
+    messages: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Tally {
+    pub fn stream(&self) {
+        self.streams.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn datagram(&self) {
+        self.datagrams.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn message(&self) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn messages(&self) -> u64 {
+        self.messages.load(Ordering::Relaxed)
+    }
+
+    pub fn datagrams(&self) -> u64 {
+        self.datagrams.load(Ordering::Relaxed)
+    }
+
+    /// An error frame or error response sent to the client.
+    pub fn error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// One closed session, logged as its own event and appended to `--session-log`.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub id: ConnectionId,
+    pub closed_at_unix_ms: u64,
+    pub duration_ms: u64,
+    /// UDP payload bytes, QUIC and HTTP/3 overhead included.
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Bidirectional streams the client opened.
+    pub streams: u64,
+    /// Datagrams the client sent.
+    pub datagrams: u64,
+    /// Error frames and error responses sent to the client.
+    pub errors: u64,
+    pub close_reason: String,
+}
+
+impl Summary {
+    /// Sum up `connection` once it has closed.
+    pub async fn of(
+        id: ConnectionId,
+        connection: &Connection,
+        duration: Duration,
+        tally: &Tally,
+    ) -> Self {
+        let close_reason = connection.closed().await.to_string();
+        let stats = connection.quic_connection().stats();
+        Self {
+            id,
+            closed_at_unix_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            duration_ms: duration.as_millis() as u64,
+            bytes_sent: stats.udp_tx.bytes,
+            bytes_received: stats.udp_rx.bytes,
+            streams: tally.streams.load(Ordering::Relaxed),
+            datagrams: tally.datagrams.load(Ordering::Relaxed),
+            errors: tally.errors.load(Ordering::Relaxed),
+            close_reason,
+        }
+    }
+
+    /// Emit the summary as one event with a field per figure, under the
+    /// `session_summary` target so it can be filtered on its own.
+    pub fn log(&self) {
+        info!(
+            target: "session_summary",
+            id = self.id,
+            duration_ms = self.duration_ms,
+            bytes_sent = self.bytes_sent,
+            bytes_received = self.bytes_received,
+            streams = self.streams,
+            datagrams = self.datagrams,
+            errors = self.errors,
+            close_reason = %self.close_reason,
+            "Connection {} closed",
+            self.id
+        );
+    }
+
+    fn csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},\"{}\"\n",
+            self.id,
+            self.closed_at_unix_ms,
+            self.duration_ms,
+            self.bytes_sent,
+            self.bytes_received,
+            self.streams,
+            self.datagrams,
+            self.errors,
+            self.close_reason.replace('"', "\"\"")
+        )
+    }
+}
+
+// The file given by `--session-log`: CSV if it ends in `.csv`, JSON Lines
+// otherwise. Appended to across runs; a CSV file gets its header when empty.
+pub struct SessionLog {
+    path: PathBuf,
+    csv: bool,
+    /// Held while appending, so rows from sessions closing together don't
+    /// interleave and only one writes the header.
+    lock: Mutex<()>,
+}
+
+impl SessionLog {
+    pub fn new(path: PathBuf) -> Self {
+        let csv = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+        Self {
+            path,
+            csv,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn append(&self, summary: &Summary) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        let line = if self.csv {
+            let mut line = String::new();
+            if file.metadata().await?.len() == 0 {
+                line.push_str(CSV_HEADER);
+            }
+            line.push_str(&summary.csv_row());
+            line
+        } else {
+            serde_json::to_string(summary)? + "\n"
+        };
+        file.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+}
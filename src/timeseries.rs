@@ -0,0 +1,165 @@
+use crate::registry::ConnectionId;
+use crate::state::State;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// Time covered by each sample.
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// Samples a slow dashboard may fall behind by before it skips some.
+const CHANNEL_CAPACITY: usize = 16;
+
+/// One second of server activity.
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    /// End of the second, Unix milliseconds.
+    pub at_unix_ms: u64,
+    /// Live sessions at the end of the second.
+    pub connections: usize,
+    /// Messages received from clients, streams and datagrams together.
+    pub messages: u64,
+    pub bytes: u64,
+    /// Error frames and error responses sent to clients.
+    pub errors: u64,
+    /// QUIC packets sent to clients, and of those declared lost, across the
+    /// sessions live at the end of the second.
+    pub packets_sent: u64,
+    pub packets_lost: u64,
+    /// `packets_lost / packets_sent`, 0 when nothing was sent.
+    pub loss: f64,
+}
+
+/// Body of the `/stats/timeseries` endpoint.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub interval_ms: u64,
+    /// Seconds kept, from `stats.window_minutes`.
+    pub window_secs: usize,
+    /// Oldest first.
+    pub samples: Vec<Sample>,
+}
+
+// Per-second aggregates for the last `stats.window_minutes`, oldest dropped
+// first. Each new sample is also broadcast to dashboards following along.
+pub struct TimeSeries {
+    samples: Mutex<VecDeque<Sample>>,
+    latest: broadcast::Sender<Sample>,
+}
+
+impl TimeSeries {
+    pub fn new() -> Self {
+        Self {
+            samples: Mutex::new(VecDeque::new()),
+            latest: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+
+    /// Every sample from now on, as it's taken.
+    pub fn subscribe(&self) -> broadcast::Receiver<Sample> {
+        self.latest.subscribe()
+    }
+
+    fn push(&self, sample: Sample, capacity: usize) {
+        // No subscribers is fine: no dashboard is open
+        let _ = self.latest.send(sample.clone());
+        let mut samples = self.samples.lock().unwrap();
+        samples.push_back(sample);
+        // Also trims at once when a reload shrinks the window
+        while samples.len() > capacity {
+            samples.pop_front();
+        }
+    }
+
+    /// The newest `seconds` samples, or every one kept.
+    pub fn report(&self, window_secs: usize, seconds: Option<usize>) -> Report {
+        let samples = self.samples.lock().unwrap();
+        let skip = samples
+            .len()
+            .saturating_sub(seconds.unwrap_or(samples.len()));
+        Report {
+            interval_ms: INTERVAL.as_millis() as u64,
+            window_secs,
+            samples: samples.iter().skip(skip).cloned().collect(),
+        }
+    }
+}
+
+// Running totals the next sample is taken as the difference from
+struct Totals {
+    messages: u64,
+    bytes: u64,
+    errors: u64,
+    /// `(sent, lost)` packets of each live session.
+    packets: HashMap<ConnectionId, (u64, u64)>,
+}
+
+impl Totals {
+    fn collect(state: &State) -> Self {
+        let (stream_messages, stream_bytes) = state.stats.stream.totals();
+        let (datagram_messages, datagram_bytes) = state.stats.datagram.totals();
+        let packets = state
+            .registry
+            .all()
+            .into_iter()
+            .map(|(id, connection)| {
+                let path = connection.quic_connection().stats().path;
+                (id, (path.sent_packets, path.lost_packets))
+            })
+            .collect();
+        Self {
+            messages: stream_messages + datagram_messages,
+            bytes: stream_bytes + datagram_bytes,
+            errors: state.stats.errors.total(),
+            packets,
+        }
+    }
+}
+
+// Take a sample every second. A session that opened during the second counts
+// all its packets; one that closed counts none of its last second's.
+pub async fn run(state: Arc<State>) {
+    let mut last = Totals::collect(&state);
+    let mut interval = tokio::time::interval(INTERVAL);
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let now = Totals::collect(&state);
+        let (mut packets_sent, mut packets_lost) = (0, 0);
+        for (id, (sent, lost)) in &now.packets {
+            let (sent_before, lost_before) = last.packets.get(id).copied().unwrap_or_default();
+            packets_sent += sent.saturating_sub(sent_before);
+            packets_lost += lost.saturating_sub(lost_before);
+        }
+        let sample = Sample {
+            at_unix_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            connections: now.packets.len(),
+            messages: now.messages - last.messages,
+            bytes: now.bytes - last.bytes,
+            errors: now.errors - last.errors,
+            packets_sent,
+            packets_lost,
+            loss: if packets_sent == 0 {
+                0.0
+            } else {
+                packets_lost as f64 / packets_sent as f64
+            },
+        };
+
+        let capacity = window_secs(&state);
+        state.timeseries.push(sample, capacity);
+        last = now;
+    }
+}
+
+/// Seconds of samples kept under the live config.
+pub fn window_secs(state: &State) -> usize {
+    state.config.borrow().stats.window_minutes as usize * 60
+}
@@ -0,0 +1,410 @@
+use quinn_proto::transport_parameters::TransportParameters;
+use quinn_proto::{ConnectionId, Side, TransportError};
+use serde::Serialize;
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use wtransport::Connection;
+use wtransport::quinn::crypto::{
+    self, ExportKeyingMaterialError, HeaderKey, KeyPair, Keys, PacketKey, UnsupportedVersion,
+};
+use wtransport::tls::rustls::{CipherSuite, NamedGroup, ProtocolVersion};
+
+/// Exporter label a handshake's parameters are filed under, so the session
+/// accepted on that connection can find them.
+const LABEL: &[u8] = b"EXPORTER-wtransport-playground-tls";
+
+/// How long parameters wait for their session before they're dropped, e.g.
+/// for a connection whose session request was refused.
+const UNCLAIMED: Duration = Duration::from_secs(60);
+
+/// Most handshake bytes kept while looking for the hellos.
+const MAX_BUFFERED: usize = 64 * 1024;
+
+/// Random of a ServerHello that is really a HelloRetryRequest (RFC 8446 4.1.3).
+const HELLO_RETRY_RANDOM: [u8; 32] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91,
+    0xc2, 0xa2, 0x11, 0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+
+const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+const ENCRYPTED_EXTENSIONS: u8 = 8;
+
+const SUPPORTED_GROUPS: u16 = 0x000a;
+const ALPN: u16 = 0x0010;
+const EARLY_DATA: u16 = 0x002a;
+const SUPPORTED_VERSIONS: u16 = 0x002b;
+const KEY_SHARE: u16 = 0x0033;
+
+/// What the client offered in its first ClientHello.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Offered {
+    pub cipher_suites: Vec<String>,
+    pub groups: Vec<String>,
+    /// Groups the client sent a key share for up front.
+    pub key_shares: Vec<String>,
+    pub alpn: Vec<String>,
+    pub early_data: bool,
+}
+
+/// A session's negotiated TLS parameters, as listed by `/connections`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Tls {
+    pub version: Option<String>,
+    pub cipher_suite: Option<String>,
+    pub key_exchange_group: Option<String>,
+    pub alpn: Option<String>,
+    pub server_name: Option<String>,
+    /// Whether the server asked for another key share, the client having
+    /// guessed none it supports.
+    pub hello_retry: bool,
+    /// Whether the server accepted 0-RTT data.
+    pub early_data: bool,
+    pub offered: Offered,
+}
+
+// Parameters of handshakes that finished, until their session is accepted.
+// Neither quinn nor wtransport exposes the negotiated suite or group, so each
+// handshake is read as it passes through quinn: the ClientHello for what the
+// client offered, the ServerHello and EncryptedExtensions for what was chosen.
+pub struct Handshakes {
+    finished: Mutex<HashMap<[u8; 16], (Instant, Tls)>>,
+}
+
+impl Handshakes {
+    pub fn new() -> Self {
+        Self {
+            finished: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn finished(&self, key: [u8; 16], tls: Tls) {
+        let mut finished = self.finished.lock().unwrap();
+        finished.retain(|_, (at, _)| at.elapsed() < UNCLAIMED);
+        finished.insert(key, (Instant::now(), tls));
+    }
+
+    /// The parameters `connection`'s handshake negotiated, if they were read.
+    pub fn take(&self, connection: &Connection) -> Option<Tls> {
+        let mut key = [0; 16];
+        connection
+            .quic_connection()
+            .export_keying_material(&mut key, LABEL, &[])
+            .ok()?;
+        let (_, mut tls) = self.finished.lock().unwrap().remove(&key)?;
+        let handshake = connection.handshake_data();
+        tls.alpn = handshake
+            .alpn()
+            .map(|alpn| String::from_utf8_lossy(alpn).into_owned());
+        tls.server_name = handshake.server_name().map(str::to_string);
+        Some(tls)
+    }
+}
+
+/// Wraps the server's QUIC crypto config to read every handshake into
+/// `handshakes`.
+pub struct Capture {
+    inner: Arc<dyn crypto::ServerConfig>,
+    handshakes: Arc<Handshakes>,
+}
+
+impl Capture {
+    pub fn new(inner: Arc<dyn crypto::ServerConfig>, handshakes: Arc<Handshakes>) -> Self {
+        Self { inner, handshakes }
+    }
+}
+
+impl crypto::ServerConfig for Capture {
+    fn initial_keys(
+        &self,
+        version: u32,
+        dst_cid: &ConnectionId,
+    ) -> Result<Keys, UnsupportedVersion> {
+        self.inner.initial_keys(version, dst_cid)
+    }
+
+    fn retry_tag(&self, version: u32, orig_dst_cid: &ConnectionId, packet: &[u8]) -> [u8; 16] {
+        self.inner.retry_tag(version, orig_dst_cid, packet)
+    }
+
+    fn start_session(
+        self: Arc<Self>,
+        version: u32,
+        params: &TransportParameters,
+    ) -> Box<dyn crypto::Session> {
+        Box::new(Session {
+            inner: self.inner.clone().start_session(version, params),
+            handshakes: self.handshakes.clone(),
+            received: Some(Vec::new()),
+            sent: Some(Vec::new()),
+            tls: Tls::default(),
+            filed: false,
+        })
+    }
+}
+
+struct Session {
+    inner: Box<dyn crypto::Session>,
+    handshakes: Arc<Handshakes>,
+    /// Handshake bytes from and to the client, until the hellos are read.
+    received: Option<Vec<u8>>,
+    sent: Option<Vec<u8>>,
+    tls: Tls,
+    filed: bool,
+}
+
+impl Session {
+    // File the parameters once the handshake is done and keys can be exported
+    fn file(&mut self) {
+        if self.filed || self.inner.is_handshaking() {
+            return;
+        }
+        self.filed = true;
+        let mut key = [0; 16];
+        if self
+            .inner
+            .export_keying_material(&mut key, LABEL, &[])
+            .is_ok()
+        {
+            self.handshakes.finished(key, std::mem::take(&mut self.tls));
+        }
+    }
+}
+
+impl crypto::Session for Session {
+    fn initial_keys(&self, dst_cid: &ConnectionId, side: Side) -> Keys {
+        self.inner.initial_keys(dst_cid, side)
+    }
+
+    fn handshake_data(&self) -> Option<Box<dyn Any>> {
+        self.inner.handshake_data()
+    }
+
+    fn peer_identity(&self) -> Option<Box<dyn Any>> {
+        self.inner.peer_identity()
+    }
+
+    fn early_crypto(&self) -> Option<(Box<dyn HeaderKey>, Box<dyn PacketKey>)> {
+        self.inner.early_crypto()
+    }
+
+    fn early_data_accepted(&self) -> Option<bool> {
+        self.inner.early_data_accepted()
+    }
+
+    fn is_handshaking(&self) -> bool {
+        self.inner.is_handshaking()
+    }
+
+    fn read_handshake(&mut self, buf: &[u8]) -> Result<bool, TransportError> {
+        if let Some(received) = &mut self.received {
+            received.extend_from_slice(buf);
+            if read_client(received, &mut self.tls) || received.len() > MAX_BUFFERED {
+                self.received = None;
+            }
+        }
+        let ready = self.inner.read_handshake(buf);
+        self.file();
+        ready
+    }
+
+    fn transport_parameters(&self) -> Result<Option<TransportParameters>, TransportError> {
+        self.inner.transport_parameters()
+    }
+
+    fn write_handshake(&mut self, buf: &mut Vec<u8>) -> Option<Keys> {
+        let start = buf.len();
+        let keys = self.inner.write_handshake(buf);
+        if let Some(sent) = &mut self.sent {
+            sent.extend_from_slice(&buf[start..]);
+            if read_server(sent, &mut self.tls) || sent.len() > MAX_BUFFERED {
+                self.sent = None;
+            }
+        }
+        self.file();
+        keys
+    }
+
+    fn next_1rtt_keys(&mut self) -> Option<KeyPair<Box<dyn PacketKey>>> {
+        self.inner.next_1rtt_keys()
+    }
+
+    fn is_valid_retry(&self, orig_dst_cid: &ConnectionId, header: &[u8], payload: &[u8]) -> bool {
+        self.inner.is_valid_retry(orig_dst_cid, header, payload)
+    }
+
+    fn export_keying_material(
+        &self,
+        output: &mut [u8],
+        label: &[u8],
+        context: &[u8],
+    ) -> Result<(), ExportKeyingMaterialError> {
+        self.inner.export_keying_material(output, label, context)
+    }
+}
+
+// Read the client's offer from its first ClientHello into `tls`, once it's all
+// in. True once read, or if the client's first message turned out to be
+// something else.
+fn read_client(received: &[u8], tls: &mut Tls) -> bool {
+    let Some((kind, body)) = messages(received).next() else {
+        return false;
+    };
+    if kind == CLIENT_HELLO {
+        tls.offered = offered(body).unwrap_or_default();
+    }
+    true
+}
+
+fn offered(body: &[u8]) -> Option<Offered> {
+    let mut hello = Reader(body);
+    hello.take(2 + 32)?;
+    hello.vec8()?;
+    let mut offered = Offered {
+        cipher_suites: ids(hello.vec16()?)
+            .map(|id| format!("{:?}", CipherSuite::from(id)))
+            .collect(),
+        ..Offered::default()
+    };
+    hello.vec8()?;
+    for (kind, mut data) in extensions(hello.vec16()?) {
+        match kind {
+            SUPPORTED_GROUPS => offered.groups = ids(data.vec16()?).map(group).collect(),
+            KEY_SHARE => {
+                let mut shares = Reader(data.vec16()?);
+                while !shares.0.is_empty() {
+                    let id = shares.u16()?;
+                    shares.vec16()?;
+                    if !is_grease(id) {
+                        offered.key_shares.push(group(id));
+                    }
+                }
+            }
+            ALPN => {
+                let mut protocols = Reader(data.vec16()?);
+                while !protocols.0.is_empty() {
+                    let protocol = protocols.vec8()?;
+                    offered
+                        .alpn
+                        .push(String::from_utf8_lossy(protocol).into_owned());
+                }
+            }
+            EARLY_DATA => offered.early_data = true,
+            _ => {}
+        }
+    }
+    Some(offered)
+}
+
+// Read what the server chose into `tls`: the suite, version and group from the
+// ServerHello (after any HelloRetryRequest), and whether it accepted early data
+// from the EncryptedExtensions that follow. True once both are read.
+fn read_server(sent: &[u8], tls: &mut Tls) -> bool {
+    for (kind, body) in messages(sent) {
+        match kind {
+            // One that can't be read is as far as this gets
+            SERVER_HELLO if chosen(body, tls).is_none() => return true,
+            ENCRYPTED_EXTENSIONS => {
+                if let Some(data) = Reader(body).vec16() {
+                    tls.early_data = extensions(data).any(|(kind, _)| kind == EARLY_DATA);
+                }
+                return true;
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+fn chosen(body: &[u8], tls: &mut Tls) -> Option<()> {
+    let mut hello = Reader(body);
+    hello.take(2)?;
+    if hello.take(32)? == HELLO_RETRY_RANDOM {
+        tls.hello_retry = true;
+    }
+    hello.vec8()?;
+    tls.cipher_suite = Some(format!("{:?}", CipherSuite::from(hello.u16()?)));
+    hello.u8()?;
+    for (kind, mut data) in extensions(hello.vec16()?) {
+        match kind {
+            SUPPORTED_VERSIONS => {
+                tls.version = Some(format!("{:?}", ProtocolVersion::from(data.u16()?)));
+            }
+            // A HelloRetryRequest names the group it wants; a ServerHello
+            // starts its share with the group
+            KEY_SHARE => tls.key_exchange_group = Some(group(data.u16()?)),
+            _ => {}
+        }
+    }
+    Some(())
+}
+
+// Complete handshake messages at the start of `buf`, as (type, body)
+fn messages(buf: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut reader = Reader(buf);
+    std::iter::from_fn(move || {
+        let kind = reader.u8()?;
+        let len = reader.u24()?;
+        Some((kind, reader.take(len)?))
+    })
+}
+
+// A hello's extensions, as (type, data)
+fn extensions(buf: &[u8]) -> impl Iterator<Item = (u16, Reader<'_>)> {
+    let mut reader = Reader(buf);
+    std::iter::from_fn(move || {
+        let kind = reader.u16()?;
+        Some((kind, Reader(reader.vec16()?)))
+    })
+}
+
+// A list of 16-bit ids, GREASE (RFC 8701) left out
+fn ids(buf: &[u8]) -> impl Iterator<Item = u16> {
+    buf.chunks_exact(2)
+        .map(|id| u16::from_be_bytes([id[0], id[1]]))
+        .filter(|&id| !is_grease(id))
+}
+
+fn is_grease(id: u16) -> bool {
+    id & 0x0f0f == 0x0a0a && id >> 8 == id & 0xff
+}
+
+fn group(id: u16) -> String {
+    format!("{:?}", NamedGroup::from(id))
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (taken, rest) = self.0.split_at_checked(len)?;
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.take(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        let bytes = self.take(3)?;
+        Some(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) as usize)
+    }
+
+    fn vec8(&mut self) -> Option<&'a [u8]> {
+        let len = self.u8()? as usize;
+        self.take(len)
+    }
+
+    fn vec16(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+}
@@ -0,0 +1,563 @@
+use crate::config::TransferConfig;
+use crate::registry::ConnectionId;
+use crate::storage::{self, Storage, Writer};
+use protocol::transfer::{self, EMPTY_CHECKSUM, MAX_CHUNK_LEN};
+use protocol::{Frame, MAX_MESSAGE_ID_LEN, PlaygroundError};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+/// Finished transfers listed by `/admin/transfers`, newest last.
+const RECENT: usize = 32;
+
+/// Unfinished transfers held at once, across every client.
+const MAX_PENDING: usize = 1024;
+
+/// Longest file name a transfer may have, in bytes.
+const MAX_NAME_LEN: usize = 255;
+
+/// Storage prefix of the transfers' logs, one per transfer id.
+const STORAGE_PREFIX: &str = "transfers";
+
+// A transfer still waiting for bytes
+struct Pending {
+    name: String,
+    size: u64,
+    /// Bytes received so far, and their checksum.
+    offset: u64,
+    checksum: u32,
+    /// Connection its last chunk or resume came from, or 0 if it was
+    /// reloaded from storage at startup.
+    connection: ConnectionId,
+    /// Times it was picked up again with a `resume_transfer`.
+    resumes: u32,
+    started_at: Instant,
+    started_at_unix_ms: u64,
+    /// When it last moved, for `transfers.keep_secs`.
+    touched_at: Instant,
+}
+
+impl Pending {
+    fn ack(&self, id: &str) -> Frame {
+        Frame::TransferAck {
+            id: id.to_string(),
+            offset: self.offset,
+            checksum: self.checksum,
+        }
+    }
+
+    fn saved(&self) -> Saved {
+        Saved {
+            name: self.name.clone(),
+            size: self.size,
+            offset: self.offset,
+            checksum: self.checksum,
+            resumes: self.resumes,
+            started_at_unix_ms: self.started_at_unix_ms,
+            touched_at_unix_ms: unix_ms(),
+        }
+    }
+}
+
+// A transfer's progress as kept in storage, rewritten each time it moves.
+// One whose offset reached its size is finished.
+#[derive(Serialize, Deserialize)]
+struct Saved {
+    name: String,
+    size: u64,
+    offset: u64,
+    checksum: u32,
+    resumes: u32,
+    started_at_unix_ms: u64,
+    touched_at_unix_ms: u64,
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+// When something `ago_ms` milliseconds back happened, on the monotonic clock
+fn instant_ago(ago_ms: u64) -> Instant {
+    let now = Instant::now();
+    now.checked_sub(Duration::from_millis(ago_ms))
+        .unwrap_or(now)
+}
+
+/// A transfer all of whose bytes arrived.
+#[derive(Debug, Clone, Serialize)]
+pub struct Finished {
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+    pub checksum: u32,
+    pub resumes: u32,
+    pub duration_ms: u64,
+}
+
+/// An unfinished transfer, as listed by `/admin/transfers`.
+#[derive(Debug, Serialize)]
+pub struct Unfinished {
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+    pub offset: u64,
+    pub connection: ConnectionId,
+    pub resumes: u32,
+    pub idle_ms: u64,
+}
+
+/// Running totals since the server started, not counting transfers reloaded
+/// from storage.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Totals {
+    pub started: u64,
+    pub finished: u64,
+    pub resumed: u64,
+    /// Unfinished transfers forgotten after `transfers.keep_secs` idle.
+    pub expired: u64,
+    /// Chunk bytes taken, and chunks refused (wrong offset, too big, not hex).
+    pub bytes: u64,
+    pub rejected_chunks: u64,
+}
+
+/// Body of the `/admin/transfers` endpoint.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub unfinished: Vec<Unfinished>,
+    pub finished: Vec<Finished>,
+    pub totals: Totals,
+}
+
+struct Inner {
+    pending: HashMap<String, Pending>,
+    finished: VecDeque<Finished>,
+    totals: Totals,
+    writer: Writer,
+}
+
+impl Inner {
+    // Forget unfinished transfers idle for longer than `keep`
+    fn purge(&mut self, keep: Duration) {
+        let totals = &mut self.totals;
+        let writer = &self.writer;
+        self.pending.retain(|id, pending| {
+            let keep = pending.touched_at.elapsed() < keep;
+            if !keep {
+                debug!(
+                    "Transfer {} expired at {} of {} bytes",
+                    id, pending.offset, pending.size
+                );
+                totals.expired += 1;
+                forget(writer, id);
+            }
+            keep
+        });
+    }
+
+    // Queue pending transfer `id`'s progress for storage
+    fn save(&self, id: &str) {
+        let Some(pending) = self.pending.get(id) else {
+            return;
+        };
+        let result = serde_json::to_vec(&pending.saved())
+            .map_err(anyhow::Error::from)
+            .and_then(|record| {
+                self.writer
+                    .append(storage::name_key(STORAGE_PREFIX, id), record, 1)
+            });
+        if let Err(e) = result {
+            warn!("Failed to save transfer {}: {:#}", id, e);
+        }
+    }
+
+    // Take back the transfers an earlier run saved, forgetting those idle
+    // for longer than `keep` and all but the latest `RECENT` finished ones
+    fn reload(&mut self, saved: Vec<(String, Saved)>, keep: Duration) {
+        let now = unix_ms();
+        let mut finished = Vec::new();
+        for (id, saved) in saved {
+            let idle_ms = now.saturating_sub(saved.touched_at_unix_ms);
+            if saved.offset >= saved.size {
+                finished.push((id, saved));
+            } else if Duration::from_millis(idle_ms) < keep {
+                let pending = Pending {
+                    name: saved.name,
+                    size: saved.size,
+                    offset: saved.offset,
+                    checksum: saved.checksum,
+                    connection: 0,
+                    resumes: saved.resumes,
+                    started_at: instant_ago(now.saturating_sub(saved.started_at_unix_ms)),
+                    started_at_unix_ms: saved.started_at_unix_ms,
+                    touched_at: instant_ago(idle_ms),
+                };
+                self.pending.insert(id, pending);
+            } else {
+                forget(&self.writer, &id);
+            }
+        }
+        finished.sort_by_key(|(_, saved)| saved.touched_at_unix_ms);
+        let stale = finished.len().saturating_sub(RECENT);
+        for (id, _) in finished.drain(..stale) {
+            forget(&self.writer, &id);
+        }
+        self.finished = finished
+            .into_iter()
+            .map(|(id, saved)| Finished {
+                id,
+                name: saved.name,
+                size: saved.size,
+                checksum: saved.checksum,
+                resumes: saved.resumes,
+                duration_ms: saved
+                    .touched_at_unix_ms
+                    .saturating_sub(saved.started_at_unix_ms),
+            })
+            .collect();
+        if !self.pending.is_empty() || !self.finished.is_empty() {
+            info!(
+                "Reloaded {} unfinished and {} finished transfer(s) from storage",
+                self.pending.len(),
+                self.finished.len()
+            );
+        }
+    }
+
+    fn finished(&self, id: &str) -> Option<&Finished> {
+        self.finished.iter().find(|finished| finished.id == id)
+    }
+
+    // Move `id` to the finished list if all its bytes are in
+    fn finish_if_done(&mut self, id: &str) {
+        if self.pending.get(id).is_none_or(|p| p.offset < p.size) {
+            return;
+        }
+        let Some(pending) = self.pending.remove(id) else {
+            return;
+        };
+        info!(
+            "Transfer {} of '{}' finished: {} bytes, checksum {:08x}, {} resume(s)",
+            id, pending.name, pending.size, pending.checksum, pending.resumes
+        );
+        self.totals.finished += 1;
+        if self.finished.len() == RECENT
+            && let Some(oldest) = self.finished.pop_front()
+        {
+            forget(&self.writer, &oldest.id);
+        }
+        self.finished.push_back(Finished {
+            id: id.to_string(),
+            name: pending.name,
+            size: pending.size,
+            checksum: pending.checksum,
+            resumes: pending.resumes,
+            duration_ms: pending.started_at.elapsed().as_millis() as u64,
+        });
+    }
+}
+
+// The latest record of each transfer in storage. One that can't be read is
+// logged and skipped.
+fn load_saved(storage: &dyn Storage) -> anyhow::Result<Vec<(String, Saved)>> {
+    let mut saved = Vec::new();
+    for key in storage.logs(&format!("{}/", STORAGE_PREFIX))? {
+        let Some(id) = storage::key_name(STORAGE_PREFIX, &key) else {
+            continue;
+        };
+        let Some(record) = storage.tail(&key, 1)?.pop() else {
+            continue;
+        };
+        match serde_json::from_slice(&record) {
+            Ok(record) => saved.push((id, record)),
+            Err(e) => warn!("Skipping unreadable transfer {}: {}", id, e),
+        }
+    }
+    Ok(saved)
+}
+
+// Queue transfer `id`'s log for removal from storage
+fn forget(writer: &Writer, id: &str) {
+    if let Err(e) = writer.remove(storage::name_key(STORAGE_PREFIX, id)) {
+        warn!("Failed to forget transfer {}: {:#}", id, e);
+    }
+}
+
+fn unknown(id: &str) -> PlaygroundError {
+    PlaygroundError::InvalidParams(format!("unknown or expired transfer {:?}", id))
+}
+
+// Files clients upload in chunks, resumable from another session (see
+// `protocol::transfer`). The server checks each chunk follows on from the
+// last and keeps the running checksum, but not the bytes: like the bench, a
+// transfer is a demo of moving data, and the checksum is what a resuming
+// client needs. Transfers are keyed by the client's id alone, so whoever has
+// it can resume one. Progress is kept in storage too, so with a persistent
+// `storage.backend` a transfer can be resumed after a restart.
+pub struct Transfers {
+    inner: Mutex<Inner>,
+}
+
+impl Transfers {
+    /// Pick up the transfers `storage` holds, saving progress from here on
+    /// through `writer`. Those idle past `transfers.keep_secs` are forgotten,
+    /// and a storage failure is logged and the transfers started afresh.
+    pub fn load(storage: &dyn Storage, writer: Writer, config: &TransferConfig) -> Self {
+        let mut inner = Inner {
+            pending: HashMap::new(),
+            finished: VecDeque::new(),
+            totals: Totals::default(),
+            writer,
+        };
+        match load_saved(storage) {
+            Ok(saved) => inner.reload(saved, Duration::from_secs(config.keep_secs)),
+            Err(e) => warn!("Failed to load transfers from storage: {:#}", e),
+        }
+        Self {
+            inner: Mutex::new(inner),
+        }
+    }
+
+    /// Start transfer `id`, answering with its first ack. Starting it again
+    /// with the same name and size acks where it stands.
+    pub fn start(
+        &self,
+        connection: ConnectionId,
+        id: &str,
+        name: &str,
+        size: u64,
+        config: &TransferConfig,
+    ) -> Result<Frame, PlaygroundError> {
+        if id.is_empty() || id.len() > MAX_MESSAGE_ID_LEN {
+            return Err(PlaygroundError::InvalidParams(format!(
+                "transfer id must be 1 to {} bytes",
+                MAX_MESSAGE_ID_LEN
+            )));
+        }
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(PlaygroundError::InvalidParams(format!(
+                "transfer name must be 1 to {} bytes",
+                MAX_NAME_LEN
+            )));
+        }
+        if size > config.max_size {
+            return Err(PlaygroundError::QuotaExceeded(format!(
+                "transfers are limited to {} bytes",
+                config.max_size
+            )));
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.purge(Duration::from_secs(config.keep_secs));
+        if let Some(pending) = inner.pending.get_mut(id) {
+            if pending.name != name || pending.size != size {
+                return Err(PlaygroundError::InvalidParams(format!(
+                    "transfer {:?} is already sending another file",
+                    id
+                )));
+            }
+            pending.connection = connection;
+            pending.touched_at = Instant::now();
+            let ack = pending.ack(id);
+            inner.save(id);
+            return Ok(ack);
+        }
+        if let Some(finished) = inner.finished(id) {
+            return Ok(Frame::TransferAck {
+                id: id.to_string(),
+                offset: finished.size,
+                checksum: finished.checksum,
+            });
+        }
+        if inner.pending.len() >= MAX_PENDING {
+            return Err(PlaygroundError::QuotaExceeded(format!(
+                "{} transfers are already unfinished",
+                MAX_PENDING
+            )));
+        }
+
+        info!(
+            "Connection {} started transfer {} of '{}' ({} bytes)",
+            connection, id, name, size
+        );
+        inner.totals.started += 1;
+        let now = Instant::now();
+        let pending = Pending {
+            name: name.to_string(),
+            size,
+            offset: 0,
+            checksum: EMPTY_CHECKSUM,
+            connection,
+            resumes: 0,
+            started_at: now,
+            started_at_unix_ms: unix_ms(),
+            touched_at: now,
+        };
+        let ack = pending.ack(id);
+        inner.pending.insert(id.to_string(), pending);
+        inner.save(id);
+        // An empty file is done as soon as it starts
+        inner.finish_if_done(id);
+        Ok(ack)
+    }
+
+    /// Take transfer `id`'s bytes from `offset`, hex-encoded in `data`, and
+    /// ack how far it has got.
+    pub fn chunk(
+        &self,
+        connection: ConnectionId,
+        id: &str,
+        offset: u64,
+        data: &str,
+        config: &TransferConfig,
+    ) -> Result<Frame, PlaygroundError> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.purge(Duration::from_secs(config.keep_secs));
+        let Some(pending) = inner.pending.get_mut(id) else {
+            return Err(unknown(id));
+        };
+        let rejected = match transfer::decode_chunk(data) {
+            None => Err("data must be hex".to_string()),
+            Some(bytes) if bytes.len() > MAX_CHUNK_LEN => {
+                Err(format!("a chunk carries at most {} bytes", MAX_CHUNK_LEN))
+            }
+            Some(_) if offset != pending.offset => Err(format!(
+                "transfer {:?} continues at offset {}, not {}",
+                id, pending.offset, offset
+            )),
+            Some(bytes) if offset + bytes.len() as u64 > pending.size => {
+                Err(format!("transfer {:?} is only {} bytes", id, pending.size))
+            }
+            Some(bytes) => Ok(bytes),
+        };
+        let bytes = match rejected {
+            Ok(bytes) => bytes,
+            Err(error) => {
+                inner.totals.rejected_chunks += 1;
+                return Err(PlaygroundError::InvalidParams(error));
+            }
+        };
+
+        pending.offset += bytes.len() as u64;
+        pending.checksum = transfer::checksum(pending.checksum, &bytes);
+        pending.connection = connection;
+        pending.touched_at = Instant::now();
+        let ack = pending.ack(id);
+        inner.totals.bytes += bytes.len() as u64;
+        inner.save(id);
+        inner.finish_if_done(id);
+        Ok(ack)
+    }
+
+    /// Ack how far transfer `id` got, for a client picking it up again.
+    pub fn resume(
+        &self,
+        connection: ConnectionId,
+        id: &str,
+        config: &TransferConfig,
+    ) -> Result<Frame, PlaygroundError> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.purge(Duration::from_secs(config.keep_secs));
+        if let Some(finished) = inner.finished(id) {
+            return Ok(Frame::TransferAck {
+                id: id.to_string(),
+                offset: finished.size,
+                checksum: finished.checksum,
+            });
+        }
+        let Some(pending) = inner.pending.get_mut(id) else {
+            return Err(unknown(id));
+        };
+        info!(
+            "Connection {} resumed transfer {} at {} of {} bytes",
+            connection, id, pending.offset, pending.size
+        );
+        pending.resumes += 1;
+        pending.connection = connection;
+        pending.touched_at = Instant::now();
+        let ack = pending.ack(id);
+        inner.totals.resumed += 1;
+        inner.save(id);
+        Ok(ack)
+    }
+
+    pub fn report(&self) -> Report {
+        let inner = self.inner.lock().unwrap();
+        let mut unfinished: Vec<Unfinished> = inner
+            .pending
+            .iter()
+            .map(|(id, pending)| Unfinished {
+                id: id.clone(),
+                name: pending.name.clone(),
+                size: pending.size,
+                offset: pending.offset,
+                connection: pending.connection,
+                resumes: pending.resumes,
+                idle_ms: pending.touched_at.elapsed().as_millis() as u64,
+            })
+            .collect();
+        unfinished.sort_by(|a, b| a.id.cmp(&b.id));
+        Report {
+            unfinished,
+            finished: inner.finished.iter().cloned().collect(),
+            totals: inner.totals,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use std::sync::Arc;
+    use std::thread;
+
+    // Wait for the writer to get transfer `id` to `offset` in storage
+    fn wait_for_offset(storage: &dyn Storage, id: &str, offset: u64) {
+        let key = storage::name_key(STORAGE_PREFIX, id);
+        for _ in 0..200 {
+            let saved = storage.tail(&key, 1).unwrap().pop();
+            if saved.is_some_and(|record| {
+                serde_json::from_slice::<Saved>(&record).unwrap().offset == offset
+            }) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("transfer {} never reached offset {} in storage", id, offset);
+    }
+
+    #[test]
+    fn progress_survives_a_reload() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let config = TransferConfig::default();
+        let transfers = Transfers::load(storage.as_ref(), Writer::spawn(storage.clone()), &config);
+        let bytes = b"hello, ";
+        transfers.start(1, "a", "notes.txt", 12, &config).unwrap();
+        transfers
+            .chunk(1, "a", 0, &transfer::encode_chunk(bytes), &config)
+            .unwrap();
+        transfers.start(1, "b", "empty.txt", 0, &config).unwrap();
+        wait_for_offset(storage.as_ref(), "a", 7);
+        wait_for_offset(storage.as_ref(), "b", 0);
+
+        let reloaded = Transfers::load(storage.as_ref(), Writer::spawn(storage.clone()), &config);
+        let ack = reloaded.resume(2, "a", &config).unwrap();
+        assert_eq!(
+            ack,
+            Frame::TransferAck {
+                id: "a".to_string(),
+                offset: 7,
+                checksum: transfer::checksum(EMPTY_CHECKSUM, bytes),
+            }
+        );
+        let report = reloaded.report();
+        assert_eq!(report.unfinished.len(), 1);
+        assert_eq!(report.finished.len(), 1);
+        assert_eq!(report.finished[0].id, "b");
+    }
+}
@@ -0,0 +1,142 @@
+use crate::registry::ConnectionId;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many violations are kept for the admin API.
+const LOG_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    /// A frame whose body doesn't decode.
+    MalformedFrame,
+    /// A frame over `protocol::MAX_FRAME_LEN`, or more undecoded input than the
+    /// decoder buffers. The stream can't be resynchronized after one.
+    Oversized,
+    /// A numbered message whose `seq` repeats or goes back on its stream.
+    BadSequence,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    pub timestamp_ms: u64,
+    pub connection: ConnectionId,
+    pub kind: Kind,
+    pub detail: String,
+    /// The connection's violation count including this one.
+    pub strike: u32,
+    /// Whether this strike closed the connection.
+    pub closed: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Counters {
+    pub malformed_frame: u64,
+    pub oversized: u64,
+    pub bad_sequence: u64,
+    /// Connections closed for reaching the strike threshold.
+    pub closed: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub counters: Counters,
+    pub log: Vec<LogEntry>,
+}
+
+// Strikes against one connection, shared by all of its streams.
+pub struct Strikes {
+    count: AtomicU32,
+    /// Set by the strike that closed the connection.
+    closed: AtomicBool,
+}
+
+impl Strikes {
+    pub fn new() -> Self {
+        Self {
+            count: AtomicU32::new(0),
+            closed: AtomicBool::new(false),
+        }
+    }
+}
+
+// Protocol violations across every connection, served at `/admin/violations`.
+pub struct Violations {
+    malformed_frame: AtomicU64,
+    oversized: AtomicU64,
+    bad_sequence: AtomicU64,
+    closed: AtomicU64,
+    log: Mutex<VecDeque<LogEntry>>,
+}
+
+impl Violations {
+    pub fn new() -> Self {
+        Self {
+            malformed_frame: AtomicU64::new(0),
+            oversized: AtomicU64::new(0),
+            bad_sequence: AtomicU64::new(0),
+            closed: AtomicU64::new(0),
+            log: Mutex::new(VecDeque::with_capacity(LOG_CAPACITY)),
+        }
+    }
+
+    /// Count a violation against `connection` and log it. Returns the
+    /// connection's strike count if this one reaches `threshold`, meaning the
+    /// caller should close it; only one strike per connection does.
+    pub fn record(
+        &self,
+        strikes: &Strikes,
+        connection: ConnectionId,
+        kind: Kind,
+        detail: String,
+        threshold: u32,
+    ) -> Option<u32> {
+        let counter = match kind {
+            Kind::MalformedFrame => &self.malformed_frame,
+            Kind::Oversized => &self.oversized,
+            Kind::BadSequence => &self.bad_sequence,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        let strike = strikes.count.fetch_add(1, Ordering::Relaxed) + 1;
+        // At or past it, in case a reload lowered the threshold meanwhile
+        let closed = strike >= threshold && !strikes.closed.swap(true, Ordering::Relaxed);
+        if closed {
+            self.closed.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let mut log = self.log.lock().unwrap();
+        if log.len() == LOG_CAPACITY {
+            log.pop_front();
+        }
+        log.push_back(LogEntry {
+            timestamp_ms,
+            connection,
+            kind,
+            detail,
+            strike,
+            closed,
+        });
+
+        closed.then_some(strike)
+    }
+
+    pub fn report(&self) -> Report {
+        Report {
+            counters: Counters {
+                malformed_frame: self.malformed_frame.load(Ordering::Relaxed),
+                oversized: self.oversized.load(Ordering::Relaxed),
+                bad_sequence: self.bad_sequence.load(Ordering::Relaxed),
+                closed: self.closed.load(Ordering::Relaxed),
+            },
+            log: self.log.lock().unwrap().iter().cloned().collect(),
+        }
+    }
+}
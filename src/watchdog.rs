@@ -0,0 +1,65 @@
+use crate::admin::AdminEvent;
+use crate::registry::ConnectionId;
+use crate::state::State;
+use protocol::CloseCode;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::error;
+use wtransport::{Connection, VarInt};
+
+/// How often a connection's handler beats, and how often its watchdog looks.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When a connection's handler last got round to its heartbeat.
+pub struct Heartbeat {
+    since: Instant,
+    /// Milliseconds after `since`.
+    last_ms: AtomicU64,
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Self {
+            since: Instant::now(),
+            last_ms: AtomicU64::new(0),
+        }
+    }
+
+    pub fn beat(&self) {
+        let ms = self.since.elapsed().as_millis() as u64;
+        self.last_ms.fetch_max(ms, Ordering::Relaxed);
+    }
+
+    fn stalled(&self) -> Duration {
+        self.since.elapsed() - Duration::from_millis(self.last_ms.load(Ordering::Relaxed))
+    }
+}
+
+// Wait until the handler has gone `watchdog.deadline_ms` without a heartbeat,
+// and return how long it has been stuck. Never returns while the watchdog is off
+pub async fn expired(state: &State, heartbeat: &Heartbeat) -> Duration {
+    let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        interval.tick().await;
+        let deadline_ms = state.config.borrow().watchdog.deadline_ms;
+        let stalled = heartbeat.stalled();
+        if deadline_ms > 0 && stalled >= Duration::from_millis(deadline_ms) {
+            return stalled;
+        }
+    }
+}
+
+/// Close a connection whose handler was aborted, wedged or panicked, as a
+/// server error, and tell admin consoles.
+pub fn close(state: &State, id: ConnectionId, connection: &Connection, reason: &str) {
+    state.stats.wedged.fetch_add(1, Ordering::Relaxed);
+    error!("Closing connection {}: handler {}", id, reason);
+    connection.close(
+        VarInt::from_u32(CloseCode::ServerError.code()),
+        reason.as_bytes(),
+    );
+    state.admin.publish(AdminEvent::HandlerAborted {
+        connection: id,
+        reason: reason.to_string(),
+    });
+}
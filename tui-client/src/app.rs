@@ -0,0 +1,344 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use protocol::Frame;
+use protocol::room::{self, RelayTransport, valid_room};
+
+/// How many lines of history are kept.
+const MAX_LINES: usize = 1000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    System,
+    Sent,
+    Received,
+    Error,
+}
+
+/// How messages to the server (not to a room) are sent.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Stream,
+    Datagram,
+}
+
+/// Something the event loop has to do on the session.
+pub enum Action {
+    Send(Frame),
+    Datagram(String),
+}
+
+pub struct App {
+    pub nick: String,
+    pub lines: Vec<(Kind, String)>,
+    pub input: String,
+    /// Joined rooms, in the order they were joined.
+    pub rooms: Vec<String>,
+    /// Room plain input goes to; None sends it to the server, which echoes it.
+    pub target: Option<String>,
+    pub transport: Transport,
+    /// Smoothed RTT from the server's latest bandwidth estimate.
+    pub rtt_ms: Option<f64>,
+    pub connected: bool,
+    pub quit: bool,
+}
+
+impl App {
+    pub fn new(nick: String) -> Self {
+        Self {
+            nick,
+            lines: Vec::new(),
+            input: String::new(),
+            rooms: Vec::new(),
+            target: None,
+            transport: Transport::Stream,
+            rtt_ms: None,
+            connected: true,
+            quit: false,
+        }
+    }
+
+    pub fn push(&mut self, kind: Kind, text: impl Into<String>) {
+        self.lines.push((kind, text.into()));
+        if self.lines.len() > MAX_LINES {
+            self.lines.remove(0);
+        }
+    }
+
+    pub fn on_key(&mut self, key: KeyEvent) -> Vec<Action> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => self.quit = true,
+            KeyCode::Char('c') if ctrl => self.quit = true,
+            KeyCode::Char('t') if ctrl => self.toggle_transport(),
+            KeyCode::Tab => self.next_target(),
+            KeyCode::Enter => return self.submit(),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Char(c) if !ctrl => self.input.push(c),
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    fn submit(&mut self) -> Vec<Action> {
+        let input = std::mem::take(&mut self.input);
+        let input = input.trim();
+        if input.is_empty() {
+            return Vec::new();
+        }
+        let Some(command) = input.strip_prefix('/') else {
+            return self.say(input);
+        };
+
+        let (command, arg) = match command.split_once(' ') {
+            Some((command, arg)) => (command, Some(arg.trim())),
+            None => (command, None),
+        };
+        match (command, arg) {
+            ("join", Some(room)) => return self.join(room),
+            ("leave", room) => return self.leave(room),
+            ("room", room) => self.switch(room),
+            ("kick" | "mute" | "unmute", Some(member)) => return self.control(command, member),
+            ("dgram", None) => self.toggle_transport(),
+            ("quit", None) => self.quit = true,
+            ("help", None) => self.help(),
+            _ => self.push(
+                Kind::Error,
+                format!("Unknown command /{}; /help lists them", command),
+            ),
+        }
+        Vec::new()
+    }
+
+    fn say(&mut self, text: &str) -> Vec<Action> {
+        match (&self.target, self.transport) {
+            (Some(room), _) => {
+                let frame = Frame::Relay {
+                    room: room.clone(),
+                    from: None,
+                    nick: None,
+                    text: text.to_string(),
+                    replayed: false,
+                    id: None,
+                    timing: None,
+                };
+                self.push(Kind::Sent, format!("[{}] {}: {}", room, self.nick, text));
+                vec![Action::Send(frame)]
+            }
+            (None, Transport::Stream) => {
+                self.push(Kind::Sent, format!("[stream] {}", text));
+                vec![Action::Send(Frame::Message {
+                    text: text.to_string(),
+                    seq: None,
+                    id: None,
+                    timing: None,
+                })]
+            }
+            (None, Transport::Datagram) => {
+                self.push(Kind::Sent, format!("[datagram] {}", text));
+                vec![Action::Datagram(text.to_string())]
+            }
+        }
+    }
+
+    pub fn join(&mut self, room: &str) -> Vec<Action> {
+        if !valid_room(room) {
+            self.push(
+                Kind::Error,
+                format!("Room names are 1 to {} bytes", room::MAX_ROOM_LEN),
+            );
+            return Vec::new();
+        }
+        if !self.rooms.iter().any(|r| r == room) {
+            self.rooms.push(room.to_string());
+        }
+        self.target = Some(room.to_string());
+        self.push(Kind::System, format!("Joined room '{}'", room));
+        // Relay datagrams would arrive as raw text, so stick to the stream
+        vec![Action::Send(Frame::Join {
+            room: room.to_string(),
+            transport: Some(RelayTransport::Stream),
+        })]
+    }
+
+    fn leave(&mut self, room: Option<&str>) -> Vec<Action> {
+        let Some(room) = room.map(str::to_string).or_else(|| self.target.clone()) else {
+            self.push(Kind::Error, "Not in a room; /leave <room>");
+            return Vec::new();
+        };
+        if !self.rooms.contains(&room) {
+            self.push(Kind::Error, format!("Not in room '{}'", room));
+            return Vec::new();
+        }
+        self.rooms.retain(|r| *r != room);
+        if self.target.as_ref() == Some(&room) {
+            self.target = self.rooms.last().cloned();
+        }
+        self.push(Kind::System, format!("Left room '{}'", room));
+        vec![Action::Send(Frame::Leave { room })]
+    }
+
+    // Have the server kick, mute or unmute a member of the current room
+    fn control(&mut self, command: &str, member: &str) -> Vec<Action> {
+        let Some(room) = self.target.clone() else {
+            self.push(
+                Kind::Error,
+                format!("Not in a room; /room <room>, then /{}", command),
+            );
+            return Vec::new();
+        };
+        let Ok(member) = member.trim_start_matches('#').parse() else {
+            self.push(
+                Kind::Error,
+                format!("/{} takes a connection id, e.g. #7", command),
+            );
+            return Vec::new();
+        };
+        vec![Action::Send(match command {
+            "kick" => Frame::Kick { room, member },
+            "mute" => Frame::Mute { room, member },
+            _ => Frame::Unmute { room, member },
+        })]
+    }
+
+    fn switch(&mut self, room: Option<&str>) {
+        match room {
+            None => self.target = None,
+            Some(room) if self.rooms.iter().any(|r| r == room) => {
+                self.target = Some(room.to_string())
+            }
+            Some(room) => self.push(
+                Kind::Error,
+                format!("Not in room '{}'; /join it first", room),
+            ),
+        }
+    }
+
+    // Cycle plain input through the server and each joined room
+    fn next_target(&mut self) {
+        let next = match &self.target {
+            None => 0,
+            Some(room) => self
+                .rooms
+                .iter()
+                .position(|r| r == room)
+                .map_or(0, |i| i + 1),
+        };
+        self.target = self.rooms.get(next).cloned();
+    }
+
+    fn toggle_transport(&mut self) {
+        self.transport = match self.transport {
+            Transport::Stream => Transport::Datagram,
+            Transport::Datagram => Transport::Stream,
+        };
+        let note = if self.target.is_some() {
+            " (rooms always use the stream)"
+        } else {
+            ""
+        };
+        let transport = match self.transport {
+            Transport::Stream => "stream",
+            Transport::Datagram => "datagrams",
+        };
+        self.push(
+            Kind::System,
+            format!("Messages to the server now go as {}{}", transport, note),
+        );
+    }
+
+    fn help(&mut self) {
+        for line in [
+            "/join <room>    join a room and talk in it",
+            "/leave [room]   leave a room (the current one by default)",
+            "/room [room]    talk in a joined room, or to the server with no room",
+            "/kick <id>      kick a connection out of the current room (owner only)",
+            "/mute <id>      stop relaying a connection's messages, /unmute to undo",
+            "/dgram          toggle stream/datagrams for messages to the server (Ctrl+T)",
+            "/quit           disconnect (Esc)",
+            "Tab cycles between the server and joined rooms",
+        ] {
+            self.push(Kind::System, line);
+        }
+    }
+
+    pub fn on_frame(&mut self, frame: Frame) {
+        match frame {
+            Frame::Message { text, .. } => self.push(Kind::Received, text),
+            Frame::Relay {
+                room,
+                from,
+                nick,
+                text,
+                replayed,
+                ..
+            } => {
+                let from = nick.unwrap_or_else(|| match from {
+                    Some(id) => format!("#{}", id),
+                    None => "?".to_string(),
+                });
+                let replayed = if replayed { " (replayed)" } else { "" };
+                self.push(
+                    Kind::Received,
+                    format!("[{}] {}: {}{}", room, from, text, replayed),
+                );
+            }
+            Frame::RoomNotice {
+                room,
+                action,
+                member,
+                by,
+            } => self.push(
+                Kind::System,
+                match by {
+                    Some(by) => format!("[{}] #{} {} by #{}", room, member, action.as_str(), by),
+                    None => format!("[{}] #{} owns the room", room, member),
+                },
+            ),
+            Frame::Error { error } => self.push(Kind::Error, format!("Server error: {}", error)),
+            Frame::QuotaWarning { used, limit } => self.push(
+                Kind::System,
+                format!("Used {} of the session's {} byte quota", used, limit),
+            ),
+            Frame::BandwidthEstimate(estimate) => {
+                self.rtt_ms = Some(estimate.rtt_us as f64 / 1000.0)
+            }
+            Frame::Aggregate(aggregate) => self.push(
+                Kind::Received,
+                format!(
+                    "[{}] {} messages: {}",
+                    aggregate.topic,
+                    aggregate.count,
+                    aggregate.payloads.join(" | ")
+                ),
+            ),
+            Frame::Redirect { url } => self.push(
+                Kind::System,
+                format!("Server asked us to move to {}; reconnect with --url", url),
+            ),
+            Frame::Hello(info) => self.push(
+                Kind::System,
+                format!(
+                    "Server {} {} ({}), protocol {:?}, {} messages/s limit",
+                    info.build.name,
+                    info.build.version,
+                    info.build.profile,
+                    info.protocol_versions,
+                    info.limits.messages_per_second
+                ),
+            ),
+            _ => {}
+        }
+    }
+
+    pub fn on_datagram(&mut self, text: String) {
+        self.push(Kind::Received, format!("[datagram] {}", text));
+    }
+
+    pub fn on_closed(&mut self, reason: String) {
+        if self.connected {
+            self.connected = false;
+            self.push(Kind::Error, format!("Session closed: {}", reason));
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Terminal chat client for the playground server. Joins the same rooms as
+//! browser users, under a nickname sent as the `nick` session tag.
+//!
+//! ```bash
+//! cargo run -p tui-client -- --nick alice --join lobby
+//! ```
+
+mod app;
+mod net;
+mod ui;
+
+use anyhow::{Result, bail};
+use app::{Action, App, Kind};
+use clap::Parser;
+use crossterm::event::{Event, EventStream, KeyEventKind};
+use futures::StreamExt;
+use net::{Incoming, Session};
+use protocol::Frame;
+use ratatui::DefaultTerminal;
+use tokio::sync::mpsc;
+
+/// Longest nickname, in bytes.
+const MAX_NICK_LEN: usize = 32;
+
+#[derive(Parser)]
+#[command(about = "Terminal chat client for the WebTransport playground")]
+struct Args {
+    /// Server URL
+    #[arg(long, default_value = "https://localhost:8765/")]
+    url: String,
+
+    /// Nickname shown to other room members; defaults to $USER
+    #[arg(long)]
+    nick: Option<String>,
+
+    /// Rooms to join on connect; repeat for more
+    #[arg(long = "join")]
+    rooms: Vec<String>,
+
+    /// SHA-256 of the server certificate in hex, as served at /cert-hash.
+    /// Without it the certificate isn't verified.
+    #[arg(long)]
+    cert_hash: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let nick = args
+        .nick
+        .or_else(|| std::env::var("USER").ok())
+        .unwrap_or_else(|| "terminal".to_string());
+    if nick.is_empty()
+        || nick.len() > MAX_NICK_LEN
+        || !nick
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!(
+            "nickname must be 1 to {} letters, digits, '-' or '_'",
+            MAX_NICK_LEN
+        );
+    }
+    let cert_hash = args
+        .cert_hash
+        .as_deref()
+        .map(net::parse_cert_hash)
+        .transpose()?;
+
+    let separator = if args.url.contains('?') { '&' } else { '?' };
+    let url = format!("{}{}nick={}", args.url, separator, nick);
+    let (tx, rx) = mpsc::unbounded_channel();
+    let pinned = cert_hash.is_some();
+    let mut session = Session::open(&url, cert_hash, tx).await?;
+
+    let mut app = App::new(nick);
+    app.push(Kind::System, format!("Connected to {}", args.url));
+    if !pinned {
+        app.push(
+            Kind::System,
+            "Server certificate not verified; pass --cert-hash to pin it",
+        );
+    }
+    app.push(Kind::System, "Type /help for commands");
+    for room in &args.rooms {
+        let actions = app.join(room);
+        perform(&mut app, &mut session, actions).await;
+    }
+
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, &mut app, &mut session, rx).await;
+    ratatui::restore();
+    session.close();
+    result
+}
+
+async fn run(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    session: &mut Session,
+    mut incoming: mpsc::UnboundedReceiver<Incoming>,
+) -> Result<()> {
+    let mut events = EventStream::new();
+    while !app.quit {
+        terminal.draw(|frame| ui::draw(frame, app))?;
+        tokio::select! {
+            event = events.next() => match event {
+                Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                    let actions = app.on_key(key);
+                    perform(app, session, actions).await;
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+                None => break,
+            },
+            Some(message) = incoming.recv() => match message {
+                // Unanswered, the server closes the session as unresponsive
+                Incoming::Frame(Frame::Ping { nonce, .. }) => {
+                    perform(app, session, vec![Action::Send(Frame::Pong { nonce })]).await;
+                }
+                Incoming::Frame(frame) => app.on_frame(frame),
+                Incoming::Datagram(text) => app.on_datagram(text),
+                Incoming::Closed(reason) => app.on_closed(reason),
+            },
+        }
+    }
+    Ok(())
+}
+
+async fn perform(app: &mut App, session: &mut Session, actions: Vec<Action>) {
+    for action in actions {
+        let result = match action {
+            Action::Send(frame) => session.send_frame(&frame).await,
+            Action::Datagram(text) => session.send_datagram(&text),
+        };
+        if let Err(e) = result {
+            app.push(Kind::Error, format!("Send failed: {}", e));
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use anyhow::{Context, Result, bail};
+use protocol::{CloseCode, Frame, FrameDecoder};
+use tokio::sync::mpsc;
+use wtransport::error::ConnectionError;
+use wtransport::tls::Sha256Digest;
+use wtransport::{ClientConfig, Connection, Endpoint, RecvStream, SendStream, VarInt};
+
+/// What the session hands the UI.
+pub enum Incoming {
+    Frame(Frame),
+    Datagram(String),
+    /// The session ended, for the given reason.
+    Closed(String),
+}
+
+// A session with the playground server and its main bidirectional stream,
+// which carries everything the client sends on a stream
+pub struct Session {
+    connection: Connection,
+    send: SendStream,
+}
+
+impl Session {
+    /// Connect to `url`, pinning the server certificate to `cert_hash` if given,
+    /// and start passing everything the server sends to `incoming`.
+    pub async fn open(
+        url: &str,
+        cert_hash: Option<Sha256Digest>,
+        incoming: mpsc::UnboundedSender<Incoming>,
+    ) -> Result<Self> {
+        let builder = ClientConfig::builder().with_bind_default();
+        let config = match cert_hash {
+            Some(hash) => builder.with_server_certificate_hashes([hash]).build(),
+            None => builder.with_no_cert_validation().build(),
+        };
+        let connection = Endpoint::client(config)?
+            .connect(url)
+            .await
+            .with_context(|| format!("can't connect to {}", url))?;
+        let (send, recv) = connection.open_bi().await?.await?;
+
+        tokio::spawn(read_frames(recv, incoming.clone()));
+        tokio::spawn(receive(connection.clone(), incoming));
+        Ok(Self { connection, send })
+    }
+
+    pub async fn send_frame(&mut self, frame: &Frame) -> Result<()> {
+        self.send.write_all(&protocol::encode(frame)).await?;
+        Ok(())
+    }
+
+    pub fn send_datagram(&self, text: &str) -> Result<()> {
+        self.connection.send_datagram(text.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        self.connection
+            .close(VarInt::from_u32(CloseCode::Normal.code()), b"bye");
+    }
+}
+
+// Accept the server's unidirectional streams and datagrams until the session closes
+async fn receive(connection: Connection, incoming: mpsc::UnboundedSender<Incoming>) {
+    let error = loop {
+        tokio::select! {
+            stream = connection.accept_uni() => match stream {
+                Ok(recv) => {
+                    tokio::spawn(read_frames(recv, incoming.clone()));
+                }
+                Err(e) => break e,
+            },
+            datagram = connection.receive_datagram() => match datagram {
+                Ok(datagram) => {
+                    let text = String::from_utf8_lossy(&datagram).into_owned();
+                    let _ = incoming.send(Incoming::Datagram(text));
+                }
+                Err(e) => break e,
+            },
+        }
+    };
+    let _ = incoming.send(Incoming::Closed(describe(&error)));
+}
+
+// Decode frames off one stream until it ends
+async fn read_frames(mut recv: RecvStream, incoming: mpsc::UnboundedSender<Incoming>) {
+    let mut decoder = FrameDecoder::new();
+    let mut buffer = vec![0u8; 4096];
+    while let Ok(Some(n)) = recv.read(&mut buffer).await {
+        decoder.push(&buffer[..n]);
+        loop {
+            match decoder.next_frame() {
+                Ok(Some(frame)) => {
+                    let _ = incoming.send(Incoming::Frame(frame));
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    let _ = incoming.send(Incoming::Closed(format!("bad frame: {}", e)));
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn describe(error: &ConnectionError) -> String {
+    match error {
+        ConnectionError::ApplicationClosed(close) => {
+            let reason = String::from_utf8_lossy(close.reason());
+            match CloseCode::from_code(close.code().into_inner() as u32) {
+                Some(code) => format!("{} ({})", code.description(), reason),
+                None => format!("closed with code {} ({})", close.code(), reason),
+            }
+        }
+        e => e.to_string(),
+    }
+}
+
+/// A certificate hash as served at `/cert-hash`: 64 hex digits, colons allowed.
+pub fn parse_cert_hash(hex: &str) -> Result<Sha256Digest> {
+    let hex = hex.replace(':', "");
+    if hex.len() != 64 {
+        bail!("certificate hash must be 64 hex digits");
+    }
+    let mut bytes = [0u8; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .context("certificate hash must be hex")?;
+    }
+    Ok(Sha256Digest::new(bytes))
+}
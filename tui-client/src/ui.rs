@@ -0,0 +1,75 @@
+use crate::app::{App, Kind, Transport};
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Layout, Position};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph};
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let [messages, status, input] = Layout::vertical([
+        Constraint::Min(1),
+        Constraint::Length(1),
+        Constraint::Length(3),
+    ])
+    .areas(frame.area());
+
+    // Newest lines at the bottom; older ones scroll off the top
+    let height = messages.height.saturating_sub(2) as usize;
+    let start = app.lines.len().saturating_sub(height);
+    let lines: Vec<Line> = app.lines[start..]
+        .iter()
+        .map(|(kind, text)| Line::styled(text.as_str(), style(*kind)))
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" wtransport-playground ")),
+        messages,
+    );
+
+    let target = match &app.target {
+        Some(room) => format!("room {}", room),
+        None => "server".to_string(),
+    };
+    let transport = match app.transport {
+        Transport::Stream => "stream",
+        Transport::Datagram => "datagram",
+    };
+    let rtt = app
+        .rtt_ms
+        .map_or_else(String::new, |rtt| format!(" | RTT {:.1} ms", rtt));
+    let connection = if app.connected { "" } else { " | disconnected" };
+    let status_line = format!(
+        " {} -> {} | rooms: {} | {}{}{}",
+        app.nick,
+        target,
+        app.rooms.join(", "),
+        transport,
+        rtt,
+        connection
+    );
+    frame.render_widget(
+        Paragraph::new(status_line).style(Style::new().add_modifier(Modifier::REVERSED)),
+        status,
+    );
+
+    frame.render_widget(
+        Paragraph::new(app.input.as_str()).block(
+            Block::bordered().title(
+                " Enter: send | Tab: next room | Ctrl+T: stream/datagram | /help | Esc: quit ",
+            ),
+        ),
+        input,
+    );
+    frame.set_cursor_position(Position::new(
+        input.x + 1 + app.input.chars().count() as u16,
+        input.y + 1,
+    ));
+}
+
+fn style(kind: Kind) -> Style {
+    match kind {
+        Kind::System => Style::new().fg(Color::DarkGray),
+        Kind::Sent => Style::new().fg(Color::Cyan),
+        Kind::Received => Style::new(),
+        Kind::Error => Style::new().fg(Color::Red),
+    }
+}
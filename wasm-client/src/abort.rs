@@ -0,0 +1,63 @@
+use futures::channel::oneshot;
+use wasm_bindgen::prelude::*;
+use web_sys::AbortSignal;
+
+/// The `AbortSignal` a long-running call was given, if any. Calls check
+/// `aborted` between steps, and race waits against `fired`.
+pub struct Abort {
+    signal: Option<AbortSignal>,
+    fired: Option<oneshot::Receiver<()>>,
+    /// The signal's "abort" listener, removed on drop.
+    listener: Option<Closure<dyn FnMut()>>,
+}
+
+impl Abort {
+    pub fn new(signal: Option<AbortSignal>) -> Self {
+        let mut abort = Self {
+            signal,
+            fired: None,
+            listener: None,
+        };
+        if let Some(signal) = &abort.signal {
+            let (tx, rx) = oneshot::channel();
+            let mut tx = Some(tx);
+            let listener = Closure::<dyn FnMut()>::new(move || {
+                if let Some(tx) = tx.take() {
+                    let _ = tx.send(());
+                }
+            });
+            let _ =
+                signal.add_event_listener_with_callback("abort", listener.as_ref().unchecked_ref());
+            abort.fired = Some(rx);
+            abort.listener = Some(listener);
+        }
+        abort
+    }
+
+    pub fn aborted(&self) -> bool {
+        self.signal.as_ref().is_some_and(AbortSignal::aborted)
+    }
+
+    /// Resolves once the signal fires, at once if it already has; never
+    /// without a signal.
+    pub async fn fired(&mut self) {
+        if self.aborted() {
+            return;
+        }
+        match self.fired.as_mut() {
+            Some(fired) => {
+                let _ = fired.await;
+            }
+            None => futures::future::pending().await,
+        }
+    }
+}
+
+impl Drop for Abort {
+    fn drop(&mut self) {
+        if let (Some(signal), Some(listener)) = (&self.signal, &self.listener) {
+            let _ = signal
+                .remove_event_listener_with_callback("abort", listener.as_ref().unchecked_ref());
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use crate::events::{self, Event};
+use crate::{quality, rate, to_js};
+use protocol::telemetry::BandwidthEstimate;
+use std::cell::Cell;
+use wasm_bindgen::prelude::*;
+
+thread_local! {
+    static LATEST: Cell<Option<BandwidthEstimate>> = const { Cell::new(None) };
+}
+
+/// Latest bandwidth estimate pushed by the server, as
+/// `{ bits_per_second, rtt_us, cwnd_bytes, sent_packets, lost_packets, datagrams_received }`, or `null` before the first one.
+/// Estimates are also delivered as `bandwidth_estimate` events as they arrive.
+#[wasm_bindgen]
+pub fn get_bandwidth_estimate() -> Result<JsValue, JsValue> {
+    match LATEST.get() {
+        Some(estimate) => to_js(&estimate),
+        None => Ok(JsValue::NULL),
+    }
+}
+
+pub fn update(estimate: BandwidthEstimate) {
+    LATEST.set(Some(estimate));
+    events::emit(Event::BandwidthEstimate(estimate));
+    rate::on_feedback(&estimate);
+    quality::on_estimate(&estimate);
+}
+
+pub fn clear() {
+    LATEST.set(None);
+}
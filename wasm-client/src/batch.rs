@@ -0,0 +1,112 @@
+use crate::{CONNECTION, add_message, fail, throttle, to_js};
+use protocol::PlaygroundError;
+use protocol::batch;
+use serde::Serialize;
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+use web_sys::console;
+
+/// Largest batch sent, comfortably under a typical path's datagram limit.
+const MAX_BATCH_BYTES: usize = 1100;
+
+#[derive(Debug, Default, Serialize)]
+struct BatchStats {
+    sent_batches: u64,
+    sent_messages: u64,
+    received_batches: u64,
+    received_messages: u64,
+    /// Messages in the biggest batch either way.
+    largest: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Sent {
+    datagrams: usize,
+    messages: usize,
+}
+
+thread_local! {
+    static STATS: RefCell<BatchStats> = RefCell::new(BatchStats::default());
+}
+
+/// Send `messages` packed into as few datagrams as fit, up to 255 messages of
+/// no more than about 1 KiB in total each. The server echoes them back batched
+/// the same way. Resolves with `{ datagrams, messages }`.
+#[wasm_bindgen]
+pub async fn send_datagram_batch(messages: Vec<String>) -> Result<JsValue, JsValue> {
+    if messages.is_empty() {
+        let error = "a batch needs at least one message".to_string();
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+    if let Some(message) = messages.iter().find(|m| m.len() > MAX_BATCH_BYTES) {
+        let error = format!(
+            "batched messages are at most {} bytes, got {}",
+            MAX_BATCH_BYTES,
+            message.len()
+        );
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+    let Some(mut session) = CONNECTION.with(|conn| conn.borrow().session.clone()) else {
+        return Err(fail(PlaygroundError::NotConnected));
+    };
+
+    let datagrams = batch::pack(&messages, MAX_BATCH_BYTES);
+    let sent = Sent {
+        datagrams: datagrams.len(),
+        messages: messages.len(),
+    };
+    for datagram in datagrams {
+        let count = batch::decode(&datagram).map_or(0, |m| m.len());
+        throttle::pace(datagram.len()).await;
+        session
+            .send_datagram(bytes::Bytes::from(datagram))
+            .await
+            .map_err(|e| fail(PlaygroundError::SendFailed(e.to_string())))?;
+        STATS.with(|s| {
+            let mut s = s.borrow_mut();
+            s.sent_batches += 1;
+            s.sent_messages += count as u64;
+            s.largest = s.largest.max(count);
+        });
+    }
+
+    add_message(
+        &format!(
+            "[Batch] {} messages in {} datagrams",
+            sent.messages, sent.datagrams
+        ),
+        "sent",
+    );
+    to_js(&sent)
+}
+
+/// Batches sent and received since the last reset: `{ sent_batches,
+/// sent_messages, received_batches, received_messages, largest }`.
+#[wasm_bindgen]
+pub fn get_batch_stats() -> Result<JsValue, JsValue> {
+    STATS.with(|s| to_js(&*s.borrow()))
+}
+
+#[wasm_bindgen]
+pub fn reset_batch_stats() {
+    STATS.with(|s| *s.borrow_mut() = BatchStats::default());
+}
+
+/// Show each message of a batch datagram. Returns false if it isn't a batch.
+pub fn on_datagram(datagram: &[u8]) -> bool {
+    let Some(messages) = batch::decode(datagram) else {
+        return false;
+    };
+    STATS.with(|s| {
+        let mut s = s.borrow_mut();
+        s.received_batches += 1;
+        s.received_messages += messages.len() as u64;
+        s.largest = s.largest.max(messages.len());
+    });
+    for message in messages {
+        let message = String::from_utf8_lossy(message);
+        console::log_1(&format!("Received [Batch]: {}", message).into());
+        add_message(&format!("[Batch] {}", message), "received");
+    }
+    true
+}
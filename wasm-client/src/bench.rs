@@ -0,0 +1,354 @@
+use crate::abort::Abort;
+use crate::{CONNECTION, add_message, coalesce, fail, streams, throttle, to_js, visibility};
+use bytes::Bytes;
+use futures::channel::oneshot;
+use futures::future::{Either, select};
+use gloo_timers::future::TimeoutFuture;
+use protocol::{Frame, MAX_FRAME_LEN, PlaygroundError};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+
+/// Label of the stream a stream bench sends on; its echoes are counted, not displayed.
+pub const STREAM: &str = "bench";
+
+/// Largest datagram payload a bench sends, leaving room for the server's echo prefix.
+const MAX_DATAGRAM_BYTES: usize = 1100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Transport {
+    Stream,
+    Datagram,
+}
+
+/// Knobs for a bench run. Every field is optional from JS.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct BenchConfig {
+    transport: Transport,
+    /// Payload bytes to send in total.
+    total_bytes: usize,
+    /// Payload bytes per stream message or datagram.
+    chunk_bytes: usize,
+    /// Buffers generated before the clock starts; the run recycles them.
+    pool_size: usize,
+    /// How long to wait for outstanding echoes once everything is sent.
+    echo_timeout_ms: u32,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        Self {
+            transport: Transport::Stream,
+            total_bytes: 1024 * 1024,
+            chunk_bytes: 1024,
+            pool_size: 8,
+            echo_timeout_ms: 2000,
+        }
+    }
+}
+
+// Payload buffers generated up front. A run takes a buffer, sends it and puts
+// it back, so once the pool is filled only a pool that runs dry allocates.
+// Buffers are `Bytes`, so handing one to a datagram send is a refcount bump.
+struct BufferPool {
+    free: Vec<Bytes>,
+    transport: Transport,
+    chunk_bytes: usize,
+    /// Buffers generated so far, including the initial fill.
+    generated: u64,
+}
+
+impl BufferPool {
+    fn new(transport: Transport, chunk_bytes: usize, size: usize) -> Self {
+        let mut pool = Self {
+            free: Vec::with_capacity(size),
+            transport,
+            chunk_bytes,
+            generated: 0,
+        };
+        for _ in 0..size {
+            let buffer = pool.generate();
+            pool.free.push(buffer);
+        }
+        pool
+    }
+
+    fn take(&mut self) -> Bytes {
+        self.free.pop().unwrap_or_else(|| self.generate())
+    }
+
+    fn put(&mut self, buffer: Bytes) {
+        self.free.push(buffer);
+    }
+
+    // A chunk of printable filler, varied per buffer; stream chunks are
+    // encoded as message frames ready to write
+    fn generate(&mut self) -> Bytes {
+        let offset = self.generated as usize;
+        self.generated += 1;
+        let text: String = (0..self.chunk_bytes)
+            .map(|i| char::from(b'a' + ((i + offset) % 26) as u8))
+            .collect();
+        match self.transport {
+            Transport::Stream => protocol::encode(&Frame::Message {
+                text,
+                seq: None,
+                id: None,
+                timing: None,
+            })
+            .into(),
+            Transport::Datagram => text.into(),
+        }
+    }
+}
+
+// Echo accounting for the running bench
+struct Run {
+    transport: Transport,
+    expected: u64,
+    chunk_bytes: usize,
+    echoes: u64,
+    echo_bytes: u64,
+    unchanged_echoes: u64,
+    last_echo_at: Option<f64>,
+    done: Option<oneshot::Sender<()>>,
+}
+
+thread_local! {
+    static RUN: RefCell<Option<Run>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Serialize)]
+struct BenchReport {
+    transport: Transport,
+    /// Chunks sent, fewer than planned if the run was aborted.
+    chunks: u64,
+    bytes: u64,
+    /// Time to hand every chunk to the transport, not counting `paused_ms`.
+    send_ms: f64,
+    /// Time spent paused because the page was hidden.
+    paused_ms: f64,
+    megabits_per_second: f64,
+    echoes: u64,
+    echo_bytes: u64,
+    /// Echoes the size of the chunk sent, i.e. echoed without the server's
+    /// prefix (with `datagrams.echo_prefix = false`, straight from its receive buffer).
+    unchanged_echoes: u64,
+    /// Time from the first send to the last echo, if every echo came back.
+    round_trip_ms: Option<f64>,
+    pool_size: usize,
+    /// Buffers allocated after the clock started, because the pool ran dry.
+    allocations: u64,
+    /// Whether `signal` aborted the run, cutting the sending or the wait for
+    /// echoes short.
+    aborted: bool,
+}
+
+/// Measure send throughput. Payloads are generated into a pool of `pool_size`
+/// buffers before the clock starts and recycled chunk by chunk, so the numbers
+/// reflect the transport rather than allocation. `config` may set `transport`
+/// (`"stream"` or `"datagram"`), `total_bytes`, `chunk_bytes`, `pool_size` and
+/// `echo_timeout_ms`. A stream bench sends message frames on its own `bench`
+/// stream. Sending holds off while the page is hidden. Resolves with
+/// `{ transport, chunks, bytes, send_ms, paused_ms, megabits_per_second, echoes,
+/// echo_bytes, unchanged_echoes, round_trip_ms, pool_size, allocations,
+/// aborted }`, `allocations` counting buffers allocated during the run. Given
+/// an `AbortSignal`, the run stops when it fires and resolves with what it got
+/// through, `aborted` set.
+#[wasm_bindgen]
+pub async fn run_bench(
+    config: JsValue,
+    signal: Option<web_sys::AbortSignal>,
+) -> Result<JsValue, JsValue> {
+    let mut abort = Abort::new(signal);
+    let config: BenchConfig = if config.is_undefined() || config.is_null() {
+        BenchConfig::default()
+    } else {
+        serde_wasm_bindgen::from_value(config)
+            .map_err(|e| fail(PlaygroundError::InvalidParams(e.to_string())))?
+    };
+    let max_chunk = match config.transport {
+        Transport::Stream => MAX_FRAME_LEN / 2,
+        Transport::Datagram => MAX_DATAGRAM_BYTES,
+    };
+    if config.chunk_bytes == 0 || config.chunk_bytes > max_chunk {
+        let error = format!("chunk_bytes must be between 1 and {}", max_chunk);
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+    let Some(mut session) = CONNECTION.with(|conn| conn.borrow().session.clone()) else {
+        return Err(fail(PlaygroundError::NotConnected));
+    };
+
+    let chunks = config.total_bytes.div_ceil(config.chunk_bytes) as u64;
+    let (tx, rx) = oneshot::channel();
+    let started = RUN.with(|run| {
+        let mut run = run.borrow_mut();
+        if run.is_some() {
+            return false;
+        }
+        *run = Some(Run {
+            transport: config.transport,
+            expected: chunks,
+            chunk_bytes: config.chunk_bytes,
+            echoes: 0,
+            echo_bytes: 0,
+            unchanged_echoes: 0,
+            last_echo_at: None,
+            done: Some(tx),
+        });
+        true
+    });
+    if !started {
+        let error = "a bench is already running".to_string();
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+
+    let mut stream = None;
+    if config.transport == Transport::Stream {
+        match session.open_bi().await {
+            Ok((send, recv)) => {
+                streams::register(STREAM, send, recv);
+                stream = CONNECTION.with(|conn| conn.borrow().streams.get(STREAM).cloned());
+            }
+            Err(e) => {
+                RUN.with(|run| run.borrow_mut().take());
+                let error = PlaygroundError::StreamFailed(format!("opening '{}': {}", STREAM, e));
+                return Err(fail(error));
+            }
+        }
+    }
+
+    let mut pool = BufferPool::new(config.transport, config.chunk_bytes, config.pool_size);
+    let prefilled = pool.generated;
+    let started_at = js_sys::Date::now();
+    let mut paused_ms = 0.0;
+    let mut result = Ok(());
+    let mut sent = 0;
+    while sent < chunks && !abort.aborted() {
+        paused_ms += visibility::visible().await;
+        let buffer = pool.take();
+        result = match &stream {
+            // The coalescer paces whole batches
+            Some(stream) if coalesce::enabled() => coalesce::write(STREAM, stream.clone(), &buffer)
+                .await
+                .map_err(|e| e.to_string()),
+            Some(stream) => {
+                throttle::pace(buffer.len()).await;
+                let written = stream.lock().await.write(&buffer).await;
+                written.map(|_| ()).map_err(|e| e.to_string())
+            }
+            None => {
+                throttle::pace(buffer.len()).await;
+                let sent = session.send_datagram(buffer.clone()).await;
+                sent.map_err(|e| e.to_string())
+            }
+        };
+        pool.put(buffer);
+        if result.is_err() {
+            break;
+        }
+        sent += 1;
+    }
+    let send_ms = js_sys::Date::now() - started_at - paused_ms;
+
+    let mut aborted = abort.aborted();
+    if result.is_ok() && !aborted && chunks > 0 {
+        let echoes = select(rx, TimeoutFuture::new(config.echo_timeout_ms));
+        let fired = Box::pin(abort.fired());
+        aborted = matches!(select(echoes, fired).await, Either::Right(_));
+    }
+    let run = RUN.with(|run| run.borrow_mut().take());
+    if let Some(stream) = CONNECTION.with(|conn| conn.borrow_mut().streams.remove(STREAM))
+        && stream.lock().await.finish().is_ok()
+    {
+        streams::finished(STREAM);
+    }
+    if let Err(e) = result {
+        return Err(fail(PlaygroundError::SendFailed(format!("bench: {}", e))));
+    }
+    let Some(run) = run else {
+        return Err(fail(PlaygroundError::Cancelled("bench".to_string())));
+    };
+
+    let bytes = sent * config.chunk_bytes as u64;
+    let report = BenchReport {
+        transport: config.transport,
+        chunks: sent,
+        bytes,
+        send_ms,
+        paused_ms,
+        megabits_per_second: bytes as f64 * 8.0 / 1000.0 / send_ms.max(1.0),
+        echoes: run.echoes,
+        echo_bytes: run.echo_bytes,
+        unchanged_echoes: run.unchanged_echoes,
+        round_trip_ms: run
+            .last_echo_at
+            .filter(|_| run.echoes >= sent)
+            .map(|at| at - started_at - paused_ms),
+        pool_size: config.pool_size,
+        allocations: pool.generated - prefilled,
+        aborted,
+    };
+    add_message(
+        &format!(
+            "Bench{}: {} bytes in {:.0} ms ({:.1} Mbit/s), {} of {} echoes, {} allocations",
+            if report.aborted { " (aborted)" } else { "" },
+            report.bytes,
+            report.send_ms,
+            report.megabits_per_second,
+            report.echoes,
+            report.chunks,
+            report.allocations
+        ),
+        "system",
+    );
+    to_js(&report)
+}
+
+/// Count an echo of a bench chunk; false if no bench over `transport` is running.
+fn on_echo(transport: Transport, len: usize) -> bool {
+    RUN.with(|run| {
+        let mut run = run.borrow_mut();
+        let Some(run) = run.as_mut().filter(|run| run.transport == transport) else {
+            return false;
+        };
+        run.echoes += 1;
+        run.echo_bytes += len as u64;
+        if len == run.chunk_bytes {
+            run.unchanged_echoes += 1;
+        }
+        run.last_echo_at = Some(js_sys::Date::now());
+        if run.echoes >= run.expected
+            && let Some(done) = run.done.take()
+        {
+            let _ = done.send(());
+        }
+        true
+    })
+}
+
+/// Count a message echoed on the bench stream.
+pub fn on_stream_echo(len: usize) {
+    on_echo(Transport::Stream, len);
+}
+
+/// Count a datagram echo while a datagram bench runs; false otherwise, and the
+/// datagram should be handled as usual.
+pub fn on_datagram(len: usize) -> bool {
+    on_echo(Transport::Datagram, len)
+}
+
+/// Abandon the running bench; its `run_bench` call rejects.
+pub fn cancel() {
+    RUN.with(|run| run.borrow_mut().take());
+}
+
+/// Milliseconds on the page's monotonic clock, finer than `Date::now` so
+/// localhost round trips don't all tie.
+pub fn now() -> f64 {
+    web_sys::window()
+        .and_then(|window| window.performance())
+        .map_or_else(js_sys::Date::now, |performance| performance.now())
+}
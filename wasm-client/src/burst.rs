@@ -0,0 +1,64 @@
+use crate::{CONNECTION, add_message, clock};
+use gloo_timers::future::TimeoutFuture;
+use protocol::burst::{self, BurstInstruction};
+use wasm_bindgen_futures::spawn_local;
+use web_sys::console;
+
+/// Carry out a burst instruction from the server: wait until its start time,
+/// translated to our clock with the clock-offset estimate, then send every datagram back to back.
+pub fn start(instruction: BurstInstruction) {
+    spawn_local(async move {
+        let offset = match clock::get_clock_offset() {
+            Some(offset) => offset,
+            None => match clock::sync().await {
+                Ok(offset) => offset,
+                Err(e) => {
+                    add_message(
+                        &format!("Burst {} skipped: {}", instruction.burst_id, e),
+                        "system",
+                    );
+                    return;
+                }
+            },
+        };
+
+        let start_at = instruction.at_unix_ms as f64 - offset;
+        let delay = start_at - js_sys::Date::now();
+        if delay > 0.0 {
+            TimeoutFuture::new(delay as u32).await;
+        } else {
+            console::warn_1(
+                &format!(
+                    "Burst {} instruction arrived {:.1} ms late",
+                    instruction.burst_id, -delay
+                )
+                .into(),
+            );
+        }
+
+        let Some(mut session) = CONNECTION.with(|conn| conn.borrow().session.clone()) else {
+            return;
+        };
+        let started = js_sys::Date::now();
+        let mut failed = 0;
+        for seq in 0..instruction.count {
+            let datagram =
+                burst::encode_datagram(instruction.burst_id, seq, instruction.payload_bytes);
+            if session.send_datagram(datagram.into()).await.is_err() {
+                failed += 1;
+            }
+        }
+
+        add_message(
+            &format!(
+                "Burst {}: sent {} datagrams in {:.1} ms, started {:+.1} ms from target ({} failed)",
+                instruction.burst_id,
+                instruction.count - failed,
+                js_sys::Date::now() - started,
+                started - start_at,
+                failed
+            ),
+            "system",
+        );
+    });
+}
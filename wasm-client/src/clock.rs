@@ -0,0 +1,75 @@
+use crate::rpc;
+use crate::{add_message, to_js_error};
+use protocol::PlaygroundError;
+use protocol::rpc::{Outcome, TimeResult};
+use std::cell::Cell;
+use wasm_bindgen::prelude::*;
+
+/// Round trips to the server's `time` method per sync; the fastest one wins.
+const SAMPLES: usize = 5;
+
+const SAMPLE_TIMEOUT_MS: u32 = 2000;
+
+thread_local! {
+    /// Estimated server clock minus local clock, in milliseconds.
+    static OFFSET_MS: Cell<Option<f64>> = const { Cell::new(None) };
+}
+
+/// Estimate the offset between the server's clock and ours, NTP-style: the
+/// server's time is assumed to be read halfway through the round trip, and the
+/// sample with the shortest round trip is kept. Resolves with the offset in ms
+/// (server minus local); also stored for `get_clock_offset()`.
+#[wasm_bindgen]
+pub async fn sync_clock() -> Result<f64, JsValue> {
+    sync().await.map_err(|error| to_js_error(&error))
+}
+
+/// Last clock offset from `sync_clock()` in ms (server minus local), or `undefined`.
+#[wasm_bindgen]
+pub fn get_clock_offset() -> Option<f64> {
+    OFFSET_MS.get()
+}
+
+pub async fn sync() -> Result<f64, PlaygroundError> {
+    let mut best: Option<(f64, f64)> = None;
+    for _ in 0..SAMPLES {
+        let sent = js_sys::Date::now();
+        let outcome = rpc::send_request(
+            protocol::rpc::TIME.to_string(),
+            serde_json::Value::Null,
+            SAMPLE_TIMEOUT_MS,
+        )
+        .await?;
+        let received = js_sys::Date::now();
+
+        let server_ms = match outcome {
+            Outcome::Result(result) => {
+                serde_json::from_value::<TimeResult>(result)
+                    .map_err(|e| PlaygroundError::MalformedFrame(e.to_string()))?
+                    .unix_ms as f64
+            }
+            Outcome::Error(error) => return Err(error),
+        };
+
+        let rtt = received - sent;
+        let offset = server_ms - (sent + received) / 2.0;
+        if best.is_none_or(|(best_rtt, _)| rtt < best_rtt) {
+            best = Some((rtt, offset));
+        }
+    }
+
+    let (rtt, offset) = best.expect("at least one sample");
+    OFFSET_MS.set(Some(offset));
+    add_message(
+        &format!(
+            "Clock synced: server is {:+.1} ms from local (RTT {:.1} ms)",
+            offset, rtt
+        ),
+        "system",
+    );
+    Ok(offset)
+}
+
+pub fn clear() {
+    OFFSET_MS.set(None);
+}
@@ -0,0 +1,240 @@
+use crate::{add_message, fail, streams, to_js};
+use futures::lock::Mutex;
+use gloo_timers::future::TimeoutFuture;
+use protocol::PlaygroundError;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::spawn_local;
+use web_sys::console;
+use web_transport::SendStream;
+
+/// Longest a frame can be held for others to join it.
+const MAX_WINDOW_MS: u32 = 1000;
+
+/// Coalescer settings. Every field is optional from JS.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct CoalesceOptions {
+    /// How long the first frame of a batch waits for more; 0 turns coalescing off.
+    window_ms: u32,
+    /// A batch this big is written at once, without waiting out the window.
+    max_bytes: usize,
+}
+
+impl Default for CoalesceOptions {
+    fn default() -> Self {
+        Self {
+            window_ms: 0,
+            max_bytes: 16 * 1024,
+        }
+    }
+}
+
+/// Frames waiting to go out on one stream in a single write.
+struct Batch {
+    /// Tells the flush timer whether the batch it was started for is still this one.
+    id: u64,
+    bytes: Vec<u8>,
+    /// When each frame joined.
+    joined: Vec<f64>,
+}
+
+#[derive(Debug, Default)]
+struct Totals {
+    frames: u64,
+    writes: u64,
+    bytes: u64,
+    /// Batches written because they reached `max_bytes`, and because their
+    /// window ran out.
+    full_flushes: u64,
+    window_flushes: u64,
+    /// Time frames spent held in batches, in milliseconds.
+    held_ms: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct CoalesceStats {
+    #[serde(flatten)]
+    options: CoalesceOptions,
+    frames: u64,
+    writes: u64,
+    bytes: u64,
+    full_flushes: u64,
+    window_flushes: u64,
+    frames_per_write: f64,
+    mean_held_ms: f64,
+}
+
+enum Flush {
+    Full,
+    Window,
+}
+
+thread_local! {
+    static OPTIONS: RefCell<CoalesceOptions> = RefCell::new(CoalesceOptions::default());
+    static TOTALS: RefCell<Totals> = RefCell::new(Totals::default());
+    /// Batches being gathered, by stream label.
+    static PENDING: RefCell<HashMap<String, Batch>> = RefCell::new(HashMap::new());
+    static NEXT_ID: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Coalesce small frame writes, as Nagle's algorithm does on TCP:
+/// `{ window_ms, max_bytes }`, with `window_ms` 0 (the default) or `null`
+/// turning it off. The first frame written to a stream waits up to
+/// `window_ms` (at most 1000) for more, and they all go out in one write once
+/// the window ends or they reach `max_bytes` (default 16384). Fewer, bigger
+/// writes trade each frame's latency for throughput. Bench and comparison
+/// streams are coalesced too. Resets the coalescing stats.
+#[wasm_bindgen]
+pub fn set_coalescing(options: JsValue) -> Result<(), JsValue> {
+    let options: CoalesceOptions = if options.is_undefined() || options.is_null() {
+        CoalesceOptions::default()
+    } else {
+        serde_wasm_bindgen::from_value(options)
+            .map_err(|e| fail(PlaygroundError::InvalidParams(e.to_string())))?
+    };
+    if options.window_ms > MAX_WINDOW_MS {
+        let error = format!("window_ms must be at most {}", MAX_WINDOW_MS);
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+    if options.max_bytes == 0 {
+        let error = "max_bytes must be at least 1".to_string();
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+
+    OPTIONS.with(|o| *o.borrow_mut() = options);
+    TOTALS.with(|t| *t.borrow_mut() = Totals::default());
+    let message = if options.window_ms == 0 {
+        "Write coalescing off".to_string()
+    } else {
+        format!(
+            "Coalescing stream writes within {} ms, up to {} bytes",
+            options.window_ms, options.max_bytes
+        )
+    };
+    add_message(&message, "system");
+    Ok(())
+}
+
+/// What coalescing has done since it was last set: `{ window_ms, max_bytes,
+/// frames, writes, bytes, full_flushes, window_flushes, frames_per_write,
+/// mean_held_ms }`.
+#[wasm_bindgen]
+pub fn get_coalescing_stats() -> Result<JsValue, JsValue> {
+    let options = OPTIONS.with(|o| *o.borrow());
+    let stats = TOTALS.with(|t| {
+        let t = t.borrow();
+        CoalesceStats {
+            options,
+            frames: t.frames,
+            writes: t.writes,
+            bytes: t.bytes,
+            full_flushes: t.full_flushes,
+            window_flushes: t.window_flushes,
+            frames_per_write: if t.writes == 0 {
+                0.0
+            } else {
+                t.frames as f64 / t.writes as f64
+            },
+            mean_held_ms: if t.frames == 0 {
+                0.0
+            } else {
+                t.held_ms / t.frames as f64
+            },
+        }
+    });
+    to_js(&stats)
+}
+
+pub fn enabled() -> bool {
+    OPTIONS.with(|o| o.borrow().window_ms > 0)
+}
+
+/// Add an encoded frame to the batch for the stream under `label`. Like a TCP
+/// write under Nagle, it returns once the frame is batched; only the frame
+/// that fills a batch waits for it to be written, and gets its result. A batch
+/// written when its window ends has no one to tell, so a failure is logged.
+pub async fn write(
+    label: &str,
+    stream: Rc<Mutex<SendStream>>,
+    bytes: &[u8],
+) -> Result<(), PlaygroundError> {
+    let options = OPTIONS.with(|o| *o.borrow());
+    let (started, full) = PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        let batch = pending.entry(label.to_string()).or_insert_with(|| Batch {
+            id: NEXT_ID.with(|id| {
+                id.set(id.get() + 1);
+                id.get()
+            }),
+            bytes: Vec::new(),
+            joined: Vec::new(),
+        });
+        batch.bytes.extend_from_slice(bytes);
+        batch.joined.push(js_sys::Date::now());
+        let started = (batch.joined.len() == 1).then_some(batch.id);
+        (started, batch.bytes.len() >= options.max_bytes)
+    });
+
+    if full {
+        return flush(label, &stream, None, Some(Flush::Full)).await;
+    }
+    if let Some(id) = started {
+        let label = label.to_string();
+        spawn_local(async move {
+            TimeoutFuture::new(options.window_ms).await;
+            if let Err(e) = flush(&label, &stream, Some(id), Some(Flush::Window)).await {
+                console::warn_1(&format!("Coalesced write failed: {}", e).into());
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Write whatever is batched for `label` now, e.g. before finishing its stream.
+pub async fn finish(label: &str, stream: &Rc<Mutex<SendStream>>) -> Result<(), PlaygroundError> {
+    flush(label, stream, None, None).await
+}
+
+// Write the batch gathered for `label`, if it's still batch `id` (any batch
+// without one)
+async fn flush(
+    label: &str,
+    stream: &Rc<Mutex<SendStream>>,
+    id: Option<u64>,
+    why: Option<Flush>,
+) -> Result<(), PlaygroundError> {
+    let batch = PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        match pending.get(label) {
+            Some(batch) if id.is_none_or(|id| id == batch.id) => pending.remove(label),
+            _ => None,
+        }
+    });
+    let Some(batch) = batch else {
+        return Ok(());
+    };
+
+    let now = js_sys::Date::now();
+    TOTALS.with(|t| {
+        let mut t = t.borrow_mut();
+        t.frames += batch.joined.len() as u64;
+        t.writes += 1;
+        t.bytes += batch.bytes.len() as u64;
+        match why {
+            Some(Flush::Full) => t.full_flushes += 1,
+            Some(Flush::Window) => t.window_flushes += 1,
+            None => {}
+        }
+        t.held_ms += batch.joined.iter().map(|at| now - at).sum::<f64>();
+    });
+    streams::write_bytes(label, stream, &batch.bytes).await
+}
+
+/// Drop the batches of a session that ended.
+pub fn clear() {
+    PENDING.with(|pending| pending.borrow_mut().clear());
+}
@@ -0,0 +1,439 @@
+use crate::abort::Abort;
+use crate::bench::now;
+use crate::events::{self, Event};
+use crate::{CONNECTION, add_message, coalesce, fail, streams, to_js};
+use futures::channel::oneshot;
+use futures::future::{Either, join, select};
+use gloo_timers::future::TimeoutFuture;
+use protocol::telemetry::ServerTiming;
+use protocol::{Frame, PlaygroundError};
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use wasm_bindgen::prelude::*;
+
+/// Label of the stream a comparison sends on; its echoes are timed, not displayed.
+pub const STREAM: &str = "compare";
+
+/// Starts the text of every comparison message, followed by `<run>:<seq>`, so
+/// its echoes can be told apart whatever prefix the server adds.
+const MARKER: &str = "compare:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    Stream,
+    Datagram,
+}
+
+/// Knobs for a comparison. Every field is optional from JS.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CompareOptions {
+    /// Messages to send over both transports.
+    count: u32,
+    /// Pause between messages.
+    interval_ms: u32,
+    /// How long to wait for outstanding echoes once everything is sent.
+    timeout_ms: u32,
+}
+
+impl Default for CompareOptions {
+    fn default() -> Self {
+        Self {
+            count: 20,
+            interval_ms: 100,
+            timeout_ms: 2000,
+        }
+    }
+}
+
+// One message's two copies: when they were sent, each echo's round trip, and
+// how long the server held the stream copy
+struct Sample {
+    sent_at: f64,
+    stream_ms: Option<f64>,
+    datagram_ms: Option<f64>,
+    stream_dwell_ms: Option<f64>,
+}
+
+// Echo accounting for the running comparison
+struct Run {
+    id: u32,
+    count: usize,
+    samples: Vec<Sample>,
+    /// Echoes still to come from the messages sent so far.
+    outstanding: usize,
+    done: Option<oneshot::Sender<()>>,
+}
+
+thread_local! {
+    static RUN: RefCell<Option<Run>> = const { RefCell::new(None) };
+    static NEXT_RUN: Cell<u32> = const { Cell::new(1) };
+}
+
+/// Round trips over one transport.
+#[derive(Debug, Serialize)]
+struct Latency {
+    echoes: u32,
+    avg_ms: f64,
+    min_ms: f64,
+    max_ms: f64,
+    /// The average split into time in the server, from the echoes' timing,
+    /// and the rest, on the network. Null for datagrams, which carry no timing.
+    avg_dwell_ms: Option<f64>,
+    avg_network_ms: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct CompareReport {
+    sent: u32,
+    /// Messages whose stream echo came back first.
+    stream_first: u32,
+    /// Messages whose datagram echo came back first.
+    datagram_first: u32,
+    /// Messages whose echoes came back within the same timer tick.
+    ties: u32,
+    stream: Option<Latency>,
+    datagram: Option<Latency>,
+    /// Datagram echoes that never came back; stream echoes only go missing if
+    /// the server drops the message, e.g. over its rate limit.
+    datagrams_lost: u32,
+    streams_lost: u32,
+    /// Mean of stream minus datagram round trip over messages with both
+    /// echoes: positive when datagrams were faster.
+    avg_datagram_lead_ms: Option<f64>,
+    /// Whether `signal` aborted the run. Echoes still outstanding then aren't
+    /// counted lost.
+    aborted: bool,
+}
+
+/// Race reliable and unreliable delivery: send `count` messages (default 20),
+/// `interval_ms` apart (default 100), each at once as a numbered message frame
+/// on a dedicated `compare` stream and as a datagram with the same number.
+/// As both echoes of a message arrive, a `transport_compared` event reports
+/// which came back first and by how much; messages missing an echo are
+/// reported once `timeout_ms` (default 2000) has passed after the last send.
+/// Resolves with `{ sent, stream_first, datagram_first, ties, stream,
+/// datagram, datagrams_lost, streams_lost, avg_datagram_lead_ms, aborted }`,
+/// where `stream` and `datagram` are `{ echoes, avg_ms, min_ms, max_ms,
+/// avg_dwell_ms, avg_network_ms }`: the stream's average round trip split by
+/// the server's timing into time in the server and time on the network.
+/// Given an `AbortSignal`, the run stops when it fires and resolves with the
+/// messages sent so far, `aborted` set.
+#[wasm_bindgen]
+pub async fn compare_transports(
+    options: JsValue,
+    signal: Option<web_sys::AbortSignal>,
+) -> Result<JsValue, JsValue> {
+    let mut abort = Abort::new(signal);
+    let options: CompareOptions = if options.is_undefined() || options.is_null() {
+        CompareOptions::default()
+    } else {
+        serde_wasm_bindgen::from_value(options)
+            .map_err(|e| fail(PlaygroundError::InvalidParams(e.to_string())))?
+    };
+    let Some(mut session) = CONNECTION.with(|conn| conn.borrow().session.clone()) else {
+        return Err(fail(PlaygroundError::NotConnected));
+    };
+
+    let id = NEXT_RUN.replace(NEXT_RUN.get() + 1);
+    let (tx, rx) = oneshot::channel();
+    let started = RUN.with(|run| {
+        let mut run = run.borrow_mut();
+        if run.is_some() {
+            return false;
+        }
+        *run = Some(Run {
+            id,
+            count: options.count as usize,
+            samples: Vec::with_capacity(options.count as usize),
+            outstanding: 0,
+            done: Some(tx),
+        });
+        true
+    });
+    if !started {
+        let error = "a comparison is already running".to_string();
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+
+    let stream = match session.open_bi().await {
+        Ok((send, recv)) => {
+            streams::register(STREAM, send, recv);
+            CONNECTION.with(|conn| conn.borrow().streams.get(STREAM).cloned())
+        }
+        Err(e) => {
+            RUN.with(|run| run.borrow_mut().take());
+            let error = PlaygroundError::StreamFailed(format!("opening '{}': {}", STREAM, e));
+            return Err(fail(error));
+        }
+    };
+
+    let mut result = Ok(());
+    for seq in 1..=options.count as u64 {
+        if abort.aborted() {
+            break;
+        }
+        let text = format!("{}{}:{}", MARKER, id, seq);
+        let frame = protocol::encode(&Frame::Message {
+            text: text.clone(),
+            seq: Some(seq),
+            id: None,
+            timing: None,
+        });
+        RUN.with(|run| {
+            if let Some(run) = run.borrow_mut().as_mut() {
+                run.samples.push(Sample {
+                    sent_at: now(),
+                    stream_ms: None,
+                    datagram_ms: None,
+                    stream_dwell_ms: None,
+                });
+                run.outstanding += 2;
+            }
+        });
+        let written = async {
+            match &stream {
+                Some(stream) if coalesce::enabled() => {
+                    coalesce::write(STREAM, stream.clone(), &frame)
+                        .await
+                        .map_err(|e| e.to_string())
+                }
+                Some(stream) => stream
+                    .lock()
+                    .await
+                    .write(&frame)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string()),
+                None => Err("stream closed".to_string()),
+            }
+        };
+        let sent = session.send_datagram(text.into_bytes().into());
+        result = match join(written, sent).await {
+            (Err(e), _) => Err(e),
+            (_, Err(e)) => Err(e.to_string()),
+            (Ok(_), Ok(())) => Ok(()),
+        };
+        if result.is_err() {
+            break;
+        }
+        if seq < options.count as u64 {
+            let interval = TimeoutFuture::new(options.interval_ms);
+            let fired = Box::pin(abort.fired());
+            if let Either::Right(_) = select(interval, fired).await {
+                break;
+            }
+        }
+    }
+
+    let mut aborted = abort.aborted();
+    if result.is_ok() && !aborted && options.count > 0 {
+        let echoes = select(rx, TimeoutFuture::new(options.timeout_ms));
+        let fired = Box::pin(abort.fired());
+        aborted = matches!(select(echoes, fired).await, Either::Right(_));
+    }
+    let run = RUN.with(|run| run.borrow_mut().take());
+    if let Some(stream) = CONNECTION.with(|conn| conn.borrow_mut().streams.remove(STREAM))
+        && stream.lock().await.finish().is_ok()
+    {
+        streams::finished(STREAM);
+    }
+    if let Err(e) = result {
+        return Err(fail(PlaygroundError::SendFailed(format!(
+            "comparison: {}",
+            e
+        ))));
+    }
+    let Some(run) = run else {
+        return Err(fail(PlaygroundError::Cancelled("comparison".to_string())));
+    };
+
+    let mut report = CompareReport {
+        sent: run.samples.len() as u32,
+        stream_first: 0,
+        datagram_first: 0,
+        ties: 0,
+        stream: latency(
+            run.samples
+                .iter()
+                .filter_map(|sample| Some((sample.stream_ms?, sample.stream_dwell_ms))),
+        ),
+        datagram: latency(
+            run.samples
+                .iter()
+                .filter_map(|sample| Some((sample.datagram_ms?, None))),
+        ),
+        datagrams_lost: 0,
+        streams_lost: 0,
+        avg_datagram_lead_ms: None,
+        aborted,
+    };
+    let mut leads = Vec::new();
+    for (seq, sample) in run.samples.iter().enumerate() {
+        match (sample.stream_ms, sample.datagram_ms) {
+            (Some(stream_ms), Some(datagram_ms)) => {
+                match first(stream_ms, datagram_ms) {
+                    Some(Transport::Stream) => report.stream_first += 1,
+                    Some(Transport::Datagram) => report.datagram_first += 1,
+                    None => report.ties += 1,
+                }
+                leads.push(stream_ms - datagram_ms);
+            }
+            _ if aborted => {}
+            (stream_ms, datagram_ms) => {
+                if stream_ms.is_none() {
+                    report.streams_lost += 1;
+                }
+                if datagram_ms.is_none() {
+                    report.datagrams_lost += 1;
+                }
+                compared(seq as u64 + 1, sample);
+            }
+        }
+    }
+    if !leads.is_empty() {
+        report.avg_datagram_lead_ms = Some(leads.iter().sum::<f64>() / leads.len() as f64);
+    }
+
+    add_message(
+        &format!(
+            "Comparison{}: datagram first {} times, stream first {} ({} ties); {} datagrams and {} stream messages lost",
+            if report.aborted { " (aborted)" } else { "" },
+            report.datagram_first,
+            report.stream_first,
+            report.ties,
+            report.datagrams_lost,
+            report.streams_lost
+        ),
+        "system",
+    );
+    to_js(&report)
+}
+
+// The `(run, seq)` a comparison message's echo carries, if it's one
+fn parse(text: &str) -> Option<(u32, u64)> {
+    let (_, rest) = text.split_once(MARKER)?;
+    let (run, seq) = rest.split_once(':')?;
+    Some((run.parse().ok()?, seq.parse().ok()?))
+}
+
+// Time an echo over `transport`, and note the server's `timing` of a stream
+// echo; false if it isn't one of the running comparison's
+fn on_echo(transport: Transport, text: &str, timing: Option<ServerTiming>) -> bool {
+    let Some((id, seq)) = parse(text) else {
+        return false;
+    };
+    RUN.with(|run| {
+        let mut run = run.borrow_mut();
+        let Some(run) = run.as_mut().filter(|run| run.id == id) else {
+            return false;
+        };
+        let Some(sample) = (seq as usize)
+            .checked_sub(1)
+            .and_then(|index| run.samples.get_mut(index))
+        else {
+            return true;
+        };
+        let slot = match transport {
+            Transport::Stream => &mut sample.stream_ms,
+            Transport::Datagram => &mut sample.datagram_ms,
+        };
+        if slot.is_some() {
+            return true;
+        }
+        *slot = Some(now() - sample.sent_at);
+        if transport == Transport::Stream {
+            sample.stream_dwell_ms = timing.map(|timing| timing.dwell_us() as f64 / 1000.0);
+        }
+        if sample.stream_ms.is_some() && sample.datagram_ms.is_some() {
+            compared(seq, sample);
+        }
+        run.outstanding = run.outstanding.saturating_sub(1);
+        if run.outstanding == 0
+            && run.samples.len() == run.count
+            && let Some(done) = run.done.take()
+        {
+            let _ = done.send(());
+        }
+        true
+    })
+}
+
+/// Time a message echoed on the comparison stream.
+pub fn on_stream_echo(text: &str, timing: Option<ServerTiming>) {
+    on_echo(Transport::Stream, text, timing);
+}
+
+/// Time a datagram echo while a comparison runs; false if it isn't one, and
+/// the datagram should be handled as usual.
+pub fn on_datagram(datagram: &[u8]) -> bool {
+    std::str::from_utf8(datagram).is_ok_and(|text| on_echo(Transport::Datagram, text, None))
+}
+
+/// Abandon the running comparison; its `compare_transports` call rejects.
+pub fn cancel() {
+    RUN.with(|run| run.borrow_mut().take());
+}
+
+fn compared(seq: u64, sample: &Sample) {
+    let (first, delta_ms) = match (sample.stream_ms, sample.datagram_ms) {
+        (Some(stream_ms), Some(datagram_ms)) => (
+            first(stream_ms, datagram_ms),
+            Some((stream_ms - datagram_ms).abs()),
+        ),
+        (Some(_), None) => (Some(Transport::Stream), None),
+        (None, Some(_)) => (Some(Transport::Datagram), None),
+        (None, None) => (None, None),
+    };
+    events::emit(Event::TransportCompared {
+        seq,
+        first,
+        delta_ms,
+        stream_ms: sample.stream_ms,
+        datagram_ms: sample.datagram_ms,
+        stream_dwell_ms: sample.stream_dwell_ms,
+    });
+}
+
+// Which echo came back first; None for a tie
+fn first(stream_ms: f64, datagram_ms: f64) -> Option<Transport> {
+    if stream_ms < datagram_ms {
+        Some(Transport::Stream)
+    } else if datagram_ms < stream_ms {
+        Some(Transport::Datagram)
+    } else {
+        None
+    }
+}
+
+// Round trips, each with the server's dwell time if known. The breakdown
+// averages over echoes with timing only
+fn latency(echoes: impl Iterator<Item = (f64, Option<f64>)>) -> Option<Latency> {
+    let echoes: Vec<(f64, Option<f64>)> = echoes.collect();
+    if echoes.is_empty() {
+        return None;
+    }
+    let round_trips = echoes.iter().map(|(round_trip, _)| *round_trip);
+    let timed: Vec<(f64, f64)> = echoes
+        .iter()
+        .filter_map(|(round_trip, dwell)| Some((*round_trip, (*dwell)?)))
+        .collect();
+    let timed_avg = |part: fn(f64, f64) -> f64| {
+        (!timed.is_empty()).then(|| {
+            let total: f64 = timed
+                .iter()
+                .map(|&(round_trip, dwell)| part(round_trip, dwell))
+                .sum();
+            total / timed.len() as f64
+        })
+    };
+    Some(Latency {
+        echoes: echoes.len() as u32,
+        avg_ms: round_trips.clone().sum::<f64>() / echoes.len() as f64,
+        min_ms: round_trips.clone().fold(f64::INFINITY, f64::min),
+        max_ms: round_trips.fold(0.0, f64::max),
+        avg_dwell_ms: timed_avg(|_, dwell| dwell),
+        avg_network_ms: timed_avg(|round_trip, dwell| round_trip - dwell),
+    })
+}
@@ -0,0 +1,522 @@
+use crate::disconnects::{self, ClientClose};
+use crate::events::{self, Event};
+#[cfg(feature = "reconnect")]
+use crate::reconnect;
+#[cfg(feature = "history")]
+use crate::settings;
+use crate::ui::add_message;
+use crate::{
+    bandwidth, clock, coalesce, fail, hello, hex_to_bytes, mtu, pause, quality, rate, raw,
+    reliable, rooms, rpc, streams, to_js, transfer, verify, visibility,
+};
+#[cfg(feature = "bench")]
+use crate::{bench, compare, ping};
+use futures::future::{Either, join_all, select};
+use futures::lock::Mutex;
+use gloo_timers::future::TimeoutFuture;
+use protocol::telemetry::decode_mtu_probe;
+#[cfg(feature = "bench")]
+use protocol::telemetry::decode_ping;
+use protocol::{CloseCode, PlaygroundError};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::spawn_local;
+use web_sys::console;
+use web_transport::{ClientBuilder, SendStream, Session};
+
+// Global state to store the session and send streams
+// Session is cloneable and provides multiple handles to the same connection
+// Send streams are keyed by label ("main" is opened on connect) and sit behind an
+// async Mutex: writes await, and concurrent senders (e.g. overlapping request()
+// calls) must queue rather than panic on a RefCell borrow
+pub(crate) struct ConnectionState {
+    pub status: Status,
+    /// Bumped on every connect, so tasks of an old session can tell they're stale.
+    pub generation: u64,
+    pub session: Option<Session>,
+    /// URL and certificate digest the session was opened with, for following a
+    /// redirect or reconnecting.
+    pub url: Option<String>,
+    pub cert_hash: Option<Vec<u8>>,
+    /// Redirects followed in a row to reach this session; 0 for one the user opened.
+    pub redirects: u32,
+    pub streams: HashMap<String, Rc<Mutex<SendStream>>>,
+    /// Set when this client closes a session itself, with that session's generation.
+    pub closed_by_client: Option<(u64, ClientClose)>,
+}
+
+impl ConnectionState {
+    fn new() -> Self {
+        Self {
+            status: Status::Disconnected,
+            generation: 0,
+            session: None,
+            url: None,
+            cert_hash: None,
+            redirects: 0,
+            streams: HashMap::new(),
+            closed_by_client: None,
+        }
+    }
+}
+
+/// How long `disconnect()` waits for streams to drain unless told otherwise.
+const DRAIN_TIMEOUT_MS: u32 = 2000;
+
+/// Most redirects followed in a row, so servers redirecting to each other can't
+/// bounce the client forever.
+const MAX_REDIRECTS: u32 = 5;
+
+thread_local! {
+    pub(crate) static CONNECTION: RefCell<ConnectionState> = RefCell::new(ConnectionState::new());
+}
+
+// Connection lifecycle: Disconnected -> Connecting -> Connected, and back to
+// Disconnected on a failed connect, a user disconnect (via Disconnecting while
+// streams drain), or the session closing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Disconnected,
+    Connecting,
+    Connected,
+    /// `disconnect()` is draining streams before closing.
+    Disconnecting,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Disconnected => "disconnected",
+            Status::Connecting => "connecting",
+            Status::Connected => "connected",
+            Status::Disconnecting => "disconnecting",
+        }
+    }
+}
+
+/// Current connection status: `"disconnected"`, `"connecting"`, `"connected"`
+/// or `"disconnecting"`.
+#[wasm_bindgen]
+pub fn connection_status() -> String {
+    CONNECTION.with(|conn| conn.borrow().status.as_str().to_string())
+}
+
+#[wasm_bindgen]
+pub async fn connect_to_server(url_str: String) -> Result<(), JsValue> {
+    // Get the certificate hash (same as in client.html)
+    let cert_hash_hex = "dbecff3c052db73b98936dc11ebce78bafe3d70044243835ed221f091ee0fea7";
+    let cert_hash = hex_to_bytes(cert_hash_hex);
+
+    connect(&url_str, cert_hash).await.map_err(fail)
+}
+
+/// Connect to `url_str`, pinning the certificate whose SHA-256 is `cert_hash_hex`
+/// (as served by the server's `/cert-hash`).
+#[wasm_bindgen]
+pub async fn connect_with_cert_hash(url_str: String, cert_hash_hex: String) -> Result<(), JsValue> {
+    if cert_hash_hex.len() != 64 || !cert_hash_hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        let error = format!("{:?} is not a hex SHA-256 digest", cert_hash_hex);
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+
+    connect(&url_str, hex_to_bytes(&cert_hash_hex))
+        .await
+        .map_err(fail)
+}
+
+// A session this client didn't close was lost: reconnect as the resilience
+// policy says, in a build that can
+#[cfg(feature = "reconnect")]
+fn on_session_lost(url: String, cert_hash: Vec<u8>, code: Option<u32>) {
+    reconnect::on_session_lost(url, cert_hash, code);
+}
+
+#[cfg(not(feature = "reconnect"))]
+fn on_session_lost(_: String, _: Vec<u8>, _: Option<u32>) {}
+
+/// Connect to `url_str`, pinning the certificate with SHA-256 digest `cert_hash`.
+pub(crate) async fn connect(url_str: &str, cert_hash: Vec<u8>) -> Result<(), PlaygroundError> {
+    let result = start(url_str, cert_hash, 0).await;
+    #[cfg(feature = "history")]
+    if result.is_ok() {
+        settings::remember_server_url(url_str);
+    }
+    result
+}
+
+// Connect, having followed `redirects` redirects in a row to get here
+async fn start(url_str: &str, cert_hash: Vec<u8>, redirects: u32) -> Result<(), PlaygroundError> {
+    let generation = CONNECTION.with(|conn| {
+        let mut state = conn.borrow_mut();
+        if state.status != Status::Disconnected {
+            return Err(PlaygroundError::ConnectFailed(format!(
+                "already {}",
+                state.status.as_str()
+            )));
+        }
+        state.status = Status::Connecting;
+        state.generation += 1;
+        state.redirects = redirects;
+        Ok(state.generation)
+    })?;
+
+    let result = open_session(url_str, cert_hash, generation).await;
+    CONNECTION.with(|conn| {
+        conn.borrow_mut().status = match result {
+            Ok(()) => Status::Connected,
+            Err(_) => Status::Disconnected,
+        }
+    });
+    result
+}
+
+async fn open_session(
+    url_str: &str,
+    cert_hash: Vec<u8>,
+    generation: u64,
+) -> Result<(), PlaygroundError> {
+    console::log_1(&format!("Connecting to: {}", url_str).into());
+
+    // Parse the URL
+    let url = url_str
+        .parse()
+        .map_err(|e| PlaygroundError::InvalidUrl(format!("{}", e)))?;
+
+    // Build client with certificate pinning and enable unreliable transport (datagrams)
+    let client = ClientBuilder::new()
+        .with_unreliable(true)
+        .with_server_certificate_hashes(vec![cert_hash.clone()])
+        .map_err(|e| PlaygroundError::ConnectFailed(e.to_string()))?;
+
+    match client.connect(url).await {
+        Ok(mut session) => {
+            console::log_1(&"Connected successfully!".into());
+            add_message("Connected successfully!", "system");
+
+            // Open a bidirectional stream
+            match session.open_bi().await {
+                Ok((send_stream, recv_stream)) => {
+                    console::log_1(&"Bidirectional stream opened".into());
+                    add_message("Stream opened, ready to send/receive", "system");
+
+                    // Clone session for datagram operations
+                    // Session is cloneable and each clone is a handle to the same connection
+                    let session_for_datagrams = session.clone();
+                    let session_for_uni = session.clone();
+                    let session_for_close = session.clone();
+
+                    // Store the session in global state
+                    CONNECTION.with(|conn| {
+                        let mut state = conn.borrow_mut();
+                        state.session = Some(session);
+                        state.url = Some(url_str.to_string());
+                        state.cert_hash = Some(cert_hash);
+                    });
+
+                    // Store the main send stream and start reading frames from it
+                    streams::register(streams::MAIN_STREAM, send_stream, recv_stream);
+                    transfer::on_connected();
+
+                    // Spawn a task to receive datagrams
+                    // Use the cloned session - no mutex needed!
+                    spawn_local(async move {
+                        let mut session_dg = session_for_datagrams;
+                        loop {
+                            match session_dg.recv_datagram().await {
+                                Ok(bytes) => {
+                                    if let Some(probe_id) = decode_mtu_probe(&bytes) {
+                                        // Acks are timed, so they skip the pause buffer
+                                        mtu::on_ack(probe_id);
+                                        continue;
+                                    }
+                                    #[cfg(feature = "bench")]
+                                    if let Some(ping_id) = decode_ping(&bytes) {
+                                        ping::on_echo(ping_id);
+                                        continue;
+                                    }
+                                    pause::deliver_datagram(bytes);
+                                }
+                                Err(e) => {
+                                    console::error_1(
+                                        &format!("Datagram recv error: {:?}", e).into(),
+                                    );
+                                    break;
+                                }
+                            }
+                        }
+                    });
+
+                    // Spawn a task to accept server-initiated unidirectional streams;
+                    // they carry frames too (scenario messages, bandwidth estimates)
+                    spawn_local(async move {
+                        let mut session_uni = session_for_uni;
+                        while let Ok(recv) = session_uni.accept_uni().await {
+                            streams::read_server_stream(recv);
+                        }
+                    });
+
+                    // Estimate the server clock offset up front so burst tests can start on time
+                    spawn_local(async {
+                        if let Err(e) = clock::sync().await {
+                            console::warn_1(&format!("Clock sync failed: {}", e).into());
+                        }
+                    });
+
+                    // Keep the server posted on whether we're alive and in the foreground
+                    spawn_local(visibility::heartbeat_loop(generation));
+
+                    // Report how the session ended, with a readable reason for our close codes
+                    let opened_at = js_sys::Date::now();
+                    spawn_local(async move {
+                        let error = session_for_close.closed().await;
+                        let (current, client, target) = CONNECTION.with(|conn| {
+                            let mut state = conn.borrow_mut();
+                            let current = state.generation == generation;
+                            if current {
+                                state.status = Status::Disconnected;
+                                state.session = None;
+                                state.streams.clear();
+                                streams::clear();
+                                raw::clear();
+                                transfer::interrupt();
+                            }
+                            let client = state
+                                .closed_by_client
+                                .filter(|(closed, _)| *closed == generation)
+                                .map(|(_, client)| client);
+                            (
+                                current,
+                                client,
+                                state.url.clone().zip(state.cert_hash.clone()),
+                            )
+                        });
+                        let code = error.code().map(u32::from);
+                        let session_ms = js_sys::Date::now() - opened_at;
+                        disconnects::record(client, code, error.to_string(), session_ms);
+                        report_close(code, error.to_string());
+                        // A session dropped for the network is lost all the same
+                        #[cfg(feature = "reconnect")]
+                        let lost = matches!(client, None | Some(ClientClose::Network));
+                        #[cfg(not(feature = "reconnect"))]
+                        let lost = client.is_none();
+                        if let (true, true, Some((url, cert_hash))) = (current, lost, target) {
+                            let code = code.filter(|_| client.is_none());
+                            on_session_lost(url, cert_hash, code);
+                        }
+                    });
+
+                    Ok(())
+                }
+                Err(e) => Err(PlaygroundError::StreamFailed(e.to_string())),
+            }
+        }
+        Err(e) => Err(PlaygroundError::ConnectFailed(e.to_string())),
+    }
+}
+
+/// Streams (by label) that finished cleanly and ones cut off, from `disconnect()`.
+#[derive(Debug, Serialize)]
+struct DrainReport {
+    flushed: Vec<String>,
+    dropped: Vec<String>,
+    /// Requests still waiting for a response when the session closed.
+    cancelled_requests: usize,
+    /// Whether everything drained in time and the session closed with code 0.
+    graceful: bool,
+}
+
+/// Disconnect gracefully: finish every send stream and wait up to `timeout_ms`
+/// (default 2000) for queued writes to go out and the server to acknowledge
+/// them, then close as `normal` (code 0). Streams still sending at the timeout
+/// are cut off and the session is closed as `drain` (code 103) instead.
+/// Resolves with `{ flushed, dropped, cancelled_requests, graceful }`.
+#[wasm_bindgen]
+pub async fn disconnect(timeout_ms: Option<u32>) -> Result<JsValue, JsValue> {
+    shutdown(timeout_ms, ClientClose::User).await
+}
+
+// Drain and close the session, recording that this client closed it and why
+async fn shutdown(timeout_ms: Option<u32>, why: ClientClose) -> Result<JsValue, JsValue> {
+    console::log_1(&"Disconnecting...".into());
+    #[cfg(feature = "reconnect")]
+    reconnect::cancel();
+
+    // New sends fail from here on, and connect waits until we're done
+    let streams = CONNECTION.with(|conn| {
+        let mut state = conn.borrow_mut();
+        if state.session.is_some() {
+            state.status = Status::Disconnecting;
+            state.closed_by_client = Some((state.generation, why));
+        }
+        std::mem::take(&mut state.streams)
+    });
+
+    let timeout_ms = timeout_ms.unwrap_or(DRAIN_TIMEOUT_MS);
+    let drains = streams.into_iter().map(|(label, stream)| async move {
+        // Frames still batched go out before the stream finishes
+        let flushed =
+            async { coalesce::finish(&label, &stream).await.is_ok() && drain(stream).await };
+        let drained = matches!(
+            select(Box::pin(flushed), TimeoutFuture::new(timeout_ms)).await,
+            Either::Left((true, _))
+        );
+        if drained {
+            streams::finished(&label);
+        }
+        (label, drained)
+    });
+    let (flushed, dropped): (Vec<_>, Vec<_>) = join_all(drains)
+        .await
+        .into_iter()
+        .partition(|(_, drained)| *drained);
+    let flushed: Vec<String> = flushed.into_iter().map(|(label, _)| label).collect();
+    let dropped: Vec<String> = dropped.into_iter().map(|(label, _)| label).collect();
+
+    let session = CONNECTION.with(|conn| {
+        let mut state = conn.borrow_mut();
+        state.status = Status::Disconnected;
+        state.session.take()
+    });
+
+    // Reject anything still waiting for a response
+    let cancelled_requests = rpc::cancel_all();
+    bandwidth::clear();
+    quality::clear();
+    rate::stop();
+    clock::clear();
+    verify::cancel_all();
+    mtu::clear();
+    pause::clear();
+    streams::clear();
+    raw::clear();
+    rooms::clear();
+    reliable::clear();
+    hello::clear();
+    #[cfg(feature = "bench")]
+    {
+        bench::cancel();
+        compare::cancel();
+        ping::clear();
+    }
+
+    let graceful = dropped.is_empty();
+    if let Some(mut session) = session {
+        if graceful {
+            session.close(CloseCode::Normal.code(), "User requested disconnect");
+        } else {
+            let reason = format!("{} streams did not drain", dropped.len());
+            session.close(CloseCode::Drain.code(), &reason);
+        }
+    }
+
+    add_message(
+        &format!(
+            "Disconnected: {} streams flushed, {} dropped, {} requests cancelled",
+            flushed.len(),
+            dropped.len(),
+            cancelled_requests
+        ),
+        "system",
+    );
+    let report = DrainReport {
+        flushed,
+        dropped,
+        cancelled_requests,
+        graceful,
+    };
+    to_js(&report)
+}
+
+/// Close a session the network went away under, without waiting for it to
+/// drain, and leave it to be reconnected and resumed as if it were lost.
+#[cfg(feature = "reconnect")]
+pub fn restart(why: &str) {
+    let session = CONNECTION.with(|conn| {
+        let mut state = conn.borrow_mut();
+        let session = state.session.clone()?;
+        state.closed_by_client = Some((state.generation, ClientClose::Network));
+        Some(session)
+    });
+    if let Some(mut session) = session {
+        session.close(CloseCode::Drain.code(), why);
+    }
+}
+
+/// Close this session as `disconnect()` does and connect to `url` instead,
+/// with the same certificate pin. Emits a `redirected` event with the outcome.
+/// After [`MAX_REDIRECTS`] in a row, further redirects are ignored.
+pub fn redirect(url: String) {
+    let target = CONNECTION.with(|conn| {
+        let state = conn.borrow();
+        state.session.as_ref()?;
+        Some((state.cert_hash.clone()?, state.redirects + 1))
+    });
+    let Some((cert_hash, hops)) = target else {
+        return;
+    };
+    if hops > MAX_REDIRECTS || !url.starts_with("https://") {
+        let why = if hops > MAX_REDIRECTS {
+            format!("{} redirects in a row", MAX_REDIRECTS)
+        } else {
+            "not an https URL".to_string()
+        };
+        add_message(
+            &format!("Not following redirect to {}: {}", url, why),
+            "system",
+        );
+        events::emit(Event::Redirected {
+            url,
+            hops,
+            followed: false,
+            reconnected: false,
+        });
+        return;
+    }
+
+    add_message(&format!("Redirected to {}, reconnecting", url), "system");
+    spawn_local(async move {
+        let _ = shutdown(None, ClientClose::Redirect).await;
+        let result = start(&url, cert_hash, hops).await;
+        events::emit(Event::Redirected {
+            url,
+            hops,
+            followed: true,
+            reconnected: result.is_ok(),
+        });
+        if let Err(error) = result {
+            fail(error);
+        }
+    });
+}
+
+// Log and emit the end of the session. `code` is None if the session ended
+// without a close code, e.g. the connection was lost.
+fn report_close(code: Option<u32>, reason: String) {
+    let description = match code.map(|code| (code, CloseCode::from_code(code))) {
+        None => "Connection lost".to_string(),
+        Some((_, Some(close))) => close.description().to_string(),
+        Some((code, None)) => format!("Closed with unknown code {}", code),
+    };
+    let message = format!("Session closed: {} ({})", description, reason);
+    if code.is_some_and(|code| code != CloseCode::Normal.code()) {
+        console::error_1(&message.clone().into());
+    }
+    add_message(&message, "system");
+
+    events::emit(Event::SessionClosed {
+        code,
+        close: code.and_then(CloseCode::from_code),
+        description,
+        reason,
+    });
+}
+
+// Wait for writes queued on the stream, then finish it and wait until the
+// server has everything. False if the stream failed instead.
+async fn drain(stream: Rc<Mutex<SendStream>>) -> bool {
+    let mut stream = stream.lock().await;
+    stream.finish().is_ok() && stream.closed().await.is_ok()
+}
@@ -0,0 +1,64 @@
+use crate::events::{self, Event};
+use std::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
+
+/// Message ids remembered; a copy arriving after this many newer ones gets through.
+const WINDOW: usize = 1024;
+
+#[derive(Default)]
+struct Seen {
+    ids: HashSet<String>,
+    /// Oldest first, to forget in order.
+    order: VecDeque<String>,
+}
+
+thread_local! {
+    static SEEN: RefCell<Seen> = RefCell::new(Seen::default());
+}
+
+/// A fresh id for a message or relay, so the server handles it once however
+/// often it's sent.
+pub fn new_id() -> String {
+    #[cfg(feature = "crypto")]
+    if let Some(crypto) = web_sys::window().and_then(|window| window.crypto().ok()) {
+        return crypto.random_uuid();
+    }
+    // Without Web Crypto (outside a window, or built without it), a version 4
+    // UUID from Math.random
+    let mut hex: Vec<char> = (0..32)
+        .map(|_| char::from_digit((js_sys::Math::random() * 16.0) as u32, 16).unwrap_or('0'))
+        .collect();
+    hex[12] = '4';
+    hex[16] = char::from_digit(8 + (js_sys::Math::random() * 4.0) as u32, 16).unwrap_or('8');
+    let hex: String = hex.into_iter().collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Whether an echo or relay carrying `id` is the first with it. Repeats emit a
+/// `duplicate_dropped` event and should be dropped.
+pub fn first(id: &str) -> bool {
+    let first = SEEN.with(|seen| {
+        let mut seen = seen.borrow_mut();
+        if !seen.ids.insert(id.to_string()) {
+            return false;
+        }
+        seen.order.push_back(id.to_string());
+        if seen.order.len() > WINDOW
+            && let Some(oldest) = seen.order.pop_front()
+        {
+            seen.ids.remove(&oldest);
+        }
+        true
+    });
+    if !first {
+        events::emit(Event::DuplicateDropped { id: id.to_string() });
+    }
+    first
+}
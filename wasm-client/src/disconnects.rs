@@ -0,0 +1,84 @@
+use crate::to_js;
+use protocol::CloseCode;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+
+/// Why this client closed a session itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientClose {
+    /// The page called `disconnect()`.
+    User,
+    /// Following a server redirect.
+    Redirect,
+    /// The network went away under the session; it's reconnected as if lost.
+    #[cfg(feature = "reconnect")]
+    Network,
+}
+
+/// One session's end.
+#[derive(Debug, Clone, Serialize)]
+struct Disconnect {
+    reason: String,
+    /// Close code, if the session was closed with one.
+    code: Option<u32>,
+    detail: String,
+    /// How long the session had been open.
+    session_ms: f64,
+    /// When it ended, in ms since the Unix epoch.
+    at_ms: f64,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Stats {
+    total: u64,
+    reasons: BTreeMap<String, u64>,
+    last: Option<Disconnect>,
+}
+
+thread_local! {
+    static STATS: RefCell<Stats> = RefCell::new(Stats::default());
+}
+
+/// Why sessions ended since the page loaded (or `reset_disconnect_stats()`):
+/// `{ total, reasons, last: { reason, code, detail, session_ms, at_ms } }`.
+/// `reasons` counts each of `user` (`disconnect()`), `redirect` (following a
+/// server redirect), `network` (dropped after the network changed), `idle` (the server's `idle` close code), `server_close:<code>`
+/// (any other close code) and `transport_error` (lost without a close code).
+#[wasm_bindgen]
+pub fn get_disconnect_stats() -> Result<JsValue, JsValue> {
+    STATS.with(|stats| to_js(&*stats.borrow()))
+}
+
+/// Start counting disconnects afresh.
+#[wasm_bindgen]
+pub fn reset_disconnect_stats() {
+    STATS.with(|stats| *stats.borrow_mut() = Stats::default());
+}
+
+/// Count a session that ended, `session_ms` after it opened. `client` is set if
+/// this client closed it.
+pub fn record(client: Option<ClientClose>, code: Option<u32>, detail: String, session_ms: f64) {
+    let reason = match (client, code) {
+        (Some(ClientClose::User), _) => "user".to_string(),
+        (Some(ClientClose::Redirect), _) => "redirect".to_string(),
+        #[cfg(feature = "reconnect")]
+        (Some(ClientClose::Network), _) => "network".to_string(),
+        (None, None) => "transport_error".to_string(),
+        (None, Some(code)) if code == CloseCode::Idle.code() => "idle".to_string(),
+        (None, Some(code)) => format!("server_close:{}", code),
+    };
+    STATS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        stats.total += 1;
+        *stats.reasons.entry(reason.clone()).or_default() += 1;
+        stats.last = Some(Disconnect {
+            reason,
+            code,
+            detail,
+            session_ms,
+            at_ms: js_sys::Date::now(),
+        });
+    });
+}
@@ -0,0 +1,358 @@
+#[cfg(feature = "bench")]
+use crate::compare::Transport;
+use crate::quality::QualityLevel;
+use crate::streams::{QueueReason, StreamChange};
+use crate::verify::Violation;
+use futures::StreamExt;
+use futures::channel::mpsc;
+use futures::lock::Mutex;
+use protocol::CloseCode;
+use protocol::hello::ServerInfo;
+use protocol::pipeline::Aggregate;
+use protocol::room::RoomAction;
+use protocol::telemetry::BandwidthEstimate;
+use serde::Serialize;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use web_sys::console;
+
+/// Events held for `next_event()` while nothing awaits them. Past it, newer
+/// events are dropped from the queue; the callback still gets them.
+const QUEUE_CAPACITY: usize = 256;
+
+// Structured events pushed to JS through a single registered callback.
+// Serialized as plain objects tagged by `type`, e.g. { type: "unmatched_response", id: 3, ... }
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A response arrived with no pending request for its id (late, duplicate, or unknown).
+    UnmatchedResponse { id: u64 },
+    /// The server announced its capabilities and limits as the session opened.
+    Hello(ServerInfo),
+    /// The server pushed a new estimate of the connection's available bandwidth.
+    BandwidthEstimate(BandwidthEstimate),
+    /// The connection quality level changed (`previous` is null for the first
+    /// estimate). `score` is the composite score, 0 to 100, that crossed the threshold.
+    QualityChanged {
+        level: QualityLevel,
+        previous: Option<QualityLevel>,
+        score: f64,
+    },
+    /// The rate controller processed a feedback report. `rate` is in datagrams per second.
+    RateAdjusted {
+        rate: f64,
+        loss: f64,
+        rtt_us: u64,
+        congested: bool,
+    },
+    /// An ordered-delivery check failed. `expected` is set for out-of-order echoes.
+    ProtocolViolation {
+        stream: String,
+        violation: Violation,
+        seq: u64,
+        expected: Option<u64>,
+    },
+    /// One window of a subscribed pipeline topic.
+    Aggregate(Aggregate),
+    /// Connection `member` of a joined room became its owner, or its owner,
+    /// `by`, had it `kicked`, `muted` or `unmuted`.
+    RoomNotice {
+        room: String,
+        action: RoomAction,
+        member: u64,
+        by: Option<u64>,
+    },
+    /// The session ended. `close` names the close code if it's one of ours
+    /// (`protocol::CloseCode`); `code` is null if the connection was lost without one.
+    SessionClosed {
+        code: Option<u32>,
+        close: Option<CloseCode>,
+        description: String,
+        reason: String,
+    },
+    /// While paused, buffered incoming data passed the high watermark. At `limit`
+    /// bytes stream reads stop and datagrams are dropped.
+    BufferHighWatermark {
+        buffered_bytes: usize,
+        buffered_items: usize,
+        limit: usize,
+    },
+    /// The page was hidden or shown (the Page Visibility API). On becoming
+    /// visible, `hidden_ms` is how long it was hidden.
+    VisibilityChanged {
+        visible: bool,
+        hidden_ms: Option<f64>,
+    },
+    /// The browser went offline or came back online (`navigator.onLine`), or
+    /// moved to another kind of network while online. On coming back,
+    /// `offline_ms` is how long it was offline. `kind` is
+    /// `navigator.connection.type` (e.g. `wifi`), where the browser has it.
+    NetworkChanged {
+        online: bool,
+        offline_ms: Option<f64>,
+        kind: Option<String>,
+    },
+    /// The server redirected the session to `url`, the `hops`th redirect in a
+    /// row. `followed` is false if the client refused (too many hops, or not
+    /// https); otherwise `reconnected` says whether the new session opened.
+    Redirected {
+        url: String,
+        hops: u32,
+        followed: bool,
+        reconnected: bool,
+    },
+    /// The reliable datagram layer gave up on `lost` messages (out of retransmits
+    /// or buffer) and delivered past them; `total_lost` counts the whole session.
+    ReliableLost { lost: u64, total_lost: u64 },
+    /// A datagram (`stream` null) or a read from the labelled stream, hexdumped
+    /// while inspection is on. `dump` covers the first 4 KiB of the `len` bytes.
+    Inspected {
+        stream: Option<String>,
+        len: usize,
+        dump: String,
+    },
+    /// A stream was opened, finished, reset or stopped. `id` is unique for the
+    /// page's lifetime; `label` is the stream's label, or `server` for streams
+    /// the server opened. `code` is the stream error code of a `reset` or
+    /// `stopped`, if the browser reports one.
+    StreamChanged {
+        id: u64,
+        label: String,
+        change: StreamChange,
+        code: Option<u8>,
+    },
+    /// Opening the stream under `label` has to wait, for `reason`; it's opened
+    /// (and reported as `stream_changed`) once it can be.
+    StreamOpenQueued { label: String, reason: QueueReason },
+    /// The server refused the stream under `label` with `code`, over its
+    /// stream limit. It's reopened shortly if `retrying`; otherwise the client
+    /// gave up after several refusals in a row.
+    StreamOpenRejected {
+        label: String,
+        code: u8,
+        retrying: bool,
+    },
+    /// A lost session will be reconnected in `delay_ms`, the `attempt`th try
+    /// of `max_retries` under the resilience policy.
+    #[cfg(feature = "reconnect")]
+    Reconnecting {
+        attempt: u32,
+        max_retries: u32,
+        delay_ms: u32,
+    },
+    /// Reconnecting succeeded on the `attempts`th try.
+    #[cfg(feature = "reconnect")]
+    Reconnected { attempts: u32 },
+    /// The server moved the lost session onto the new one: it's back in
+    /// `rooms`, as `nick`, and `missed` relayed messages are on their way as
+    /// `replayed`. `dropped` more didn't fit the server's queue.
+    #[cfg(feature = "reconnect")]
+    Resumed {
+        rooms: Vec<String>,
+        nick: Option<String>,
+        missed: u64,
+        dropped: u64,
+    },
+    /// An echo or relay carrying message `id` arrived again, e.g. replayed after
+    /// a resume, and was dropped.
+    DuplicateDropped { id: String },
+    /// Both copies of comparison message `seq` came back, or the comparison
+    /// gave up waiting for one: `first` is the transport whose echo arrived
+    /// first (null for a tie or if neither did), `delta_ms` how much sooner,
+    /// and `stream_ms` and `datagram_ms` each copy's round trip (null if lost).
+    /// `stream_dwell_ms` is how much of the stream's round trip the server held
+    /// the message for, from its echo's timing.
+    #[cfg(feature = "bench")]
+    TransportCompared {
+        seq: u64,
+        first: Option<Transport>,
+        delta_ms: Option<f64>,
+        stream_ms: Option<f64>,
+        datagram_ms: Option<f64>,
+        stream_dwell_ms: Option<f64>,
+    },
+    /// A datagram sent with `max_age_ms` waited `age_ms` for the throttle and
+    /// was dropped instead of sent.
+    DatagramExpired { age_ms: f64, max_age_ms: u32 },
+    /// Reconnecting stopped after `attempts` tries (0 if the close code isn't
+    /// retried), for `reason`.
+    #[cfg(feature = "reconnect")]
+    ReconnectGaveUp { attempts: u32, reason: String },
+    /// The session caught up after the page became visible again: the server was
+    /// told and the clock offset re-estimated (`clock_offset_ms` is null if that failed).
+    Resynced {
+        hidden_ms: f64,
+        clock_offset_ms: Option<f64>,
+    },
+    /// The server acked `offset` of the `size` bytes of file transfer `id`.
+    TransferProgress { id: String, offset: u64, size: u64 },
+    /// The session carrying transfer `id` was lost with `offset` bytes acked;
+    /// it resumes once a new session opens.
+    TransferInterrupted { id: String, offset: u64, size: u64 },
+    /// Transfer `id` carries on from `offset`, or, if `restarted`, starts over
+    /// under this new id because the server no longer had the old one.
+    TransferResumed {
+        id: String,
+        offset: u64,
+        size: u64,
+        restarted: bool,
+    },
+    /// The session has used `used` of the `limit` bytes the server lets it
+    /// send and receive; at the limit it's closed as `quota_exceeded`.
+    QuotaWarning { used: u64, limit: u64 },
+}
+
+// Events waiting for `next_event()`. Only exists once something has asked for
+// one, so a page using just the callback doesn't pile them up
+struct Queue {
+    sender: mpsc::Sender<JsValue>,
+    receiver: Rc<Mutex<mpsc::Receiver<JsValue>>>,
+    /// Events sent and not yet taken.
+    queued: Rc<Cell<usize>>,
+    /// Events dropped since the queue last had room.
+    dropped: u64,
+}
+
+impl Queue {
+    fn new() -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        Self {
+            sender,
+            receiver: Rc::new(Mutex::new(receiver)),
+            queued: Rc::new(Cell::new(0)),
+            dropped: 0,
+        }
+    }
+}
+
+// The object `event_stream()` hands out, and the closure behind its `next`
+struct EventStream {
+    _next: Closure<dyn FnMut() -> js_sys::Promise>,
+    iterator: JsValue,
+}
+
+thread_local! {
+    static HANDLER: RefCell<Option<js_sys::Function>> = const { RefCell::new(None) };
+    static QUEUE: RefCell<Option<Queue>> = const { RefCell::new(None) };
+    static STREAM: RefCell<Option<EventStream>> = const { RefCell::new(None) };
+}
+
+/// Register the callback that receives client events. Pass `undefined` to remove it.
+#[wasm_bindgen]
+pub fn set_event_handler(handler: Option<js_sys::Function>) {
+    HANDLER.with(|h| *h.borrow_mut() = handler);
+}
+
+/// Resolve with the next client event, as an async iterator result: `{ value,
+/// done: false }`, or `{ value: undefined, done: true }` once `stop_events()`
+/// ends the stream. The first call starts queueing events; up to 256 wait for
+/// the next call, and newer ones are dropped while the queue is full. Events
+/// still go to the `set_event_handler` callback too.
+#[wasm_bindgen]
+pub async fn next_event() -> Result<JsValue, JsValue> {
+    let (receiver, queued) = QUEUE.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        let queue = queue.get_or_insert_with(Queue::new);
+        (queue.receiver.clone(), queue.queued.clone())
+    });
+    let event = receiver.lock().await.next().await;
+    if event.is_some() {
+        queued.set(queued.get().saturating_sub(1));
+    }
+    iterator_result(event)
+}
+
+/// An async iterable over client events, for `for await (const event of
+/// event_stream())`. Every call returns the same one, backed by `next_event()`;
+/// breaking out of the loop doesn't stop queueing, `stop_events()` does.
+#[wasm_bindgen]
+pub fn event_stream() -> Result<JsValue, JsValue> {
+    STREAM.with(|stream| {
+        let mut stream = stream.borrow_mut();
+        if let Some(stream) = stream.as_ref() {
+            return Ok(stream.iterator.clone());
+        }
+        let next = Closure::<dyn FnMut() -> js_sys::Promise>::new(|| {
+            wasm_bindgen_futures::future_to_promise(next_event())
+        });
+        let iterator = js_sys::Object::new();
+        js_sys::Reflect::set(&iterator, &"next".into(), next.as_ref())?;
+        let itself = js_sys::Function::new_no_args("return this;");
+        js_sys::Reflect::set(&iterator, &js_sys::Symbol::async_iterator(), &itself)?;
+        let iterator = JsValue::from(iterator);
+        *stream = Some(EventStream {
+            _next: next,
+            iterator: iterator.clone(),
+        });
+        Ok(iterator)
+    })
+}
+
+/// Stop queueing events and drop those waiting. Pending and later
+/// `next_event()` calls resolve as done, until one starts the queue again.
+#[wasm_bindgen]
+pub fn stop_events() {
+    QUEUE.with(|queue| queue.borrow_mut().take());
+}
+
+/// Events waiting for `next_event()`.
+pub fn queued() -> usize {
+    QUEUE.with(|queue| {
+        queue
+            .borrow()
+            .as_ref()
+            .map_or(0, |queue| queue.queued.get())
+    })
+}
+
+fn iterator_result(event: Option<JsValue>) -> Result<JsValue, JsValue> {
+    let result = js_sys::Object::new();
+    let done = event.is_none();
+    js_sys::Reflect::set(&result, &"value".into(), &event.unwrap_or_default())?;
+    js_sys::Reflect::set(&result, &"done".into(), &done.into())?;
+    Ok(result.into())
+}
+
+pub fn emit(event: Event) {
+    let handler = HANDLER.with(|h| h.borrow().clone());
+    let queueing = QUEUE.with(|queue| queue.borrow().is_some());
+    if handler.is_none() && !queueing {
+        return;
+    }
+
+    let value = match serde_wasm_bindgen::to_value(&event) {
+        Ok(value) => value,
+        Err(e) => {
+            console::error_1(&format!("Failed to serialize event: {:?}", e).into());
+            return;
+        }
+    };
+    if let Some(handler) = handler
+        && let Err(e) = handler.call1(&JsValue::NULL, &value)
+    {
+        console::error_2(&"Event handler threw:".into(), &e);
+    }
+    // The handler may have stopped the queue
+    QUEUE.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        let Some(queue) = queue.as_mut() else {
+            return;
+        };
+        if queue.queued.get() < QUEUE_CAPACITY && queue.sender.try_send(value).is_ok() {
+            queue.queued.set(queue.queued.get() + 1);
+            queue.dropped = 0;
+            return;
+        }
+        queue.dropped += 1;
+        if queue.dropped == 1 {
+            console::warn_1(
+                &format!(
+                    "Event queue full: dropping events until next_event() catches up ({} waiting)",
+                    QUEUE_CAPACITY
+                )
+                .into(),
+            );
+        }
+    });
+}
@@ -0,0 +1,59 @@
+use crate::events::{self, Event};
+use crate::{add_message, clock, to_js};
+use protocol::expiry;
+use serde::Serialize;
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+
+#[derive(Debug, Default, Serialize)]
+struct ExpiryStats {
+    /// Datagrams sent with a max age.
+    sent: u64,
+    /// Datagrams dropped before sending, having waited past their max age.
+    expired: u64,
+}
+
+thread_local! {
+    static STATS: RefCell<ExpiryStats> = RefCell::new(ExpiryStats::default());
+}
+
+/// Datagrams with a max age sent and dropped since the last reset: `{ sent,
+/// expired }`. Those dropped by the server for arriving late are counted in its
+/// `/stats` under `datagram_expiry`.
+#[wasm_bindgen]
+pub fn get_expiry_stats() -> Result<JsValue, JsValue> {
+    STATS.with(|s| to_js(&*s.borrow()))
+}
+
+#[wasm_bindgen]
+pub fn reset_expiry_stats() {
+    STATS.with(|s| *s.borrow_mut() = ExpiryStats::default());
+}
+
+/// Wrap `message`, created at local time `created_at`, so the server drops it
+/// once older than `max_age_ms`. The send time is put on the server's clock
+/// with the offset from `sync_clock()`, if there is one.
+pub fn encode(message: &[u8], created_at: f64, max_age_ms: u32) -> Vec<u8> {
+    let offset = clock::get_clock_offset().unwrap_or(0.0);
+    STATS.with(|s| s.borrow_mut().sent += 1);
+    expiry::encode((created_at + offset) as u64, max_age_ms, message)
+}
+
+/// Whether a datagram created at `created_at` has waited past `max_age_ms` to
+/// be sent. An expired one is counted and reported as a `datagram_expired` event.
+pub fn expired(created_at: f64, max_age_ms: u32) -> bool {
+    let age_ms = js_sys::Date::now() - created_at;
+    if age_ms <= max_age_ms as f64 {
+        return false;
+    }
+    STATS.with(|s| s.borrow_mut().expired += 1);
+    events::emit(Event::DatagramExpired { age_ms, max_age_ms });
+    add_message(
+        &format!(
+            "[Datagram] dropped after {:.0} ms queued (max age {} ms)",
+            age_ms, max_age_ms
+        ),
+        "system",
+    );
+    true
+}
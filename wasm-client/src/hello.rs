@@ -0,0 +1,55 @@
+use crate::add_message;
+use crate::events::{self, Event};
+use crate::to_js;
+use protocol::hello::{Limits, PROTOCOL_VERSION, ServerInfo};
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+use web_sys::console;
+
+thread_local! {
+    static INFO: RefCell<Option<ServerInfo>> = const { RefCell::new(None) };
+}
+
+/// What the server announced about itself when the session opened, as
+/// `{ protocol_versions, max_datagram_size, limits: { max_frame_len,
+/// messages_per_second, violation_strikes, max_bi_streams, max_uni_streams },
+/// features, build: { name, version, profile }, session_token }`, or `null`
+/// before its hello arrives. Also delivered as a `hello` event.
+#[wasm_bindgen]
+pub fn get_server_info() -> Result<JsValue, JsValue> {
+    INFO.with(|info| match &*info.borrow() {
+        Some(info) => to_js(info),
+        None => Ok(JsValue::NULL),
+    })
+}
+
+/// The limits the server announced for the current session, if its hello arrived.
+pub fn limits() -> Option<Limits> {
+    INFO.with(|info| Some(info.borrow().as_ref()?.limits))
+}
+
+/// Token the server issued for resuming the last session, kept after the
+/// session is lost until the next hello replaces it.
+#[cfg(feature = "reconnect")]
+pub fn session_token() -> Option<String> {
+    INFO.with(|info| info.borrow().as_ref()?.session_token.clone())
+}
+
+pub fn store(info: ServerInfo) {
+    console::log_1(&format!("Server hello: {:?}", info).into());
+    if !info.protocol_versions.contains(&PROTOCOL_VERSION) {
+        add_message(
+            &format!(
+                "Server speaks protocol versions {:?}, this client speaks {}",
+                info.protocol_versions, PROTOCOL_VERSION
+            ),
+            "system",
+        );
+    }
+    INFO.with(|slot| *slot.borrow_mut() = Some(info.clone()));
+    events::emit(Event::Hello(info));
+}
+
+pub fn clear() {
+    INFO.with(|info| *info.borrow_mut() = None);
+}
@@ -0,0 +1,90 @@
+use crate::events::{self, Event};
+use std::cell::Cell;
+use std::fmt::Write;
+use wasm_bindgen::prelude::*;
+
+/// Bytes shown per dump; the rest of a larger payload is only counted.
+const MAX_DUMP_LEN: usize = 4096;
+
+const BYTES_PER_LINE: usize = 16;
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Turn inspection on or off. While on, every datagram and every stream read
+/// is emitted as an `inspected` event (`{ stream, len, dump }`, `stream` null
+/// for datagrams) with a hexdump of its bytes as they arrived, before any
+/// decoding.
+#[wasm_bindgen]
+pub fn set_inspect_mode(enabled: bool) {
+    ENABLED.with(|e| e.set(enabled));
+}
+
+#[wasm_bindgen]
+pub fn inspect_mode() -> bool {
+    ENABLED.with(Cell::get)
+}
+
+/// Render `bytes` as a hexdump, 16 bytes a line: the offset, the bytes in hex
+/// and as ASCII with `.` for anything unprintable. A last line gives the length:
+///
+/// ```text
+/// 00000000  68 65 6c 6c 6f                                   |hello|
+/// 00000005
+/// ```
+#[wasm_bindgen]
+pub fn hexdump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (line, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let _ = write!(out, "{:08x} ", line * BYTES_PER_LINE);
+        for i in 0..BYTES_PER_LINE {
+            // An extra space splits each line into two groups of 8
+            if i % 8 == 0 {
+                out.push(' ');
+            }
+            match chunk.get(i) {
+                Some(byte) => {
+                    let _ = write!(out, "{:02x} ", byte);
+                }
+                None => out.push_str("   "),
+            }
+        }
+        out.push('|');
+        out.extend(chunk.iter().map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        }));
+        out.push_str("|\n");
+    }
+    let _ = write!(out, "{:08x}", bytes.len());
+    out
+}
+
+/// Emit a datagram as it arrived, if inspection is on.
+pub fn on_datagram(bytes: &[u8]) {
+    emit(None, bytes);
+}
+
+/// Emit one read from the stream registered under `label`, if inspection is on.
+pub fn on_stream_read(label: &str, bytes: &[u8]) {
+    emit(Some(label), bytes);
+}
+
+fn emit(stream: Option<&str>, bytes: &[u8]) {
+    if !inspect_mode() {
+        return;
+    }
+    let mut dump = hexdump(&bytes[..bytes.len().min(MAX_DUMP_LEN)]);
+    if bytes.len() > MAX_DUMP_LEN {
+        let _ = write!(dump, "\n... {} more bytes", bytes.len() - MAX_DUMP_LEN);
+    }
+    events::emit(Event::Inspected {
+        stream: stream.map(str::to_string),
+        len: bytes.len(),
+        dump,
+    });
+}
@@ -1,187 +1,280 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+mod abort;
+mod bandwidth;
+mod batch;
+#[cfg(feature = "bench")]
+mod bench;
+mod burst;
+mod clock;
+mod coalesce;
+#[cfg(feature = "bench")]
+mod compare;
+mod connection;
+mod dedup;
+mod disconnects;
+mod events;
+mod expiry;
+mod hello;
+mod inspect;
+mod memory;
+mod mtu;
+mod network;
+mod pause;
+#[cfg(feature = "bench")]
+mod ping;
+mod pins;
+mod pipeline;
+mod quality;
+mod rate;
+mod raw;
+mod reader;
+#[cfg(feature = "reconnect")]
+mod reconnect;
+mod reliable;
+mod rooms;
+mod rpc;
+mod script;
+#[cfg(feature = "history")]
+mod settings;
+mod stream_limit;
+mod streams;
+mod throttle;
+mod transfer;
+mod ui;
+mod verify;
+mod visibility;
+
+pub use connection::{connect_to_server, connect_with_cert_hash, connection_status, disconnect};
+pub use rpc::{call, request};
+pub use streams::{finish_stream, open_stream, send_on_stream};
+pub use transfer::send_file;
+pub use verify::verify_stream;
+
+pub(crate) use connection::CONNECTION;
+pub(crate) use ui::add_message;
+
+use protocol::room;
+use protocol::{Frame, PlaygroundError};
+use serde::Serialize;
 use wasm_bindgen::prelude::*;
-use wasm_bindgen_futures::spawn_local;
-use web_sys::{console, window};
-use web_transport::{ClientBuilder, SendStream, Session};
-
-// Global state to store the session and send stream
-// Session is cloneable and provides multiple handles to the same connection
-// Use Rc<RefCell> for SendStream since stream operations are synchronous
-struct ConnectionState {
-    session: Option<Session>,
-    send_stream: Option<Rc<RefCell<SendStream>>>,
-}
-
-impl ConnectionState {
-    fn new() -> Self {
-        Self {
-            session: None,
-            send_stream: None,
-        }
-    }
-}
-
-thread_local! {
-    static CONNECTION: RefCell<ConnectionState> = RefCell::new(ConnectionState::new());
-}
+use web_sys::console;
 
 #[wasm_bindgen(start)]
 pub fn main() {
     console_error_panic_hook::set_once();
+    visibility::install();
+    network::install();
+    #[cfg(feature = "history")]
+    settings::restore();
     console::log_1(&"WASM WebTransport client initialized".into());
 }
 
-#[wasm_bindgen]
-pub async fn connect_to_server(url_str: String) -> Result<(), JsValue> {
-    console::log_1(&format!("Connecting to: {}", url_str).into());
-
-    // Parse the URL
-    let url = url_str
-        .parse()
-        .map_err(|e| JsValue::from_str(&format!("Invalid URL: {:?}", e)))?;
-
-    // Get the certificate hash (same as in client.html)
-    let cert_hash_hex = "dbecff3c052db73b98936dc11ebce78bafe3d70044243835ed221f091ee0fea7";
-    let cert_hash = hex_to_bytes(cert_hash_hex);
-
-    // Build client with certificate pinning and enable unreliable transport (datagrams)
-    let client = ClientBuilder::new()
-        .with_unreliable(true)
-        .with_server_certificate_hashes(vec![cert_hash])
-        .map_err(|e| JsValue::from_str(&format!("Client build error: {:?}", e)))?;
-
-    match client.connect(url).await {
-        Ok(mut session) => {
-            console::log_1(&"Connected successfully!".into());
-            add_message("Connected successfully!", "system");
-
-            // Open a bidirectional stream
-            match session.open_bi().await {
-                Ok((send_stream, mut recv_stream)) => {
-                    console::log_1(&"Bidirectional stream opened".into());
-                    add_message("Stream opened, ready to send/receive", "system");
-
-                    // Clone session for datagram operations
-                    // Session is cloneable and each clone is a handle to the same connection
-                    let session_for_datagrams = session.clone();
+/// What every exported function rejects with, so JS can branch on `kind` or
+/// `code` instead of parsing strings: `{ kind, code, message, retryable }`.
+#[derive(Serialize)]
+struct ClientError {
+    /// Snake_case error kind, e.g. `"not_connected"`.
+    kind: &'static str,
+    /// Stable numeric code, as in `protocol::PlaygroundError::code`.
+    code: u32,
+    message: String,
+    /// Whether the same call may succeed if simply made again later.
+    retryable: bool,
+}
 
-                    // Store the session and send stream in global state
-                    CONNECTION.with(|conn| {
-                        let mut state = conn.borrow_mut();
-                        state.session = Some(session);
-                        state.send_stream = Some(Rc::new(RefCell::new(send_stream)));
-                    });
+/// Convert an error for returning to JS.
+pub(crate) fn to_js_error(error: &PlaygroundError) -> JsValue {
+    let error = ClientError {
+        kind: error.kind(),
+        code: error.code(),
+        message: error.to_string(),
+        retryable: error.is_retryable(),
+    };
+    serde_wasm_bindgen::to_value(&error).unwrap_or_else(|_| JsValue::from_str(&error.message))
+}
 
-                    // Spawn a task to continuously read from the stream
-                    spawn_local(async move {
-                        loop {
-                            // Read up to 1024 bytes at a time
-                            match recv_stream.read(1024).await {
-                                Ok(Some(bytes)) => {
-                                    let message = String::from_utf8_lossy(&bytes);
-                                    console::log_1(&format!("Received [Stream]: {}", message).into());
-                                    add_message(&format!("[Stream] {}", message), "received");
-                                }
-                                Ok(None) => {
-                                    console::log_1(&"Stream closed by server".into());
-                                    add_message("Stream closed by server", "system");
-                                    break;
-                                }
-                                Err(e) => {
-                                    console::error_1(&format!("Read error: {:?}", e).into());
-                                    add_message(&format!("Read error: {:?}", e), "system");
-                                    break;
-                                }
-                            }
-                        }
-                    });
+/// Log and display an error, and convert it for returning to JS.
+pub(crate) fn fail(error: PlaygroundError) -> JsValue {
+    let err_msg = error.to_string();
+    console::error_1(&err_msg.clone().into());
+    add_message(&err_msg, "system");
+    to_js_error(&error)
+}
 
-                    // Spawn a task to receive datagrams
-                    // Use the cloned session - no mutex needed!
-                    spawn_local(async move {
-                        let mut session_dg = session_for_datagrams;
-                        loop {
-                            match session_dg.recv_datagram().await {
-                                Ok(bytes) => {
-                                    let message = String::from_utf8_lossy(&bytes);
-                                    console::log_1(&format!("Received [Datagram]: {}", message).into());
-                                    add_message(&format!("[Datagram] {}", message), "received");
-                                }
-                                Err(e) => {
-                                    console::error_1(&format!("Datagram recv error: {:?}", e).into());
-                                    break;
-                                }
-                            }
-                        }
-                    });
+/// Convert a result for returning to JS; a value that can't be converted
+/// becomes an `internal` error.
+pub(crate) fn to_js<T: Serialize>(value: &T) -> Result<JsValue, JsValue> {
+    serde_wasm_bindgen::to_value(value)
+        .map_err(|e| to_js_error(&PlaygroundError::Internal(e.to_string())))
+}
 
-                    Ok(())
-                }
-                Err(e) => {
-                    let err_msg = format!("Failed to open stream: {:?}", e);
-                    console::error_1(&err_msg.clone().into());
-                    add_message(&err_msg, "system");
-                    Err(JsValue::from_str(&err_msg))
+fn handle_frame(label: &str, frame: Frame) {
+    // A resent message's echo, or a relay replayed after a resume, may come twice
+    if let Frame::Message { id: Some(id), .. } | Frame::Relay { id: Some(id), .. } = &frame
+        && !dedup::first(id)
+    {
+        return;
+    }
+    match frame {
+        #[cfg(feature = "bench")]
+        Frame::Message { text, .. } if label == bench::STREAM => bench::on_stream_echo(text.len()),
+        #[cfg(feature = "bench")]
+        Frame::Message { text, timing, .. } if label == compare::STREAM => {
+            compare::on_stream_echo(&text, timing)
+        }
+        Frame::Message { seq: Some(seq), .. } => verify::on_echo(label, seq),
+        Frame::Message { text, .. } => {
+            console::log_1(&format!("Received [Stream]: {}", text).into());
+            add_message(&format!("[Stream] {}", text), "received");
+        }
+        Frame::Response { id, outcome } => rpc::resolve(id, outcome),
+        Frame::Request { id, .. } => {
+            console::warn_1(&format!("Ignoring request {} from server", id).into());
+        }
+        Frame::Error { error } if label == transfer::STREAM => transfer::on_error(error),
+        Frame::Error { error } => {
+            console::error_1(&format!("Server error: {}", error).into());
+            add_message(&format!("Server error: {}", error), "system");
+        }
+        Frame::BandwidthEstimate(estimate) => bandwidth::update(estimate),
+        Frame::Burst(instruction) => burst::start(instruction),
+        Frame::Aggregate(aggregate) => events::emit(events::Event::Aggregate(aggregate)),
+        Frame::Relay {
+            room,
+            from,
+            nick,
+            text,
+            replayed,
+            ..
+        } => rooms::dispatch(&room, from, nick.as_deref(), &text, replayed),
+        Frame::RoomNotice {
+            room,
+            action,
+            member,
+            by,
+        } => rooms::on_notice(room, action, member, by),
+        Frame::Redirect { url } => connection::redirect(url),
+        Frame::Hello(info) => hello::store(info),
+        #[cfg(feature = "reconnect")]
+        Frame::Resumed {
+            rooms,
+            nick,
+            missed,
+            dropped,
+        } => reconnect::on_resumed(rooms, nick, missed, dropped),
+        // Only sent in answer to a `resume`, which only reconnecting sends
+        #[cfg(not(feature = "reconnect"))]
+        Frame::Resumed { .. } => {
+            console::warn_1(&"Ignoring resumed frame: built without reconnect".into());
+        }
+        Frame::Subscribe { .. } | Frame::Unsubscribe { .. } | Frame::Publish { .. } => {
+            console::warn_1(&"Ignoring pipeline frame only clients send".into());
+        }
+        Frame::Join { .. }
+        | Frame::Leave { .. }
+        | Frame::Kick { .. }
+        | Frame::Mute { .. }
+        | Frame::Unmute { .. } => {
+            console::warn_1(&"Ignoring room frame only clients send".into());
+        }
+        Frame::Ping { nonce, .. } => {
+            // Unanswered, the server closes the session as unresponsive
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Err(e) = write_frame(&Frame::Pong { nonce }).await {
+                    console::warn_1(&format!("Failed to answer ping {}: {}", nonce, e).into());
                 }
-            }
+            });
         }
-        Err(e) => {
-            let err_msg = format!("Connection failed: {:?}", e);
-            console::error_1(&err_msg.clone().into());
-            add_message(&err_msg, "system");
-            Err(JsValue::from_str(&err_msg))
+        Frame::TransferAck {
+            id,
+            offset,
+            checksum,
+        } => transfer::on_ack(id, offset, checksum),
+        Frame::QuotaWarning { used, limit } => {
+            add_message(
+                &format!("Warning: used {} of the {} byte quota", used, limit),
+                "system",
+            );
+            events::emit(events::Event::QuotaWarning { used, limit });
+        }
+        Frame::TransferStart { .. }
+        | Frame::TransferChunk { .. }
+        | Frame::ResumeTransfer { .. } => {
+            console::warn_1(&"Ignoring transfer frame only clients send".into());
+        }
+        Frame::Resume { .. } | Frame::Pong { .. } => {
+            console::warn_1(&"Ignoring resume or pong frame only clients send".into());
         }
     }
 }
 
+fn handle_datagram(datagram: &[u8]) {
+    inspect::on_datagram(datagram);
+    if let Some(relay) = room::decode_datagram(datagram) {
+        handle_frame("room datagram", relay);
+        return;
+    }
+    if raw::on_datagram(datagram) || reliable::on_datagram(datagram) || batch::on_datagram(datagram)
+    {
+        return;
+    }
+    #[cfg(feature = "bench")]
+    if bench::on_datagram(datagram.len()) || compare::on_datagram(datagram) {
+        return;
+    }
+    let message = String::from_utf8_lossy(datagram);
+    console::log_1(&format!("Received [Datagram]: {}", message).into());
+    add_message(&format!("[Datagram] {}", message), "received");
+}
+
+/// Write one frame to the main send stream.
+pub(crate) async fn write_frame(frame: &Frame) -> Result<(), PlaygroundError> {
+    streams::write_frame_on(streams::MAIN_STREAM, frame).await
+}
+
 #[wasm_bindgen]
 pub async fn send_message_stream(message: String) -> Result<(), JsValue> {
     console::log_1(&format!("Attempting to send: {}", message).into());
 
-    // Get a cloned reference to the send stream
-    let send_stream_rc = CONNECTION.with(|conn| {
-        let state = conn.borrow();
-        state.send_stream.clone()
-    });
-
-    match send_stream_rc {
-        Some(stream_rc) => {
-            // Now we can use the stream without holding the CONNECTION borrow
-            let message_bytes = message.as_bytes().to_vec();
-
-            let result = {
-                let mut stream = stream_rc.borrow_mut();
-                stream.write(&message_bytes).await
-            };
-
-            match result {
-                Ok(_) => {
-                    add_message(&message, "sent");
-                    console::log_1(&"Message sent successfully".into());
-                    Ok(())
-                }
-                Err(e) => {
-                    let err_msg = format!("Send error: {:?}", e);
-                    console::error_1(&err_msg.clone().into());
-                    add_message(&err_msg, "system");
-                    Err(JsValue::from_str(&err_msg))
-                }
-            }
-        }
-        None => {
-            let err_msg = "Not connected - no send stream available";
-            console::error_1(&err_msg.into());
-            add_message(err_msg, "system");
-            Err(JsValue::from_str(err_msg))
+    let frame = Frame::Message {
+        text: message.clone(),
+        seq: None,
+        id: Some(dedup::new_id()),
+        timing: None,
+    };
+
+    match write_frame(&frame).await {
+        Ok(()) => {
+            add_message(&message, "sent");
+            console::log_1(&"Message sent successfully".into());
+            Ok(())
         }
+        Err(error) => Err(fail(error)),
     }
 }
 
+/// Send `message` as a datagram. With `reliable`, it goes through the reliable
+/// layer instead: numbered, retransmitted on NACK and delivered in order (see
+/// `set_reliable_options` and `get_reliable_stats`). With `max_age_ms`, it's
+/// only worth delivering for that long: dropped here if the throttle holds it
+/// back longer, and by the server if it arrives later (see `get_expiry_stats`).
 #[wasm_bindgen]
-pub async fn send_message_datagram(message: String) -> Result<(), JsValue> {
+pub async fn send_message_datagram(
+    message: String,
+    reliable: Option<bool>,
+    max_age_ms: Option<u32>,
+) -> Result<(), JsValue> {
     console::log_1(&format!("Attempting to send datagram: {}", message).into());
 
+    if reliable == Some(true) {
+        if max_age_ms.is_some() {
+            let error = "max_age_ms doesn't apply to reliable datagrams".to_string();
+            return Err(fail(PlaygroundError::InvalidParams(error)));
+        }
+        reliable::send(&message).await.map_err(fail)?;
+        add_message(&format!("[Reliable] {}", message), "sent");
+        return Ok(());
+    }
+
     // Get a cloned session
     let session = CONNECTION.with(|conn| {
         let state = conn.borrow();
@@ -191,8 +284,19 @@ pub async fn send_message_datagram(message: String) -> Result<(), JsValue> {
     match session {
         Some(mut sess) => {
             // Convert message to bytes
-            let message_bytes = bytes::Bytes::from(message.as_bytes().to_vec());
-
+            let created_at = js_sys::Date::now();
+            let message_bytes = bytes::Bytes::from(match max_age_ms {
+                Some(max_age_ms) => expiry::encode(message.as_bytes(), created_at, max_age_ms),
+                None => message.as_bytes().to_vec(),
+            });
+
+            network::online().await;
+            throttle::pace(message_bytes.len()).await;
+            if let Some(max_age_ms) = max_age_ms
+                && expiry::expired(created_at, max_age_ms)
+            {
+                return Ok(());
+            }
             // Send the datagram - no mutex needed!
             match sess.send_datagram(message_bytes).await {
                 Ok(_) => {
@@ -200,108 +304,16 @@ pub async fn send_message_datagram(message: String) -> Result<(), JsValue> {
                     console::log_1(&"Datagram sent successfully".into());
                     Ok(())
                 }
-                Err(e) => {
-                    let err_msg = format!("Datagram send error: {:?}", e);
-                    console::error_1(&err_msg.clone().into());
-                    add_message(&err_msg, "system");
-                    Err(JsValue::from_str(&err_msg))
-                }
+                Err(e) => Err(fail(PlaygroundError::SendFailed(e.to_string()))),
             }
         }
-        None => {
-            let err_msg = "Not connected - no session available";
-            console::error_1(&err_msg.into());
-            add_message(err_msg, "system");
-            Err(JsValue::from_str(err_msg))
-        }
+        None => Err(fail(PlaygroundError::NotConnected)),
     }
 }
 
-#[wasm_bindgen]
-pub async fn disconnect() {
-    console::log_1(&"Disconnecting...".into());
-
-    // Get the session to close it
-    let session = CONNECTION.with(|conn| {
-        let mut state = conn.borrow_mut();
-
-        // Clear the send stream
-        state.send_stream = None;
-
-        // Take the session
-        state.session.take()
-    });
-
-    // Close the session if it exists
-    if let Some(mut session) = session {
-        session.close(0, "User requested disconnect");
-    }
-
-    add_message("Disconnected", "system");
-}
-
-fn hex_to_bytes(hex: &str) -> Vec<u8> {
+pub(crate) fn hex_to_bytes(hex: &str) -> Vec<u8> {
     (0..hex.len())
         .step_by(2)
         .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
         .collect()
 }
-
-fn add_message(text: &str, msg_type: &str) {
-    let window = window().expect("no global `window` exists");
-    let document = window.document().expect("should have a document on window");
-
-    if let Some(messages_div) = document.get_element_by_id("messages") {
-        if let Some(message_div) = document.create_element("div").ok() {
-            message_div.set_class_name(&format!("message {}", msg_type));
-            message_div.set_text_content(Some(text));
-            let _ = messages_div.append_child(&message_div);
-
-            // Scroll to bottom
-            if let Some(html_div) = messages_div.dyn_ref::<web_sys::HtmlElement>() {
-                html_div.set_scroll_top(html_div.scroll_height());
-            }
-        }
-    }
-}
-
-#[wasm_bindgen]
-pub fn update_status(connected: bool) {
-    let window = window().expect("no global `window` exists");
-    let document = window.document().expect("should have a document on window");
-
-    if let Some(status_div) = document.get_element_by_id("status") {
-        if connected {
-            status_div.set_text_content(Some("Status: Connected"));
-            status_div.set_class_name("status connected");
-        } else {
-            status_div.set_text_content(Some("Status: Disconnected"));
-            status_div.set_class_name("status disconnected");
-        }
-    }
-
-    // Enable/disable buttons
-    if let Some(connect_btn) = document.get_element_by_id("connectBtn") {
-        if let Some(btn) = connect_btn.dyn_ref::<web_sys::HtmlButtonElement>() {
-            btn.set_disabled(connected);
-        }
-    }
-
-    if let Some(disconnect_btn) = document.get_element_by_id("disconnectBtn") {
-        if let Some(btn) = disconnect_btn.dyn_ref::<web_sys::HtmlButtonElement>() {
-            btn.set_disabled(!connected);
-        }
-    }
-
-    if let Some(send_btn) = document.get_element_by_id("sendStreamBtn") {
-        if let Some(btn) = send_btn.dyn_ref::<web_sys::HtmlButtonElement>() {
-            btn.set_disabled(!connected);
-        }
-    }
-
-    if let Some(send_btn) = document.get_element_by_id("sendDatagramBtn") {
-        if let Some(btn) = send_btn.dyn_ref::<web_sys::HtmlButtonElement>() {
-            btn.set_disabled(!connected);
-        }
-    }
-}
@@ -0,0 +1,136 @@
+use crate::{events, pause, raw, reliable, rpc, streams, throttle, to_js};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use wasm_bindgen::JsCast;
+use wasm_bindgen::prelude::*;
+
+/// Items held somewhere and their size.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Buffer {
+    pub count: usize,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Queues {
+    /// Our bidirectional streams the server may still count as open.
+    open_streams: usize,
+    /// Stream opens waiting for room under the server's limit.
+    stream_opens: usize,
+    /// Raw streams not yet closed.
+    raw_streams: usize,
+    /// Requests waiting for a response.
+    pending_requests: usize,
+    /// Bytes of writes held back by the throttle.
+    throttled_bytes: u64,
+    /// Events waiting for `next_event()`.
+    events: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct MemoryStats {
+    /// Size of the module's linear memory. It only ever grows, so this is the
+    /// high-water mark of the Rust heap rather than what's in use now.
+    linear_memory_bytes: u64,
+    /// Partial frames in stream decoders: streams with bytes waiting for the
+    /// rest of a frame, and those bytes.
+    reassembly: Buffer,
+    /// Frames and datagrams held while paused.
+    paused: Buffer,
+    /// Reliable-layer messages kept for retransmission.
+    reliable_history: Buffer,
+    /// Reliable-layer messages waiting ahead of a gap.
+    reliable_reorder: Buffer,
+    queues: Queues,
+    /// Entries in the page's message log.
+    message_log: u32,
+}
+
+thread_local! {
+    // Bytes buffered by each stream's decoder, for streams holding any
+    static REASSEMBLY: RefCell<HashMap<u64, usize>> = RefCell::new(HashMap::new());
+}
+
+/// Tracks how much of a partial frame one stream's decoder holds, until dropped.
+pub struct Reassembly(u64);
+
+impl Reassembly {
+    pub fn new(stream: u64) -> Self {
+        Self(stream)
+    }
+
+    /// Record what the decoder holds after taking every complete frame out.
+    pub fn set(&self, bytes: usize) {
+        REASSEMBLY.with(|streams| {
+            let mut streams = streams.borrow_mut();
+            if bytes == 0 {
+                streams.remove(&self.0);
+            } else {
+                streams.insert(self.0, bytes);
+            }
+        });
+    }
+}
+
+impl Drop for Reassembly {
+    fn drop(&mut self) {
+        self.set(0);
+    }
+}
+
+/// What this client holds in memory, for watching long sessions for leaks:
+/// `{ linear_memory_bytes, reassembly, paused, reliable_history,
+/// reliable_reorder, queues: { open_streams, stream_opens, raw_streams,
+/// pending_requests, throttled_bytes, events }, message_log }`, with each buffer as
+/// `{ count, bytes }`.
+#[wasm_bindgen]
+pub fn get_memory_stats() -> Result<JsValue, JsValue> {
+    let (open_streams, stream_opens) = streams::counts();
+    let (reliable_history, reliable_reorder) = reliable::buffered();
+    let stats = MemoryStats {
+        linear_memory_bytes: linear_memory_bytes(),
+        reassembly: REASSEMBLY.with(|streams| {
+            let streams = streams.borrow();
+            Buffer {
+                count: streams.len(),
+                bytes: streams.values().sum(),
+            }
+        }),
+        paused: pause::buffered(),
+        reliable_history,
+        reliable_reorder,
+        queues: Queues {
+            open_streams,
+            stream_opens,
+            raw_streams: raw::open_streams(),
+            pending_requests: rpc::pending(),
+            throttled_bytes: throttle::queued_bytes(),
+            events: events::queued(),
+        },
+        message_log: message_log(),
+    };
+    to_js(&stats)
+}
+
+// Messages shown on the page, if the client renders into one
+#[cfg(feature = "dom-ui")]
+fn message_log() -> u32 {
+    web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.get_element_by_id("messages"))
+        .map_or(0, |messages| messages.child_element_count())
+}
+
+#[cfg(not(feature = "dom-ui"))]
+fn message_log() -> u32 {
+    0
+}
+
+fn linear_memory_bytes() -> u64 {
+    wasm_bindgen::memory()
+        .dyn_into::<js_sys::WebAssembly::Memory>()
+        .ok()
+        .and_then(|memory| memory.buffer().dyn_into::<js_sys::ArrayBuffer>().ok())
+        .map_or(0, |buffer| buffer.byte_length() as u64)
+}
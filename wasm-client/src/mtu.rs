@@ -0,0 +1,193 @@
+use crate::{CONNECTION, add_message, fail, rpc, to_js};
+use gloo_timers::future::TimeoutFuture;
+use protocol::PlaygroundError;
+use protocol::rpc::{MtuReport, REPORT_MTU};
+use protocol::telemetry::encode_mtu_probe;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use wasm_bindgen::prelude::*;
+use web_sys::console;
+
+/// Bisection stops once the usable size is known to within this many bytes.
+const RESOLUTION: usize = 16;
+
+/// Knobs for MTU discovery. Every field is optional from JS.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct MtuConfig {
+    /// First datagram size tried; doubled until a size fails.
+    start: usize,
+    /// Largest size tried.
+    max: usize,
+    /// Datagrams sent at each size.
+    probes_per_size: u32,
+    /// Loss rate (0..1) above which a size counts as unusable.
+    max_loss: f64,
+    /// How long to wait for acks at each size.
+    timeout_ms: u32,
+    /// Bisect between the last good and first failed size after doubling.
+    refine: bool,
+}
+
+impl Default for MtuConfig {
+    fn default() -> Self {
+        Self {
+            start: 64,
+            max: 65_535,
+            probes_per_size: 5,
+            max_loss: 0.4,
+            timeout_ms: 500,
+            refine: true,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Step {
+    size: usize,
+    sent: u32,
+    acked: u32,
+    ok: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct MtuResult {
+    /// Largest size that made the round trip within the loss limit; 0 if none did.
+    max_datagram_size: usize,
+    steps: Vec<Step>,
+}
+
+thread_local! {
+    static NEXT_PROBE: Cell<u32> = const { Cell::new(0) };
+    /// Probe ids the server has acked and no step has collected yet.
+    static ACKED: RefCell<HashSet<u32>> = RefCell::new(HashSet::new());
+}
+
+/// Find the largest datagram the path carries: send `probes_per_size` datagrams
+/// at sizes doubling from `start`, each acked by the server, until sends fail
+/// or loss exceeds `max_loss`, then bisect between the last good and first
+/// failed size. Resolves with `{ max_datagram_size, steps: [{ size, sent, acked, ok }] }`
+/// and reports the size to the server's stats. `config` may set any of `start`,
+/// `max`, `probes_per_size`, `max_loss`, `timeout_ms` and `refine`.
+#[wasm_bindgen]
+pub async fn discover_mtu(config: JsValue) -> Result<JsValue, JsValue> {
+    let config: MtuConfig = if config.is_undefined() || config.is_null() {
+        MtuConfig::default()
+    } else {
+        serde_wasm_bindgen::from_value(config)
+            .map_err(|e| fail(PlaygroundError::InvalidParams(e.to_string())))?
+    };
+    if config.start == 0 || config.probes_per_size == 0 {
+        let error = "start and probes_per_size must be positive".to_string();
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+
+    let result = discover(&config).await.map_err(fail)?;
+    add_message(
+        &format!(
+            "MTU discovery: {} byte datagrams usable ({} steps)",
+            result.max_datagram_size,
+            result.steps.len()
+        ),
+        "system",
+    );
+
+    let report = MtuReport {
+        max_datagram_size: result.max_datagram_size,
+    };
+    let params = serde_json::to_value(report).unwrap_or_default();
+    if let Err(e) = rpc::send_request(REPORT_MTU.to_string(), params, config.timeout_ms).await {
+        console::warn_1(&format!("Failed to report MTU to server: {}", e).into());
+    }
+
+    to_js(&result)
+}
+
+/// Note an ack from the server for `probe_id`.
+pub fn on_ack(probe_id: u32) {
+    ACKED.with(|acked| acked.borrow_mut().insert(probe_id));
+}
+
+pub fn clear() {
+    ACKED.with(|acked| acked.borrow_mut().clear());
+}
+
+async fn discover(config: &MtuConfig) -> Result<MtuResult, PlaygroundError> {
+    let mut steps = Vec::new();
+    let mut good = 0;
+    let mut failed = None;
+
+    // Double until something fails
+    let mut size = config.start.min(config.max);
+    loop {
+        let step = probe(config, size).await?;
+        let ok = step.ok;
+        steps.push(step);
+        if !ok {
+            failed = Some(size);
+            break;
+        }
+        good = size;
+        if size >= config.max {
+            break;
+        }
+        size = (size * 2).min(config.max);
+    }
+
+    // Then narrow the gap between the last good size and the first failure
+    if let Some(mut bad) = failed.filter(|_| config.refine && good > 0) {
+        while bad - good > RESOLUTION {
+            let mid = good + (bad - good) / 2;
+            let step = probe(config, mid).await?;
+            if step.ok {
+                good = mid;
+            } else {
+                bad = mid;
+            }
+            steps.push(step);
+        }
+    }
+
+    Ok(MtuResult {
+        max_datagram_size: good,
+        steps,
+    })
+}
+
+// Send one round of probes at `size` and count the acks that arrive in time
+async fn probe(config: &MtuConfig, size: usize) -> Result<Step, PlaygroundError> {
+    let Some(mut session) = CONNECTION.with(|conn| conn.borrow().session.clone()) else {
+        return Err(PlaygroundError::NotConnected);
+    };
+
+    let mut ids = Vec::new();
+    for _ in 0..config.probes_per_size {
+        let id = NEXT_PROBE.get();
+        NEXT_PROBE.set(id.wrapping_add(1));
+        // A failed send means the browser won't carry datagrams this big at all
+        if session
+            .send_datagram(encode_mtu_probe(id, size).into())
+            .await
+            .is_err()
+        {
+            break;
+        }
+        ids.push(id);
+    }
+
+    TimeoutFuture::new(config.timeout_ms).await;
+
+    let sent = ids.len() as u32;
+    let acked = ACKED.with(|acked| {
+        let mut acked = acked.borrow_mut();
+        ids.iter().filter(|id| acked.remove(id)).count() as u32
+    });
+    let loss = 1.0 - acked as f64 / config.probes_per_size as f64;
+    Ok(Step {
+        size,
+        sent,
+        acked,
+        ok: sent == config.probes_per_size && loss <= config.max_loss,
+    })
+}
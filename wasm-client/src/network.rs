@@ -0,0 +1,169 @@
+use crate::add_message;
+use crate::events::{self, Event};
+#[cfg(feature = "reconnect")]
+use crate::{CONNECTION, connection, reconnect};
+use futures::channel::oneshot;
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+use web_sys::{EventTarget, console, window};
+
+#[derive(Default)]
+struct Network {
+    /// When the browser went offline; None while it's online.
+    offline_at: Option<f64>,
+    /// `navigator.connection.type` when last seen, where the browser has it.
+    kind: Option<String>,
+    /// Woken on the next change.
+    watchers: Vec<oneshot::Sender<()>>,
+}
+
+thread_local! {
+    static NETWORK: RefCell<Network> = RefCell::new(Network::default());
+    static LISTENERS: RefCell<Vec<Closure<dyn FnMut()>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Follow the browser's connectivity from here on: `online` and `offline` on
+/// the window, and `change` on `navigator.connection` where there is one.
+/// Without a window (e.g. in a worker) the client counts as always online.
+pub fn install() {
+    let Some(window) = window() else {
+        return;
+    };
+    let navigator = window.navigator();
+    let connection = js_sys::Reflect::get(&navigator, &"connection".into())
+        .ok()
+        .filter(JsValue::is_object);
+    NETWORK.with(|n| {
+        let mut n = n.borrow_mut();
+        if !navigator.on_line() {
+            n.offline_at = Some(js_sys::Date::now());
+        }
+        n.kind = connection.as_ref().and_then(kind_of);
+    });
+
+    let window: &EventTarget = window.as_ref();
+    listen(window, "online", on_online);
+    listen(window, "offline", on_offline);
+    if let Some(connection) = connection {
+        let target: EventTarget = connection.clone().unchecked_into();
+        listen(&target, "change", move || on_change(kind_of(&connection)));
+    }
+}
+
+fn listen(target: &EventTarget, event: &str, handler: impl FnMut() + 'static) {
+    let listener = Closure::<dyn FnMut()>::new(handler);
+    if let Err(e) =
+        target.add_event_listener_with_callback(event, listener.as_ref().unchecked_ref())
+    {
+        console::warn_2(&format!("Can't follow '{}' events:", event).into(), &e);
+        return;
+    }
+    LISTENERS.with(|l| l.borrow_mut().push(listener));
+}
+
+fn kind_of(connection: &JsValue) -> Option<String> {
+    js_sys::Reflect::get(connection, &"type".into())
+        .ok()?
+        .as_string()
+}
+
+/// Whether the browser has a network connection, per `navigator.onLine`.
+/// While it hasn't, stream writes and datagram sends wait for it to come
+/// back, and so does reconnecting.
+#[wasm_bindgen]
+pub fn is_online() -> bool {
+    NETWORK.with(|n| n.borrow().offline_at.is_none())
+}
+
+/// Wait until the browser is online. Resolves with how long that took, in ms.
+pub async fn online() -> f64 {
+    let started = js_sys::Date::now();
+    while !is_online() {
+        let (tx, rx) = oneshot::channel();
+        NETWORK.with(|n| {
+            let mut n = n.borrow_mut();
+            // Drop watchers that gave up, e.g. sends whose session closed
+            n.watchers.retain(|watcher| !watcher.is_canceled());
+            n.watchers.push(tx);
+        });
+        let _ = rx.await;
+    }
+    js_sys::Date::now() - started
+}
+
+fn on_offline() {
+    let kind = NETWORK.with(|n| {
+        let mut n = n.borrow_mut();
+        n.offline_at.get_or_insert_with(js_sys::Date::now);
+        n.kind.clone()
+    });
+    add_message("Network lost: sending paused until it's back", "system");
+    events::emit(Event::NetworkChanged {
+        online: false,
+        offline_ms: None,
+        kind,
+    });
+}
+
+fn on_online() {
+    let (offline_ms, kind, watchers) = NETWORK.with(|n| {
+        let mut n = n.borrow_mut();
+        let offline_ms = n.offline_at.take().map(|at| js_sys::Date::now() - at);
+        (offline_ms, n.kind.clone(), std::mem::take(&mut n.watchers))
+    });
+    let Some(offline_ms) = offline_ms else {
+        return;
+    };
+
+    for watcher in watchers {
+        let _ = watcher.send(());
+    }
+    add_message(
+        &format!("Network back after {:.1} s", offline_ms / 1000.0),
+        "system",
+    );
+    events::emit(Event::NetworkChanged {
+        online: true,
+        offline_ms: Some(offline_ms),
+        kind,
+    });
+    restart("the network came back");
+}
+
+// `navigator.connection` also fires on bandwidth and RTT estimates; only a new
+// kind of network, e.g. wifi to cellular, means the session's path is gone
+fn on_change(kind: Option<String>) {
+    let previous = NETWORK.with(|n| std::mem::replace(&mut n.borrow_mut().kind, kind.clone()));
+    let (Some(previous), Some(current)) = (previous, kind.clone()) else {
+        return;
+    };
+    if previous == current || !is_online() {
+        return;
+    }
+
+    add_message(
+        &format!("Network changed from {} to {}", previous, current),
+        "system",
+    );
+    events::emit(Event::NetworkChanged {
+        online: true,
+        offline_ms: None,
+        kind,
+    });
+    restart("the network changed");
+}
+
+// A session opened over the old network rarely survives on the new one, and
+// would otherwise only be given up on at its idle timeout: drop it and resume
+// on a new one now. Left alone unless the resilience policy reconnects.
+#[cfg(feature = "reconnect")]
+fn restart(why: &str) {
+    let open = CONNECTION.with(|conn| conn.borrow().session.is_some());
+    if open && reconnect::enabled() {
+        add_message(&format!("Reconnecting: {}", why), "system");
+        connection::restart(why);
+    }
+}
+
+#[cfg(not(feature = "reconnect"))]
+fn restart(_: &str) {}
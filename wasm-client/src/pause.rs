@@ -0,0 +1,192 @@
+use crate::events::{self, Event};
+use crate::memory::Buffer;
+use crate::{add_message, handle_datagram, handle_frame, to_js};
+use bytes::Bytes;
+use futures::channel::oneshot;
+use protocol::Frame;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use wasm_bindgen::prelude::*;
+
+/// Most bytes held while paused. Once full, stream reads stop (leaving the rest
+/// to QUIC flow control) and datagrams are dropped.
+const MAX_BUFFERED_BYTES: usize = 256 * 1024;
+
+/// Crossing this while paused emits a `buffer_high_watermark` event.
+const HIGH_WATERMARK_BYTES: usize = MAX_BUFFERED_BYTES * 3 / 4;
+
+enum Incoming {
+    Frame { label: String, frame: Frame },
+    Datagram(Bytes),
+}
+
+#[derive(Default)]
+struct Paused {
+    buffer: VecDeque<Incoming>,
+    bytes: usize,
+    dropped: u64,
+    high_watermark_reported: bool,
+    /// Stream readers waiting for room in the buffer.
+    waiting: Vec<oneshot::Sender<()>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ResumeSummary {
+    delivered: usize,
+    dropped: u64,
+}
+
+thread_local! {
+    /// Set while paused; receive loops hand everything here instead of handling it.
+    static PAUSED: RefCell<Option<Paused>> = const { RefCell::new(None) };
+}
+
+/// Stop handling incoming frames and datagrams; they're buffered (up to 256 KiB)
+/// until `resume()`. Past 192 KiB a `buffer_high_watermark` event is emitted;
+/// when the buffer is full, stream reads stop and datagrams are dropped.
+/// Responses are buffered too, so requests made while paused may time out.
+#[wasm_bindgen]
+pub fn pause() {
+    PAUSED.with(|paused| {
+        let mut paused = paused.borrow_mut();
+        if paused.is_none() {
+            *paused = Some(Paused::default());
+            add_message("Receiving paused", "system");
+        }
+    });
+}
+
+/// Deliver everything buffered while paused, in arrival order, and start
+/// handling traffic again. Returns `{ delivered, dropped }`.
+#[wasm_bindgen]
+pub fn resume() -> Result<JsValue, JsValue> {
+    let Some(paused) = PAUSED.with(|paused| paused.borrow_mut().take()) else {
+        return to_js(&ResumeSummary {
+            delivered: 0,
+            dropped: 0,
+        });
+    };
+
+    let summary = ResumeSummary {
+        delivered: paused.buffer.len(),
+        dropped: paused.dropped,
+    };
+    for incoming in paused.buffer {
+        dispatch(incoming);
+    }
+    for waiter in paused.waiting {
+        let _ = waiter.send(());
+    }
+
+    add_message(
+        &format!(
+            "Receiving resumed: {} delivered, {} datagrams dropped",
+            summary.delivered, summary.dropped
+        ),
+        "system",
+    );
+    to_js(&summary)
+}
+
+#[wasm_bindgen]
+pub fn is_paused() -> bool {
+    PAUSED.with(|paused| paused.borrow().is_some())
+}
+
+/// Handle a frame read from stream `label`, or buffer it while paused.
+/// `size` is the frame's length on the wire.
+pub fn deliver_frame(label: &str, frame: Frame, size: usize) {
+    let incoming = Incoming::Frame {
+        label: label.to_string(),
+        frame,
+    };
+    if let Some(incoming) = buffer(incoming, size, false) {
+        dispatch(incoming);
+    }
+}
+
+/// Handle a datagram, or buffer it while paused; dropped if the buffer is full.
+pub fn deliver_datagram(datagram: Bytes) {
+    let size = datagram.len();
+    if let Some(incoming) = buffer(Incoming::Datagram(datagram), size, true) {
+        dispatch(incoming);
+    }
+}
+
+/// Wait until a stream reader may read again: immediately unless paused with a full buffer.
+pub async fn readable() {
+    let waiter = PAUSED.with(|paused| {
+        let mut paused = paused.borrow_mut();
+        let paused = paused.as_mut().filter(|p| p.bytes >= MAX_BUFFERED_BYTES)?;
+        let (tx, rx) = oneshot::channel();
+        paused.waiting.push(tx);
+        Some(rx)
+    });
+
+    if let Some(rx) = waiter {
+        let _ = rx.await;
+    }
+}
+
+/// Frames and datagrams held while paused, and their bytes on the wire.
+pub fn buffered() -> Buffer {
+    PAUSED.with(|paused| {
+        paused
+            .borrow()
+            .as_ref()
+            .map_or(Buffer::default(), |p| Buffer {
+                count: p.buffer.len(),
+                bytes: p.bytes,
+            })
+    })
+}
+
+/// Drop anything buffered, e.g. on disconnect.
+pub fn clear() {
+    PAUSED.with(|paused| paused.borrow_mut().take());
+}
+
+// Keep `incoming` if paused, handing it back if it should be handled now.
+// Frames are always kept, since their reader already stopped at the limit
+fn buffer(incoming: Incoming, size: usize, droppable: bool) -> Option<Incoming> {
+    let high_watermark = PAUSED.with(|paused| {
+        let mut paused = paused.borrow_mut();
+        let Some(paused) = paused.as_mut() else {
+            return Err(incoming);
+        };
+
+        if droppable && paused.bytes + size > MAX_BUFFERED_BYTES {
+            paused.dropped += 1;
+            return Ok(None);
+        }
+        paused.bytes += size;
+        paused.buffer.push_back(incoming);
+
+        if paused.bytes >= HIGH_WATERMARK_BYTES && !paused.high_watermark_reported {
+            paused.high_watermark_reported = true;
+            return Ok(Some((paused.bytes, paused.buffer.len())));
+        }
+        Ok(None)
+    });
+
+    match high_watermark {
+        Err(incoming) => Some(incoming),
+        Ok(Some((buffered_bytes, buffered_items))) => {
+            events::emit(Event::BufferHighWatermark {
+                buffered_bytes,
+                buffered_items,
+                limit: MAX_BUFFERED_BYTES,
+            });
+            None
+        }
+        Ok(None) => None,
+    }
+}
+
+fn dispatch(incoming: Incoming) {
+    match incoming {
+        Incoming::Frame { label, frame } => handle_frame(&label, frame),
+        Incoming::Datagram(datagram) => handle_datagram(&datagram),
+    }
+}
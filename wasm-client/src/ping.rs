@@ -0,0 +1,153 @@
+use crate::bench::now;
+use crate::{CONNECTION, add_message, fail, to_js};
+use gloo_timers::future::TimeoutFuture;
+use protocol::PlaygroundError;
+use protocol::telemetry::encode_ping;
+use serde::Serialize;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+use web_sys::console;
+
+/// How long a ping's echo is waited for, unless `ping` is told otherwise.
+const DEFAULT_TIMEOUT_MS: u32 = 1000;
+
+/// Most pings one call sends.
+const MAX_COUNT: u32 = 10_000;
+
+/// How often the wait after the last ping checks whether every echo is in.
+const POLL_MS: u32 = 10;
+
+#[derive(Debug, Serialize)]
+struct PingResult {
+    seq: u32,
+    /// When the ping was sent, in Unix milliseconds.
+    sent_at: f64,
+    /// Round trip in milliseconds, or null if lost.
+    rtt_ms: Option<f64>,
+    lost: bool,
+}
+
+// A ping sent: its id, and when on each clock
+struct Sent {
+    id: u32,
+    sent_at: f64,
+    sent_mono: f64,
+}
+
+thread_local! {
+    static NEXT_PING: Cell<u32> = const { Cell::new(0) };
+    /// Pings waiting on their echo, by id, and when it arrived once it has, on
+    /// the monotonic clock.
+    static ECHOES: RefCell<HashMap<u32, Option<f64>>> = RefCell::new(HashMap::new());
+}
+
+/// Send `count` pings, `interval_ms` apart, and time each one's echo, for
+/// mtr-like probing from the browser. A ping is a small datagram the server
+/// sends straight back, through the connection's impairment. One not echoed
+/// within `timeout_ms` (default 1000) of being sent is lost. Resolves with
+/// every ping in order: `[{ seq, sent_at, rtt_ms, lost }]`, with `sent_at` in
+/// Unix ms and `rtt_ms` null for lost pings.
+#[wasm_bindgen]
+pub async fn ping(
+    count: u32,
+    interval_ms: u32,
+    timeout_ms: Option<u32>,
+) -> Result<JsValue, JsValue> {
+    if !(1..=MAX_COUNT).contains(&count) {
+        let error = format!("count must be from 1 to {}", MAX_COUNT);
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+    let timeout_ms = timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS);
+    let Some(mut session) = CONNECTION.with(|conn| conn.borrow().session.clone()) else {
+        return Err(fail(PlaygroundError::NotConnected));
+    };
+
+    let mut sent = Vec::with_capacity(count as usize);
+    for seq in 0..count {
+        if seq > 0 {
+            TimeoutFuture::new(interval_ms).await;
+        }
+        let id = NEXT_PING.get();
+        NEXT_PING.set(id.wrapping_add(1));
+        ECHOES.with(|echoes| echoes.borrow_mut().insert(id, None));
+        let ping = Sent {
+            id,
+            sent_at: js_sys::Date::now(),
+            sent_mono: now(),
+        };
+        // A ping that couldn't be sent is never echoed, so it counts as lost
+        if let Err(e) = session.send_datagram(encode_ping(id).into()).await {
+            console::warn_1(&format!("Failed to send ping {}: {:?}", seq, e).into());
+        }
+        sent.push(ping);
+    }
+
+    // Wait out the last ping's timeout, or less if every echo is in
+    let deadline = sent.last().map_or(0.0, |ping| ping.sent_mono) + timeout_ms as f64;
+    while now() < deadline && !all_echoed(&sent) {
+        TimeoutFuture::new(POLL_MS).await;
+    }
+
+    let results: Vec<PingResult> = sent
+        .iter()
+        .zip(0..)
+        .map(|(ping, seq)| {
+            let echoed_at = ECHOES.with(|echoes| echoes.borrow_mut().remove(&ping.id).flatten());
+            let rtt_ms = echoed_at
+                .map(|at| at - ping.sent_mono)
+                .filter(|rtt| *rtt <= timeout_ms as f64);
+            PingResult {
+                seq,
+                sent_at: ping.sent_at,
+                rtt_ms,
+                lost: rtt_ms.is_none(),
+            }
+        })
+        .collect();
+    add_message(&summary(&results), "system");
+    to_js(&results)
+}
+
+/// Note the echo of ping `ping_id`.
+pub fn on_echo(ping_id: u32) {
+    ECHOES.with(|echoes| {
+        if let Some(echoed_at) = echoes.borrow_mut().get_mut(&ping_id) {
+            echoed_at.get_or_insert_with(now);
+        }
+    });
+}
+
+/// Forget pings in flight; running `ping` calls count them lost.
+pub fn clear() {
+    ECHOES.with(|echoes| echoes.borrow_mut().clear());
+}
+
+fn all_echoed(sent: &[Sent]) -> bool {
+    ECHOES.with(|echoes| {
+        let echoes = echoes.borrow();
+        sent.iter()
+            .all(|ping| echoes.get(&ping.id).is_none_or(Option::is_some))
+    })
+}
+
+// e.g. "Ping: 10 sent, 9 echoed (10% lost), RTT min/avg/max 1.2/3.4/8.9 ms"
+fn summary(results: &[PingResult]) -> String {
+    let rtts: Vec<f64> = results.iter().filter_map(|result| result.rtt_ms).collect();
+    let lost = 100.0 * (results.len() - rtts.len()) as f64 / results.len() as f64;
+    let mut summary = format!(
+        "Ping: {} sent, {} echoed ({:.0}% lost)",
+        results.len(),
+        rtts.len(),
+        lost
+    );
+    if !rtts.is_empty() {
+        summary += &format!(
+            ", RTT min/avg/max {:.1}/{:.1}/{:.1} ms",
+            rtts.iter().copied().fold(f64::INFINITY, f64::min),
+            rtts.iter().sum::<f64>() / rtts.len() as f64,
+            rtts.iter().copied().fold(0.0, f64::max)
+        );
+    }
+    summary
+}
@@ -0,0 +1,93 @@
+use crate::connection::connect;
+use crate::{add_message, fail, hex_to_bytes};
+use protocol::PlaygroundError;
+use protocol::certs::{CertPin, CertPins};
+use wasm_bindgen::JsCast;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{console, window};
+
+/// Fetch the certificate pins from `pins_url` (the server's `/cert-hash`) and
+/// connect to `url_str` with each unexpired one in turn, moving on to the next
+/// when a pin is rejected, so a connection survives overlapping cert rotations.
+#[wasm_bindgen]
+pub async fn connect_pinned(url_str: String, pins_url: String) -> Result<(), JsValue> {
+    let pins = fetch_pins(&pins_url).await.map_err(fail)?;
+
+    let now = js_sys::Date::now() as u64;
+    let usable: Vec<&CertPin> = pins
+        .iter()
+        .filter(|pin| {
+            let usable =
+                pin.algorithm == "sha-256" && is_hex_digest(&pin.value) && !pin.is_expired(now);
+            if !usable {
+                console::warn_1(&format!("Skipping unusable or expired pin {}", pin.value).into());
+            }
+            usable
+        })
+        .collect();
+
+    let mut last_error = PlaygroundError::ConnectFailed("no usable certificate pins".to_string());
+    for (index, pin) in usable.iter().enumerate() {
+        match connect(&url_str, hex_to_bytes(&pin.value)).await {
+            Ok(()) => return Ok(()),
+            // Only a rejected handshake is worth retrying with another pin
+            Err(error @ PlaygroundError::ConnectFailed(_)) => {
+                if index + 1 < usable.len() {
+                    add_message(
+                        &format!("Pin {} rejected, trying next: {}", short(&pin.value), error),
+                        "system",
+                    );
+                }
+                last_error = error;
+            }
+            Err(error) => return Err(fail(error)),
+        }
+    }
+
+    Err(fail(last_error))
+}
+
+/// Pins from a `/cert-hash` endpoint, current certificate first. Servers that
+/// only report a single `value` yield just that one.
+async fn fetch_pins(pins_url: &str) -> Result<Vec<CertPin>, PlaygroundError> {
+    let window =
+        window().ok_or_else(|| PlaygroundError::Internal("no global `window`".to_string()))?;
+    let fetch_failed =
+        |e: JsValue| PlaygroundError::ReceiveFailed(format!("fetching {}: {:?}", pins_url, e));
+
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_str(pins_url))
+        .await
+        .map_err(fetch_failed)?
+        .dyn_into()
+        .map_err(fetch_failed)?;
+    if !response.ok() {
+        return Err(PlaygroundError::ReceiveFailed(format!(
+            "fetching {}: HTTP {}",
+            pins_url,
+            response.status()
+        )));
+    }
+    let json = JsFuture::from(response.json().map_err(fetch_failed)?)
+        .await
+        .map_err(fetch_failed)?;
+
+    let pins: CertPins = serde_wasm_bindgen::from_value(json)
+        .map_err(|e| PlaygroundError::MalformedFrame(e.to_string()))?;
+    if pins.hashes.is_empty() {
+        return Ok(vec![CertPin {
+            algorithm: pins.algorithm,
+            value: pins.value,
+            expires_unix_ms: None,
+        }]);
+    }
+    Ok(pins.hashes)
+}
+
+fn is_hex_digest(value: &str) -> bool {
+    value.len() == 64 && value.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn short(value: &str) -> &str {
+    &value[..value.len().min(12)]
+}
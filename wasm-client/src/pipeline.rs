@@ -0,0 +1,42 @@
+use crate::{fail, write_frame};
+use protocol::pipeline::{self, valid_topic};
+use protocol::{Frame, PlaygroundError};
+use wasm_bindgen::prelude::*;
+
+/// Receive the server's periodic aggregates of `topic` as `aggregate` events
+/// (`{ topic, window_ms, count, publishers, payloads, truncated }`).
+#[wasm_bindgen]
+pub async fn subscribe_topic(topic: String) -> Result<(), JsValue> {
+    check_topic(&topic)?;
+    write_frame(&Frame::Subscribe { topic }).await.map_err(fail)
+}
+
+#[wasm_bindgen]
+pub async fn unsubscribe_topic(topic: String) -> Result<(), JsValue> {
+    check_topic(&topic)?;
+    write_frame(&Frame::Unsubscribe { topic })
+        .await
+        .map_err(fail)
+}
+
+/// Add `text` to the current window of `topic`. The server drops it if nobody
+/// subscribes to the topic.
+#[wasm_bindgen]
+pub async fn publish(topic: String, text: String) -> Result<(), JsValue> {
+    check_topic(&topic)?;
+    write_frame(&Frame::Publish { topic, text })
+        .await
+        .map_err(fail)
+}
+
+fn check_topic(topic: &str) -> Result<(), JsValue> {
+    if valid_topic(topic) {
+        return Ok(());
+    }
+    let error = format!(
+        "topic {:?} must be 1 to {} bytes",
+        topic,
+        pipeline::MAX_TOPIC_LEN
+    );
+    Err(fail(PlaygroundError::InvalidParams(error)))
+}
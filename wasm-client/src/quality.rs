@@ -0,0 +1,167 @@
+use crate::events::{self, Event};
+use crate::{add_message, to_js};
+use protocol::telemetry::BandwidthEstimate;
+use serde::Serialize;
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+
+/// Weights of the loss, RTT, jitter and bandwidth components; they sum to 1.
+const LOSS_WEIGHT: f64 = 0.35;
+const RTT_WEIGHT: f64 = 0.25;
+const JITTER_WEIGHT: f64 = 0.2;
+const BANDWIDTH_WEIGHT: f64 = 0.2;
+
+/// Each component scores 100 at its first bound or better and 0 at its second
+/// or worse, linearly in between.
+const LOSS_BOUNDS: (f64, f64) = (0.0, 0.1);
+const RTT_MS_BOUNDS: (f64, f64) = (50.0, 500.0);
+const JITTER_MS_BOUNDS: (f64, f64) = (5.0, 100.0);
+/// Bandwidth in bits per second, scored on a log scale.
+const BANDWIDTH_BOUNDS: (f64, f64) = (10_000_000.0, 256_000.0);
+
+/// Gain of the jitter estimate. RFC 3550 uses 1/16 per packet, but estimates
+/// only arrive once a second.
+const JITTER_GAIN: f64 = 0.25;
+
+/// Gain of the smoothed score, so one bad second doesn't change the level.
+const SCORE_GAIN: f64 = 0.5;
+
+/// Scores from GOOD up are good, from DEGRADED up degraded, and below that bad.
+const GOOD: f64 = 80.0;
+const DEGRADED: f64 = 50.0;
+
+/// How far past a threshold the score must move to change the level, so a
+/// score hovering around one doesn't flap.
+const HYSTERESIS: f64 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityLevel {
+    Good,
+    Degraded,
+    Bad,
+}
+
+#[derive(Clone, Copy, Serialize)]
+struct QualityScore {
+    /// 0 (unusable) to 100 (ideal), smoothed over recent estimates.
+    score: f64,
+    level: QualityLevel,
+    /// Fraction of packets lost since the previous estimate.
+    loss: f64,
+    rtt_ms: f64,
+    jitter_ms: f64,
+    bits_per_second: u64,
+}
+
+#[derive(Default)]
+struct Tracker {
+    latest: Option<QualityScore>,
+    /// Packets sent and lost since the connection started, at the previous estimate.
+    packets: (u64, u64),
+    /// Smoothed RTT at the previous estimate.
+    last_rtt_ms: Option<f64>,
+    jitter_ms: f64,
+}
+
+thread_local! {
+    static TRACKER: RefCell<Tracker> = RefCell::new(Tracker::default());
+}
+
+/// Composite connection quality, recomputed from each of the server's bandwidth
+/// estimates: `{ score, level, loss, rtt_ms, jitter_ms, bits_per_second }`, or
+/// `null` before the first. `score` runs from 0 to 100 and weighs packet loss,
+/// RTT, jitter (variation in RTT) and estimated bandwidth; `level` is `good`,
+/// `degraded` or `bad`. Level changes are also delivered as `quality_changed` events.
+#[wasm_bindgen]
+pub fn get_quality_score() -> Result<JsValue, JsValue> {
+    match TRACKER.with(|t| t.borrow().latest) {
+        Some(quality) => to_js(&quality),
+        None => Ok(JsValue::NULL),
+    }
+}
+
+pub fn on_estimate(estimate: &BandwidthEstimate) {
+    let change = TRACKER.with(|t| {
+        let mut t = t.borrow_mut();
+
+        let (last_sent, last_lost) = t.packets;
+        t.packets = (estimate.sent_packets, estimate.lost_packets);
+        let sent = estimate.sent_packets.saturating_sub(last_sent);
+        let lost = estimate.lost_packets.saturating_sub(last_lost);
+        let loss = if sent == 0 {
+            0.0
+        } else {
+            (lost as f64 / sent as f64).min(1.0)
+        };
+
+        let rtt_ms = estimate.rtt_us as f64 / 1000.0;
+        if let Some(last_rtt_ms) = t.last_rtt_ms.replace(rtt_ms) {
+            t.jitter_ms += ((rtt_ms - last_rtt_ms).abs() - t.jitter_ms) * JITTER_GAIN;
+        }
+
+        let bits_per_second = estimate.bits_per_second;
+        let instant = LOSS_WEIGHT * component(loss, LOSS_BOUNDS)
+            + RTT_WEIGHT * component(rtt_ms, RTT_MS_BOUNDS)
+            + JITTER_WEIGHT * component(t.jitter_ms, JITTER_MS_BOUNDS)
+            + BANDWIDTH_WEIGHT
+                * component(
+                    (bits_per_second.max(1) as f64).log10(),
+                    (BANDWIDTH_BOUNDS.0.log10(), BANDWIDTH_BOUNDS.1.log10()),
+                );
+
+        let previous = t.latest.map(|quality| quality.level);
+        let score = match t.latest {
+            Some(quality) => quality.score + (instant - quality.score) * SCORE_GAIN,
+            None => instant,
+        };
+        let level = level(score, previous);
+        t.latest = Some(QualityScore {
+            score,
+            level,
+            loss,
+            rtt_ms,
+            jitter_ms: t.jitter_ms,
+            bits_per_second,
+        });
+        (previous != Some(level)).then_some((level, previous, score))
+    });
+
+    if let Some((level, previous, score)) = change {
+        add_message(
+            &format!("Connection quality {:?} (score {:.0})", level, score).to_lowercase(),
+            "system",
+        );
+        events::emit(Event::QualityChanged {
+            level,
+            previous,
+            score,
+        });
+    }
+}
+
+pub fn clear() {
+    TRACKER.with(|t| *t.borrow_mut() = Tracker::default());
+}
+
+// 100 at `best` or better, 0 at `worst` or worse; either bound may be the larger
+fn component(value: f64, (best, worst): (f64, f64)) -> f64 {
+    (100.0 * (worst - value) / (worst - best)).clamp(0.0, 100.0)
+}
+
+// The level for `score`, with the thresholds shifted away from the current level
+fn level(score: f64, current: Option<QualityLevel>) -> QualityLevel {
+    let (good, degraded) = match current {
+        None => (GOOD, DEGRADED),
+        Some(QualityLevel::Good) => (GOOD - HYSTERESIS, DEGRADED - HYSTERESIS),
+        Some(QualityLevel::Degraded) => (GOOD + HYSTERESIS, DEGRADED - HYSTERESIS),
+        Some(QualityLevel::Bad) => (GOOD + HYSTERESIS, DEGRADED + HYSTERESIS),
+    };
+    if score >= good {
+        QualityLevel::Good
+    } else if score >= degraded {
+        QualityLevel::Degraded
+    } else {
+        QualityLevel::Bad
+    }
+}
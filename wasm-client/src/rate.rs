@@ -0,0 +1,221 @@
+use crate::events::{self, Event};
+use crate::{CONNECTION, add_message, fail, throttle, visibility};
+use gloo_timers::future::TimeoutFuture;
+use protocol::PlaygroundError;
+use protocol::telemetry::{BandwidthEstimate, PROBE_PREFIX};
+use serde::Deserialize;
+use std::cell::{Cell, RefCell};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::spawn_local;
+use web_sys::console;
+
+/// How often the send loop wakes up to emit the datagrams that are due.
+const TICK_MS: u32 = 20;
+
+/// RTT inflation below this never counts as congestion, so jitter on a
+/// sub-millisecond local path doesn't read as queueing.
+const RTT_SLACK_US: f64 = 5_000.0;
+
+/// Knobs for the rate controller. Every field is optional from JS.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct RateConfig {
+    /// Loss rate (0..1) above which the rate is cut.
+    target_loss: f64,
+    /// Lower bound on the send rate, in datagrams per second.
+    min_rate: f64,
+    /// Upper bound on the send rate, in datagrams per second.
+    max_rate: f64,
+    /// Rate to start at, in datagrams per second.
+    initial_rate: f64,
+    /// Added to the rate after each feedback report without congestion.
+    increase: f64,
+    /// Multiplies the rate after a report showing loss or latency above target.
+    decrease: f64,
+    /// RTT above `rtt_tolerance` x the minimum RTT seen counts as congestion.
+    rtt_tolerance: f64,
+    /// Size of each probe datagram.
+    payload_bytes: usize,
+}
+
+impl Default for RateConfig {
+    fn default() -> Self {
+        Self {
+            target_loss: 0.02,
+            min_rate: 10.0,
+            max_rate: 2000.0,
+            initial_rate: 50.0,
+            increase: 20.0,
+            decrease: 0.5,
+            rtt_tolerance: 2.0,
+            payload_bytes: 256,
+        }
+    }
+}
+
+// AIMD controller over the server's periodic feedback: each bandwidth estimate
+// carries the server's datagram receive count and RTT. If the datagrams that
+// went missing since the last report exceed the target loss, or the RTT has
+// inflated well past its minimum, the rate is cut multiplicatively; otherwise
+// it grows by a fixed step.
+struct Controller {
+    config: RateConfig,
+    /// Distinguishes this run's send loop from a stopped one still winding down.
+    generation: u64,
+    rate: f64,
+    sent: u64,
+    /// (sent, received) at the previous feedback report.
+    last_report: Option<(u64, u64)>,
+    min_rtt_us: u64,
+}
+
+thread_local! {
+    static CONTROLLER: RefCell<Option<Controller>> = const { RefCell::new(None) };
+    static GENERATION: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Start sending probe datagrams at a rate adapted to the server's loss and
+/// latency feedback. `config` may set any of `target_loss`, `min_rate`,
+/// `max_rate`, `initial_rate`, `increase`, `decrease`, `rtt_tolerance` and
+/// `payload_bytes`; rates are in datagrams per second. Adjustments are
+/// reported as `rate_adjusted` events. Restarting replaces the running controller.
+#[wasm_bindgen]
+pub fn start_rate_controller(config: JsValue) -> Result<(), JsValue> {
+    let config: RateConfig = if config.is_undefined() || config.is_null() {
+        RateConfig::default()
+    } else {
+        serde_wasm_bindgen::from_value(config)
+            .map_err(|e| fail(PlaygroundError::InvalidParams(e.to_string())))?
+    };
+    if !(config.min_rate > 0.0 && config.min_rate <= config.max_rate) {
+        let error = "min_rate must be positive and at most max_rate".to_string();
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+    if !CONNECTION.with(|conn| conn.borrow().session.is_some()) {
+        return Err(fail(PlaygroundError::NotConnected));
+    }
+
+    let generation = GENERATION.get() + 1;
+    GENERATION.set(generation);
+    let rate = config.initial_rate.clamp(config.min_rate, config.max_rate);
+    let payload_bytes = config.payload_bytes.max(PROBE_PREFIX.len() + 8);
+    CONTROLLER.with(|c| {
+        *c.borrow_mut() = Some(Controller {
+            config,
+            generation,
+            rate,
+            sent: 0,
+            last_report: None,
+            min_rtt_us: u64::MAX,
+        })
+    });
+
+    add_message(&format!("Rate controller started at {:.0} datagrams/s", rate), "system");
+    spawn_local(send_loop(generation, payload_bytes));
+    Ok(())
+}
+
+/// Stop the rate controller, if running.
+#[wasm_bindgen]
+pub fn stop_rate_controller() {
+    if stop() {
+        add_message("Rate controller stopped", "system");
+    }
+}
+
+/// Stop the controller; returns whether one was running.
+pub fn stop() -> bool {
+    CONTROLLER.with(|c| c.borrow_mut().take()).is_some()
+}
+
+// Send the datagrams that are due every tick until this generation is stopped or replaced
+async fn send_loop(generation: u64, payload_bytes: usize) {
+    let mut payload = vec![0u8; payload_bytes];
+    payload[..PROBE_PREFIX.len()].copy_from_slice(PROBE_PREFIX);
+    let mut due = 0.0;
+
+    loop {
+        TimeoutFuture::new(TICK_MS).await;
+        // Hold off while the page is hidden; nothing falls due meanwhile
+        visibility::visible().await;
+
+        let rate = CONTROLLER.with(|c| match &*c.borrow() {
+            Some(controller) if controller.generation == generation => Some(controller.rate),
+            _ => None,
+        });
+        let Some(rate) = rate else {
+            return;
+        };
+        let Some(mut session) = CONNECTION.with(|conn| conn.borrow().session.clone()) else {
+            stop();
+            return;
+        };
+
+        due += rate * TICK_MS as f64 / 1000.0;
+        while due >= 1.0 {
+            due -= 1.0;
+            let seq = CONTROLLER.with(|c| match &mut *c.borrow_mut() {
+                Some(controller) => {
+                    controller.sent += 1;
+                    controller.sent
+                }
+                None => 0,
+            });
+            let at = PROBE_PREFIX.len();
+            payload[at..at + 8].copy_from_slice(&seq.to_be_bytes());
+
+            throttle::pace(payload.len()).await;
+            if let Err(e) = session.send_datagram(payload.clone().into()).await {
+                console::error_1(&format!("Probe datagram send error: {}", e).into());
+            }
+        }
+    }
+}
+
+/// Feed one report from the server into the controller.
+pub fn on_feedback(estimate: &BandwidthEstimate) {
+    let event = CONTROLLER.with(|c| {
+        let mut controller = c.borrow_mut();
+        let controller = controller.as_mut()?;
+
+        let received = estimate.datagrams_received;
+        let Some((last_sent, last_received)) =
+            controller.last_report.replace((controller.sent, received))
+        else {
+            // First report only sets the baseline
+            return None;
+        };
+
+        let sent = controller.sent - last_sent;
+        if sent == 0 {
+            return None;
+        }
+        let arrived = received.saturating_sub(last_received).min(sent);
+        let loss = 1.0 - arrived as f64 / sent as f64;
+
+        controller.min_rtt_us = controller.min_rtt_us.min(estimate.rtt_us);
+        let min_rtt = controller.min_rtt_us as f64;
+        let rtt_limit = (min_rtt * controller.config.rtt_tolerance).max(min_rtt + RTT_SLACK_US);
+        let congested =
+            loss > controller.config.target_loss || estimate.rtt_us as f64 > rtt_limit;
+
+        let config = &controller.config;
+        controller.rate = if congested {
+            controller.rate * config.decrease
+        } else {
+            controller.rate + config.increase
+        }
+        .clamp(config.min_rate, config.max_rate);
+
+        Some(Event::RateAdjusted {
+            rate: controller.rate,
+            loss,
+            rtt_us: estimate.rtt_us,
+            congested,
+        })
+    });
+
+    if let Some(event) = event {
+        events::emit(event);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::abort::Abort;
+use crate::{CONNECTION, fail, inspect, throttle};
+use futures::future::{Either, select};
+use futures::lock::Mutex;
+use protocol::PlaygroundError;
+use protocol::raw::RAW_PREAMBLE;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::spawn_local;
+use web_sys::console;
+use web_transport::{RecvStream, SendStream};
+
+/// Largest read handed to a raw stream's callback at once.
+const READ_CHUNK: usize = 64 * 1024;
+
+/// Most bytes `send_raw` writes at once, so an abort is noticed between pieces.
+const WRITE_CHUNK: usize = 64 * 1024;
+
+thread_local! {
+    static NEXT_HANDLE: Cell<u32> = const { Cell::new(1) };
+    /// Send half of each open raw stream, by handle.
+    static STREAMS: RefCell<HashMap<u32, Rc<Mutex<SendStream>>>> = RefCell::new(HashMap::new());
+    /// Takes every incoming datagram while set.
+    static DATAGRAM_HANDLER: RefCell<Option<js_sys::Function>> = const { RefCell::new(None) };
+}
+
+/// Open a raw bidirectional stream, bypassing frames: bytes given to
+/// `send_raw(handle, bytes)` go out as they are, and `callback` is called with
+/// a `Uint8Array` for each chunk that arrives, then with `null` once the server
+/// finishes its side. The playground server echoes raw streams byte for byte.
+/// Resolves with the stream's handle.
+#[wasm_bindgen]
+pub async fn open_raw_stream(callback: js_sys::Function) -> Result<u32, JsValue> {
+    let Some(mut session) = CONNECTION.with(|conn| conn.borrow().session.clone()) else {
+        return Err(fail(PlaygroundError::NotConnected));
+    };
+    let (mut send_stream, recv_stream) = session.open_bi().await.map_err(|e| {
+        fail(PlaygroundError::StreamFailed(format!(
+            "opening raw stream: {}",
+            e
+        )))
+    })?;
+    // Marks the stream raw for the server; the callback never sees it
+    send_stream
+        .write(RAW_PREAMBLE)
+        .await
+        .map_err(|e| fail(PlaygroundError::SendFailed(format!("raw stream: {}", e))))?;
+
+    let handle = NEXT_HANDLE.with(|next| next.replace(next.get() + 1));
+    STREAMS.with(|streams| {
+        streams
+            .borrow_mut()
+            .insert(handle, Rc::new(Mutex::new(send_stream)))
+    });
+    spawn_local(read(handle, recv_stream, callback));
+    Ok(handle)
+}
+
+/// Write `bytes` (a `Uint8Array`) to raw stream `handle`, as they are, in
+/// pieces of up to 64 KiB. Given an `AbortSignal`, writing stops before the
+/// next piece once it fires, even one the throttle is holding back. Resolves with the bytes written: all of them, unless
+/// aborted.
+#[wasm_bindgen]
+pub async fn send_raw(
+    handle: u32,
+    bytes: Vec<u8>,
+    signal: Option<web_sys::AbortSignal>,
+) -> Result<usize, JsValue> {
+    let mut abort = Abort::new(signal);
+    let stream = send_stream(handle)?;
+    let mut written = 0;
+    for piece in bytes.chunks(WRITE_CHUNK) {
+        if abort.aborted() {
+            break;
+        }
+        // The throttle may hold a piece back a while; an abort cuts that short
+        let paced = Box::pin(throttle::pace(piece.len()));
+        if let Either::Right(_) = select(paced, Box::pin(abort.fired())).await {
+            break;
+        }
+        let mut stream = stream.lock().await;
+        stream.write(piece).await.map_err(|e| {
+            fail(PlaygroundError::SendFailed(format!(
+                "raw stream {} after {} bytes: {}",
+                handle, written, e
+            )))
+        })?;
+        written += piece.len();
+    }
+    if written < bytes.len() {
+        console::log_1(
+            &format!(
+                "Raw stream {}: aborted after {} of {} bytes",
+                handle,
+                written,
+                bytes.len()
+            )
+            .into(),
+        );
+    }
+    Ok(written)
+}
+
+/// Finish our side of raw stream `handle`. Its callback keeps getting whatever
+/// the server still sends, then `null`.
+#[wasm_bindgen]
+pub async fn finish_raw(handle: u32) -> Result<(), JsValue> {
+    let stream = send_stream(handle)?;
+    STREAMS.with(|streams| streams.borrow_mut().remove(&handle));
+    let result = stream.lock().await.finish();
+    result.map_err(|e| {
+        fail(PlaygroundError::StreamFailed(format!(
+            "finishing raw stream {}: {}",
+            handle, e
+        )))
+    })
+}
+
+/// Route every incoming datagram to `callback` as a `Uint8Array`, instead of
+/// the client's own handling (reliable, batches, echoes). `null` restores it.
+/// Datagrams are still inspected (see `set_inspect_mode`).
+#[wasm_bindgen]
+pub fn set_raw_datagram_handler(callback: Option<js_sys::Function>) {
+    DATAGRAM_HANDLER.with(|h| *h.borrow_mut() = callback);
+}
+
+/// Send `bytes` (a `Uint8Array`) as one datagram, as they are.
+#[wasm_bindgen]
+pub async fn send_raw_datagram(bytes: Vec<u8>) -> Result<(), JsValue> {
+    let Some(mut session) = CONNECTION.with(|conn| conn.borrow().session.clone()) else {
+        return Err(fail(PlaygroundError::NotConnected));
+    };
+    throttle::pace(bytes.len()).await;
+    session
+        .send_datagram(bytes::Bytes::from(bytes))
+        .await
+        .map_err(|e| fail(PlaygroundError::SendFailed(e.to_string())))
+}
+
+/// Hand a datagram to the raw handler. Returns false if none is set.
+pub fn on_datagram(datagram: &[u8]) -> bool {
+    let Some(handler) = DATAGRAM_HANDLER.with(|h| h.borrow().clone()) else {
+        return false;
+    };
+    let bytes = js_sys::Uint8Array::from(datagram);
+    if let Err(e) = handler.call1(&JsValue::NULL, &bytes) {
+        console::error_2(&"Raw datagram handler threw:".into(), &e);
+    }
+    true
+}
+
+/// Raw streams opened and not yet closed.
+pub fn open_streams() -> usize {
+    STREAMS.with(|streams| streams.borrow().len())
+}
+
+/// Forget the raw streams of a session that ended.
+pub fn clear() {
+    STREAMS.with(|streams| streams.borrow_mut().clear());
+}
+
+fn send_stream(handle: u32) -> Result<Rc<Mutex<SendStream>>, JsValue> {
+    STREAMS
+        .with(|streams| streams.borrow().get(&handle).cloned())
+        .ok_or_else(|| {
+            let error = format!("no open raw stream {}", handle);
+            fail(PlaygroundError::StreamFailed(error))
+        })
+}
+
+// Pass each chunk read to the stream's callback until the server finishes it
+async fn read(handle: u32, mut recv_stream: RecvStream, callback: js_sys::Function) {
+    let label = format!("raw {}", handle);
+    loop {
+        match recv_stream.read(READ_CHUNK).await {
+            Ok(Some(bytes)) => {
+                inspect::on_stream_read(&label, &bytes);
+                let bytes = js_sys::Uint8Array::from(&bytes[..]);
+                if let Err(e) = callback.call1(&JsValue::NULL, &bytes) {
+                    console::error_2(&"Raw stream callback threw:".into(), &e);
+                }
+            }
+            Ok(None) => {
+                if let Err(e) = callback.call1(&JsValue::NULL, &JsValue::NULL) {
+                    console::error_2(&"Raw stream callback threw:".into(), &e);
+                }
+                return;
+            }
+            Err(e) => {
+                STREAMS.with(|streams| streams.borrow_mut().remove(&handle));
+                fail(PlaygroundError::ReceiveFailed(format!("{}: {}", label, e)));
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{add_message, fail, inspect, to_js};
+use protocol::{FrameDecoder, MAX_BUFFERED_LEN, PlaygroundError};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+use web_transport::{Error, RecvStream};
+
+/// How stream readers pull bytes off the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Mode {
+    /// Read at most `chunk_size` bytes at a time, decoding after every read.
+    #[default]
+    Chunked,
+    /// Take whatever the browser has buffered, growing the buffer until it holds
+    /// a complete frame, and only then decode.
+    Frame,
+}
+
+/// Read settings, shared by every stream. Every field is optional from JS.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default)]
+struct ReadOptions {
+    mode: Mode,
+    /// Largest read in `chunked` mode.
+    chunk_size: usize,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Chunked,
+            chunk_size: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    reads: u64,
+    bytes: u64,
+    frames: u64,
+    largest_read: usize,
+    /// Time spent waiting on reads, in milliseconds.
+    read_ms: f64,
+    /// When the first read since the last reset started.
+    started_at: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct ReadStats {
+    #[serde(flatten)]
+    options: ReadOptions,
+    reads: u64,
+    bytes: u64,
+    frames: u64,
+    largest_read: usize,
+    avg_read_bytes: f64,
+    read_ms: f64,
+    elapsed_ms: f64,
+    /// Bytes read per second of wall time since the first read.
+    bytes_per_second: f64,
+}
+
+thread_local! {
+    static OPTIONS: RefCell<ReadOptions> = RefCell::new(ReadOptions::default());
+    static COUNTERS: RefCell<Counters> = RefCell::new(Counters::default());
+}
+
+/// Change how streams are read: `{ mode: "chunked" | "frame", chunk_size }`.
+/// `chunked` (the default) reads up to `chunk_size` bytes (default 1024) at a
+/// time; `frame` reads whatever has arrived into a growing buffer until it holds
+/// a whole frame. Applies to every stream from its next read and resets the
+/// read stats, so runs with different settings can be compared.
+#[wasm_bindgen]
+pub fn set_read_options(options: JsValue) -> Result<(), JsValue> {
+    let options: ReadOptions = if options.is_undefined() || options.is_null() {
+        ReadOptions::default()
+    } else {
+        serde_wasm_bindgen::from_value(options)
+            .map_err(|e| fail(PlaygroundError::InvalidParams(e.to_string())))?
+    };
+    if options.chunk_size == 0 || options.chunk_size > MAX_BUFFERED_LEN {
+        let error = format!("chunk_size must be 1 to {} bytes", MAX_BUFFERED_LEN);
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+
+    OPTIONS.with(|o| *o.borrow_mut() = options);
+    reset_read_stats();
+    add_message(
+        &format!(
+            "Reading streams in {:?} mode ({} byte chunks)",
+            options.mode, options.chunk_size
+        ),
+        "system",
+    );
+    Ok(())
+}
+
+/// Read throughput since the last reset: `{ mode, chunk_size, reads, bytes,
+/// frames, largest_read, avg_read_bytes, read_ms, elapsed_ms, bytes_per_second }`.
+#[wasm_bindgen]
+pub fn get_read_stats() -> Result<JsValue, JsValue> {
+    let options = OPTIONS.with(|o| *o.borrow());
+    let stats = COUNTERS.with(|c| {
+        let c = c.borrow();
+        let elapsed_ms = c.started_at.map_or(0.0, |at| js_sys::Date::now() - at);
+        ReadStats {
+            options,
+            reads: c.reads,
+            bytes: c.bytes,
+            frames: c.frames,
+            largest_read: c.largest_read,
+            avg_read_bytes: if c.reads == 0 {
+                0.0
+            } else {
+                c.bytes as f64 / c.reads as f64
+            },
+            read_ms: c.read_ms,
+            elapsed_ms,
+            bytes_per_second: if elapsed_ms > 0.0 {
+                c.bytes as f64 * 1000.0 / elapsed_ms
+            } else {
+                0.0
+            },
+        }
+    });
+    to_js(&stats)
+}
+
+#[wasm_bindgen]
+pub fn reset_read_stats() {
+    COUNTERS.with(|c| *c.borrow_mut() = Counters::default());
+}
+
+/// Read from the stream registered under `label` into `decoder` following the
+/// current options. Returns false once the stream is finished. In `frame` mode
+/// this keeps reading until a frame is complete, so a FIN can leave a partial
+/// frame behind.
+pub async fn fill(
+    label: &str,
+    stream: &mut RecvStream,
+    decoder: &mut FrameDecoder,
+) -> Result<bool, Error> {
+    let options = OPTIONS.with(|o| *o.borrow());
+    loop {
+        let max = match options.mode {
+            Mode::Chunked => options.chunk_size,
+            Mode::Frame => usize::MAX,
+        };
+
+        let started = js_sys::Date::now();
+        let Some(bytes) = stream.read(max).await? else {
+            return Ok(false);
+        };
+        record_read(started, bytes.len());
+        inspect::on_stream_read(label, &bytes);
+        decoder.push(&bytes);
+
+        if options.mode == Mode::Chunked || decoder.needed() == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Count a frame taken out of a decoder.
+pub fn record_frame() {
+    COUNTERS.with(|c| c.borrow_mut().frames += 1);
+}
+
+fn record_read(started: f64, len: usize) {
+    let now = js_sys::Date::now();
+    COUNTERS.with(|c| {
+        let mut c = c.borrow_mut();
+        c.started_at.get_or_insert(started);
+        c.reads += 1;
+        c.bytes += len as u64;
+        c.largest_read = c.largest_read.max(len);
+        c.read_ms += now - started;
+    });
+}
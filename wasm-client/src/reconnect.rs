@@ -0,0 +1,222 @@
+use crate::CONNECTION;
+use crate::connection::{Status, connect};
+use crate::events::{self, Event};
+#[cfg(feature = "history")]
+use crate::settings;
+use crate::{add_message, fail, hello, network, to_js, write_frame};
+use gloo_timers::future::TimeoutFuture;
+use protocol::{CloseCode, Frame, PlaygroundError};
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::spawn_local;
+use web_sys::console;
+
+/// When and how the client reconnects after losing a session it didn't close
+/// itself. Every field is optional from JS.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Policy {
+    /// Attempts before giving up; 0 never reconnects.
+    max_retries: u32,
+    /// Delay before the first attempt, doubled for each one after it.
+    backoff_base_ms: u32,
+    /// Longest delay between attempts.
+    backoff_max_ms: u32,
+    /// Fraction (0.0..=1.0) each delay is randomly lengthened or shortened by,
+    /// so clients dropped together don't reconnect together.
+    jitter: f64,
+    /// Close codes worth reconnecting after; empty means any not in `give_up_on`.
+    /// A session lost without a close code is always worth it.
+    retry_on: Vec<u32>,
+    /// Close codes never reconnected after.
+    give_up_on: Vec<u32>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            backoff_base_ms: 500,
+            backoff_max_ms: 30_000,
+            jitter: 0.2,
+            retry_on: Vec::new(),
+            give_up_on: vec![
+                CloseCode::AuthFailure.code(),
+                CloseCode::ProtocolViolation.code(),
+                CloseCode::Kicked.code(),
+                CloseCode::TakenOver.code(),
+                CloseCode::QuotaExceeded.code(),
+            ],
+        }
+    }
+}
+
+impl Policy {
+    pub(crate) fn validate(&self) -> Result<(), PlaygroundError> {
+        if !(0.0..=1.0).contains(&self.jitter) {
+            let error = "jitter must be between 0.0 and 1.0".to_string();
+            return Err(PlaygroundError::InvalidParams(error));
+        }
+        Ok(())
+    }
+
+    fn retries(&self, code: Option<u32>) -> bool {
+        let Some(code) = code else {
+            return true;
+        };
+        !self.give_up_on.contains(&code)
+            && (self.retry_on.is_empty() || self.retry_on.contains(&code))
+    }
+
+    // Delay before the `attempt`th attempt, counting from 1
+    fn delay_ms(&self, attempt: u32) -> u32 {
+        let backoff = f64::from(self.backoff_base_ms) * 2f64.powi(attempt as i32 - 1);
+        let backoff = backoff.min(f64::from(self.backoff_max_ms));
+        let jitter = self.jitter * (2.0 * js_sys::Math::random() - 1.0);
+        (backoff * (1.0 + jitter)).round() as u32
+    }
+}
+
+thread_local! {
+    static POLICY: RefCell<Policy> = RefCell::new(Policy::default());
+    // Bumped to cancel the running reconnect loop, if any
+    static RUN: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Set the reconnect policy: `{ max_retries, backoff_base_ms, backoff_max_ms,
+/// jitter, retry_on, give_up_on }`. By default `max_retries` is 0, so lost
+/// sessions stay lost; `null` restores the defaults. Applies from the next
+/// lost session, and is saved with the settings for the next page load.
+#[wasm_bindgen]
+pub fn set_resilience_policy(policy: JsValue) -> Result<(), JsValue> {
+    let policy: Policy = if policy.is_undefined() || policy.is_null() {
+        Policy::default()
+    } else {
+        serde_wasm_bindgen::from_value(policy)
+            .map_err(|e| fail(PlaygroundError::InvalidParams(e.to_string())))?
+    };
+    policy.validate().map_err(fail)?;
+    #[cfg(feature = "history")]
+    settings::remember_resilience_policy(&policy);
+    apply(policy);
+    Ok(())
+}
+
+/// Put an already validated policy in force.
+pub(crate) fn apply(policy: Policy) {
+    POLICY.with(|p| *p.borrow_mut() = policy);
+}
+
+/// The reconnect policy in force.
+#[wasm_bindgen]
+pub fn get_resilience_policy() -> Result<JsValue, JsValue> {
+    POLICY.with(|p| to_js(&*p.borrow()))
+}
+
+/// Whether the policy in force reconnects lost sessions at all.
+pub fn enabled() -> bool {
+    POLICY.with(|p| p.borrow().max_retries > 0)
+}
+
+/// A session to `url` was lost with close `code` (None if it had none) without
+/// this client closing it: reconnect as the policy says, resuming the lost
+/// session if the server issued a token for it.
+pub fn on_session_lost(url: String, cert_hash: Vec<u8>, code: Option<u32>) {
+    let policy = POLICY.with(|p| p.borrow().clone());
+    let session_token = hello::session_token();
+    if policy.max_retries == 0 {
+        return;
+    }
+    if !policy.retries(code) {
+        give_up(
+            0,
+            format!("close code {} is not retried", code.unwrap_or_default()),
+        );
+        return;
+    }
+
+    let run = RUN.with(|run| {
+        run.set(run.get() + 1);
+        run.get()
+    });
+    spawn_local(async move {
+        for attempt in 1..=policy.max_retries {
+            let delay_ms = policy.delay_ms(attempt);
+            events::emit(Event::Reconnecting {
+                attempt,
+                max_retries: policy.max_retries,
+                delay_ms,
+            });
+            add_message(
+                &format!(
+                    "Reconnecting in {} ms (attempt {} of {})",
+                    delay_ms, attempt, policy.max_retries
+                ),
+                "system",
+            );
+            TimeoutFuture::new(delay_ms).await;
+            // Attempts while offline would only fail
+            network::online().await;
+            // Cancelled, or the user connected in the meantime
+            let status = CONNECTION.with(|conn| conn.borrow().status);
+            if RUN.with(Cell::get) != run || status != Status::Disconnected {
+                return;
+            }
+
+            match connect(&url, cert_hash.clone()).await {
+                Ok(()) => {
+                    events::emit(Event::Reconnected { attempts: attempt });
+                    add_message(&format!("Reconnected on attempt {}", attempt), "system");
+                    if let Some(session_token) = session_token {
+                        let resume = Frame::Resume { session_token };
+                        if let Err(error) = write_frame(&resume).await {
+                            console::warn_1(&format!("Failed to resume: {}", error).into());
+                        }
+                    }
+                    return;
+                }
+                // Connecting again won't fix a bad URL
+                Err(error) if !error.is_retryable() => {
+                    give_up(attempt, error.to_string());
+                    return;
+                }
+                Err(error) => {
+                    console::warn_1(
+                        &format!("Reconnect attempt {} failed: {}", attempt, error).into(),
+                    );
+                }
+            }
+        }
+        give_up(policy.max_retries, "out of retries".to_string());
+    });
+}
+
+/// The server answered our `resume`: the new session is back in `rooms`.
+pub fn on_resumed(rooms: Vec<String>, nick: Option<String>, missed: u64, dropped: u64) {
+    add_message(
+        &format!(
+            "Session resumed in {} room(s), {} missed message(s) to follow, {} dropped",
+            rooms.len(),
+            missed,
+            dropped
+        ),
+        "system",
+    );
+    events::emit(Event::Resumed {
+        rooms,
+        nick,
+        missed,
+        dropped,
+    });
+}
+
+/// Stop reconnecting, e.g. because the user disconnected.
+pub fn cancel() {
+    RUN.with(|run| run.set(run.get() + 1));
+}
+
+fn give_up(attempts: u32, reason: String) {
+    add_message(&format!("Not reconnecting: {}", reason), "system");
+    events::emit(Event::ReconnectGaveUp { attempts, reason });
+}
@@ -0,0 +1,258 @@
+use crate::events::{self, Event};
+use crate::memory::Buffer;
+use crate::{CONNECTION, add_message, fail, throttle, to_js};
+use gloo_timers::future::TimeoutFuture;
+use protocol::PlaygroundError;
+use protocol::reliable::{
+    self, Output, Packet, Receiver, ReceiverStats, ReliableConfig, Sender, SenderStats,
+};
+use serde::Serialize;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::spawn_local;
+use web_sys::console;
+
+// Both ends of the reliable datagram layer. The server reflects reliable-layer
+// datagrams, so this client's sender and receiver talk to each other across the
+// network: messages and NACKs each make the round trip, and see its loss.
+struct Layer {
+    config: ReliableConfig,
+    sender: Sender,
+    receiver: Receiver,
+    /// When each message not yet delivered was first sent.
+    sent_at: BTreeMap<u32, f64>,
+    latency: Latency,
+    /// Id of the running NACK timer, if any.
+    ticker: Option<u64>,
+}
+
+impl Layer {
+    fn new(config: ReliableConfig) -> Self {
+        Self {
+            config,
+            sender: Sender::new(config),
+            receiver: Receiver::new(config),
+            sent_at: BTreeMap::new(),
+            latency: Latency::default(),
+            ticker: None,
+        }
+    }
+}
+
+/// Time from first send to in-order delivery, retransmissions and waiting on
+/// earlier gaps included.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+struct Latency {
+    count: u64,
+    mean_ms: f64,
+    max_ms: f64,
+}
+
+impl Latency {
+    fn record(&mut self, ms: f64) {
+        self.count += 1;
+        self.mean_ms += (ms - self.mean_ms) / self.count as f64;
+        self.max_ms = self.max_ms.max(ms);
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ReliableStats {
+    sender: SenderStats,
+    receiver: ReceiverStats,
+    /// Messages sent and neither delivered nor given up on yet.
+    in_flight: usize,
+    latency: Latency,
+}
+
+thread_local! {
+    static LAYER: RefCell<Option<Layer>> = const { RefCell::new(None) };
+    static CONFIG: RefCell<ReliableConfig> = RefCell::new(ReliableConfig::default());
+    static NEXT_TICKER: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Configure reliable datagrams (`send_message_datagram(text, true)`) and start
+/// a fresh sequence. `config` may set any of `history`, `max_retransmits`,
+/// `max_nacks`, `nack_interval_ms` and `max_buffered`.
+#[wasm_bindgen]
+pub fn set_reliable_options(config: JsValue) -> Result<(), JsValue> {
+    let config: ReliableConfig = if config.is_undefined() || config.is_null() {
+        ReliableConfig::default()
+    } else {
+        serde_wasm_bindgen::from_value(config)
+            .map_err(|e| fail(PlaygroundError::InvalidParams(e.to_string())))?
+    };
+    if config.history == 0 || config.max_buffered == 0 || config.nack_interval_ms == 0 {
+        let error = "history, max_buffered and nack_interval_ms must be positive".to_string();
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+    CONFIG.with(|c| *c.borrow_mut() = config);
+    clear();
+    Ok(())
+}
+
+/// Counters of both ends of the reliable layer for this session:
+/// `{ sender: { sent, retransmitted, retransmitted_bytes, nacked, unrecoverable },
+/// receiver: { delivered, reordered, duplicates, recovered, lost, nacked },
+/// in_flight, latency: { count, mean_ms, max_ms } }`.
+#[wasm_bindgen]
+pub fn get_reliable_stats() -> Result<JsValue, JsValue> {
+    let stats = with_layer(|layer| ReliableStats {
+        sender: layer.sender.stats(),
+        receiver: layer.receiver.stats(),
+        in_flight: layer.sent_at.len(),
+        latency: layer.latency,
+    });
+    to_js(&stats)
+}
+
+/// Send `text` as the next message of the reliable sequence.
+pub async fn send(text: &str) -> Result<(), PlaygroundError> {
+    if !CONNECTION.with(|conn| conn.borrow().session.is_some()) {
+        return Err(PlaygroundError::NotConnected);
+    }
+    let datagram = with_layer(|layer| {
+        let (seq, datagram) = layer.sender.send(text.as_bytes());
+        layer.sent_at.insert(seq, js_sys::Date::now());
+        datagram
+    });
+    transmit(vec![datagram]).await
+}
+
+/// Handle a reflected reliable-layer datagram; false if `datagram` isn't one.
+pub fn on_datagram(datagram: &[u8]) -> bool {
+    let Some(packet) = reliable::decode(datagram) else {
+        return false;
+    };
+    let retransmits = with_layer(|layer| match packet {
+        Packet::Nack(seqs) => layer.sender.on_nack(&seqs),
+        Packet::Data { seq, payload } => {
+            let now = js_sys::Date::now();
+            let output = layer.receiver.on_data(seq, payload, now as u64);
+            handle(layer, output, now)
+        }
+    });
+    send_later(retransmits);
+    ensure_ticking();
+    true
+}
+
+/// Messages the sender keeps for retransmission and those the receiver holds
+/// ahead of a gap, or nothing before the layer is first used.
+pub fn buffered() -> (Buffer, Buffer) {
+    LAYER.with(|layer| {
+        let layer = layer.borrow();
+        let Some(layer) = layer.as_ref() else {
+            return (Buffer::default(), Buffer::default());
+        };
+        let (count, bytes) = layer.sender.history();
+        let history = Buffer { count, bytes };
+        let (count, bytes) = layer.receiver.buffered();
+        (history, Buffer { count, bytes })
+    })
+}
+
+/// Forget both ends' state, so the next message starts a new sequence.
+pub fn clear() {
+    LAYER.with(|layer| *layer.borrow_mut() = None);
+}
+
+fn with_layer<T>(f: impl FnOnce(&mut Layer) -> T) -> T {
+    LAYER.with(|layer| {
+        let mut layer = layer.borrow_mut();
+        let layer = layer.get_or_insert_with(|| Layer::new(CONFIG.with(|c| *c.borrow())));
+        f(layer)
+    })
+}
+
+// Show what the receiver delivered and return the NACK datagrams it asked for
+fn handle(layer: &mut Layer, output: Output, now: f64) -> Vec<Vec<u8>> {
+    for (seq, payload) in output.delivered {
+        // Anything older still waiting was given up on
+        let newer = layer.sent_at.split_off(&(seq + 1));
+        if let Some(sent_at) = std::mem::replace(&mut layer.sent_at, newer).remove(&seq) {
+            layer.latency.record(now - sent_at);
+        }
+        let text = String::from_utf8_lossy(&payload);
+        add_message(&format!("[Reliable #{}] {}", seq, text), "received");
+    }
+    if output.lost > 0 {
+        add_message(
+            &format!("[Reliable] Gave up on {} messages", output.lost),
+            "system",
+        );
+        events::emit(Event::ReliableLost {
+            lost: output.lost,
+            total_lost: layer.receiver.stats().lost,
+        });
+    }
+    reliable::encode_nacks(&output.nack)
+}
+
+// Re-NACK and give up on gaps every nack_interval_ms while any are open
+fn ensure_ticking() {
+    let ticker = with_layer(|layer| {
+        if layer.ticker.is_some() || !layer.receiver.waiting() {
+            return None;
+        }
+        let id = NEXT_TICKER.get();
+        NEXT_TICKER.set(id + 1);
+        layer.ticker = Some(id);
+        Some((id, layer.config.nack_interval_ms))
+    });
+    let Some((id, interval)) = ticker else {
+        return;
+    };
+
+    spawn_local(async move {
+        loop {
+            TimeoutFuture::new(interval as u32).await;
+            // Stop if the layer was cleared or reconfigured meanwhile
+            let tick = LAYER.with(|layer| {
+                let mut layer = layer.borrow_mut();
+                let layer = layer.as_mut().filter(|layer| layer.ticker == Some(id))?;
+                let now = js_sys::Date::now();
+                let output = layer.receiver.poll(now as u64);
+                let nacks = handle(layer, output, now);
+                let waiting = layer.receiver.waiting();
+                if !waiting {
+                    layer.ticker = None;
+                }
+                Some((nacks, waiting))
+            });
+            let Some((nacks, waiting)) = tick else {
+                return;
+            };
+            send_later(nacks);
+            if !waiting {
+                return;
+            }
+        }
+    });
+}
+
+fn send_later(datagrams: Vec<Vec<u8>>) {
+    if datagrams.is_empty() {
+        return;
+    }
+    spawn_local(async move {
+        if let Err(e) = transmit(datagrams).await {
+            console::warn_1(&format!("Reliable layer send failed: {}", e).into());
+        }
+    });
+}
+
+async fn transmit(datagrams: Vec<Vec<u8>>) -> Result<(), PlaygroundError> {
+    let Some(mut session) = CONNECTION.with(|conn| conn.borrow().session.clone()) else {
+        return Err(PlaygroundError::NotConnected);
+    };
+    for datagram in datagrams {
+        throttle::pace(datagram.len()).await;
+        session
+            .send_datagram(datagram.into())
+            .await
+            .map_err(|e| PlaygroundError::SendFailed(e.to_string()))?;
+    }
+    Ok(())
+}
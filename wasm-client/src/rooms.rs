@@ -0,0 +1,191 @@
+use crate::events::{self, Event};
+#[cfg(feature = "history")]
+use crate::settings;
+use crate::{add_message, dedup, fail, write_frame};
+use protocol::room::{self, RelayTransport, RoomAction, valid_room};
+use protocol::{Frame, PlaygroundError};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+use web_sys::console;
+
+/// What a room callback is called with.
+#[derive(Serialize)]
+struct Relayed<'a> {
+    room: &'a str,
+    /// Sender's connection id on the server.
+    from: Option<u64>,
+    /// Sender's nickname, if it connected with a `nick` session tag.
+    nick: Option<&'a str>,
+    text: &'a str,
+    /// Queued while this client was disconnected, and delivered once it resumed.
+    replayed: bool,
+}
+
+thread_local! {
+    /// Callback for each joined room.
+    static ROOMS: RefCell<HashMap<String, js_sys::Function>> = RefCell::new(HashMap::new());
+}
+
+/// Join `room`, calling `callback` with `{ room, from, nick, text, replayed }` for every message
+/// another member sends to it. Any number of rooms can be joined at once (up to
+/// the server's limit); joining a room again replaces its callback. `transport`
+/// (`"stream"`, `"datagram"` or `"both"`) picks how this client gets the room's
+/// messages instead of the room's own setting.
+#[wasm_bindgen]
+pub async fn subscribe(
+    room: String,
+    callback: js_sys::Function,
+    transport: Option<String>,
+) -> Result<(), JsValue> {
+    check_room(&room)?;
+    let transport = match transport {
+        Some(name) => Some(RelayTransport::from_name(&name).ok_or_else(|| {
+            let error = format!("transport must be stream, datagram or both, not {:?}", name);
+            fail(PlaygroundError::InvalidParams(error))
+        })?),
+        None => None,
+    };
+    write_frame(&Frame::Join {
+        room: room.clone(),
+        transport,
+    })
+    .await
+    .map_err(fail)?;
+
+    add_message(&format!("Joined room '{}'", room), "system");
+    #[cfg(feature = "history")]
+    settings::remember_room(&room);
+    ROOMS.with(|rooms| rooms.borrow_mut().insert(room, callback));
+    Ok(())
+}
+
+/// Leave `room` and drop its callback.
+#[wasm_bindgen]
+pub async fn unsubscribe(room: String) -> Result<(), JsValue> {
+    check_room(&room)?;
+    ROOMS.with(|rooms| rooms.borrow_mut().remove(&room));
+    write_frame(&Frame::Leave { room: room.clone() })
+        .await
+        .map_err(fail)?;
+
+    add_message(&format!("Left room '{}'", room), "system");
+    Ok(())
+}
+
+/// Send `text` to the other members of `room`, which must have been joined.
+#[wasm_bindgen]
+pub async fn send_to_room(room: String, text: String) -> Result<(), JsValue> {
+    check_room(&room)?;
+    let frame = Frame::Relay {
+        room: room.clone(),
+        from: None,
+        nick: None,
+        text: text.clone(),
+        replayed: false,
+        id: Some(dedup::new_id()),
+        timing: None,
+    };
+    write_frame(&frame).await.map_err(fail)?;
+
+    add_message(&format!("[{}] {}", room, text), "sent");
+    Ok(())
+}
+
+/// Kick connection `member` out of `room`, for as long as the room lasts.
+/// Only the room's owner may: its creator, or whoever joined with the room's
+/// owner token as its `token` tag.
+#[wasm_bindgen]
+pub async fn kick(room: String, member: f64) -> Result<(), JsValue> {
+    check_room(&room)?;
+    let member = check_member(member)?;
+    write_frame(&Frame::Kick { room, member })
+        .await
+        .map_err(fail)
+}
+
+/// Stop relaying connection `member`'s messages in `room`. Owner only.
+#[wasm_bindgen]
+pub async fn mute(room: String, member: f64) -> Result<(), JsValue> {
+    check_room(&room)?;
+    let member = check_member(member)?;
+    write_frame(&Frame::Mute { room, member })
+        .await
+        .map_err(fail)
+}
+
+/// Relay connection `member`'s messages in `room` again. Owner only.
+#[wasm_bindgen]
+pub async fn unmute(room: String, member: f64) -> Result<(), JsValue> {
+    check_room(&room)?;
+    let member = check_member(member)?;
+    write_frame(&Frame::Unmute { room, member })
+        .await
+        .map_err(fail)
+}
+
+/// Rooms currently joined.
+#[wasm_bindgen]
+pub fn joined_rooms() -> Vec<String> {
+    ROOMS.with(|rooms| rooms.borrow().keys().cloned().collect())
+}
+
+/// Hand a relayed message to its room's callback.
+pub fn dispatch(room: &str, from: Option<u64>, nick: Option<&str>, text: &str, replayed: bool) {
+    let Some(callback) = ROOMS.with(|rooms| rooms.borrow().get(room).cloned()) else {
+        console::warn_1(&format!("Message for room '{}', which isn't joined", room).into());
+        return;
+    };
+
+    match serde_wasm_bindgen::to_value(&Relayed {
+        room,
+        from,
+        nick,
+        text,
+        replayed,
+    }) {
+        Ok(value) => {
+            if let Err(e) = callback.call1(&JsValue::NULL, &value) {
+                console::error_2(&format!("Callback for room '{}' threw:", room).into(), &e);
+            }
+        }
+        Err(e) => console::error_1(&format!("Failed to serialize relay: {:?}", e).into()),
+    }
+}
+
+/// Report a room's new owner, or a kick or mute, as a `room_notice` event.
+pub fn on_notice(room: String, action: RoomAction, member: u64, by: Option<u64>) {
+    let text = match by {
+        Some(by) => format!("[{}] #{} {} by #{}", room, member, action.as_str(), by),
+        None => format!("[{}] #{} owns the room", room, member),
+    };
+    add_message(&text, "system");
+    events::emit(Event::RoomNotice {
+        room,
+        action,
+        member,
+        by,
+    });
+}
+
+/// Forget every room; the server drops memberships with the session.
+pub fn clear() {
+    ROOMS.with(|rooms| rooms.borrow_mut().clear());
+}
+
+fn check_room(room: &str) -> Result<(), JsValue> {
+    if valid_room(room) {
+        return Ok(());
+    }
+    let error = format!("room {:?} must be 1 to {} bytes", room, room::MAX_ROOM_LEN);
+    Err(fail(PlaygroundError::InvalidParams(error)))
+}
+
+fn check_member(member: f64) -> Result<u64, JsValue> {
+    if member >= 0.0 && member.fract() == 0.0 && member <= u64::MAX as f64 {
+        return Ok(member as u64);
+    }
+    let error = format!("member must be a connection id, not {}", member);
+    Err(fail(PlaygroundError::InvalidParams(error)))
+}
@@ -0,0 +1,128 @@
+use crate::events::{self, Event};
+use crate::to_js_error;
+use futures::channel::oneshot;
+use futures::future::{Either, select};
+use gloo_timers::future::TimeoutFuture;
+use protocol::rpc::{self, Outcome};
+use protocol::{Frame, PlaygroundError};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+use web_sys::console;
+
+// Requests waiting for a response, keyed by correlation id.
+// Each waiter is a oneshot sender; dropping it (e.g. on disconnect) rejects the request.
+#[derive(Default)]
+struct Pending {
+    next_id: u64,
+    waiting: HashMap<u64, oneshot::Sender<Outcome>>,
+}
+
+thread_local! {
+    static PENDING: RefCell<Pending> = RefCell::new(Pending::default());
+}
+
+/// Call an RPC method on the server (`echo`, `time`, `stats`, `roll_dice`).
+/// Resolves with the method's result object; rejects with the server's error,
+/// or with a local one (e.g. timeout) if no response arrives within `timeout_ms`,
+/// both as `{ kind, code, message, retryable }`.
+#[wasm_bindgen]
+pub async fn call(method: String, params: JsValue, timeout_ms: u32) -> Result<JsValue, JsValue> {
+    let params: serde_json::Value = if params.is_undefined() || params.is_null() {
+        serde_json::Value::Null
+    } else {
+        serde_wasm_bindgen::from_value(params)
+            .map_err(|e| to_js_error(&PlaygroundError::InvalidParams(e.to_string())))?
+    };
+
+    match send_request(method, params, timeout_ms).await {
+        Ok(Outcome::Result(result)) => to_js(&result),
+        Ok(Outcome::Error(error)) | Err(error) => Err(to_js_error(&error)),
+    }
+}
+
+/// Send `payload` through the server's `echo` method and resolve with the echoed text.
+#[wasm_bindgen]
+pub async fn request(payload: String, timeout_ms: u32) -> Result<String, JsValue> {
+    let params = serde_json::to_value(rpc::EchoParams { text: payload })
+        .map_err(|e| to_js_error(&PlaygroundError::Internal(e.to_string())))?;
+
+    match send_request(rpc::ECHO.to_string(), params, timeout_ms).await {
+        Ok(Outcome::Result(result)) => serde_json::from_value::<rpc::EchoResult>(result)
+            .map(|echo| echo.text)
+            .map_err(|e| to_js_error(&PlaygroundError::MalformedFrame(e.to_string()))),
+        Ok(Outcome::Error(error)) | Err(error) => Err(to_js_error(&error)),
+    }
+}
+
+pub async fn send_request(
+    method: String,
+    params: serde_json::Value,
+    timeout_ms: u32,
+) -> Result<Outcome, PlaygroundError> {
+    let (id, response) = PENDING.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        pending.next_id += 1;
+        let id = pending.next_id;
+        let (tx, rx) = oneshot::channel();
+        pending.waiting.insert(id, tx);
+        (id, rx)
+    });
+
+    let frame = Frame::Request { id, method, params };
+    if let Err(error) = crate::write_frame(&frame).await {
+        forget(id);
+        return Err(error);
+    }
+
+    match select(response, TimeoutFuture::new(timeout_ms)).await {
+        Either::Left((Ok(outcome), _)) => Ok(outcome),
+        Either::Left((Err(_), _)) => Err(PlaygroundError::Cancelled(format!(
+            "request {}: connection closed",
+            id
+        ))),
+        Either::Right(_) => {
+            forget(id);
+            Err(PlaygroundError::Timeout(format!(
+                "request {} after {} ms",
+                id, timeout_ms
+            )))
+        }
+    }
+}
+
+/// Hand a response to its waiting request, or report it if nobody is waiting.
+pub fn resolve(id: u64, outcome: Outcome) {
+    let waiter = PENDING.with(|pending| pending.borrow_mut().waiting.remove(&id));
+    match waiter {
+        Some(tx) => {
+            let _ = tx.send(outcome);
+        }
+        None => {
+            console::warn_1(&format!("Unmatched response for request {}", id).into());
+            events::emit(Event::UnmatchedResponse { id });
+        }
+    }
+}
+
+/// Requests still waiting for a response.
+pub fn pending() -> usize {
+    PENDING.with(|pending| pending.borrow().waiting.len())
+}
+
+/// Reject every outstanding request, returning how many there were.
+pub fn cancel_all() -> usize {
+    PENDING.with(|pending| pending.borrow_mut().waiting.drain().count())
+}
+
+fn forget(id: u64) {
+    PENDING.with(|pending| pending.borrow_mut().waiting.remove(&id));
+}
+
+fn to_js<T: serde::Serialize>(value: &T) -> Result<JsValue, JsValue> {
+    // Maps come out as plain JS objects rather than Map instances
+    let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+    value
+        .serialize(&serializer)
+        .map_err(|e| to_js_error(&PlaygroundError::Internal(e.to_string())))
+}
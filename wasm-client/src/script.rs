@@ -0,0 +1,150 @@
+use crate::{add_message, fail, send_message_datagram, send_message_stream, to_js};
+use gloo_timers::future::TimeoutFuture;
+use protocol::PlaygroundError;
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use wasm_bindgen::prelude::*;
+
+/// Most sends one script may make, counting repeats.
+const MAX_SENDS: u64 = 10_000;
+
+/// Longest a single step may wait, in milliseconds.
+const MAX_DELAY_MS: u32 = 60_000;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Script {
+    steps: Vec<Step>,
+}
+
+/// One send, made `repeat` times. Every field but `message` is optional.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Step {
+    /// Text to send, with `{n}`, `{step}` and `{time}` filled in.
+    message: String,
+    #[serde(default)]
+    transport: Transport,
+    /// Wait before each send, in milliseconds.
+    #[serde(default)]
+    delay_ms: u32,
+    #[serde(default = "one")]
+    repeat: u32,
+}
+
+fn one() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Transport {
+    #[default]
+    Stream,
+    Datagram,
+    /// A datagram through the reliable layer.
+    Reliable,
+}
+
+#[derive(Debug, Serialize)]
+struct Outcome {
+    sent: u64,
+    /// Whether `stop_script` or another script cut it short.
+    stopped: bool,
+    elapsed_ms: f64,
+}
+
+thread_local! {
+    // Bumped by every run and stop, so a superseded script notices and ends
+    static GENERATION: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Run a scripted sequence of sends, given as JSON: `{ "steps": [{ "message",
+/// "transport", "delay_ms", "repeat" }] }`. Each step waits `delay_ms` (default
+/// 0) and then sends `message` over `transport` (`"stream"`, the default,
+/// `"datagram"` or `"reliable"`), `repeat` times (default 1). In the message,
+/// `{n}` becomes the number of the send within the script, `{step}` the number
+/// of the step and `{time}` the Unix time in milliseconds. Starting a script
+/// stops any running one. Resolves with `{ sent, stopped, elapsed_ms }`, or
+/// rejects at the first send that fails.
+#[wasm_bindgen]
+pub async fn run_script(json: String) -> Result<JsValue, JsValue> {
+    let script: Script = serde_json::from_str(&json)
+        .map_err(|e| fail(PlaygroundError::InvalidParams(e.to_string())))?;
+    validate(&script).map_err(|error| fail(PlaygroundError::InvalidParams(error)))?;
+
+    let generation = GENERATION.get() + 1;
+    GENERATION.set(generation);
+    let started_at = js_sys::Date::now();
+    add_message(
+        &format!("Running script of {} steps", script.steps.len()),
+        "system",
+    );
+
+    let mut sent = 0;
+    let mut stopped = false;
+    'steps: for (index, step) in script.steps.iter().enumerate() {
+        for _ in 0..step.repeat {
+            if step.delay_ms > 0 {
+                TimeoutFuture::new(step.delay_ms).await;
+            }
+            if GENERATION.get() != generation {
+                stopped = true;
+                break 'steps;
+            }
+            let message = step
+                .message
+                .replace("{n}", &(sent + 1).to_string())
+                .replace("{step}", &(index + 1).to_string())
+                .replace("{time}", &(js_sys::Date::now() as u64).to_string());
+            match step.transport {
+                Transport::Stream => send_message_stream(message).await?,
+                Transport::Datagram => send_message_datagram(message, None, None).await?,
+                Transport::Reliable => send_message_datagram(message, Some(true), None).await?,
+            }
+            sent += 1;
+        }
+    }
+
+    let outcome = Outcome {
+        sent,
+        stopped,
+        elapsed_ms: js_sys::Date::now() - started_at,
+    };
+    add_message(
+        &format!(
+            "Script {} after {} sends in {:.0} ms",
+            if stopped { "stopped" } else { "finished" },
+            outcome.sent,
+            outcome.elapsed_ms
+        ),
+        "system",
+    );
+    to_js(&outcome)
+}
+
+/// Stop the running script, if any, before its next send.
+#[wasm_bindgen]
+pub fn stop_script() {
+    GENERATION.set(GENERATION.get() + 1);
+}
+
+fn validate(script: &Script) -> Result<(), String> {
+    if script.steps.is_empty() {
+        return Err("a script needs at least one step".to_string());
+    }
+    if let Some(step) = script.steps.iter().find(|s| s.delay_ms > MAX_DELAY_MS) {
+        return Err(format!(
+            "delay_ms is at most {}, got {}",
+            MAX_DELAY_MS, step.delay_ms
+        ));
+    }
+    let sends: u64 = script.steps.iter().map(|s| s.repeat as u64).sum();
+    if sends > MAX_SENDS {
+        return Err(format!(
+            "a script makes at most {} sends, this one makes {}",
+            MAX_SENDS, sends
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,144 @@
+#[cfg(feature = "reconnect")]
+use crate::reconnect::{self, Policy};
+use crate::{fail, to_js};
+use protocol::PlaygroundError;
+use protocol::room::{MAX_ROOM_LEN, valid_room};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+use web_sys::{Storage, console, window};
+
+/// localStorage key the settings are saved under, as JSON.
+const STORAGE_KEY: &str = "wtransport-playground.settings";
+
+/// What the client remembers across page loads. Every field is optional from JS.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct Settings {
+    /// Last URL a session was opened to, session tags included.
+    server_url: Option<String>,
+    nickname: Option<String>,
+    /// Last room joined.
+    room: Option<String>,
+    #[cfg(feature = "reconnect")]
+    resilience_policy: Option<Policy>,
+}
+
+impl Settings {
+    // Take every field `other` sets
+    fn merge(&mut self, other: Settings) {
+        if other.server_url.is_some() {
+            self.server_url = other.server_url;
+        }
+        if other.nickname.is_some() {
+            self.nickname = other.nickname;
+        }
+        if other.room.is_some() {
+            self.room = other.room;
+        }
+        #[cfg(feature = "reconnect")]
+        if other.resilience_policy.is_some() {
+            self.resilience_policy = other.resilience_policy;
+        }
+    }
+}
+
+thread_local! {
+    static SETTINGS: RefCell<Settings> = RefCell::new(Settings::default());
+}
+
+/// Saved settings: `{ server_url, nickname, room, resilience_policy }`, each
+/// null until set. `server_url` and `room` are remembered on each successful
+/// connect and join, and `resilience_policy` by `set_resilience_policy`.
+#[wasm_bindgen]
+pub fn get_settings() -> Result<JsValue, JsValue> {
+    SETTINGS.with(|s| to_js(&*s.borrow()))
+}
+
+/// Change the saved settings: fields given replace the saved ones, and the rest
+/// are kept. A `resilience_policy` is also put in force. `null` forgets every
+/// setting, though a policy already in force stays so until the next load.
+#[wasm_bindgen]
+pub fn set_settings(settings: JsValue) -> Result<(), JsValue> {
+    if settings.is_undefined() || settings.is_null() {
+        SETTINGS.with(|s| *s.borrow_mut() = Settings::default());
+        if let Some(storage) = storage() {
+            let _ = storage.remove_item(STORAGE_KEY);
+        }
+        return Ok(());
+    }
+
+    let settings: Settings = serde_wasm_bindgen::from_value(settings)
+        .map_err(|e| fail(PlaygroundError::InvalidParams(e.to_string())))?;
+    if let Some(room) = settings.room.as_deref().filter(|room| !valid_room(room)) {
+        let error = format!("room {:?} must be 1 to {} bytes", room, MAX_ROOM_LEN);
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+    #[cfg(feature = "reconnect")]
+    if let Some(policy) = &settings.resilience_policy {
+        policy.validate().map_err(fail)?;
+        reconnect::apply(policy.clone());
+    }
+    update(|s| s.merge(settings));
+    Ok(())
+}
+
+/// Load the saved settings and put the saved reconnect policy in force. Saved
+/// settings that no longer parse are ignored.
+pub fn restore() {
+    let Some(storage) = storage() else {
+        return;
+    };
+    let Ok(Some(json)) = storage.get_item(STORAGE_KEY) else {
+        return;
+    };
+    let settings: Settings = match serde_json::from_str(&json) {
+        Ok(settings) => settings,
+        Err(e) => {
+            console::warn_1(&format!("Ignoring saved settings: {}", e).into());
+            return;
+        }
+    };
+    #[cfg(feature = "reconnect")]
+    if let Some(policy) = settings
+        .resilience_policy
+        .clone()
+        .filter(|policy| policy.validate().is_ok())
+    {
+        reconnect::apply(policy);
+    }
+    SETTINGS.with(|s| *s.borrow_mut() = settings);
+}
+
+pub fn remember_server_url(url: &str) {
+    update(|s| s.server_url = Some(url.to_string()));
+}
+
+pub fn remember_room(room: &str) {
+    update(|s| s.room = Some(room.to_string()));
+}
+
+#[cfg(feature = "reconnect")]
+pub fn remember_resilience_policy(policy: &Policy) {
+    update(|s| s.resilience_policy = Some(policy.clone()));
+}
+
+// Change the settings and save them. Without localStorage (disabled, or a
+// worker) they last until the page is closed.
+fn update(change: impl FnOnce(&mut Settings)) {
+    let json = SETTINGS.with(|s| {
+        let mut s = s.borrow_mut();
+        change(&mut s);
+        serde_json::to_string(&*s)
+    });
+    let (Some(storage), Ok(json)) = (storage(), json) else {
+        return;
+    };
+    if let Err(e) = storage.set_item(STORAGE_KEY, &json) {
+        console::warn_2(&"Can't save settings:".into(), &e);
+    }
+}
+
+fn storage() -> Option<Storage> {
+    window()?.local_storage().ok().flatten()
+}
@@ -0,0 +1,217 @@
+use crate::{CONNECTION, add_message, fail, hello, to_js};
+use futures::future::{Either, select};
+use gloo_timers::future::TimeoutFuture;
+use protocol::{Frame, PlaygroundError};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_transport::{RecvStream, SendStream};
+
+/// Most streams a probe opens when neither it nor the server sets a limit.
+const DEFAULT_MAX_STREAMS: u32 = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Direction {
+    Bi,
+    Uni,
+}
+
+/// Knobs for a stream limit probe. Every field is optional from JS.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ProbeOptions {
+    direction: Direction,
+    /// Streams to open at most; by default one more than the server announced.
+    max_streams: Option<u32>,
+    /// How long to wait for each stream to be answered or refused. A
+    /// unidirectional stream still open after this counts as accepted.
+    timeout_ms: u32,
+}
+
+impl Default for ProbeOptions {
+    fn default() -> Self {
+        Self {
+            direction: Direction::Bi,
+            max_streams: None,
+            timeout_ms: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    /// The server reset or stopped a stream over its limit.
+    Refused,
+    /// Opening a stream stalled: QUIC flow control ran out before the server's
+    /// own limit did.
+    Blocked,
+    /// `max_streams` were opened without a refusal.
+    NoLimit,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    direction: Direction,
+    /// The limit the server announced in its hello; 0 means unlimited.
+    announced: u32,
+    /// Streams the server took before the outcome.
+    accepted: u32,
+    outcome: Outcome,
+    /// The code the refused stream was reset or stopped with.
+    code: Option<u8>,
+}
+
+/// Open streams and hold them open until the server refuses one, to show its
+/// concurrent stream limit at work. `options` may set `direction` (`"bi"`, the
+/// default, or `"uni"`), `max_streams` (default one more than the limit the
+/// server announced in its hello) and `timeout_ms` (default 1000). Each
+/// bidirectional stream sends a message and waits for the echo; each
+/// unidirectional one sends a byte and waits out the timeout. Every stream is
+/// finished afterwards. Resolves with `{ direction, announced, accepted,
+/// outcome, code }`, where `outcome` is `"refused"`, `"blocked"` or `"no_limit"`.
+#[wasm_bindgen]
+pub async fn probe_stream_limit(options: JsValue) -> Result<JsValue, JsValue> {
+    let options: ProbeOptions = if options.is_undefined() || options.is_null() {
+        ProbeOptions::default()
+    } else {
+        serde_wasm_bindgen::from_value(options)
+            .map_err(|e| fail(PlaygroundError::InvalidParams(e.to_string())))?
+    };
+    let Some(mut session) = CONNECTION.with(|conn| conn.borrow().session.clone()) else {
+        return Err(fail(PlaygroundError::NotConnected));
+    };
+    let announced = hello::limits().map_or(0, |limits| match options.direction {
+        Direction::Bi => limits.max_bi_streams,
+        Direction::Uni => limits.max_uni_streams,
+    });
+    let max_streams = options.max_streams.unwrap_or(match announced {
+        0 => DEFAULT_MAX_STREAMS,
+        announced => announced + 1,
+    });
+
+    // Open streams stay open until the probe is over
+    let mut held = Vec::new();
+    let mut report = Report {
+        direction: options.direction,
+        announced,
+        accepted: 0,
+        outcome: Outcome::NoLimit,
+        code: None,
+    };
+    for n in 1..=max_streams {
+        let opened = match options.direction {
+            Direction::Bi => timed(session.open_bi(), options.timeout_ms)
+                .await
+                .map(|opened| opened.map(|(send, recv)| (send, Some(recv)))),
+            Direction::Uni => timed(session.open_uni(), options.timeout_ms)
+                .await
+                .map(|opened| opened.map(|send| (send, None))),
+        };
+        let (mut send, recv): (SendStream, Option<RecvStream>) = match opened {
+            Some(Ok(opened)) => opened,
+            None => {
+                report.outcome = Outcome::Blocked;
+                break;
+            }
+            Some(Err(e)) => {
+                finish_all(held);
+                let error = format!("opening stream {}: {}", n, e);
+                return Err(fail(PlaygroundError::StreamFailed(error)));
+            }
+        };
+
+        let answer = match recv {
+            Some(mut recv) => {
+                let answer = echoed(&mut send, &mut recv, n, options.timeout_ms).await;
+                held.push((send, Some(recv)));
+                answer
+            }
+            None => {
+                let answer = still_open(&mut send, options.timeout_ms).await;
+                held.push((send, None));
+                answer
+            }
+        };
+        match answer {
+            Ok(()) => report.accepted += 1,
+            Err(code) => {
+                report.outcome = Outcome::Refused;
+                report.code = code;
+                break;
+            }
+        }
+    }
+    finish_all(held);
+
+    let message = match report.outcome {
+        Outcome::Refused => format!(
+            "Server refused {:?} stream {} (code {:?}); limit announced as {}",
+            report.direction,
+            report.accepted + 1,
+            report.code,
+            report.announced
+        ),
+        Outcome::Blocked => format!(
+            "Opening {:?} stream {} stalled on flow control",
+            report.direction,
+            report.accepted + 1
+        ),
+        Outcome::NoLimit => format!(
+            "Server took all {} {:?} streams",
+            report.accepted, report.direction
+        ),
+    };
+    add_message(&message, "system");
+    to_js(&report)
+}
+
+// Send a message on a bidirectional stream and wait for any answer. Err with
+// the stream's code if the server resets or stops it instead
+async fn echoed(
+    send: &mut SendStream,
+    recv: &mut RecvStream,
+    n: u32,
+    timeout_ms: u32,
+) -> Result<(), Option<u8>> {
+    let frame = Frame::Message {
+        text: format!("stream limit probe {}", n),
+        seq: None,
+        id: None,
+        timing: None,
+    };
+    send.write(&protocol::encode(&frame))
+        .await
+        .map_err(|e| e.code())?;
+    match select(Box::pin(recv.read(1024)), TimeoutFuture::new(timeout_ms)).await {
+        Either::Left((Ok(Some(_)), _)) => Ok(()),
+        Either::Left((Ok(None), _)) => Err(None),
+        Either::Left((Err(e), _)) => Err(e.code()),
+        // Neither answered nor refused; count it as held open
+        Either::Right(_) => Ok(()),
+    }
+}
+
+// Send a byte on a unidirectional stream and wait for the server to stop it
+async fn still_open(send: &mut SendStream, timeout_ms: u32) -> Result<(), Option<u8>> {
+    send.write(&[0]).await.map_err(|e| e.code())?;
+    match select(Box::pin(send.closed()), TimeoutFuture::new(timeout_ms)).await {
+        Either::Left((Ok(code), _)) => Err(code),
+        Either::Left((Err(e), _)) => Err(e.code()),
+        Either::Right(_) => Ok(()),
+    }
+}
+
+// `future`'s output, or `None` if it takes longer than `timeout_ms`
+async fn timed<T>(future: impl Future<Output = T>, timeout_ms: u32) -> Option<T> {
+    match select(Box::pin(future), TimeoutFuture::new(timeout_ms)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+fn finish_all(streams: Vec<(SendStream, Option<RecvStream>)>) {
+    for (mut send, _) in streams {
+        let _ = send.finish();
+    }
+}
@@ -0,0 +1,472 @@
+use crate::events::{self, Event};
+use crate::{
+    CONNECTION, add_message, coalesce, dedup, fail, hello, memory, network, pause, reader, throttle,
+};
+use futures::channel::oneshot;
+use futures::future::{Either, select};
+use futures::lock::Mutex;
+use gloo_timers::future::TimeoutFuture;
+use protocol::hello::STREAM_REFUSED_CODE;
+use protocol::{Frame, FrameDecoder, PlaygroundError};
+use serde::Serialize;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::spawn_local;
+use web_sys::console;
+use web_transport::{Error, RecvStream, SendStream};
+
+/// Label of the bidirectional stream opened on connect.
+pub const MAIN_STREAM: &str = "main";
+
+/// Label used when logging server-initiated unidirectional streams.
+const SERVER_STREAM: &str = "server";
+
+/// How long opening a stream may take before it's reported as held up by flow control.
+const FLOW_CONTROL_WAIT_MS: u32 = 1000;
+
+/// How long to wait before reopening a stream the server refused.
+const REOPEN_DELAY_MS: u32 = 1000;
+
+/// Times a refused stream is reopened before giving up on it.
+const MAX_REOPENS: u32 = 5;
+
+/// A step in a stream's life as this client sees it, reported in a
+/// `stream_changed` event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamChange {
+    /// We opened a bidirectional stream, or the server opened a unidirectional one.
+    Opened,
+    /// We finished our sending side.
+    Finished,
+    /// The server finished its sending side.
+    RemoteFinished,
+    /// The server reset its sending side.
+    Reset,
+    /// The server asked us to stop sending.
+    Stopped,
+}
+
+/// Why opening a stream has to wait, reported in a `stream_open_queued` event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueReason {
+    /// As many streams are open as the server's hello allows.
+    Limit,
+    /// The browser is holding the open until QUIC flow control allows another stream.
+    FlowControl,
+    /// The server refused the stream; it's reopened shortly.
+    Refused,
+}
+
+thread_local! {
+    // Ids are never reused, so a stream table can key on them across sessions
+    static NEXT_ID: Cell<u64> = const { Cell::new(1) };
+    // Id of the send stream registered under each label
+    static SEND_IDS: RefCell<HashMap<String, u64>> = RefCell::new(HashMap::new());
+    // Ids of our bidirectional streams the server may still count as open
+    static OPEN: RefCell<HashSet<u64>> = RefCell::new(HashSet::new());
+    // Opens let through that haven't registered a stream yet
+    static OPENING: Cell<u32> = const { Cell::new(0) };
+    // Times the stream under each label was refused in a row
+    static REFUSALS: RefCell<HashMap<String, u32>> = RefCell::new(HashMap::new());
+    // Opens waiting for a stream to close, in order
+    static QUEUE: RefCell<VecDeque<(String, oneshot::Sender<()>)>> = const { RefCell::new(VecDeque::new()) };
+}
+
+fn opened(label: &str) -> u64 {
+    let id = NEXT_ID.with(|next| next.replace(next.get() + 1));
+    changed(id, label, StreamChange::Opened, None);
+    id
+}
+
+fn changed(id: u64, label: &str, change: StreamChange, code: Option<u8>) {
+    events::emit(Event::StreamChanged {
+        id,
+        label: label.to_string(),
+        change,
+        code,
+    });
+}
+
+// Report a change to the send side under `label`, which is then no longer tracked
+fn send_side_ended(label: &str, change: StreamChange, code: Option<u8>) {
+    if let Some(id) = SEND_IDS.with(|ids| ids.borrow_mut().remove(label)) {
+        changed(id, label, change, code);
+    }
+}
+
+/// Report that we finished the send stream registered under `label`.
+pub fn finished(label: &str) {
+    send_side_ended(label, StreamChange::Finished, None);
+}
+
+/// Forget the send streams of a session that ended. Queued opens fail.
+pub fn clear() {
+    SEND_IDS.with(|ids| ids.borrow_mut().clear());
+    OPEN.with(|open| open.borrow_mut().clear());
+    OPENING.set(0);
+    REFUSALS.with(|refusals| refusals.borrow_mut().clear());
+    QUEUE.with(|queue| queue.borrow_mut().clear());
+    coalesce::clear();
+}
+
+/// Our bidirectional streams the server may still count as open, and opens
+/// queued behind them.
+pub fn counts() -> (usize, usize) {
+    let open = OPEN.with(|open| open.borrow().len());
+    (open, QUEUE.with(|queue| queue.borrow().len()))
+}
+
+// Whether another stream fits under the limit the server announced
+fn has_room() -> bool {
+    let max = hello::limits().map_or(0, |limits| limits.max_bi_streams);
+    let open = OPEN.with(|open| open.borrow().len()) as u32 + OPENING.get();
+    max == 0 || open < max
+}
+
+fn queued(label: &str) -> bool {
+    QUEUE.with(|queue| queue.borrow().iter().any(|(queued, _)| queued == label))
+}
+
+// Wait in line until a stream closes and makes room for this one
+async fn wait_for_room(label: &str, reason: QueueReason) -> Result<(), PlaygroundError> {
+    let (tx, rx) = oneshot::channel();
+    QUEUE.with(|queue| queue.borrow_mut().push_back((label.to_string(), tx)));
+    queued_event(label, reason);
+    // Dropped by clear() when the session ends
+    rx.await.map_err(|_| PlaygroundError::NotConnected)
+}
+
+fn queued_event(label: &str, reason: QueueReason) {
+    let message = match reason {
+        QueueReason::Limit => "waiting for another stream to close",
+        QueueReason::FlowControl => "waiting for flow control",
+        QueueReason::Refused => "refused by the server, reopening",
+    };
+    add_message(&format!("Stream '{}' queued: {}", label, message), "system");
+    events::emit(Event::StreamOpenQueued {
+        label: label.to_string(),
+        reason,
+    });
+}
+
+// Let queued opens through while there's room
+fn admit_queued() {
+    while has_room() {
+        let Some((_, tx)) = QUEUE.with(|queue| queue.borrow_mut().pop_front()) else {
+            return;
+        };
+        OPENING.set(OPENING.get() + 1);
+        // The opener gave up waiting
+        if tx.send(()).is_err() {
+            OPENING.set(OPENING.get() - 1);
+        }
+    }
+}
+
+// A stream the server may have counted is gone, so a queued open may fit
+fn closed(id: u64) {
+    if OPEN.with(|open| open.borrow_mut().remove(&id)) {
+        admit_queued();
+    }
+}
+
+/// Store the send half under `label` and start reading frames from the receive half.
+pub fn register(label: &str, send_stream: SendStream, recv_stream: RecvStream) {
+    CONNECTION.with(|conn| {
+        conn.borrow_mut()
+            .streams
+            .insert(label.to_string(), Rc::new(Mutex::new(send_stream)));
+    });
+    let id = opened(label);
+    SEND_IDS.with(|ids| ids.borrow_mut().insert(label.to_string(), id));
+    OPEN.with(|open| open.borrow_mut().insert(id));
+
+    let label = label.to_string();
+    spawn_local(async move {
+        read_frames(id, label, recv_stream).await;
+        closed(id);
+    });
+}
+
+/// Read frames from a stream the server opened towards us.
+pub fn read_server_stream(recv_stream: RecvStream) {
+    let id = opened(SERVER_STREAM);
+    spawn_local(read_frames(id, SERVER_STREAM.to_string(), recv_stream));
+}
+
+// Continuously read frames from one stream until the server finishes it
+async fn read_frames(id: u64, label: String, mut recv_stream: RecvStream) {
+    let mut decoder = FrameDecoder::new();
+    let reassembly = memory::Reassembly::new(id);
+    // Whether the server has sent anything, so it took the stream
+    let mut answered = false;
+    loop {
+        // While paused with a full buffer, stop reading and let flow control push back
+        pause::readable().await;
+
+        // Chunk size and buffering follow set_read_options()
+        match reader::fill(&label, &mut recv_stream, &mut decoder).await {
+            Ok(true) => {
+                if !answered {
+                    answered = true;
+                    REFUSALS.with(|refusals| refusals.borrow_mut().remove(&label));
+                }
+            }
+            Ok(false) => {
+                console::log_1(&format!("Stream '{}' finished by server", label).into());
+                add_message(&format!("Stream '{}' finished by server", label), "system");
+                changed(id, &label, StreamChange::RemoteFinished, None);
+                break;
+            }
+            Err(e) => {
+                // Session errors mean the whole session went, which is reported on its own
+                if let Error::Stream(_) = e {
+                    changed(id, &label, StreamChange::Reset, e.code());
+                }
+                if e.code() == Some(STREAM_REFUSED_CODE) && refused(id, &label) {
+                    break;
+                }
+                let error = PlaygroundError::ReceiveFailed(format!("stream '{}': {}", label, e));
+                fail(error);
+                break;
+            }
+        }
+
+        loop {
+            let buffered = decoder.buffered();
+            match decoder.next_frame() {
+                Ok(Some(frame)) => {
+                    reader::record_frame();
+                    pause::deliver_frame(&label, frame, buffered - decoder.buffered());
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    fail(PlaygroundError::from(e));
+                    return;
+                }
+            }
+        }
+        reassembly.set(decoder.buffered());
+    }
+}
+
+// The server refused our stream `id` over its stream limit. Drop its send half
+// and reopen it under the same label shortly; false if it isn't ours to reopen
+fn refused(id: u64, label: &str) -> bool {
+    if !OPEN.with(|open| open.borrow().contains(&id)) {
+        return false;
+    }
+    send_side_ended(label, StreamChange::Stopped, Some(STREAM_REFUSED_CODE));
+    CONNECTION.with(|conn| conn.borrow_mut().streams.remove(label));
+    let refusals = REFUSALS.with(|refusals| {
+        let mut refusals = refusals.borrow_mut();
+        let count = refusals.entry(label.to_string()).or_insert(0);
+        *count += 1;
+        *count
+    });
+    let retrying = refusals <= MAX_REOPENS;
+    events::emit(Event::StreamOpenRejected {
+        label: label.to_string(),
+        code: STREAM_REFUSED_CODE,
+        retrying,
+    });
+    if retrying {
+        spawn_local(reopen(label.to_string(), refusals));
+    } else {
+        REFUSALS.with(|refusals| refusals.borrow_mut().remove(label));
+        let error = format!("'{}' refused {} times", label, refusals);
+        fail(PlaygroundError::StreamFailed(error));
+    }
+    true
+}
+
+// Reopen a refused stream, backing off a little more after each refusal
+async fn reopen(label: String, attempt: u32) {
+    queued_event(&label, QueueReason::Refused);
+    TimeoutFuture::new(REOPEN_DELAY_MS * attempt).await;
+    let (connected, exists) = CONNECTION.with(|conn| {
+        let state = conn.borrow();
+        (state.session.is_some(), state.streams.contains_key(&label))
+    });
+    // Gone with the session, or opened again by hand meanwhile
+    if !connected || exists || queued(&label) {
+        return;
+    }
+    let opened = match admit(&label).await {
+        Ok(()) => open_admitted(&label).await,
+        Err(error) => Err(error),
+    };
+    if let Err(error) = opened {
+        fail(error);
+    }
+}
+
+// Take a place among the streams the server allows, queueing if there's none
+async fn admit(label: &str) -> Result<(), PlaygroundError> {
+    let waiting = QUEUE.with(|queue| !queue.borrow().is_empty());
+    if has_room() && !waiting {
+        OPENING.set(OPENING.get() + 1);
+        return Ok(());
+    }
+    wait_for_room(label, QueueReason::Limit).await
+}
+
+// Open and register a stream under `label`, once admitted. An open the browser
+// holds back for flow control is reported as queued and waited out
+async fn open_admitted(label: &str) -> Result<(), PlaygroundError> {
+    let session = CONNECTION.with(|conn| conn.borrow().session.clone());
+    let Some(mut session) = session else {
+        OPENING.set(OPENING.get().saturating_sub(1));
+        return Err(PlaygroundError::NotConnected);
+    };
+    let result = {
+        let opening = Box::pin(session.open_bi());
+        match select(opening, TimeoutFuture::new(FLOW_CONTROL_WAIT_MS)).await {
+            Either::Left((result, _)) => result,
+            Either::Right((_, opening)) => {
+                queued_event(label, QueueReason::FlowControl);
+                opening.await
+            }
+        }
+    };
+    OPENING.set(OPENING.get().saturating_sub(1));
+    match result {
+        Ok((send_stream, recv_stream)) => {
+            register(label, send_stream, recv_stream);
+            Ok(())
+        }
+        Err(e) => {
+            admit_queued();
+            Err(PlaygroundError::StreamFailed(format!(
+                "opening '{}': {}",
+                label, e
+            )))
+        }
+    }
+}
+
+/// Write one frame to the stream registered under `label`.
+pub async fn write_frame_on(label: &str, frame: &Frame) -> Result<(), PlaygroundError> {
+    // Get a cloned reference to the send stream
+    let (connected, send_stream_rc) = CONNECTION.with(|conn| {
+        let state = conn.borrow();
+        (state.session.is_some(), state.streams.get(label).cloned())
+    });
+
+    let Some(stream_rc) = send_stream_rc else {
+        return Err(if connected {
+            PlaygroundError::StreamFailed(format!("no open send stream '{}'", label))
+        } else {
+            PlaygroundError::NotConnected
+        });
+    };
+
+    // Now we can use the stream without holding the CONNECTION borrow
+    let bytes = protocol::encode(frame);
+    network::online().await;
+    if coalesce::enabled() {
+        return coalesce::write(label, stream_rc, &bytes).await;
+    }
+    write_bytes(label, &stream_rc, &bytes).await
+}
+
+/// Write `bytes` to the send stream registered under `label`, once the
+/// throttle lets them out.
+pub async fn write_bytes(
+    label: &str,
+    stream_rc: &Rc<Mutex<SendStream>>,
+    bytes: &[u8],
+) -> Result<(), PlaygroundError> {
+    throttle::pace(bytes.len()).await;
+    let mut stream = stream_rc.lock().await;
+    stream.write(bytes).await.map(|_| ()).map_err(|e| {
+        if let Error::Stream(_) = e {
+            send_side_ended(label, StreamChange::Stopped, e.code());
+        }
+        PlaygroundError::SendFailed(format!("stream '{}': {}", label, e))
+    })
+}
+
+/// Open an additional bidirectional stream, addressable by `label`. With as
+/// many streams open as the server's hello allows, or while the browser holds
+/// the open back for flow control, it's queued (reported as a
+/// `stream_open_queued` event) and resolves once a stream closes and makes
+/// room. A stream the server refuses anyway is reported as
+/// `stream_open_rejected` and reopened shortly.
+#[wasm_bindgen]
+pub async fn open_stream(label: String) -> Result<(), JsValue> {
+    let (connected, exists) = CONNECTION.with(|conn| {
+        let state = conn.borrow();
+        (state.session.is_some(), state.streams.contains_key(&label))
+    });
+
+    if exists || queued(&label) {
+        let error = PlaygroundError::StreamFailed(format!("stream '{}' is already open", label));
+        return Err(fail(error));
+    }
+    if !connected {
+        return Err(fail(PlaygroundError::NotConnected));
+    }
+
+    admit(&label).await.map_err(fail)?;
+    open_admitted(&label).await.map_err(fail)?;
+    add_message(&format!("Stream '{}' opened", label), "system");
+    Ok(())
+}
+
+/// Send a text message on the stream registered under `label`.
+#[wasm_bindgen]
+pub async fn send_on_stream(label: String, message: String) -> Result<(), JsValue> {
+    let frame = Frame::Message {
+        text: message.clone(),
+        seq: None,
+        id: Some(dedup::new_id()),
+        timing: None,
+    };
+
+    match write_frame_on(&label, &frame).await {
+        Ok(()) => {
+            add_message(&format!("[{}] {}", label, message), "sent");
+            Ok(())
+        }
+        Err(error) => Err(fail(error)),
+    }
+}
+
+/// Finish (half-close) our side of the stream registered under `label`.
+/// The receive side stays open: the server's remaining frames and its own FIN
+/// still arrive and are reported as usual.
+#[wasm_bindgen]
+pub async fn finish_stream(label: String) -> Result<(), JsValue> {
+    let send_stream_rc = CONNECTION.with(|conn| conn.borrow_mut().streams.remove(&label));
+
+    let Some(stream_rc) = send_stream_rc else {
+        let error = PlaygroundError::StreamFailed(format!("no open send stream '{}'", label));
+        return Err(fail(error));
+    };
+
+    coalesce::finish(&label, &stream_rc).await.map_err(fail)?;
+    let result = stream_rc.lock().await.finish();
+    match result {
+        Ok(()) => {
+            finished(&label);
+            console::log_1(&format!("Stream '{}' finished (half-closed)", label).into());
+            add_message(
+                &format!(
+                    "Stream '{}' finished - still receiving until the server finishes",
+                    label
+                ),
+                "system",
+            );
+            Ok(())
+        }
+        Err(e) => Err(fail(PlaygroundError::StreamFailed(format!(
+            "finishing '{}': {}",
+            label, e
+        )))),
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{add_message, fail, to_js};
+use gloo_timers::future::TimeoutFuture;
+use protocol::PlaygroundError;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+
+/// Slowest rate a throttle can be set to, so a single write can't stall for
+/// minutes.
+const MIN_BITS_PER_SECOND: u64 = 8_000;
+
+/// Throttle settings. Every field is optional from JS.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+struct ThrottleOptions {
+    /// Send rate cap; 0 turns the throttle off.
+    bits_per_second: u64,
+    /// Bytes that can go out back to back before pacing kicks in.
+    burst_bytes: u64,
+}
+
+impl Default for ThrottleOptions {
+    fn default() -> Self {
+        Self {
+            bits_per_second: 0,
+            burst_bytes: 16 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Bucket {
+    /// Bytes that can be sent without waiting; negative while writes are
+    /// queued up behind the rate.
+    tokens: f64,
+    refilled_at: f64,
+    sent_bytes: u64,
+    delayed_writes: u64,
+    /// Time writes spent held back, in milliseconds.
+    delay_ms: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct ThrottleStats {
+    #[serde(flatten)]
+    options: ThrottleOptions,
+    sent_bytes: u64,
+    delayed_writes: u64,
+    delay_ms: f64,
+}
+
+thread_local! {
+    static OPTIONS: RefCell<ThrottleOptions> = RefCell::new(ThrottleOptions::default());
+    static BUCKET: RefCell<Bucket> = RefCell::new(Bucket::default());
+}
+
+/// Cap how fast the client sends: `{ bits_per_second, burst_bytes }`, with
+/// `bits_per_second` 0 (the default) or `null` turning the throttle off. Once
+/// `burst_bytes` (default 16384) have gone out back to back, writes on streams
+/// and datagrams are held until the rate allows them, simulating a constrained
+/// uplink. Resets the throttle stats.
+#[wasm_bindgen]
+pub fn set_throttle(options: JsValue) -> Result<(), JsValue> {
+    let options: ThrottleOptions = if options.is_undefined() || options.is_null() {
+        ThrottleOptions::default()
+    } else {
+        serde_wasm_bindgen::from_value(options)
+            .map_err(|e| fail(PlaygroundError::InvalidParams(e.to_string())))?
+    };
+    if options.bits_per_second != 0 && options.bits_per_second < MIN_BITS_PER_SECOND {
+        let error = format!(
+            "bits_per_second must be 0 or at least {}",
+            MIN_BITS_PER_SECOND
+        );
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+    if options.burst_bytes == 0 {
+        let error = "burst_bytes must be at least 1".to_string();
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+
+    OPTIONS.with(|o| *o.borrow_mut() = options);
+    BUCKET.with(|b| {
+        *b.borrow_mut() = Bucket {
+            tokens: options.burst_bytes as f64,
+            refilled_at: js_sys::Date::now(),
+            ..Bucket::default()
+        }
+    });
+    let message = if options.bits_per_second == 0 {
+        "Send throttle off".to_string()
+    } else {
+        format!(
+            "Throttling sends to {} kbit/s ({} byte burst)",
+            options.bits_per_second / 1000,
+            options.burst_bytes
+        )
+    };
+    add_message(&message, "system");
+    Ok(())
+}
+
+/// What the throttle has done since it was last set: `{ bits_per_second,
+/// burst_bytes, sent_bytes, delayed_writes, delay_ms }`.
+#[wasm_bindgen]
+pub fn get_throttle_stats() -> Result<JsValue, JsValue> {
+    let options = OPTIONS.with(|o| *o.borrow());
+    let stats = BUCKET.with(|b| {
+        let b = b.borrow();
+        ThrottleStats {
+            options,
+            sent_bytes: b.sent_bytes,
+            delayed_writes: b.delayed_writes,
+            delay_ms: b.delay_ms,
+        }
+    });
+    to_js(&stats)
+}
+
+/// Bytes of writes waiting for the throttle to let them out.
+pub fn queued_bytes() -> u64 {
+    BUCKET.with(|b| (-b.borrow().tokens).max(0.0) as u64)
+}
+
+/// Wait until the throttle lets `len` more bytes out. Returns at once while it's
+/// off. Writes are paced in the order they call this, since each one takes its
+/// bytes from the bucket before waiting.
+pub async fn pace(len: usize) {
+    let options = OPTIONS.with(|o| *o.borrow());
+    if options.bits_per_second == 0 {
+        return;
+    }
+    let bytes_per_ms = options.bits_per_second as f64 / 8000.0;
+    let wait_ms = BUCKET.with(|b| {
+        let mut b = b.borrow_mut();
+        let now = js_sys::Date::now();
+        b.tokens =
+            (b.tokens + (now - b.refilled_at) * bytes_per_ms).min(options.burst_bytes as f64);
+        b.refilled_at = now;
+        b.tokens -= len as f64;
+        b.sent_bytes += len as u64;
+        if b.tokens >= 0.0 {
+            return 0.0;
+        }
+        let wait_ms = -b.tokens / bytes_per_ms;
+        b.delayed_writes += 1;
+        b.delay_ms += wait_ms;
+        wait_ms
+    });
+    if wait_ms > 0.0 {
+        TimeoutFuture::new(wait_ms.ceil() as u32).await;
+    }
+}
@@ -0,0 +1,434 @@
+use crate::abort::Abort;
+use crate::events::{self, Event};
+use crate::{CONNECTION, add_message, dedup, fail, streams, to_js};
+use futures::StreamExt;
+use futures::channel::mpsc;
+use futures::future::{Either, select};
+use gloo_timers::future::TimeoutFuture;
+use protocol::transfer::{self, EMPTY_CHECKSUM, MAX_CHUNK_LEN};
+use protocol::{Frame, PlaygroundError};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+use web_sys::console;
+
+/// Label of the stream a transfer's frames go on.
+pub const STREAM: &str = "transfer";
+
+/// Chunks sent ahead of the server's acks.
+const WINDOW: u64 = 8;
+
+/// How long a failed write waits for the session to be reported lost before
+/// the transfer gives up on it.
+const STALL_GRACE_MS: u32 = 1000;
+
+/// localStorage key an unfinished transfer is saved under, as JSON.
+#[cfg(feature = "history")]
+const STORAGE_KEY: &str = "wtransport-playground.transfer";
+
+/// How far an unfinished transfer got by the server's last ack: enough to
+/// resume it, in another session or page load, given the same file.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct Saved {
+    id: String,
+    name: String,
+    size: u64,
+    offset: u64,
+    checksum: u32,
+}
+
+// What the running transfer hears about, in order
+enum Signal {
+    Ack {
+        id: String,
+        offset: u64,
+        checksum: u32,
+    },
+    Error(PlaygroundError),
+    /// The session closed; the transfer waits for the next one.
+    Lost,
+    Connected,
+}
+
+// Where the running transfer stands
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Waiting for the ack to a `transfer_start`, or to a `resume_transfer`
+    /// if `resuming`, to learn where to carry on from.
+    Syncing {
+        resuming: bool,
+    },
+    Sending,
+    /// The session was lost; waiting for a new one.
+    Offline,
+}
+
+thread_local! {
+    static SIGNALS: RefCell<Option<mpsc::UnboundedSender<Signal>>> = const { RefCell::new(None) };
+    static SAVED: RefCell<Option<Saved>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Serialize)]
+struct TransferReport {
+    /// Id the server knows the transfer by; a restart picks a new one.
+    id: String,
+    name: String,
+    /// Bytes the server acked, all of them unless aborted.
+    bytes: u64,
+    /// CRC-32 of those bytes, as the server acked it.
+    checksum: u32,
+    /// Times the transfer carried on from the server's offset after a
+    /// reconnect or from a saved transfer.
+    resumes: u32,
+    /// Times it started over, because the server had forgotten it or its
+    /// checksum didn't match this file.
+    restarts: u32,
+    elapsed_ms: f64,
+    aborted: bool,
+}
+
+/// Upload `file` (a `Uint8Array`) as `name`, in chunks on its own `transfer`
+/// stream, each acked by the server with how far it got and the checksum so
+/// far. If the session is lost, the transfer waits for a new one (reconnected
+/// under the resilience policy, or by hand), asks the server where it stands
+/// and carries on from the last acked chunk once the acked checksum matches
+/// the file. Progress is saved after each ack (in localStorage, with the
+/// `history` feature), so sending the same file under the same name again
+/// resumes it too. Emits `transfer_progress`, `transfer_interrupted` and
+/// `transfer_resumed` events. Resolves with `{ id, name, bytes, checksum,
+/// resumes, restarts, elapsed_ms, aborted }`; given an `AbortSignal`, stops
+/// when it fires, keeping what was acked for a later resume.
+#[wasm_bindgen]
+pub async fn send_file(
+    name: String,
+    file: js_sys::Uint8Array,
+    signal: Option<web_sys::AbortSignal>,
+) -> Result<JsValue, JsValue> {
+    let mut abort = Abort::new(signal);
+    if CONNECTION.with(|conn| conn.borrow().session.is_none()) {
+        return Err(fail(PlaygroundError::NotConnected));
+    }
+    let (tx, mut rx) = mpsc::unbounded();
+    let started = SIGNALS.with(|signals| {
+        let mut signals = signals.borrow_mut();
+        if signals.is_some() {
+            return false;
+        }
+        *signals = Some(tx);
+        true
+    });
+    if !started {
+        let error = "a transfer is already running".to_string();
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+
+    let result = run(name, &file.to_vec(), &mut rx, &mut abort).await;
+    SIGNALS.with(|signals| signals.borrow_mut().take());
+    if let Some(stream) = CONNECTION.with(|conn| conn.borrow_mut().streams.remove(STREAM))
+        && stream.lock().await.finish().is_ok()
+    {
+        streams::finished(STREAM);
+    }
+    let report = result.map_err(fail)?;
+    add_message(
+        &format!(
+            "Transfer of '{}'{}: {} bytes in {:.0} ms, {} resume(s), checksum {:08x}",
+            report.name,
+            if report.aborted { " (aborted)" } else { "" },
+            report.bytes,
+            report.elapsed_ms,
+            report.resumes,
+            report.checksum
+        ),
+        "system",
+    );
+    to_js(&report)
+}
+
+async fn run(
+    name: String,
+    bytes: &[u8],
+    rx: &mut mpsc::UnboundedReceiver<Signal>,
+    abort: &mut Abort,
+) -> Result<TransferReport, PlaygroundError> {
+    let started_at = js_sys::Date::now();
+    let size = bytes.len() as u64;
+    let mut resumes = 0;
+    let mut restarts = 0;
+    // Bytes handed to the stream, and the server's last ack
+    let mut sent = 0;
+    let mut acked = 0;
+    let mut checksum = EMPTY_CHECKSUM;
+    open().await?;
+    // A write that failed, until the session is reported lost
+    let saved = load().filter(|saved| saved.name == name && saved.size == size);
+    let (mut id, mut phase, mut stalled) = match saved {
+        Some(saved) => {
+            let resume = Frame::ResumeTransfer {
+                id: saved.id.clone(),
+            };
+            let stalled = send(&resume).await.err();
+            (saved.id, Phase::Syncing { resuming: true }, stalled)
+        }
+        None => {
+            let id = dedup::new_id();
+            let stalled = send(&start_frame(&id, &name, size)).await.err();
+            (id, Phase::Syncing { resuming: false }, stalled)
+        }
+    };
+
+    while !abort.aborted() {
+        if phase == Phase::Sending {
+            if acked == size {
+                break;
+            }
+            while stalled.is_none() && sent < size && sent < acked + WINDOW * MAX_CHUNK_LEN as u64 {
+                let end = size.min(sent + MAX_CHUNK_LEN as u64);
+                let chunk = Frame::TransferChunk {
+                    id: id.clone(),
+                    offset: sent,
+                    data: transfer::encode_chunk(&bytes[sent as usize..end as usize]),
+                };
+                stalled = send(&chunk).await.err();
+                if stalled.is_none() {
+                    sent = end;
+                }
+            }
+        }
+
+        let signal = {
+            let waited = select(rx.next(), Box::pin(abort.fired()));
+            let waited = match stalled {
+                None => Either::Left(waited.await),
+                Some(_) => match select(waited, TimeoutFuture::new(STALL_GRACE_MS)).await {
+                    Either::Left((waited, _)) => Either::Left(waited),
+                    Either::Right(_) => Either::Right(()),
+                },
+            };
+            match waited {
+                Either::Left(Either::Left((Some(signal), _))) => signal,
+                Either::Left(Either::Left((None, _))) => {
+                    return Err(PlaygroundError::Cancelled("transfer".to_string()));
+                }
+                // The loop sees the abort
+                Either::Left(Either::Right(_)) => continue,
+                Either::Right(()) => {
+                    return Err(stalled.take().unwrap_or(PlaygroundError::NotConnected));
+                }
+            }
+        };
+
+        match (phase, signal) {
+            (_, Signal::Lost) => {
+                stalled = None;
+                if phase != Phase::Offline {
+                    events::emit(Event::TransferInterrupted {
+                        id: id.clone(),
+                        offset: acked,
+                        size,
+                    });
+                }
+                phase = Phase::Offline;
+            }
+            (Phase::Offline, Signal::Connected) => {
+                stalled = match open().await {
+                    Ok(()) => send(&Frame::ResumeTransfer { id: id.clone() }).await.err(),
+                    Err(error) => Some(error),
+                };
+                phase = Phase::Syncing { resuming: true };
+            }
+            (Phase::Offline, _) | (_, Signal::Connected) => {}
+            (_, Signal::Ack { id: acked_id, .. }) if acked_id != id => {}
+            (
+                Phase::Syncing { resuming },
+                Signal::Ack {
+                    offset,
+                    checksum: at,
+                    ..
+                },
+            ) => {
+                let matches = offset <= size
+                    && transfer::checksum(EMPTY_CHECKSUM, &bytes[..offset as usize]) == at;
+                if matches {
+                    sent = offset;
+                    acked = offset;
+                    checksum = at;
+                    save(&id, &name, size, acked, checksum);
+                    if resuming {
+                        resumes += 1;
+                        events::emit(Event::TransferResumed {
+                            id: id.clone(),
+                            offset,
+                            size,
+                            restarted: false,
+                        });
+                    }
+                    phase = Phase::Sending;
+                } else {
+                    // The server has another file under this id
+                    id = restart(&name, size, &mut stalled).await;
+                    restarts += 1;
+                }
+            }
+            (Phase::Syncing { resuming: true }, Signal::Error(error)) => {
+                console::log_1(&format!("Can't resume transfer {}: {}", id, error).into());
+                id = restart(&name, size, &mut stalled).await;
+                restarts += 1;
+            }
+            (_, Signal::Error(error)) => return Err(error),
+            (
+                Phase::Sending,
+                Signal::Ack {
+                    offset,
+                    checksum: at,
+                    ..
+                },
+            ) => {
+                if offset > acked && offset <= sent {
+                    acked = offset;
+                    checksum = at;
+                    save(&id, &name, size, acked, checksum);
+                    events::emit(Event::TransferProgress {
+                        id: id.clone(),
+                        offset,
+                        size,
+                    });
+                }
+            }
+        }
+    }
+
+    let aborted = acked < size;
+    if !aborted {
+        clear();
+    }
+    Ok(TransferReport {
+        id,
+        name,
+        bytes: acked,
+        checksum,
+        resumes,
+        restarts,
+        elapsed_ms: js_sys::Date::now() - started_at,
+        aborted,
+    })
+}
+
+fn start_frame(id: &str, name: &str, size: u64) -> Frame {
+    Frame::TransferStart {
+        id: id.to_string(),
+        name: name.to_string(),
+        size,
+    }
+}
+
+// Start over under a new id, reported as a resume from nothing
+async fn restart(name: &str, size: u64, stalled: &mut Option<PlaygroundError>) -> String {
+    let id = dedup::new_id();
+    events::emit(Event::TransferResumed {
+        id: id.clone(),
+        offset: 0,
+        size,
+        restarted: true,
+    });
+    if stalled.is_none() {
+        *stalled = send(&start_frame(&id, name, size)).await.err();
+    }
+    id
+}
+
+// Open the transfer stream on the current session
+async fn open() -> Result<(), PlaygroundError> {
+    let Some(mut session) = CONNECTION.with(|conn| conn.borrow().session.clone()) else {
+        return Err(PlaygroundError::NotConnected);
+    };
+    match session.open_bi().await {
+        Ok((send, recv)) => {
+            streams::register(STREAM, send, recv);
+            Ok(())
+        }
+        Err(e) => Err(PlaygroundError::StreamFailed(format!(
+            "opening '{}': {}",
+            STREAM, e
+        ))),
+    }
+}
+
+async fn send(frame: &Frame) -> Result<(), PlaygroundError> {
+    streams::write_frame_on(STREAM, frame).await
+}
+
+fn signal(signal: Signal) {
+    SIGNALS.with(|signals| {
+        if let Some(signals) = signals.borrow().as_ref() {
+            let _ = signals.unbounded_send(signal);
+        }
+    });
+}
+
+/// Pass an ack on to the running transfer.
+pub fn on_ack(id: String, offset: u64, checksum: u32) {
+    signal(Signal::Ack {
+        id,
+        offset,
+        checksum,
+    });
+}
+
+/// Pass an error the server sent on the transfer stream on to the running
+/// transfer.
+pub fn on_error(error: PlaygroundError) {
+    console::error_1(&format!("Transfer error: {}", error).into());
+    signal(Signal::Error(error));
+}
+
+/// The session closed: the running transfer, if any, waits for the next one.
+pub fn interrupt() {
+    signal(Signal::Lost);
+}
+
+/// A session opened: an interrupted transfer resumes on it.
+pub fn on_connected() {
+    signal(Signal::Connected);
+}
+
+fn save(id: &str, name: &str, size: u64, offset: u64, checksum: u32) {
+    let saved = Saved {
+        id: id.to_string(),
+        name: name.to_string(),
+        size,
+        offset,
+        checksum,
+    };
+    #[cfg(feature = "history")]
+    if let (Some(storage), Ok(json)) = (storage(), serde_json::to_string(&saved))
+        && let Err(e) = storage.set_item(STORAGE_KEY, &json)
+    {
+        console::warn_2(&"Can't save transfer:".into(), &e);
+    }
+    SAVED.with(|s| *s.borrow_mut() = Some(saved));
+}
+
+fn load() -> Option<Saved> {
+    #[cfg(feature = "history")]
+    if let Some(saved) = storage()
+        .and_then(|storage| storage.get_item(STORAGE_KEY).ok().flatten())
+        .and_then(|json| serde_json::from_str(&json).ok())
+    {
+        return Some(saved);
+    }
+    SAVED.with(|s| s.borrow().clone())
+}
+
+fn clear() {
+    #[cfg(feature = "history")]
+    if let Some(storage) = storage() {
+        let _ = storage.remove_item(STORAGE_KEY);
+    }
+    SAVED.with(|s| s.borrow_mut().take());
+}
+
+#[cfg(feature = "history")]
+fn storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok().flatten()
+}
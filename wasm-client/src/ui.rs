@@ -0,0 +1,89 @@
+// DOM output for the demo page. Everything here degrades to console logging when
+// there is no document (e.g. tests in a worker), or when built without the
+// `dom-ui` feature, so the rest of the client never depends on the page's markup.
+#[cfg(feature = "dom-ui")]
+use wasm_bindgen::prelude::*;
+use web_sys::console;
+#[cfg(feature = "dom-ui")]
+use web_sys::window;
+
+#[cfg(not(feature = "dom-ui"))]
+pub fn add_message(text: &str, msg_type: &str) {
+    console::log_1(&format!("[{}] {}", msg_type, text).into());
+}
+
+#[cfg(feature = "dom-ui")]
+pub fn add_message(text: &str, msg_type: &str) {
+    let Some(document) = window().and_then(|window| window.document()) else {
+        console::log_1(&format!("[{}] {}", msg_type, text).into());
+        return;
+    };
+
+    if let Some(messages_div) = document.get_element_by_id("messages")
+        && let Ok(message_div) = document.create_element("div")
+    {
+        message_div.set_class_name(&format!("message {}", msg_type));
+        message_div.set_text_content(Some(text));
+        let _ = messages_div.append_child(&message_div);
+
+        // Scroll to bottom
+        if let Some(html_div) = messages_div.dyn_ref::<web_sys::HtmlElement>() {
+            html_div.set_scroll_top(html_div.scroll_height().into());
+        }
+    }
+}
+
+#[cfg(feature = "dom-ui")]
+#[wasm_bindgen]
+pub fn update_status(connected: bool) {
+    let Some(document) = window().and_then(|window| window.document()) else {
+        return;
+    };
+
+    if let Some(status_div) = document.get_element_by_id("status") {
+        if connected {
+            status_div.set_text_content(Some("Status: Connected"));
+            status_div.set_class_name("status connected");
+        } else {
+            status_div.set_text_content(Some("Status: Disconnected"));
+            status_div.set_class_name("status disconnected");
+        }
+    }
+
+    // Enable/disable buttons
+    if let Some(connect_btn) = document.get_element_by_id("connectBtn")
+        && let Some(btn) = connect_btn.dyn_ref::<web_sys::HtmlButtonElement>()
+    {
+        btn.set_disabled(connected);
+    }
+
+    if let Some(disconnect_btn) = document.get_element_by_id("disconnectBtn")
+        && let Some(btn) = disconnect_btn.dyn_ref::<web_sys::HtmlButtonElement>()
+    {
+        btn.set_disabled(!connected);
+    }
+
+    if let Some(send_btn) = document.get_element_by_id("sendStreamBtn")
+        && let Some(btn) = send_btn.dyn_ref::<web_sys::HtmlButtonElement>()
+    {
+        btn.set_disabled(!connected);
+    }
+
+    if let Some(send_btn) = document.get_element_by_id("sendDatagramBtn")
+        && let Some(btn) = send_btn.dyn_ref::<web_sys::HtmlButtonElement>()
+    {
+        btn.set_disabled(!connected);
+    }
+
+    if let Some(send_btn) = document.get_element_by_id("sendRequestBtn")
+        && let Some(btn) = send_btn.dyn_ref::<web_sys::HtmlButtonElement>()
+    {
+        btn.set_disabled(!connected);
+    }
+
+    if let Some(call_btn) = document.get_element_by_id("callBtn")
+        && let Some(btn) = call_btn.dyn_ref::<web_sys::HtmlButtonElement>()
+    {
+        btn.set_disabled(!connected);
+    }
+}
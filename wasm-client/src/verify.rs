@@ -0,0 +1,176 @@
+use crate::events::{self, Event};
+use crate::streams::write_frame_on;
+use crate::{fail, to_js};
+use futures::channel::oneshot;
+use futures::future::select;
+use gloo_timers::future::TimeoutFuture;
+use protocol::{Frame, PlaygroundError};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use wasm_bindgen::prelude::*;
+use web_sys::console;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Violation {
+    /// An echo arrived before one with a lower sequence number.
+    OutOfOrder,
+    /// The same sequence number was echoed twice.
+    Duplicate,
+    /// A sequence number was never echoed before the run timed out.
+    Missing,
+    /// A numbered echo arrived on a stream with no verification running.
+    Unexpected,
+}
+
+// One verification run on a stream: numbered messages 1..=count, whose echoes
+// must come back in order and exactly once.
+struct Run {
+    count: u64,
+    next: u64,
+    seen: HashSet<u64>,
+    out_of_order: u64,
+    duplicates: u64,
+    done: Option<oneshot::Sender<()>>,
+}
+
+thread_local! {
+    static RUNS: RefCell<HashMap<String, Run>> = RefCell::new(HashMap::new());
+}
+
+#[derive(Serialize)]
+struct Summary {
+    stream: String,
+    sent: u64,
+    received: u64,
+    out_of_order: u64,
+    duplicates: u64,
+    missing: u64,
+    ok: bool,
+}
+
+/// Send `count` numbered messages on the stream `label` and check that their
+/// echoes arrive in order and exactly once. Each violation is reported as a
+/// `protocol_violation` event as it happens; resolves with a summary
+/// `{ stream, sent, received, out_of_order, duplicates, missing, ok }` once
+/// every echo is in, or after `timeout_ms`.
+#[wasm_bindgen]
+pub async fn verify_stream(label: String, count: u32, timeout_ms: u32) -> Result<JsValue, JsValue> {
+    let count = count as u64;
+    let (tx, rx) = oneshot::channel();
+    let started = RUNS.with(|runs| {
+        let mut runs = runs.borrow_mut();
+        if runs.contains_key(&label) {
+            return false;
+        }
+        runs.insert(
+            label.clone(),
+            Run {
+                count,
+                next: 1,
+                seen: HashSet::new(),
+                out_of_order: 0,
+                duplicates: 0,
+                done: Some(tx),
+            },
+        );
+        true
+    });
+    if !started {
+        let error = format!("verification already running on '{}'", label);
+        return Err(fail(PlaygroundError::InvalidParams(error)));
+    }
+
+    for seq in 1..=count {
+        let frame = Frame::Message {
+            text: format!("verify {}", seq),
+            seq: Some(seq),
+            id: None,
+            timing: None,
+        };
+        if let Err(error) = write_frame_on(&label, &frame).await {
+            RUNS.with(|runs| runs.borrow_mut().remove(&label));
+            return Err(fail(error));
+        }
+    }
+    if count > 0 {
+        let _ = select(rx, TimeoutFuture::new(timeout_ms)).await;
+    }
+
+    let Some(run) = RUNS.with(|runs| runs.borrow_mut().remove(&label)) else {
+        return Err(fail(PlaygroundError::Cancelled(format!(
+            "verification on '{}'",
+            label
+        ))));
+    };
+
+    let missing: Vec<u64> = (1..=count).filter(|seq| !run.seen.contains(seq)).collect();
+    for seq in &missing {
+        report(&label, Violation::Missing, *seq, None);
+    }
+
+    let summary = Summary {
+        stream: label,
+        sent: count,
+        received: run.seen.len() as u64,
+        out_of_order: run.out_of_order,
+        duplicates: run.duplicates,
+        missing: missing.len() as u64,
+        ok: run.out_of_order == 0 && run.duplicates == 0 && missing.is_empty(),
+    };
+    to_js(&summary)
+}
+
+/// Abandon every running verification; their `verify_stream` calls reject.
+pub fn cancel_all() {
+    RUNS.with(|runs| runs.borrow_mut().clear());
+}
+
+/// Check a numbered echo received on stream `label`.
+pub fn on_echo(label: &str, seq: u64) {
+    let violation = RUNS.with(|runs| {
+        let mut runs = runs.borrow_mut();
+        let Some(run) = runs.get_mut(label) else {
+            return Some((Violation::Unexpected, None));
+        };
+
+        let violation = if !run.seen.insert(seq) {
+            run.duplicates += 1;
+            Some((Violation::Duplicate, None))
+        } else if seq != run.next {
+            run.out_of_order += 1;
+            Some((Violation::OutOfOrder, Some(run.next)))
+        } else {
+            None
+        };
+        run.next = run.next.max(seq + 1);
+
+        if run.seen.len() as u64 == run.count
+            && let Some(done) = run.done.take()
+        {
+            let _ = done.send(());
+        }
+        violation
+    });
+
+    if let Some((violation, expected)) = violation {
+        report(label, violation, seq, expected);
+    }
+}
+
+fn report(stream: &str, violation: Violation, seq: u64, expected: Option<u64>) {
+    console::warn_1(
+        &format!(
+            "Protocol violation on '{}': {:?} (seq {}, expected {:?})",
+            stream, violation, seq, expected
+        )
+        .into(),
+    );
+    events::emit(Event::ProtocolViolation {
+        stream: stream.to_string(),
+        violation,
+        seq,
+        expected,
+    });
+}
@@ -0,0 +1,205 @@
+use crate::events::{self, Event};
+use crate::{CONNECTION, add_message, clock, rpc};
+use futures::channel::oneshot;
+use futures::future::select;
+use gloo_timers::future::TimeoutFuture;
+use protocol::PlaygroundError;
+use protocol::rpc::{HEARTBEAT, HeartbeatParams, Outcome, REPORT_VISIBILITY, VisibilityReport};
+use std::cell::RefCell;
+use wasm_bindgen::prelude::*;
+use web_sys::{console, window};
+
+/// Heartbeat interval while the page is visible.
+const HEARTBEAT_MS: u32 = 5_000;
+
+/// Heartbeat interval while the page is hidden; browsers throttle background
+/// timers anyway, so there's no point asking for more.
+const HIDDEN_HEARTBEAT_MS: u32 = 30_000;
+
+const REQUEST_TIMEOUT_MS: u32 = 5_000;
+
+#[derive(Default)]
+struct Visibility {
+    /// When the page was hidden; None while it's visible.
+    hidden_at: Option<f64>,
+    /// Woken on the next visibility change.
+    watchers: Vec<oneshot::Sender<()>>,
+}
+
+thread_local! {
+    static VISIBILITY: RefCell<Visibility> = RefCell::new(Visibility::default());
+    static LISTENER: RefCell<Option<Closure<dyn FnMut()>>> = const { RefCell::new(None) };
+}
+
+/// Follow the page's visibility from here on. Without a document (e.g. in a
+/// worker) the page counts as always visible.
+pub fn install() {
+    let Some(document) = window().and_then(|window| window.document()) else {
+        return;
+    };
+    if document.hidden() {
+        VISIBILITY.with(|v| v.borrow_mut().hidden_at = Some(js_sys::Date::now()));
+    }
+
+    let listener = Closure::<dyn FnMut()>::new(on_change);
+    if let Err(e) = document
+        .add_event_listener_with_callback("visibilitychange", listener.as_ref().unchecked_ref())
+    {
+        console::warn_2(&"Can't follow page visibility:".into(), &e);
+        return;
+    }
+    LISTENER.with(|l| *l.borrow_mut() = Some(listener));
+}
+
+/// Whether the page is visible, per the Page Visibility API. While it's hidden
+/// heartbeats slow down and the rate controller and bench hold off sending.
+#[wasm_bindgen]
+pub fn is_page_visible() -> bool {
+    VISIBILITY.with(|v| v.borrow().hidden_at.is_none())
+}
+
+/// Wait until the page is visible. Resolves with how long that took, in ms.
+pub async fn visible() -> f64 {
+    let started = js_sys::Date::now();
+    while !is_page_visible() {
+        changed().await;
+    }
+    js_sys::Date::now() - started
+}
+
+// Resolves on the next visibility change
+async fn changed() {
+    let (tx, rx) = oneshot::channel();
+    VISIBILITY.with(|v| {
+        let mut v = v.borrow_mut();
+        // Drop watchers that gave up, e.g. heartbeat waits that timed out
+        v.watchers.retain(|watcher| !watcher.is_canceled());
+        v.watchers.push(tx);
+    });
+    let _ = rx.await;
+}
+
+fn on_change() {
+    let Some(hidden) = window()
+        .and_then(|window| window.document())
+        .map(|document| document.hidden())
+    else {
+        return;
+    };
+
+    let now = js_sys::Date::now();
+    let change = VISIBILITY.with(|v| {
+        let mut v = v.borrow_mut();
+        let hidden_ms = match (hidden, v.hidden_at) {
+            (true, None) => {
+                v.hidden_at = Some(now);
+                None
+            }
+            (false, Some(at)) => {
+                v.hidden_at = None;
+                Some(now - at)
+            }
+            _ => return None,
+        };
+        Some((hidden_ms, std::mem::take(&mut v.watchers)))
+    });
+    let Some((hidden_ms, watchers)) = change else {
+        return;
+    };
+
+    for watcher in watchers {
+        let _ = watcher.send(());
+    }
+    match hidden_ms {
+        None => add_message(
+            "Page hidden: heartbeats slowed, bulk traffic paused",
+            "system",
+        ),
+        Some(hidden_ms) => add_message(
+            &format!(
+                "Page visible after {:.1} s: resuming and resyncing",
+                hidden_ms / 1000.0
+            ),
+            "system",
+        ),
+    }
+    events::emit(Event::VisibilityChanged {
+        visible: !hidden,
+        hidden_ms,
+    });
+
+    if CONNECTION.with(|conn| conn.borrow().session.is_some()) {
+        wasm_bindgen_futures::spawn_local(async move {
+            report(!hidden).await;
+            if let Some(hidden_ms) = hidden_ms {
+                resync(hidden_ms).await;
+            }
+        });
+    }
+}
+
+// Tell the server whether the page is visible
+async fn report(visible: bool) {
+    let params = serde_json::to_value(VisibilityReport { visible }).unwrap_or_default();
+    if let Err(e) = notify(REPORT_VISIBILITY, params).await {
+        console::warn_1(&format!("Failed to report page visibility: {}", e).into());
+    }
+}
+
+// Timers were throttled while hidden, so the clock offset may have drifted
+async fn resync(hidden_ms: f64) {
+    let clock_offset_ms = match clock::sync().await {
+        Ok(offset) => Some(offset),
+        Err(e) => {
+            console::warn_1(&format!("Clock resync failed: {}", e).into());
+            None
+        }
+    };
+    events::emit(Event::Resynced {
+        hidden_ms,
+        clock_offset_ms,
+    });
+}
+
+/// Send heartbeats until the session of `generation` ends: every 5 s while the
+/// page is visible and every 30 s while it's hidden, plus one on each change,
+/// each announcing when the next is due.
+pub async fn heartbeat_loop(generation: u64) {
+    if !is_page_visible() {
+        report(false).await;
+    }
+
+    loop {
+        let interval_ms = if is_page_visible() {
+            HEARTBEAT_MS
+        } else {
+            HIDDEN_HEARTBEAT_MS
+        };
+        if let Err(e) = heartbeat(interval_ms).await {
+            console::warn_1(&format!("Heartbeat failed: {}", e).into());
+        }
+
+        // On a change, go straight to the next heartbeat to announce the new interval
+        select(TimeoutFuture::new(interval_ms), Box::pin(changed())).await;
+        let current = CONNECTION.with(|conn| {
+            let state = conn.borrow();
+            state.generation == generation && state.session.is_some()
+        });
+        if !current {
+            return;
+        }
+    }
+}
+
+async fn heartbeat(interval_ms: u32) -> Result<(), PlaygroundError> {
+    let params = serde_json::to_value(HeartbeatParams { interval_ms }).unwrap_or_default();
+    notify(HEARTBEAT, params).await
+}
+
+// Call a server method that has no result
+async fn notify(method: &str, params: serde_json::Value) -> Result<(), PlaygroundError> {
+    match rpc::send_request(method.to_string(), params, REQUEST_TIMEOUT_MS).await? {
+        Outcome::Result(_) => Ok(()),
+        Outcome::Error(error) => Err(error),
+    }
+}
@@ -0,0 +1,105 @@
+// Browser tests for the WASM client. Run from wasm-client/ with:
+//
+//     wasm-pack test --headless --chrome
+//
+// The live tests need a running server (`cargo run` in the repo root) and the
+// hash of its certificate at build time:
+//
+//     PLAYGROUND_CERT_HASH=$(curl -s http://127.0.0.1:7654/cert-hash | jq -r .value) \
+//         wasm-pack test --headless --chrome
+//
+// Without PLAYGROUND_CERT_HASH they pass without connecting.
+use protocol::{Frame, FrameDecoder, PlaygroundError};
+use wasm_bindgen::JsValue;
+use wasm_bindgen_test::*;
+use wasm_client::{
+    connect_with_cert_hash, connection_status, disconnect, request, send_message_stream,
+    verify_stream,
+};
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+const SERVER_URL: &str = "https://localhost:8765";
+
+fn cert_hash() -> Option<&'static str> {
+    let hash = option_env!("PLAYGROUND_CERT_HASH");
+    if hash.is_none() {
+        web_sys::console::log_1(&"PLAYGROUND_CERT_HASH not set, skipping live test".into());
+    }
+    hash
+}
+
+// The `kind` of a structured error rejected by an exported function
+fn error_kind(error: JsValue) -> String {
+    js_sys::Reflect::get(&error, &"kind".into())
+        .ok()
+        .and_then(|kind| kind.as_string())
+        .unwrap_or_default()
+}
+
+#[wasm_bindgen_test]
+fn frames_survive_byte_at_a_time_delivery() {
+    let frames = [
+        Frame::Message {
+            text: "hello".to_string(),
+            seq: Some(7),
+            id: None,
+            timing: None,
+        },
+        Frame::Error {
+            error: PlaygroundError::RateLimited("slow down".to_string()),
+        },
+    ];
+    let bytes: Vec<u8> = frames.iter().flat_map(protocol::encode).collect();
+
+    let mut decoder = FrameDecoder::new();
+    let mut decoded = Vec::new();
+    for byte in bytes {
+        decoder.push(&[byte]);
+        while let Some(frame) = decoder.next_frame().unwrap() {
+            decoded.push(frame);
+        }
+    }
+    assert_eq!(decoded, frames);
+}
+
+#[wasm_bindgen_test]
+async fn sending_while_disconnected_fails() {
+    assert_eq!(connection_status(), "disconnected");
+
+    let kind = error_kind(send_message_stream("hello".to_string()).await.unwrap_err());
+    assert_eq!(kind, "not_connected");
+    assert_eq!(connection_status(), "disconnected");
+}
+
+#[wasm_bindgen_test]
+async fn malformed_cert_hash_is_rejected() {
+    let result = connect_with_cert_hash(SERVER_URL.to_string(), "not-a-hash".to_string()).await;
+    let kind = error_kind(result.unwrap_err());
+    assert_eq!(kind, "invalid_params");
+    assert_eq!(connection_status(), "disconnected");
+}
+
+#[wasm_bindgen_test]
+async fn connect_send_receive() {
+    let Some(hash) = cert_hash() else {
+        return;
+    };
+
+    connect_with_cert_hash(SERVER_URL.to_string(), hash.to_string())
+        .await
+        .unwrap();
+    assert_eq!(connection_status(), "connected");
+
+    let echoed = request("ping".to_string(), 2000).await.unwrap();
+    assert_eq!(echoed, "ping");
+
+    let summary = verify_stream("main".to_string(), 20, 2000).await.unwrap();
+    let ok = js_sys::Reflect::get(&summary, &"ok".into()).unwrap();
+    assert_eq!(ok.as_bool(), Some(true));
+
+    let report = disconnect(None).await.unwrap();
+    let graceful = js_sys::Reflect::get(&report, &"graceful".into()).unwrap();
+    assert_eq!(graceful.as_bool(), Some(true));
+    assert_eq!(connection_status(), "disconnected");
+}